@@ -0,0 +1,107 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Exposes build information to the `version` command
+fn main() {
+    watch_git_head();
+    if Path::new("Cargo.lock").exists() {
+        println!("cargo:rerun-if-changed=Cargo.lock");
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git_commit = command_output("git", &["rev-parse", "HEAD"]);
+    println!("cargo:rustc-env=KWCTL_GIT_COMMIT={git_commit}");
+
+    // honor SOURCE_DATE_EPOCH to keep builds reproducible
+    let build_timestamp = env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().to_string())
+            .unwrap_or_else(|_| "0".to_string())
+    });
+    println!("cargo:rustc-env=KWCTL_BUILD_TIMESTAMP={build_timestamp}");
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+    println!("cargo:rustc-env=KWCTL_RUSTC_VERSION={rustc_version}");
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=KWCTL_FEATURES={}", features.join(","));
+
+    let wasmtime_version = locked_version("wasmtime").unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=KWCTL_WASMTIME_VERSION={wasmtime_version}");
+}
+
+// The commit changes when HEAD moves to another branch, or when the branch it
+// points to moves. Only existing paths are watched, cargo would rerun the build
+// script every time otherwise.
+fn watch_git_head() {
+    let Some(git_dir) = git_path("--git-dir") else {
+        return;
+    };
+    // worktrees share the refs of the main repository
+    let common_dir = git_path("--git-common-dir").unwrap_or_else(|| git_dir.clone());
+    let head = git_dir.join("HEAD");
+
+    let mut watched = vec![head.clone(), common_dir.join("packed-refs")];
+    if let Some(reference) = fs::read_to_string(&head)
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        let reference = common_dir.join(reference);
+        // a packed branch gets a loose ref once it moves, which is noticed by
+        // watching the directory holding it
+        match reference.parent().filter(|_| !reference.exists()) {
+            Some(parent) => watched.push(parent.to_path_buf()),
+            None => watched.push(reference),
+        }
+    }
+
+    for path in watched.iter().filter(|path| path.exists()) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+fn git_path(option: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(command_output("git", &["rev-parse", option])))
+        .filter(|path| path.as_os_str() != "unknown")
+}
+
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|stdout| stdout.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Version of the given package, as recorded inside of Cargo.lock
+fn locked_version(package: &str) -> Option<String> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").ok()?;
+    let lock = fs::read_to_string(Path::new(&manifest_dir).join("Cargo.lock")).ok()?;
+    let name = format!("name = \"{package}\"");
+
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() == name {
+            return lines
+                .next()
+                .and_then(|line| line.trim().strip_prefix("version = "))
+                .map(|version| version.trim_matches('"').to_string());
+        }
+    }
+    None
+}
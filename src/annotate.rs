@@ -1,15 +1,41 @@
+use crate::annotations::SETTINGS_SCHEMA_ANNOTATION;
 use crate::backend::{Backend, BackendDetector};
+use crate::config::strict::{self, ConfigFile};
+use crate::optimize::{self, OptimizeLevel};
+use crate::store_sync::write_atomically;
 use anyhow::{anyhow, Result};
 use policy_evaluator::validator::Validate;
 use policy_evaluator::{constants::*, policy_metadata::Metadata, ProtocolVersion};
+use serde_yaml::Value;
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Changes made to the metadata via the flags of `kwctl annotate`, applied on
+/// top of the metadata file when given
+#[derive(Default)]
+pub(crate) struct Overrides {
+    /// Fields in the `path=value` format, the path being made of the names of
+    /// the fields separated by `.` and of the indexes of the items, like
+    /// `rules[0].operations`
+    pub(crate) set: Vec<String>,
+    pub(crate) mutating: bool,
+    /// Rules in the `GROUP/VERSION/RESOURCE:OPERATIONS` format
+    pub(crate) rules: Vec<String>,
+    /// JSON Schema of the settings, embedded as an annotation
+    pub(crate) settings_schema: Option<serde_json::Value>,
+}
 
+/// Annotates the policy with the metadata, writing it to `destination`.
+/// When `destination` is `None` the metadata is only validated.
 pub(crate) fn write_annotation(
     wasm_path: PathBuf,
-    metadata_path: PathBuf,
-    destination: PathBuf,
+    metadata_path: Option<PathBuf>,
+    overrides: Overrides,
+    destination: Option<PathBuf>,
     usage_path: Option<PathBuf>,
+    strict: bool,
+    optimize_level: Option<OptimizeLevel>,
 ) -> Result<()> {
     let usage = usage_path
         .map(|path| {
@@ -17,24 +43,73 @@ pub(crate) fn write_annotation(
         })
         .transpose()?;
     let backend_detector = BackendDetector::default();
+    if let Some(path) = metadata_path.as_ref().filter(|_| strict) {
+        strict::validate(ConfigFile::Metadata, path)?;
+    }
+    let metadata_display = metadata_path
+        .as_ref()
+        .map_or("command line".to_string(), |path| {
+            path.display().to_string()
+        });
     let metadata = prepare_metadata(
         wasm_path.clone(),
         metadata_path,
+        &overrides,
+        strict,
         backend_detector,
         usage.as_deref(),
     )?;
+    let Some(destination) = destination else {
+        println!("{metadata_display}: the metadata is valid");
+        return Ok(());
+    };
+
+    // the metadata is added to the optimized module
+    if let Some(level) = optimize_level {
+        let optimized = tempfile::Builder::new().suffix(".wasm").tempfile()?;
+        let report =
+            optimize::optimize(&wasm_path, optimized.path(), level, metadata.execution_mode)?;
+        eprintln!("{report}");
+        return write_annotated_wasm_file(optimized.path().to_path_buf(), destination, metadata);
+    }
+
     write_annotated_wasm_file(wasm_path, destination, metadata)
 }
 
 fn prepare_metadata(
     wasm_path: PathBuf,
-    metadata_path: PathBuf,
+    metadata_path: Option<PathBuf>,
+    overrides: &Overrides,
+    strict: bool,
     backend_detector: BackendDetector,
     usage: Option<&str>,
 ) -> Result<Metadata> {
-    let metadata_file =
-        File::open(metadata_path).map_err(|e| anyhow!("Error opening metadata file: {}", e))?;
-    let mut metadata: Metadata = serde_yaml::from_reader(&metadata_file)
+    let mut document = match metadata_path {
+        Some(path) => {
+            let metadata_file =
+                File::open(path).map_err(|e| anyhow!("Error opening metadata file: {}", e))?;
+            serde_yaml::from_reader(&metadata_file)
+                .map_err(|e| anyhow!("Error unmarshalling metadata {}", e))?
+        }
+        None => Value::Null,
+    };
+    apply_overrides(&mut document, overrides)?;
+    // the metadata file has already been checked, only the fields given via
+    // --set can be unknown
+    if strict && !overrides.set.is_empty() {
+        let violations = strict::check(ConfigFile::Metadata, &serde_yaml::to_string(&document)?);
+        if !violations.is_empty() {
+            return Err(anyhow!(
+                "--set:\n  {}\nUse --lenient to skip the validation",
+                violations.join("\n  ")
+            ));
+        }
+    }
+    let errors = crate::lint::type_errors(&document);
+    if !errors.is_empty() {
+        return Err(anyhow!("Metadata is invalid:\n  {}", errors.join("\n  ")));
+    }
+    let mut metadata: Metadata = serde_yaml::from_value(document)
         .map_err(|e| anyhow!("Error unmarshalling metadata {}", e))?;
 
     let backend = backend_detector.detect(wasm_path, &metadata)?;
@@ -63,19 +138,203 @@ fn prepare_metadata(
 
     metadata
         .validate()
-        .map_err(|e| anyhow!("Metadata is invalid: {:?}", e))
+        .map_err(|e| anyhow!("Metadata is invalid: {}", e))
         .and(Ok(metadata))
 }
 
+/// Applies the changes given via the flags, in this order: the rules are
+/// appended, then the policy is marked as mutating, the settings JSON Schema
+/// is embedded and last the fields given via `--set` are replaced
+fn apply_overrides(document: &mut Value, overrides: &Overrides) -> Result<()> {
+    if document.is_null() {
+        *document = Value::Mapping(Default::default());
+    }
+    for rule in &overrides.rules {
+        let rule = parse_rule(rule)?;
+        let rules = child(document, &Segment::Field("rules"))?;
+        if rules.is_null() {
+            *rules = Value::Sequence(Vec::new());
+        }
+        rules
+            .as_sequence_mut()
+            .ok_or_else(|| anyhow!("rules: expected a sequence"))?
+            .push(rule);
+    }
+    if overrides.mutating {
+        *child(document, &Segment::Field("mutating"))? = Value::Bool(true);
+    }
+    if let Some(schema) = &overrides.settings_schema {
+        let annotations = child(document, &Segment::Field("annotations"))?;
+        *child(annotations, &Segment::Field(SETTINGS_SCHEMA_ANNOTATION))? =
+            Value::String(serde_json::to_string(schema)?);
+    }
+    for item in &overrides.set {
+        let (path, value) = item
+            .split_once('=')
+            .filter(|(path, _)| !path.is_empty())
+            .ok_or_else(|| anyhow!("invalid --set '{}', expected PATH=VALUE", item))?;
+        let segments = segments(path)?;
+        // annotations are always strings, even when they look like numbers
+        let value = if segments[0] == Segment::Field("annotations") {
+            Value::String(value.to_string())
+        } else {
+            serde_yaml::from_str(value)
+                .map_err(|e| anyhow!("invalid value of --set '{}': {}", item, e))?
+        };
+        let mut field = &mut *document;
+        for segment in &segments {
+            field =
+                child(field, segment).map_err(|e| anyhow!("invalid --set '{}': {}", item, e))?;
+        }
+        *field = value;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    Field(&'a str),
+    Index(usize),
+}
+
+/// Splits a path like `rules[0].operations` into its segments. The name of
+/// an annotation can contain dots, hence everything after `annotations.` is
+/// taken as the name.
+fn segments(path: &str) -> Result<Vec<Segment<'_>>> {
+    if let Some(name) = path.strip_prefix("annotations.") {
+        return Ok(vec![Segment::Field("annotations"), Segment::Field(name)]);
+    }
+    let invalid = || anyhow!("invalid path '{}'", path);
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        let (field, mut indexes) = part.find('[').map_or((part, ""), |at| part.split_at(at));
+        if field.is_empty() {
+            return Err(invalid());
+        }
+        segments.push(Segment::Field(field));
+        while let Some(rest) = indexes.strip_prefix('[') {
+            let (index, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            segments.push(Segment::Index(index.parse().map_err(|_| invalid())?));
+            indexes = rest;
+        }
+        if !indexes.is_empty() {
+            return Err(invalid());
+        }
+    }
+    Ok(segments)
+}
+
+/// The field or the item of `value` selected by the segment, created when
+/// missing. An item can be appended by using the length of the sequence as
+/// index.
+fn child<'a>(value: &'a mut Value, segment: &Segment) -> Result<&'a mut Value> {
+    match segment {
+        Segment::Field(name) => {
+            if value.is_null() {
+                *value = Value::Mapping(Default::default());
+            }
+            let fields = value
+                .as_mapping_mut()
+                .ok_or_else(|| anyhow!("cannot set field {}, the parent is not a mapping", name))?;
+            Ok(fields
+                .entry(Value::String(name.to_string()))
+                .or_insert(Value::Null))
+        }
+        Segment::Index(index) => {
+            if value.is_null() {
+                *value = Value::Sequence(Vec::new());
+            }
+            let items = value.as_sequence_mut().ok_or_else(|| {
+                anyhow!("cannot set item {}, the parent is not a sequence", index)
+            })?;
+            if *index == items.len() {
+                items.push(Value::Null);
+            }
+            items.get_mut(*index).ok_or_else(|| {
+                anyhow!(
+                    "cannot set item {}, the sequence has {} items",
+                    index,
+                    items.len()
+                )
+            })
+        }
+    }
+}
+
+/// Parses a rule in the `GROUP/VERSION/RESOURCE:OPERATIONS` format, like
+/// `apps/v1/deployments:CREATE,UPDATE`. The group can be omitted for the
+/// resources of the core API group, like `v1/pods:CREATE`.
+fn parse_rule(rule: &str) -> Result<Value> {
+    let invalid = || {
+        anyhow!(
+            "invalid rule '{}', expected GROUP/VERSION/RESOURCE:OPERATIONS",
+            rule
+        )
+    };
+    let (resource, operations) = rule.rsplit_once(':').ok_or_else(invalid)?;
+    let (group, version, resource) = match resource.split('/').collect::<Vec<_>>()[..] {
+        [group, version, resource] => (group, version, resource),
+        [version, resource] => ("", version, resource),
+        _ => return Err(invalid()),
+    };
+    if version.is_empty() || resource.is_empty() || operations.is_empty() {
+        return Err(invalid());
+    }
+    let operations: Vec<&str> = operations.split(',').collect();
+    Ok(serde_yaml::to_value(serde_json::json!({
+        "apiGroups": [group],
+        "apiVersions": [version],
+        "resources": [resource],
+        "operations": operations,
+    }))?)
+}
+
+/// Removes the Kubewarden metadata from the policy, writing it to
+/// `destination`
+pub(crate) fn strip_annotation(wasm_path: PathBuf, destination: PathBuf) -> Result<()> {
+    let buf: Vec<u8> = std::fs::read(&wasm_path)?;
+    let mut module = walrus::Module::from_buffer(buf.as_slice())?;
+    if !remove_metadata(&mut module) {
+        warn!(
+            policy = %wasm_path.display(),
+            "the policy does not contain Kubewarden metadata"
+        );
+    }
+    write_atomically(&destination, &module.emit_wasm())
+}
+
+/// Removes the custom sections holding the Kubewarden metadata, returns
+/// whether any was found
+fn remove_metadata(module: &mut walrus::Module) -> bool {
+    let mut found = false;
+    while module
+        .customs
+        .remove_raw(KUBEWARDEN_CUSTOM_SECTION_METADATA)
+        .is_some()
+    {
+        found = true;
+    }
+    found
+}
+
+/// The metadata of an already annotated policy is replaced. The output path
+/// can be the input one, when annotating in place.
 fn write_annotated_wasm_file(
     input_path: PathBuf,
     output_path: PathBuf,
     metadata: Metadata,
 ) -> Result<()> {
+    write_atomically(&output_path, &annotated_wasm(&input_path, &metadata)?)
+}
+
+/// Returns the module at `input_path` annotated with `metadata`, replacing
+/// the metadata it's already annotated with
+fn annotated_wasm(input_path: &Path, metadata: &Metadata) -> Result<Vec<u8>> {
     let buf: Vec<u8> = std::fs::read(input_path)?;
-    let metadata_json = serde_json::to_vec(&metadata)?;
+    let metadata_json = serde_json::to_vec(metadata)?;
 
     let mut module = walrus::Module::from_buffer(buf.as_slice())?;
+    remove_metadata(&mut module);
 
     let custom_section = walrus::RawCustomSection {
         name: String::from(KUBEWARDEN_CUSTOM_SECTION_METADATA),
@@ -83,13 +342,36 @@ fn write_annotated_wasm_file(
     };
     module.customs.add(custom_section);
 
-    module.emit_wasm_file(output_path)?;
-    Ok(())
+    Ok(module.emit_wasm())
+}
+
+/// Optimizes an annotated policy the way `annotate --optimize-level` does:
+/// the code is optimized without the metadata, which is then added back
+/// untouched. Returns the optimized module.
+pub(crate) fn optimize_annotated(
+    wasm_path: &Path,
+    metadata: &Metadata,
+    level: OptimizeLevel,
+) -> Result<(Vec<u8>, optimize::OptimizationReport)> {
+    let mut module = walrus::Module::from_buffer(&fs::read(wasm_path)?)?;
+    remove_metadata(&mut module);
+    let code = tempfile::Builder::new().suffix(".wasm").tempfile()?;
+    fs::write(code.path(), module.emit_wasm())?;
+
+    let optimized = tempfile::Builder::new().suffix(".wasm").tempfile()?;
+    let report = optimize::optimize(
+        code.path(),
+        optimized.path(),
+        level,
+        metadata.execution_mode,
+    )?;
+    Ok((annotated_wasm(optimized.path(), metadata)?, report))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rstest::rstest;
     use std::io::Write;
     use tempfile::tempdir;
 
@@ -136,7 +418,9 @@ mod tests {
         );
         let metadata = prepare_metadata(
             PathBuf::from("irrelevant.wasm"),
-            file_path,
+            Some(file_path),
+            &Overrides::default(),
+            true,
             backend_detector,
             None,
         )?;
@@ -187,7 +471,9 @@ mod tests {
         );
         let metadata = prepare_metadata(
             PathBuf::from("irrelevant.wasm"),
-            file_path,
+            Some(file_path),
+            &Overrides::default(),
+            true,
             backend_detector,
             None,
         )?;
@@ -232,7 +518,9 @@ mod tests {
         );
         let metadata = prepare_metadata(
             PathBuf::from("irrelevant.wasm"),
-            file_path,
+            Some(file_path),
+            &Overrides::default(),
+            true,
             backend_detector,
             None,
         )?;
@@ -272,7 +560,9 @@ mod tests {
         );
         let metadata = prepare_metadata(
             PathBuf::from("irrelevant.wasm"),
-            file_path,
+            Some(file_path),
+            &Overrides::default(),
+            true,
             backend_detector,
             Some("readme contents"),
         )?;
@@ -286,6 +576,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_invalid_metadata_reports_the_path_of_the_error() -> Result<()> {
+        let dir = tempdir()?;
+
+        let file_path = dir.path().join("metadata.yml");
+        let mut file = File::create(file_path.clone())?;
+
+        let raw_metadata = r#"
+        rules:
+        - apiGroups: [""]
+          apiVersions: ["v1"]
+          resources: ["pods"]
+          operations: ["CREATE", "PATCH"]
+        mutating: false
+        executionMode: kubewarden-wapc
+        "#;
+
+        write!(file, "{}", raw_metadata)?;
+
+        let backend_detector = BackendDetector::new(
+            mock_rego_policy_detector_false,
+            mock_protocol_version_detector_v1,
+        );
+        let error = prepare_metadata(
+            PathBuf::from("irrelevant.wasm"),
+            Some(file_path),
+            &Overrides::default(),
+            true,
+            backend_detector,
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("rules[0].operations[1]"), "{error}");
+        assert!(error.contains("PATCH"), "{error}");
+
+        Ok(())
+    }
+
     #[test]
     fn test_final_metadata_for_a_rego_policy() -> Result<()> {
         let dir = tempdir()?;
@@ -314,7 +644,9 @@ mod tests {
         );
         let metadata = prepare_metadata(
             PathBuf::from("irrelevant.wasm"),
-            file_path,
+            Some(file_path),
+            &Overrides::default(),
+            true,
             backend_detector,
             None,
         );
@@ -326,4 +658,212 @@ mod tests {
 
         Ok(())
     }
+
+    #[rstest]
+    #[case::field("mutating", vec![Segment::Field("mutating")])]
+    #[case::index(
+        "rules[0].operations[1]",
+        vec![
+            Segment::Field("rules"),
+            Segment::Index(0),
+            Segment::Field("operations"),
+            Segment::Index(1),
+        ]
+    )]
+    #[case::annotation(
+        "annotations.io.kubewarden.policy.title",
+        vec![Segment::Field("annotations"), Segment::Field("io.kubewarden.policy.title")]
+    )]
+    fn test_set_paths(#[case] path: &str, #[case] expected: Vec<Segment>) {
+        assert_eq!(segments(path).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::empty_field("rules..operations")]
+    #[case::not_an_index("rules[first]")]
+    #[case::unclosed_index("rules[0")]
+    fn test_invalid_set_paths(#[case] path: &str) {
+        assert!(segments(path).is_err());
+    }
+
+    #[rstest]
+    #[case::group("apps/v1/deployments:CREATE,UPDATE", "apps", "v1", "deployments", vec!["CREATE", "UPDATE"])]
+    #[case::core_group("v1/pods:CREATE", "", "v1", "pods", vec!["CREATE"])]
+    #[case::explicit_core_group("/v1/pods:*", "", "v1", "pods", vec!["*"])]
+    fn test_rules_from_flags(
+        #[case] rule: &str,
+        #[case] group: &str,
+        #[case] version: &str,
+        #[case] resource: &str,
+        #[case] operations: Vec<&str>,
+    ) {
+        let expected = serde_yaml::to_value(serde_json::json!({
+            "apiGroups": [group],
+            "apiVersions": [version],
+            "resources": [resource],
+            "operations": operations,
+        }))
+        .unwrap();
+        assert_eq!(parse_rule(rule).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::no_operations("v1/pods")]
+    #[case::no_resource("v1:CREATE")]
+    #[case::too_many_segments("a/b/v1/pods:CREATE")]
+    fn test_invalid_rules_from_flags(#[case] rule: &str) {
+        assert!(parse_rule(rule).is_err());
+    }
+
+    #[test]
+    fn test_metadata_from_flags_only() -> Result<()> {
+        let overrides = Overrides {
+            set: vec![
+                "executionMode=kubewarden-wapc".to_string(),
+                "annotations.io.kubewarden.policy.version=1.0".to_string(),
+                "rules[0].operations[1]=UPDATE".to_string(),
+            ],
+            mutating: true,
+            rules: vec!["v1/pods:CREATE".to_string()],
+            settings_schema: None,
+        };
+        let backend_detector = BackendDetector::new(
+            mock_rego_policy_detector_false,
+            mock_protocol_version_detector_v1,
+        );
+        let metadata = prepare_metadata(
+            PathBuf::from("irrelevant.wasm"),
+            None,
+            &overrides,
+            true,
+            backend_detector,
+            None,
+        )?;
+
+        assert!(metadata.mutating);
+        assert_eq!(metadata.rules.len(), 1);
+        assert_eq!(metadata.rules[0].operations.len(), 2);
+        assert_eq!(
+            metadata
+                .annotations
+                .unwrap()
+                .get("io.kubewarden.policy.version"),
+            Some(&String::from("1.0")),
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_overrides_the_metadata_file() -> Result<()> {
+        let mut document: Value = serde_yaml::from_str(
+            r#"
+        rules:
+        - apiGroups: [""]
+          apiVersions: ["v1"]
+          resources: ["pods"]
+          operations: ["CREATE"]
+        mutating: false
+        "#,
+        )?;
+        let overrides = Overrides {
+            set: vec!["rules[0].resources=[pods, services]".to_string()],
+            mutating: true,
+            rules: vec!["apps/v1/deployments:UPDATE".to_string()],
+            settings_schema: None,
+        };
+        apply_overrides(&mut document, &overrides)?;
+
+        assert_eq!(document["mutating"], Value::Bool(true));
+        assert_eq!(
+            document["rules"][0]["resources"],
+            serde_yaml::from_str::<Value>("[pods, services]")?
+        );
+        assert_eq!(document["rules"][1]["apiGroups"][0], Value::from("apps"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_unknown_field_is_rejected() {
+        let overrides = Overrides {
+            set: vec!["mutatin=true".to_string()],
+            ..Default::default()
+        };
+        let backend_detector = BackendDetector::new(
+            mock_rego_policy_detector_false,
+            mock_protocol_version_detector_v1,
+        );
+        let error = prepare_metadata(
+            PathBuf::from("irrelevant.wasm"),
+            None,
+            &overrides,
+            true,
+            backend_detector,
+            None,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("mutatin"), "{error}");
+    }
+
+    fn metadata_sections(path: &std::path::Path) -> usize {
+        wasmparser::Parser::new(0)
+            .parse_all(&fs::read(path).unwrap())
+            .filter(|payload| {
+                matches!(
+                    payload,
+                    Ok(wasmparser::Payload::CustomSection(section))
+                        if section.name() == KUBEWARDEN_CUSTOM_SECTION_METADATA
+                )
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_annotate_in_place_and_strip() -> Result<()> {
+        let dir = tempdir()?;
+        let wasm_path = dir.path().join("policy.wasm");
+        fs::write(&wasm_path, walrus::Module::default().emit_wasm())?;
+
+        // annotating twice replaces the metadata
+        for _ in 0..2 {
+            write_annotated_wasm_file(wasm_path.clone(), wasm_path.clone(), Metadata::default())?;
+        }
+        assert_eq!(metadata_sections(&wasm_path), 1);
+
+        strip_annotation(wasm_path.clone(), wasm_path.clone())?;
+        assert_eq!(metadata_sections(&wasm_path), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_settings_schema_is_embedded() -> Result<()> {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"replicas": {"type": "integer"}}
+        });
+        let overrides = Overrides {
+            rules: vec!["v1/pods:CREATE".to_string()],
+            settings_schema: Some(schema.clone()),
+            ..Default::default()
+        };
+        let backend_detector = BackendDetector::new(
+            mock_rego_policy_detector_false,
+            mock_protocol_version_detector_v1,
+        );
+        let metadata = prepare_metadata(
+            PathBuf::from("irrelevant.wasm"),
+            None,
+            &overrides,
+            true,
+            backend_detector,
+            None,
+        )?;
+
+        assert_eq!(crate::inspect::settings_schema(&metadata)?, Some(schema));
+
+        Ok(())
+    }
 }
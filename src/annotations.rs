@@ -0,0 +1,9 @@
+//! Annotations of the policy metadata defined by kwctl, next to the ones of
+//! `policy_evaluator::constants`.
+
+/// Version of the policy, used by Artifact Hub
+pub(crate) const POLICY_VERSION_ANNOTATION: &str = "io.kubewarden.policy.version";
+
+/// JSON Schema of the settings of the policy, embedded by
+/// `kwctl annotate --settings-schema`
+pub(crate) const SETTINGS_SCHEMA_ANNOTATION: &str = "io.kubewarden.policy.settings-schema";
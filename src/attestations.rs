@@ -0,0 +1,698 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use policy_evaluator::policy_fetcher::{
+    oci_client::{
+        client::{Config, ImageLayer},
+        manifest::{OciDescriptor, OciImageManifest, OciManifest, OCI_IMAGE_MEDIA_TYPE},
+        Client, Reference,
+    },
+    sigstore::{
+        crypto::{CosignVerificationKey, Signature},
+        registry::ClientConfig,
+    },
+    sources::Sources,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::config::registry_auth::registry_auth;
+
+const SPDX_MEDIA_TYPE: &str = "application/spdx+json";
+const CYCLONEDX_MEDIA_TYPE: &str = "application/vnd.cyclonedx+json";
+const IN_TOTO_MEDIA_TYPE: &str = "application/vnd.in-toto+json";
+const DSSE_ENVELOPE_MEDIA_TYPE: &str = "application/vnd.dsse.envelope.v1+json";
+// Prefix of the predicate types of all the SLSA provenance versions
+const SLSA_PROVENANCE_PREDICATE_TYPE_PREFIX: &str = "https://slsa.dev/provenance/";
+
+// The referrers carry no configuration, as recommended by the OCI image spec
+const EMPTY_CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const EMPTY_CONFIG_DATA: &[u8] = b"{}";
+
+/// A supply-chain document to be attached to a policy
+#[derive(Debug)]
+pub(crate) enum Attestation {
+    Sbom(Vec<u8>),
+    Provenance(Vec<u8>),
+}
+
+impl Attestation {
+    pub(crate) fn sbom(path: &Path) -> Result<Self> {
+        read_document(path).map(Self::Sbom)
+    }
+
+    pub(crate) fn provenance(path: &Path) -> Result<Self> {
+        read_document(path).map(Self::Provenance)
+    }
+
+    fn data(&self) -> &[u8] {
+        match self {
+            Self::Sbom(data) | Self::Provenance(data) => data,
+        }
+    }
+
+    /// Media type of the document, used also as artifact type of the referrer.
+    ///
+    /// SBOMs must be SPDX or CycloneDX JSON documents, provenances must be
+    /// in-toto statements, optionally wrapped inside of a DSSE envelope.
+    fn media_type(&self) -> Result<&'static str> {
+        let document: Value = serde_json::from_slice(self.data())?;
+        match self {
+            Self::Sbom(_) if document.get("spdxVersion").is_some() => Ok(SPDX_MEDIA_TYPE),
+            Self::Sbom(_) if document.get("bomFormat") == Some(&Value::from("CycloneDX")) => {
+                Ok(CYCLONEDX_MEDIA_TYPE)
+            }
+            Self::Sbom(_) => Err(anyhow!(
+                "the SBOM is neither a SPDX nor a CycloneDX JSON document"
+            )),
+            Self::Provenance(_) if document.get("payloadType").is_some() => {
+                Ok(DSSE_ENVELOPE_MEDIA_TYPE)
+            }
+            Self::Provenance(_)
+                if document
+                    .get("_type")
+                    .and_then(Value::as_str)
+                    .is_some_and(|t| t.starts_with("https://in-toto.io/Statement/")) =>
+            {
+                Ok(IN_TOTO_MEDIA_TYPE)
+            }
+            Self::Provenance(_) => Err(anyhow!(
+                "the provenance is neither an in-toto statement nor a DSSE envelope"
+            )),
+        }
+    }
+}
+
+fn read_document(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))
+}
+
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+/// Uploads the attestations as OCI referrers of the policy referenced by
+/// `immutable_ref`, returning the immutable references of the referrers.
+///
+/// Each attestation is pushed as an artifact whose `subject` is the manifest
+/// of the policy, which makes it discoverable via the referrers API of the
+/// registry.
+pub(crate) async fn attach(
+    immutable_ref: &str,
+    attestations: &[Attestation],
+    sources: Option<&Sources>,
+) -> Result<Vec<String>> {
+    let image = immutable_ref
+        .strip_prefix("registry://")
+        .unwrap_or(immutable_ref);
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let auth = registry_auth(image)?;
+
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let client = Client::new(client_config.into());
+    let (manifest, manifest_digest) = client
+        .pull_manifest_raw(&reference, &auth, &[OCI_IMAGE_MEDIA_TYPE])
+        .await?;
+    let subject = OciDescriptor {
+        media_type: OCI_IMAGE_MEDIA_TYPE.to_string(),
+        digest: manifest_digest,
+        size: manifest.len() as i64,
+        ..Default::default()
+    };
+
+    let mut referrers = Vec::with_capacity(attestations.len());
+    for attestation in attestations {
+        let media_type = attestation.media_type()?;
+        let manifest = referrer_manifest(attestation.data(), media_type, &subject);
+        let digest = sha256_digest(&serde_json::to_vec(&OciManifest::Image(manifest.clone()))?);
+        let referrer = Reference::with_digest(
+            reference.registry().to_string(),
+            reference.repository().to_string(),
+            digest,
+        );
+
+        client
+            .push(
+                &referrer,
+                &[ImageLayer::new(
+                    attestation.data().to_vec().into(),
+                    media_type.to_string(),
+                    None,
+                )],
+                Config::new(
+                    EMPTY_CONFIG_DATA.to_vec().into(),
+                    EMPTY_CONFIG_MEDIA_TYPE.to_string(),
+                    None,
+                ),
+                &auth,
+                Some(manifest),
+            )
+            .await?;
+        debug!(referrer = %referrer, media_type, "attestation pushed");
+        referrers.push(format!("registry://{referrer}"));
+    }
+
+    info!(
+        policy = immutable_ref,
+        count = referrers.len(),
+        "attestations attached"
+    );
+    Ok(referrers)
+}
+
+/// Claims the SLSA provenance of a policy must satisfy
+#[derive(Debug)]
+pub(crate) struct ProvenanceRequirements {
+    /// PEM encoded public keys, one of them must have signed the provenance
+    pub(crate) keys: Vec<Vec<u8>>,
+    pub(crate) builder_id: String,
+    /// URI of the repository the policy must have been built from
+    pub(crate) source_repository: Option<String>,
+}
+
+/// Verifies the SLSA provenance attached to the policy referenced by `uri`,
+/// whose manifest digest has already been verified. At least one of the
+/// provenance referrers of the policy must be signed by one of the given keys
+/// and satisfy the requirements.
+pub(crate) async fn verify_provenance(
+    uri: &str,
+    verified_manifest_digest: &str,
+    requirements: &ProvenanceRequirements,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let image = uri.strip_prefix("registry://").ok_or_else(|| {
+        anyhow!(
+            "{} is not hosted on a registry, its attestations cannot be verified",
+            uri
+        )
+    })?;
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let auth = registry_auth(image)?;
+
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let client = Client::new(client_config.into());
+    let (manifest, manifest_digest) = client
+        .pull_manifest_raw(&reference, &auth, &[OCI_IMAGE_MEDIA_TYPE])
+        .await?;
+    if manifest_digest != verified_manifest_digest {
+        return Err(anyhow!(
+            "the manifest digest of {} changed while it was being verified",
+            uri
+        ));
+    }
+    // the provenance can describe either the OCI artifact, or the wasm module
+    let manifest: OciImageManifest = serde_json::from_slice(&manifest)?;
+    let subject_digests: Vec<&str> = std::iter::once(manifest_digest.as_str())
+        .chain(manifest.layers.iter().map(|layer| layer.digest.as_str()))
+        .collect();
+
+    let subject = Reference::with_digest(
+        reference.registry().to_string(),
+        reference.repository().to_string(),
+        manifest_digest.clone(),
+    );
+    let referrers = client
+        .pull_referrers(&subject, Some(DSSE_ENVELOPE_MEDIA_TYPE))
+        .await
+        .map_err(|e| anyhow!("cannot list the referrers of {}: {}", uri, e))?;
+
+    let mut errors = Vec::new();
+    for descriptor in &referrers.manifests {
+        let referrer = Reference::with_digest(
+            reference.registry().to_string(),
+            reference.repository().to_string(),
+            descriptor.digest.clone(),
+        );
+        let (referrer_manifest, _) = client
+            .pull_manifest_raw(&referrer, &auth, &[OCI_IMAGE_MEDIA_TYPE])
+            .await?;
+        let referrer_manifest: OciImageManifest = serde_json::from_slice(&referrer_manifest)?;
+        for layer in referrer_manifest
+            .layers
+            .iter()
+            .filter(|layer| layer.media_type == DSSE_ENVELOPE_MEDIA_TYPE)
+        {
+            let mut envelope = Vec::new();
+            client.pull_blob(&referrer, layer, &mut envelope).await?;
+            match check_provenance(&envelope, requirements, &subject_digests) {
+                Ok(()) => {
+                    info!(attestation = %referrer, "SLSA provenance verified");
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!(attestation = %referrer, error = %e, "attestation rejected");
+                    errors.push(format!("  - {referrer}: {e}"));
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Err(anyhow!("policy {} has no SLSA provenance attached", uri))
+    } else {
+        Err(anyhow!(
+            "no SLSA provenance of {} satisfies the requirements:\n{}",
+            uri,
+            errors.join("\n")
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DsseEnvelope {
+    payload_type: String,
+    payload: String,
+    signatures: Vec<DsseSignature>,
+}
+
+#[derive(Deserialize)]
+struct DsseSignature {
+    sig: String,
+}
+
+/// Pre-Authentication Encoding of a DSSE envelope, which is what is actually
+/// signed
+fn dsse_pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut pae = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    pae.extend_from_slice(payload);
+    pae
+}
+
+/// Checks the signature and the claims of a DSSE envelope holding a SLSA
+/// provenance about one of the given subject digests
+fn check_provenance(
+    envelope: &[u8],
+    requirements: &ProvenanceRequirements,
+    subject_digests: &[&str],
+) -> Result<()> {
+    let envelope: DsseEnvelope =
+        serde_json::from_slice(envelope).map_err(|e| anyhow!("not a DSSE envelope: {}", e))?;
+    if envelope.payload_type != IN_TOTO_MEDIA_TYPE {
+        return Err(anyhow!("unexpected payload type {}", envelope.payload_type));
+    }
+    let payload = STANDARD.decode(&envelope.payload)?;
+
+    let pae = dsse_pae(&envelope.payload_type, &payload);
+    let verification_keys = requirements
+        .keys
+        .iter()
+        .map(|key| CosignVerificationKey::try_from_pem(key))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let signed = envelope.signatures.iter().any(|signature| {
+        STANDARD.decode(&signature.sig).is_ok_and(|sig| {
+            verification_keys
+                .iter()
+                .any(|key| key.verify_signature(Signature::Raw(&sig), &pae).is_ok())
+        })
+    });
+    if !signed {
+        return Err(anyhow!("not signed by any of the verification keys"));
+    }
+
+    let statement: Value = serde_json::from_slice(&payload)?;
+    let predicate_type = statement["predicateType"].as_str().unwrap_or_default();
+    if !predicate_type.starts_with(SLSA_PROVENANCE_PREDICATE_TYPE_PREFIX) {
+        return Err(anyhow!("not a SLSA provenance: {}", predicate_type));
+    }
+    let describes_policy = statement["subject"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|subject| subject["digest"]["sha256"].as_str())
+        .any(|digest| subject_digests.contains(&format!("sha256:{digest}").as_str()));
+    if !describes_policy {
+        return Err(anyhow!("the provenance is about a different artifact"));
+    }
+
+    let predicate = &statement["predicate"];
+    // SLSA v1 first, then v0.2
+    let builder_id = predicate["runDetails"]["builder"]["id"]
+        .as_str()
+        .or_else(|| predicate["builder"]["id"].as_str())
+        .unwrap_or_default();
+    if builder_id != requirements.builder_id {
+        return Err(anyhow!(
+            "built by {}, expected {}",
+            builder_id,
+            requirements.builder_id
+        ));
+    }
+
+    if let Some(source_repository) = &requirements.source_repository {
+        let sources = source_uris(predicate);
+        if !sources
+            .iter()
+            .any(|uri| same_repository(uri, source_repository))
+        {
+            return Err(anyhow!(
+                "built from {}, expected {}",
+                sources.join(", "),
+                source_repository
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// URIs of the sources a provenance claims the artifact was built from
+fn source_uris(predicate: &Value) -> Vec<String> {
+    let v1_sources = predicate["buildDefinition"]["externalParameters"]["workflow"]["repository"]
+        .as_str()
+        .into_iter()
+        .chain(
+            predicate["buildDefinition"]["resolvedDependencies"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|dependency| dependency["uri"].as_str()),
+        );
+    let v0_2_sources = predicate["invocation"]["configSource"]["uri"]
+        .as_str()
+        .into_iter()
+        .chain(
+            predicate["materials"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|material| material["uri"].as_str()),
+        );
+    v1_sources.chain(v0_2_sources).map(str::to_owned).collect()
+}
+
+/// The host and the path of a repository URI, ignoring the `git+` scheme
+/// prefix, the user, the git ref and the `.git` suffix used by SLSA
+/// provenances
+fn repository(uri: &str) -> Option<(String, String)> {
+    let uri = uri.strip_prefix("git+").unwrap_or(uri);
+    let (host, path) = match Url::parse(uri) {
+        Ok(url) => (url.host_str()?.to_ascii_lowercase(), url.path().to_owned()),
+        // scp-like syntax, like git@github.com:kubewarden/policy.git
+        Err(_) => {
+            let (authority, path) = uri.split_once(':')?;
+            let host = authority
+                .rsplit_once('@')
+                .map_or(authority, |(_, host)| host);
+            (host.to_ascii_lowercase(), path.to_owned())
+        }
+    };
+    // the git ref follows the path, like in repository@refs/tags/v1.0.0
+    let path = path.split_once('@').map_or(path.as_str(), |(path, _)| path);
+    let path = path
+        .trim_matches('/')
+        .trim_end_matches(".git")
+        .trim_end_matches('/');
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some((host, path.to_owned()))
+}
+
+/// Compares the hosts and the paths of repository URIs
+fn same_repository(uri: &str, expected: &str) -> bool {
+    match (repository(uri), repository(expected)) {
+        (Some(repository), Some(expected)) => repository == expected,
+        _ => false,
+    }
+}
+
+fn referrer_manifest(data: &[u8], media_type: &str, subject: &OciDescriptor) -> OciImageManifest {
+    OciImageManifest {
+        media_type: Some(OCI_IMAGE_MEDIA_TYPE.to_string()),
+        artifact_type: Some(media_type.to_string()),
+        config: OciDescriptor {
+            media_type: EMPTY_CONFIG_MEDIA_TYPE.to_string(),
+            digest: sha256_digest(EMPTY_CONFIG_DATA),
+            size: EMPTY_CONFIG_DATA.len() as i64,
+            ..Default::default()
+        },
+        layers: vec![OciDescriptor {
+            media_type: media_type.to_string(),
+            digest: sha256_digest(data),
+            size: data.len() as i64,
+            ..Default::default()
+        }],
+        subject: Some(subject.clone()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::policy_fetcher::sigstore::crypto::{SigStoreKeyPair, SigningScheme};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::spdx(
+        Attestation::Sbom(br#"{"spdxVersion": "SPDX-2.3"}"#.to_vec()),
+        Some(SPDX_MEDIA_TYPE)
+    )]
+    #[case::cyclonedx(
+        Attestation::Sbom(br#"{"bomFormat": "CycloneDX", "specVersion": "1.5"}"#.to_vec()),
+        Some(CYCLONEDX_MEDIA_TYPE)
+    )]
+    #[case::unknown_sbom(Attestation::Sbom(br#"{"foo": "bar"}"#.to_vec()), None)]
+    #[case::in_toto_statement(
+        Attestation::Provenance(
+            br#"{"_type": "https://in-toto.io/Statement/v1", "predicateType": "https://slsa.dev/provenance/v1"}"#.to_vec()
+        ),
+        Some(IN_TOTO_MEDIA_TYPE)
+    )]
+    #[case::dsse_envelope(
+        Attestation::Provenance(
+            br#"{"payloadType": "application/vnd.in-toto+json", "payload": "", "signatures": []}"#.to_vec()
+        ),
+        Some(DSSE_ENVELOPE_MEDIA_TYPE)
+    )]
+    #[case::not_json(Attestation::Provenance(b"provenance".to_vec()), None)]
+    fn attestation_media_type(#[case] attestation: Attestation, #[case] expected: Option<&str>) {
+        assert_eq!(attestation.media_type().ok(), expected);
+    }
+
+    #[test]
+    fn referrer_manifest_references_subject() {
+        let subject = OciDescriptor {
+            media_type: OCI_IMAGE_MEDIA_TYPE.to_string(),
+            digest: "sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c"
+                .to_string(),
+            size: 721,
+            ..Default::default()
+        };
+        let data = br#"{"spdxVersion": "SPDX-2.3"}"#;
+
+        let manifest = referrer_manifest(data, SPDX_MEDIA_TYPE, &subject);
+
+        assert_eq!(manifest.artifact_type.as_deref(), Some(SPDX_MEDIA_TYPE));
+        assert_eq!(manifest.subject, Some(subject));
+        assert_eq!(
+            manifest.config.digest,
+            "sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(manifest.layers[0].digest, sha256_digest(data));
+        assert_eq!(manifest.layers[0].size, data.len() as i64);
+    }
+
+    const POLICY_DIGEST: &str =
+        "sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c";
+    const BUILDER_ID: &str =
+        "https://github.com/slsa-framework/slsa-github-generator/.github/workflows/generator_generic_slsa3.yml@refs/tags/v2.0.0";
+
+    fn signed_envelope(statement: &Value, key: &[u8]) -> Vec<u8> {
+        let payload = serde_json::to_vec(statement).unwrap();
+        let signer = SigStoreKeyPair::from_encrypted_pem(key, b"kubewarden")
+            .unwrap()
+            .to_sigstore_signer(&SigningScheme::default())
+            .unwrap();
+        let sig = signer
+            .sign(&dsse_pae(IN_TOTO_MEDIA_TYPE, &payload))
+            .unwrap();
+        serde_json::to_vec(&serde_json::json!({
+            "payloadType": IN_TOTO_MEDIA_TYPE,
+            "payload": STANDARD.encode(payload),
+            "signatures": [{"keyid": "", "sig": STANDARD.encode(sig)}],
+        }))
+        .unwrap()
+    }
+
+    fn slsa_v1_statement(digest: &str, builder_id: &str) -> Value {
+        serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v1",
+            "subject": [{"name": "policy.wasm", "digest": {"sha256": digest}}],
+            "predicateType": "https://slsa.dev/provenance/v1",
+            "predicate": {
+                "buildDefinition": {
+                    "externalParameters": {
+                        "workflow": {
+                            "ref": "refs/tags/v0.1.14",
+                            "repository": "https://github.com/kubewarden/safe-labels-policy",
+                            "path": ".github/workflows/release.yml"
+                        }
+                    },
+                    "resolvedDependencies": [{
+                        "uri": "git+https://github.com/kubewarden/safe-labels-policy@refs/tags/v0.1.14"
+                    }]
+                },
+                "runDetails": {"builder": {"id": builder_id}}
+            }
+        })
+    }
+
+    fn requirements(source_repository: Option<&str>) -> ProvenanceRequirements {
+        ProvenanceRequirements {
+            keys: vec![include_bytes!("../tests/data/sigstore/cosign1.pub").to_vec()],
+            builder_id: BUILDER_ID.to_string(),
+            source_repository: source_repository.map(str::to_owned),
+        }
+    }
+
+    #[rstest]
+    #[case::builder(None)]
+    #[case::source_repository(Some("https://github.com/kubewarden/safe-labels-policy"))]
+    #[case::source_repository_git_uri(Some(
+        "git+https://github.com/kubewarden/safe-labels-policy.git"
+    ))]
+    fn valid_provenance(#[case] source_repository: Option<&str>) {
+        let envelope = signed_envelope(
+            &slsa_v1_statement(POLICY_DIGEST.trim_start_matches("sha256:"), BUILDER_ID),
+            include_bytes!("../tests/data/sigstore/cosign1.key"),
+        );
+
+        check_provenance(
+            &envelope,
+            &requirements(source_repository),
+            &[POLICY_DIGEST],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn slsa_v0_2_provenance() {
+        let statement = serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v0.1",
+            "subject": [{"name": "policy.wasm", "digest": {"sha256": POLICY_DIGEST.trim_start_matches("sha256:")}}],
+            "predicateType": "https://slsa.dev/provenance/v0.2",
+            "predicate": {
+                "builder": {"id": BUILDER_ID},
+                "invocation": {
+                    "configSource": {
+                        "uri": "git+https://github.com/kubewarden/safe-labels-policy@refs/tags/v0.1.14"
+                    }
+                }
+            }
+        });
+        let envelope = signed_envelope(
+            &statement,
+            include_bytes!("../tests/data/sigstore/cosign1.key"),
+        );
+
+        check_provenance(
+            &envelope,
+            &requirements(Some("https://github.com/kubewarden/safe-labels-policy")),
+            &[POLICY_DIGEST],
+        )
+        .unwrap();
+    }
+
+    #[rstest]
+    #[case::other_key(
+        slsa_v1_statement(POLICY_DIGEST.trim_start_matches("sha256:"), BUILDER_ID),
+        include_bytes!("../tests/data/sigstore/cosign2.key").as_slice(),
+        None
+    )]
+    #[case::other_builder(
+        slsa_v1_statement(POLICY_DIGEST.trim_start_matches("sha256:"), "https://example.com/builder"),
+        include_bytes!("../tests/data/sigstore/cosign1.key").as_slice(),
+        None
+    )]
+    #[case::other_artifact(
+        slsa_v1_statement("0000000000000000000000000000000000000000000000000000000000000000", BUILDER_ID),
+        include_bytes!("../tests/data/sigstore/cosign1.key").as_slice(),
+        None
+    )]
+    #[case::other_repository(
+        slsa_v1_statement(POLICY_DIGEST.trim_start_matches("sha256:"), BUILDER_ID),
+        include_bytes!("../tests/data/sigstore/cosign1.key").as_slice(),
+        Some("https://github.com/kubewarden/safe-labels-policy-fork")
+    )]
+    fn rejected_provenance(
+        #[case] statement: Value,
+        #[case] key: &[u8],
+        #[case] source_repository: Option<&str>,
+    ) {
+        let envelope = signed_envelope(&statement, key);
+
+        assert!(check_provenance(
+            &envelope,
+            &requirements(source_repository),
+            &[POLICY_DIGEST]
+        )
+        .is_err());
+    }
+
+    #[rstest]
+    #[case::git_suffix(
+        "git+https://github.com/kubewarden/safe-labels-policy.git@refs/tags/v0.1.14",
+        "https://github.com/kubewarden/safe-labels-policy",
+        true
+    )]
+    #[case::ssh_user(
+        "git+ssh://git@github.com/kubewarden/safe-labels-policy.git",
+        "https://github.com/kubewarden/safe-labels-policy",
+        true
+    )]
+    #[case::scp_like(
+        "git@github.com:kubewarden/safe-labels-policy.git",
+        "https://github.com/kubewarden/safe-labels-policy",
+        true
+    )]
+    #[case::other_ssh_repository(
+        "git+ssh://git@github.com/evil/x",
+        "git+ssh://git@github.com/kubewarden/safe-labels-policy",
+        false
+    )]
+    #[case::other_host(
+        "https://gitlab.com/kubewarden/safe-labels-policy",
+        "https://github.com/kubewarden/safe-labels-policy",
+        false
+    )]
+    #[case::user_only("git+ssh://git@", "git+ssh://git@", false)]
+    fn repositories(#[case] uri: &str, #[case] expected: &str, #[case] same: bool) {
+        assert_eq!(same_repository(uri, expected), same);
+    }
+
+    #[test]
+    fn tampered_provenance() {
+        let envelope = signed_envelope(
+            &slsa_v1_statement(POLICY_DIGEST.trim_start_matches("sha256:"), BUILDER_ID),
+            include_bytes!("../tests/data/sigstore/cosign1.key"),
+        );
+        let mut envelope: Value = serde_json::from_slice(&envelope).unwrap();
+        let other_statement = slsa_v1_statement(
+            POLICY_DIGEST.trim_start_matches("sha256:"),
+            "https://example.com/builder",
+        );
+        envelope["payload"] = Value::from(STANDARD.encode(other_statement.to_string()));
+
+        assert!(check_provenance(
+            &serde_json::to_vec(&envelope).unwrap(),
+            &requirements(None),
+            &[POLICY_DIGEST]
+        )
+        .is_err());
+    }
+}
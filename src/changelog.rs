@@ -0,0 +1,468 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    constants::{KUBEWARDEN_ANNOTATION_POLICY_SOURCE, KUBEWARDEN_ANNOTATION_POLICY_USAGE},
+    policy_fetcher::{sources::Sources, PullDestination},
+    policy_metadata::Metadata,
+};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    annotations::SETTINGS_SCHEMA_ANNOTATION, config::sources::RegistryMirrors,
+    inspect::settings_schema, mirror_health, scaffold,
+};
+
+// Fields of the metadata reported in their own section
+const ANNOTATIONS_FIELD: &str = "annotations";
+const RULES_FIELD: &str = "rules";
+const CONTEXT_AWARE_RESOURCES_FIELD: &str = "contextAwareResources";
+
+/// URI of the policy of `repo` tagged with `tag`
+pub(crate) fn tagged_uri(repo: &str, tag: &str) -> Result<String> {
+    let repo = repo.strip_prefix("registry://").unwrap_or(repo);
+    let name = repo.rsplit('/').next().unwrap_or(repo);
+    if name.contains(':') || name.contains('@') {
+        return Err(anyhow!(
+            "{} must be a repository, without tag or digest",
+            repo
+        ));
+    }
+    Ok(format!("registry://{repo}:{tag}"))
+}
+
+/// Fetches the metadata of the policy, from the annotations of its OCI
+/// manifest when available, otherwise by pulling the policy into a temporary
+/// directory
+pub(crate) async fn fetch_metadata(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Result<Metadata> {
+    let candidates = mirror_health::ordered_candidates(uri, sources, mirrors).await;
+    if let Some(metadata) = scaffold::remote_metadata(&candidates, sources).await? {
+        return Ok(metadata);
+    }
+
+    info!(
+        policy = uri,
+        "the OCI manifest does not contain the policy metadata, pulling the policy"
+    );
+    let tempdir = tempfile::tempdir()?;
+    let policy = crate::pull::pull(
+        uri,
+        sources,
+        mirrors,
+        PullDestination::LocalFile(tempdir.path().join("policy.wasm")),
+    )
+    .await?;
+    Metadata::from_path(&policy.local_path)?
+        .ok_or_else(|| anyhow!("No Kubewarden metadata found inside of '{}'", uri))
+}
+
+/// Renders the changes between two releases of a policy as Markdown
+pub(crate) fn render(
+    repo: &str,
+    from: &str,
+    to: &str,
+    old: &Metadata,
+    new: &Metadata,
+) -> Result<String> {
+    let old_fields = fields(old)?;
+    let new_fields = fields(new)?;
+    let old_annotations = old.annotations.clone().unwrap_or_default();
+    let new_annotations = new.annotations.clone().unwrap_or_default();
+
+    let mut changelog = format!("# {repo}: {from} → {to}\n");
+
+    section(
+        &mut changelog,
+        "Metadata",
+        &metadata_changes(&old_fields, &new_fields),
+    );
+    section(
+        &mut changelog,
+        "Annotations",
+        &annotation_changes(&old_annotations, &new_annotations),
+    );
+
+    let _ = write!(changelog, "\n## Settings\n\n");
+    let old_usage = old_annotations.get(KUBEWARDEN_ANNOTATION_POLICY_USAGE);
+    let new_usage = new_annotations.get(KUBEWARDEN_ANNOTATION_POLICY_USAGE);
+    if old_usage == new_usage {
+        changelog.push_str("No changes to the documentation of the settings.\n");
+    } else {
+        let _ = write!(
+            changelog,
+            "The documentation of the settings changed:\n\n```diff\n{}```\n",
+            line_diff(
+                old_usage.map(String::as_str).unwrap_or_default(),
+                new_usage.map(String::as_str).unwrap_or_default()
+            )
+        );
+    }
+    if let Some(diff) = settings_schema_diff(old, new)? {
+        let _ = write!(
+            changelog,
+            "\nThe JSON Schema of the settings changed:\n\n```diff\n{diff}```\n"
+        );
+    }
+
+    let source = new_annotations
+        .get(KUBEWARDEN_ANNOTATION_POLICY_SOURCE)
+        .or_else(|| old_annotations.get(KUBEWARDEN_ANNOTATION_POLICY_SOURCE));
+    if let Some(source) = source {
+        let _ = write!(changelog, "\n## Commits\n\n");
+        match compare_url(source, from, to) {
+            Some(url) => {
+                let _ = writeln!(changelog, "[Commits between {from} and {to}]({url})");
+            }
+            None => {
+                let _ = writeln!(changelog, "Source code: {source}");
+            }
+        }
+    }
+
+    Ok(changelog)
+}
+
+pub(crate) fn section(changelog: &mut String, title: &str, changes: &[String]) {
+    let _ = write!(changelog, "\n## {title}\n\n");
+    if changes.is_empty() {
+        changelog.push_str("No changes.\n");
+    }
+    for change in changes {
+        let _ = writeln!(changelog, "- {change}");
+    }
+}
+
+/// Top level fields of the metadata, as JSON
+pub(crate) fn fields(metadata: &Metadata) -> Result<BTreeMap<String, Value>> {
+    match serde_json::to_value(metadata)? {
+        Value::Object(fields) => Ok(fields.into_iter().collect()),
+        _ => Err(anyhow!("the metadata is not an object")),
+    }
+}
+
+fn json(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        value => value.to_string(),
+    }
+}
+
+fn join(value: &Value) -> String {
+    match value {
+        Value::Array(values) => values.iter().map(json).collect::<Vec<_>>().join(","),
+        value => json(value),
+    }
+}
+
+fn rule(rule: &Value) -> String {
+    let group = match rule.get("apiGroups").map(join) {
+        Some(group) if !group.is_empty() => group,
+        _ => "core".to_string(),
+    };
+    format!(
+        "`{} {}/{}/{}`",
+        rule.get("operations").map(join).unwrap_or_default(),
+        group,
+        rule.get("apiVersions").map(join).unwrap_or_default(),
+        rule.get("resources").map(join).unwrap_or_default()
+    )
+}
+
+fn context_aware_resource(resource: &Value) -> String {
+    format!(
+        "`{}/{}`",
+        resource.get("apiVersion").map(json).unwrap_or_default(),
+        resource.get("kind").map(json).unwrap_or_default()
+    )
+}
+
+/// Items of the `field` list, rendered by `render`
+fn items(
+    fields: &BTreeMap<String, Value>,
+    field: &str,
+    render: fn(&Value) -> String,
+) -> Vec<String> {
+    fields
+        .get(field)
+        .and_then(Value::as_array)
+        .map(|items| items.iter().map(render).collect())
+        .unwrap_or_default()
+}
+
+pub(crate) fn metadata_changes(
+    old: &BTreeMap<String, Value>,
+    new: &BTreeMap<String, Value>,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        if [
+            ANNOTATIONS_FIELD,
+            RULES_FIELD,
+            CONTEXT_AWARE_RESOURCES_FIELD,
+        ]
+        .contains(&key.as_str())
+        {
+            continue;
+        }
+        let old_value = old.get(key).unwrap_or(&Value::Null);
+        let new_value = new.get(key).unwrap_or(&Value::Null);
+        if old_value != new_value {
+            changes.push(format!(
+                "`{key}`: `{}` → `{}`",
+                json(old_value),
+                json(new_value)
+            ));
+        }
+    }
+
+    for (field, label, render) in [
+        (RULES_FIELD, "Rule", rule as fn(&Value) -> String),
+        (
+            CONTEXT_AWARE_RESOURCES_FIELD,
+            "Context aware resource",
+            context_aware_resource,
+        ),
+    ] {
+        let old_items = items(old, field, render);
+        let new_items = items(new, field, render);
+        for item in new_items.iter().filter(|item| !old_items.contains(item)) {
+            changes.push(format!("{label} added: {item}"));
+        }
+        for item in old_items.iter().filter(|item| !new_items.contains(item)) {
+            changes.push(format!("{label} removed: {item}"));
+        }
+    }
+
+    changes
+}
+
+pub(crate) fn annotation_changes(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (key, value) in new {
+        // reported in the settings section
+        if is_settings_annotation(key) {
+            continue;
+        }
+        match old.get(key) {
+            None => changes.push(format!("Added `{key}`: {value}")),
+            Some(old_value) if old_value != value => {
+                changes.push(format!("Changed `{key}`: {old_value} → {value}"))
+            }
+            Some(_) => {}
+        }
+    }
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        if !is_settings_annotation(key) {
+            changes.push(format!("Removed `{key}`"));
+        }
+    }
+    changes
+}
+
+fn is_settings_annotation(key: &str) -> bool {
+    key == KUBEWARDEN_ANNOTATION_POLICY_USAGE || key == SETTINGS_SCHEMA_ANNOTATION
+}
+
+/// Diff of the settings JSON Schemas embedded into the metadata, `None` when
+/// they are the same
+pub(crate) fn settings_schema_diff(old: &Metadata, new: &Metadata) -> Result<Option<String>> {
+    let old_schema = settings_schema(old)?;
+    let new_schema = settings_schema(new)?;
+    if old_schema == new_schema {
+        return Ok(None);
+    }
+    let pretty = |schema: Option<Value>| {
+        schema
+            .map(|schema| serde_json::to_string_pretty(&schema))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    };
+    Ok(Some(line_diff(&pretty(old_schema)?, &pretty(new_schema)?)))
+}
+
+/// Unified diff of the lines of `old` and `new`, without hunk headers
+pub(crate) fn line_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            let _ = writeln!(diff, " {}", old[i]);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            let _ = writeln!(diff, "+{}", new[j]);
+            j += 1;
+        } else {
+            let _ = writeln!(diff, "-{}", old[i]);
+            i += 1;
+        }
+    }
+    diff
+}
+
+/// Link to the commits between the two tags, for the source code hosting
+/// services known to provide one
+fn compare_url(source: &str, from: &str, to: &str) -> Option<String> {
+    let url = url::Url::parse(source).ok()?;
+    let base = source.trim_end_matches('/').trim_end_matches(".git");
+    match url.host_str()? {
+        "github.com" => Some(format!("{base}/compare/{from}...{to}")),
+        "gitlab.com" => Some(format!("{base}/-/compare/{from}...{to}")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn metadata(yaml: &str) -> Metadata {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[rstest]
+    #[case::with_scheme(
+        "registry://ghcr.io/kubewarden/policies/pod-privileged",
+        Some("registry://ghcr.io/kubewarden/policies/pod-privileged:v1.0.0")
+    )]
+    #[case::without_scheme(
+        "localhost:5000/pod-privileged",
+        Some("registry://localhost:5000/pod-privileged:v1.0.0")
+    )]
+    #[case::with_tag("ghcr.io/kubewarden/policies/pod-privileged:v0.2.5", None)]
+    fn repository_uri(#[case] repo: &str, #[case] expected: Option<&str>) {
+        assert_eq!(tagged_uri(repo, "v1.0.0").ok().as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case::github(
+        "https://github.com/kubewarden/pod-privileged-policy",
+        Some("https://github.com/kubewarden/pod-privileged-policy/compare/v1.0.0...v1.2.0")
+    )]
+    #[case::gitlab(
+        "https://gitlab.com/acme/policy.git",
+        Some("https://gitlab.com/acme/policy/-/compare/v1.0.0...v1.2.0")
+    )]
+    #[case::unknown_host("https://git.example.com/acme/policy", None)]
+    fn commits_link(#[case] source: &str, #[case] expected: Option<&str>) {
+        assert_eq!(compare_url(source, "v1.0.0", "v1.2.0").as_deref(), expected);
+    }
+
+    #[test]
+    fn settings_documentation_diff() {
+        assert_eq!(
+            line_diff(
+                "# Settings\nfoo: bar\n",
+                "# Settings\nfoo: baz\nnew: true\n"
+            ),
+            " # Settings\n-foo: bar\n+foo: baz\n+new: true\n"
+        );
+    }
+
+    #[test]
+    fn settings_schema_changes() {
+        let old = metadata(
+            r#"
+annotations:
+  io.kubewarden.policy.settings-schema: '{"additionalProperties": false}'
+"#,
+        );
+        let new = metadata(
+            r#"
+annotations:
+  io.kubewarden.policy.settings-schema: '{"additionalProperties": false, "type": "object"}'
+"#,
+        );
+        assert_eq!(settings_schema_diff(&old, &old).unwrap(), None);
+        assert_eq!(
+            settings_schema_diff(&old, &new).unwrap().as_deref(),
+            Some(" {\n+  \"additionalProperties\": false,\n+  \"type\": \"object\"\n-  \"additionalProperties\": false\n }\n")
+        );
+    }
+
+    #[test]
+    fn changelog() {
+        let old = metadata(
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE"]
+mutating: false
+annotations:
+  io.kubewarden.policy.title: pod-privileged
+  io.kubewarden.policy.source: https://github.com/kubewarden/pod-privileged-policy
+  io.kubewarden.policy.usage: "allow: false"
+  io.kubewarden.policy.category: PSP
+"#,
+        );
+        let new = metadata(
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE", "UPDATE"]
+mutating: true
+contextAwareResources:
+- apiVersion: v1
+  kind: Namespace
+annotations:
+  io.kubewarden.policy.title: pod-privileged
+  io.kubewarden.policy.source: https://github.com/kubewarden/pod-privileged-policy
+  io.kubewarden.policy.usage: "allow: true"
+  io.kubewarden.policy.severity: medium
+"#,
+        );
+
+        let changelog = render(
+            "ghcr.io/kubewarden/policies/pod-privileged",
+            "v1.0.0",
+            "v1.2.0",
+            &old,
+            &new,
+        )
+        .unwrap();
+        for expected in [
+            "# ghcr.io/kubewarden/policies/pod-privileged: v1.0.0 → v1.2.0",
+            "- `mutating`: `false` → `true`",
+            "- Rule added: `CREATE,UPDATE core/v1/pods`",
+            "- Rule removed: `CREATE core/v1/pods`",
+            "- Context aware resource added: `v1/Namespace`",
+            "- Added `io.kubewarden.policy.severity`: medium",
+            "- Removed `io.kubewarden.policy.category`",
+            "-allow: false\n+allow: true\n",
+            "[Commits between v1.0.0 and v1.2.0](https://github.com/kubewarden/pod-privileged-policy/compare/v1.0.0...v1.2.0)",
+        ] {
+            assert!(changelog.contains(expected), "{expected} not found in:\n{changelog}");
+        }
+        assert!(!changelog.contains("io.kubewarden.policy.title"));
+    }
+}
@@ -4,8 +4,11 @@ use clap::{
 };
 use lazy_static::lazy_static;
 
+pub(crate) mod audit;
 pub(crate) mod bench;
 pub(crate) mod run;
+pub(crate) mod serve;
+pub(crate) mod validate;
 
 lazy_static! {
     static ref VERSION_AND_BUILTINS: String = {
@@ -47,7 +50,7 @@ A YAML file may contain multiple Custom Resource declarations. In this case, `kw
 
 // Minimum set of flags required to pull a policy from a registry
 fn pull_shared_flags() -> Vec<Arg> {
-    vec![
+    let mut args = vec![
         Arg::new("docker-config-json-path")
             .long("docker-config-json-path")
             .value_name("DOCKER_CONFIG")
@@ -66,12 +69,16 @@ fn pull_shared_flags() -> Vec<Arg> {
             .action(ArgAction::Append)
             .number_of_values(1)
             .value_name("PATH")
-            .help("Path to key used to verify the policy. Can be repeated multiple times"),
+            .help("Path to key used to verify the policy, or KMS key URI (awskms://, gcpkms://, azurekms://, hashivault://). Can be repeated multiple times"),
         Arg::new("fulcio-cert-path")
             .long("fulcio-cert-path")
             .action(ArgAction::Append)
             .value_name("PATH")
-            .help("Path to the Fulcio certificate. Can be repeated multiple times"),
+            .help("Path to the Fulcio certificate. Can be a bundle with the intermediate certificates of a private Fulcio instance and their root, the chain is validated. Can be repeated multiple times"),
+        Arg::new("offline")
+            .long("offline")
+            .num_args(0)
+            .help("Verify signatures without reaching the Sigstore infrastructure. Keyless signatures are verified using the Rekor bundle embedded in them, together with the Fulcio and Rekor trust root given via flags, or cached by a previous online run"),
         Arg::new("rekor-public-key-path")
             .long("rekor-public-key-path")
             .action(ArgAction::Append)
@@ -94,6 +101,18 @@ fn pull_shared_flags() -> Vec<Arg> {
             .number_of_values(1)
             .value_name("VALUE")
             .help("Expected OIDC issuer in Fulcio certificates"),
+        Arg::new("cert-identity-regexp")
+            .long("cert-identity-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-email")
+            .help("Regular expression matching the whole identity (email or URI) in Fulcio certificates"),
+        Arg::new("cert-oidc-issuer-regexp")
+            .long("cert-oidc-issuer-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-oidc-issuer")
+            .help("Regular expression matching the whole OIDC issuer in Fulcio certificates"),
         Arg::new("github-owner")
             .long("github-owner")
             .number_of_values(1)
@@ -104,11 +123,118 @@ fn pull_shared_flags() -> Vec<Arg> {
             .number_of_values(1)
             .value_name("VALUE")
             .help("GitHub repository expected in the certificates generated in CD pipelines"),
+    ];
+    args.extend(sigstore_network_flags());
+    args
+}
+
+// Flags tuning the interactions with the Sigstore infrastructure, made when
+// verifying keyless signatures without '--offline', and when signing keyless
+fn sigstore_network_flags() -> Vec<Arg> {
+    vec![
+        Arg::new("sigstore-timeout")
+            .long("sigstore-timeout")
+            .value_name("SECONDS")
+            .env("KWCTL_SIGSTORE_TIMEOUT")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("30")
+            .help("Time granted to each attempt to reach the Sigstore infrastructure: to fetch the trust root (Fulcio certificates and Rekor keys), and the Fulcio and Rekor requests of keyless signing. It covers both the connection and the transfer"),
+        Arg::new("sigstore-retries")
+            .long("sigstore-retries")
+            .value_name("COUNT")
+            .env("KWCTL_SIGSTORE_RETRIES")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("2")
+            .help("Attempts made to reach the Sigstore infrastructure after the first failed one, waiting longer before each of them"),
+        Arg::new("sigstore-unreachable")
+            .long("sigstore-unreachable")
+            .value_name("BEHAVIOR")
+            .env("KWCTL_SIGSTORE_UNREACHABLE")
+            .value_parser(PossibleValuesParser::new(["fail", "warn"]))
+            .default_value("fail")
+            .help("What to do when the Sigstore infrastructure cannot be reached: fail, or warn and continue. The trust root cached by a previous run is used, without it keyless signatures cannot be verified. Without Fulcio or Rekor, the policies are left unsigned"),
+    ]
+}
+
+// Flags used to provide registry credentials without a Docker config file
+fn report_flags() -> Vec<Arg> {
+    vec![
+        Arg::new("report-path")
+            .long("report-path")
+            .value_name("PATH")
+            .help("Write the outcome of every test case to PATH, for the test summaries of CI systems and the code scanning dashboards"),
+        Arg::new("report-format")
+            .long("report-format")
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["junit", "tap", "sarif"]))
+            .default_value("junit")
+            .requires("report-path")
+            .help("Format of the report written to '--report-path': JUnit XML, TAP or SARIF"),
     ]
 }
 
+fn registry_credentials_flags() -> Vec<Arg> {
+    vec![
+        Arg::new("registry-username")
+            .long("registry-username")
+            .value_name("USERNAME")
+            .env("KWCTL_REGISTRY_USERNAME")
+            .conflicts_with("docker-config-json-path")
+            .help("Username used to authenticate against the registry"),
+        Arg::new("registry-password")
+            .long("registry-password")
+            .value_name("PASSWORD")
+            .env("KWCTL_REGISTRY_PASSWORD")
+            .hide_env_values(true)
+            .requires("registry-username")
+            .conflicts_with_all(["docker-config-json-path", "registry-token"])
+            .help("Password used to authenticate against the registry. Prefer the environment variable, to not leak the password into the shell history"),
+        Arg::new("registry-token")
+            .long("registry-token")
+            .value_name("TOKEN")
+            .env("KWCTL_REGISTRY_TOKEN")
+            .hide_env_values(true)
+            .conflicts_with("docker-config-json-path")
+            .help("Token used to authenticate against the registry, sent as password together with '--registry-username' (defaults to 'kwctl')"),
+    ]
+}
+
+fn subcommand_policies() -> Command {
+    let mut args = vec![
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("check-updates")
+            .long("check-updates")
+            .num_args(0)
+            .help("Look up the tags of the repository of each policy pulled from a registry, reporting the newer releases available. Only the policies tagged with a semantic version are checked"),
+        Arg::new("verify-remote")
+            .long("verify-remote")
+            .num_args(0)
+            .help("Compare each policy with the one currently referenced by its remote URI, flagging the local copies that are stale, together with the metadata fields changed by the remote policy. The signatures of the remote policies are looked up too, and shown by the wide, JSON and YAML outputs"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["text", "wide", "json", "yaml"]))
+            .default_value("text")
+            .help("Output format. The wide output shows the whole SHA-256 and the path of the policies"),
+    ];
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("policies")
+        .about("Lists all downloaded policies")
+        .args(args)
+}
+
 fn subcommand_pull() -> Command {
     let mut args = pull_shared_flags();
+    args.extend(registry_credentials_flags());
     args.extend_from_slice(&[Arg::new("output-path")
         .short('o')
         .long("output-path")
@@ -127,6 +253,36 @@ fn subcommand_pull() -> Command {
         .args(args)
 }
 
+fn subcommand_update() -> Command {
+    let mut args = pull_shared_flags();
+    args.extend(registry_credentials_flags());
+    args.extend_from_slice(&[
+        Arg::new("constraint")
+            .long("constraint")
+            .value_name("VERSION_REQ")
+            .help("Semantic version constraint the new releases must satisfy, like '~0.2' to stay on 0.2.x or '<1.0.0'. By default the newest release is pulled"),
+        Arg::new("dry-run")
+            .long("dry-run")
+            .num_args(0)
+            .help("Print the policies that would be updated, without pulling them"),
+        Arg::new("keep")
+            .long("keep")
+            .num_args(0)
+            .help("Keep the previous releases of the updated policies inside of the store"),
+    ]);
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("pattern")
+            .index(1)
+            .help("Update only the policies whose URI contains PATTERN, '*' matches any sequence of characters. By default all the policies are updated"),
+    );
+
+    Command::new("update")
+        .about("Pulls the newest release of the policies of the store pulled via a semantic version tag")
+        .long_about("Pulls the newest release of the policies of the store pulled via a semantic version tag. The new releases are verified like done by 'kwctl pull', and replace the previous ones unless --keep is given")
+        .args(args)
+}
+
 fn subcommand_verify() -> Command {
     let mut args = vec![
         Arg::new("docker-config-json-path")
@@ -147,13 +303,17 @@ fn subcommand_verify() -> Command {
             .action(ArgAction::Append)
             .number_of_values(1)
             .value_name("PATH")
-            .help("Path to key used to verify the policy. Can be repeated multiple times"),
+            .help("Path to key used to verify the policy, or KMS key URI (awskms://, gcpkms://, azurekms://, hashivault://). Can be repeated multiple times"),
         Arg::new("fulcio-cert-path")
             .long("fulcio-cert-path")
             .action(ArgAction::Append)
             .number_of_values(1)
             .value_name("PATH")
-            .help("Path to the Fulcio certificate. Can be repeated multiple times"),
+            .help("Path to the Fulcio certificate. Can be a bundle with the intermediate certificates of a private Fulcio instance and their root, the chain is validated. Can be repeated multiple times"),
+        Arg::new("offline")
+            .long("offline")
+            .num_args(0)
+            .help("Verify signatures without reaching the Sigstore infrastructure. Keyless signatures are verified using the Rekor bundle embedded in them, together with the Fulcio and Rekor trust root given via flags, or cached by a previous online run"),
         Arg::new("rekor-public-key-path")
             .long("rekor-public-key-path")
             .value_name("PATH")
@@ -175,6 +335,18 @@ fn subcommand_verify() -> Command {
             .number_of_values(1)
             .value_name("VALUE")
             .help("Expected OIDC issuer in Fulcio certificates"),
+        Arg::new("cert-identity-regexp")
+            .long("cert-identity-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-email")
+            .help("Regular expression matching the whole identity (email or URI) in Fulcio certificates"),
+        Arg::new("cert-oidc-issuer-regexp")
+            .long("cert-oidc-issuer-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-oidc-issuer")
+            .help("Regular expression matching the whole OIDC issuer in Fulcio certificates"),
         Arg::new("github-owner")
             .long("github-owner")
             .number_of_values(1)
@@ -185,13 +357,56 @@ fn subcommand_verify() -> Command {
             .number_of_values(1)
             .value_name("VALUE")
             .help("GitHub repository expected in the certificates generated in CD pipelines"),
+        Arg::new("attestation")
+            .long("attestation")
+            .value_name("TYPE")
+            .value_parser(PossibleValuesParser::new(["slsa-provenance"]))
+            .requires("builder-id")
+            .help("Verify also the attestation of the given type, attached to the policy as OCI referrer. The attestation must be a DSSE envelope signed by one of the --verification-key keys"),
+        Arg::new("builder-id")
+            .long("builder-id")
+            .value_name("ID")
+            .requires("attestation")
+            .help("Builder expected in the SLSA provenance"),
+        Arg::new("source-repository")
+            .long("source-repository")
+            .value_name("URI")
+            .requires("attestation")
+            .help("Repository the SLSA provenance must report the policy has been built from"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["text", "json"]))
+            .default_value("text")
+            .help("Output format. The JSON report lists the signatures that satisfied the verification, with the keys and the certificate identities they matched"),
+        Arg::new("store")
+            .long("store")
+            .num_args(0)
+            .conflicts_with_all([
+                "uri",
+                "attestation",
+                "signature",
+                "output",
+                "registry-username",
+                "registry-token",
+            ])
+            .help("Verify all the policies of the local store pulled from a registry, including the integrity of their local copies, and print the outcome as a table. Useful after rotating the signing keys"),
+        Arg::new("signature")
+            .long("signature")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("PATH_OR_URL")
+            .help("Detached signature of a policy distributed via https:// or file://, as produced by 'cosign sign-blob --key', either with '--output-signature' or '--bundle'. Can be repeated multiple times. Defaults to the .sig and .bundle files next to the policy"),
     ];
+    args.extend(sigstore_network_flags());
+    args.extend(registry_credentials_flags());
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
         Arg::new("uri")
-            .required(true)
+            .required_unless_present("store")
             .index(1)
-            .help("Policy URI. Supported schemes: registry://"),
+            .help("Policy URI. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory. Policies distributed via https:// and file:// are verified using detached signatures"),
     );
 
     Command::new("verify")
@@ -201,6 +416,27 @@ fn subcommand_verify() -> Command {
 
 fn subcommand_push() -> Command {
     let mut args = vec![
+        Arg::new("annotation")
+            .short('a')
+            .long("annotation")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("KEY=VALUE")
+            .help("Annotation in key=value format added to the OCI manifest, overriding the one derived from the policy metadata. Can be repeated multiple times"),
+        Arg::new("also-push")
+            .long("also-push")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("URI")
+            .help("Additional destination the policy is pushed to, after the main one. Can be repeated multiple times. The registry credentials given via flags or environment variables are used only for the main destination"),
+        Arg::new("attach-provenance")
+            .long("attach-provenance")
+            .value_name("PATH")
+            .help("SLSA provenance, as in-toto statement or DSSE envelope, uploaded as OCI referrer of the pushed policy"),
+        Arg::new("attach-sbom")
+            .long("attach-sbom")
+            .value_name("PATH")
+            .help("SBOM, in SPDX or CycloneDX JSON format, uploaded as OCI referrer of the pushed policy"),
         Arg::new("docker-config-json-path")
             .long("docker-config-json-path")
             .value_name("PATH")
@@ -209,10 +445,29 @@ fn subcommand_push() -> Command {
             .long("sources-path")
             .value_name("PATH")
             .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("dry-run")
+            .long("dry-run")
+            .num_args(0)
+            .help("Check the policy and resolve the registry credentials, then print what would be pushed without modifying the registry"),
         Arg::new("force")
             .short('f')
             .long("force")
-            .help("Push also a policy that is not annotated"),
+            .help("Push also a policy that is not annotated. This flag has no effect on existing tags, which are overwritten unless --if-not-exists is set"),
+        Arg::new("if-not-exists")
+            .long("if-not-exists")
+            .num_args(0)
+            .help("Abort when the destination tag already references a different policy. Pushing the same policy again is allowed"),
+        Arg::new("store")
+            .long("store")
+            .value_name("PREFIX")
+            .conflicts_with_all(["policy", "uri", "also-push", "attach-sbom", "attach-provenance", "optimize-level"])
+            .help("Push all the policies of the local store pulled from a registry under the given registry location, for example registry://internal.example.com/kubewarden. The repository paths and the tags of the policies are preserved"),
+        Arg::new("optimize-level")
+            .long("optimize-level")
+            .value_name("LEVEL")
+            .value_parser(PossibleValuesParser::new(["1", "2", "3", "4", "s", "z"]))
+            .conflicts_with_all(["attach-sbom", "attach-provenance"])
+            .help("Optimize the policy with binaryen's wasm-opt, like 'wasm-opt -O<LEVEL>', reporting the size and instantiation time before and after the optimization. The code is optimized without the metadata, which is pushed untouched, like 'annotate --optimize-level' does. Cannot be combined with attestations, which describe the local policy. wasm-opt is looked up in PATH, unless KWCTL_WASM_OPT is set to its path"),
         Arg::new("output")
             .long("output")
             .short('o')
@@ -220,17 +475,58 @@ fn subcommand_push() -> Command {
             .value_parser(PossibleValuesParser::new(["text", "json"]))
             .default_value("text")
             .help("Output format"),
+        Arg::new("sign-key")
+            .long("sign-key")
+            .value_name("PATH")
+            .conflicts_with("sign-keyless")
+            .help("Private key used to sign the pushed policy, like the ones generated by 'cosign generate-key-pair', or KMS key URI (awskms://, gcpkms://, azurekms://, hashivault://). The signature is pushed next to the policy"),
+        Arg::new("sign-keyless")
+            .long("sign-keyless")
+            .num_args(0)
+            .help("Sign the pushed policy with an ephemeral key certified by Fulcio for the OIDC identity of the signer, and record the signature inside of Rekor, like 'cosign sign' without a key"),
+        Arg::new("sign-identity-token")
+            .long("sign-identity-token")
+            .value_name("TOKEN")
+            .env("SIGSTORE_ID_TOKEN")
+            .hide_env_values(true)
+            .help("OIDC identity token used for keyless signing. By default, the token is obtained from the Sigstore credential provider declared inside of the sources file, or from the GitHub Actions job granted the 'id-token: write' permission"),
+        Arg::new("sign-fulcio-url")
+            .long("sign-fulcio-url")
+            .value_name("URL")
+            .default_value("https://fulcio.sigstore.dev")
+            .help("Fulcio instance issuing the certificates of keyless signatures"),
+        Arg::new("sign-rekor-url")
+            .long("sign-rekor-url")
+            .value_name("URL")
+            .default_value("https://rekor.sigstore.dev")
+            .help("Rekor instance recording keyless signatures"),
+        Arg::new("sign-key-password")
+            .long("sign-key-password")
+            .value_name("PASSWORD")
+            .env("COSIGN_PASSWORD")
+            .hide_env_values(true)
+            .requires("sign-key")
+            .help("Password of the signing key"),
+        Arg::new("sign-annotation")
+            .long("sign-annotation")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("KEY=VALUE")
+            .requires("signing")
+            .help("Annotation in key=value format added to the signature. Can be repeated multiple times"),
     ];
+    args.extend(sigstore_network_flags());
+    args.extend(registry_credentials_flags());
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
         Arg::new("policy")
-            .required(true)
+            .required_unless_present("store")
             .index(1)
             .help("Policy to push. Can be the path to a local file, a policy URI or the SHA prefix of a policy in the store."),
     );
     args.push(
         Arg::new("uri")
-            .required(true)
+            .required_unless_present("store")
             .index(2)
             .help("Policy URI. Supported schemes: registry://"),
     );
@@ -241,13 +537,100 @@ fn subcommand_push() -> Command {
             r#"The annotations found inside of policy's metadata are going to be part of the OCI manifest.
 The multi-line annotations are skipped because they are not compatible with the OCI specification.
 The 'io.kubewarden.policy.source' annotation is propagated as 'org.opencontainers.image.source' to allow tools like
-renovatebot to detect policy updates."#,
+renovatebot to detect policy updates.
+The whole metadata is also stored as JSON inside of the 'io.kubewarden.policy.metadata' annotation, which allows
+'scaffold manifest' to skip the download of the policy.
+Additional annotations, like 'org.opencontainers.image.revision' or 'org.opencontainers.image.licenses', can be
+set with the '--annotation' flag."#,
+        )
+        .args(args)
+        .group(
+            // the pushed policy is signed either with a key, or keyless
+            ArgGroup::new("signing").args(["sign-key", "sign-keyless"]),
+        )
+}
+
+fn subcommand_sign() -> Command {
+    let mut args = vec![
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("key")
+            .long("key")
+            .short('k')
+            .value_name("PATH")
+            .required_unless_present("keyless")
+            .conflicts_with("keyless")
+            .help("Private key used to sign the policy, like the ones generated by 'cosign generate-key-pair', or KMS key URI (awskms://, gcpkms://, azurekms://, hashivault://)"),
+        Arg::new("keyless")
+            .long("keyless")
+            .num_args(0)
+            .help("Sign with an ephemeral key certified by Fulcio for the OIDC identity of the signer, and record the signature inside of Rekor, like 'cosign sign' without a key"),
+        Arg::new("identity-token")
+            .long("identity-token")
+            .value_name("TOKEN")
+            .env("SIGSTORE_ID_TOKEN")
+            .hide_env_values(true)
+            .help("OIDC identity token used for keyless signing. By default, the token is obtained from the Sigstore credential provider declared inside of the sources file, or from the GitHub Actions job granted the 'id-token: write' permission"),
+        Arg::new("fulcio-url")
+            .long("fulcio-url")
+            .value_name("URL")
+            .default_value("https://fulcio.sigstore.dev")
+            .help("Fulcio instance issuing the certificates of keyless signatures"),
+        Arg::new("rekor-url")
+            .long("rekor-url")
+            .value_name("URL")
+            .default_value("https://rekor.sigstore.dev")
+            .help("Rekor instance recording keyless signatures"),
+        Arg::new("key-password")
+            .long("key-password")
+            .value_name("PASSWORD")
+            .env("COSIGN_PASSWORD")
+            .hide_env_values(true)
+            .help("Password of the signing key"),
+        Arg::new("annotation")
+            .short('a')
+            .long("annotation")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("KEY=VALUE")
+            .help("Annotation in key=value format added to the signature. Can be repeated multiple times"),
+    ];
+    args.extend(sigstore_network_flags());
+    args.extend(registry_credentials_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("URI of the policy to sign. Supported schemes: registry://"),
+    );
+
+    Command::new("sign")
+        .about("Signs a Kubewarden policy that has already been pushed to an OCI registry")
+        .after_long_help(
+            r#"The signature is cosign-compatible and it's pushed to the registry next to the policy.
+Signatures can be checked with the 'verify' command."#,
         )
         .args(args)
 }
 
+// Refuses the policies that cannot be trusted, for the commands evaluating them
+fn trusted_only_flag() -> Arg {
+    Arg::new("trusted-only")
+        .long("trusted-only")
+        .num_args(0)
+        .env("KWCTL_TRUSTED_ONLY")
+        .help("Refuse to run policies that lack Kubewarden metadata, or that cannot be verified. Requires verification options, either via flags or via the default verification config file. Can be enforced machine-wide via the environment variable")
+}
+
 fn run_args() -> Vec<Arg> {
-    vec![
+    let mut args = vec![
         Arg::new("docker-config-json-path")
             .long("docker-config-json-path")
             .value_name("PATH")
@@ -281,13 +664,17 @@ fn run_args() -> Vec<Arg> {
             .action(ArgAction::Append)
             .number_of_values(1)
             .value_name("PATH")
-            .help("Path to key used to verify the policy. Can be repeated multiple times"),
+            .help("Path to key used to verify the policy, or KMS key URI (awskms://, gcpkms://, azurekms://, hashivault://). Can be repeated multiple times"),
         Arg::new("fulcio-cert-path")
             .long("fulcio-cert-path")
             .action(ArgAction::Append)
             .number_of_values(1)
             .value_name("PATH")
-            .help("Path to the Fulcio certificate. Can be repeated multiple times"),
+            .help("Path to the Fulcio certificate. Can be a bundle with the intermediate certificates of a private Fulcio instance and their root, the chain is validated. Can be repeated multiple times"),
+        Arg::new("offline")
+            .long("offline")
+            .num_args(0)
+            .help("Verify signatures without reaching the Sigstore infrastructure. Keyless signatures are verified using the Rekor bundle embedded in them, together with the Fulcio and Rekor trust root given via flags, or cached by a previous online run"),
         Arg::new("rekor-public-key-path")
             .long("rekor-public-key-path")
             .value_name("PATH")
@@ -309,6 +696,18 @@ fn run_args() -> Vec<Arg> {
             .number_of_values(1)
             .value_name("VALUE")
             .help("Expected OIDC issuer in Fulcio certificates"),
+        Arg::new("cert-identity-regexp")
+            .long("cert-identity-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-email")
+            .help("Regular expression matching the whole identity (email or URI) in Fulcio certificates"),
+        Arg::new("cert-oidc-issuer-regexp")
+            .long("cert-oidc-issuer-regexp")
+            .number_of_values(1)
+            .value_name("REGEXP")
+            .conflicts_with("cert-oidc-issuer")
+            .help("Regular expression matching the whole OIDC issuer in Fulcio certificates"),
         Arg::new("github-owner")
             .long("github-owner")
             .number_of_values(1)
@@ -334,6 +733,7 @@ fn run_args() -> Vec<Arg> {
             .long("disable-wasmtime-cache")
             .num_args(0)
             .help("Turn off usage of wasmtime cache"),
+        trusted_only_flag(),
         Arg::new("allow-context-aware")
             .long("allow-context-aware")
             .num_args(0)
@@ -344,6 +744,15 @@ fn run_args() -> Vec<Arg> {
             .long_help(r#"Record all the policy and host capabilities
 communications to the given file.
 Useful to be combined later with '--replay-host-capabilities-interactions' flag"#),
+        Arg::new("validate-mutation-schema")
+            .long("validate-mutation-schema")
+            .num_args(0)
+            .help("Validate the object mutated by the policy against the OpenAPI schema of its kind. The schema is fetched from the Kubernetes cluster, unless '--openapi-schema-path' is provided"),
+        Arg::new("openapi-schema-path")
+            .long("openapi-schema-path")
+            .value_name("PATH")
+            .requires("validate-mutation-schema")
+            .help("OpenAPI v3 document used by '--validate-mutation-schema', like the ones served by the API server under '/openapi/v3'"),
         Arg::new("replay-host-capabilities-interactions")
             .long("replay-host-capabilities-interactions")
             .value_name("FILE")
@@ -351,11 +760,37 @@ Useful to be combined later with '--replay-host-capabilities-interactions' flag"
 the host replays back the answers found inside of the provided file.
 This is useful to test policies in a reproducible way, given no external
 interactions with OCI registries, DNS, Kubernetes are performed."#),
-     ]
+    ];
+    args.extend(sigstore_network_flags());
+    args
+}
+
+// Flags explaining the evaluations of the Rego policies, made by `run`
+fn explain_flags() -> Vec<Arg> {
+    vec![
+        Arg::new("explain")
+            .long("explain")
+            .value_name("MODE")
+            .value_parser(PossibleValuesParser::new(["notes", "full"]))
+            .requires("rego-path")
+            .help("Print the trace of the evaluation of the Rego policy to the standard error, made by 'opa eval --explain <MODE>' on the Rego sources given via '--rego-path': 'full' traces every step with the bindings of the variables, 'notes' only the 'trace' calls of the policy. Only for the opa and gatekeeper execution modes. opa is looked up in PATH, unless KWCTL_OPA is set to its path"),
+        Arg::new("rego-path")
+            .long("rego-path")
+            .value_name("PATH")
+            .requires("explain")
+            .help("Rego file the policy was built from, or directory holding its Rego files, evaluated by '--explain'"),
+        Arg::new("explain-query")
+            .long("explain-query")
+            .value_name("QUERY")
+            .requires("explain")
+            .help("Query evaluated by '--explain'. Defaults to the 'violation' rule of the package of the policy for the gatekeeper execution mode, like 'data.k8srequiredlabels.violation', and to its 'main' rule for the opa one"),
+    ]
 }
 
 fn subcommand_run() -> Command {
     let mut args = run_args();
+    args.extend(report_flags());
+    args.extend(explain_flags());
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
         Arg::new("uri_or_sha_prefix_or_yaml_file")
@@ -387,9 +822,35 @@ fn subcommand_annotate() -> Command {
         Arg::new("metadata-path")
             .long("metadata-path")
             .short('m')
-            .required(true)
+            .required_unless_present_any(["set", "rule", "mutating", "settings-schema", "strip"])
             .value_name("PATH")
-            .help("File containing the metadata"),
+            .help("File containing the metadata. Can be omitted when the metadata is given via --set, --rule and --mutating"),
+        Arg::new("in-place")
+            .long("in-place")
+            .num_args(0)
+            .conflicts_with_all(["output-path", "validate-only"])
+            .help("Write the annotated policy over the WebAssembly module given as input"),
+        Arg::new("mutating")
+            .long("mutating")
+            .num_args(0)
+            .help("Mark the policy as mutating, overriding the metadata file"),
+        Arg::new("rule")
+            .long("rule")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("GROUP/VERSION/RESOURCE:OPERATIONS")
+            .help("Rule appended to the ones of the metadata file, like 'apps/v1/deployments:CREATE,UPDATE'. The group is omitted for the core API group, like 'v1/pods:CREATE'. Can be repeated multiple times"),
+        Arg::new("set")
+            .long("set")
+            .action(ArgAction::Append)
+            .number_of_values(1)
+            .value_name("PATH=VALUE")
+            .help("Set a field of the metadata, overriding the metadata file. The path is made of the names of the fields separated by '.', with the index of the items of lists, like 'rules[0].operations=[CREATE, UPDATE]' or 'annotations.io.kubewarden.policy.title=my-policy'. The value is parsed as YAML. Applied after --rule and --mutating. Can be repeated multiple times"),
+        Arg::new("optimize-level")
+            .long("optimize-level")
+            .value_name("LEVEL")
+            .value_parser(PossibleValuesParser::new(["1", "2", "3", "4", "s", "z"]))
+            .help("Optimize the policy with binaryen's wasm-opt, like 'wasm-opt -O<LEVEL>', reporting the size and instantiation time before and after the optimization. wasm-opt is looked up in PATH, unless KWCTL_WASM_OPT is set to its path"),
         Arg::new("usage-path")
             .long("usage-path")
             .short('u')
@@ -398,9 +859,23 @@ fn subcommand_annotate() -> Command {
         Arg::new("output-path")
             .long("output-path")
             .short('o')
-            .required(true)
+            .required_unless_present_any(["validate-only", "in-place"])
             .value_name("PATH")
             .help("Output file"),
+        Arg::new("validate-only")
+            .long("validate-only")
+            .num_args(0)
+            .conflicts_with_all(["output-path", "optimize-level"])
+            .help("Validate the metadata against the WebAssembly module, without writing the annotated policy"),
+        Arg::new("settings-schema")
+            .long("settings-schema")
+            .value_name("PATH")
+            .help("File containing the JSON Schema of the policy settings, embedded into the metadata. The schema is checked to be a valid JSON Schema"),
+        Arg::new("strip")
+            .long("strip")
+            .num_args(0)
+            .conflicts_with_all(["metadata-path", "set", "rule", "mutating", "settings-schema", "usage-path", "optimize-level", "validate-only"])
+            .help("Remove the Kubewarden metadata from the WebAssembly module, instead of adding it"),
     ];
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
@@ -411,7 +886,7 @@ fn subcommand_annotate() -> Command {
     );
 
     Command::new("annotate")
-        .about("Add Kubewarden metadata to a WebAssembly module")
+        .about("Add Kubewarden metadata to a WebAssembly module, or remove it")
         .args(args)
 }
 
@@ -421,8 +896,8 @@ fn subcommand_inspect() -> Command {
             .long("output")
             .short('o')
             .value_name("FORMAT")
-            .value_parser(PossibleValuesParser::new(["yaml"]))
-            .help("Output format"),
+            .value_parser(PossibleValuesParser::new(["yaml", "json"]))
+            .help("Output format. json is available only together with --settings-schema-only"),
         Arg::new("sources-path")
             .long("sources-path")
             .value_name("PATH")
@@ -435,6 +910,15 @@ fn subcommand_inspect() -> Command {
             .long("show-signatures")
             .num_args(0)
             .help("Show sigstore signatures"),
+        Arg::new("remote")
+            .long("remote")
+            .num_args(0)
+            .help("Fetch the metadata of a registry:// policy from its OCI manifest, without pulling the policy. The policy is pulled into a temporary directory only when its manifest does not hold the metadata"),
+        Arg::new("settings-schema-only")
+            .long("settings-schema-only")
+            .num_args(0)
+            .conflicts_with("show-signatures")
+            .help("Print only the JSON Schema of the policy settings embedded into the metadata"),
     ];
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
@@ -450,6 +934,26 @@ fn subcommand_inspect() -> Command {
 }
 
 fn subcommand_scaffold() -> Command {
+    // The signatures required by the generated verification config are given
+    // via the same flags used when verifying policies
+    let mut verification_config_args: Vec<Arg> = pull_shared_flags()
+        .into_iter()
+        .filter(|arg| {
+            [
+                "verification-key",
+                "verification-annotation",
+                "cert-email",
+                "cert-oidc-issuer",
+                "cert-identity-regexp",
+                "cert-oidc-issuer-regexp",
+                "github-owner",
+                "github-repo",
+            ]
+            .contains(&arg.get_id().as_str())
+        })
+        .collect();
+    verification_config_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
     let mut artifacthub_args = vec![
         Arg::new("metadata-path")
             .long("metadata-path")
@@ -461,7 +965,7 @@ fn subcommand_scaffold() -> Command {
             .short('v')
             .number_of_values(1)
             .value_name("VALUE")
-            .help("Semver version of the policy"),
+            .help("Semver version of the policy, overriding the `io.kubewarden.policy.version` annotation of the metadata"),
         Arg::new("questions-path")
             .long("questions-path")
             .short('q')
@@ -479,8 +983,10 @@ fn subcommand_scaffold() -> Command {
         Arg::new("settings-path")
             .long("settings-path")
             .short('s')
+            .action(ArgAction::Append)
+            .number_of_values(1)
             .value_name("PATH")
-            .help("File containing the settings for this policy"),
+            .help("File containing the settings for this policy. Policy groups take one NAME=PATH value for each policy that has settings"),
         Arg::new("settings-json")
             .long("settings-json")
             .value_name("VALUE")
@@ -489,10 +995,71 @@ fn subcommand_scaffold() -> Command {
         Arg::new("type")
             .long("type")
             .short('t')
-            .required(true)
+            .required_unless_present("from-file")
             .value_name("VALUE")
-            .value_parser(PossibleValuesParser::new(["ClusterAdmissionPolicy", "AdmissionPolicy"]))
+            .value_parser(PossibleValuesParser::new([
+                "ClusterAdmissionPolicy",
+                "AdmissionPolicy",
+                "ClusterAdmissionPolicyGroup",
+                "AdmissionPolicyGroup",
+            ]))
             .help("Kubewarden Custom Resource type"),
+        Arg::new("expression")
+            .long("expression")
+            .value_name("EXPRESSION")
+            .required_if_eq_any([
+                ("type", "ClusterAdmissionPolicyGroup"),
+                ("type", "AdmissionPolicyGroup"),
+            ])
+            .help("CEL expression of a policy group, calling its policies by name. E.g. `privileged() && labels()`"),
+        Arg::new("message")
+            .long("message")
+            .value_name("MESSAGE")
+            .requires("expression")
+            .help("Message returned when a policy group rejects a request"),
+        Arg::new("policy-server")
+            .long("policy-server")
+            .value_name("NAME")
+            .help("PolicyServer running the policy. The `default` one is used when not given"),
+        Arg::new("mode")
+            .long("mode")
+            .value_name("MODE")
+            .value_parser(PossibleValuesParser::new(["protect", "monitor"]))
+            .help("Whether the policy rejects the requests or only reports its evaluations. Defaults to protect"),
+        Arg::new("failure-policy")
+            .long("failure-policy")
+            .value_name("POLICY")
+            .value_parser(PossibleValuesParser::new(["Fail", "Ignore"]))
+            .help("How the API server handles the requests when the policy cannot be evaluated. Defaults to Fail"),
+        Arg::new("background-audit")
+            .long("background-audit")
+            .value_name("BOOL")
+            .value_parser(clap::value_parser!(bool))
+            .help("Whether the policy is used by the audit scanner. Defaults to the value of the policy metadata"),
+        Arg::new("namespace-selector")
+            .long("namespace-selector")
+            .value_name("SELECTOR")
+            .help("Label selector of the namespaces whose resources are evaluated, e.g. `environment=production,tier in (frontend,backend)`. Only for cluster-wide resources"),
+        Arg::new("object-selector")
+            .long("object-selector")
+            .value_name("SELECTOR")
+            .help("Label selector of the resources that are evaluated, e.g. `app=nginx,!deprecated`"),
+        Arg::new("from-file")
+            .long("from-file")
+            .value_name("PATH")
+            .conflicts_with_all(["settings-path", "settings-json", "title", "expression"])
+            .help("YAML file listing the policies to scaffold, each one with its uri, and optionally its title and settings. The manifests are printed as a multi-document YAML. Generates ClusterAdmissionPolicies unless --type is given"),
+        Arg::new("output-format")
+            .long("output-format")
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["yaml", "kustomize", "helm"]))
+            .default_value("yaml")
+            .help("yaml prints the manifest, kustomize adds it to the kustomization of --output-dir, helm prints the values of the kubewarden-defaults chart deploying the policy, only for the recommended policies of the chart"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .value_name("PATH")
+            .required_if_eq("output-format", "kustomize")
+            .help("Kustomization directory where the manifest is written. Created when missing"),
         Arg::new("title")
             .long("title")
             .value_name("VALUE")
@@ -500,16 +1067,24 @@ fn subcommand_scaffold() -> Command {
         Arg::new("allow-context-aware")
             .long("allow-context-aware")
             .num_args(0)
-            .help("Uses the policy metadata to define which Kubernetes resources can be accessed by the policy. Warning: review the list of resources carefully to avoid abuses. Disabled by default"),
+            .hide(true)
+            .help("Deprecated: the Kubernetes resources declared by the policy metadata are granted by default"),
+        Arg::new("no-context-aware")
+            .long("no-context-aware")
+            .num_args(0)
+            .conflicts_with("allow-context-aware")
+            .help("Leaves `contextAwareResources` empty, instead of granting the policy access to the Kubernetes resources declared by its metadata"),
     ];
     // When scaffolding the manifest of a missing policy, we can pull it from a registry
     manifest_args.extend_from_slice(&pull_shared_flags());
     manifest_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     manifest_args.push(
         Arg::new("uri_or_sha_prefix")
-            .required(true)
+            .required_unless_present("from-file")
+            .conflicts_with("from-file")
             .index(1)
-            .help("Policy URI or SHA prefix. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory."),
+            .num_args(1..)
+            .help("Policy URI or SHA prefix. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory. Policy groups take one [NAME=]URI value for each policy, NAME defaults to the last segment of the URI"),
     );
 
     let mut vap_args = vec![
@@ -530,6 +1105,10 @@ fn subcommand_scaffold() -> Command {
             .required(true)
             .value_name("VALIDATING-ADMISSION-POLICY-BINDING.yaml")
             .help("The file containing the ValidatingAdmissionPolicyBinding definition"),
+        Arg::new("params")
+            .long("params")
+            .value_name("PARAMS.yaml")
+            .help("The file containing the parameters object referenced by the paramRef of the binding, embedded into the settings of the policy"),
     ];
     vap_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
 
@@ -539,22 +1118,57 @@ fn subcommand_scaffold() -> Command {
             .short('o')
             .required(true)
             .value_name("TYPE")
-            .value_parser(PossibleValuesParser::new(["CREATE"])) //TODO: add UPDATE and DELETE
-            .help("Kubewarden Custom Resource type"),
+            .value_parser(PossibleValuesParser::new(["CREATE", "UPDATE", "DELETE"]))
+            .help("Operation of the AdmissionRequest"),
         Arg::new("object")
             .long("object")
+            .visible_aliases(["new", "resource"])
             .value_name("PATH")
-            .help("The file containing the new object being admitted"),
+            .help("The file containing the new object being admitted. With the DELETE operation, the object being deleted"),
+        Arg::new("from-k8s")
+            .long("from-k8s")
+            .value_name("KIND/NAME")
+            .conflicts_with("old-object")
+            .help("Fetch the object from the cluster of the current kubeconfig context, like 'deployment/web'. The object fetched is the object of CREATE requests, and the old object of UPDATE and DELETE requests"),
+        Arg::new("namespace")
+            .long("namespace")
+            .short('n')
+            .value_name("NAMESPACE")
+            .requires("from-k8s")
+            .help("Namespace of the object fetched via --from-k8s. Defaults to the namespace of the current kubeconfig context"),
         Arg::new("old-object")
             .long("old-object")
+            .visible_alias("old")
             .value_name("PATH")
-            .help("The file containing the existing object"),
+            .help("The file containing the existing object. Required by the UPDATE operation. With the DELETE operation, the object being deleted"),
     ];
     admission_request_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
 
+    let mut policy_args = vec![
+        Arg::new("language")
+            .long("language")
+            .short('l')
+            .required(true)
+            .value_name("LANGUAGE")
+            .value_parser(PossibleValuesParser::new(["rust", "go", "rego"]))
+            .help("Language of the policy"),
+        Arg::new("name")
+            .long("name")
+            .required(true)
+            .value_name("NAME")
+            .help("Name of the policy, made of lowercase letters, digits and dashes"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .short('o')
+            .value_name("PATH")
+            .help("Directory where the project is generated. Defaults to a directory named after the policy"),
+    ];
+    policy_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
     let mut subcommands = vec![
         Command::new("verification-config")
-            .about("Output a default Sigstore verification configuration file"),
+            .about("Output a Sigstore verification configuration file, requiring the signatures given via flags or, by default, the ones of the Kubewarden infrastructure")
+            .args(verification_config_args),
         Command::new("artifacthub")
             .about("Output an artifacthub-pkg.yml file from a metadata.yml file")
             .args(artifacthub_args),
@@ -567,6 +1181,9 @@ fn subcommand_scaffold() -> Command {
         Command::new("admission-request")
             .about("Scaffold an AdmissionRequest object")
             .args(admission_request_args),
+        Command::new("policy")
+            .about("Scaffold a policy project, with the SDK dependency, validation stubs, metadata, Makefile and test fixtures")
+            .args(policy_args),
     ];
     subcommands.sort_by(|a, b| a.get_name().cmp(b.get_name()));
 
@@ -588,6 +1205,7 @@ fn subcommand_digest() -> Command {
             .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
 
     ];
+    args.extend(registry_credentials_flags());
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(Arg::new("uri").required(true).index(1).help("Policy URI"));
 
@@ -596,64 +1214,982 @@ fn subcommand_digest() -> Command {
         .args(args)
 }
 
-fn subcommand_bench() -> Command {
+fn subcommand_sources() -> Command {
+    let mut probe_args = vec![
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+    ];
+    probe_args.extend(registry_credentials_flags());
+    probe_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    probe_args.push(
+        Arg::new("uri")
+            .required(true)
+            .index(1)
+            .help("Policy URI. Only registry:// policies can be served by mirrors"),
+    );
+
+    Command::new("sources")
+        .about("Inspects the sources policies are pulled from")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("probe")
+                .about("Probes the registry mirrors and the upstream registry of a policy, and shows which one would be used by 'pull'")
+                .long_about(
+                    r#"Probes the registry mirrors and the upstream registry of a policy, and shows which one would be used by 'pull'.
+
+When multiple mirrors are configured, 'pull' tries the available ones first,
+the fastest first, and falls back to the upstream registry. The health of
+the mirrors is cached for 5 minutes. This command always probes all of them,
+refreshing the cache."#,
+                )
+                .args(probe_args),
+        )
+}
+
+fn subcommand_diff() -> Command {
     let mut args = vec![
-        Arg::new("measurement_time")
-            .long("measurement-time")
-            .number_of_values(1)
-            .value_name("SECONDS")
-            .help("How long the bench 'should' run, num_samples is prioritized so benching will take longer to be able to collect num_samples if the code to be benched is slower than this time limit allowed"),
-        Arg::new("num_resamples")
-            .long("num-resamples")
-            .number_of_values(1)
-            .value_name("NUM")
-            .help("How many resamples should be done"),
-        Arg::new("num_samples")
-            .long("num-samples")
-            .number_of_values(1)
-            .value_name("NUM")
-            .help("How many resamples should be done. Recommended at least 50, above 100 doesn't seem to yield a significantly different result"),
-        Arg::new("warm_up_time")
-            .long("warm-up-time")
-            .number_of_values(1)
-            .value_name("SECONDS")
-            .help("How long the bench should warm up"),
-        Arg::new("dump_results_to_disk")
-            .long("dump-results-to-disk")
-            .help("Puts results in target/tiny-bench/label/.. if target can be found. used for comparing previous runs"),
+        Arg::new("requests-dir")
+            .long("requests-dir")
+            .value_name("DIR")
+            .help("Directory of JSON requests to evaluate with both the policies, reporting the ones evaluated differently"),
+        Arg::new("settings-path")
+            .long("settings-path")
+            .short('s')
+            .value_name("PATH")
+            .requires("requests-dir")
+            .help("File containing the settings used to evaluate the requests"),
+        Arg::new("settings-json")
+            .long("settings-json")
+            .value_name("VALUE")
+            .requires("requests-dir")
+            .conflicts_with("settings-path")
+            .help("JSON string containing the settings used to evaluate the requests"),
+        Arg::new("raw")
+            .long("raw")
+            .num_args(0)
+            .requires("requests-dir")
+            .help("Evaluate raw requests"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
     ];
-    let mut run_args = run_args();
-    args.append(&mut run_args);
+    args.extend(registry_credentials_flags());
     args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
     args.push(
-        Arg::new("uri_or_sha_prefix_or_yaml_file")
+        Arg::new("old_uri_or_sha_prefix")
             .required(true)
             .index(1)
-            .help("Policy URI, SHA prefix or YAML file containing Kubewarden policy resources. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory.")
+            .help("Policy URI or SHA prefix of the old policy. Supported schemes: registry://, https://, file://"),
+    );
+    args.push(
+        Arg::new("new_uri_or_sha_prefix")
+            .required(true)
+            .index(2)
+            .help("Policy URI or SHA prefix of the new policy. Supported schemes: registry://, https://, file://"),
     );
 
-    Command::new("bench")
-        .about("Benchmarks a Kubewarden policy")
-        .long_about(format!(
-            r#"Benchmarks a Kubewarden policy.
+    Command::new("diff")
+        .about("Compares two policies, like two releases of the same policy")
+        .long_about(
+            r#"Compares two policies, like two releases of the same policy.
 
-{}"#,
-            RUN_AND_BENCH_COMMON_LONG_ABOUT
-        ))
-        .args(args)
-        .group(
-            // these flags cannot be used at the same time
-            ArgGroup::new("host-capabilities-proxy").args([
-                "record-host-capabilities-interactions",
-                "replay-host-capabilities-interactions",
-            ]),
+The differences are reported as Markdown, covering:
+- the metadata of the policies, like their rules and the context aware resources they read
+- their annotations
+- their settings JSON Schema
+- the size of their modules
+
+When a directory of requests is given, every JSON request it holds is
+evaluated by both the policies, and the requests evaluated differently are
+reported. The policies are evaluated without access to the host capabilities.
+
+The policies missing from the store are pulled into it."#,
         )
+        .args(args)
 }
 
-fn subcommand_save() -> Command {
-    Command::new("save")
-        .about("save policies to a tar.gz file")
-        .arg(
+fn subcommand_test() -> Command {
+    let mut args = vec![
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+        Arg::new("filter")
+            .long("filter")
+            .value_name("REGEXP")
+            .help("Run only the test cases whose name matches the regular expression"),
+        Arg::new("fail-fast")
+            .long("fail-fast")
+            .num_args(0)
+            .help("Stop running the test cases once one fails"),
+        Arg::new("jobs")
+            .long("jobs")
+            .short('j')
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("1")
+            .help("Number of test cases evaluated in parallel"),
+        Arg::new("metrics-path")
+            .long("metrics-path")
+            .value_name("PATH")
+            .help("Write a summary of the run (test cases passed, failed and skipped, durations of the suites) to PATH, using the OpenMetrics text format"),
+        Arg::new("update-snapshots")
+            .long("update-snapshots")
+            .num_args(0)
+            .help("Record the patches returned by the policy into the patch snapshots of the test cases, instead of comparing them"),
+    ];
+    args.extend(registry_credentials_flags());
+    args.extend(report_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("suites")
+            .required(true)
+            .num_args(1..)
+            .index(1)
+            .value_name("SUITE")
+            .help("YAML files holding the test suites"),
+    );
+
+    Command::new("test")
+        .about("Runs the test suites of a policy")
+        .long_about(
+            r#"Runs the test suites of a policy.
+
+A test suite is a YAML file naming the policy, its settings and a list of
+test cases. Every case evaluates a JSON request and describes the expected
+outcome:
+
+  policy: registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5
+  settings: {}
+  tests:
+  - name: privileged pods are rejected
+    request: test_data/privileged-pod.json
+    expect:
+      allowed: false
+      message: "Privileged container is not allowed"
+
+The request files, and the policy when it's a local file, are relative to the
+suite. A case can replace the settings of the suite via its own 'settings'.
+Besides 'allowed', a case can expect the rejection 'message' and the JSON
+'patch' returned by the policy, an empty list when the request must not be
+mutated. The patch can be kept inside of a JSON file instead, given via
+'patchSnapshot': the snapshots are recorded, and later refreshed, by
+'--update-snapshots'.
+
+The policies are evaluated without access to the host capabilities. The
+policies missing from the store are pulled into it. The command fails when at
+least a test case fails.
+
+Large suites can be run in parallel via '--jobs', while '--filter' selects the
+cases to run by name, which helps to re-run a single case while debugging."#,
+        )
+        .args(args)
+}
+
+fn subcommand_fuzz() -> Command {
+    let mut args = vec![
+        Arg::new("policy")
+            .long("policy")
+            .required(true)
+            .value_name("URI")
+            .help("Policy URI or SHA prefix. Supported schemes: registry://, https://, file://"),
+        Arg::new("seed-request")
+            .long("seed-request")
+            .required(true)
+            .value_name("PATH")
+            .help("File containing the admission request mutated to build the fuzzed requests"),
+        Arg::new("iterations")
+            .long("iterations")
+            .short('n')
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("1000")
+            .help("Number of mutated requests to evaluate"),
+        Arg::new("seed")
+            .long("seed")
+            .value_name("N")
+            .value_parser(clap::value_parser!(u64))
+            .help("Seed of the mutations, given to reproduce a previous run. A random one is used by default"),
+        Arg::new("timeout")
+            .long("timeout")
+            .value_name("SECONDS")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("2")
+            .help("Time granted to the evaluation of a request"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .value_name("DIR")
+            .help("Directory receiving the requests that made the policy misbehave"),
+        Arg::new("settings-path")
+            .long("settings-path")
+            .short('s')
+            .value_name("PATH")
+            .help("File containing the settings of the policy"),
+        Arg::new("settings-json")
+            .long("settings-json")
+            .value_name("VALUE")
+            .conflicts_with("settings-path")
+            .help("JSON string containing the settings of the policy"),
+        Arg::new("raw")
+            .long("raw")
+            .num_args(0)
+            .help("Mutate a raw request"),
+        Arg::new("fuzz-settings")
+            .long("fuzz-settings")
+            .num_args(0)
+            .conflicts_with_all(["settings-path", "settings-json"])
+            .help("Fuzz the settings of the policy, generated from its settings JSON Schema, instead of the request"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+    ];
+    args.extend(registry_credentials_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("fuzz")
+        .about("Evaluates mutated requests to find the ones making a policy misbehave")
+        .long_about(
+            r#"Evaluates mutated requests to find the ones making a policy misbehave.
+
+The object of the seed request is mutated: its fields are dropped, their
+values are replaced with values of other types and huge strings are injected
+into them. The whole request is mutated when it's a raw one.
+
+Every mutated request is evaluated twice, and the requests are reported when
+the policy:
+- fails to evaluate them, like when it panics
+- takes longer than '--timeout' to evaluate them
+- returns different responses for them
+
+The seed of the mutations is printed, giving it via '--seed' reproduces the
+same requests. The requests reported are saved into '--output-dir', and can be
+evaluated again via 'kwctl run --request-path'.
+
+With '--fuzz-settings', the seed request is evaluated with settings generated
+from the settings JSON Schema embedded into the policy metadata. Half of the
+settings break the schema, and are reported when the policy accepts them.
+The settings making the policy fail are reported too.
+
+The policy is evaluated without access to the host capabilities. The command
+fails when at least a request, or settings, are reported."#,
+        )
+        .args(args)
+}
+
+fn subcommand_changelog() -> Command {
+    let mut args = vec![
+        Arg::new("from")
+            .long("from")
+            .required(true)
+            .value_name("TAG")
+            .help("Tag of the previous release of the policy"),
+        Arg::new("to")
+            .long("to")
+            .required(true)
+            .value_name("TAG")
+            .help("Tag of the new release of the policy"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+    ];
+    args.extend(registry_credentials_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("repository")
+            .required(true)
+            .index(1)
+            .help("Registry repository of the policy, without tag. For example: registry://ghcr.io/kubewarden/policies/pod-privileged"),
+    );
+
+    Command::new("changelog")
+        .about("Generates a Markdown changelog between two releases of a policy")
+        .long_about(
+            r#"Generates a Markdown changelog between two releases of a policy.
+
+The changelog reports the changes of:
+- the metadata of the policy, like its rules and the context aware resources it reads
+- the annotations of the policy
+- the documentation of the settings, found inside of the usage annotation
+- the settings JSON Schema, found inside of the settings-schema annotation
+
+When the policy declares its source code repository, and the repository is
+hosted on GitHub or GitLab, the changelog links the commits between the two
+tags.
+
+The metadata is read from the OCI manifest of the policies pushed by recent
+versions of kwctl, the other policies are downloaded to a temporary directory."#,
+        )
+        .args(args)
+}
+
+fn subcommand_store() -> Command {
+    let target = Arg::new("target")
+        .required(true)
+        .index(1)
+        .help("Policy store of the remote machine: ssh://[user@]host[:port]/path");
+
+    let sync_help = "Synchronizes the local policy store with the store of another machine, reached via ssh. Only the policies that are missing, or whose digest is different, are transferred. The 'ssh' and 'sha256sum' commands must be available";
+
+    let mut gc_args = vec![
+        Arg::new("dry-run")
+            .long("dry-run")
+            .num_args(0)
+            .help("Print the policies that would be removed, without removing them"),
+        Arg::new("keep-latest")
+            .long("keep-latest")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Keep the N most recently pulled policies of each repository"),
+        Arg::new("older-than")
+            .long("older-than")
+            .value_name("AGE")
+            .help("Remove the policies neither pulled nor evaluated within AGE, like 90d. Supported units: m (minutes), h (hours), d (days), w (weeks)"),
+        Arg::new("unused")
+            .long("unused")
+            .num_args(0)
+            .help("Remove the policies never evaluated by 'run' or 'bench'"),
+    ];
+    gc_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("store")
+        .about("Manages the local policy store: synchronization with other machines, export, deduplication, garbage collection and integrity verification")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("push")
+                .about("Copies the local policies to the remote store")
+                .long_about(sync_help)
+                .arg(target.clone()),
+        )
+        .subcommand(
+            Command::new("pull")
+                .about("Copies the policies of the remote store to the local one")
+                .long_about(sync_help)
+                .arg(target),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Exports policies of the local store as an OCI image layout")
+                .long_about(
+                    r#"Exports policies of the local store as an OCI image layout.
+
+The layout can be consumed by stock OCI tooling, for example to copy the
+policies into a registry with 'oras cp --from-oci-layout' or 'skopeo copy oci:'.
+Each policy is listed inside of the index under its image reference, like
+ghcr.io/kubewarden/policies/safe-labels:v1.0.0. The policies not pulled from a
+registry are skipped. Exporting into an existing layout adds the policies to it."#,
+                )
+                .arg(
+                    Arg::new("oci-layout")
+                        .long("oci-layout")
+                        .value_name("DIR")
+                        .required(true)
+                        .help("Directory of the OCI image layout, created when missing"),
+                )
+                .arg(
+                    Arg::new("policies")
+                        .action(ArgAction::Append)
+                        .index(1)
+                        .help("Policies to export, by URI or SHA prefix. Defaults to all the policies of the local store"),
+                ),
+        )
+        .subcommand(
+            Command::new("dedup")
+                .about("Stores only once the modules shared by multiple policies of the local store")
+                .long_about(
+                    r#"Stores only once the modules shared by multiple policies of the local store.
+
+The same module pulled under several tags, or from several registries, is kept
+once and linked from all the policies. The policies pulled by kwctl are
+deduplicated automatically, this command deduplicates the policies pulled by
+older versions of kwctl or copied into the store by other means."#,
+                ),
+        )
+        .subcommand(
+            Command::new("gc")
+                .about("Removes stale policies from the local store, reporting the reclaimed space")
+                .long_about(
+                    r#"Removes stale policies from the local store, reporting the reclaimed space.
+
+A policy is removed when it matches all the given filters. The policies kept
+by --keep-latest are never removed. At least one filter must be given."#,
+                )
+                .args(gc_args),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Verifies that the policies of the local store have not been corrupted since they have been pulled")
+                .long_about(
+                    r#"Verifies that the policies of the local store have not been corrupted since they have been pulled.
+
+Every policy is hashed again and compared against the SHA-256 digest recorded
+when it has been pulled. The policies pulled by older versions of kwctl, or
+copied into the store by other means, have no recorded digest: pull them again
+to record it. Fails when any policy is corrupted."#,
+                ),
+        )
+}
+
+fn subcommand_trust_root() -> Command {
+    let mut update_args = vec![
+        Arg::new("tuf-mirror")
+            .long("tuf-mirror")
+            .value_name("URL")
+            .requires("tuf-root")
+            .conflicts_with("reset")
+            .help("Base URL of a self-hosted TUF repository serving the Sigstore trust root. Used by all the following commands"),
+        Arg::new("tuf-root")
+            .long("tuf-root")
+            .value_name("PATH")
+            .requires("tuf-mirror")
+            .help("Trusted root.json of the TUF repository given via --tuf-mirror"),
+        Arg::new("reset")
+            .long("reset")
+            .num_args(0)
+            .help("Go back to the TUF repository of the Sigstore public good instance"),
+    ];
+    update_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("trust-root")
+        .about("Manages the Sigstore trust root used to verify keyless signatures")
+        .long_about(
+            r#"Manages the Sigstore trust root used to verify keyless signatures.
+
+The Fulcio certificates and the Rekor keys are obtained from the TUF
+repository of the Sigstore public good instance, or from a self-hosted TUF
+repository. A copy is cached locally, which is used by '--offline'."#,
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("update")
+                .about("Refreshes the trust root from the TUF repository")
+                .args(update_args),
+        )
+        .subcommand(
+            Command::new("status")
+                .about("Shows the source, the age and the fingerprints of the cached trust root"),
+        )
+}
+
+fn subcommand_validate() -> Command {
+    let mut args = pull_shared_flags();
+    args.push(trusted_only_flag());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("yaml_file")
+            .required(true)
+            .index(1)
+            .help("YAML file containing Kubewarden Custom Resources, like ClusterAdmissionPolicy and ClusterAdmissionPolicyGroup"),
+    );
+
+    Command::new("validate")
+        .about("Validates Kubewarden Custom Resources without evaluating a request")
+        .long_about(
+            r#"Validates Kubewarden Custom Resources without evaluating a request.
+
+All the policies referenced by the Custom Resources, including the members of
+the policy groups, are pulled and verified (when verification flags are given).
+Their settings are then validated. The expressions of the policy groups must
+reference only the policies of the group."#,
+        )
+        .args(args)
+}
+
+fn subcommand_audit() -> Command {
+    let mut args = pull_shared_flags();
+    args.push(trusted_only_flag());
+    args.push(
+        Arg::new("kubeconfig")
+            .long("kubeconfig")
+            .value_name("PATH")
+            .help("Kubeconfig of the cluster to audit. Defaults to the one of kubectl. With '--path', the custom resources of the manifests are resolved through the CustomResourceDefinitions of the cluster"),
+    );
+    args.push(
+        Arg::new("path")
+            .long("path")
+            .value_name("PATH")
+            .help("Directory, or file, containing the manifests to audit in place of the resources of a cluster. Use `-` to read them from the standard input"),
+    );
+    args.push(
+        Arg::new("policies")
+            .long("policies")
+            .required(true)
+            .value_name("PATH")
+            .help("YAML file containing Kubewarden Custom Resources, like ClusterAdmissionPolicy and ClusterAdmissionPolicyGroup"),
+    );
+    args.push(
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("FORMAT")
+            .value_parser(PossibleValuesParser::new(["text", "policy-report"]))
+            .default_value("text")
+            .help("Output format. policy-report prints PolicyReport and ClusterPolicyReport resources, like the ones of the Kubewarden audit scanner"),
+    );
+    args.push(
+        Arg::new("severities")
+            .long("severities")
+            .value_name("PATH")
+            .help("YAML file mapping the names of the policies to their severities, like `no-privileged-pods: error`. Takes precedence over the io.kubewarden.policy.severity annotations"),
+    );
+    args.push(
+        Arg::new("fail-on")
+            .long("fail-on")
+            .value_name("SEVERITY")
+            .value_parser(PossibleValuesParser::new(["error", "warning"]))
+            .help("Fail when the policies of SEVERITY, or of a higher one, reject more than '--max-violations' resources. Defaults to warning when auditing manifests"),
+    );
+    args.push(
+        Arg::new("metrics-path")
+            .long("metrics-path")
+            .value_name("PATH")
+            .help("Write a summary of the audit (resources accepted, rejected and skipped by every policy, violations and duration) to PATH, using the OpenMetrics text format"),
+    );
+    args.push(
+        Arg::new("expand-violations")
+            .long("expand-violations")
+            .num_args(0)
+            .help("List every resource rejected with the same message, in place of the first ones"),
+    );
+    args.push(
+        Arg::new("evaluation-timeout")
+            .long("evaluation-timeout")
+            .value_name("SECONDS")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help("Interrupt the evaluations taking longer than SECONDS, the resources are skipped and reported"),
+    );
+    args.push(
+        Arg::new("evaluation-memory-limit")
+            .long("evaluation-memory-limit")
+            .value_name("MIB")
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .help("Deny the policies more than MIB mebibytes of memory, the resources whose evaluation fails are skipped and reported"),
+    );
+    args.push(
+        Arg::new("max-violations")
+            .long("max-violations")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Number of resources the policies of the '--fail-on' severity can reject without failing the audit. Defaults to 0"),
+    );
+    args.extend(report_flags());
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("audit")
+        .about("Evaluates the policies against the resources of a cluster, reporting the ones they would reject")
+        .long_about(
+            r#"Evaluates the policies against the resources of a cluster, reporting the ones they would reject.
+
+The resources matching the rules, the namespace selector and the object
+selector of every policy are listed from the cluster. Each resource is turned
+into the CREATE request the API server would send if the resource was created
+again, and evaluated by the policy locally: nothing is deployed to the
+cluster.
+
+The resources rejected by the policies are reported together with the
+rejection messages. The resources rejected with the same message are grouped,
+the most frequent messages first, listing only the first resources of every
+group unless --expand-violations is given. The policies are evaluated
+regardless of their mode, so that the policies in monitor mode show what would
+break once they protect the cluster.
+
+With --path, the objects defined by the YAML and JSON manifests of a directory
+tree are audited in place of the resources of a cluster, no cluster is needed.
+`--path -` reads the manifests from the standard input, like the output of
+`helm template`. The audit fails when some of the manifests are rejected.
+
+The custom resources of the cluster are discovered together with the built-in
+ones. The custom resources of the manifests are resolved through the
+CustomResourceDefinitions defined by the manifests and, when --kubeconfig is
+given, through the ones of the cluster; the other ones are guessed from their
+kinds.
+
+--report-path writes the outcome of every resource, SARIF reports locate the
+rejected manifests by their files and lines for the code scanning
+dashboards.
+
+Every policy has a severity, error or warning, taken from the file given via
+--severities, or from the io.kubewarden.policy.severity annotation of its
+Custom Resource or of its metadata: high and critical are errors, info, low
+and medium warnings. Policies without a severity are errors. With --fail-on,
+the audit fails when the policies of that severity, or of a higher one,
+reject more than --max-violations resources.
+
+--evaluation-timeout and --evaluation-memory-limit cap the time and the
+memory granted to every evaluation, so that a policy stuck on a resource does
+not stall the audit. The resources going beyond the limits are skipped: they
+are reported apart, with the reason, and do not count as violations.
+
+`--output policy-report` prints the outcome as PolicyReport and
+ClusterPolicyReport resources of the Policy Working Group, with the shape of
+the ones written by the Kubewarden audit scanner: a report for every resource,
+with a result for every policy. Policy Reporter shows them together with the
+reports coming from the clusters."#,
+        )
+        .args(args)
+}
+
+fn subcommand_serve() -> Command {
+    let mut args = pull_shared_flags();
+    args.push(trusted_only_flag());
+    args.push(
+        Arg::new("policies")
+            .long("policies")
+            .required(true)
+            .value_name("PATH")
+            .help("policies.yml file of policy-server, listing the policies to serve"),
+    );
+    args.push(
+        Arg::new("port")
+            .long("port")
+            .value_name("PORT")
+            .value_parser(clap::value_parser!(u16))
+            .default_value("3000")
+            .help("Port to listen on"),
+    );
+    args.push(
+        Arg::new("address")
+            .long("address")
+            .value_name("ADDRESS")
+            .default_value("127.0.0.1")
+            .help("Address to listen on. Use 0.0.0.0 to be reachable from a cluster running inside of containers, like kind"),
+    );
+    args.push(
+        Arg::new("cert-file")
+            .long("cert-file")
+            .value_name("PATH")
+            .requires("key-file")
+            .help("PEM encoded certificate chain to serve HTTPS with"),
+    );
+    args.push(
+        Arg::new("key-file")
+            .long("key-file")
+            .value_name("PATH")
+            .requires("cert-file")
+            .help("PEM encoded private key of the certificate given via '--cert-file'"),
+    );
+    args.push(
+        Arg::new("generate-certs")
+            .long("generate-certs")
+            .num_args(0)
+            .help("Serve HTTPS with a self-signed certificate, generated for the '--webhook-host' and the listening address"),
+    );
+    args.push(
+        Arg::new("print-webhook-config")
+            .long("print-webhook-config")
+            .num_args(0)
+            .requires("tls")
+            .help("Print the ValidatingWebhookConfiguration and the MutatingWebhookConfiguration calling the policies at '--webhook-host', with the certificate as CA bundle"),
+    );
+    args.push(
+        Arg::new("webhook-host")
+            .long("webhook-host")
+            .value_name("HOST")
+            .default_value("host.docker.internal")
+            .help("Host name, or IP address, the API server reaches kwctl at. host.minikube.internal for minikube, the gateway of the kind network for kind on Linux"),
+    );
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("serve")
+        .visible_alias("daemon")
+        .about("Serves the policies of a policies.yml file over HTTP, like policy-server")
+        .long_about(
+            r#"Serves the policies of a policies.yml file over HTTP, like policy-server.
+
+The policies listed by the policies.yml file of policy-server are pulled,
+verified (when verification flags are given) and their settings validated.
+They are then served through the endpoints of policy-server:
+
+- POST /validate/<policy>: evaluates an AdmissionReview, honoring the mode of
+  the policy, whether it can mutate and its custom rejection message
+- POST /validate_raw/<policy>: evaluates a raw request, for raw policies
+- POST /audit/<policy>: evaluates an AdmissionReview regardless of the mode
+  of the policy
+- GET /readiness
+- GET /metrics: the evaluations of the policies and their latencies, with the
+  names and the labels of the metrics of policy-server
+
+The requests are evaluated and logged, so that the policies can be tried from
+curl or from a development cluster without deploying them. Every connection
+is served concurrently; the requests of one policy are evaluated one at a
+time. Every request is logged once served, together with its status and
+latency.
+
+The policies.yml file and the modules of the policies referenced via file://
+URIs are watched: the policies whose entries, or modules, change are reloaded
+once the requests being evaluated are done, without restarting the server. When a policy cannot be
+reloaded, like when its settings are not valid, the previous policies keep
+being served.
+
+The policies are served over plain HTTP, unless a certificate is given via
+--cert-file and --key-file. The API server of Kubernetes only calls webhooks
+over HTTPS: --generate-certs serves HTTPS with a self-signed certificate,
+valid for --webhook-host, and --print-webhook-config prints the webhook
+configurations calling the policies, trusting that certificate:
+
+  kwctl serve --policies policies.yml --address 0.0.0.0 \
+    --generate-certs --print-webhook-config > webhooks.yaml
+
+The webhooks match the rules of the metadata of the policies, skip the
+kube-system namespace and ignore the failures of kwctl. The certificate is
+generated again on every start, apply the webhook configurations again too.
+
+`kwctl daemon stats`, or `kwctl serve stats`, prints the latencies of the
+evaluations of a running server, by policy, kind and operation of the
+requests."#,
+        )
+        .args(args)
+        .group(
+            ArgGroup::new("tls")
+                .args(["cert-file", "generate-certs"])
+                .multiple(false),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(subcommand_serve_stats())
+}
+
+fn subcommand_serve_stats() -> Command {
+    Command::new("stats")
+        .about("Prints the latencies of the evaluations of a running 'kwctl serve', or 'kwctl daemon'")
+        .long_about(
+            r#"Prints the latencies of the evaluations of a running 'kwctl serve', or
+'kwctl daemon'.
+
+The latency histograms served by the /metrics endpoint of the server are
+summed by policy, and by kind and operation of the evaluated requests. Every
+row shows the number of evaluations, their mean latency and the buckets
+holding the 50th, 90th and 99th percentiles. The slowest rows come first, to
+spot the requests a policy is slow on during long testing sessions."#,
+        )
+        .arg(
+            Arg::new("url")
+                .long("url")
+                .value_name("URL")
+                .default_value("http://127.0.0.1:3000")
+                .help("URL of the running 'kwctl serve'"),
+        )
+        .arg(
+            Arg::new("insecure")
+                .long("insecure")
+                .num_args(0)
+                .help("Do not verify the certificate of the server, like the self-signed one of '--generate-certs'"),
+        )
+}
+
+fn subcommand_lint() -> Command {
+    Command::new("lint")
+        .about("Checks the metadata of a policy before it reaches policy-server")
+        .long_about(
+            r#"Checks the metadata of a policy before it reaches policy-server.
+
+Either a metadata file or an annotated policy can be checked. Besides the
+checks performed by `kwctl annotate`, the linter reports:
+- fields of the metadata file not matching the metadata schema, like
+  unknown fields
+- rules with empty fields, and mutating policies whose rules match only
+  DELETE or CONNECT operations
+- context aware resources with a malformed apiVersion or kind
+- missing title, description and source annotations, as errors
+- missing author, url and license annotations, as warnings
+- url and source annotations that are not http(s) URLs, and version
+  annotations that are not semantic versions
+
+The command fails when any error is found."#,
+        )
+        .arg(
+            Arg::new("path")
+                .required(true)
+                .index(1)
+                .help("Metadata file, or annotated policy, to check"),
+        )
+}
+
+fn subcommand_graph() -> Command {
+    let mut args = vec![Arg::new("output")
+        .long("output")
+        .short('o')
+        .value_name("FORMAT")
+        .value_parser(PossibleValuesParser::new(["dot", "mermaid"]))
+        .default_value("dot")
+        .help("Output format")];
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("yaml_file")
+            .index(1)
+            .help("YAML file containing Kubewarden Custom Resources. When omitted, all the policies of the local store are included"),
+    );
+
+    Command::new("graph")
+        .about("Renders which host capabilities and cluster resources are used by the policies")
+        .long_about(
+            r#"Renders which host capabilities and cluster resources are used by the policies.
+
+Each policy is linked to:
+- the host capabilities it uses, detected by looking at the host calls performed by its module
+- the Kubernetes resources it reads at evaluation time. For Kubewarden Custom Resources these
+  are the resources allowed by the Custom Resource, otherwise the ones declared by the policy
+  metadata
+- the Kubernetes resources it validates, as declared by the rules of the policy metadata
+
+The policies referenced by Kubewarden Custom Resources must have been pulled already."#,
+        )
+        .args(args)
+}
+
+fn subcommand_bench() -> Command {
+    let mut args = vec![
+        Arg::new("measurement_time")
+            .long("measurement-time")
+            .number_of_values(1)
+            .value_name("SECONDS")
+            .help("How long the bench 'should' run, num_samples is prioritized so benching will take longer to be able to collect num_samples if the code to be benched is slower than this time limit allowed"),
+        Arg::new("num_resamples")
+            .long("num-resamples")
+            .number_of_values(1)
+            .value_name("NUM")
+            .help("How many resamples should be done"),
+        Arg::new("num_samples")
+            .long("num-samples")
+            .number_of_values(1)
+            .value_name("NUM")
+            .help("How many resamples should be done. Recommended at least 50, above 100 doesn't seem to yield a significantly different result"),
+        Arg::new("warm_up_time")
+            .long("warm-up-time")
+            .number_of_values(1)
+            .value_name("SECONDS")
+            .help("How long the bench should warm up"),
+        Arg::new("dump_results_to_disk")
+            .long("dump-results-to-disk")
+            .help("Puts results in target/tiny-bench/label/.. if target can be found. used for comparing previous runs"),
+        Arg::new("metrics-path")
+            .long("metrics-path")
+            .value_name("PATH")
+            .help("Write a summary of the benchmark (policies benchmarked, iterations and mean durations of the operations) to PATH, using the OpenMetrics text format"),
+    ];
+    let mut run_args = run_args();
+    args.append(&mut run_args);
+    args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+    args.push(
+        Arg::new("uri_or_sha_prefix_or_yaml_file")
+            .required(true)
+            .index(1)
+            .help("Policy URI, SHA prefix or YAML file containing Kubewarden policy resources. Supported schemes: registry://, https://, file://. If schema is omitted, file:// is assumed, rooted on the current directory.")
+    );
+
+    Command::new("bench")
+        .about("Benchmarks a Kubewarden policy")
+        .long_about(format!(
+            r#"Benchmarks a Kubewarden policy.
+
+{}"#,
+            RUN_AND_BENCH_COMMON_LONG_ABOUT
+        ))
+        .args(args)
+        .group(
+            // these flags cannot be used at the same time
+            ArgGroup::new("host-capabilities-proxy").args([
+                "record-host-capabilities-interactions",
+                "replay-host-capabilities-interactions",
+            ]),
+        )
+}
+
+fn subcommand_migrate() -> Command {
+    let mut gatekeeper_args = vec![
+        Arg::new("constraint-template")
+            .long("constraint-template")
+            .required(true)
+            .value_name("PATH")
+            .help("File containing the Gatekeeper ConstraintTemplate"),
+        Arg::new("constraint")
+            .long("constraint")
+            .required(true)
+            .value_name("PATH")
+            .help("File containing the Gatekeeper Constraint, an instance of the ConstraintTemplate"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .short('o')
+            .value_name("PATH")
+            .help("Directory where the Rego files and the metadata of the policy are written. Defaults to a directory named after the ConstraintTemplate"),
+        Arg::new("module")
+            .long("module")
+            .value_name("URI")
+            .required_unless_present_any(["annotate", "push"])
+            .conflicts_with("push")
+            .help("Module of the ClusterAdmissionPolicy, like the registry URI the policy is going to be pushed to. Defaults to the annotated policy with --annotate"),
+        Arg::new("annotate")
+            .long("annotate")
+            .num_args(0)
+            .help("Build the policy with 'opa build' and annotate it with the metadata. opa is looked up in PATH, unless KWCTL_OPA is set to its path"),
+        Arg::new("push")
+            .long("push")
+            .value_name("URI")
+            .help("Build and annotate the policy, like --annotate, then push it to the registry URI, used as module of the ClusterAdmissionPolicy"),
+        Arg::new("docker-config-json-path")
+            .long("docker-config-json-path")
+            .value_name("PATH")
+            .requires("push")
+            .help("Path to a directory containing the Docker 'config.json' file. Can be used to indicate registry authentication details"),
+        Arg::new("sources-path")
+            .long("sources-path")
+            .value_name("PATH")
+            .requires("push")
+            .help("YAML file holding source information (https, registry insecure hosts, custom CA's...)"),
+    ];
+    gatekeeper_args.extend(registry_credentials_flags());
+    gatekeeper_args.sort_by(|a, b| a.get_id().cmp(b.get_id()));
+
+    Command::new("migrate")
+        .about("Migrate policies of other policy engines to Kubewarden")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("gatekeeper")
+                .about("Convert a Gatekeeper `ConstraintTemplate` and one of its Constraints into a Kubewarden policy and `ClusterAdmissionPolicy`")
+                .after_long_help(
+                    r#"The Rego of the ConstraintTemplate is written, together with a metadata.yml file running it in the
+gatekeeper execution mode, to the output directory. The parameters of the Constraint become the settings of the
+policy, its match section the rules, namespaceSelector and objectSelector of the ClusterAdmissionPolicy printed to
+the standard output. The Constraints that do not deny the requests (dryrun and warn) are deployed in monitor mode.
+
+The namespaces selected by prefix, like 'kube-*', and the scope and name matchers are not supported by Kubewarden,
+they are ignored with a warning. The policies reading data.inventory must be adapted to the context-aware
+capabilities of Kubewarden.
+
+With --annotate and --push the policy is built with opa, which must be v1.0 or later."#,
+                )
+                .args(gatekeeper_args),
+        )
+}
+
+fn subcommand_save() -> Command {
+    Command::new("save")
+        .about("save policies to a tar.gz file")
+        .arg(
             Arg::new("output")
                 .long("output")
                 .short('o')
@@ -684,15 +2220,58 @@ fn subcommand_docs() -> Command {
 
 pub fn build_cli() -> Command {
     let mut subcommands = vec![
-        Command::new("policies").about("Lists all downloaded policies"),
+        subcommand_policies(),
         Command::new("info").about("Display system information"),
+        Command::new("version")
+            .about("Display version and build information")
+            .arg(
+                Arg::new("output")
+                    .long("output")
+                    .short('o')
+                    .value_name("FORMAT")
+                    .value_parser(PossibleValuesParser::new(["text", "json"]))
+                    .default_value("text")
+                    .help("Output format"),
+            ),
         Command::new("rm")
-            .about("Removes a Kubewarden policy from the store")
+            .about("Removes Kubewarden policies from the store")
+            .arg(
+                Arg::new("all")
+                    .long("all")
+                    .num_args(0)
+                    .help("Remove all the policies of the store"),
+            )
+            .arg(
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .num_args(0)
+                    .help("Print the policies that would be removed and the space that would be reclaimed, without removing them"),
+            )
+            .arg(
+                Arg::new("interactive")
+                    .long("interactive")
+                    .short('i')
+                    .num_args(0)
+                    .conflicts_with("dry-run")
+                    .help("Ask for confirmation before removing each policy"),
+            )
             .arg(
                 Arg::new("uri_or_sha_prefix")
+                    .required_unless_present("all")
+                    .conflicts_with("all")
+                    .num_args(1..)
+                    .index(1)
+                    .help("Policy URIs, SHA prefixes or glob patterns matched against the URIs, like 'registry://ghcr.io/kubewarden/*:v1.*'. '*' matches any sequence of characters, '?' any single character"),
+            ),
+        Command::new("schema")
+            .about("Prints the JSON Schema of a kwctl configuration file")
+            .after_long_help("The schema can be used by editors to validate the configuration files, for example by adding a '# yaml-language-server: $schema=<path to the schema>' comment to them")
+            .arg(
+                Arg::new("kind")
                     .required(true)
                     .index(1)
-                    .help("Policy URI or SHA prefix"),
+                    .value_parser(PossibleValuesParser::new(["metadata", "sources", "test-suite", "verification-config"]))
+                    .help("Kind of configuration file"),
             ),
         Command::new("completions")
             .about("Generate shell completions")
@@ -711,23 +2290,52 @@ pub fn build_cli() -> Command {
                     ]))
                     .help("Shell type"),
             ),
+        subcommand_store(),
+        subcommand_sources(),
+        subcommand_changelog(),
+        subcommand_diff(),
+        subcommand_trust_root(),
         Command::new("load")
-            .about("load policies from a tar.gz file")
+            .about("load policies from a tar.gz file or from an OCI image layout")
             .arg(
                 Arg::new("input")
                     .long("input")
-                    .required(true)
+                    .required_unless_present("oci-layout")
                     .help("load policies from tarball"),
+            )
+            .arg(
+                Arg::new("oci-layout")
+                    .long("oci-layout")
+                    .value_name("DIR")
+                    .conflicts_with("input")
+                    .help("Load the policies of an OCI image layout, like the ones written by 'kwctl store export', 'oras' or 'skopeo'. The digests of the policies are validated"),
+            )
+            .arg(
+                Arg::new("repository")
+                    .long("repository")
+                    .value_name("REPOSITORY")
+                    .requires("oci-layout")
+                    .help("Repository of the policies listed inside of the OCI image layout by tag only, like registry://ghcr.io/kubewarden/policies/safe-labels"),
             ),
         subcommand_pull(),
+        subcommand_update(),
         subcommand_verify(),
         subcommand_push(),
         subcommand_run(),
+        subcommand_validate(),
+        subcommand_audit(),
+        subcommand_serve(),
+        subcommand_graph(),
+        subcommand_lint(),
         subcommand_annotate(),
         subcommand_inspect(),
+        subcommand_migrate(),
         subcommand_scaffold(),
+        subcommand_sign(),
         subcommand_digest(),
         subcommand_bench(),
+        subcommand_test(),
+        subcommand_fuzz(),
         subcommand_save(),
         subcommand_docs(),
     ];
@@ -744,13 +2352,78 @@ pub fn build_cli() -> Command {
                 .num_args(0)
                 .help("Increase verbosity"),
         )
+        .arg(
+            Arg::new("ca-cert")
+                .long("ca-cert")
+                .value_name("PATH")
+                .action(ArgAction::Append)
+                .number_of_values(1)
+                .help("PEM encoded CA certificate to trust, in addition to the system ones, when connecting to registries, https:// servers and Sigstore services. Can be repeated multiple times"),
+        )
+        .arg(
+            Arg::new("lenient")
+                .long("lenient")
+                .num_args(0)
+                .global(true)
+                .help("Skip the validation of the configuration files (sources, verification config, policy metadata) against their schemas, and of the policy settings against the settings JSON Schema of the policy"),
+        )
+        .arg(
+            Arg::new("lockfile")
+                .long("lockfile")
+                .value_name("PATH")
+                .env("KWCTL_LOCKFILE")
+                .global(true)
+                .help("YAML file recording the version resolved for each policy URI using a semantic version constraint as tag, like registry://ghcr.io/kubewarden/policies/safe-labels:^1.2. The file is created when missing"),
+        )
         .arg(
             Arg::new("no-color")
                 .long("no-color")
                 .num_args(0)
                 .help("Disable colorful output"),
         )
+        .arg(
+            Arg::new("store-overlay")
+                .long("store-overlay")
+                .value_name("DIR")
+                .env("KWCTL_STORE_OVERLAY")
+                .global(true)
+                .requires("store-read-only")
+                .help("Writable directory the policies missing from the read-only store are pulled into"),
+        )
+        .arg(
+            Arg::new("store-profile")
+                .long("store-profile")
+                .value_name("NAME_OR_PATH")
+                .env("KWCTL_STORE")
+                .global(true)
+                .help("Named store to use, with its own policies, sources and verification defaults. A name selects a store inside of the kwctl cache directory, a path selects the given directory. Defaults to the default store"),
+        )
+        .arg(
+            Arg::new("store-read-only")
+                .long("store-read-only")
+                .num_args(0)
+                .env("KWCTL_STORE_READ_ONLY")
+                .global(true)
+                .help("Never write into the local policy store, like a store shared by many CI jobs. The policies found inside of the store are not pulled again, the missing ones cause an error unless --store-overlay is given"),
+        )
+        .arg(
+            Arg::new("utc")
+                .long("utc")
+                .num_args(0)
+                .global(true)
+                .help("Print timestamps in UTC instead of the local time zone. Timestamps are always formatted as RFC3339"),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Proxy used to reach registries, https:// servers and Sigstore services. Supported schemes: http://, https://, socks5://, socks5h://. By default the HTTP_PROXY, HTTPS_PROXY and NO_PROXY environment variables are honored"),
+        )
         .subcommands(subcommands)
+        // unknown subcommands are dispatched to the `kwctl-<subcommand>` plugins
+        .allow_external_subcommands(true)
+        .external_subcommand_value_parser(clap::value_parser!(std::ffi::OsString))
+        .after_help("Any kwctl-<NAME> executable found inside of $PATH can be invoked as 'kwctl <NAME>'")
         .long_version(VERSION_AND_BUILTINS.as_str())
         .subcommand_required(true)
         .arg_required_else_help(true)
@@ -0,0 +1,99 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::Result;
+use clap::ArgMatches;
+
+use crate::{
+    command::{
+        audit::{
+            manifests::{CustomResources, Manifests},
+            severity::{self, FailureThreshold, Severity},
+            AuditOptions, AuditOutput, PolicyScope,
+        },
+        run::limits::EvaluationLimits,
+    },
+    config::{
+        policy_definition::PolicyDefinition,
+        pull_and_run::{parse_pull_settings, resolve_version_constraints, PullAndRunSettings},
+    },
+    test_report::report_output,
+};
+
+pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
+    // read by all the clients connecting to the cluster, including the ones
+    // serving the host capabilities to the policies
+    let kubeconfig = matches.get_one::<String>("kubeconfig").map(PathBuf::from);
+    let policies = matches
+        .get_one::<String>("policies")
+        .expect("policies is required");
+    let mut policy_definitions = PolicyDefinition::from_yaml_file(policies)?;
+    let scopes = PolicyScope::from_yaml_file(policies)?;
+    resolve_version_constraints(matches, &mut policy_definitions).await?;
+    let pull_settings = parse_pull_settings(matches, &policy_definitions).await?;
+    let manifests = match matches.get_one::<String>("path") {
+        Some(path) => {
+            let custom_resources = if let Some(kubeconfig) = &kubeconfig {
+                CustomResources::from_cluster(kubeconfig).await?
+            } else {
+                CustomResources::default()
+            };
+            Some(Manifests::read(path, custom_resources)?)
+        }
+        None => None,
+    };
+    let severities = matches
+        .get_one::<String>("severities")
+        .map(|path| severity::from_yaml_file(path))
+        .transpose()?
+        .unwrap_or_default();
+    // the manifests are audited by CI before being deployed, any rejection
+    // fails the audit unless told otherwise
+    let fail_on = match matches.get_one::<String>("fail-on") {
+        Some(fail_on) => Some(Severity::try_from(fail_on.as_str())?),
+        None if manifests.is_some() || matches.contains_id("max-violations") => {
+            Some(Severity::Warning)
+        }
+        None => None,
+    };
+    let threshold = fail_on.map(|fail_on| FailureThreshold {
+        fail_on,
+        max_violations: matches
+            .get_one::<usize>("max-violations")
+            .copied()
+            .unwrap_or_default(),
+    });
+    let output = AuditOutput::try_from(
+        matches
+            .get_one::<String>("output")
+            .map(String::as_str)
+            .unwrap_or("text"),
+    )?;
+    let timeout = matches
+        .get_one::<u64>("evaluation-timeout")
+        .map(|seconds| Duration::from_secs(*seconds));
+    let memory = matches.get_one::<u64>("evaluation-memory-limit").copied();
+    let evaluation_limits = (timeout.is_some() || memory.is_some())
+        .then(|| EvaluationLimits::new(timeout, memory, true))
+        .transpose()?;
+
+    crate::command::audit::exec(
+        &policy_definitions,
+        &scopes,
+        AuditOptions {
+            manifests,
+            severities,
+            threshold,
+            output,
+            report: report_output(matches)?,
+            metrics: matches.get_one::<String>("metrics-path").map(PathBuf::from),
+            expand_violations: matches.get_flag("expand-violations"),
+        },
+        PullAndRunSettings {
+            enable_wasmtime_cache: true,
+            evaluation_limits,
+            kubeconfig,
+            ..pull_settings
+        },
+    )
+    .await
+}
@@ -1,12 +1,15 @@
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 
-use crate::config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings};
+use crate::config::pull_and_run::{
+    parse_policy_definitions, parse_pull_and_run_settings, resolve_version_constraints,
+};
 
 pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
-    let policy_definitions = parse_policy_definitions(matches)?;
+    let mut policy_definitions = parse_policy_definitions(matches)?;
+    resolve_version_constraints(matches, &mut policy_definitions).await?;
     let pull_and_run_settings = parse_pull_and_run_settings(matches, &policy_definitions).await?;
     let benchmark_config = create_benchmark_config(matches)?;
 
@@ -14,6 +17,7 @@ pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
         &policy_definitions,
         &pull_and_run_settings,
         &benchmark_config,
+        matches.get_one::<String>("metrics-path").map(Path::new),
     )
     .await
 }
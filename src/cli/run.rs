@@ -1,11 +1,29 @@
 use anyhow::Result;
 use clap::ArgMatches;
 
-use crate::config::pull_and_run::{parse_policy_definitions, parse_pull_and_run_settings};
+use crate::{
+    config::pull_and_run::{
+        parse_policy_definitions, parse_pull_and_run_settings, resolve_version_constraints,
+    },
+    test_report::report_output,
+};
 
 pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
-    let policy_definitions = parse_policy_definitions(matches)?;
+    let mut policy_definitions = parse_policy_definitions(matches)?;
+    resolve_version_constraints(matches, &mut policy_definitions).await?;
     let pull_and_run_settings = parse_pull_and_run_settings(matches, &policy_definitions).await?;
+    let report_output = report_output(matches)?;
+    // the cases are named after the policies, the suite after the request
+    let report_name = matches
+        .get_one::<String>("request-path")
+        .expect("request-path is required");
 
-    crate::command::run::exec(&policy_definitions, &pull_and_run_settings).await
+    crate::command::run::exec(
+        &policy_definitions,
+        &pull_and_run_settings,
+        report_output
+            .as_ref()
+            .map(|report_output| (report_output, report_name.as_str())),
+    )
+    .await
 }
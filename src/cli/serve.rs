@@ -0,0 +1,85 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+
+use crate::{
+    command::serve::{tls::TlsCertificate, ServeOptions},
+    config::{
+        policy_definition::PolicyDefinition,
+        pull_and_run::{parse_pull_settings, resolve_version_constraints, PullAndRunSettings},
+    },
+};
+
+pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("stats") {
+        let url = matches
+            .get_one::<String>("url")
+            .expect("url has a default value");
+        return crate::command::serve::stats::exec(url, matches.get_flag("insecure")).await;
+    }
+    let policies = matches
+        .get_one::<String>("policies")
+        .expect("policies is required");
+    let address = matches
+        .get_one::<String>("address")
+        .expect("address has a default value");
+    let address: IpAddr = address
+        .parse()
+        .map_err(|e| anyhow!("invalid address {}: {}", address, e))?;
+    let port = *matches
+        .get_one::<u16>("port")
+        .expect("port has a default value");
+    let webhook_host = matches
+        .get_one::<String>("webhook-host")
+        .expect("webhook-host has a default value");
+
+    let certificate = if matches.get_flag("generate-certs") {
+        let mut hosts = vec![webhook_host.clone(), "localhost".to_string()];
+        if !address.is_unspecified() {
+            hosts.push(address.to_string());
+        }
+        Some(TlsCertificate::generate(&hosts)?)
+    } else if let (Some(cert_file), Some(key_file)) = (
+        matches.get_one::<String>("cert-file"),
+        matches.get_one::<String>("key-file"),
+    ) {
+        Some(TlsCertificate::read(cert_file, key_file)?)
+    } else {
+        None
+    };
+
+    crate::command::serve::exec(
+        async || load_policies(matches, policies).await,
+        ServeOptions {
+            address: SocketAddr::new(address, port),
+            config_path: PathBuf::from(policies),
+            certificate,
+            webhook_host: matches
+                .get_flag("print-webhook-config")
+                .then(|| webhook_host.clone()),
+        },
+    )
+    .await
+}
+
+/// Reads the policies of the policies.yml file, and the settings to pull
+/// them, when starting and every time the policies are reloaded
+async fn load_policies(
+    matches: &ArgMatches,
+    policies: &str,
+) -> Result<(Vec<PolicyDefinition>, PullAndRunSettings)> {
+    let mut policy_definitions = PolicyDefinition::from_policy_server_config(policies)?;
+    resolve_version_constraints(matches, &mut policy_definitions).await?;
+    let pull_settings = parse_pull_settings(matches, &policy_definitions).await?;
+    Ok((
+        policy_definitions,
+        PullAndRunSettings {
+            enable_wasmtime_cache: true,
+            ..pull_settings
+        },
+    ))
+}
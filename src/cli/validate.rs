@@ -0,0 +1,14 @@
+use anyhow::Result;
+use clap::ArgMatches;
+
+use crate::config::{policy_definition::PolicyDefinition, pull_and_run::parse_pull_settings};
+
+pub(crate) async fn exec(matches: &ArgMatches) -> Result<()> {
+    let yaml_file = matches
+        .get_one::<String>("yaml_file")
+        .expect("yaml_file is required");
+    let policy_definitions = PolicyDefinition::from_yaml_file(yaml_file)?;
+    let pull_settings = parse_pull_settings(matches, &policy_definitions).await?;
+
+    crate::command::validate::exec(&policy_definitions, &pull_settings).await
+}
@@ -1,2 +1,5 @@
+pub(crate) mod audit;
 pub(crate) mod bench;
 pub(crate) mod run;
+pub(crate) mod serve;
+pub(crate) mod validate;
@@ -0,0 +1,1040 @@
+//! Audit of the resources of a cluster, run by `kwctl audit`.
+//!
+//! The resources matching the rules and the selectors of the policies are
+//! listed from the cluster, and evaluated by the policies as if they were
+//! created again. The policies are evaluated locally, nothing is deployed to
+//! the cluster.
+//!
+//! The objects defined by manifests can be audited in place of the ones of a
+//! cluster, see [`manifests`].
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::{
+    api::{
+        admissionregistration::v1::RuleWithOperations,
+        authentication::v1::UserInfo,
+        core::v1::{Namespace, ObjectReference},
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{LabelSelector, ObjectMeta},
+        runtime::RawExtension,
+    },
+};
+use policy_evaluator::{
+    admission_request::{AdmissionRequest, GroupVersionKind, GroupVersionResource},
+    admission_response::AdmissionResponse,
+    kube::{
+        self,
+        api::{ApiResource, DynamicObject, ListParams},
+        core::TypeMeta,
+        discovery::{verbs, Discovery, Scope},
+    },
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::error;
+
+use crate::{
+    command::run::{
+        evaluator::{build_kube_client, build_validate_request, Evaluator},
+        local_data::LocalData,
+    },
+    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    metrics::Metrics,
+    test_report::{self, CaseResult, Location, ReportOutput, TestCaseReport, TestSuiteReport},
+};
+
+pub(crate) mod manifests;
+mod policy_report;
+pub(crate) mod severity;
+
+use manifests::Manifests;
+use policy_report::PolicyReports;
+use severity::{FailureThreshold, Severity};
+
+/// Metadata fields set by the API server, missing from CREATE requests
+const SERVER_METADATA_FIELDS: &[&str] = &[
+    "creationTimestamp",
+    "generation",
+    "managedFields",
+    "resourceVersion",
+    "uid",
+];
+
+/// References listed for every group of identical violations, unless they
+/// are expanded
+const GROUPED_REFERENCES: usize = 5;
+
+/// The resources a policy applies to, as defined by its Custom Resource
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PolicyScope {
+    #[serde(default)]
+    kind: String,
+    #[serde(default)]
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: ScopeSpec,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScopeSpec {
+    #[serde(default)]
+    rules: Vec<RuleWithOperations>,
+    namespace_selector: Option<LabelSelector>,
+    object_selector: Option<LabelSelector>,
+    #[serde(default)]
+    mutating: bool,
+}
+
+impl PolicyScope {
+    /// Reads the scopes of the Custom Resources defined inside of the file,
+    /// in the same order as [`PolicyDefinition::from_yaml_file`]
+    pub(crate) fn from_yaml_file(yaml_path: &str) -> Result<Vec<PolicyScope>> {
+        let deserializer = serde_yaml::Deserializer::from_reader(
+            std::fs::File::open(yaml_path)
+                .map_err(|e| anyhow!("Cannot open YAML file {:?}: {}", yaml_path, e))?,
+        );
+        deserializer
+            .map(|document| {
+                PolicyScope::deserialize(document)
+                    .map_err(|e| anyhow!("Cannot parse YAML file {:?}: {}", yaml_path, e))
+            })
+            .collect()
+    }
+
+    /// AdmissionPolicies, and their groups, apply only to the resources of
+    /// their own namespace
+    fn namespace(&self) -> Option<&str> {
+        matches!(
+            self.kind.as_str(),
+            "AdmissionPolicy" | "AdmissionPolicyGroup"
+        )
+        .then(|| self.metadata.namespace.as_deref().unwrap_or("default"))
+    }
+
+    /// `clusterwide-<name>`, or `namespaced-<namespace>-<name>`, the name of
+    /// the policy inside of the reports of the audit scanner
+    fn unique_name(&self) -> String {
+        let name = self.metadata.name.as_deref().unwrap_or_default();
+        match self.namespace() {
+            Some(namespace) => format!("namespaced-{namespace}-{name}"),
+            None => format!("clusterwide-{name}"),
+        }
+    }
+
+    /// Whether the object is selected by the policy. The labels of the
+    /// namespace are checked against the namespace selector, they are missing
+    /// for the resources that are not namespaced.
+    fn selects(
+        &self,
+        object: &DynamicObject,
+        namespace_labels: Option<&BTreeMap<String, String>>,
+    ) -> bool {
+        self.namespace()
+            .is_none_or(|namespace| object.metadata.namespace.as_deref() == Some(namespace))
+            && namespace_labels.is_none_or(|labels| {
+                selector_matches(self.spec.namespace_selector.as_ref(), labels)
+            })
+            && selector_matches(
+                self.spec.object_selector.as_ref(),
+                object.metadata.labels.as_ref().unwrap_or(&BTreeMap::new()),
+            )
+    }
+
+    /// Whether the object of the resource is selected by the policy, given
+    /// the labels of the namespaces
+    fn selects_in(
+        &self,
+        object: &DynamicObject,
+        resource: &ApiResource,
+        namespaced: bool,
+        namespace_labels: &HashMap<String, BTreeMap<String, String>>,
+    ) -> bool {
+        let no_labels = BTreeMap::new();
+        // namespaces are selected by their own labels
+        let labels = if namespaced {
+            Some(
+                object
+                    .metadata
+                    .namespace
+                    .as_ref()
+                    .and_then(|namespace| namespace_labels.get(namespace))
+                    .unwrap_or(&no_labels),
+            )
+        } else if resource.group.is_empty() && resource.kind == "Namespace" {
+            Some(object.metadata.labels.as_ref().unwrap_or(&no_labels))
+        } else {
+            None
+        };
+        self.selects(object, labels)
+    }
+}
+
+/// Whether CREATE requests of the resource are matched by the rule
+fn rule_matches(rule: &RuleWithOperations, resource: &ApiResource, namespaced: bool) -> bool {
+    let includes = |values: &Option<Vec<String>>, value: &str| {
+        values
+            .as_ref()
+            .is_some_and(|values| values.iter().any(|v| v == "*" || v == value))
+    };
+    includes(&rule.operations, "CREATE")
+        && includes(&rule.api_groups, &resource.group)
+        && includes(&rule.api_versions, &resource.version)
+        && rule.resources.as_ref().is_some_and(|resources| {
+            resources
+                .iter()
+                .any(|r| r == "*" || r == "*/*" || *r == resource.plural)
+        })
+        && match rule.scope.as_deref() {
+            Some("Cluster") => !namespaced,
+            Some("Namespaced") => namespaced,
+            _ => true,
+        }
+}
+
+/// Whether the labels match the selector, a missing selector matches
+/// everything
+fn selector_matches(selector: Option<&LabelSelector>, labels: &BTreeMap<String, String>) -> bool {
+    let Some(selector) = selector else {
+        return true;
+    };
+    selector
+        .match_labels
+        .iter()
+        .flatten()
+        .all(|(key, value)| labels.get(key) == Some(value))
+        && selector
+            .match_expressions
+            .iter()
+            .flatten()
+            .all(|requirement| {
+                let value = labels.get(&requirement.key);
+                let values = requirement.values.as_deref().unwrap_or_default();
+                match requirement.operator.as_str() {
+                    "In" => value.is_some_and(|value| values.contains(value)),
+                    "NotIn" => value.is_none_or(|value| !values.contains(value)),
+                    "Exists" => value.is_some(),
+                    "DoesNotExist" => value.is_none(),
+                    _ => false,
+                }
+            })
+}
+
+/// The CREATE request of the object, as if it was created again
+fn create_request(object: &DynamicObject, resource: &ApiResource) -> Result<Value> {
+    let mut object_json = serde_json::to_value(object)?;
+    if let Some(object) = object_json.as_object_mut() {
+        object.remove("status");
+        if let Some(metadata) = object.get_mut("metadata").and_then(Value::as_object_mut) {
+            for field in SERVER_METADATA_FIELDS {
+                metadata.remove(*field);
+            }
+        }
+    }
+
+    let kind = GroupVersionKind {
+        group: resource.group.clone(),
+        version: resource.version.clone(),
+        kind: resource.kind.clone(),
+    };
+    let gvr = GroupVersionResource {
+        group: resource.group.clone(),
+        version: resource.version.clone(),
+        resource: resource.plural.clone(),
+    };
+    let request = AdmissionRequest {
+        uid: object.metadata.uid.clone().unwrap_or_default(),
+        kind: kind.clone(),
+        request_kind: Some(kind),
+        resource: gvr.clone(),
+        request_resource: Some(gvr),
+        sub_resource: None,
+        request_sub_resource: None,
+        name: object.metadata.name.clone(),
+        namespace: object.metadata.namespace.clone(),
+        operation: "CREATE".to_string(),
+        user_info: UserInfo {
+            username: Some("kubernetes-admin".to_string()),
+            groups: Some(vec![
+                "kubeadm:cluster-admins".to_string(),
+                "system:authenticated".to_string(),
+            ]),
+            ..Default::default()
+        },
+        options: Some(RawExtension(serde_json::json!({
+            "apiVersion": "meta.k8s.io/v1",
+            "kind": "CreateOptions",
+        }))),
+        object: Some(RawExtension(object_json)),
+        old_object: None,
+        dry_run: Some(false),
+    };
+    Ok(serde_json::to_value(request)?)
+}
+
+/// The reference of the object, as the scope of its policy report
+fn object_reference(object: &DynamicObject, resource: &ApiResource) -> ObjectReference {
+    ObjectReference {
+        api_version: Some(resource.api_version.clone()),
+        kind: Some(resource.kind.clone()),
+        name: object.metadata.name.clone(),
+        namespace: object.metadata.namespace.clone(),
+        uid: object.metadata.uid.clone(),
+        resource_version: object.metadata.resource_version.clone(),
+        ..Default::default()
+    }
+}
+
+/// The CREATE request of an object selected by a policy
+struct ObjectRequest {
+    reference: String,
+    object: ObjectReference,
+    /// Where the object is defined, for the objects of manifests
+    location: Option<Location>,
+    request: Value,
+}
+
+/// `Pod default/nginx`, or `Namespace kube-system` for the resources that
+/// are not namespaced
+fn reference(object: &DynamicObject, resource: &ApiResource) -> String {
+    let name = object.metadata.name.as_deref().unwrap_or_default();
+    match &object.metadata.namespace {
+        Some(namespace) => format!("{} {}/{}", resource.kind, namespace, name),
+        None => format!("{} {}", resource.kind, name),
+    }
+}
+
+fn objects_key(resource: &ApiResource) -> String {
+    format!("{}/{}", resource.api_version, resource.plural)
+}
+
+/// The resources served by the cluster, and the objects listed so far
+struct Cluster {
+    client: kube::Client,
+    /// The resources that can be listed and created, together with whether
+    /// they are namespaced
+    resources: Vec<(ApiResource, bool)>,
+    namespace_labels: HashMap<String, BTreeMap<String, String>>,
+    /// The objects listed so far, by `apiVersion/plural`
+    objects: HashMap<String, Vec<DynamicObject>>,
+}
+
+impl Cluster {
+    async fn connect(kubeconfig: Option<&Path>) -> Result<Self> {
+        let client = build_kube_client(kubeconfig).await?;
+        let discovery = Discovery::new(client.clone())
+            .run()
+            .await
+            .map_err(|e| anyhow!("cannot discover the resources of the cluster: {}", e))?;
+        let mut resources = Vec::new();
+        for group in discovery.groups() {
+            for version in group.versions() {
+                for (api_resource, capabilities) in group.versioned_resources(version) {
+                    if capabilities.supports_operation(verbs::LIST)
+                        && capabilities.supports_operation(verbs::CREATE)
+                    {
+                        resources.push((
+                            api_resource,
+                            matches!(capabilities.scope, Scope::Namespaced),
+                        ));
+                    }
+                }
+            }
+        }
+
+        let namespace_labels = kube::Api::<Namespace>::all(client.clone())
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| anyhow!("cannot list the namespaces of the cluster: {}", e))?
+            .items
+            .into_iter()
+            .filter_map(|namespace| {
+                Some((
+                    namespace.metadata.name?,
+                    namespace.metadata.labels.unwrap_or_default(),
+                ))
+            })
+            .collect();
+
+        Ok(Cluster {
+            client,
+            resources,
+            namespace_labels,
+            objects: HashMap::new(),
+        })
+    }
+
+    /// The resources matching the rules. Resources served in more versions
+    /// are taken once, in the preferred version matching the rules.
+    fn matching_resources(&self, rules: &[RuleWithOperations]) -> Vec<(ApiResource, bool)> {
+        let mut seen = HashSet::new();
+        self.resources
+            .iter()
+            .filter(|(resource, namespaced)| {
+                rules
+                    .iter()
+                    .any(|rule| rule_matches(rule, resource, *namespaced))
+            })
+            .filter(|(resource, _)| seen.insert((resource.group.clone(), resource.plural.clone())))
+            .cloned()
+            .collect()
+    }
+
+    /// Lists the objects of the resource, once
+    async fn load(&mut self, resource: &ApiResource) -> Result<()> {
+        let key = objects_key(resource);
+        if !self.objects.contains_key(&key) {
+            let api: kube::Api<DynamicObject> = kube::Api::all_with(self.client.clone(), resource);
+            let mut objects = api
+                .list(&ListParams::default())
+                .await
+                .map_err(|e| anyhow!("cannot list {}: {}", key, e))?
+                .items;
+            // the items of the lists have no type
+            for object in &mut objects {
+                object.types = Some(TypeMeta {
+                    api_version: resource.api_version.clone(),
+                    kind: resource.kind.clone(),
+                });
+            }
+            self.objects.insert(key, objects);
+        }
+        Ok(())
+    }
+
+    /// The CREATE requests of the objects selected by the policy
+    async fn requests(&mut self, scope: &PolicyScope) -> Result<Vec<ObjectRequest>> {
+        let mut requests = Vec::new();
+        for (resource, namespaced) in self.matching_resources(&scope.spec.rules) {
+            self.load(&resource).await?;
+            for object in &self.objects[&objects_key(&resource)] {
+                if scope.selects_in(object, &resource, namespaced, &self.namespace_labels) {
+                    requests.push(ObjectRequest {
+                        reference: reference(object, &resource),
+                        object: object_reference(object, &resource),
+                        location: None,
+                        request: create_request(object, &resource)?,
+                    });
+                }
+            }
+        }
+        Ok(requests)
+    }
+}
+
+/// How the outcome of the audit is printed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AuditOutput {
+    Text,
+    /// PolicyReport and ClusterPolicyReport resources, in place of the text
+    PolicyReport,
+}
+
+impl TryFrom<&str> for AuditOutput {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(Self::Text),
+            "policy-report" => Ok(Self::PolicyReport),
+            _ => Err(anyhow!("unknown output format: {}", value)),
+        }
+    }
+}
+
+/// How the audit is run and reported
+pub(crate) struct AuditOptions {
+    /// The manifests audited in place of the resources of the cluster
+    pub(crate) manifests: Option<Manifests>,
+    /// The severities of the policies, by name
+    pub(crate) severities: BTreeMap<String, Severity>,
+    pub(crate) threshold: Option<FailureThreshold>,
+    pub(crate) output: AuditOutput,
+    pub(crate) report: Option<ReportOutput>,
+    /// Where the summary of the audit is written, using the OpenMetrics text
+    /// format
+    pub(crate) metrics: Option<PathBuf>,
+    /// Whether every resource of the grouped violations is listed
+    pub(crate) expand_violations: bool,
+}
+
+/// Where the audited objects come from
+enum Resources {
+    Cluster(Cluster),
+    Manifests(Manifests),
+}
+
+impl Resources {
+    async fn requests(&mut self, scope: &PolicyScope) -> Result<Vec<ObjectRequest>> {
+        match self {
+            Resources::Cluster(cluster) => cluster.requests(scope).await,
+            Resources::Manifests(manifests) => manifests.requests(scope),
+        }
+    }
+}
+
+/// Outcome of the evaluation of a resource
+enum Outcome {
+    Accepted,
+    /// Holds the rejection message
+    Rejected(String),
+    /// The evaluation went beyond the limits, holds the reason
+    Skipped(String),
+}
+
+/// The reference of the object, followed by where it is defined for the
+/// objects of manifests
+fn located_reference(object: &ObjectRequest) -> String {
+    match &object.location {
+        Some(Location {
+            path,
+            line: Some(line),
+        }) => format!("{} ({}:{})", object.reference, path, line),
+        Some(Location { path, line: None }) => format!("{} ({})", object.reference, path),
+        None => object.reference.clone(),
+    }
+}
+
+/// Outcome of the evaluation of the resources selected by a policy
+#[derive(Default)]
+struct PolicyAudit {
+    evaluated: usize,
+    mutated: usize,
+    /// The references of the rejected resources, with the rejection messages
+    rejected: Vec<(String, String)>,
+    /// The references of the resources whose evaluation went beyond the
+    /// limits, with the reasons
+    skipped: Vec<(String, String)>,
+    /// The outcome of every evaluated resource, for the report
+    cases: Vec<TestCaseReport>,
+    /// The outcome of every resource, for the policy reports
+    objects: Vec<(ObjectReference, Outcome)>,
+}
+
+impl PolicyAudit {
+    fn record(&mut self, object: ObjectRequest, response: AdmissionResponse, duration: Duration) {
+        self.evaluated += 1;
+        let result = if !response.allowed {
+            let message = response
+                .status
+                .and_then(|status| status.message)
+                .unwrap_or_default();
+            self.rejected
+                .push((located_reference(&object), message.clone()));
+            self.objects
+                .push((object.object, Outcome::Rejected(message.clone())));
+            CaseResult::Failed(format!("rejected: {message}"))
+        } else {
+            if response.patch.is_some() {
+                self.mutated += 1;
+            }
+            self.objects.push((object.object, Outcome::Accepted));
+            CaseResult::Passed
+        };
+        self.cases.push(TestCaseReport {
+            name: object.reference,
+            result,
+            duration,
+            location: object.location,
+        });
+    }
+
+    /// Records the object whose evaluation went beyond the limits
+    fn skip(&mut self, object: ObjectRequest, reason: String, duration: Duration) {
+        self.skipped
+            .push((located_reference(&object), reason.clone()));
+        self.objects
+            .push((object.object, Outcome::Skipped(reason.clone())));
+        self.cases.push(TestCaseReport {
+            name: object.reference,
+            result: CaseResult::Error(format!("skipped: {reason}")),
+            duration,
+            location: object.location,
+        });
+    }
+
+    /// The violations with the same message are grouped, the most frequent
+    /// first. Only the first references of every group are listed, unless
+    /// `expand` is set.
+    fn render(
+        &self,
+        policy_definition: &PolicyDefinition,
+        severity: Severity,
+        expand: bool,
+    ) -> String {
+        let mut output = format!(
+            "{} [{}]: {} resources evaluated, {} rejected, {} mutated",
+            policy_definition,
+            severity,
+            self.evaluated,
+            self.rejected.len(),
+            self.mutated
+        );
+        if !self.skipped.is_empty() {
+            output.push_str(&format!(", {} skipped", self.skipped.len()));
+        }
+        output.push('\n');
+        output.push_str(&render_violations(&self.rejected, expand));
+        for (reference, reason) in &self.skipped {
+            output.push_str(&format!("  {reference}: skipped, {reason}\n"));
+        }
+        output
+    }
+
+    /// The report of the audit, named after the policy
+    fn report(self, policy_definition: &PolicyDefinition) -> TestSuiteReport {
+        TestSuiteReport {
+            name: policy_definition.to_string(),
+            cases: self.cases,
+        }
+    }
+}
+
+/// The references of the rejected resources, grouped by rejection message.
+/// The most frequent messages come first, the ones as frequent keep their
+/// order.
+fn violation_groups(rejected: &[(String, String)]) -> Vec<(&str, Vec<&str>)> {
+    let mut groups: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut indexes = HashMap::new();
+    for (reference, message) in rejected {
+        let index = *indexes.entry(message.as_str()).or_insert_with(|| {
+            groups.push((message.as_str(), Vec::new()));
+            groups.len() - 1
+        });
+        groups[index].1.push(reference.as_str());
+    }
+    groups.sort_by(|(_, a), (_, b)| b.len().cmp(&a.len()));
+    groups
+}
+
+/// The violations, the ones sharing the message being grouped together
+fn render_violations(rejected: &[(String, String)], expand: bool) -> String {
+    let mut output = String::new();
+    for (message, references) in violation_groups(rejected) {
+        if let [reference] = references.as_slice() {
+            output.push_str(&format!("  {reference}: {message}\n"));
+            continue;
+        }
+        output.push_str(&format!(
+            "  {} ({} resources):\n",
+            message,
+            references.len()
+        ));
+        let listed = if expand {
+            references.len()
+        } else {
+            GROUPED_REFERENCES
+        };
+        for reference in references.iter().take(listed) {
+            output.push_str(&format!("    {reference}\n"));
+        }
+        if references.len() > listed {
+            output.push_str(&format!("    ... and {} more\n", references.len() - listed));
+        }
+    }
+    output
+}
+
+/// Evaluates the resources selected by every policy, reporting the ones the
+/// policies would reject. The resources are the ones of the cluster, unless
+/// manifests are given.
+///
+/// The audit fails when the resources rejected by the policies of the
+/// threshold severity, or of a higher one, are more than the allowed ones.
+pub(crate) async fn exec(
+    policy_definitions: &[PolicyDefinition],
+    scopes: &[PolicyScope],
+    options: AuditOptions,
+    mut pull_settings: PullAndRunSettings,
+) -> Result<()> {
+    let AuditOptions {
+        manifests,
+        severities,
+        threshold,
+        output,
+        report,
+        metrics,
+        expand_violations,
+    } = options;
+    let start = Instant::now();
+    let local_data = LocalData::new(policy_definitions, &pull_settings).await?;
+    let auditing_manifests = manifests.is_some();
+    let mut resources = match manifests {
+        Some(manifests) => Resources::Manifests(manifests),
+        None => Resources::Cluster(Cluster::connect(pull_settings.kubeconfig.as_deref()).await?),
+    };
+
+    let mut rejecting = 0;
+    let mut violations = 0;
+    let mut reports = Vec::new();
+    let mut policy_reports = PolicyReports::default();
+    let mut summaries = Vec::new();
+    for (policy_definition, scope) in policy_definitions.iter().zip(scopes) {
+        let requests = resources.requests(scope).await?;
+        let severity =
+            severity::policy_severity(policy_definition, scope, &local_data, &severities)?;
+        let audit =
+            audit_policy(policy_definition, &mut pull_settings, &local_data, requests).await?;
+        if output == AuditOutput::Text {
+            print!(
+                "{}",
+                audit.render(policy_definition, severity, expand_violations)
+            );
+        }
+        if !audit.rejected.is_empty() {
+            rejecting += 1;
+        }
+        if threshold.is_some_and(|threshold| severity >= threshold.fail_on) {
+            violations += audit.rejected.len();
+        }
+        for (object, outcome) in &audit.objects {
+            policy_reports.record(scope, object, outcome);
+        }
+        reports.push(audit.report(policy_definition));
+        summaries.push(AuditSummary {
+            policy: policy_definition.to_string(),
+            evaluated: audit.evaluated,
+            rejected: audit.rejected.len(),
+            skipped: audit.skipped.len(),
+        });
+    }
+    if let Some(report) = &report {
+        test_report::write(&reports, report)?;
+    }
+    if let Some(metrics) = &metrics {
+        audit_metrics(&summaries, violations, start.elapsed()).write(metrics)?;
+    }
+
+    if output == AuditOutput::PolicyReport {
+        print!("{}", policy_reports.render()?);
+    } else if auditing_manifests {
+        println!(
+            "\n{} out of {} policies reject the manifests",
+            rejecting,
+            policy_definitions.len()
+        );
+    } else {
+        println!(
+            "\n{} out of {} policies would reject existing resources",
+            rejecting,
+            policy_definitions.len()
+        );
+    }
+
+    match threshold {
+        Some(threshold) if violations > threshold.max_violations => Err(anyhow!(
+            "{} violations of severity {} or higher, more than the {} allowed",
+            violations,
+            threshold.fail_on,
+            threshold.max_violations
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Outcome of the audit of a policy, for the metrics
+struct AuditSummary {
+    policy: String,
+    evaluated: usize,
+    rejected: usize,
+    skipped: usize,
+}
+
+fn audit_metrics(summaries: &[AuditSummary], violations: usize, duration: Duration) -> Metrics {
+    let mut metrics = Metrics::default();
+    let rejecting = summaries
+        .iter()
+        .filter(|summary| summary.rejected > 0)
+        .count();
+    for (result, policies) in [
+        ("rejecting", rejecting),
+        ("accepting", summaries.len() - rejecting),
+    ] {
+        metrics.counter(
+            "kwctl_audit_policies",
+            "Policies audited, by whether they reject some of the resources",
+            &[("result", result)],
+            policies as f64,
+        );
+    }
+    for summary in summaries {
+        for (result, resources) in [
+            ("accepted", summary.evaluated - summary.rejected),
+            ("rejected", summary.rejected),
+            ("skipped", summary.skipped),
+        ] {
+            metrics.counter(
+                "kwctl_audit_resources",
+                "Resources audited, by policy and result",
+                &[("policy", &summary.policy), ("result", result)],
+                resources as f64,
+            );
+        }
+    }
+    metrics.gauge(
+        "kwctl_audit_violations",
+        "Resources rejected by the policies of the --fail-on severity, or of a higher one",
+        &[],
+        violations as f64,
+    );
+    metrics.gauge(
+        "kwctl_audit_duration_seconds",
+        "Duration of the whole audit, pull of the policies included",
+        &[],
+        duration.as_secs_f64(),
+    );
+    metrics
+}
+
+/// Evaluates the requests with the policy. When an evaluation goes beyond
+/// the limits, the object is skipped and the policy is instantiated again
+/// for the remaining requests.
+async fn audit_policy(
+    policy_definition: &PolicyDefinition,
+    pull_settings: &mut PullAndRunSettings,
+    local_data: &LocalData,
+    requests: Vec<ObjectRequest>,
+) -> Result<PolicyAudit> {
+    let mut audit = PolicyAudit::default();
+    let mut requests = requests.into_iter().peekable();
+    // the evaluator is built for the first request, and then given the
+    // other ones
+    while let Some(first) = requests.peek() {
+        pull_settings.request = first.request.clone();
+        let (mut evaluator, callback_handler, shutdown_channel_tx) =
+            Evaluator::new(policy_definition, pull_settings, local_data).await?;
+
+        // the policies can use the host capabilities, like when run
+        let handler = tokio::spawn(async { callback_handler.loop_eval().await });
+
+        let limits = pull_settings.evaluation_limits.as_ref();
+        let evaluated = tokio::task::block_in_place(|| {
+            let settings_validation_response = evaluator.validate_settings();
+            if !settings_validation_response.valid {
+                return Err(anyhow!(
+                    "{}: provided settings are not valid: {}",
+                    policy_definition,
+                    settings_validation_response.message.unwrap_or_default()
+                ));
+            }
+            for object in requests.by_ref() {
+                let start = Instant::now();
+                evaluator.set_request(build_validate_request(&object.request, false)?);
+                let response = evaluator.evaluate();
+                let duration = start.elapsed();
+                if let Some(reason) = limits.and_then(|limits| limits.exceeded(&response)) {
+                    audit.skip(object, reason, duration);
+                    break;
+                }
+                audit.record(object, response, duration);
+            }
+            Ok(())
+        });
+
+        if shutdown_channel_tx.send(()).is_err() {
+            error!("Cannot shut down the CallbackHandler task");
+        } else if let Err(e) = handler.await {
+            error!(
+                error = e.to_string().as_str(),
+                "Error waiting for the CallbackHandler task"
+            );
+        }
+        evaluated?;
+    }
+    Ok(audit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn pods() -> ApiResource {
+        ApiResource {
+            group: String::new(),
+            version: "v1".to_string(),
+            api_version: "v1".to_string(),
+            kind: "Pod".to_string(),
+            plural: "pods".to_string(),
+        }
+    }
+
+    fn rule(operations: &[&str], resources: &[&str], scope: Option<&str>) -> RuleWithOperations {
+        let strings = |values: &[&str]| Some(values.iter().map(|v| v.to_string()).collect());
+        RuleWithOperations {
+            api_groups: strings(&[""]),
+            api_versions: strings(&["v1"]),
+            operations: strings(operations),
+            resources: strings(resources),
+            scope: scope.map(str::to_string),
+        }
+    }
+
+    fn object(namespace: &str, labels: &[(&str, &str)]) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": "nginx",
+                "namespace": namespace,
+                "uid": "1299d386-525b-4032-98ae-1949f69f9cfc",
+                "resourceVersion": "42",
+                "labels": labels.iter().cloned().collect::<BTreeMap<_, _>>(),
+            },
+            "spec": {"containers": [{"name": "nginx", "image": "nginx"}]},
+            "status": {"phase": "Running"},
+        }))
+        .unwrap()
+    }
+
+    fn labels(labels: &[(&str, &str)]) -> BTreeMap<String, String> {
+        labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case::create(rule(&["CREATE"], &["pods"], None), true)]
+    #[case::any_operation(rule(&["*"], &["*"], None), true)]
+    #[case::update_only(rule(&["UPDATE"], &["pods"], None), false)]
+    #[case::other_resource(rule(&["CREATE"], &["services"], None), false)]
+    #[case::cluster_scope(rule(&["CREATE"], &["pods"], Some("Cluster")), false)]
+    fn rules(#[case] rule: RuleWithOperations, #[case] expected: bool) {
+        assert_eq!(rule_matches(&rule, &pods(), true), expected);
+    }
+
+    #[rstest]
+    #[case::match_labels(r#"{"matchLabels": {"app": "web"}}"#, true)]
+    #[case::other_labels(r#"{"matchLabels": {"app": "db"}}"#, false)]
+    #[case::in_values(
+        r#"{"matchExpressions": [{"key": "tier", "operator": "In", "values": ["front", "back"]}]}"#,
+        true
+    )]
+    #[case::not_in_values(
+        r#"{"matchExpressions": [{"key": "tier", "operator": "NotIn", "values": ["front"]}]}"#,
+        false
+    )]
+    #[case::does_not_exist(
+        r#"{"matchExpressions": [{"key": "debug", "operator": "DoesNotExist"}]}"#,
+        true
+    )]
+    #[case::exists(
+        r#"{"matchExpressions": [{"key": "debug", "operator": "Exists"}]}"#,
+        false
+    )]
+    fn selectors(#[case] selector: &str, #[case] expected: bool) {
+        let selector: LabelSelector = serde_json::from_str(selector).unwrap();
+        assert_eq!(
+            selector_matches(
+                Some(&selector),
+                &labels(&[("app", "web"), ("tier", "front")])
+            ),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::cluster_policy("ClusterAdmissionPolicy", "team-a", true)]
+    #[case::namespaced_policy("AdmissionPolicy", "team-a", true)]
+    #[case::other_namespace("AdmissionPolicy", "team-b", false)]
+    fn namespaced_policies(#[case] kind: &str, #[case] namespace: &str, #[case] expected: bool) {
+        let scope: PolicyScope = serde_yaml::from_str(&format!(
+            r#"
+kind: {kind}
+metadata:
+  name: no-privileged-pods
+  namespace: team-a
+spec:
+  namespaceSelector:
+    matchLabels:
+      env: prod
+"#
+        ))
+        .unwrap();
+        let prod = labels(&[("env", "prod")]);
+        assert_eq!(
+            scope.selects(&object(namespace, &[]), Some(&prod)),
+            expected
+        );
+        assert!(!scope.selects(&object(namespace, &[]), Some(&BTreeMap::new())));
+    }
+
+    #[test]
+    fn create_requests_have_no_server_fields() {
+        let request = create_request(&object("default", &[("app", "web")]), &pods()).unwrap();
+        assert_eq!(request["operation"], "CREATE");
+        assert_eq!(request["resource"]["resource"], "pods");
+        assert_eq!(request["namespace"], "default");
+        assert_eq!(request["object"]["metadata"]["labels"]["app"], "web");
+        assert!(request["object"].get("status").is_none());
+        assert!(request["object"]["metadata"]
+            .get("resourceVersion")
+            .is_none());
+    }
+
+    #[rstest]
+    #[case::collapsed(
+        false,
+        "  image is not signed (7 resources):\n    Pod default/pod-0\n    Pod default/pod-1\n    Pod default/pod-2\n    Pod default/pod-3\n    Pod default/pod-4\n    ... and 2 more\n"
+    )]
+    #[case::expanded(
+        true,
+        "  image is not signed (7 resources):\n    Pod default/pod-0\n    Pod default/pod-1\n    Pod default/pod-2\n    Pod default/pod-3\n    Pod default/pod-4\n    Pod default/pod-5\n    Pod default/pod-6\n"
+    )]
+    fn grouped_violations(#[case] expand: bool, #[case] expected_group: &str) {
+        let mut rejected = vec![(
+            "Pod default/debug".to_string(),
+            "privileged containers are not allowed".to_string(),
+        )];
+        rejected.extend((0..7).map(|index| {
+            (
+                format!("Pod default/pod-{index}"),
+                "image is not signed".to_string(),
+            )
+        }));
+
+        assert_eq!(
+            render_violations(&rejected, expand),
+            format!("{expected_group}  Pod default/debug: privileged containers are not allowed\n")
+        );
+    }
+
+    #[test]
+    fn metrics_of_the_audit() {
+        let summaries = [
+            AuditSummary {
+                policy: "no-privileged-pods".to_string(),
+                evaluated: 5,
+                rejected: 2,
+                skipped: 1,
+            },
+            AuditSummary {
+                policy: "safe-labels".to_string(),
+                evaluated: 5,
+                rejected: 0,
+                skipped: 0,
+            },
+        ];
+
+        let metrics = audit_metrics(&summaries, 2, Duration::from_secs(4)).render();
+        for sample in [
+            "kwctl_audit_policies_total{result=\"rejecting\"} 1\n",
+            "kwctl_audit_policies_total{result=\"accepting\"} 1\n",
+            "kwctl_audit_resources_total{policy=\"no-privileged-pods\",result=\"accepted\"} 3\n",
+            "kwctl_audit_resources_total{policy=\"no-privileged-pods\",result=\"rejected\"} 2\n",
+            "kwctl_audit_resources_total{policy=\"no-privileged-pods\",result=\"skipped\"} 1\n",
+            "kwctl_audit_violations 2\n",
+            "kwctl_audit_duration_seconds 4\n",
+        ] {
+            assert!(metrics.contains(sample), "{sample} missing from {metrics}");
+        }
+    }
+}
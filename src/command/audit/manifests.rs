@@ -0,0 +1,567 @@
+//! Objects defined by manifests, audited without a cluster.
+//!
+//! The resources of the objects are guessed from their kinds, like
+//! `kubectl` does when it cannot reach the API server. The custom resources
+//! are resolved through their CustomResourceDefinitions instead, the ones
+//! defined by the manifests or served by a cluster. Every object keeps
+//! the file and the line of its manifest. The manifests rendered by
+//! `helm template` are located by the `# Source:` comments of Helm instead.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use policy_evaluator::kube::{
+    self,
+    api::{ApiResource, DynamicObject, ListParams},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::info;
+
+use super::{
+    create_request, object_reference, reference, rule_matches, ObjectRequest, PolicyScope,
+};
+use crate::{command::run::evaluator::build_kube_client, test_report::Location};
+
+/// Kinds of the built-in resources that are not namespaced. The objects of
+/// the other kinds are namespaced, the ones without a namespace are created
+/// inside of the `default` namespace, like `kubectl apply` does.
+const CLUSTER_KINDS: &[&str] = &[
+    "APIService",
+    "CSIDriver",
+    "CSINode",
+    "CertificateSigningRequest",
+    "ClusterAdmissionPolicy",
+    "ClusterAdmissionPolicyGroup",
+    "ClusterRole",
+    "ClusterRoleBinding",
+    "CustomResourceDefinition",
+    "IngressClass",
+    "MutatingWebhookConfiguration",
+    "Namespace",
+    "Node",
+    "PersistentVolume",
+    "PolicyServer",
+    "PriorityClass",
+    "RuntimeClass",
+    "StorageClass",
+    "ValidatingAdmissionPolicy",
+    "ValidatingAdmissionPolicyBinding",
+    "ValidatingWebhookConfiguration",
+    "VolumeAttachment",
+];
+
+/// The plurals of the custom resources, and whether they are namespaced, by
+/// group and kind, as defined by their CustomResourceDefinitions
+#[derive(Default)]
+pub(crate) struct CustomResources(HashMap<(String, String), (String, bool)>);
+
+impl CustomResources {
+    /// The custom resources served by the cluster of the kubeconfig file
+    pub(crate) async fn from_cluster(kubeconfig: &Path) -> Result<Self> {
+        let client = build_kube_client(Some(kubeconfig)).await?;
+        let mut custom_resources = CustomResources::default();
+        for crd in kube::Api::<CustomResourceDefinition>::all(client)
+            .list(&ListParams::default())
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "cannot list the CustomResourceDefinitions of the cluster: {}",
+                    e
+                )
+            })?
+        {
+            custom_resources.add(&crd);
+        }
+        Ok(custom_resources)
+    }
+
+    fn add(&mut self, crd: &CustomResourceDefinition) {
+        self.0.insert(
+            (crd.spec.group.clone(), crd.spec.names.kind.clone()),
+            (
+                crd.spec.names.plural.clone(),
+                crd.spec.scope == "Namespaced",
+            ),
+        );
+    }
+
+    /// The resource of the objects of the type, and whether it is namespaced.
+    /// The resources that are not custom ones are guessed from their kinds.
+    fn resolve(&self, types: &kube::core::TypeMeta) -> (ApiResource, bool) {
+        let mut resource = api_resource(types);
+        match self.0.get(&(resource.group.clone(), resource.kind.clone())) {
+            Some((plural, namespaced)) => {
+                resource.plural = plural.clone();
+                (resource, *namespaced)
+            }
+            None => {
+                let namespaced = !CLUSTER_KINDS.contains(&resource.kind.as_str());
+                (resource, namespaced)
+            }
+        }
+    }
+}
+
+/// An object defined by the manifests
+struct ManifestObject {
+    resource: ApiResource,
+    namespaced: bool,
+    object: DynamicObject,
+    location: Option<Location>,
+}
+
+/// The objects defined by the manifests
+pub(crate) struct Manifests {
+    objects: Vec<ManifestObject>,
+    /// The labels of the namespaces defined by the manifests, the other
+    /// namespaces have no labels
+    namespace_labels: HashMap<String, BTreeMap<String, String>>,
+}
+
+impl Manifests {
+    /// Reads the objects defined by the YAML and JSON files of the directory
+    /// tree, or by the file. `-` reads them from the standard input, like
+    /// the output of `helm template`.
+    ///
+    /// The custom resources are resolved through the CustomResourceDefinitions
+    /// of the manifests, and through the given ones.
+    pub(crate) fn read(path: &str, custom_resources: CustomResources) -> Result<Self> {
+        let mut documents = Vec::new();
+        if path == "-" {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| anyhow!("cannot read the manifests from the standard input: {}", e))?;
+            documents.extend(parse_documents(&input, None)?);
+        } else {
+            for file in manifest_files(Path::new(path))? {
+                let contents = fs::read_to_string(&file)
+                    .map_err(|e| anyhow!("cannot read {}: {}", file.display(), e))?;
+                documents.extend(parse_documents(
+                    &contents,
+                    Some(&file.display().to_string()),
+                )?);
+            }
+        }
+        Self::from_documents(documents, custom_resources)
+    }
+
+    fn from_documents(
+        documents: Vec<(Option<Location>, Value)>,
+        mut custom_resources: CustomResources,
+    ) -> Result<Self> {
+        for (location, document) in &documents {
+            if document["apiVersion"] == "apiextensions.k8s.io/v1"
+                && document["kind"] == "CustomResourceDefinition"
+            {
+                let crd: CustomResourceDefinition = serde_json::from_value(document.clone())
+                    .map_err(|e| {
+                        anyhow!(
+                            "cannot parse the CustomResourceDefinition{}: {}",
+                            describe(location.as_ref()),
+                            e
+                        )
+                    })?;
+                custom_resources.add(&crd);
+            }
+        }
+
+        let mut objects = Vec::new();
+        let mut namespace_labels = HashMap::new();
+        for (location, document) in documents {
+            let mut object: DynamicObject = serde_json::from_value(document).map_err(|e| {
+                anyhow!(
+                    "cannot parse the manifest of an object{}: {}",
+                    describe(location.as_ref()),
+                    e
+                )
+            })?;
+            let Some((resource, namespaced)) = object
+                .types
+                .as_ref()
+                .map(|types| custom_resources.resolve(types))
+            else {
+                continue;
+            };
+            if namespaced && object.metadata.namespace.is_none() {
+                object.metadata.namespace = Some("default".to_string());
+            }
+            if resource.group.is_empty() && resource.kind == "Namespace" {
+                if let Some(name) = &object.metadata.name {
+                    namespace_labels.insert(
+                        name.clone(),
+                        object.metadata.labels.clone().unwrap_or_default(),
+                    );
+                }
+            }
+            objects.push(ManifestObject {
+                resource,
+                namespaced,
+                object,
+                location,
+            });
+        }
+        Ok(Manifests {
+            objects,
+            namespace_labels,
+        })
+    }
+
+    /// The CREATE requests of the objects selected by the policy, together
+    /// with the references of the objects
+    pub(super) fn requests(&self, scope: &PolicyScope) -> Result<Vec<ObjectRequest>> {
+        let mut requests = Vec::new();
+        for manifest in &self.objects {
+            let (resource, object) = (&manifest.resource, &manifest.object);
+            if scope
+                .spec
+                .rules
+                .iter()
+                .any(|rule| rule_matches(rule, resource, manifest.namespaced))
+                && scope.selects_in(
+                    object,
+                    resource,
+                    manifest.namespaced,
+                    &self.namespace_labels,
+                )
+            {
+                requests.push(ObjectRequest {
+                    reference: reference(object, resource),
+                    object: object_reference(object, resource),
+                    location: manifest.location.clone(),
+                    request: create_request(object, resource)?,
+                });
+            }
+        }
+        Ok(requests)
+    }
+}
+
+/// The resource of the objects of the type, the plural is guessed from the
+/// kind
+fn api_resource(types: &kube::core::TypeMeta) -> ApiResource {
+    let (group, version) = types
+        .api_version
+        .split_once('/')
+        .unwrap_or(("", types.api_version.as_str()));
+    ApiResource::from_gvk(&kube::core::GroupVersionKind::gvk(
+        group,
+        version,
+        &types.kind,
+    ))
+}
+
+/// The YAML and JSON files of the directory tree, sorted. Hidden files and
+/// directories, like `.github`, are skipped.
+fn manifest_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| anyhow!("cannot read directory {}: {}", path.display(), e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if entry.is_dir() {
+            files.extend(manifest_files(&entry)?);
+        } else if entry
+            .extension()
+            .is_some_and(|extension| ["yaml", "yml", "json"].iter().any(|e| extension == *e))
+        {
+            files.push(entry);
+        }
+    }
+    Ok(files)
+}
+
+/// ` (deployment.yaml:3)`, to point at the manifest inside of messages
+fn describe(location: Option<&Location>) -> String {
+    match location {
+        Some(Location {
+            path,
+            line: Some(line),
+        }) => format!(" ({path}:{line})"),
+        Some(Location { path, line: None }) => format!(" ({path})"),
+        None => String::new(),
+    }
+}
+
+/// Whether the line separates two documents of a YAML stream
+fn is_document_separator(line: &str) -> bool {
+    line.strip_prefix("---").is_some_and(|rest| {
+        let rest = rest.trim_start();
+        rest.is_empty() || rest.starts_with('#')
+    })
+}
+
+/// The objects defined by the documents of the YAML stream, `List` objects
+/// are expanded into their items. The documents that are not Kubernetes
+/// objects, like the values of Helm charts, are skipped.
+///
+/// The documents are located inside of the file, when given. Otherwise they
+/// are located by the `# Source:` comments written by `helm template`.
+fn parse_documents(contents: &str, file: Option<&str>) -> Result<Vec<(Option<Location>, Value)>> {
+    // the documents, with the lines they start from
+    let mut documents: Vec<(usize, Vec<&str>)> = vec![(1, Vec::new())];
+    for (index, line) in contents.lines().enumerate() {
+        if is_document_separator(line) {
+            documents.push((index + 2, Vec::new()));
+        } else if let Some((_, lines)) = documents.last_mut() {
+            lines.push(line);
+        }
+    }
+
+    let mut objects = Vec::new();
+    for (first_line, lines) in documents {
+        // the object starts after the blank lines and the comments
+        let Some(offset) = lines.iter().position(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with('#')
+        }) else {
+            continue;
+        };
+        let location = match file {
+            Some(path) => Some(Location {
+                path: path.to_string(),
+                line: Some(first_line + offset),
+            }),
+            None => lines.iter().find_map(|line| {
+                line.trim()
+                    .strip_prefix("# Source:")
+                    .map(|source| Location {
+                        path: source.trim().to_string(),
+                        line: None,
+                    })
+            }),
+        };
+        let document: Value = serde_yaml::from_str(&lines.join("\n")).map_err(|e| {
+            anyhow!(
+                "cannot parse the manifest{}: {}",
+                describe(location.as_ref()),
+                e
+            )
+        })?;
+        let is_object = document.get("apiVersion").is_some_and(Value::is_string)
+            && document.get("kind").is_some_and(Value::is_string);
+        if !is_object {
+            info!(
+                "skipping a document that is not a Kubernetes object{}",
+                describe(location.as_ref())
+            );
+            continue;
+        }
+        match document.get("items") {
+            Some(Value::Array(items)) if document["kind"] == "List" => {
+                objects.extend(items.iter().map(|item| (location.clone(), item.clone())))
+            }
+            _ => objects.push((location, document)),
+        }
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFESTS: &str = r#"
+# Source: app/templates/namespace.yaml
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: team-a
+  labels:
+    env: prod
+---
+# Source: app/templates/deployment.yaml
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: web
+  namespace: team-a
+spec:
+  template:
+    spec:
+      containers:
+        - name: web
+          image: nginx
+---
+---
+replicaCount: 1
+---
+apiVersion: v1
+kind: List
+items:
+  - apiVersion: v1
+    kind: ConfigMap
+    metadata:
+      name: settings
+"#;
+
+    fn manifests() -> Manifests {
+        Manifests::from_documents(
+            parse_documents(MANIFESTS, Some("manifests.yaml")).unwrap(),
+            CustomResources::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn objects_of_the_manifests() {
+        let manifests = manifests();
+        let objects: Vec<(&str, &str, bool, Option<&str>, Option<usize>)> = manifests
+            .objects
+            .iter()
+            .map(|manifest| {
+                (
+                    manifest.resource.api_version.as_str(),
+                    manifest.resource.plural.as_str(),
+                    manifest.namespaced,
+                    manifest.object.metadata.namespace.as_deref(),
+                    manifest
+                        .location
+                        .as_ref()
+                        .and_then(|location| location.line),
+                )
+            })
+            .collect();
+        assert_eq!(
+            objects,
+            vec![
+                ("v1", "namespaces", false, None, Some(3)),
+                ("apps/v1", "deployments", true, Some("team-a"), Some(11)),
+                ("v1", "configmaps", true, Some("default"), Some(26)),
+            ]
+        );
+        assert_eq!(manifests.namespace_labels["team-a"]["env"], "prod");
+    }
+
+    #[test]
+    fn custom_resources() {
+        let documents = parse_documents(
+            r#"
+apiVersion: apiextensions.k8s.io/v1
+kind: CustomResourceDefinition
+metadata:
+  name: providers.pkg.crossplane.io
+spec:
+  group: pkg.crossplane.io
+  names:
+    kind: Provider
+    plural: providers
+  scope: Cluster
+  versions:
+    - name: v1
+      served: true
+      storage: true
+---
+apiVersion: pkg.crossplane.io/v1
+kind: Provider
+metadata:
+  name: provider-aws
+---
+apiVersion: cert-manager.io/v1
+kind: ClusterIssuer
+metadata:
+  name: letsencrypt
+---
+apiVersion: example.com/v1
+kind: Widget
+metadata:
+  name: widget
+"#,
+            None,
+        )
+        .unwrap();
+        // served by the cluster
+        let mut custom_resources = CustomResources::default();
+        custom_resources.0.insert(
+            ("cert-manager.io".to_string(), "ClusterIssuer".to_string()),
+            ("clusterissuers".to_string(), false),
+        );
+
+        let manifests = Manifests::from_documents(documents, custom_resources).unwrap();
+        let objects: Vec<(&str, bool, Option<&str>)> = manifests
+            .objects
+            .iter()
+            .skip(1)
+            .map(|manifest| {
+                (
+                    manifest.resource.plural.as_str(),
+                    manifest.namespaced,
+                    manifest.object.metadata.namespace.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            objects,
+            vec![
+                ("providers", false, None),
+                ("clusterissuers", false, None),
+                // unknown, guessed from its kind
+                ("widgets", true, Some("default")),
+            ]
+        );
+    }
+
+    #[test]
+    fn requests_of_the_selected_objects() {
+        let scope: PolicyScope = serde_yaml::from_str(
+            r#"
+kind: ClusterAdmissionPolicy
+spec:
+  rules:
+    - apiGroups: ["apps"]
+      apiVersions: ["v1"]
+      resources: ["deployments"]
+      operations: ["CREATE", "UPDATE"]
+  namespaceSelector:
+    matchLabels:
+      env: prod
+"#,
+        )
+        .unwrap();
+        let requests = manifests().requests(&scope).unwrap();
+        let references: Vec<&str> = requests
+            .iter()
+            .map(|request| request.reference.as_str())
+            .collect();
+        assert_eq!(references, vec!["Deployment team-a/web"]);
+        assert_eq!(
+            requests[0].request["object"]["spec"]["template"]["spec"]["containers"][0]["image"],
+            "nginx"
+        );
+    }
+
+    #[test]
+    fn helm_sources() {
+        let documents = parse_documents(MANIFESTS, None).unwrap();
+        let locations: Vec<Option<&str>> = documents
+            .iter()
+            .map(|(location, _)| location.as_ref().map(|location| location.path.as_str()))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                Some("app/templates/namespace.yaml"),
+                Some("app/templates/deployment.yaml"),
+                None,
+            ]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! PolicyReport and ClusterPolicyReport resources of the Policy Working Group,
+//! written by `kwctl audit --output policy-report`.
+//!
+//! The reports have the same shape of the ones written by the audit scanner
+//! of Kubewarden: a report for every audited resource, with a result for
+//! every policy evaluating it. Policy Reporter shows them together with the
+//! ones coming from the clusters.
+
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use k8s_openapi::api::core::v1::ObjectReference;
+use serde_json::{json, Value};
+
+use super::{severity::SEVERITY_ANNOTATION, Outcome, PolicyScope};
+
+const API_VERSION: &str = "wgpolicyk8s.io/v1alpha2";
+const CATEGORY_ANNOTATION: &str = "io.kubewarden.policy.category";
+
+/// The report of a resource
+struct Report {
+    object: ObjectReference,
+    results: Vec<Value>,
+}
+
+/// The reports of the audited resources, by namespace and name
+#[derive(Default)]
+pub(super) struct PolicyReports {
+    reports: BTreeMap<(Option<String>, String), Report>,
+}
+
+impl PolicyReports {
+    /// Records the outcome of the evaluation of the resource by the policy.
+    /// The resources skipped by the audit are reported as errors.
+    pub(super) fn record(
+        &mut self,
+        scope: &PolicyScope,
+        object: &ObjectReference,
+        outcome: &Outcome,
+    ) {
+        let annotations = scope.metadata.annotations.clone().unwrap_or_default();
+        let mut result = json!({
+            "source": "kubewarden",
+            "policy": scope.unique_name(),
+            "result": match outcome {
+                Outcome::Accepted => "pass",
+                Outcome::Rejected(_) => "fail",
+                Outcome::Skipped(_) => "error",
+            },
+            "scored": true,
+            "timestamp": timestamp(),
+            "properties": {
+                "policy-name": scope.metadata.name.clone().unwrap_or_default(),
+                "mutating": scope.spec.mutating.to_string(),
+                "validating": "true",
+            },
+        });
+        if let Some(namespace) = scope.namespace() {
+            result["properties"]["policy-namespace"] = json!(namespace);
+        }
+        if let Outcome::Rejected(message) | Outcome::Skipped(message) = outcome {
+            result["message"] = json!(message);
+        }
+        if let Some(severity) = annotations.get(SEVERITY_ANNOTATION) {
+            result["severity"] = json!(severity);
+        }
+        if let Some(category) = annotations.get(CATEGORY_ANNOTATION) {
+            result["category"] = json!(category);
+        }
+
+        self.reports
+            .entry((object.namespace.clone(), report_name(object)))
+            .or_insert_with(|| Report {
+                object: object.clone(),
+                results: Vec::new(),
+            })
+            .results
+            .push(result);
+    }
+
+    /// The reports, as a stream of YAML documents
+    pub(super) fn render(&self) -> Result<String> {
+        let mut output = String::new();
+        for ((namespace, name), report) in &self.reports {
+            let count = |outcome: &str| {
+                report
+                    .results
+                    .iter()
+                    .filter(|result| result["result"] == outcome)
+                    .count()
+            };
+            let mut metadata = json!({
+                "name": name,
+                "labels": {"app.kubernetes.io/managed-by": "kubewarden"},
+            });
+            if let Some(namespace) = namespace {
+                metadata["namespace"] = json!(namespace);
+            }
+            let document = json!({
+                "apiVersion": API_VERSION,
+                "kind": if namespace.is_some() { "PolicyReport" } else { "ClusterPolicyReport" },
+                "metadata": metadata,
+                "scope": report.object,
+                "summary": {
+                    "pass": count("pass"),
+                    "fail": count("fail"),
+                    "warn": 0,
+                    "error": count("error"),
+                    "skip": 0,
+                },
+                "results": report.results,
+            });
+            output.push_str("---\n");
+            output.push_str(&serde_yaml::to_string(&document)?);
+        }
+        Ok(output)
+    }
+}
+
+/// Reports are named after the UIDs of the resources, like the audit
+/// scanner does. The resources defined by manifests have no UID yet.
+fn report_name(object: &ObjectReference) -> String {
+    object.uid.clone().unwrap_or_else(|| {
+        format!(
+            "{}-{}",
+            object.kind.as_deref().unwrap_or_default(),
+            object.name.as_deref().unwrap_or_default()
+        )
+        .to_lowercase()
+    })
+}
+
+fn timestamp() -> Value {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    json!({"seconds": elapsed.as_secs(), "nanos": elapsed.subsec_nanos()})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(kind: &str) -> PolicyScope {
+        serde_yaml::from_str(&format!(
+            r#"
+kind: {kind}
+metadata:
+  name: no-privileged-pods
+  namespace: team-a
+  annotations:
+    io.kubewarden.policy.severity: high
+spec:
+  mutating: false
+"#
+        ))
+        .unwrap()
+    }
+
+    fn pod(uid: Option<&str>) -> ObjectReference {
+        ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Pod".to_string()),
+            name: Some("nginx".to_string()),
+            namespace: Some("team-a".to_string()),
+            uid: uid.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reports_of_the_resources() {
+        let mut reports = PolicyReports::default();
+        reports.record(
+            &scope("ClusterAdmissionPolicy"),
+            &pod(Some("1234")),
+            &Outcome::Accepted,
+        );
+        reports.record(
+            &scope("AdmissionPolicy"),
+            &pod(Some("1234")),
+            &Outcome::Rejected("privileged containers are not allowed".to_string()),
+        );
+        reports.record(
+            &scope("ClusterAdmissionPolicy"),
+            &pod(None),
+            &Outcome::Skipped("the evaluation took longer than 2 seconds".to_string()),
+        );
+
+        let documents: Vec<Value> = serde_yaml::Deserializer::from_str(&reports.render().unwrap())
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect();
+        assert_eq!(documents.len(), 2);
+
+        let report = &documents[0];
+        assert_eq!(report["kind"], "PolicyReport");
+        assert_eq!(report["metadata"]["name"], "1234");
+        assert_eq!(report["scope"]["name"], "nginx");
+        assert_eq!(report["summary"]["pass"], 1);
+        assert_eq!(report["summary"]["fail"], 1);
+        assert_eq!(
+            report["results"][0]["policy"],
+            "clusterwide-no-privileged-pods"
+        );
+        assert_eq!(report["results"][0]["severity"], "high");
+        assert_eq!(
+            report["results"][1]["policy"],
+            "namespaced-team-a-no-privileged-pods"
+        );
+        assert_eq!(
+            report["results"][1]["message"],
+            "privileged containers are not allowed"
+        );
+
+        assert_eq!(documents[1]["metadata"]["name"], "pod-nginx");
+        assert_eq!(documents[1]["summary"]["error"], 1);
+        assert_eq!(documents[1]["results"][0]["result"], "error");
+    }
+}
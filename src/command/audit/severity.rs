@@ -0,0 +1,136 @@
+//! Severities of the audited policies, deciding which rejections make
+//! `kwctl audit` fail.
+//!
+//! The severity of a policy is taken from, in order: the file given via
+//! `--severities`, the `io.kubewarden.policy.severity` annotation of its
+//! Custom Resource and the same annotation of the metadata of its module.
+//! Policies without a severity are errors.
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{anyhow, Result};
+
+use super::PolicyScope;
+use crate::{command::run::local_data::LocalData, config::policy_definition::PolicyDefinition};
+
+pub(super) const SEVERITY_ANNOTATION: &str = "io.kubewarden.policy.severity";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Severity {
+    Warning,
+    Error,
+}
+
+impl TryFrom<&str> for Severity {
+    type Error = anyhow::Error;
+
+    /// Besides `warning` and `error`, the severities of the Kubewarden
+    /// policies are understood: `high` and `critical` are errors, the other
+    /// ones are warnings
+    fn try_from(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            // `Self::Error` would be the error type
+            "warning" | "info" | "low" | "medium" => Ok(Severity::Warning),
+            "error" | "high" | "critical" => Ok(Severity::Error),
+            _ => Err(anyhow!("unknown severity: {}", value)),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The rejections making the audit fail: the ones by the policies of the
+/// given severity, or of a higher one, beyond the allowed number
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FailureThreshold {
+    pub(crate) fail_on: Severity,
+    pub(crate) max_violations: usize,
+}
+
+/// Reads the severities of the policies, by name, from a YAML mapping like
+/// `no-privileged-pods: error`
+pub(crate) fn from_yaml_file(path: &str) -> Result<BTreeMap<String, Severity>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot open YAML file {:?}: {}", path, e))?;
+    let severities: BTreeMap<String, String> = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("Cannot parse YAML file {:?}: {}", path, e))?;
+    severities
+        .into_iter()
+        .map(|(policy, severity)| {
+            Severity::try_from(severity.as_str())
+                .map(|severity| (policy, severity))
+                .map_err(|e| anyhow!("{}: {}", path, e))
+        })
+        .collect()
+}
+
+/// The severity of the policy. Policy groups take the highest severity of
+/// their members.
+pub(super) fn policy_severity(
+    policy_definition: &PolicyDefinition,
+    scope: &PolicyScope,
+    local_data: &LocalData,
+    severities: &BTreeMap<String, Severity>,
+) -> Result<Severity> {
+    if let Some(severity) = scope
+        .metadata
+        .name
+        .as_ref()
+        .and_then(|name| severities.get(name))
+    {
+        return Ok(*severity);
+    }
+    if let Some(severity) = scope
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(SEVERITY_ANNOTATION))
+    {
+        return Severity::try_from(severity.as_str())
+            .map_err(|e| anyhow!("{}: {}", policy_definition, e));
+    }
+
+    let mut module_severities = Vec::new();
+    for uri in policy_definition.uris() {
+        if let Some(severity) = local_data
+            .metadata(&uri)
+            .and_then(|metadata| metadata.annotations.as_ref())
+            .and_then(|annotations| annotations.get(SEVERITY_ANNOTATION))
+        {
+            module_severities.push(
+                Severity::try_from(severity.as_str()).map_err(|e| anyhow!("{}: {}", uri, e))?,
+            );
+        }
+    }
+    Ok(module_severities
+        .into_iter()
+        .max()
+        .unwrap_or(Severity::Error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("warning", Severity::Warning)]
+    #[case("Medium", Severity::Warning)]
+    #[case("error", Severity::Error)]
+    #[case("critical", Severity::Error)]
+    fn severities(#[case] value: &str, #[case] expected: Severity) {
+        assert_eq!(Severity::try_from(value).unwrap(), expected);
+    }
+
+    #[test]
+    fn unknown_severity() {
+        assert!(Severity::try_from("urgent").is_err());
+    }
+}
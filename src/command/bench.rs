@@ -1,3 +1,8 @@
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
 use anyhow::{anyhow, Result};
 use tiny_bench::{bench_with_configuration_labeled, BenchmarkConfig};
 use tracing::{debug, error};
@@ -5,27 +10,127 @@ use tracing::{debug, error};
 use crate::{
     command::run::{evaluator::Evaluator, local_data::LocalData},
     config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    metrics::Metrics,
 };
 
+/// Time spent by the iterations of a benchmarked operation, warm up included
+pub(crate) struct Measurement {
+    operation: &'static str,
+    iterations: u64,
+    elapsed: Duration,
+}
+
+impl Measurement {
+    fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            iterations: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    fn measure<T>(&mut self, operation: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = operation();
+        self.elapsed += start.elapsed();
+        self.iterations += 1;
+        result
+    }
+
+    fn mean(&self) -> Duration {
+        if self.iterations == 0 {
+            Duration::ZERO
+        } else {
+            self.elapsed.div_f64(self.iterations as f64)
+        }
+    }
+}
+
 pub(crate) async fn exec(
     policy_definitions: &[PolicyDefinition],
     pull_and_run_settings: &PullAndRunSettings,
     benchmark_config: &BenchmarkConfig,
+    metrics_path: Option<&Path>,
 ) -> Result<()> {
+    let start = Instant::now();
     let local_data = LocalData::new(policy_definitions, pull_and_run_settings).await?;
 
+    let mut measurements = Vec::new();
+    let mut failure = None;
     for policy_definition in policy_definitions {
-        pull_and_bench(
+        match pull_and_bench(
             policy_definition,
             pull_and_run_settings,
             &local_data,
             benchmark_config,
         )
         .await
-        .map_err(|e| anyhow!("[{}] - {}", policy_definition, e))?;
+        {
+            Ok(policy_measurements) => {
+                measurements.push((policy_definition.to_string(), policy_measurements))
+            }
+            Err(e) => {
+                failure = Some(anyhow!("[{}] - {}", policy_definition, e));
+                break;
+            }
+        }
     }
 
-    Ok(())
+    if let Some(metrics_path) = metrics_path {
+        bench_metrics(&measurements, failure.is_some(), start.elapsed()).write(metrics_path)?;
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn bench_metrics(
+    measurements: &[(String, Vec<Measurement>)],
+    failed: bool,
+    duration: Duration,
+) -> Metrics {
+    let mut metrics = Metrics::default();
+    metrics.counter(
+        "kwctl_bench_policies",
+        "Policies benchmarked, by result",
+        &[("result", "passed")],
+        measurements.len() as f64,
+    );
+    metrics.counter(
+        "kwctl_bench_policies",
+        "Policies benchmarked, by result",
+        &[("result", "failed")],
+        if failed { 1.0 } else { 0.0 },
+    );
+    metrics.gauge(
+        "kwctl_bench_duration_seconds",
+        "Duration of the whole benchmark, pull of the policies included",
+        &[],
+        duration.as_secs_f64(),
+    );
+    for (policy, policy_measurements) in measurements {
+        for measurement in policy_measurements {
+            let labels = [
+                ("policy", policy.as_str()),
+                ("operation", measurement.operation),
+            ];
+            metrics.counter(
+                "kwctl_bench_iterations",
+                "Iterations of the benchmarked operation, warm up included",
+                &labels,
+                measurement.iterations as f64,
+            );
+            metrics.gauge(
+                "kwctl_bench_operation_mean_duration_seconds",
+                "Mean duration of an iteration of the benchmarked operation",
+                &labels,
+                measurement.mean().as_secs_f64(),
+            );
+        }
+    }
+    metrics
 }
 
 pub(crate) async fn pull_and_bench(
@@ -33,7 +138,7 @@ pub(crate) async fn pull_and_bench(
     pull_and_run_settings: &PullAndRunSettings,
     local_data: &LocalData,
     benchmark_config: &BenchmarkConfig,
-) -> Result<()> {
+) -> Result<Vec<Measurement>> {
     let (mut evaluator, callback_handler, shutdown_channel_tx) =
         Evaluator::new(policy_definition, pull_and_run_settings, local_data).await?;
 
@@ -58,18 +163,21 @@ pub(crate) async fn pull_and_bench(
     // We have to wrap the settings validation in a `tokio::task::block_in_place` context
     // because if the policy uses context aware functions, this would lead to blocking the
     // tokio runtime. Remember, we're running inside of an async context.
+    let mut settings_validation = Measurement::new("validate_settings");
     tokio::task::block_in_place(|| {
         bench_with_configuration_labeled("validate_settings", benchmark_config, || {
-            let _settings_validation_response = evaluator.validate_settings();
+            let _settings_validation_response =
+                settings_validation.measure(|| evaluator.validate_settings());
         });
     });
 
     // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
     // because if the policy uses context aware functions, this would lead to blocking the
     // tokio runtime. Remember, we're running inside of an async context.
+    let mut evaluation = Measurement::new("validate");
     tokio::task::block_in_place(|| {
         bench_with_configuration_labeled("validate", benchmark_config, || {
-            let _evaluation_result = evaluator.evaluate();
+            let _evaluation_result = evaluation.measure(|| evaluator.evaluate());
         });
     });
 
@@ -82,5 +190,5 @@ pub(crate) async fn pull_and_bench(
         );
     }
 
-    Ok(())
+    Ok(vec![settings_validation, evaluation])
 }
@@ -1,19 +1,35 @@
+use std::time::Instant;
+
 use anyhow::{anyhow, Result};
-use policy_evaluator::admission_response_handler::AdmissionResponseHandler;
+use policy_evaluator::{
+    admission_response::AdmissionResponse, admission_response_handler::AdmissionResponseHandler,
+};
 use tracing::{error, warn};
 
 use crate::{
-    command::run::{evaluator::Evaluator, local_data::LocalData},
+    command::run::{
+        evaluator::Evaluator, gatekeeper, local_data::LocalData,
+        mutation_schema::validate_mutated_object,
+    },
     config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+    test_report::{self, CaseResult, ReportOutput, TestCaseReport, TestSuiteReport},
 };
 
 pub(crate) mod evaluator;
+pub(crate) mod explain;
+pub(crate) mod gatekeeper;
+pub(crate) mod limits;
 pub(crate) mod local_data;
+pub(crate) mod mutation_schema;
 pub(crate) mod policy_execution_mode;
 
+/// Runs the policies against the request. When a report is requested, every
+/// policy is a test case of the suite named `report_name`: the case passes
+/// when the request is accepted.
 pub(crate) async fn exec(
     policy_definitions: &[PolicyDefinition],
     pull_and_run_settings: &PullAndRunSettings,
+    report: Option<(&ReportOutput, &str)>,
 ) -> Result<()> {
     let local_data = LocalData::new(policy_definitions, pull_and_run_settings).await?;
 
@@ -21,50 +37,125 @@ pub(crate) async fn exec(
         warn!("Multiple policies defined inside of the CRD file. All of them will run sequentially using the same request.");
     }
 
+    let mut cases = Vec::new();
+    let mut result = Ok(());
     for policy_definition in policy_definitions {
-        let (mut evaluator, callback_handler, shutdown_channel_tx) =
-            Evaluator::new(policy_definition, pull_and_run_settings, &local_data).await?;
-
-        // start the callback handler
-        let handler = tokio::spawn(async { callback_handler.loop_eval().await });
-
-        // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
-        // because if the policy uses context aware functions, this would lead to blocking the
-        // tokio runtime. Remember, we're running inside of an async context.
-        let evaluation_result = tokio::task::block_in_place(move || {
-            // validate the settings given by the user
-            let settings_validation_response = evaluator.validate_settings();
-            if !settings_validation_response.valid {
-                return Err(anyhow!(
-                    "Provided settings are not valid: {:?}",
-                    settings_validation_response.message.unwrap_or_default()
-                ));
-            }
-            let vanilla_validation_response = evaluator.evaluate();
-
-            let policy_id = policy_definition.get_policy_id()?;
-            let policy_mode = policy_definition.get_policy_mode();
-            let admission_response_handler = AdmissionResponseHandler::new(
-                &policy_id,
-                &policy_mode,
-                policy_definition.get_policy_allowed_to_mutate(),
-                policy_definition.get_policy_custom_rejection_message(),
-            );
-            Ok(admission_response_handler.process_response(vanilla_validation_response))
+        let start = Instant::now();
+        let evaluation_result =
+            run_policy(policy_definition, pull_and_run_settings, &local_data).await;
+        cases.push(TestCaseReport {
+            name: policy_definition.to_string(),
+            result: match &evaluation_result {
+                Ok(response) if response.allowed => CaseResult::Passed,
+                Ok(response) => CaseResult::Failed(format!(
+                    "rejected: {}",
+                    response
+                        .status
+                        .as_ref()
+                        .and_then(|status| status.message.clone())
+                        .unwrap_or_default()
+                )),
+                Err(e) => CaseResult::Error(e.to_string()),
+            },
+            duration: start.elapsed(),
+            location: None,
         });
+        if let Err(e) = evaluation_result {
+            result = Err(e);
+            break;
+        }
+    }
+
+    if let Some((report_output, report_name)) = report {
+        test_report::write(
+            &[TestSuiteReport {
+                name: report_name.to_string(),
+                cases,
+            }],
+            report_output,
+        )?;
+    }
+    result
+}
+
+async fn run_policy(
+    policy_definition: &PolicyDefinition,
+    pull_and_run_settings: &PullAndRunSettings,
+    local_data: &LocalData,
+) -> Result<AdmissionResponse> {
+    let (mut evaluator, callback_handler, shutdown_channel_tx) =
+        Evaluator::new(policy_definition, pull_and_run_settings, local_data).await?;
 
-        if shutdown_channel_tx.send(()).is_err() {
-            error!("Cannot shut down the CallbackHandler task");
-        } else if let Err(e) = handler.await {
-            error!(
-                error = e.to_string().as_str(),
-                "Error waiting for the CallbackHandler task"
-            );
+    // start the callback handler
+    let handler = tokio::spawn(async { callback_handler.loop_eval().await });
+
+    // We have to wrap the evaluation code inside of a `tokio::task::block_in_place` context
+    // because if the policy uses context aware functions, this would lead to blocking the
+    // tokio runtime. Remember, we're running inside of an async context.
+    let evaluation_result = tokio::task::block_in_place(move || {
+        // validate the settings given by the user
+        let settings_validation_response = evaluator.validate_settings();
+        if !settings_validation_response.valid {
+            return Err(anyhow!(
+                "Provided settings are not valid: {:?}",
+                settings_validation_response.message.unwrap_or_default()
+            ));
+        }
+        let vanilla_validation_response = evaluator.evaluate();
+        if let Some(explain) = &pull_and_run_settings.explain {
+            // the standard output holds the response
+            eprintln!("{}", evaluator.explain(explain)?);
         }
 
-        // Print the evaluation result back to the user, on STDOUT
-        println!("{}", serde_json::to_string(&evaluation_result?)?);
+        let policy_id = policy_definition.get_policy_id()?;
+        let policy_mode = policy_definition.get_policy_mode();
+        let admission_response_handler = AdmissionResponseHandler::new(
+            &policy_id,
+            &policy_mode,
+            policy_definition.get_policy_allowed_to_mutate(),
+            policy_definition.get_policy_custom_rejection_message(),
+        );
+        let mut response = admission_response_handler.process_response(vanilla_validation_response);
+
+        // the custom rejection message of the policy replaces the violations
+        if let PolicyDefinition::Policy {
+            id,
+            custom_rejection_message: None,
+            ..
+        } = policy_definition
+        {
+            if !response.allowed {
+                match evaluator.gatekeeper_violations() {
+                    Ok(Some(violations)) => {
+                        gatekeeper::render_rejection(&mut response, id, &violations)
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!(
+                        error = e.to_string().as_str(),
+                        "cannot list the violations of the policy"
+                    ),
+                }
+            }
+        }
+        Ok(response)
+    });
+
+    if shutdown_channel_tx.send(()).is_err() {
+        error!("Cannot shut down the CallbackHandler task");
+    } else if let Err(e) = handler.await {
+        error!(
+            error = e.to_string().as_str(),
+            "Error waiting for the CallbackHandler task"
+        );
+    }
+
+    // Print the evaluation result back to the user, on STDOUT
+    let evaluation_result = evaluation_result?;
+    println!("{}", serde_json::to_string(&evaluation_result)?);
+
+    if let Some(source) = &pull_and_run_settings.mutation_schema_source {
+        validate_mutated_object(source, &pull_and_run_settings.request, &evaluation_result).await?;
     }
 
-    Ok(())
+    Ok(evaluation_result)
 }
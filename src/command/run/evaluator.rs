@@ -1,4 +1,8 @@
-use std::{collections::BTreeSet, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use anyhow::{anyhow, Result};
 use policy_evaluator::{
@@ -7,7 +11,7 @@ use policy_evaluator::{
     evaluation_context::EvaluationContext,
     kube,
     kubewarden_policy_sdk::settings::SettingsValidationResponse,
-    policy_evaluator::{PolicyEvaluator, PolicySettings, ValidateRequest},
+    policy_evaluator::{PolicyEvaluator, PolicyExecutionMode, PolicySettings, ValidateRequest},
     policy_evaluator_builder::PolicyEvaluatorBuilder,
     policy_group_evaluator::evaluator::PolicyGroupEvaluator,
     policy_metadata::{ContextAwareResource, Metadata, PolicyType},
@@ -18,17 +22,23 @@ use tracing::{info, warn};
 use crate::{
     backend::BackendDetector,
     callback_handler::{CallbackHandler, ProxyMode},
-    command::run::{local_data::LocalData, policy_execution_mode::determine_execution_mode},
+    command::run::{
+        explain::{self, Explain},
+        gatekeeper::{self, Violation},
+        local_data::LocalData,
+        policy_execution_mode::determine_execution_mode,
+    },
     config::{
         policy_definition::{
             ContextAwareConfiguration, PolicyDefinition, PolicyExecutionConfiguration,
         },
         pull_and_run::PullAndRunSettings,
-        HostCapabilitiesMode,
+        strict, HostCapabilitiesMode,
     },
+    inspect::settings_schema,
 };
 
-fn has_raw_policy_type(metadata: Option<&Metadata>) -> bool {
+pub(crate) fn has_raw_policy_type(metadata: Option<&Metadata>) -> bool {
     if let Some(metadata) = metadata {
         metadata.policy_type == PolicyType::Raw
     } else {
@@ -46,16 +56,35 @@ async fn build_callback_handler(
     } else {
         match &cfg.host_capabilities_mode {
             HostCapabilitiesMode::Proxy(ProxyMode::Replay { source: _ }) => None,
-            _ => Some(build_kube_client().await?),
+            _ => Some(build_kube_client(cfg.kubeconfig.as_deref()).await?),
         }
     };
     CallbackHandler::new(cfg, kube_client, shutdown_channel_rx).await
 }
 
+/// The engine of the evaluation limits, or the cached one
+fn engine_settings(
+    builder: PolicyEvaluatorBuilder,
+    cfg: &PullAndRunSettings,
+) -> PolicyEvaluatorBuilder {
+    match &cfg.evaluation_limits {
+        Some(limits) => limits.apply(builder),
+        None if cfg.enable_wasmtime_cache => builder.enable_wasmtime_cache(),
+        None => builder,
+    }
+}
+
 pub(crate) enum Evaluator {
     Policy {
         policy_evaluator: PolicyEvaluator,
+        /// The Wasm module of the policy
+        policy_path: PathBuf,
+        execution_mode: PolicyExecutionMode,
+        /// Whether the policy can read resources from the cluster
+        context_aware: bool,
         settings: PolicySettings,
+        /// The violations of the settings JSON Schema of the policy
+        settings_violations: Vec<String>,
         request: ValidateRequest,
     },
     GroupPolicy {
@@ -109,12 +138,12 @@ impl Evaluator {
                 )
                 .await?;
 
-                let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
-                    .policy_file(local_data.local_path(uri)?)?
-                    .execution_mode(execution_mode);
-                if cfg.enable_wasmtime_cache {
-                    policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
-                }
+                let policy_evaluator_builder = engine_settings(
+                    PolicyEvaluatorBuilder::new()
+                        .policy_file(local_data.local_path(uri)?)?
+                        .execution_mode(execution_mode),
+                    cfg,
+                );
                 let eval_ctx = EvaluationContext {
                     policy_id: uri.to_owned(),
                     callback_channel: Some(callback_handler.sender_channel()),
@@ -123,11 +152,23 @@ impl Evaluator {
                 let policy_evaluator =
                     policy_evaluator_builder.build_pre()?.rehydrate(&eval_ctx)?;
 
+                let settings_violations = match metadata.map(settings_schema).transpose()?.flatten()
+                {
+                    Some(schema) if !cfg.lenient => {
+                        strict::check_settings(&schema, &serde_json::to_value(settings)?)?
+                    }
+                    _ => Vec::new(),
+                };
+
                 Ok((
                     Self::Policy {
                         policy_evaluator,
+                        policy_path: local_data.local_path(uri)?.to_owned(),
+                        execution_mode,
+                        context_aware: !context_aware_allowed_resources.is_empty(),
                         request,
                         settings: settings.clone(),
+                        settings_violations,
                     },
                     callback_handler,
                     shutdown_channel_tx,
@@ -158,11 +199,11 @@ impl Evaluator {
                 );
 
                 for (member_id, member) in policy_members {
-                    let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
-                        .policy_file(local_data.local_path(&member.uri)?)?;
-                    if cfg.enable_wasmtime_cache {
-                        policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
-                    }
+                    let policy_evaluator_builder = engine_settings(
+                        PolicyEvaluatorBuilder::new()
+                            .policy_file(local_data.local_path(&member.uri)?)?,
+                        cfg,
+                    );
 
                     let policy_evaluator_pre = Arc::new(policy_evaluator_builder.build_pre()?);
 
@@ -185,6 +226,16 @@ impl Evaluator {
         }
     }
 
+    /// Replaces the request evaluated by the policy, so that the policy can
+    /// evaluate many requests without being instantiated again
+    pub(crate) fn set_request(&mut self, new_request: ValidateRequest) {
+        match self {
+            Self::Policy { request, .. } | Self::GroupPolicy { request, .. } => {
+                *request = new_request
+            }
+        }
+    }
+
     /// Evaluates the policy against the request and settings.
     /// Note well: this does **not** validate the settings, it assumes that the settings
     /// are already validated.
@@ -194,6 +245,7 @@ impl Evaluator {
                 policy_evaluator,
                 settings,
                 request,
+                ..
             } => policy_evaluator.validate(request.clone(), settings),
             Self::GroupPolicy {
                 policy_group_evaluator,
@@ -202,9 +254,22 @@ impl Evaluator {
         }
     }
 
-    /// Validates the settings given by the user.
+    /// Validates the settings given by the user. The settings of a policy
+    /// are checked against its settings JSON Schema before being given to
+    /// it, unless `--lenient` is set. The members of policy groups are left
+    /// to the policies.
     pub(crate) fn validate_settings(&mut self) -> SettingsValidationResponse {
         match self {
+            Self::Policy {
+                settings_violations,
+                ..
+            } if !settings_violations.is_empty() => SettingsValidationResponse {
+                valid: false,
+                message: Some(format!(
+                    "the settings do not match the settings JSON Schema of the policy: {}. Use --lenient to skip the validation",
+                    settings_violations.join(", ")
+                )),
+            },
             Self::Policy {
                 policy_evaluator,
                 settings,
@@ -219,9 +284,62 @@ impl Evaluator {
             } => policy_group_evaluator.validate_settings(),
         }
     }
+
+    /// Returns the trace of the evaluation of the Rego sources of the
+    /// policy, see [`explain::explain`]. The evaluations of the policy groups
+    /// cannot be explained.
+    pub(crate) fn explain(&self, explain: &Explain) -> Result<String> {
+        match self {
+            Self::Policy {
+                execution_mode,
+                settings,
+                request,
+                ..
+            } => explain::explain(
+                explain,
+                *execution_mode,
+                request_value(request)?,
+                serde_json::to_value(settings)?,
+            ),
+            Self::GroupPolicy { .. } => Err(anyhow!(
+                "the evaluations of the policy groups cannot be explained"
+            )),
+        }
+    }
+
+    /// Lists every violation of the request, for the policies using the
+    /// `gatekeeper` execution mode, see [`gatekeeper::violations`]. The
+    /// violations of the context-aware policies are not listed, the
+    /// resources of the cluster are given to them only by the evaluator.
+    pub(crate) fn gatekeeper_violations(&self) -> Result<Option<Vec<Violation>>> {
+        match self {
+            Self::Policy {
+                policy_path,
+                execution_mode: PolicyExecutionMode::OpaGatekeeper,
+                context_aware: false,
+                settings,
+                request,
+                ..
+            } => gatekeeper::violations(
+                policy_path,
+                request_value(request)?,
+                serde_json::to_value(settings)?,
+            )
+            .map(Some),
+            _ => Ok(None),
+        }
+    }
 }
 
-fn build_validate_request(
+/// The request given to the policy, as JSON
+fn request_value(request: &ValidateRequest) -> Result<serde_json::Value> {
+    match request {
+        ValidateRequest::AdmissionRequest(request) => Ok(serde_json::to_value(request)?),
+        ValidateRequest::Raw(request) => Ok(request.clone()),
+    }
+}
+
+pub(crate) fn build_validate_request(
     request: &serde_json::Value,
     raw_request: bool,
 ) -> Result<ValidateRequest> {
@@ -279,9 +397,25 @@ fn build_context_aware_allowed_resources(
 /// yet (see https://github.com/kube-rs/kube/issues/1003).
 ///
 /// This function provides a workaround to this limitation.
-async fn build_kube_client() -> Result<kube::Client> {
-    // This is the usual way of obtaining a kubeconfig
-    let mut kube_config = kube::Config::infer().await.map_err(anyhow::Error::new)?;
+///
+/// The client connects to the cluster of the given kubeconfig file, or to
+/// the one of the environment when no file is given.
+pub(crate) async fn build_kube_client(kubeconfig: Option<&Path>) -> Result<kube::Client> {
+    let mut kube_config = match kubeconfig {
+        Some(path) => {
+            let kubeconfig = kube::config::Kubeconfig::read_from(path).map_err(|e| {
+                anyhow!("cannot read the kubeconfig file {}: {}", path.display(), e)
+            })?;
+            kube::Config::from_custom_kubeconfig(
+                kubeconfig,
+                &kube::config::KubeConfigOptions::default(),
+            )
+            .await
+            .map_err(anyhow::Error::new)?
+        }
+        // This is the usual way of obtaining a kubeconfig
+        None => kube::Config::infer().await.map_err(anyhow::Error::new)?,
+    };
 
     // Does the cluster_url have an host? This is probably true 99.999% of the times
     if let Some(host) = kube_config.cluster_url.host() {
@@ -0,0 +1,310 @@
+//! Traces of the evaluations of the Rego policies, printed by
+//! `kwctl run --explain`.
+//!
+//! `opa build -t wasm` does not keep the traces of the evaluations inside of
+//! the Wasm modules: the Rego sources of the policy are evaluated by
+//! `opa eval --explain` instead, with the input and the data the Rego engine
+//! of Kubewarden gives to the Wasm module.
+
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_evaluator::PolicyExecutionMode;
+use serde_json::{json, Value};
+use tracing::debug;
+
+use crate::utils::find_in_path;
+
+/// Environment variable holding the path of the opa binary to use. When not
+/// set, opa is looked up in `PATH`.
+const OPA_ENV: &str = "KWCTL_OPA";
+
+/// The packages of the Gatekeeper libraries, which cannot hold the
+/// entrypoint of the policy
+const GATEKEEPER_LIBS_PACKAGE: &str = "lib";
+
+/// How much of the evaluation is traced, like `opa eval --explain`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ExplainMode {
+    /// Only the notes of the `trace` calls of the policy
+    Notes,
+    /// Every step of the evaluation, with the bindings of the variables
+    Full,
+}
+
+impl TryFrom<&str> for ExplainMode {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "notes" => Ok(Self::Notes),
+            "full" => Ok(Self::Full),
+            unknown => Err(anyhow!("Invalid explain mode '{}'", unknown)),
+        }
+    }
+}
+
+impl ExplainMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Notes => "notes",
+            Self::Full => "full",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Explain {
+    pub(crate) mode: ExplainMode,
+    /// The Rego file the policy was built from, or the directory holding
+    /// its Rego files
+    pub(crate) rego_path: PathBuf,
+    /// The query evaluated, the entrypoint of the policy by default
+    pub(crate) query: Option<String>,
+}
+
+/// Evaluates the Rego sources of the policy with `opa eval --explain`,
+/// returning the trace of the evaluation. `request` is the request given to
+/// the policy, `settings` its settings.
+pub(crate) fn explain(
+    explain: &Explain,
+    execution_mode: PolicyExecutionMode,
+    request: Value,
+    settings: Value,
+) -> Result<String> {
+    let (input, data) = input_and_data(execution_mode, request, settings)?;
+    let query = match &explain.query {
+        Some(query) => query.clone(),
+        None => default_query(execution_mode, &explain.rego_path)?,
+    };
+
+    let input_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    serde_json::to_writer(&input_file, &input)?;
+    let data_file = tempfile::Builder::new().suffix(".json").tempfile()?;
+    serde_json::to_writer(&data_file, &data)?;
+
+    let opa = opa()?;
+    debug!(opa = %opa.display(), query = query.as_str(), "explaining policy");
+    let result = Command::new(&opa)
+        .args(["eval", "--format", "pretty", "--v0-compatible", "--explain"])
+        .arg(explain.mode.as_str())
+        .arg("--data")
+        .arg(&explain.rego_path)
+        .arg("--data")
+        .arg(data_file.path())
+        .arg("--input")
+        .arg(input_file.path())
+        .arg(&query)
+        .output()
+        .map_err(|e| anyhow!("cannot run {}: {}", opa.display(), e))?;
+    if !result.status.success() {
+        return Err(anyhow!(
+            "opa eval failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&result.stdout).into_owned())
+}
+
+/// The opa binary, the one of `KWCTL_OPA` or the first one found in `PATH`
+pub(crate) fn opa() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(OPA_ENV) {
+        let path = PathBuf::from(path);
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(anyhow!(
+                "cannot find opa at {}, set via {}",
+                path.display(),
+                OPA_ENV
+            ))
+        };
+    }
+
+    find_in_path(&format!("opa{}", env::consts::EXE_SUFFIX)).ok_or_else(|| {
+        anyhow!(
+            "cannot find opa: install it or set {} to the path of opa",
+            OPA_ENV
+        )
+    })
+}
+
+/// The input and the data given to the policy by the Rego engine of
+/// Kubewarden. OPA policies receive the request wrapped into an
+/// AdmissionReview, and their settings as data. Gatekeeper policies receive
+/// the request under `review`, and their settings under `parameters`.
+pub(crate) fn input_and_data(
+    execution_mode: PolicyExecutionMode,
+    request: Value,
+    settings: Value,
+) -> Result<(Value, Value)> {
+    match execution_mode {
+        PolicyExecutionMode::Opa => Ok((
+            json!({
+                "apiVersion": "admission.k8s.io/v1",
+                "kind": "AdmissionReview",
+                "request": request,
+            }),
+            settings,
+        )),
+        PolicyExecutionMode::OpaGatekeeper => Ok((
+            json!({
+                "parameters": settings,
+                "review": request,
+            }),
+            json!({}),
+        )),
+        execution_mode => Err(anyhow!(
+            "only the evaluations of the Rego policies can be explained, the policy uses the {} execution mode",
+            execution_mode
+        )),
+    }
+}
+
+/// The entrypoint of the policy: the `violation` rule of its package for
+/// Gatekeeper policies, the `main` one for OPA policies
+fn default_query(execution_mode: PolicyExecutionMode, rego_path: &Path) -> Result<String> {
+    let rule = match execution_mode {
+        PolicyExecutionMode::OpaGatekeeper => "violation",
+        _ => "main",
+    };
+    let mut packages = BTreeSet::new();
+    for path in rego_files(rego_path)? {
+        let rego = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+        if let Some(package) = package(&rego) {
+            if package != GATEKEEPER_LIBS_PACKAGE
+                && !package.starts_with(&format!("{GATEKEEPER_LIBS_PACKAGE}."))
+            {
+                packages.insert(package.to_string());
+            }
+        }
+    }
+
+    let mut packages = packages.into_iter();
+    match (packages.next(), packages.next()) {
+        (Some(package), None) => Ok(format!("data.{package}.{rule}")),
+        (None, _) => Err(anyhow!(
+            "cannot find the package of the policy inside of {}",
+            rego_path.display()
+        )),
+        (Some(_), Some(_)) => Err(anyhow!(
+            "{} holds many packages, use --explain-query to choose the query to explain",
+            rego_path.display()
+        )),
+    }
+}
+
+/// The Rego files of `rego_path`, leaving the tests out
+fn rego_files(rego_path: &Path) -> Result<Vec<PathBuf>> {
+    if !rego_path.is_dir() {
+        return Ok(vec![rego_path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let entries = fs::read_dir(rego_path)
+        .map_err(|e| anyhow!("cannot read {}: {}", rego_path.display(), e))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(rego_files(&path)?);
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "rego")
+            && !path.to_string_lossy().ends_with("_test.rego")
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn package(rego: &str) -> Option<&str> {
+    rego.lines()
+        .find_map(|line| line.trim().strip_prefix("package "))
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::opa(
+        PolicyExecutionMode::Opa,
+        json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {"uid": "1"},
+        }),
+        json!({"allowed": ["nginx"]})
+    )]
+    #[case::gatekeeper(
+        PolicyExecutionMode::OpaGatekeeper,
+        json!({
+            "parameters": {"allowed": ["nginx"]},
+            "review": {"uid": "1"},
+        }),
+        json!({})
+    )]
+    fn input_and_data_of_the_execution_modes(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] expected_input: Value,
+        #[case] expected_data: Value,
+    ) {
+        let (input, data) = input_and_data(
+            execution_mode,
+            json!({"uid": "1"}),
+            json!({"allowed": ["nginx"]}),
+        )
+        .unwrap();
+        assert_eq!(input, expected_input);
+        assert_eq!(data, expected_data);
+    }
+
+    #[test]
+    fn wapc_policies_cannot_be_explained() {
+        assert!(input_and_data(PolicyExecutionMode::KubewardenWapc, json!({}), json!({})).is_err());
+    }
+
+    #[rstest]
+    #[case::gatekeeper(PolicyExecutionMode::OpaGatekeeper, "data.k8srequiredlabels.violation")]
+    #[case::opa(PolicyExecutionMode::Opa, "data.k8srequiredlabels.main")]
+    fn default_query_skips_the_libraries_and_the_tests(
+        #[case] execution_mode: PolicyExecutionMode,
+        #[case] expected: &str,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("policy.rego"),
+            "# required labels\npackage k8srequiredlabels\n\nviolation[{\"msg\": msg}] { msg := \"denied\" }\n",
+        )
+        .unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(
+            dir.path().join("lib").join("lib-0.rego"),
+            "package lib.helpers\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("policy_test.rego"), "package tests\n").unwrap();
+
+        assert_eq!(default_query(execution_mode, dir.path()).unwrap(), expected);
+    }
+
+    #[test]
+    fn default_query_of_many_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rego"), "package a\n").unwrap();
+        fs::write(dir.path().join("b.rego"), "package b\n").unwrap();
+
+        let error = default_query(PolicyExecutionMode::OpaGatekeeper, dir.path()).unwrap_err();
+        assert!(error.to_string().contains("--explain-query"));
+    }
+}
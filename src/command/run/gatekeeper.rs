@@ -0,0 +1,159 @@
+//! Rejection messages of the policies using the `gatekeeper` execution mode,
+//! rendered like the denials of Gatekeeper.
+//!
+//! Gatekeeper lists every violation of a request, prefixing its `msg` with
+//! the name of the Constraint, like `[must-have-owner] missing label owner`,
+//! and joins them with new lines. The `violation` rule of the policy is
+//! evaluated again to list the violations behind the rejection.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    admission_response::{AdmissionResponse, AdmissionResponseStatus},
+    burrego::{EvaluatorBuilder, HostCallbacks},
+    policy_evaluator::PolicyExecutionMode,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::command::run::explain::input_and_data;
+
+/// `opa build` gives the id 0 to the entrypoint of the policy, the
+/// `violation` rule of the Gatekeeper policies built for Kubewarden
+const VIOLATION_ENTRYPOINT_ID: i32 = 0;
+
+/// A violation returned by the `violation` rule of the policy. Its
+/// `details` are not part of the denials of Gatekeeper.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub(crate) struct Violation {
+    pub(crate) msg: String,
+}
+
+/// Evaluates the `violation` rule of the policy stored at `wasm_path`,
+/// returning every violation of the request
+pub(crate) fn violations(
+    wasm_path: &Path,
+    request: Value,
+    settings: Value,
+) -> Result<Vec<Violation>> {
+    let (input, data) = input_and_data(PolicyExecutionMode::OpaGatekeeper, request, settings)?;
+    let mut evaluator = EvaluatorBuilder::default()
+        .policy_path(wasm_path)
+        .host_callbacks(HostCallbacks::default())
+        .build()
+        .map_err(|e| anyhow!("cannot load the policy {}: {}", wasm_path.display(), e))?;
+    let result = evaluator
+        .evaluate(VIOLATION_ENTRYPOINT_ID, &input, &data)
+        .map_err(|e| anyhow!("cannot evaluate the violations of the policy: {}", e))?;
+    parse_violations(result)
+}
+
+/// Parses the result of the evaluation, like `[{"result": [{"msg": "..."}]}]`
+fn parse_violations(result: Value) -> Result<Vec<Violation>> {
+    let violations = result
+        .get(0)
+        .and_then(|result| result.get("result"))
+        .cloned()
+        .unwrap_or(Value::Array(Vec::new()));
+    serde_json::from_value(violations)
+        .map_err(|e| anyhow!("the policy returned invalid violations: {}", e))
+}
+
+/// The message of the denial of Gatekeeper: every violation prefixed with the
+/// name of the Constraint, one per line
+fn denial_message(constraint: &str, violations: &[Violation]) -> String {
+    violations
+        .iter()
+        .map(|violation| format!("[{}] {}", constraint, violation.msg))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Replaces the message of the rejection with the denial of Gatekeeper. The
+/// accepted requests, and the rejections without violations, like the ones
+/// of the policies failing to evaluate, are left untouched.
+pub(crate) fn render_rejection(
+    response: &mut AdmissionResponse,
+    constraint: &str,
+    violations: &[Violation],
+) {
+    if response.allowed || violations.is_empty() {
+        return;
+    }
+    let message = denial_message(constraint, violations);
+    match &mut response.status {
+        Some(status) => status.message = Some(message),
+        None => {
+            response.status = Some(AdmissionResponseStatus {
+                message: Some(message),
+                code: None,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn violations() -> Vec<Violation> {
+        vec![
+            Violation {
+                msg: "you must provide labels: {\"owner\"}".to_string(),
+            },
+            Violation {
+                msg: "the owner label is empty".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn parse_the_violations() {
+        let result = json!([{
+            "result": [
+                {
+                    "msg": "you must provide labels: {\"owner\"}",
+                    "details": {"missing_labels": ["owner"]},
+                },
+                {"msg": "the owner label is empty"},
+            ]
+        }]);
+        assert_eq!(parse_violations(result).unwrap(), violations());
+        assert!(parse_violations(json!([{"result": []}]))
+            .unwrap()
+            .is_empty());
+        assert!(parse_violations(json!([{"result": [{"details": {}}]}])).is_err());
+    }
+
+    #[test]
+    fn every_violation_is_reported() {
+        let mut response = AdmissionResponse {
+            uid: "1".to_string(),
+            allowed: false,
+            status: Some(AdmissionResponseStatus {
+                message: Some("you must provide labels: {\"owner\"}".to_string()),
+                code: None,
+            }),
+            ..Default::default()
+        };
+        render_rejection(&mut response, "must-have-owner", &violations());
+
+        assert_eq!(
+            response.status.unwrap().message.unwrap(),
+            "[must-have-owner] you must provide labels: {\"owner\"}\n[must-have-owner] the owner label is empty"
+        );
+    }
+
+    #[test]
+    fn accepted_requests_are_left_untouched() {
+        let mut response = AdmissionResponse {
+            uid: "1".to_string(),
+            allowed: true,
+            ..Default::default()
+        };
+        render_rejection(&mut response, "must-have-owner", &violations());
+        assert!(response.status.is_none());
+    }
+}
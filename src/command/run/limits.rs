@@ -0,0 +1,161 @@
+//! Limits of the time and of the memory granted to every evaluation,
+//! enforced by the Wasmtime engine running the policies.
+
+use std::{thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    admission_response::AdmissionResponse, policy_evaluator_builder::PolicyEvaluatorBuilder,
+};
+
+/// How often the epoch of the engine is increased, the timeouts are
+/// enforced with this granularity
+const EPOCH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The failures of the evaluations interrupted once past their deadline: the
+/// one of the waPC and WASI policies, the one of the Rego policies, and the
+/// trap of the engine
+const INTERRUPTED: [&str; 3] = [
+    "guest code interrupted, execution deadline exceeded",
+    "Execution deadline exceeded",
+    "wasm trap: interrupt",
+];
+
+/// The trap raised when the memory of a policy cannot grow, see
+/// `wasmtime::Config::trap_on_grow_failure`
+const MEMORY_EXHAUSTED: &str = "forcing trap when growing memory";
+
+const MIB: u64 = 1024 * 1024;
+
+/// The engine the policies are compiled with, interrupting the evaluations
+/// taking longer than `timeout` and denying the memory beyond `memory` MiB
+#[derive(Clone)]
+pub(crate) struct EvaluationLimits {
+    timeout: Option<Duration>,
+    memory: Option<u64>,
+    engine: wasmtime::Engine,
+}
+
+impl EvaluationLimits {
+    /// `memory` is in MiB. When `cache` is set, the compiled modules are
+    /// cached like by `PolicyEvaluatorBuilder::enable_wasmtime_cache`.
+    pub(crate) fn new(timeout: Option<Duration>, memory: Option<u64>, cache: bool) -> Result<Self> {
+        let mut config = wasmtime::Config::new();
+        if cache {
+            let cache = wasmtime::Cache::from_file(None)
+                .map_err(|e| anyhow!("cannot load the Wasmtime cache configuration: {}", e))?;
+            config.cache(Some(cache));
+        }
+        if timeout.is_some() {
+            config.epoch_interruption(true);
+        }
+        if let Some(memory) = memory {
+            // the memories are reserved upfront and cannot be moved, they
+            // cannot grow beyond the reservation. Growing them beyond it
+            // traps, instead of letting the policy handle the failure.
+            config
+                .memory_reservation(memory * MIB)
+                .memory_reservation_for_growth(0)
+                .memory_may_move(false)
+                .trap_on_grow_failure(true);
+        }
+        let engine = wasmtime::Engine::new(&config)
+            .map_err(|e| anyhow!("cannot create the Wasmtime engine: {}", e))?;
+
+        if timeout.is_some() {
+            // the ticker stops once the engine is dropped
+            let engine = engine.weak();
+            thread::spawn(move || {
+                while let Some(engine) = engine.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    thread::sleep(EPOCH_INTERVAL);
+                }
+            });
+        }
+
+        Ok(EvaluationLimits {
+            timeout,
+            memory,
+            engine,
+        })
+    }
+
+    /// Builds the policy with the engine enforcing the limits
+    pub(crate) fn apply(&self, builder: PolicyEvaluatorBuilder) -> PolicyEvaluatorBuilder {
+        let builder = builder.engine(self.engine.clone());
+        match self.timeout {
+            Some(timeout) => {
+                let ticks = (timeout.as_millis() / EPOCH_INTERVAL.as_millis()).max(1) as u64;
+                builder.enable_epoch_interruptions(ticks, ticks)
+            }
+            None => builder,
+        }
+    }
+
+    /// Why the evaluation went beyond the limits, if it did. The evaluations
+    /// trapped by the engine, because they were interrupted or ran out of
+    /// memory, fail with an internal server error reporting the trap.
+    pub(crate) fn exceeded(&self, response: &AdmissionResponse) -> Option<String> {
+        let message = response
+            .status
+            .as_ref()
+            .filter(|status| !response.allowed && status.code == Some(500))?
+            .message
+            .as_deref()?;
+        if let Some(timeout) = self
+            .timeout
+            .filter(|_| INTERRUPTED.iter().any(|trap| message.contains(trap)))
+        {
+            return Some(format!(
+                "the evaluation took longer than {} seconds",
+                timeout.as_secs_f64()
+            ));
+        }
+        self.memory
+            .filter(|_| message.contains(MEMORY_EXHAUSTED))
+            .map(|memory| format!("the evaluation needed more than {} MiB of memory", memory))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+
+    fn response(allowed: bool, code: u16, message: &str) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed,
+            status: Some(AdmissionResponseStatus {
+                message: Some(message.to_string()),
+                code: Some(code),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::allowed(response(true, 200, ""), None)]
+    #[case::rejected(response(false, 400, "privileged containers are not allowed"), None)]
+    #[case::failed(
+        response(false, 500, "wasm trap: wasm `unreachable` instruction executed"),
+        None
+    )]
+    #[case::interrupted(
+        response(false, 500, "guest code interrupted, execution deadline exceeded"),
+        Some("the evaluation took longer than 2 seconds")
+    )]
+    #[case::rego_interrupted(
+        response(false, 500, "Execution deadline exceeded"),
+        Some("the evaluation took longer than 2 seconds")
+    )]
+    #[case::out_of_memory(
+        response(false, 500, "forcing trap when growing memory to 16842752 bytes"),
+        Some("the evaluation needed more than 16 MiB of memory")
+    )]
+    fn exceeded_limits(#[case] response: AdmissionResponse, #[case] expected: Option<&str>) {
+        let limits = EvaluationLimits::new(Some(Duration::from_secs(2)), Some(16), false).unwrap();
+        assert_eq!(limits.exceeded(&response).as_deref(), expected);
+    }
+}
@@ -22,7 +22,8 @@ impl LocalData {
         cfg: &PullAndRunSettings,
     ) -> Result<Self> {
         let local_paths = pull_all(policy_definitions, cfg).await?;
-        let modules_metadata = build_metadata(&local_paths)?;
+        let modules_metadata = build_metadata(&local_paths, cfg.trusted_only)?;
+        crate::policies::record_usage(local_paths.keys());
 
         Ok(Self {
             local_paths,
@@ -55,20 +56,25 @@ async fn pull_all(
             if local_paths.contains_key(&uri) {
                 continue;
             }
-            let policy = pull::pull(&uri, sources, PullDestination::MainStore).await?;
+            let policy =
+                pull::pull(&uri, sources, &cfg.mirrors, PullDestination::MainStore).await?;
 
             if let Some(digests) = cfg.verified_manifest_digests.as_ref() {
                 let digest = digests
                     .get(&uri)
                     .ok_or_else(|| anyhow!("No digest found for {}", uri))?;
 
-                verify::verify_local_checksum(
+                let result = verify::verify_local_checksum(
                     &policy,
                     sources,
                     digest,
                     cfg.sigstore_trust_root.clone(),
                 )
-                .await?
+                .await;
+                if let Some(origin) = &cfg.verification_origin {
+                    crate::provenance::record_verification(&uri, result.is_ok(), origin);
+                }
+                result?
             }
 
             local_paths.insert(uri, policy.local_path);
@@ -77,12 +83,23 @@ async fn pull_all(
     Ok(local_paths)
 }
 
-fn build_metadata(local_paths: &HashMap<String, PathBuf>) -> Result<HashMap<String, Metadata>> {
+// Reads the metadata of the policies. When `trusted_only` is set, the
+// policies without metadata are refused.
+fn build_metadata(
+    local_paths: &HashMap<String, PathBuf>,
+    trusted_only: bool,
+) -> Result<HashMap<String, Metadata>> {
     let mut modules_metadata = HashMap::new();
 
     for (uri, local_path) in local_paths {
         let metadata = Metadata::from_path(local_path)?;
         if metadata.is_none() {
+            if trusted_only {
+                return Err(anyhow!(
+                    "policy {} has no Kubewarden metadata, it cannot be run with --trusted-only",
+                    uri
+                ));
+            }
             continue;
         }
         has_minimum_kubewarden_version(metadata.as_ref())?;
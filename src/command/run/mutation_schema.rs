@@ -0,0 +1,397 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use k8s_openapi::http;
+use policy_evaluator::{admission_response::AdmissionResponse, kube};
+use serde_json::Value;
+use tracing::info;
+
+/// Where the OpenAPI schema used to validate mutated objects is obtained from
+#[derive(Clone, Debug)]
+pub(crate) enum MutationSchemaSource {
+    /// OpenAPI v3 document stored on the local filesystem
+    File(PathBuf),
+    /// OpenAPI v3 document served by the Kubernetes API server
+    Cluster,
+}
+
+/// An OpenAPI v3 document, like the ones served by the Kubernetes API server
+/// under `/openapi/v3/apis/<group>/<version>`.
+pub(crate) struct OpenApiSchema(Value);
+
+impl OpenApiSchema {
+    async fn load(source: &MutationSchemaSource, api_version: &str) -> Result<Self> {
+        match source {
+            MutationSchemaSource::File(path) => Self::from_file(path),
+            MutationSchemaSource::Cluster => Self::from_cluster(api_version).await,
+        }
+    }
+
+    fn from_file(path: &PathBuf) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read OpenAPI schema {}: {}", path.display(), e))?;
+        // YAML is a superset of JSON, this allows both formats to be used
+        let document: Value = serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("cannot parse OpenAPI schema {}: {}", path.display(), e))?;
+        Ok(Self(document))
+    }
+
+    async fn from_cluster(api_version: &str) -> Result<Self> {
+        let path = if api_version.contains('/') {
+            format!("/openapi/v3/apis/{api_version}")
+        } else {
+            format!("/openapi/v3/api/{api_version}")
+        };
+        let request = http::Request::get(path.as_str()).body(vec![])?;
+
+        let client = crate::command::run::evaluator::build_kube_client(None).await?;
+        let document = client
+            .request::<Value>(request)
+            .await
+            .map_err(|e: kube::Error| anyhow!("cannot fetch OpenAPI schema from {path}: {e}"))?;
+        Ok(Self(document))
+    }
+
+    /// Validates the given object against the schema of its kind.
+    ///
+    /// Returns the list of violations found, an empty list means the object
+    /// would be accepted by the API server.
+    fn validate(&self, object: &Value) -> Result<Vec<String>> {
+        let api_version = object
+            .get("apiVersion")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("the mutated object doesn't have an apiVersion"))?;
+        let kind = object
+            .get("kind")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("the mutated object doesn't have a kind"))?;
+        let schema = self
+            .find_kind_schema(api_version, kind)
+            .ok_or_else(|| anyhow!("cannot find the OpenAPI schema of {api_version}/{kind}"))?;
+
+        let mut violations = Vec::new();
+        self.check(schema, object, "", &mut violations);
+        Ok(violations)
+    }
+
+    fn find_kind_schema(&self, api_version: &str, kind: &str) -> Option<&Value> {
+        let (group, version) = api_version.rsplit_once('/').unwrap_or(("", api_version));
+
+        self.0
+            .pointer("/components/schemas")?
+            .as_object()?
+            .values()
+            .find(|schema| {
+                schema
+                    .get("x-kubernetes-group-version-kind")
+                    .and_then(Value::as_array)
+                    .is_some_and(|gvks| {
+                        gvks.iter().any(|gvk| {
+                            gvk.get("group").and_then(Value::as_str).unwrap_or_default() == group
+                                && gvk.get("version").and_then(Value::as_str) == Some(version)
+                                && gvk.get("kind").and_then(Value::as_str) == Some(kind)
+                        })
+                    })
+            })
+    }
+
+    // Follows `$ref` and the single-element `allOf` used by Kubernetes to
+    // reference other schemas
+    fn resolve<'a>(&'a self, schema: &'a Value) -> &'a Value {
+        let reference = schema.get("$ref").and_then(Value::as_str).or_else(|| {
+            schema
+                .get("allOf")
+                .and_then(Value::as_array)
+                .filter(|all_of| all_of.len() == 1)
+                .and_then(|all_of| all_of[0].get("$ref"))
+                .and_then(Value::as_str)
+        });
+
+        match reference
+            .and_then(|r| r.strip_prefix('#'))
+            .and_then(|pointer| self.0.pointer(pointer))
+        {
+            Some(resolved) => self.resolve(resolved),
+            None => schema,
+        }
+    }
+
+    fn check(&self, schema: &Value, value: &Value, path: &str, violations: &mut Vec<String>) {
+        let schema = self.resolve(schema);
+        let field = if path.is_empty() { "." } else { path };
+
+        // optional fields can be explicitly set to null
+        if value.is_null() {
+            return;
+        }
+
+        if schema
+            .get("x-kubernetes-int-or-string")
+            .and_then(Value::as_bool)
+            .unwrap_or_default()
+        {
+            if !(value.is_i64() || value.is_u64() || value.is_string()) {
+                violations.push(format!("{field}: expected integer or string"));
+            }
+            return;
+        }
+
+        let preserve_unknown_fields = schema
+            .get("x-kubernetes-preserve-unknown-fields")
+            .and_then(Value::as_bool)
+            .unwrap_or_default();
+
+        match schema.get("type").and_then(Value::as_str) {
+            Some("object") => {
+                let Some(object) = value.as_object() else {
+                    violations.push(format!("{field}: expected object"));
+                    return;
+                };
+                let properties = schema.get("properties").and_then(Value::as_object);
+                let additional_properties = schema.get("additionalProperties");
+
+                for (key, child) in object {
+                    let child_path = format!("{path}.{key}");
+                    if let Some(property) = properties.and_then(|p| p.get(key)) {
+                        self.check(property, child, &child_path, violations);
+                    } else if let Some(additional) = additional_properties.filter(|a| a.is_object())
+                    {
+                        self.check(additional, child, &child_path, violations);
+                    } else if properties.is_some() && !preserve_unknown_fields {
+                        violations.push(format!("{child_path}: unknown field"));
+                    }
+                }
+            }
+            Some("array") => {
+                let Some(items) = value.as_array() else {
+                    violations.push(format!("{field}: expected array"));
+                    return;
+                };
+                if let Some(items_schema) = schema.get("items") {
+                    for (index, item) in items.iter().enumerate() {
+                        self.check(items_schema, item, &format!("{path}[{index}]"), violations);
+                    }
+                }
+            }
+            Some("string") if !value.is_string() => {
+                violations.push(format!("{field}: expected string"));
+            }
+            Some("integer") if !(value.is_i64() || value.is_u64()) => {
+                violations.push(format!("{field}: expected integer"));
+            }
+            Some("number") if !value.is_number() => {
+                violations.push(format!("{field}: expected number"));
+            }
+            Some("boolean") if !value.is_boolean() => {
+                violations.push(format!("{field}: expected boolean"));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Validates the object mutated by the policy against the OpenAPI schema of
+/// its kind, catching patches that produce objects rejected by the API server.
+///
+/// Nothing is done when the policy didn't mutate the request.
+pub(crate) async fn validate_mutated_object(
+    source: &MutationSchemaSource,
+    request: &Value,
+    response: &AdmissionResponse,
+) -> Result<()> {
+    let Some(object) = mutated_object(request, response)? else {
+        return Ok(());
+    };
+    let api_version = object
+        .get("apiVersion")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("the mutated object doesn't have an apiVersion"))?;
+
+    let schema = OpenApiSchema::load(source, api_version).await?;
+    let violations = schema.validate(&object)?;
+    if !violations.is_empty() {
+        return Err(anyhow!(
+            "The mutated object would be rejected by the API server:\n{}",
+            violations
+                .iter()
+                .map(|violation| format!("  - {violation}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ));
+    }
+
+    info!("The mutated object is valid according to its OpenAPI schema");
+    Ok(())
+}
+
+/// Returns the object produced by applying the patch of a mutating policy
+/// to the object of the admission request.
+///
+/// `None` is returned when the policy didn't mutate the request.
+fn mutated_object(request: &Value, response: &AdmissionResponse) -> Result<Option<Value>> {
+    let Some(patch) = &response.patch else {
+        return Ok(None);
+    };
+
+    let request = if request.get("kind").and_then(Value::as_str) == Some("AdmissionReview") {
+        request
+            .get("request")
+            .ok_or_else(|| anyhow!("invalid AdmissionReview object"))?
+    } else {
+        request
+    };
+    let mut object = request
+        .get("object")
+        .cloned()
+        .ok_or_else(|| anyhow!("the admission request doesn't have an object to patch"))?;
+
+    let patch = general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| anyhow!("cannot decode the patch returned by the policy: {e}"))?;
+    let patch: json_patch::Patch = serde_json::from_slice(&patch)
+        .map_err(|e| anyhow!("the policy returned an invalid JSON patch: {e}"))?;
+    json_patch::patch(&mut object, &patch)
+        .map_err(|e| anyhow!("the patch returned by the policy cannot be applied: {e}"))?;
+
+    Ok(Some(object))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn schema() -> OpenApiSchema {
+        OpenApiSchema(json!({
+            "components": {
+                "schemas": {
+                    "io.k8s.api.core.v1.Pod": {
+                        "type": "object",
+                        "x-kubernetes-group-version-kind": [
+                            {"group": "", "version": "v1", "kind": "Pod"}
+                        ],
+                        "properties": {
+                            "apiVersion": {"type": "string"},
+                            "kind": {"type": "string"},
+                            "metadata": {
+                                "allOf": [{"$ref": "#/components/schemas/io.k8s.apimachinery.pkg.apis.meta.v1.ObjectMeta"}]
+                            },
+                            "spec": {
+                                "allOf": [{"$ref": "#/components/schemas/io.k8s.api.core.v1.PodSpec"}]
+                            }
+                        }
+                    },
+                    "io.k8s.apimachinery.pkg.apis.meta.v1.ObjectMeta": {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string"},
+                            "labels": {
+                                "type": "object",
+                                "additionalProperties": {"type": "string"}
+                            }
+                        }
+                    },
+                    "io.k8s.api.core.v1.PodSpec": {
+                        "type": "object",
+                        "properties": {
+                            "terminationGracePeriodSeconds": {"type": "integer"},
+                            "containers": {
+                                "type": "array",
+                                "items": {"$ref": "#/components/schemas/io.k8s.api.core.v1.Container"}
+                            }
+                        }
+                    },
+                    "io.k8s.api.core.v1.Container": {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string"},
+                            "image": {"type": "string"},
+                            "ports": {
+                                "type": "array",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "containerPort": {"type": "integer"},
+                                        "name": {"x-kubernetes-int-or-string": true}
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    fn pod(spec: Value) -> Value {
+        json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {"name": "nginx", "labels": {"owner": "team-a"}},
+            "spec": spec,
+        })
+    }
+
+    #[rstest]
+    #[case::valid(
+        pod(json!({"containers": [{"name": "nginx", "image": "nginx", "ports": [{"containerPort": 80, "name": "http"}]}]})),
+        vec![]
+    )]
+    #[case::null_field(pod(json!({"terminationGracePeriodSeconds": null, "containers": []})), vec![])]
+    #[case::wrong_type(
+        pod(json!({"terminationGracePeriodSeconds": "30", "containers": [{"name": 1}]})),
+        vec![".spec.containers[0].name: expected string", ".spec.terminationGracePeriodSeconds: expected integer"]
+    )]
+    #[case::unknown_field(
+        pod(json!({"containers": [{"name": "nginx", "imageName": "nginx"}]})),
+        vec![".spec.containers[0].imageName: unknown field"]
+    )]
+    #[case::wrong_int_or_string(
+        pod(json!({"containers": [{"name": "nginx", "ports": [{"name": true}]}]})),
+        vec![".spec.containers[0].ports[0].name: expected integer or string"]
+    )]
+    fn validate_object(#[case] object: Value, #[case] expected: Vec<&str>) {
+        let mut violations = schema().validate(&object).unwrap();
+        violations.sort();
+        assert_eq!(violations, expected);
+    }
+
+    #[test]
+    fn validate_object_of_unknown_kind() {
+        let object = json!({"apiVersion": "apps/v1", "kind": "Deployment"});
+        assert!(schema().validate(&object).is_err());
+    }
+
+    #[test]
+    fn apply_mutation_patch() {
+        let request = json!({
+            "kind": "AdmissionReview",
+            "request": {
+                "object": pod(json!({"containers": [{"name": "nginx", "image": "nginx"}]}))
+            }
+        });
+        let patch = json!([
+            {"op": "add", "path": "/spec/containers/0/imagePullPolicy", "value": "Always"}
+        ]);
+        let response = AdmissionResponse {
+            patch: Some(general_purpose::STANDARD.encode(patch.to_string())),
+            ..Default::default()
+        };
+
+        let mutated = mutated_object(&request, &response).unwrap().unwrap();
+        assert_eq!(
+            mutated.pointer("/spec/containers/0/imagePullPolicy"),
+            Some(&json!("Always"))
+        );
+    }
+
+    #[test]
+    fn no_mutation() {
+        let request = json!({"object": pod(json!({}))});
+        let response = AdmissionResponse::default();
+
+        assert!(mutated_object(&request, &response).unwrap().is_none());
+    }
+}
@@ -0,0 +1,669 @@
+//! Local webhook server, run by `kwctl serve`.
+//!
+//! The policies of a `policies.yml` file of policy-server are served through
+//! the endpoints of policy-server, so that a development cluster, or curl,
+//! can send AdmissionReviews to them:
+//!
+//! - `POST /validate/<policy>` evaluates the request like policy-server,
+//!   honoring the mode of the policy and whether it is allowed to mutate
+//! - `POST /validate_raw/<policy>` evaluates the raw requests of raw policies
+//! - `POST /audit/<policy>` evaluates the request regardless of the mode of
+//!   the policy, like for the audit scanner
+//! - `GET /readiness`
+//! - `GET /metrics`, the metrics of the evaluations, like the ones of
+//!   policy-server
+//!
+//! Every connection is served by its own task, over HTTP or HTTPS, so that a
+//! slow client does not delay the others. The evaluations run on the threads
+//! of the runtime dedicated to blocking operations: the ones of a policy are
+//! serialized, the ones of different policies run in parallel. The policies
+//! are reloaded when the `policies.yml` file, or their local modules, change.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{
+    body::{Bytes, Incoming},
+    header::{HeaderValue, CONTENT_TYPE},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use policy_evaluator::{
+    admission_response_handler::{policy_mode::PolicyMode, AdmissionResponseHandler},
+    policy_evaluator::ValidateRequest,
+    policy_metadata::Rule,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{oneshot, RwLock},
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+
+use crate::{
+    command::run::{
+        evaluator::{build_validate_request, has_raw_policy_type, Evaluator},
+        local_data::LocalData,
+    },
+    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+};
+
+mod metrics;
+mod reload;
+pub(crate) mod stats;
+pub(crate) mod tls;
+mod webhooks;
+
+use metrics::{Evaluation, EvaluationMetrics};
+use reload::WatchedFiles;
+use tls::TlsCertificate;
+
+/// How often the files of the policies are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time given to the clients to send the headers, and then the body, of
+/// their requests
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bodies bigger than the limit of the Kubernetes API server are refused
+const MAX_BODY_SIZE: usize = 3 * 1024 * 1024;
+
+/// The evaluators are built for a request, which is replaced by the served
+/// ones
+fn placeholder_request() -> Value {
+    json!({
+        "uid": "kwctl-serve",
+        "kind": {"group": "", "version": "v1", "kind": "Pod"},
+        "resource": {"group": "", "version": "v1", "resource": "pods"},
+        "operation": "CREATE",
+        "userInfo": {},
+    })
+}
+
+struct ServedPolicy {
+    definition: PolicyDefinition,
+    /// Evaluates one request at a time
+    evaluator: Mutex<Evaluator>,
+    raw: bool,
+    /// The rules of the metadata of the policy, or of its members
+    rules: Vec<Rule>,
+    /// Stops the task serving the host capabilities to the policy, once the
+    /// policy is dropped
+    shutdown_channel_tx: Option<oneshot::Sender<()>>,
+}
+
+impl ServedPolicy {
+    async fn new(
+        definition: PolicyDefinition,
+        pull_settings: &PullAndRunSettings,
+        local_data: &LocalData,
+    ) -> Result<Self> {
+        let (mut evaluator, callback_handler, shutdown_channel_tx) =
+            Evaluator::new(&definition, pull_settings, local_data).await?;
+        // the policies can use the host capabilities, like when run
+        tokio::spawn(async { callback_handler.loop_eval().await });
+
+        let settings_validation_response =
+            tokio::task::block_in_place(|| evaluator.validate_settings());
+        if !settings_validation_response.valid {
+            if shutdown_channel_tx.send(()).is_err() {
+                error!("Cannot shut down the CallbackHandler task");
+            }
+            return Err(anyhow!(
+                "{}: provided settings are not valid: {}",
+                definition,
+                settings_validation_response.message.unwrap_or_default()
+            ));
+        }
+        let raw = match &definition {
+            PolicyDefinition::Policy { uri, raw, .. } => {
+                *raw || has_raw_policy_type(local_data.metadata(uri))
+            }
+            PolicyDefinition::PolicyGroup { .. } => false,
+        };
+        let mut rules: Vec<Rule> = Vec::new();
+        for uri in definition.uris() {
+            for rule in local_data
+                .metadata(&uri)
+                .map(|metadata| metadata.rules.clone())
+                .unwrap_or_default()
+            {
+                if !rules.contains(&rule) {
+                    rules.push(rule);
+                }
+            }
+        }
+        Ok(ServedPolicy {
+            definition,
+            evaluator: Mutex::new(evaluator),
+            raw,
+            rules,
+            shutdown_channel_tx: Some(shutdown_channel_tx),
+        })
+    }
+}
+
+impl Drop for ServedPolicy {
+    fn drop(&mut self) {
+        if let Some(shutdown_channel_tx) = self.shutdown_channel_tx.take() {
+            if shutdown_channel_tx.send(()).is_err() {
+                error!("Cannot shut down the CallbackHandler task");
+            }
+        }
+    }
+}
+
+pub(crate) struct ServeOptions {
+    pub(crate) address: SocketAddr,
+    /// The `policies.yml` file, watched together with the local modules of
+    /// the policies
+    pub(crate) config_path: PathBuf,
+    /// Serves HTTPS with the certificate, instead of HTTP
+    pub(crate) certificate: Option<TlsCertificate>,
+    /// Prints the webhook configurations reaching kwctl at the host, which
+    /// requires a certificate
+    pub(crate) webhook_host: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Endpoint {
+    Validate,
+    ValidateRaw,
+    Audit,
+}
+
+struct HttpResponse {
+    status: StatusCode,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn json(status: StatusCode, body: &Value) -> Self {
+        HttpResponse {
+            status,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    fn error(status: StatusCode, message: impl ToString) -> Self {
+        Self::json(status, &json!({"message": message.to_string()}))
+    }
+}
+
+impl From<HttpResponse> for Response<Full<Bytes>> {
+    fn from(response: HttpResponse) -> Self {
+        let mut hyper_response = Response::new(Full::new(Bytes::from(response.body)));
+        *hyper_response.status_mut() = response.status;
+        hyper_response.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(response.content_type),
+        );
+        hyper_response
+    }
+}
+
+/// The state shared by the tasks serving the connections
+#[derive(Default)]
+struct Server {
+    /// Written only while the policies are reloaded. The evaluations in
+    /// flight keep the policy they started with, which is dropped once the
+    /// last of them is done.
+    policies: RwLock<BTreeMap<String, Arc<ServedPolicy>>>,
+    metrics: Mutex<EvaluationMetrics>,
+}
+
+impl Server {
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Incoming>,
+        peer: SocketAddr,
+    ) -> Response<Full<Bytes>> {
+        let start = Instant::now();
+        let method = request.method().clone();
+        // the API server appends the timeout of the webhook as query
+        let path = request.uri().path().to_string();
+        let response = match read_body(request.into_body()).await {
+            Ok(body) => self.route(&method, &path, body).await,
+            Err(response) => response,
+        };
+
+        // the access log
+        info!(
+            peer = peer.to_string().as_str(),
+            method = method.as_str(),
+            path = path.as_str(),
+            status = response.status.as_u16(),
+            latency_ms = start.elapsed().as_millis() as u64,
+            "request served"
+        );
+        response.into()
+    }
+
+    async fn route(self: Arc<Self>, method: &Method, path: &str, body: Bytes) -> HttpResponse {
+        match path {
+            "/readiness" => return HttpResponse::json(StatusCode::OK, &json!({})),
+            "/metrics" => {
+                return HttpResponse {
+                    status: StatusCode::OK,
+                    content_type: "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                    body: self.metrics.lock().unwrap().render().into_bytes(),
+                }
+            }
+            _ => {}
+        }
+        let (endpoint, id) = match path.trim_start_matches('/').split_once('/') {
+            Some(("validate", id)) => (Endpoint::Validate, id.to_string()),
+            Some(("validate_raw", id)) => (Endpoint::ValidateRaw, id.to_string()),
+            Some(("audit", id)) => (Endpoint::Audit, id.to_string()),
+            _ => return HttpResponse::error(StatusCode::NOT_FOUND, format!("unknown path {path}")),
+        };
+        if method != Method::POST {
+            return HttpResponse::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{method} is not allowed"),
+            );
+        }
+        // the lock is released before evaluating, a slow policy does not
+        // hold back the reloads
+        let Some(policy) = self.policies.read().await.get(&id).cloned() else {
+            return HttpResponse::error(StatusCode::NOT_FOUND, format!("unknown policy {id}"));
+        };
+        // the evaluation blocks the thread running it, like the policies
+        // waiting for the host capabilities
+        let evaluation = tokio::task::spawn_blocking(move || {
+            evaluate(&id, &policy, endpoint, &body, &self.metrics)
+        });
+        match evaluation.await {
+            Ok(Ok(body)) => HttpResponse::json(StatusCode::OK, &body),
+            Ok(Err(e)) => HttpResponse::error(StatusCode::BAD_REQUEST, e),
+            Err(e) => HttpResponse::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("the evaluation failed: {e}"),
+            ),
+        }
+    }
+}
+
+fn policy_id(policy_definition: &PolicyDefinition) -> &str {
+    match policy_definition {
+        PolicyDefinition::Policy { id, .. } | PolicyDefinition::PolicyGroup { id, .. } => id,
+    }
+}
+
+/// Serves the policies until interrupted. `load` reads the policies and the
+/// settings to pull them, when starting and every time the files of the
+/// policies change.
+pub(crate) async fn exec(
+    load: impl AsyncFn() -> Result<(Vec<PolicyDefinition>, PullAndRunSettings)>,
+    options: ServeOptions,
+) -> Result<()> {
+    let ServeOptions {
+        address,
+        config_path,
+        certificate,
+        webhook_host,
+    } = options;
+    let mut watched_files = WatchedFiles::new(&config_path);
+    let server = Arc::new(Server::default());
+    reload::reload(
+        &load,
+        &mut *server.policies.write().await,
+        &BTreeSet::new(),
+        &mut watched_files,
+    )
+    .await?;
+
+    let acceptor = certificate
+        .as_ref()
+        .map(TlsCertificate::acceptor)
+        .transpose()?;
+    let scheme = if acceptor.is_some() { "https" } else { "http" };
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|e| anyhow!("cannot listen on {}: {}", address, e))?;
+
+    {
+        let policies = server.policies.read().await;
+        if let Some(host) = webhook_host {
+            let certificate = certificate
+                .as_ref()
+                .ok_or_else(|| anyhow!("the webhook configurations require a certificate"))?;
+            // IPv6 addresses are bracketed inside of URLs
+            let host = match host.parse::<IpAddr>() {
+                Ok(IpAddr::V6(ip)) => format!("[{ip}]"),
+                _ => host,
+            };
+            print!(
+                "{}",
+                webhooks::render(
+                    &policies,
+                    &format!("https://{}:{}", host, address.port()),
+                    &certificate.ca_bundle()
+                )?
+            );
+        }
+        print_endpoints(&policies, scheme, address);
+    }
+
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => spawn_connection(stream, peer, acceptor.clone(), server.clone()),
+                Err(e) => warn!(error = e.to_string().as_str(), "cannot accept the connection"),
+            },
+            _ = poll.tick() => {
+                let changed = watched_files.changed();
+                if changed.is_empty() {
+                    continue;
+                }
+                // the evaluations in flight complete with the policies they
+                // started with, none of them is dropped
+                let mut policies = server.policies.write().await;
+                match reload::reload(&load, &mut policies, &changed, &mut watched_files).await {
+                    Ok(()) => print_endpoints(&policies, scheme, address),
+                    Err(e) => error!(error = e.to_string().as_str(), "cannot reload the policies, the previous ones are still served"),
+                }
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    server.policies.write().await.clear();
+    Ok(())
+}
+
+/// Lists the endpoints of the policies. The webhook configurations are
+/// printed to the standard output, to be redirected to a file, the endpoints
+/// to the standard error.
+fn print_endpoints(
+    policies: &BTreeMap<String, Arc<ServedPolicy>>,
+    scheme: &str,
+    address: SocketAddr,
+) {
+    eprintln!(
+        "Serving {} policies on {}://{}",
+        policies.len(),
+        scheme,
+        address
+    );
+    for (id, policy) in policies {
+        let endpoint = if policy.raw {
+            "validate_raw"
+        } else {
+            "validate"
+        };
+        eprintln!(
+            "  {}: {}://{}/{}/{}",
+            policy.definition, scheme, address, endpoint, id
+        );
+    }
+}
+
+/// Serves the connection inside of its own task
+fn spawn_connection(
+    stream: TcpStream,
+    peer: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    server: Arc<Server>,
+) {
+    tokio::spawn(async move {
+        let result = match acceptor {
+            None => serve_http(stream, peer, server).await,
+            Some(acceptor) => {
+                match tokio::time::timeout(READ_TIMEOUT, acceptor.accept(stream)).await {
+                    Err(_) => Err(anyhow!("timed out during the TLS handshake")),
+                    Ok(Err(e)) => Err(anyhow!("TLS handshake failed: {}", e)),
+                    Ok(Ok(stream)) => serve_http(stream, peer, server).await,
+                }
+            }
+        };
+        if let Err(e) = result {
+            warn!(
+                peer = peer.to_string().as_str(),
+                error = e.to_string().as_str(),
+                "cannot serve the connection"
+            );
+        }
+    });
+}
+
+async fn serve_http(
+    io: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    peer: SocketAddr,
+    server: Arc<Server>,
+) -> Result<()> {
+    let service = service_fn(move |request| {
+        let server = server.clone();
+        async move { Ok::<_, Infallible>(server.handle(request, peer).await) }
+    });
+    http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(READ_TIMEOUT)
+        .serve_connection(TokioIo::new(io), service)
+        .await
+        .map_err(|e| anyhow!("{}", e))
+}
+
+/// Reads the body of the request. The bodies bigger than the limit, or not
+/// sent in time, are refused with the response returned as error.
+async fn read_body<B>(body: B) -> Result<Bytes, HttpResponse>
+where
+    B: hyper::body::Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    match tokio::time::timeout(READ_TIMEOUT, Limited::new(body, MAX_BODY_SIZE).collect()).await {
+        Err(_) => Err(HttpResponse::error(
+            StatusCode::REQUEST_TIMEOUT,
+            "timed out reading the body",
+        )),
+        Ok(Err(e)) if e.is::<LengthLimitError>() => Err(HttpResponse::error(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("the body is bigger than {} bytes", MAX_BODY_SIZE),
+        )),
+        Ok(Err(e)) => Err(HttpResponse::error(StatusCode::BAD_REQUEST, e)),
+        Ok(Ok(collected)) => Ok(collected.to_bytes()),
+    }
+}
+
+/// Evaluates the request, returning the AdmissionReview, or the raw
+/// response, to send back. Blocks until the evaluations of the policy in
+/// flight are done.
+fn evaluate(
+    id: &str,
+    policy: &ServedPolicy,
+    endpoint: Endpoint,
+    body: &[u8],
+    metrics: &Mutex<EvaluationMetrics>,
+) -> Result<Value> {
+    let body: Value =
+        serde_json::from_slice(body).map_err(|e| anyhow!("invalid JSON body: {}", e))?;
+    let request_field = |pointer: &str| {
+        body.pointer(pointer)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let resource_kind = request_field("/request/kind/kind");
+    let resource_namespace = request_field("/request/namespace");
+    let resource_request_operation = request_field("/request/operation");
+    let request = match (endpoint, policy.raw) {
+        (Endpoint::ValidateRaw, true) => ValidateRequest::Raw(
+            body.get("request")
+                .cloned()
+                .ok_or_else(|| anyhow!("the body has no request"))?,
+        ),
+        (Endpoint::ValidateRaw, false) => {
+            return Err(anyhow!("policy {} is not a raw policy", id));
+        }
+        (_, true) => {
+            return Err(anyhow!(
+                "policy {} is a raw policy, send its requests to /validate_raw/{}",
+                id,
+                id
+            ));
+        }
+        (_, false) => build_validate_request(&body, false)?,
+    };
+
+    let (response, latency) = {
+        let mut evaluator = policy.evaluator.lock().unwrap();
+        evaluator.set_request(request);
+        let start = Instant::now();
+        let response = evaluator.evaluate();
+        (response, start.elapsed())
+    };
+    let response = match endpoint {
+        Endpoint::Audit => response,
+        Endpoint::Validate | Endpoint::ValidateRaw => AdmissionResponseHandler::new(
+            &policy.definition.get_policy_id()?,
+            &policy.definition.get_policy_mode(),
+            policy.definition.get_policy_allowed_to_mutate(),
+            policy.definition.get_policy_custom_rejection_message(),
+        )
+        .process_response(response),
+    };
+    info!(
+        policy = id,
+        kind = resource_kind.as_str(),
+        namespace = resource_namespace.as_str(),
+        operation = resource_request_operation.as_str(),
+        allowed = response.allowed,
+        mutated = response.patch.is_some(),
+        latency_ms = latency.as_millis() as u64,
+        "request evaluated"
+    );
+    metrics.lock().unwrap().record(
+        Evaluation {
+            policy_name: id.to_string(),
+            policy_mode: match policy.definition.get_policy_mode() {
+                PolicyMode::Protect => "protect",
+                PolicyMode::Monitor => "monitor",
+            },
+            resource_kind,
+            resource_namespace,
+            resource_request_operation,
+            accepted: response.allowed,
+            mutated: response.patch.is_some(),
+            request_origin: match endpoint {
+                Endpoint::Audit => "audit",
+                Endpoint::Validate | Endpoint::ValidateRaw => "validate",
+            },
+            error_code: response.status.as_ref().and_then(|status| status.code),
+        },
+        latency,
+    );
+
+    Ok(match endpoint {
+        Endpoint::ValidateRaw => json!({"response": response}),
+        Endpoint::Validate | Endpoint::Audit => json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "response": response,
+        }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serves the connections accepted on a random port, without policies
+    async fn start_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Arc::new(Server::default());
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = listener.accept().await.unwrap();
+                spawn_connection(stream, peer, None, server.clone());
+            }
+        });
+        address
+    }
+
+    async fn send(address: SocketAddr, request: &str) -> String {
+        let mut client = TcpStream::connect(address).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        response
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn idle_connections_do_not_block_the_others() {
+        let address = start_server().await;
+        let _idle = TcpStream::connect(address).await.unwrap();
+
+        let response = send(
+            address,
+            "GET /readiness HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+    }
+
+    #[rstest]
+    #[case::chunked_body(
+        "POST /validate/privileged-pods?timeout=10s HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n2\r\n{}\r\n0\r\n\r\n",
+        "HTTP/1.1 404 Not Found"
+    )]
+    #[case::header_without_colon(
+        "GET /readiness HTTP/1.1\r\nHost: localhost\r\nbogus\r\nConnection: close\r\n\r\n",
+        "HTTP/1.1 400 Bad Request"
+    )]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn requests(#[case] request: &str, #[case] status_line: &str) {
+        let address = start_server().await;
+        let response = send(address, request).await;
+        assert!(response.starts_with(status_line), "{response}");
+    }
+
+    #[tokio::test]
+    async fn bodies_bigger_than_the_limit() {
+        let body = Full::new(Bytes::from(vec![b' '; MAX_BODY_SIZE + 1]));
+        let Err(response) = read_body(body).await else {
+            panic!("the body has been accepted");
+        };
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn responses() {
+        let response: Response<Full<Bytes>> =
+            HttpResponse::error(StatusCode::NOT_FOUND, "unknown policy foo").into();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    }
+
+    #[rstest]
+    #[case::unknown_path(Method::POST, "/mutate/foo", StatusCode::NOT_FOUND)]
+    #[case::unknown_policy(Method::POST, "/validate/foo", StatusCode::NOT_FOUND)]
+    #[case::wrong_method(Method::GET, "/validate/foo", StatusCode::METHOD_NOT_ALLOWED)]
+    #[tokio::test]
+    async fn routes(#[case] method: Method, #[case] path: &str, #[case] status: StatusCode) {
+        let response = Arc::new(Server::default())
+            .route(&method, path, Bytes::new())
+            .await;
+        assert_eq!(response.status, status);
+    }
+}
@@ -0,0 +1,151 @@
+//! Metrics of the evaluations of `kwctl serve`, served by `/metrics`.
+//!
+//! The metrics have the names and the labels of the ones of policy-server,
+//! so that the dashboards built for policy-server show the load tests run
+//! against kwctl too.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::metrics::Metrics;
+
+/// The histogram of the latencies of the evaluations
+pub(super) const LATENCY_METRIC: &str = "kubewarden_policy_evaluation_latency_milliseconds";
+
+/// The upper bounds of the buckets of the latencies, in milliseconds
+const LATENCY_BUCKETS: &[f64] = &[
+    5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 2500.0, 5000.0, 7500.0,
+    10000.0,
+];
+
+/// The labels of an evaluation
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Evaluation {
+    pub(super) policy_name: String,
+    pub(super) policy_mode: &'static str,
+    pub(super) resource_kind: String,
+    pub(super) resource_namespace: String,
+    pub(super) resource_request_operation: String,
+    pub(super) accepted: bool,
+    pub(super) mutated: bool,
+    /// `validate` or `audit`, like the endpoints of policy-server
+    pub(super) request_origin: &'static str,
+    pub(super) error_code: Option<u16>,
+}
+
+/// The latencies of the evaluations, in milliseconds
+struct Latencies {
+    /// The cumulative counts of the buckets
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+pub(super) struct EvaluationMetrics {
+    evaluations: BTreeMap<Evaluation, Latencies>,
+}
+
+impl EvaluationMetrics {
+    pub(super) fn record(&mut self, evaluation: Evaluation, latency: Duration) {
+        let latency = latency.as_secs_f64() * 1000.0;
+        let latencies = self
+            .evaluations
+            .entry(evaluation)
+            .or_insert_with(|| Latencies {
+                buckets: vec![0; LATENCY_BUCKETS.len()],
+                sum: 0.0,
+                count: 0,
+            });
+        for (bucket, bound) in latencies.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if latency <= *bound {
+                *bucket += 1;
+            }
+        }
+        latencies.sum += latency;
+        latencies.count += 1;
+    }
+
+    pub(super) fn render(&self) -> String {
+        let mut metrics = Metrics::default();
+        for (evaluation, latencies) in &self.evaluations {
+            let accepted = evaluation.accepted.to_string();
+            let mutated = evaluation.mutated.to_string();
+            let error_code = evaluation.error_code.map(|code| code.to_string());
+            let mut labels = vec![
+                ("policy_name", evaluation.policy_name.as_str()),
+                ("policy_mode", evaluation.policy_mode),
+                ("resource_kind", evaluation.resource_kind.as_str()),
+                ("resource_namespace", evaluation.resource_namespace.as_str()),
+                (
+                    "resource_request_operation",
+                    evaluation.resource_request_operation.as_str(),
+                ),
+                ("accepted", accepted.as_str()),
+                ("mutated", mutated.as_str()),
+                ("request_origin", evaluation.request_origin),
+            ];
+            if let Some(error_code) = &error_code {
+                labels.push(("error_code", error_code.as_str()));
+            }
+
+            metrics.counter(
+                "kubewarden_policy_evaluations",
+                "Evaluations of the policies",
+                &labels,
+                latencies.count as f64,
+            );
+            let buckets: Vec<(f64, u64)> = LATENCY_BUCKETS
+                .iter()
+                .copied()
+                .zip(latencies.buckets.iter().copied())
+                .collect();
+            metrics.histogram(
+                LATENCY_METRIC,
+                "Latencies of the evaluations of the policies",
+                &labels,
+                &buckets,
+                latencies.sum,
+                latencies.count,
+            );
+        }
+        metrics.render()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluation(accepted: bool) -> Evaluation {
+        Evaluation {
+            policy_name: "privileged-pods".to_string(),
+            policy_mode: "protect",
+            resource_kind: "Pod".to_string(),
+            resource_namespace: "default".to_string(),
+            resource_request_operation: "CREATE".to_string(),
+            accepted,
+            mutated: false,
+            request_origin: "validate",
+            error_code: None,
+        }
+    }
+
+    #[test]
+    fn evaluations() {
+        let mut metrics = EvaluationMetrics::default();
+        metrics.record(evaluation(true), Duration::from_millis(3));
+        metrics.record(evaluation(true), Duration::from_millis(20));
+        metrics.record(evaluation(false), Duration::from_millis(20));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            r#"kubewarden_policy_evaluations_total{accepted="true",mutated="false",policy_mode="protect",policy_name="privileged-pods",request_origin="validate",resource_kind="Pod",resource_namespace="default",resource_request_operation="CREATE"} 2"#
+        ));
+        assert!(rendered.contains(
+            r#"kubewarden_policy_evaluation_latency_milliseconds_bucket{accepted="true",le="10",mutated="false",policy_mode="protect",policy_name="privileged-pods",request_origin="validate",resource_kind="Pod",resource_namespace="default",resource_request_operation="CREATE"} 1"#
+        ));
+        assert!(rendered.contains(
+            r#"kubewarden_policy_evaluation_latency_milliseconds_count{accepted="false",mutated="false",policy_mode="protect",policy_name="privileged-pods",request_origin="validate",resource_kind="Pod",resource_namespace="default",resource_request_operation="CREATE"} 1"#
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Reload of the policies of `kwctl serve`, when their files change.
+//!
+//! The `policies.yml` file and the modules referenced via `file://` URIs are
+//! polled for changes. The policies whose entries, or modules, changed are
+//! built again, the other ones keep being served by their evaluators. When a
+//! policy cannot be built, like when its settings are not valid, none of the
+//! changes is applied.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::Result;
+use tracing::info;
+use url::Url;
+
+use super::{placeholder_request, policy_id, ServedPolicy};
+use crate::{
+    command::run::local_data::LocalData,
+    config::{policy_definition::PolicyDefinition, pull_and_run::PullAndRunSettings},
+};
+
+/// The watched files, with their modification times. The files that do not
+/// exist have none.
+pub(super) struct WatchedFiles {
+    files: BTreeMap<PathBuf, Option<SystemTime>>,
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl WatchedFiles {
+    pub(super) fn new(config_path: &Path) -> Self {
+        let mut watched_files = WatchedFiles {
+            files: BTreeMap::new(),
+        };
+        watched_files.watch(config_path);
+        watched_files
+    }
+
+    fn watch(&mut self, path: &Path) {
+        self.files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| modification_time(path));
+    }
+
+    /// The files modified, created or removed since the last check
+    pub(super) fn changed(&mut self) -> BTreeSet<PathBuf> {
+        let mut changed = BTreeSet::new();
+        for (path, modified) in self.files.iter_mut() {
+            let current = modification_time(path);
+            if current != *modified {
+                *modified = current;
+                changed.insert(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+/// The local modules of the policy, or of its members
+fn local_files(definition: &PolicyDefinition) -> Vec<PathBuf> {
+    definition
+        .uris()
+        .iter()
+        .filter_map(|uri| {
+            Url::parse(uri)
+                .ok()
+                .filter(|url| url.scheme() == "file")
+                .and_then(|url| url.to_file_path().ok())
+        })
+        .collect()
+}
+
+/// Loads the policies again, building the ones that are new, whose entries
+/// changed or whose local modules are among the changed files
+pub(super) async fn reload(
+    load: &impl AsyncFn() -> Result<(Vec<PolicyDefinition>, PullAndRunSettings)>,
+    policies: &mut BTreeMap<String, Arc<ServedPolicy>>,
+    changed: &BTreeSet<PathBuf>,
+    watched_files: &mut WatchedFiles,
+) -> Result<()> {
+    let (definitions, pull_settings) = load().await?;
+    let pull_settings = PullAndRunSettings {
+        request: placeholder_request(),
+        ..pull_settings
+    };
+    for path in definitions.iter().flat_map(local_files) {
+        watched_files.watch(&path);
+    }
+
+    let (unchanged, updated): (Vec<PolicyDefinition>, Vec<PolicyDefinition>) =
+        definitions.into_iter().partition(|definition| {
+            policies
+                .get(policy_id(definition))
+                .is_some_and(|policy| policy.definition == *definition)
+                && !local_files(definition)
+                    .iter()
+                    .any(|path| changed.contains(path))
+        });
+
+    let local_data = LocalData::new(&updated, &pull_settings).await?;
+    let mut built = BTreeMap::new();
+    for definition in updated {
+        let id = policy_id(&definition).to_string();
+        // on failure, the policies already built are dropped
+        let policy = ServedPolicy::new(definition, &pull_settings, &local_data).await?;
+        built.insert(id, Arc::new(policy));
+    }
+
+    let mut previous = std::mem::take(policies);
+    for definition in unchanged {
+        let id = policy_id(&definition);
+        if let Some(policy) = previous.remove(id) {
+            policies.insert(id.to_string(), policy);
+        }
+    }
+    for (id, policy) in built {
+        if previous.contains_key(&id) {
+            info!(policy = id.as_str(), "policy reloaded");
+        }
+        policies.insert(id, policy);
+    }
+    // the policies replaced, or removed from the configuration, are dropped
+    // once their evaluations in flight are done
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("policies.yml");
+        let module_path = dir.path().join("policy.wasm");
+        fs::write(&config_path, "{}").unwrap();
+
+        let mut watched_files = WatchedFiles::new(&config_path);
+        watched_files.watch(&module_path);
+        assert!(watched_files.changed().is_empty());
+
+        fs::write(&module_path, "\0asm").unwrap();
+        assert_eq!(
+            watched_files.changed(),
+            BTreeSet::from([module_path.clone()])
+        );
+        assert!(watched_files.changed().is_empty());
+
+        fs::remove_file(&config_path).unwrap();
+        assert_eq!(watched_files.changed(), BTreeSet::from([config_path]));
+    }
+
+    #[test]
+    fn local_modules() {
+        let config = tempfile::NamedTempFile::with_suffix(".yml").unwrap();
+        fs::write(
+            config.path(),
+            r#"
+privileged-pods:
+  module: file:///tmp/policy.wasm
+safe-labels:
+  module: registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13
+"#,
+        )
+        .unwrap();
+        let definitions =
+            PolicyDefinition::from_policy_server_config(config.path().to_str().unwrap()).unwrap();
+        assert_eq!(
+            local_files(&definitions[0]),
+            vec![PathBuf::from("/tmp/policy.wasm")]
+        );
+        assert!(local_files(&definitions[1]).is_empty());
+    }
+}
@@ -0,0 +1,265 @@
+//! Latencies of the evaluations of a running `kwctl serve`, printed by
+//! `kwctl daemon stats`, or `kwctl serve stats`.
+//!
+//! The latency histograms are read from the `/metrics` endpoint of the
+//! server and summed by policy and by shape of the requests, their kind and
+//! operation, so that the slow ones stand out during long testing sessions.
+
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use anyhow::{anyhow, Result};
+use prettytable::{format, row, Table};
+
+use super::metrics::LATENCY_METRIC;
+
+/// The policy, the kind and the operation of the evaluated requests
+type Shape = (String, String, String);
+
+/// The latencies of the evaluations of a shape, in milliseconds
+#[derive(Debug, Default)]
+struct Latencies {
+    /// The upper bounds of the buckets, sorted, with their cumulative
+    /// counts. The `+Inf` bucket is left out, it holds all the evaluations.
+    buckets: Vec<(f64, u64)>,
+    sum: f64,
+    count: u64,
+}
+
+impl Latencies {
+    fn add_bucket(&mut self, bound: f64, count: u64) {
+        match self.buckets.iter_mut().find(|(b, _)| *b == bound) {
+            Some((_, bucket_count)) => *bucket_count += count,
+            None => self.buckets.push((bound, count)),
+        }
+    }
+
+    fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+
+    /// The upper bound of the bucket holding the quantile, `None` when it is
+    /// beyond the last bucket
+    fn quantile(&self, quantile: f64) -> Option<f64> {
+        let rank = (quantile * self.count as f64).ceil() as u64;
+        self.buckets
+            .iter()
+            .find(|(_, count)| *count >= rank)
+            .map(|(bound, _)| *bound)
+    }
+
+    fn render_quantile(&self, quantile: f64) -> String {
+        match (self.quantile(quantile), self.buckets.last()) {
+            (Some(bound), _) => format!("<= {bound} ms"),
+            (None, Some((last, _))) => format!("> {last} ms"),
+            (None, None) => "-".to_string(),
+        }
+    }
+}
+
+/// A sample of the OpenMetrics text format
+struct Sample<'a> {
+    name: &'a str,
+    labels: BTreeMap<&'a str, String>,
+    value: f64,
+}
+
+/// Parses the line of a sample, like `name{label="value"} 42`
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(['{', ' '])?;
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+    let mut labels = BTreeMap::new();
+    if let Some(mut inner) = rest.strip_prefix('{') {
+        loop {
+            inner = inner.trim_start_matches(',');
+            if let Some(after) = inner.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, quoted) = inner.split_once("=\"")?;
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (index, '"') => break index,
+                    (_, '\\') => match chars.next()?.1 {
+                        'n' => value.push('\n'),
+                        escaped => value.push(escaped),
+                    },
+                    (_, c) => value.push(c),
+                }
+            };
+            labels.insert(label, value);
+            inner = &quoted[end + 1..];
+        }
+    }
+    let value = rest.split_whitespace().next()?.parse().ok()?;
+    Some(Sample {
+        name,
+        labels,
+        value,
+    })
+}
+
+/// Sums the latency histograms of the metrics by shape of the evaluations
+fn latencies(metrics: &str) -> Result<BTreeMap<Shape, Latencies>> {
+    let mut latencies: BTreeMap<Shape, Latencies> = BTreeMap::new();
+    for line in metrics
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    {
+        let sample =
+            parse_sample(line).ok_or_else(|| anyhow!("cannot parse the metric: {}", line))?;
+        let Some(suffix) = sample.name.strip_prefix(LATENCY_METRIC) else {
+            continue;
+        };
+        let label = |name: &str| sample.labels.get(name).cloned().unwrap_or_default();
+        let shape = (
+            label("policy_name"),
+            label("resource_kind"),
+            label("resource_request_operation"),
+        );
+        let shape_latencies = latencies.entry(shape).or_default();
+        match suffix {
+            "_bucket" => {
+                let bound: f64 = sample
+                    .labels
+                    .get("le")
+                    .and_then(|bound| bound.parse().ok())
+                    .ok_or_else(|| anyhow!("bucket without a valid upper bound: {}", line))?;
+                if bound.is_finite() {
+                    shape_latencies.add_bucket(bound, sample.value as u64);
+                }
+            }
+            "_sum" => shape_latencies.sum += sample.value,
+            "_count" => shape_latencies.count += sample.value as u64,
+            _ => {}
+        }
+    }
+    for shape_latencies in latencies.values_mut() {
+        shape_latencies
+            .buckets
+            .sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    }
+    Ok(latencies)
+}
+
+/// Prints the latencies of the evaluations of the server at `url`, the
+/// slowest shapes first. `insecure` skips the verification of the
+/// certificate, like the self-signed one of `--generate-certs`.
+pub(crate) async fn exec(url: &str, insecure: bool) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(insecure)
+        .build()
+        .map_err(|e| anyhow!("cannot create the HTTP client: {}", e))?;
+    let metrics_url = format!("{}/metrics", url.trim_end_matches('/'));
+    let metrics = client
+        .get(&metrics_url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| anyhow!("cannot read the metrics of {}: {}", metrics_url, e))?
+        .text()
+        .await
+        .map_err(|e| anyhow!("cannot read the metrics of {}: {}", metrics_url, e))?;
+
+    let mut latencies: Vec<(Shape, Latencies)> = latencies(&metrics)?.into_iter().collect();
+    if latencies.is_empty() {
+        println!("No evaluations yet");
+        return Ok(());
+    }
+    latencies.sort_by(|(_, a), (_, b)| b.mean().partial_cmp(&a.mean()).unwrap_or(Ordering::Equal));
+
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row![
+        "Policy",
+        "Kind",
+        "Operation",
+        "Evaluations",
+        "Mean",
+        "p50",
+        "p90",
+        "p99"
+    ]);
+    for ((policy, kind, operation), shape_latencies) in &latencies {
+        table.add_row(row![
+            policy,
+            kind,
+            operation,
+            shape_latencies.count,
+            format!("{:.1} ms", shape_latencies.mean()),
+            shape_latencies.render_quantile(0.5),
+            shape_latencies.render_quantile(0.9),
+            shape_latencies.render_quantile(0.99),
+        ]);
+    }
+    table.printstd();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::command::serve::metrics::{Evaluation, EvaluationMetrics};
+
+    fn evaluation(policy_name: &str, accepted: bool) -> Evaluation {
+        Evaluation {
+            policy_name: policy_name.to_string(),
+            policy_mode: "protect",
+            resource_kind: "Pod".to_string(),
+            resource_namespace: "default".to_string(),
+            resource_request_operation: "CREATE".to_string(),
+            accepted,
+            mutated: false,
+            request_origin: "validate",
+            error_code: None,
+        }
+    }
+
+    #[test]
+    fn latencies_of_the_served_metrics() {
+        let mut metrics = EvaluationMetrics::default();
+        for millis in [3, 8, 20, 40] {
+            metrics.record(
+                evaluation("privileged-pods", millis < 30),
+                Duration::from_millis(millis),
+            );
+        }
+        metrics.record(evaluation("verify-images", true), Duration::from_secs(12));
+
+        let latencies = latencies(&metrics.render()).unwrap();
+
+        let pods = &latencies[&(
+            "privileged-pods".to_string(),
+            "Pod".to_string(),
+            "CREATE".to_string(),
+        )];
+        assert_eq!(pods.count, 4);
+        assert!((pods.mean() - 17.75).abs() < 1e-9);
+        assert_eq!(pods.render_quantile(0.5), "<= 10 ms");
+        assert_eq!(pods.render_quantile(0.99), "<= 50 ms");
+        let images = &latencies[&(
+            "verify-images".to_string(),
+            "Pod".to_string(),
+            "CREATE".to_string(),
+        )];
+        assert_eq!(images.render_quantile(0.5), "> 10000 ms");
+    }
+
+    #[test]
+    fn escaped_labels() {
+        let sample =
+            parse_sample(r#"metric{path="C:\\policies",message="\"odd\"\n"} 1.5"#).unwrap();
+        assert_eq!(sample.name, "metric");
+        assert_eq!(sample.labels["path"], r"C:\policies");
+        assert_eq!(sample.labels["message"], "\"odd\"\n");
+        assert_eq!(sample.value, 1.5);
+    }
+}
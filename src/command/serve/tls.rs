@@ -0,0 +1,137 @@
+//! Certificates of the HTTPS server of `kwctl serve`.
+
+use std::{fs, sync::Arc};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use rustls_pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer, PrivateSec1KeyDer,
+};
+use tokio_rustls::{rustls, TlsAcceptor};
+
+/// The certificate served by kwctl, together with its key
+pub(crate) struct TlsCertificate {
+    /// The certificate chain, PEM encoded
+    chain_pem: String,
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl TlsCertificate {
+    /// Reads the PEM encoded certificate chain and private key
+    pub(crate) fn read(cert_file: &str, key_file: &str) -> Result<Self> {
+        let chain_pem = fs::read_to_string(cert_file)
+            .map_err(|e| anyhow!("cannot read certificate {}: {}", cert_file, e))?;
+        let chain = pem::parse_many(&chain_pem)
+            .map_err(|e| anyhow!("cannot parse certificate {}: {}", cert_file, e))?
+            .into_iter()
+            .filter(|pem| pem.tag() == "CERTIFICATE")
+            .map(|pem| CertificateDer::from(pem.into_contents()))
+            .collect::<Vec<_>>();
+        if chain.is_empty() {
+            return Err(anyhow!("{} contains no certificate", cert_file));
+        }
+
+        let key = pem::parse(
+            fs::read(key_file).map_err(|e| anyhow!("cannot read key {}: {}", key_file, e))?,
+        )
+        .map_err(|e| anyhow!("cannot parse key {}: {}", key_file, e))?;
+        let key = match key.tag() {
+            "PRIVATE KEY" => PrivatePkcs8KeyDer::from(key.into_contents()).into(),
+            "EC PRIVATE KEY" => PrivateSec1KeyDer::from(key.into_contents()).into(),
+            "RSA PRIVATE KEY" => PrivatePkcs1KeyDer::from(key.into_contents()).into(),
+            tag => return Err(anyhow!("{}: unsupported key type {}", key_file, tag)),
+        };
+
+        Ok(TlsCertificate {
+            chain_pem,
+            chain,
+            key,
+        })
+    }
+
+    /// Generates a self-signed certificate, valid for the host names and the
+    /// IP addresses
+    pub(crate) fn generate(hosts: &[String]) -> Result<Self> {
+        let certified_key = rcgen::generate_simple_self_signed(hosts.to_vec())
+            .map_err(|e| anyhow!("cannot generate the certificate: {}", e))?;
+        Ok(TlsCertificate {
+            chain_pem: certified_key.cert.pem(),
+            chain: vec![certified_key.cert.der().clone()],
+            key: PrivatePkcs8KeyDer::from(certified_key.key_pair.serialize_der()).into(),
+        })
+    }
+
+    /// The `caBundle` trusting the certificate, for the webhook
+    /// configurations. Self-signed certificates are their own authority.
+    pub(crate) fn ca_bundle(&self) -> String {
+        STANDARD.encode(&self.chain_pem)
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor> {
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(self.chain.clone(), self.key.clone_key())
+        .map_err(|e| anyhow!("cannot use the certificate: {}", e))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::extensions::GeneralName;
+
+    #[test]
+    fn generated_certificate() {
+        let certificate = TlsCertificate::generate(&[
+            "host.docker.internal".to_string(),
+            "127.0.0.1".to_string(),
+        ])
+        .unwrap();
+        assert!(certificate.acceptor().is_ok());
+
+        let chain_pem =
+            String::from_utf8(STANDARD.decode(certificate.ca_bundle()).unwrap()).unwrap();
+        let pem = pem::parse(chain_pem).unwrap();
+        let (_, cert) = x509_parser::parse_x509_certificate(pem.contents()).unwrap();
+        let names = &cert
+            .subject_alternative_name()
+            .unwrap()
+            .unwrap()
+            .value
+            .general_names;
+        assert!(matches!(
+            names.as_slice(),
+            [
+                GeneralName::DNSName("host.docker.internal"),
+                GeneralName::IPAddress([127, 0, 0, 1]),
+            ]
+        ));
+    }
+
+    #[test]
+    fn certificate_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let certificate = TlsCertificate::generate(&["localhost".to_string()]).unwrap();
+        let cert_file = dir.path().join("tls.crt");
+        let key_file = dir.path().join("tls.key");
+        fs::write(&cert_file, &certificate.chain_pem).unwrap();
+        fs::write(
+            &key_file,
+            pem::encode(&pem::Pem::new(
+                "PRIVATE KEY",
+                certificate.key.secret_der().to_vec(),
+            )),
+        )
+        .unwrap();
+
+        let read =
+            TlsCertificate::read(cert_file.to_str().unwrap(), key_file.to_str().unwrap()).unwrap();
+        assert_eq!(read.chain, certificate.chain);
+        assert!(read.acceptor().is_ok());
+    }
+}
@@ -0,0 +1,139 @@
+//! Webhook configurations registering `kwctl serve` with a cluster.
+//!
+//! Every policy gets a webhook calling its `/validate` endpoint, inside of
+//! the MutatingWebhookConfiguration when it is allowed to mutate and of the
+//! ValidatingWebhookConfiguration otherwise. The webhooks match the rules of
+//! the metadata of the policies, like the Custom Resources scaffolded by
+//! kwctl do.
+//!
+//! The webhooks ignore the failures of kwctl, so that stopping it does not
+//! lock the cluster, and skip the `kube-system` namespace.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use anyhow::Result;
+use policy_evaluator::policy_metadata::Rule;
+use serde_json::{json, Value};
+
+use super::ServedPolicy;
+
+/// The name of the webhook configurations
+const NAME: &str = "kwctl-serve";
+
+/// Matches the requests evaluated by the policies without rules
+fn catch_all_rule() -> Value {
+    json!({
+        "apiGroups": ["*"],
+        "apiVersions": ["*"],
+        "resources": ["*"],
+        "operations": ["CREATE", "UPDATE"],
+    })
+}
+
+fn webhook(id: &str, rules: &[Rule], base_url: &str, ca_bundle: &str) -> Result<Value> {
+    let rules = if rules.is_empty() {
+        vec![catch_all_rule()]
+    } else {
+        rules
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()?
+    };
+    Ok(json!({
+        "name": format!("{id}.{NAME}.kubewarden.io"),
+        "clientConfig": {
+            "url": format!("{base_url}/validate/{id}"),
+            "caBundle": ca_bundle,
+        },
+        "rules": rules,
+        "namespaceSelector": {
+            "matchExpressions": [{
+                "key": "kubernetes.io/metadata.name",
+                "operator": "NotIn",
+                "values": ["kube-system"],
+            }],
+        },
+        "failurePolicy": "Ignore",
+        "sideEffects": "None",
+        "admissionReviewVersions": ["v1"],
+        "timeoutSeconds": 10,
+    }))
+}
+
+/// The webhook configurations of the policies, as a stream of YAML
+/// documents. Raw policies are not called by Kubernetes, they are skipped.
+pub(super) fn render(
+    policies: &BTreeMap<String, Arc<ServedPolicy>>,
+    base_url: &str,
+    ca_bundle: &str,
+) -> Result<String> {
+    let mut validating = Vec::new();
+    let mut mutating = Vec::new();
+    for (id, policy) in policies.iter().filter(|(_, policy)| !policy.raw) {
+        let webhook = webhook(id, &policy.rules, base_url, ca_bundle)?;
+        if policy.definition.get_policy_allowed_to_mutate() {
+            mutating.push(webhook);
+        } else {
+            validating.push(webhook);
+        }
+    }
+
+    let mut output = String::new();
+    for (kind, webhooks) in [
+        ("ValidatingWebhookConfiguration", validating),
+        ("MutatingWebhookConfiguration", mutating),
+    ] {
+        if webhooks.is_empty() {
+            continue;
+        }
+        let document = json!({
+            "apiVersion": "admissionregistration.k8s.io/v1",
+            "kind": kind,
+            "metadata": {
+                "name": NAME,
+                "labels": {"app.kubernetes.io/managed-by": "kwctl"},
+            },
+            "webhooks": webhooks,
+        });
+        output.push_str("---\n");
+        output.push_str(&serde_yaml::to_string(&document)?);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhooks_of_the_rules() {
+        let rule: Rule = serde_json::from_value(json!({
+            "apiGroups": [""],
+            "apiVersions": ["v1"],
+            "resources": ["pods"],
+            "operations": ["CREATE"],
+        }))
+        .unwrap();
+        let webhook = webhook(
+            "privileged-pods",
+            &[rule],
+            "https://host.docker.internal:3000",
+            "Y2E=",
+        )
+        .unwrap();
+        assert_eq!(webhook["name"], "privileged-pods.kwctl-serve.kubewarden.io");
+        assert_eq!(
+            webhook["clientConfig"]["url"],
+            "https://host.docker.internal:3000/validate/privileged-pods"
+        );
+        assert_eq!(webhook["clientConfig"]["caBundle"], "Y2E=");
+        assert_eq!(webhook["rules"][0]["resources"], json!(["pods"]));
+        assert_eq!(webhook["failurePolicy"], "Ignore");
+    }
+
+    #[test]
+    fn webhooks_without_rules() {
+        let webhook = webhook("safe-pods", &[], "https://127.0.0.1:3000", "Y2E=").unwrap();
+        assert_eq!(webhook["rules"], json!([catch_all_rule()]));
+    }
+}
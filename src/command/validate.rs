@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    evaluation_context::EvaluationContext,
+    kubewarden_policy_sdk::settings::SettingsValidationResponse,
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_group_evaluator::evaluator::PolicyGroupEvaluator,
+};
+
+use crate::{
+    backend::BackendDetector,
+    command::run::{local_data::LocalData, policy_execution_mode::determine_execution_mode},
+    config::{
+        policy_definition::{PolicyDefinition, PolicyExecutionConfiguration},
+        pull_and_run::PullAndRunSettings,
+    },
+};
+
+/// Checks the Kubewarden Custom Resources without evaluating any request:
+/// all the policies, including the members of the policy groups, are pulled
+/// (and verified, when requested) and their settings are validated.
+///
+/// The expressions of the policy groups are checked when the Custom Resources
+/// are parsed.
+pub(crate) async fn exec(
+    policy_definitions: &[PolicyDefinition],
+    pull_settings: &PullAndRunSettings,
+) -> Result<()> {
+    let local_data = LocalData::new(policy_definitions, pull_settings).await?;
+
+    let mut invalid = 0;
+    for policy_definition in policy_definitions {
+        let response = validate_settings(policy_definition, pull_settings, &local_data)?;
+        if response.valid {
+            println!("{policy_definition}: valid");
+        } else {
+            invalid += 1;
+            println!(
+                "{policy_definition}: invalid settings: {}",
+                response.message.unwrap_or_default()
+            );
+        }
+    }
+
+    if invalid > 0 {
+        return Err(anyhow!(
+            "{} out of {} policies are not valid",
+            invalid,
+            policy_definitions.len()
+        ));
+    }
+    Ok(())
+}
+
+fn validate_settings(
+    policy_definition: &PolicyDefinition,
+    pull_settings: &PullAndRunSettings,
+    local_data: &LocalData,
+) -> Result<SettingsValidationResponse> {
+    match policy_definition {
+        PolicyDefinition::Policy {
+            uri,
+            user_execution_cfg,
+            settings,
+            ..
+        } => {
+            let wasm_path = local_data.local_path(uri)?;
+            let execution_mode = match user_execution_cfg {
+                PolicyExecutionConfiguration::UserDefined(mode) => mode.to_owned(),
+                PolicyExecutionConfiguration::PolicyDefined => determine_execution_mode(
+                    local_data.metadata(uri),
+                    None,
+                    BackendDetector::default(),
+                    wasm_path,
+                )?,
+            };
+
+            let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+                .policy_file(wasm_path)?
+                .execution_mode(execution_mode);
+            if pull_settings.enable_wasmtime_cache {
+                policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
+            }
+            // the settings are validated without access to the host
+            // capabilities
+            let eval_ctx = EvaluationContext {
+                policy_id: uri.to_owned(),
+                callback_channel: None,
+                ctx_aware_resources_allow_list: BTreeSet::new(),
+            };
+            let mut policy_evaluator =
+                policy_evaluator_builder.build_pre()?.rehydrate(&eval_ctx)?;
+
+            Ok(policy_evaluator.validate_settings(settings))
+        }
+        PolicyDefinition::PolicyGroup {
+            id,
+            policy_members,
+            expression,
+            message,
+            ..
+        } => {
+            let mut policy_group_evaluator =
+                PolicyGroupEvaluator::new(id, message, expression, None);
+
+            for (member_id, member) in policy_members {
+                let mut policy_evaluator_builder = PolicyEvaluatorBuilder::new()
+                    .policy_file(local_data.local_path(&member.uri)?)?;
+                if pull_settings.enable_wasmtime_cache {
+                    policy_evaluator_builder = policy_evaluator_builder.enable_wasmtime_cache();
+                }
+
+                policy_group_evaluator.add_policy_member(
+                    member_id,
+                    Arc::new(policy_evaluator_builder.build_pre()?),
+                    member.settings.clone(),
+                );
+            }
+
+            Ok(policy_group_evaluator.validate_settings())
+        }
+    }
+}
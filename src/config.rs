@@ -1,6 +1,13 @@
+pub(crate) mod ca_certs;
+pub(crate) mod certificate_identity;
+pub(crate) mod credential_provider;
+pub(crate) mod fulcio_chain;
 pub(crate) mod policy_definition;
+pub(crate) mod proxy;
 pub(crate) mod pull_and_run;
+pub(crate) mod registry_auth;
 pub(crate) mod sources;
+pub(crate) mod strict;
 pub(crate) mod verification;
 
 #[derive(Default)]
@@ -0,0 +1,100 @@
+use std::{env, fs, io::Write, path::Path};
+
+use anyhow::{anyhow, Result};
+use pem::Pem;
+use tempfile::NamedTempFile;
+use tracing::{debug, warn};
+
+const SSL_CERT_FILE_ENV_VAR: &str = "SSL_CERT_FILE";
+
+/// Adds the given CA certificates to the trust store used by all the TLS
+/// connections: registries, https:// servers and Sigstore services.
+///
+/// kwctl trusts the certificates of the platform trust store, which can be
+/// replaced via the `SSL_CERT_FILE` environment variable. A bundle made of
+/// the platform certificates plus the given ones is written to a temporary
+/// file and exported via `SSL_CERT_FILE`, which must happen before any other
+/// thread is started. The file is removed when the returned value
+/// is dropped, hence it must be kept alive until kwctl exits.
+pub(crate) fn add_ca_certificates<P: AsRef<Path>>(paths: &[P]) -> Result<NamedTempFile> {
+    let native_certs = rustls_native_certs::load_native_certs();
+    for error in &native_certs.errors {
+        warn!(%error, "cannot load certificate from the platform trust store");
+    }
+    let mut bundle: Vec<Pem> = native_certs
+        .certs
+        .iter()
+        .map(|cert| Pem::new("CERTIFICATE", cert.as_ref()))
+        .collect();
+
+    for path in paths {
+        bundle.extend(read_ca_certificates(path.as_ref())?);
+    }
+
+    let mut bundle_file = tempfile::Builder::new()
+        .prefix("kwctl-ca-bundle-")
+        .suffix(".pem")
+        .tempfile()?;
+    bundle_file.write_all(pem::encode_many(&bundle).as_bytes())?;
+    bundle_file.flush()?;
+
+    debug!(
+        certificates = bundle.len(),
+        path = %bundle_file.path().display(),
+        "using custom CA bundle"
+    );
+    unsafe {
+        env::set_var(SSL_CERT_FILE_ENV_VAR, bundle_file.path());
+    }
+
+    Ok(bundle_file)
+}
+
+fn read_ca_certificates(path: &Path) -> Result<Vec<Pem>> {
+    let contents = fs::read(path)
+        .map_err(|e| anyhow!("cannot read CA certificate {}: {}", path.display(), e))?;
+    let certs: Vec<Pem> = pem::parse_many(contents)
+        .map_err(|e| anyhow!("cannot parse CA certificate {}: {}", path.display(), e))?
+        .into_iter()
+        .filter(|pem| pem.tag() == "CERTIFICATE")
+        .collect();
+    if certs.is_empty() {
+        return Err(anyhow!(
+            "no PEM encoded certificate found inside of {}",
+            path.display()
+        ));
+    }
+
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::single_certificate(
+        &[Pem::new("CERTIFICATE", vec![1, 2, 3])],
+        Some(1)
+    )]
+    #[case::bundle_with_private_key(
+        &[
+            Pem::new("CERTIFICATE", vec![1, 2, 3]),
+            Pem::new("PRIVATE KEY", vec![4, 5, 6]),
+            Pem::new("CERTIFICATE", vec![7, 8, 9]),
+        ],
+        Some(2)
+    )]
+    #[case::no_certificate(&[Pem::new("PUBLIC KEY", vec![1, 2, 3])], None)]
+    fn read_ca_certificates_from_file(#[case] contents: &[Pem], #[case] expected: Option<usize>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.pem");
+        fs::write(&path, pem::encode_many(contents)).unwrap();
+
+        assert_eq!(
+            read_ca_certificates(&path).ok().map(|certs| certs.len()),
+            expected
+        );
+    }
+}
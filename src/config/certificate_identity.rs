@@ -0,0 +1,387 @@
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::sigstore::cosign::{
+    signature_layers::{CertificateSubject, SignatureLayer},
+    verification_constraint::{AnnotationVerifier, VerificationConstraint},
+};
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::verify::VerificationAnnotations;
+
+/// Expected value of a field of a Fulcio certificate
+#[derive(Debug)]
+pub(crate) enum Matcher {
+    Equal(String),
+    UrlPrefix(String),
+    /// The original expression, and its compiled version anchored to the
+    /// start and the end of the value
+    Regexp(String, Regex),
+}
+
+impl Matcher {
+    pub(crate) fn regexp(pattern: &str) -> Result<Self> {
+        let regex = Regex::new(&format!("^(?:{pattern})$"))
+            .map_err(|e| anyhow!("invalid regular expression {}: {}", pattern, e))?;
+        Ok(Self::Regexp(pattern.to_string(), regex))
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Self::Equal(expected) => value == expected,
+            Self::UrlPrefix(prefix) => {
+                // same as policy-fetcher: `https://github.com/kubewarden` must
+                // not match `https://github.com/kubewarden-fake`
+                let prefix = if prefix.ends_with('/') {
+                    prefix.to_owned()
+                } else {
+                    format!("{prefix}/")
+                };
+                value.starts_with(&prefix)
+            }
+            Self::Regexp(_, regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equal(expected) => write!(f, "equal to '{expected}'"),
+            Self::UrlPrefix(prefix) => write!(f, "starting with '{prefix}'"),
+            Self::Regexp(pattern, _) => write!(f, "matching '{pattern}'"),
+        }
+    }
+}
+
+/// A keyless signature whose certificate identity, or OIDC issuer, is
+/// matched via a regular expression.
+///
+/// policy-fetcher only supports exact and URL prefix matches, these
+/// signatures are verified by kwctl instead.
+#[derive(Debug)]
+pub(crate) struct CertificateIdentity {
+    pub(crate) issuer: Matcher,
+    pub(crate) subject: Matcher,
+    pub(crate) annotations: Option<VerificationAnnotations>,
+}
+
+impl CertificateIdentity {
+    /// Returns whether the certificate and the annotations of the given
+    /// trusted signature satisfy the requirements
+    pub(crate) fn is_satisfied_by(&self, layer: &SignatureLayer) -> bool {
+        let Some(certificate) = &layer.certificate_signature else {
+            return false;
+        };
+        let subject = match &certificate.subject {
+            CertificateSubject::Email(email) => email,
+            CertificateSubject::Uri(uri) => uri,
+        };
+        let issuer_matches = certificate
+            .issuer
+            .as_deref()
+            .is_some_and(|issuer| self.issuer.matches(issuer));
+        let annotations_match = self.annotations.as_ref().is_none_or(|annotations| {
+            AnnotationVerifier {
+                annotations: annotations.to_owned(),
+            }
+            .verify(layer)
+            .unwrap_or(false)
+        });
+
+        issuer_matches && self.subject.matches(subject) && annotations_match
+    }
+
+    /// The `genericIssuer` signature of the verification config describing
+    /// this identity, the opposite of `extract_certificate_identities`
+    pub(crate) fn to_signature(&self) -> Value {
+        let mut signature = Mapping::new();
+        signature.insert("kind".into(), "genericIssuer".into());
+        match &self.issuer {
+            Matcher::Regexp(pattern, _) => {
+                signature.insert("issuerRegexp".into(), pattern.as_str().into())
+            }
+            Matcher::Equal(issuer) | Matcher::UrlPrefix(issuer) => {
+                signature.insert("issuer".into(), issuer.as_str().into())
+            }
+        };
+        let (kind, subject) = match &self.subject {
+            Matcher::Equal(subject) => ("equal", subject),
+            Matcher::UrlPrefix(prefix) => ("urlPrefix", prefix),
+            Matcher::Regexp(pattern, _) => ("regexp", pattern),
+        };
+        signature.insert(
+            "subject".into(),
+            Value::Mapping(Mapping::from_iter([(kind.into(), subject.as_str().into())])),
+        );
+        if let Some(annotations) = &self.annotations {
+            signature.insert(
+                "annotations".into(),
+                Value::Mapping(
+                    annotations
+                        .iter()
+                        .map(|(key, value)| (key.as_str().into(), value.as_str().into()))
+                        .collect(),
+                ),
+            );
+        }
+        Value::Mapping(signature)
+    }
+}
+
+impl fmt::Display for CertificateIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "issuer {}, subject {}", self.issuer, self.subject)?;
+        if let Some(annotations) = &self.annotations {
+            write!(f, ", annotations {annotations:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// `genericIssuer` signature of the verification config, extended with the
+/// `issuerRegexp` field and the `regexp` subject
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GenericIssuerSignature {
+    #[allow(dead_code)]
+    kind: String,
+    issuer: Option<String>,
+    issuer_regexp: Option<String>,
+    subject: Subject,
+    annotations: Option<VerificationAnnotations>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+enum Subject {
+    Equal(String),
+    UrlPrefix(String),
+    Regexp(String),
+}
+
+impl TryFrom<GenericIssuerSignature> for CertificateIdentity {
+    type Error = anyhow::Error;
+
+    fn try_from(signature: GenericIssuerSignature) -> Result<Self> {
+        let issuer = match (signature.issuer, signature.issuer_regexp) {
+            (Some(issuer), None) => Matcher::Equal(issuer),
+            (None, Some(pattern)) => Matcher::regexp(&pattern)?,
+            _ => {
+                return Err(anyhow!(
+                    "genericIssuer signatures must have either an issuer or an issuerRegexp"
+                ))
+            }
+        };
+        let subject = match signature.subject {
+            Subject::Equal(subject) => Matcher::Equal(subject),
+            Subject::UrlPrefix(prefix) => Matcher::UrlPrefix(prefix),
+            Subject::Regexp(pattern) => Matcher::regexp(&pattern)?,
+        };
+
+        Ok(Self {
+            issuer,
+            subject,
+            annotations: signature.annotations,
+        })
+    }
+}
+
+fn uses_regexp(signature: &Value) -> bool {
+    signature.get("kind").and_then(Value::as_str) == Some("genericIssuer")
+        && (signature.get("issuerRegexp").is_some()
+            || signature
+                .get("subject")
+                .and_then(|subject| subject.get("regexp"))
+                .is_some())
+}
+
+/// Removes the signatures relying on regular expressions from the
+/// verification config document, which can then be parsed by policy-fetcher.
+///
+/// These signatures are supported only inside of `allOf`.
+pub(crate) fn extract_certificate_identities(
+    document: &mut Value,
+) -> Result<Vec<CertificateIdentity>> {
+    let any_of_uses_regexp = document
+        .get("anyOf")
+        .and_then(|any_of| any_of.get("signatures"))
+        .and_then(Value::as_sequence)
+        .is_some_and(|signatures| signatures.iter().any(uses_regexp));
+    if any_of_uses_regexp {
+        return Err(anyhow!(
+            "signatures using regular expressions are supported only inside of allOf"
+        ));
+    }
+
+    let Some(Value::Sequence(all_of)) = document.get_mut("allOf") else {
+        return Ok(vec![]);
+    };
+    let (regexp_signatures, signatures): (Vec<Value>, Vec<Value>) =
+        all_of.drain(..).partition(uses_regexp);
+    *all_of = signatures;
+    if all_of.is_empty() {
+        if let Value::Mapping(mapping) = document {
+            mapping.remove("allOf");
+        }
+    }
+
+    regexp_signatures
+        .into_iter()
+        .map(|signature| {
+            let signature: GenericIssuerSignature = serde_yaml::from_value(signature)?;
+            signature.try_into()
+        })
+        .collect()
+}
+
+/// Returns whether the verification config document still has signatures
+/// once the ones relying on regular expressions have been extracted
+pub(crate) fn has_signatures(document: &Value) -> bool {
+    document
+        .as_mapping()
+        .is_some_and(|mapping| mapping.contains_key("allOf") || mapping.contains_key("anyOf"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::regexp(
+        Matcher::regexp(r"https://github\.com/kubewarden/policies/\.github/workflows/release\.yml@refs/tags/v.*").unwrap(),
+        "https://github.com/kubewarden/policies/.github/workflows/release.yml@refs/tags/v1.2.0",
+        true
+    )]
+    #[case::regexp_is_anchored(
+        Matcher::regexp(r"https://github\.com/kubewarden/.*").unwrap(),
+        "https://evil.example.com/?https://github.com/kubewarden/policies",
+        false
+    )]
+    #[case::regexp_alternatives_are_anchored(
+        Matcher::regexp("main|release").unwrap(),
+        "not-main",
+        false
+    )]
+    #[case::equal(
+        Matcher::Equal("https://token.actions.githubusercontent.com".to_string()),
+        "https://token.actions.githubusercontent.com",
+        true
+    )]
+    #[case::url_prefix(
+        Matcher::UrlPrefix("https://github.com/kubewarden".to_string()),
+        "https://github.com/kubewarden/policies",
+        true
+    )]
+    #[case::url_prefix_other_owner(
+        Matcher::UrlPrefix("https://github.com/kubewarden".to_string()),
+        "https://github.com/kubewarden-fake/policies",
+        false
+    )]
+    fn matcher(#[case] matcher: Matcher, #[case] value: &str, #[case] expected: bool) {
+        assert_eq!(matcher.matches(value), expected);
+    }
+
+    #[test]
+    fn invalid_regexp() {
+        assert!(Matcher::regexp("refs/tags/(v.*").is_err());
+    }
+
+    #[test]
+    fn regexp_signatures_are_extracted() {
+        let mut document: Value = serde_yaml::from_str(
+            r#"
+apiVersion: v1
+allOf:
+  - kind: githubAction
+    owner: kubewarden
+  - kind: genericIssuer
+    issuerRegexp: https://token\.actions\.githubusercontent\.com
+    subject:
+      regexp: https://github\.com/kubewarden/.*@refs/heads/.*
+    annotations:
+      env: prod
+"#,
+        )
+        .unwrap();
+
+        let identities = extract_certificate_identities(&mut document).unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert!(matches!(identities[0].issuer, Matcher::Regexp(..)));
+        assert!(matches!(identities[0].subject, Matcher::Regexp(..)));
+        assert_eq!(
+            identities[0].annotations,
+            Some(VerificationAnnotations::from([(
+                "env".to_string(),
+                "prod".to_string()
+            )]))
+        );
+        assert_eq!(document["allOf"].as_sequence().unwrap().len(), 1);
+        assert!(has_signatures(&document));
+    }
+
+    #[test]
+    fn identities_round_trip() {
+        let identity = CertificateIdentity {
+            issuer: Matcher::regexp(r"https://token\.actions\.githubusercontent\.com").unwrap(),
+            subject: Matcher::Equal("user@example.com".to_string()),
+            annotations: Some(VerificationAnnotations::from([(
+                "env".to_string(),
+                "prod".to_string(),
+            )])),
+        };
+        let mut document = Value::Mapping(Mapping::from_iter([(
+            "allOf".into(),
+            Value::Sequence(vec![identity.to_signature()]),
+        )]));
+
+        let identities = extract_certificate_identities(&mut document).unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].to_string(), identity.to_string());
+    }
+
+    #[test]
+    fn config_without_regexps_is_untouched() {
+        let contents = include_str!("../../tests/data/sigstore/verification-config-keyless.yml");
+        let mut document: Value = serde_yaml::from_str(contents).unwrap();
+
+        assert!(extract_certificate_identities(&mut document)
+            .unwrap()
+            .is_empty());
+        assert_eq!(document, serde_yaml::from_str::<Value>(contents).unwrap());
+    }
+
+    #[test]
+    fn only_regexp_signatures() {
+        let mut document: Value = serde_yaml::from_str(
+            "apiVersion: v1\nallOf:\n  - kind: genericIssuer\n    issuer: https://token.actions.githubusercontent.com\n    subject:\n      regexp: .*\n",
+        )
+        .unwrap();
+
+        let identities = extract_certificate_identities(&mut document).unwrap();
+
+        assert_eq!(identities.len(), 1);
+        assert!(matches!(identities[0].issuer, Matcher::Equal(_)));
+        assert!(!has_signatures(&document));
+    }
+
+    #[rstest]
+    #[case::any_of(
+        "apiVersion: v1\nanyOf:\n  signatures:\n    - kind: genericIssuer\n      issuerRegexp: .*\n      subject:\n        equal: user@example.com\n"
+    )]
+    #[case::issuer_and_issuer_regexp(
+        "apiVersion: v1\nallOf:\n  - kind: genericIssuer\n    issuer: https://accounts.google.com\n    issuerRegexp: .*\n    subject:\n      equal: user@example.com\n"
+    )]
+    #[case::invalid_regexp(
+        "apiVersion: v1\nallOf:\n  - kind: genericIssuer\n    issuer: https://accounts.google.com\n    subject:\n      regexp: (\n"
+    )]
+    fn invalid_regexp_signatures(#[case] contents: &str) {
+        let mut document: Value = serde_yaml::from_str(contents).unwrap();
+        assert!(extract_certificate_identities(&mut document).is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command, Output, Stdio},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::config::registry_auth::{RegistryCredentials, TOKEN_DEFAULT_USERNAME};
+
+const CREDENTIAL_REQUEST_API_VERSION: &str = "credentialprovider.kwctl.kubewarden.io/v1";
+
+/// Audience of the OIDC identity tokens requested for Sigstore
+const SIGSTORE_AUDIENCE: &str = "sigstore";
+
+/// Time given to a provider to answer when its `timeout` is not set
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a running provider is checked for termination
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Credential providers declared inside of the `credential_providers`
+/// section of the sources file:
+///
+/// ```yaml
+/// credential_providers:
+///   - registries:
+///       - registry.corp.example.com
+///       - "*.corp.example.com"
+///     command: /usr/local/bin/corp-registry-login
+///     args: ["--audience", "kwctl"]
+///     env:
+///       CORP_REALM: engineering
+///     timeout: 10
+///   - sigstore: true
+///     command: /usr/local/bin/corp-oidc-token
+/// ```
+///
+/// A credential provider is an executable invoked by kwctl to obtain the
+/// credentials of a registry, or the OIDC identity token used for keyless
+/// signing, similar to the exec credential plugins of kubectl. The first
+/// provider matching the registry, or serving Sigstore, is used.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct CredentialProviders(Vec<CredentialProvider>);
+
+#[derive(Clone, Debug, Deserialize)]
+struct CredentialProvider {
+    /// Registry hosts served by the provider. A leading `*.` matches all the
+    /// subdomains
+    #[serde(default)]
+    registries: Vec<String>,
+    /// Whether the provider returns the OIDC identity tokens used for
+    /// keyless signing
+    #[serde(default)]
+    sigstore: bool,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    /// Seconds given to the provider to answer, it's killed afterwards
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawSourcesCredentialProviders {
+    credential_providers: CredentialProviders,
+}
+
+/// Written by kwctl to the standard input of the provider. Registry
+/// credentials are requested with the `CredentialRequest` kind, the OIDC
+/// identity tokens for Sigstore with the `SigstoreCredentialRequest` one
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialRequest<'a> {
+    api_version: &'static str,
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registry: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audience: Option<&'static str>,
+}
+
+/// Written by the provider to its standard output. Either `password` or
+/// `token` must be set for the registries, `token` for Sigstore
+#[derive(Deserialize)]
+struct CredentialResponse {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+impl CredentialProviders {
+    pub(crate) fn from_sources_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read sources file {}: {}", path.display(), e))?;
+        let raw: RawSourcesCredentialProviders = serde_yaml::from_str(&contents).map_err(|e| {
+            anyhow!(
+                "cannot parse credential providers defined inside of {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        for provider in &raw.credential_providers.0 {
+            if provider.registries.is_empty() && !provider.sigstore {
+                return Err(anyhow!(
+                    "credential provider {} serves neither registries nor Sigstore",
+                    provider.command
+                ));
+            }
+            if provider.timeout == Some(0) {
+                return Err(anyhow!(
+                    "the timeout of credential provider {} must be greater than zero",
+                    provider.command
+                ));
+            }
+        }
+        Ok(raw.credential_providers)
+    }
+
+    /// Obtains the credentials of the registry from the first provider
+    /// serving it. `None` is returned when no provider serves the registry.
+    pub(crate) fn credentials(&self, registry: &str) -> Result<Option<RegistryCredentials>> {
+        self.0
+            .iter()
+            .find(|provider| provider.serves(registry))
+            .map(|provider| provider.run(registry))
+            .transpose()
+    }
+
+    /// Whether any of the providers obtains the credentials of registries
+    pub(crate) fn serve_registries(&self) -> bool {
+        self.0
+            .iter()
+            .any(|provider| !provider.registries.is_empty())
+    }
+
+    /// Obtains the OIDC identity token used for keyless signing from the
+    /// first provider serving Sigstore. `None` is returned when there's no
+    /// such provider.
+    pub(crate) fn identity_token(&self) -> Result<Option<String>> {
+        self.0
+            .iter()
+            .find(|provider| provider.sigstore)
+            .map(CredentialProvider::identity_token)
+            .transpose()
+    }
+}
+
+impl CredentialProvider {
+    fn serves(&self, registry: &str) -> bool {
+        self.registries
+            .iter()
+            .any(|pattern| match pattern.strip_prefix("*.") {
+                Some(domain) => registry
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.') && subdomain.len() > 1),
+                None => pattern == registry,
+            })
+    }
+
+    fn run(&self, registry: &str) -> Result<RegistryCredentials> {
+        debug!(
+            registry,
+            command = self.command.as_str(),
+            "invoking credential provider"
+        );
+        let response = self.exchange(&CredentialRequest {
+            api_version: CREDENTIAL_REQUEST_API_VERSION,
+            kind: "CredentialRequest",
+            registry: Some(registry),
+            audience: None,
+        })?;
+        match (response.password, response.token) {
+            (Some(password), _) => Ok(RegistryCredentials {
+                username: response.username.unwrap_or_default(),
+                password,
+            }),
+            (None, Some(token)) => Ok(RegistryCredentials {
+                username: response
+                    .username
+                    .unwrap_or_else(|| TOKEN_DEFAULT_USERNAME.to_string()),
+                password: token,
+            }),
+            (None, None) => Err(anyhow!(
+                "credential provider {} returned neither a password nor a token",
+                self.command
+            )),
+        }
+    }
+
+    fn identity_token(&self) -> Result<String> {
+        debug!(
+            command = self.command.as_str(),
+            "invoking Sigstore credential provider"
+        );
+        self.exchange(&CredentialRequest {
+            api_version: CREDENTIAL_REQUEST_API_VERSION,
+            kind: "SigstoreCredentialRequest",
+            registry: None,
+            audience: Some(SIGSTORE_AUDIENCE),
+        })?
+        .token
+        .ok_or_else(|| {
+            anyhow!(
+                "credential provider {} returned no identity token",
+                self.command
+            )
+        })
+    }
+
+    /// Sends the request to the provider and reads its response, killing the
+    /// provider when it doesn't answer in time
+    fn exchange(&self, request: &CredentialRequest) -> Result<CredentialResponse> {
+        let request = serde_json::to_vec(request)?;
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .envs(&self.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("cannot run credential provider {}: {}", self.command, e))?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&request)?;
+        let timeout = self.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        let output = wait_with_timeout(child, timeout)
+            .map_err(|e| anyhow!("credential provider {} failed: {}", self.command, e))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "credential provider {} failed ({}): {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            anyhow!(
+                "invalid response from credential provider {}: {}",
+                self.command,
+                e
+            )
+        })
+    }
+}
+
+/// Waits for the child to exit, killing it once the timeout expires. The
+/// output is read meanwhile, for the child not to block on full pipes
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> Result<Output> {
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // the readers terminate once the pipes of the killed child close
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("no answer within {} seconds", timeout.as_secs()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    let collect = |reader: JoinHandle<io::Result<Vec<u8>>>| {
+        reader
+            .join()
+            .map_err(|_| anyhow!("cannot read the output"))?
+            .map_err(|e| anyhow!("cannot read the output: {}", e))
+    };
+    Ok(Output {
+        status,
+        stdout: collect(stdout)?,
+        stderr: collect(stderr)?,
+    })
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut data)?;
+        }
+        Ok(data)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn provider(registries: &[&str], command: &str) -> CredentialProvider {
+        CredentialProvider {
+            registries: registries.iter().map(|r| r.to_string()).collect(),
+            sigstore: false,
+            command: command.to_string(),
+            args: vec![],
+            env: HashMap::new(),
+            timeout: None,
+        }
+    }
+
+    #[rstest]
+    #[case::exact_match("registry.corp.example.com", true)]
+    #[case::wildcard_match("eu.corp.example.com", true)]
+    #[case::wildcard_does_not_match_domain("corp.example.com", false)]
+    #[case::wildcard_does_not_match_suffix("evilcorp.example.com", false)]
+    #[case::other_registry("ghcr.io", false)]
+    fn provider_serves_registry(#[case] registry: &str, #[case] expected: bool) {
+        let provider = provider(&["registry.corp.example.com", "*.corp.example.com"], "true");
+        assert_eq!(provider.serves(registry), expected);
+    }
+
+    #[cfg(unix)]
+    fn script(dir: &Path, body: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let command = dir.join("provider");
+        fs::write(&command, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&command, fs::Permissions::from_mode(0o755)).unwrap();
+        command.to_str().unwrap().to_string()
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case::password(
+        r#"{"username": "user", "password": "pass"}"#,
+        Some(("user", "pass"))
+    )]
+    #[case::token(r#"{"token": "secret"}"#, Some((TOKEN_DEFAULT_USERNAME, "secret")))]
+    #[case::no_secret(r#"{"username": "user"}"#, None)]
+    #[case::invalid_json("not json", None)]
+    fn run_provider(#[case] response: &str, #[case] expected: Option<(&str, &str)>) {
+        let dir = tempfile::tempdir().unwrap();
+        let command = script(
+            dir.path(),
+            &format!("grep -q '\"registry\":\"ghcr.io\"' || exit 1\necho '{response}'"),
+        );
+
+        let providers = CredentialProviders(vec![provider(&["ghcr.io"], &command)]);
+        let credentials = providers.credentials("ghcr.io").ok().flatten();
+        assert_eq!(
+            credentials
+                .as_ref()
+                .map(|c| (c.username.as_str(), c.password.as_str())),
+            expected
+        );
+
+        assert!(providers.credentials("quay.io").unwrap().is_none());
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[case::token(r#"{"token": "eyJhbGciOiJSUzI1NiJ9.e30.signature"}"#, true)]
+    #[case::no_token(r#"{"username": "user", "password": "pass"}"#, false)]
+    fn sigstore_provider(#[case] response: &str, #[case] succeeds: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let command = script(
+            dir.path(),
+            &format!(
+                "grep -q '\"kind\":\"SigstoreCredentialRequest\",\"audience\":\"sigstore\"' || exit 1\necho '{response}'"
+            ),
+        );
+        let mut sigstore = provider(&[], &command);
+        sigstore.sigstore = true;
+
+        let token =
+            CredentialProviders(vec![provider(&["ghcr.io"], "false"), sigstore]).identity_token();
+        assert_eq!(token.is_ok(), succeeds);
+        if succeeds {
+            assert_eq!(
+                token.unwrap().as_deref(),
+                Some("eyJhbGciOiJSUzI1NiJ9.e30.signature")
+            );
+        }
+
+        assert!(CredentialProviders(vec![provider(&["ghcr.io"], "false")])
+            .identity_token()
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn provider_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let command = script(dir.path(), "sleep 10");
+        let mut provider = provider(&["ghcr.io"], &command);
+        provider.timeout = Some(1);
+
+        let started = Instant::now();
+        let error = provider.run("ghcr.io").unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(error.to_string().contains("no answer within 1 seconds"));
+    }
+
+    #[rstest]
+    #[case::valid(
+        "credential_providers:\n  - sigstore: true\n    command: token\n",
+        true
+    )]
+    #[case::nothing_served("credential_providers:\n  - command: token\n", false)]
+    #[case::zero_timeout(
+        "credential_providers:\n  - registries: [ghcr.io]\n    command: login\n    timeout: 0\n",
+        false
+    )]
+    fn sources_file(#[case] contents: &str, #[case] valid: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sources.yaml");
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(CredentialProviders::from_sources_file(&path).is_ok(), valid);
+    }
+}
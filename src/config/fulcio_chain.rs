@@ -0,0 +1,168 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use rustls_pki_types::CertificateDer;
+use tracing::{debug, warn};
+use x509_parser::{certificate::X509Certificate, parse_x509_certificate};
+
+// A chain longer than this is most likely a loop
+const MAX_CHAIN_LENGTH: usize = 8;
+
+/// Reads the Fulcio certificates given via `--fulcio-cert-path`. Each file can
+/// contain a single certificate, or a bundle made by the intermediate
+/// certificates of a private Fulcio instance and their root.
+///
+/// The certificates that are not self-signed must chain to one of the root
+/// certificates found across all the files. Because the leaf certificates
+/// issued by Fulcio are verified against all the returned certificates, the
+/// validated intermediates are returned together with the roots.
+pub(crate) fn read_fulcio_certs<P: AsRef<Path>>(
+    paths: &[P],
+) -> Result<Vec<CertificateDer<'static>>> {
+    let mut certs: Vec<Vec<u8>> = Vec::new();
+    for path in paths {
+        certs.extend(read_certs(path.as_ref())?);
+    }
+    validate_chains(&certs)?;
+
+    Ok(certs.into_iter().map(CertificateDer::from).collect())
+}
+
+/// Reads the PEM encoded certificates of `path`, or the single DER encoded
+/// certificate it contains
+fn read_certs(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let contents = fs::read(path)
+        .map_err(|e| anyhow!("cannot read Fulcio certificate {}: {}", path.display(), e))?;
+    let certs: Vec<Vec<u8>> = match pem::parse_many(&contents) {
+        Ok(pems) if !pems.is_empty() => pems
+            .into_iter()
+            .filter(|pem| pem.tag() == "CERTIFICATE")
+            .map(|pem| pem.into_contents())
+            .collect(),
+        _ => vec![contents],
+    };
+    for cert in &certs {
+        parse_x509_certificate(cert)
+            .map_err(|e| anyhow!("cannot parse Fulcio certificate {}: {}", path.display(), e))?;
+    }
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found inside of {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+fn is_self_signed(cert: &X509Certificate) -> bool {
+    cert.subject().as_raw() == cert.issuer().as_raw() && cert.verify_signature(None).is_ok()
+}
+
+fn name(cert: &X509Certificate) -> String {
+    cert.subject().to_string()
+}
+
+/// Ensures every certificate that is not self-signed chains to one of the
+/// self-signed certificates of `certs`. The issuers along the chain must be
+/// certificate authorities.
+fn validate_chains(certs: &[Vec<u8>]) -> Result<()> {
+    let parsed: Vec<X509Certificate> = certs
+        .iter()
+        .map(|cert| {
+            parse_x509_certificate(cert)
+                .map(|(_, cert)| cert)
+                .map_err(|e| anyhow!("cannot parse Fulcio certificate: {}", e))
+        })
+        .collect::<Result<_>>()?;
+
+    for cert in &parsed {
+        if !cert.validity().is_valid() {
+            warn!(
+                certificate = name(cert),
+                "Fulcio certificate is outside of its validity period"
+            );
+        }
+        if is_self_signed(cert) {
+            continue;
+        }
+        if !cert.is_ca() {
+            return Err(anyhow!(
+                "Fulcio certificate {} is not a certificate authority",
+                name(cert)
+            ));
+        }
+
+        let mut current = cert;
+        let mut chain = vec![name(cert)];
+        loop {
+            let issuer = parsed
+                .iter()
+                .filter(|candidate| candidate.is_ca())
+                .find(|candidate| {
+                    candidate.subject().as_raw() == current.issuer().as_raw()
+                        && current.verify_signature(Some(candidate.public_key())).is_ok()
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Fulcio certificate {} does not chain to any of the given root certificates: the issuer of {} is missing",
+                        name(cert),
+                        name(current)
+                    )
+                })?;
+            chain.push(name(issuer));
+            if is_self_signed(issuer) {
+                break;
+            }
+            if chain.len() > MAX_CHAIN_LENGTH {
+                return Err(anyhow!(
+                    "the chain of Fulcio certificate {} is too long",
+                    name(cert)
+                ));
+            }
+            current = issuer;
+        }
+        debug!(
+            chain = chain.join(" -> "),
+            "Fulcio certificate chain validated"
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn test_data(path: &str) -> String {
+        format!(
+            "{}/tests/data/sigstore/fulcio/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            path
+        )
+    }
+
+    #[rstest]
+    #[case::root(&["root.pem"], 1)]
+    #[case::bundle(&["chain.pem"], 2)]
+    #[case::chain_across_files(&["intermediate.pem", "root.pem"], 2)]
+    #[case::multiple_roots(&["chain.pem", "other-root.pem"], 3)]
+    fn valid_chains(#[case] files: &[&str], #[case] expected: usize) {
+        let paths: Vec<String> = files.iter().map(|file| test_data(file)).collect();
+        assert_eq!(read_fulcio_certs(&paths).unwrap().len(), expected);
+    }
+
+    #[rstest]
+    #[case::missing_root(&["intermediate.pem"], "does not chain")]
+    #[case::other_root(&["intermediate.pem", "other-root.pem"], "does not chain")]
+    #[case::leaf_certificate(&["leaf.pem", "chain.pem"], "not a certificate authority")]
+    fn invalid_chains(#[case] files: &[&str], #[case] error: &str) {
+        let paths: Vec<String> = files.iter().map(|file| test_data(file)).collect();
+        let result = read_fulcio_certs(&paths);
+        assert!(
+            result
+                .as_ref()
+                .is_err_and(|e| e.to_string().contains(error)),
+            "{result:?}"
+        );
+    }
+}
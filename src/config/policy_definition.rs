@@ -1,11 +1,12 @@
 use std::str::FromStr;
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt,
 };
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
+use itertools::Itertools;
 use k8s_openapi::api::core::v1::ObjectReference;
 use policy_evaluator::{
     admission_response_handler::{policy_id::PolicyID, policy_mode::PolicyMode},
@@ -16,7 +17,10 @@ use policy_evaluator::{
     policy_group_evaluator::PolicyGroupMemberSettings,
     policy_metadata::ContextAwareResource,
 };
+use regex::Regex;
 use serde::Deserialize;
+use serde_json::json;
+use tracing::warn;
 
 use crate::utils::new_policy_execution_mode_from_str;
 
@@ -227,12 +231,14 @@ impl TryFrom<ClusterAdmissionPolicyGroup> for PolicyDefinition {
         }
 
         let policy_mode = spec.mode.unwrap_or_default().into();
+        let id = cap_group
+            .metadata
+            .name
+            .unwrap_or_else(|| "crd-without-name".to_string());
+        check_group_expression(&id, &spec.expression, &policy_members)?;
 
         Ok(PolicyDefinition::PolicyGroup {
-            id: cap_group
-                .metadata
-                .name
-                .unwrap_or_else(|| "crd-without-name".to_string()),
+            id,
             policy_members,
             policy_mode,
             expression: spec.expression.clone(),
@@ -260,12 +266,14 @@ impl TryFrom<AdmissionPolicyGroup> for PolicyDefinition {
         }
 
         let policy_mode = spec.mode.unwrap_or_default().into();
+        let id = ap_group
+            .metadata
+            .name
+            .unwrap_or_else(|| "crd-without-name".to_string());
+        check_group_expression(&id, &spec.expression, &policy_members)?;
 
         Ok(PolicyDefinition::PolicyGroup {
-            id: ap_group
-                .metadata
-                .name
-                .unwrap_or_else(|| "crd-without-name".to_string()),
+            id,
             policy_members,
             policy_mode,
             expression: spec.expression.clone(),
@@ -274,6 +282,80 @@ impl TryFrom<AdmissionPolicyGroup> for PolicyDefinition {
     }
 }
 
+/// Checks that the expression of a policy group references only the members of
+/// the group.
+///
+/// Each member is exposed to the CEL expression as a function named after it.
+/// Calls to unknown members would otherwise be reported only when the group
+/// is evaluated, possibly only once it is deployed inside of the cluster.
+fn check_group_expression(
+    group: &str,
+    expression: &str,
+    policy_members: &HashMap<String, PolicyMember>,
+) -> Result<()> {
+    if policy_members.is_empty() {
+        return Err(anyhow!("policy group {} does not have any policy", group));
+    }
+    let identifier = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    if let Some(name) = policy_members
+        .keys()
+        .sorted()
+        .find(|name| !identifier.is_match(name))
+    {
+        return Err(anyhow!(
+            "policy group {}: '{}' cannot be referenced by the expression, the names of the policies must be valid CEL identifiers",
+            group,
+            name
+        ));
+    }
+
+    let called = called_functions(expression);
+    let unknown: Vec<&str> = called
+        .iter()
+        .filter(|name| !policy_members.contains_key(**name))
+        .copied()
+        .collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "the expression of policy group {} references unknown policies: {}. The policies of the group are: {}",
+            group,
+            unknown.join(", "),
+            policy_members.keys().sorted().join(", ")
+        ));
+    }
+    if called.is_empty() {
+        return Err(anyhow!(
+            "the expression of policy group {} does not reference any policy",
+            group
+        ));
+    }
+
+    for name in policy_members
+        .keys()
+        .sorted()
+        .filter(|name| !called.contains(name.as_str()))
+    {
+        warn!(
+            group,
+            policy = name.as_str(),
+            "policy is not referenced by the expression of the group, it is never evaluated"
+        );
+    }
+
+    Ok(())
+}
+
+/// Names of the functions called by a CEL expression, ignoring method calls
+/// like `object.startsWith()`
+fn called_functions(expression: &str) -> BTreeSet<&str> {
+    let call = Regex::new(r"\b([A-Za-z_]\w*)\s*\(").unwrap();
+    call.captures_iter(expression)
+        .filter_map(|captures| captures.get(1))
+        .filter(|name| !expression[..name.start()].trim_end().ends_with('.'))
+        .map(|name| name.as_str())
+        .collect()
+}
+
 impl PolicyDefinition {
     fn new(value: serde_yaml::Value) -> Result<PolicyDefinition> {
         let obj_ref: ObjectReference = serde_yaml::from_value(value.clone())
@@ -335,6 +417,55 @@ impl PolicyDefinition {
         Ok(policies)
     }
 
+    /// Reads the policies defined by a `policies.yml` file of policy-server,
+    /// sorted by their ids.
+    ///
+    /// Every entry is turned into the ClusterAdmissionPolicy, or the
+    /// ClusterAdmissionPolicyGroup, it would be generated from, so that they
+    /// are checked like the Custom Resources.
+    pub fn from_policy_server_config(yaml_path: &str) -> Result<Vec<PolicyDefinition>> {
+        let file = std::fs::File::open(yaml_path)
+            .map_err(|e| anyhow!("Cannot open YAML file {:?}: {}", yaml_path, e))?;
+        let entries: BTreeMap<String, serde_json::Value> = serde_yaml::from_reader(file)
+            .map_err(|e| anyhow!("Cannot parse YAML file {:?}: {}", yaml_path, e))?;
+
+        entries
+            .into_iter()
+            .map(|(id, entry)| {
+                let value = |key: &str, default: serde_json::Value| {
+                    entry.get(key).cloned().unwrap_or(default)
+                };
+                let mut spec = json!({
+                    "mode": value("policyMode", json!("protect")),
+                    "rules": [],
+                });
+                let kind = if entry.get("expression").is_some() {
+                    spec["policies"] = value("policies", json!({}));
+                    spec["expression"] = value("expression", json!(""));
+                    spec["message"] = value("message", json!(""));
+                    "ClusterAdmissionPolicyGroup"
+                } else {
+                    spec["module"] = value("module", json!(""));
+                    spec["settings"] = value("settings", json!({}));
+                    spec["mutating"] = value("allowedToMutate", json!(false));
+                    spec["contextAwareResources"] = value("contextAwareResources", json!([]));
+                    if let Some(message) = entry.get("message") {
+                        spec["message"] = message.clone();
+                    }
+                    "ClusterAdmissionPolicy"
+                };
+                let resource = json!({
+                    "apiVersion": "policies.kubewarden.io/v1",
+                    "kind": kind,
+                    "metadata": {"name": &id},
+                    "spec": spec,
+                });
+                PolicyDefinition::new(serde_yaml::to_value(resource)?)
+                    .map_err(|e| anyhow!("{}: policy {}: {}", yaml_path, id, e))
+            })
+            .collect()
+    }
+
     /// Creates a PolicyDefinition from CLI arguments.
     ///
     /// This will always create an individual PolicyDefinition
@@ -349,25 +480,7 @@ impl PolicyDefinition {
             .transpose()?
             .expect("uri_or_sha_prefix is guaranteed to be Some here");
 
-        let settings = if let Some(settings_path) = matches.get_one::<String>("settings-path") {
-            // 1st convert to json data
-            let json_value: serde_json::Value = serde_yaml::from_reader(
-                std::fs::File::open(settings_path)
-                    .map_err(|e| anyhow!("Cannot open settings file {}: {}", settings_path, e))?,
-            )
-            .map_err(|e| anyhow!("Cannot parse settings file {}: {}", settings_path, e))?;
-
-            // 2nd convert to PolicySettings, this makes sure we got a valid json object (only
-            // dictionaries and null are allowed)
-            PolicySettings::try_from(&json_value).map_err(anyhow::Error::msg)?
-        } else if let Some(json) = matches.get_one::<String>("settings-json") {
-            let json_value: serde_json::Value = serde_json::from_str(json)
-                .map_err(|e| anyhow!("Cannot parse settings JSON: {}", e))?;
-
-            PolicySettings::try_from(&json_value).map_err(anyhow::Error::msg)?
-        } else {
-            PolicySettings::default()
-        };
+        let settings = settings_from_cli(matches)?;
 
         let user_execution_cfg =
             if let Some(mode_name) = matches.get_one::<String>("execution-mode") {
@@ -407,6 +520,16 @@ impl PolicyDefinition {
         })
     }
 
+    /// Mutable access to the URIs of the policies, used to resolve them
+    pub(crate) fn uris_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            PolicyDefinition::Policy { uri, .. } => vec![uri],
+            PolicyDefinition::PolicyGroup { policy_members, .. } => {
+                policy_members.values_mut().map(|pm| &mut pm.uri).collect()
+            }
+        }
+    }
+
     pub(crate) fn uris(&self) -> HashSet<String> {
         match self {
             PolicyDefinition::Policy { uri, .. } => HashSet::from([uri.clone()]),
@@ -417,6 +540,30 @@ impl PolicyDefinition {
     }
 }
 
+/// Parses the settings of the policy given via `--settings-path` or
+/// `--settings-json`, the default settings are used when none is given
+pub(crate) fn settings_from_cli(matches: &ArgMatches) -> Result<PolicySettings> {
+    if let Some(settings_path) = matches.get_one::<String>("settings-path") {
+        // 1st convert to json data
+        let json_value: serde_json::Value = serde_yaml::from_reader(
+            std::fs::File::open(settings_path)
+                .map_err(|e| anyhow!("Cannot open settings file {}: {}", settings_path, e))?,
+        )
+        .map_err(|e| anyhow!("Cannot parse settings file {}: {}", settings_path, e))?;
+
+        // 2nd convert to PolicySettings, this makes sure we got a valid json object (only
+        // dictionaries and null are allowed)
+        PolicySettings::try_from(&json_value).map_err(anyhow::Error::msg)
+    } else if let Some(json) = matches.get_one::<String>("settings-json") {
+        let json_value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| anyhow!("Cannot parse settings JSON: {}", e))?;
+
+        PolicySettings::try_from(&json_value).map_err(anyhow::Error::msg)
+    } else {
+        Ok(PolicySettings::default())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -782,4 +929,120 @@ mod tests {
             _ => panic!("Expected Group PolicyDefinition"),
         }
     }
+
+    fn group_members(names: &[&str]) -> HashMap<String, PolicyMember> {
+        names
+            .iter()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    PolicyMember {
+                        uri: format!("registry://ghcr.io/kubewarden/policies/{name}:latest"),
+                        settings: PolicyGroupMemberSettings {
+                            settings: PolicySettings::try_from(&json!({})).unwrap(),
+                            ctx_aware_resources_allow_list: BTreeSet::new(),
+                        },
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[rstest::rstest]
+    #[case::all_members_referenced("signed_by_alice() && reject_latest()", &["signed_by_alice", "reject_latest"], true)]
+    #[case::nested("!(signed_by_alice() || (reject_latest()))", &["signed_by_alice", "reject_latest"], true)]
+    #[case::unused_member("signed_by_alice()", &["signed_by_alice", "reject_latest"], true)]
+    #[case::unknown_member("signed_by_bob() && reject_latest()", &["signed_by_alice", "reject_latest"], false)]
+    #[case::no_member_referenced("true", &["signed_by_alice"], false)]
+    #[case::no_members("true", &[], false)]
+    #[case::invalid_member_name("signed-by-alice()", &["signed-by-alice"], false)]
+    fn group_expression(#[case] expression: &str, #[case] members: &[&str], #[case] valid: bool) {
+        assert_eq!(
+            check_group_expression("group", expression, &group_members(members)).is_ok(),
+            valid
+        );
+    }
+
+    #[test]
+    fn called_functions_ignore_methods() {
+        assert_eq!(
+            called_functions("policy1() && object.name.startsWith(\"kube\") || policy2 ()"),
+            BTreeSet::from(["policy1", "policy2"])
+        );
+    }
+
+    #[test]
+    fn policy_definitions_from_policy_server_config() {
+        let config = tempfile::NamedTempFile::with_suffix(".yml").unwrap();
+        std::fs::write(
+            config.path(),
+            r#"
+privileged-pods:
+  module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+  policyMode: monitor
+  allowedToMutate: false
+safe-pods:
+  expression: privileged() && labels()
+  message: the pod is not safe
+  policies:
+    privileged:
+      module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+    labels:
+      module: registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13
+      settings:
+        denied_labels: [foo]
+"#,
+        )
+        .unwrap();
+
+        let policy_definitions =
+            PolicyDefinition::from_policy_server_config(config.path().to_str().unwrap()).unwrap();
+        assert_eq!(policy_definitions.len(), 2);
+        match &policy_definitions[0] {
+            PolicyDefinition::Policy {
+                id,
+                uri,
+                policy_mode,
+                ..
+            } => {
+                assert_eq!(id, "privileged-pods");
+                assert_eq!(
+                    uri,
+                    "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5"
+                );
+                assert_eq!(policy_mode, &PolicyMode::Monitor);
+            }
+            _ => panic!("Expected Individual PolicyDefinition"),
+        }
+        match &policy_definitions[1] {
+            PolicyDefinition::PolicyGroup {
+                id, policy_members, ..
+            } => {
+                assert_eq!(id, "safe-pods");
+                assert_eq!(policy_members.len(), 2);
+            }
+            _ => panic!("Expected Group PolicyDefinition"),
+        }
+    }
+
+    #[test]
+    fn policy_server_config_with_unknown_member() {
+        let config = tempfile::NamedTempFile::with_suffix(".yml").unwrap();
+        std::fs::write(
+            config.path(),
+            r#"
+safe-pods:
+  expression: missing()
+  message: the pod is not safe
+  policies:
+    privileged:
+      module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+"#,
+        )
+        .unwrap();
+
+        let error = PolicyDefinition::from_policy_server_config(config.path().to_str().unwrap())
+            .unwrap_err();
+        assert!(error.to_string().contains("policy safe-pods"));
+    }
 }
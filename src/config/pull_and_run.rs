@@ -9,17 +9,23 @@ use std::{
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
-use policy_evaluator::policy_fetcher::{
-    sigstore::trust::ManualTrustRoot, sources::Sources, verify::config::LatestVerificationConfig,
-};
+use policy_evaluator::policy_fetcher::{sigstore::trust::ManualTrustRoot, sources::Sources};
 use tracing::info;
 
 use crate::{
     callback_handler,
+    command::run::{
+        explain::{Explain, ExplainMode},
+        limits::EvaluationLimits,
+        mutation_schema::MutationSchemaSource,
+    },
     config::{
         policy_definition::PolicyDefinition,
-        sources::remote_server_options,
-        verification::{build_sigstore_trust_root, build_verification_options},
+        sources::{registry_mirrors, remote_server_options, RegistryMirrors},
+        strict,
+        verification::{
+            build_sigstore_trust_root, build_verification_options, VerificationOptions,
+        },
         HostCapabilitiesMode,
     },
     verify,
@@ -28,14 +34,35 @@ use crate::{
 #[derive(Default)]
 pub(crate) struct PullAndRunSettings {
     pub sources: Option<Sources>,
+    pub mirrors: RegistryMirrors,
     pub request: serde_json::Value,
     /// When verification is enabled, the map is populated with:
     /// - key: the policy URI
     /// - value: the digest of the verified manifest
     pub verified_manifest_digests: Option<HashMap<String, String>>,
+    /// Origin of the verification options, recorded into the provenance of
+    /// the verified policies
+    pub verification_origin: Option<String>,
     pub sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    /// When set, the policies must be verified and must have metadata
+    pub trusted_only: bool,
     pub enable_wasmtime_cache: bool,
     pub host_capabilities_mode: HostCapabilitiesMode,
+    /// When set, the objects mutated by the policies are validated against
+    /// the OpenAPI schema of their kind
+    pub mutation_schema_source: Option<MutationSchemaSource>,
+    /// When set, the trace of the evaluation of the Rego sources of the
+    /// policies is printed
+    pub explain: Option<Explain>,
+    /// When set, the policies are compiled with the engine enforcing the
+    /// limits, in place of the one of `enable_wasmtime_cache`
+    pub evaluation_limits: Option<EvaluationLimits>,
+    /// The kubeconfig file of the cluster serving the host capabilities,
+    /// the one of the environment is used when unset
+    pub kubeconfig: Option<PathBuf>,
+    /// When set, the settings are not validated against the settings JSON
+    /// Schema of the policies
+    pub lenient: bool,
 }
 
 pub(crate) fn parse_policy_definitions(matches: &ArgMatches) -> Result<Vec<PolicyDefinition>> {
@@ -62,6 +89,21 @@ pub(crate) fn parse_policy_definitions(matches: &ArgMatches) -> Result<Vec<Polic
     Ok(vec![PolicyDefinition::from_cli(matches)?])
 }
 
+/// Resolves the semantic version constraints used as tags by the URIs of the
+/// policies, see [`crate::version_constraints`]
+pub(crate) async fn resolve_version_constraints(
+    matches: &ArgMatches,
+    policy_definitions: &mut [PolicyDefinition],
+) -> Result<()> {
+    let sources = remote_server_options(matches)?;
+    for policy_definition in policy_definitions {
+        for uri in policy_definition.uris_mut() {
+            *uri = crate::version_constraints::resolve(uri, sources.as_ref()).await?;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) async fn parse_pull_and_run_settings(
     matches: &ArgMatches,
     policy_definitions: &[PolicyDefinition],
@@ -88,24 +130,7 @@ pub(crate) async fn parse_pull_and_run_settings(
     };
     let request = serde_json::from_str::<serde_json::Value>(&request_raw)?;
 
-    let sources = remote_server_options(matches)
-        .map_err(|e| anyhow!("Error getting remote server options: {}", e))?;
-    let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
-
-    let verified_manifest_digests =
-        if let Some(verification_options) = build_verification_options(matches)? {
-            Some(
-                build_verified_manifest_digests(
-                    policy_definitions,
-                    &verification_options,
-                    &sources,
-                    sigstore_trust_root.clone(),
-                )
-                .await?,
-            )
-        } else {
-            None
-        };
+    let pull_settings = parse_pull_settings(matches, policy_definitions).await?;
 
     let enable_wasmtime_cache = !matches
         .get_one::<bool>("disable-wasmtime-cache")
@@ -134,20 +159,128 @@ pub(crate) async fn parse_pull_and_run_settings(
             HostCapabilitiesMode::Proxy(callback_handler::ProxyMode::Replay { source });
     }
 
+    let mutation_schema_source = if matches
+        .get_one::<bool>("validate-mutation-schema")
+        .unwrap_or(&false)
+        .to_owned()
+    {
+        Some(
+            matches
+                .get_one::<String>("openapi-schema-path")
+                .map(|path| MutationSchemaSource::File(PathBuf::from(path)))
+                .unwrap_or(MutationSchemaSource::Cluster),
+        )
+    } else {
+        None
+    };
+
+    // only `run` explains the evaluations
+    let explain = matches
+        .try_get_one::<String>("explain")
+        .ok()
+        .flatten()
+        .map(|mode| -> Result<Explain> {
+            Ok(Explain {
+                mode: ExplainMode::try_from(mode.as_str())?,
+                rego_path: matches
+                    .get_one::<String>("rego-path")
+                    .map(PathBuf::from)
+                    .expect("rego-path is required by explain"),
+                query: matches.get_one::<String>("explain-query").cloned(),
+            })
+        })
+        .transpose()?;
+
     Ok(PullAndRunSettings {
-        sources,
         request,
-        verified_manifest_digests,
-        sigstore_trust_root,
         enable_wasmtime_cache,
         host_capabilities_mode,
+        mutation_schema_source,
+        explain,
+        ..pull_settings
+    })
+}
+
+/// Builds the settings required to pull, and verify, the policies. The
+/// settings related to the evaluation of a request are left to their
+/// defaults.
+pub(crate) async fn parse_pull_settings(
+    matches: &ArgMatches,
+    policy_definitions: &[PolicyDefinition],
+) -> Result<PullAndRunSettings> {
+    let sources = remote_server_options(matches)
+        .map_err(|e| anyhow!("Error getting remote server options: {}", e))?;
+    let mirrors = registry_mirrors(matches)?;
+    let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
+    let trusted_only = matches
+        .try_get_one::<bool>("trusted-only")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(false);
+    let verification_options = build_verification_options(matches)?;
+    if trusted_only {
+        ensure_trusted(policy_definitions, verification_options.is_some())?;
+    }
+
+    let verification_origin = verification_options
+        .as_ref()
+        .map(|verification_options| verification_options.origin.clone());
+    let verified_manifest_digests = if let Some(verification_options) = verification_options {
+        Some(
+            build_verified_manifest_digests(
+                policy_definitions,
+                &verification_options,
+                &sources,
+                &mirrors,
+                sigstore_trust_root.clone(),
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    Ok(PullAndRunSettings {
+        sources,
+        mirrors,
+        verified_manifest_digests,
+        verification_origin,
+        sigstore_trust_root,
+        trusted_only,
+        enable_wasmtime_cache: true,
+        lenient: strict::is_lenient(matches),
+        ..Default::default()
     })
 }
 
+/// Checks the requirements of `--trusted-only` that can be checked before
+/// pulling the policies: they must be verified, which is possible only for
+/// the ones distributed via registries
+fn ensure_trusted(policy_definitions: &[PolicyDefinition], verification: bool) -> Result<()> {
+    if !verification {
+        return Err(anyhow!(
+            "--trusted-only requires the policies to be verified, but no verification options were given and the default verification config file does not exist"
+        ));
+    }
+    if let Some(uri) = policy_definitions
+        .iter()
+        .flat_map(PolicyDefinition::uris)
+        .find(|uri| !uri.starts_with("registry://"))
+    {
+        return Err(anyhow!(
+            "policy {} cannot be verified, only policies distributed via registries can be run with --trusted-only",
+            uri
+        ));
+    }
+    Ok(())
+}
+
 async fn build_verified_manifest_digests(
     policy_definitions: &[PolicyDefinition],
-    verification_options: &LatestVerificationConfig,
+    verification_options: &VerificationOptions,
     sources: &Option<Sources>,
+    mirrors: &RegistryMirrors,
     sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
 ) -> Result<HashMap<String, String>> {
     let mut uris: HashSet<String> = HashSet::new();
@@ -163,6 +296,7 @@ async fn build_verified_manifest_digests(
         let verified_manifest_digest = verify::verify(
             uri.as_str(),
             sources.as_ref(),
+            mirrors,
             verification_options,
             sigstore_trust_root.clone(),
         )
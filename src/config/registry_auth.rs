@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use clap::ArgMatches;
+use docker_credential::{CredentialRetrievalError, DockerCredential};
+use policy_evaluator::policy_fetcher::{
+    oci_client::{secrets::RegistryAuth, Reference},
+    sigstore::registry::Auth,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::sources::{credential_providers, DOCKER_CONFIG_ENV_VAR},
+    utils::find_in_path,
+};
+
+// Username sent together with the token when `--registry-username` is not provided
+pub(crate) const TOKEN_DEFAULT_USERNAME: &str = "kwctl";
+
+static DOCKER_CONFIG: OnceLock<DockerConfig> = OnceLock::new();
+
+/// The Docker configuration directories, selected before the async runtime is
+/// started
+struct DockerConfig {
+    /// Directory of the user's configuration, set via
+    /// `--docker-config-json-path` or `$DOCKER_CONFIG`
+    user_dir: Option<PathBuf>,
+    /// Private directory the registry credentials are exported to, if any
+    exported_dir: Option<PathBuf>,
+}
+
+/// The credential helpers configured inside of the Docker `config.json` file.
+///
+/// Only the keys relevant to the helpers are read, everything else is ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct CredentialHelpers {
+    /// Helper used for all the registries that don't have a dedicated one
+    creds_store: Option<String>,
+    /// Registry host -> helper name
+    cred_helpers: HashMap<String, String>,
+}
+
+impl CredentialHelpers {
+    pub(crate) fn from_docker_config(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read docker config {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("cannot parse docker config {}: {}", path.display(), e))
+    }
+
+    /// Names of all the helpers referenced by the configuration, sorted and
+    /// without duplicates
+    fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .cred_helpers
+            .values()
+            .chain(self.creds_store.iter())
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Warns about the helpers that cannot be found inside of `$PATH`.
+    ///
+    /// A missing helper makes the credential lookup fail, which would
+    /// otherwise surface only as an authentication error returned by the
+    /// registry.
+    pub(crate) fn warn_missing_binaries(&self) {
+        for name in self.names() {
+            let binary = helper_binary_name(name);
+            match find_in_path(&binary) {
+                Some(path) => debug!(helper = name, path = %path.display(), "found docker credential helper"),
+                None => warn!(
+                    "Docker credential helper '{}' is configured, but the '{}' binary cannot be found inside of $PATH. Registry authentication is going to fail for the registries using it.",
+                    name, binary
+                ),
+            }
+        }
+    }
+}
+
+fn helper_binary_name(name: &str) -> String {
+    let binary = format!("docker-credential-{name}");
+    if cfg!(windows) {
+        format!("{binary}.exe")
+    } else {
+        binary
+    }
+}
+
+/// Returns the credentials to be used when interacting with the registry
+/// hosting `image`.
+///
+/// The credentials are looked up inside of the Docker `config.json` file:
+/// the `credHelpers` entry for the registry is used first, then the static
+/// `auths` entries and finally the `credsStore` helper. Helpers are invoked by
+/// running the `docker-credential-<name>` binary, as done by the docker CLI.
+///
+/// Anonymous access is used when no credentials are configured for the
+/// registry, while a failure of the credential helper is reported as an error.
+pub(crate) fn registry_auth(image: &str) -> Result<RegistryAuth> {
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let server = reference.resolve_registry();
+
+    match docker_credential::get_credential(server) {
+        Ok(credential) => Ok(into_registry_auth(server, credential)),
+        Err(CredentialRetrievalError::ConfigNotFound)
+        | Err(CredentialRetrievalError::NoCredentialConfigured) => {
+            debug!(
+                registry = server,
+                "no credentials configured, using anonymous access"
+            );
+            Ok(RegistryAuth::Anonymous)
+        }
+        Err(e) => Err(anyhow!(
+            "cannot retrieve the credentials of registry {}: {}",
+            server,
+            e
+        )),
+    }
+}
+
+/// Same as [`registry_auth`], but returns the credentials in the format
+/// expected by the sigstore client.
+pub(crate) fn sigstore_auth(image: &str) -> Result<Auth> {
+    Ok(match registry_auth(image)? {
+        RegistryAuth::Anonymous => Auth::Anonymous,
+        RegistryAuth::Basic(username, password) => Auth::Basic(username, password),
+        RegistryAuth::Bearer(token) => Auth::Bearer(token),
+    })
+}
+
+fn into_registry_auth(server: &str, credential: DockerCredential) -> RegistryAuth {
+    match credential {
+        DockerCredential::UsernamePassword(username, password) => {
+            RegistryAuth::Basic(username, password)
+        }
+        DockerCredential::IdentityToken(_) => {
+            warn!(
+                registry = server,
+                "identity tokens are not supported, using anonymous access"
+            );
+            RegistryAuth::Anonymous
+        }
+    }
+}
+
+/// Registry credentials provided via the `--registry-username`,
+/// `--registry-password` and `--registry-token` flags, via the environment
+/// variables backing them, or obtained from a credential provider.
+#[derive(Debug)]
+pub(crate) struct RegistryCredentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+impl RegistryCredentials {
+    fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        let username = matches.get_one::<String>("registry-username");
+        if let Some(token) = matches.get_one::<String>("registry-token") {
+            return Some(Self {
+                username: username
+                    .cloned()
+                    .unwrap_or_else(|| TOKEN_DEFAULT_USERNAME.to_string()),
+                password: token.to_owned(),
+            });
+        }
+
+        matches
+            .get_one::<String>("registry-password")
+            .map(|password| Self {
+                username: username.cloned().unwrap_or_default(),
+                password: password.to_owned(),
+            })
+    }
+
+    /// Returns the Docker configuration that uses these credentials for the
+    /// given registry. All the other entries of `base` are preserved.
+    fn docker_config(&self, base: Option<Value>, registry: &str) -> Value {
+        let mut config = base.filter(Value::is_object).unwrap_or_else(|| json!({}));
+
+        // credential helpers take precedence over the static credentials
+        if let Some(cred_helpers) = config.get_mut("credHelpers").and_then(Value::as_object_mut) {
+            cred_helpers.remove(registry);
+        }
+
+        let auth = general_purpose::STANDARD.encode(format!("{}:{}", self.username, self.password));
+        let auths = config
+            .as_object_mut()
+            .expect("docker config is always an object")
+            .entry("auths")
+            .or_insert_with(|| json!({}));
+        if !auths.is_object() {
+            *auths = json!({});
+        }
+        auths[registry] = json!({ "auth": auth });
+
+        config
+    }
+}
+
+/// Selects the Docker configuration used to authenticate against the
+/// registries. Must be called before any other thread is started, since
+/// `$DOCKER_CONFIG` is changed.
+///
+/// The policy fetcher looks for registry credentials only inside of the
+/// Docker configuration directory. When the invoked command can use
+/// credentials given on the command line, or obtained from a credential
+/// provider, the user's Docker configuration is copied into a private
+/// temporary directory, exported via `$DOCKER_CONFIG`. [`registry_credentials`]
+/// later adds the credentials to it. The directory is removed when the
+/// returned value is dropped, hence it must be kept alive until kwctl is done
+/// interacting with the registries.
+pub(crate) fn init(matches: &ArgMatches) -> Result<Option<TempDir>> {
+    let mut matches = matches;
+    while let Some((_, subcommand_matches)) = matches.subcommand() {
+        matches = subcommand_matches;
+    }
+
+    let user_dir = match matches
+        .try_get_one::<String>("docker-config-json-path")
+        .ok()
+        .flatten()
+    {
+        Some(docker_config_json_path) => {
+            // docker_credential crate expects the config path in the $DOCKER_CONFIG. Keep docker-config-json-path parameter for backwards compatibility
+            unsafe {
+                env::set_var(DOCKER_CONFIG_ENV_VAR, docker_config_json_path);
+            }
+            Some(PathBuf::from(docker_config_json_path))
+        }
+        None => env::var_os(DOCKER_CONFIG_ENV_VAR).map(PathBuf::from),
+    };
+
+    let inline_credentials = matches.try_get_one::<String>("registry-username").is_ok()
+        && RegistryCredentials::from_matches(matches).is_some();
+    let credential_providers = matches.try_get_one::<String>("sources-path").is_ok()
+        && credential_providers(matches)?.serve_registries();
+    if !inline_credentials && !credential_providers {
+        let _ = DOCKER_CONFIG.set(DockerConfig {
+            user_dir,
+            exported_dir: None,
+        });
+        return Ok(None);
+    }
+
+    let base_config_path = user_dir
+        .clone()
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".docker")))
+        .map(|dir| dir.join("config.json"))
+        .filter(|path| path.exists());
+    let base_config = match base_config_path {
+        Some(path) => fs::read_to_string(&path)
+            .map_err(anyhow::Error::new)
+            .and_then(|contents| {
+                serde_json::from_str::<Value>(&contents).map_err(anyhow::Error::new)
+            })
+            .map_err(|e| anyhow!("cannot read docker config {}: {}", path.display(), e))?,
+        None => json!({}),
+    };
+
+    // the directory is created with 0700 permissions
+    let docker_config_dir = tempfile::Builder::new()
+        .prefix("kwctl-docker-config-")
+        .tempdir()?;
+    fs::write(
+        docker_config_dir.path().join("config.json"),
+        serde_json::to_vec(&base_config)?,
+    )?;
+    unsafe {
+        env::set_var(DOCKER_CONFIG_ENV_VAR, docker_config_dir.path());
+    }
+    let _ = DOCKER_CONFIG.set(DockerConfig {
+        user_dir,
+        exported_dir: Some(docker_config_dir.path().to_path_buf()),
+    });
+
+    Ok(Some(docker_config_dir))
+}
+
+/// Directory of the user's Docker configuration, when set via
+/// `--docker-config-json-path` or `$DOCKER_CONFIG`
+pub(crate) fn user_docker_config_dir() -> Option<PathBuf> {
+    match DOCKER_CONFIG.get() {
+        Some(docker_config) => docker_config.user_dir.clone(),
+        None => env::var_os(DOCKER_CONFIG_ENV_VAR).map(PathBuf::from),
+    }
+}
+
+/// Makes the credentials of the registry hosting `uri` available to the
+/// policy fetcher, by adding them to the Docker configuration exported by
+/// [`init`].
+///
+/// The credentials given on the command line are used first. When none have
+/// been provided, the credential providers declared inside of the sources file
+/// are looked up.
+///
+/// Nothing is done when no credentials are found, or when `uri` doesn't
+/// reference a registry.
+pub(crate) fn registry_credentials(matches: &ArgMatches, uri: &str) -> Result<()> {
+    export_registry_credentials(matches, uri, RegistryCredentials::from_matches(matches))
+}
+
+/// Same as [`registry_credentials`], but the credentials given on the command
+/// line are ignored. Used for the registries these credentials are not meant
+/// for.
+pub(crate) fn provider_registry_credentials(matches: &ArgMatches, uri: &str) -> Result<()> {
+    export_registry_credentials(matches, uri, None)
+}
+
+fn export_registry_credentials(
+    matches: &ArgMatches,
+    uri: &str,
+    inline_credentials: Option<RegistryCredentials>,
+) -> Result<()> {
+    // the version constraint is resolved only once the credentials are known
+    let uri = crate::version_constraints::without_constraint(uri);
+    let Some(image) = uri.strip_prefix("registry://") else {
+        if inline_credentials.is_some() {
+            warn!(
+                "registry credentials are ignored, {} is not a registry URI",
+                uri
+            );
+        }
+        return Ok(());
+    };
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let registry = reference.registry();
+
+    let (credentials, origin) = match inline_credentials {
+        Some(credentials) => (credentials, "the command line"),
+        None => match credential_providers(matches)?.credentials(registry)? {
+            Some(credentials) => (credentials, "a credential provider"),
+            None => return Ok(()),
+        },
+    };
+
+    let Some(exported_dir) = DOCKER_CONFIG
+        .get()
+        .and_then(|docker_config| docker_config.exported_dir.as_ref())
+    else {
+        return Err(anyhow!(
+            "cannot use the credentials of registry {}, the Docker configuration has not been exported",
+            registry
+        ));
+    };
+    // the configuration holds the credentials added by the previous calls too
+    let config_path = exported_dir.join("config.json");
+    let base_config = fs::read_to_string(&config_path)
+        .map_err(anyhow::Error::new)
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).map_err(anyhow::Error::new))
+        .map_err(|e| anyhow!("cannot read docker config {}: {}", config_path.display(), e))?;
+    fs::write(
+        &config_path,
+        serde_json::to_vec(&credentials.docker_config(Some(base_config), registry))?,
+    )?;
+    info!(
+        registry,
+        "using the registry credentials provided by {}", origin
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::helpers_and_store(
+        r#"{"auths": {}, "credsStore": "desktop", "credHelpers": {"123456789.dkr.ecr.eu-west-1.amazonaws.com": "ecr-login", "gcr.io": "gcloud", "eu.gcr.io": "gcloud"}}"#,
+        vec!["desktop", "ecr-login", "gcloud"]
+    )]
+    #[case::only_auths(r#"{"auths": {"ghcr.io": {"auth": "dXNlcjpwYXNz"}}}"#, vec![])]
+    fn credential_helpers_names(#[case] config: &str, #[case] expected: Vec<&str>) {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.json");
+        fs::write(&config_path, config).unwrap();
+
+        let helpers = CredentialHelpers::from_docker_config(&config_path).unwrap();
+        assert_eq!(helpers.names(), expected);
+    }
+
+    #[test]
+    fn inline_credentials_override_helper() {
+        let credentials = RegistryCredentials {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        };
+        let base = json!({
+            "auths": {"quay.io": {"auth": "b3RoZXI6b3RoZXI="}},
+            "credHelpers": {"ghcr.io": "desktop", "gcr.io": "gcloud"}
+        });
+
+        let config = credentials.docker_config(Some(base), "ghcr.io");
+        assert_eq!(
+            config,
+            json!({
+                "auths": {
+                    "quay.io": {"auth": "b3RoZXI6b3RoZXI="},
+                    "ghcr.io": {"auth": "dXNlcjpwYXNz"}
+                },
+                "credHelpers": {"gcr.io": "gcloud"}
+            })
+        );
+    }
+
+    #[test]
+    fn inline_credentials_without_base_config() {
+        let credentials = RegistryCredentials {
+            username: TOKEN_DEFAULT_USERNAME.to_string(),
+            password: "token".to_string(),
+        };
+
+        let config = credentials.docker_config(None, "ghcr.io");
+        assert_eq!(
+            docker_credential::get_credential_from_reader(config.to_string().as_bytes(), "ghcr.io")
+                .unwrap(),
+            DockerCredential::UsernamePassword(
+                TOKEN_DEFAULT_USERNAME.to_string(),
+                "token".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn identity_token_falls_back_to_anonymous() {
+        let auth = into_registry_auth(
+            "myregistry.azurecr.io",
+            DockerCredential::IdentityToken("token".to_string()),
+        );
+        assert!(matches!(auth, RegistryAuth::Anonymous));
+    }
+}
@@ -1,38 +1,65 @@
-use std::{env, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use clap::ArgMatches;
-use policy_evaluator::policy_fetcher::{
-    sources::{read_sources_file, Sources},
-    store::DEFAULT_ROOT,
-};
+use policy_evaluator::policy_fetcher::sources::{read_sources_file, Sources};
+use serde::Deserialize;
 use tracing::warn;
 
-const DOCKER_CONFIG_ENV_VAR: &str = "DOCKER_CONFIG";
+use crate::config::{
+    credential_provider::CredentialProviders,
+    registry_auth::{user_docker_config_dir, CredentialHelpers},
+    strict::{is_lenient, validate, ConfigFile},
+};
 
-pub(crate) fn remote_server_options(matches: &ArgMatches) -> Result<Option<Sources>> {
-    let sources = if let Some(sources_path) = matches.get_one::<String>("sources-path") {
-        Some(read_sources_file(Path::new(&sources_path))?)
+pub(crate) const DOCKER_CONFIG_ENV_VAR: &str = "DOCKER_CONFIG";
+
+/// Returns the path of the sources file to be used: the one provided by the user
+/// via the `--sources-path` flag, or the default one if it exists.
+///
+/// Unless `--lenient` is set, the file is rejected when it does not match the
+/// sources schema.
+fn sources_file_path(matches: &ArgMatches) -> Result<Option<PathBuf>> {
+    let sources_path = if let Some(sources_path) = matches.get_one::<String>("sources-path") {
+        Some(PathBuf::from(sources_path))
     } else {
-        let sources_path = DEFAULT_ROOT.config_dir().join("sources.yaml");
+        let sources_path = crate::store_profile::config_dir().join("sources.yaml");
         if Path::exists(&sources_path) {
-            Some(read_sources_file(&sources_path)?)
+            Some(sources_path)
         } else {
             None
         }
     };
 
-    if let Some(docker_config_json_path) = matches.get_one::<String>("docker-config-json-path") {
-        // docker_credential crate expects the config path in the $DOCKER_CONFIG. Keep docker-config-json-path parameter for backwards compatibility
-        unsafe {
-            env::set_var(DOCKER_CONFIG_ENV_VAR, docker_config_json_path);
+    if let Some(sources_path) = &sources_path {
+        if !is_lenient(matches) {
+            validate(ConfigFile::Sources, sources_path)?;
         }
     }
-    if let Ok(docker_config_path_str) = env::var(DOCKER_CONFIG_ENV_VAR) {
-        let docker_config_path = Path::new(&docker_config_path_str).join("config.json");
+    Ok(sources_path)
+}
+
+pub(crate) fn remote_server_options(matches: &ArgMatches) -> Result<Option<Sources>> {
+    let sources = sources_file_path(matches)?
+        .map(|sources_path| read_sources_file(&sources_path))
+        .transpose()?;
+
+    // `$DOCKER_CONFIG` is set by `registry_auth::init`, according to
+    // `--docker-config-json-path`
+    if let Some(docker_config_dir) = user_docker_config_dir() {
+        let docker_config_path = docker_config_dir.join("config.json");
         match docker_config_path.as_path().try_exists() {
             Ok(exist) => {
-                if !exist {
+                if exist {
+                    match CredentialHelpers::from_docker_config(&docker_config_path) {
+                        Ok(helpers) => helpers.warn_missing_binaries(),
+                        Err(e) => warn!("{}", e),
+                    }
+                } else {
                     warn!("Docker config file not found. Check if you are pointing to the directory containing the file. The file path should be {}.", docker_config_path.display());
                 }
             }
@@ -44,3 +71,146 @@ pub(crate) fn remote_server_options(matches: &ArgMatches) -> Result<Option<Sourc
 
     Ok(sources)
 }
+
+/// Registry mirrors declared inside of the `mirrors` section of the sources file:
+///
+/// ```yaml
+/// mirrors:
+///   ghcr.io:
+///     - registry.example.com/ghcr
+///     - registry-backup.example.com
+/// ```
+///
+/// The key is the upstream registry host, the value is the ordered list of
+/// mirrors to be tried before falling back to the upstream registry. A mirror
+/// can include a path prefix that is prepended to the repository name.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub(crate) struct RegistryMirrors(HashMap<String, Vec<String>>);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RawSourcesMirrors {
+    mirrors: RegistryMirrors,
+}
+
+impl RegistryMirrors {
+    fn from_sources_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("cannot read sources file {}: {}", path.display(), e))?;
+        let raw: RawSourcesMirrors = serde_yaml::from_str(&contents).map_err(|e| {
+            anyhow!(
+                "cannot parse mirrors defined inside of {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        Ok(raw.mirrors)
+    }
+
+    /// Returns the list of URIs that can be used to fetch the given policy,
+    /// in the order they have to be tried: first the mirrors, then the
+    /// original URI.
+    ///
+    /// Only `registry://` URIs can be mirrored, all the other URIs are
+    /// returned untouched.
+    pub(crate) fn candidates(&self, uri: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = Vec::new();
+
+        if let Some((host, repository)) = uri
+            .strip_prefix("registry://")
+            .and_then(|reference| reference.split_once('/'))
+        {
+            if let Some(mirrors) = self.0.get(host) {
+                candidates.extend(mirrors.iter().map(|mirror| {
+                    format!(
+                        "registry://{}/{}",
+                        mirror
+                            .trim_start_matches("registry://")
+                            .trim_end_matches('/'),
+                        repository
+                    )
+                }));
+            }
+        }
+        candidates.push(uri.to_string());
+
+        candidates
+    }
+}
+
+pub(crate) fn registry_mirrors(matches: &ArgMatches) -> Result<RegistryMirrors> {
+    sources_file_path(matches)?
+        .map(|sources_path| RegistryMirrors::from_sources_file(&sources_path))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+pub(crate) fn credential_providers(matches: &ArgMatches) -> Result<CredentialProviders> {
+    sources_file_path(matches)?
+        .map(|sources_path| CredentialProviders::from_sources_file(&sources_path))
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn mirrors() -> RegistryMirrors {
+        RegistryMirrors(HashMap::from([(
+            "ghcr.io".to_string(),
+            vec![
+                "registry.example.com/ghcr/".to_string(),
+                "registry://backup.example.com".to_string(),
+            ],
+        )]))
+    }
+
+    #[rstest]
+    #[case::mirrored_registry(
+        "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5",
+        vec![
+            "registry://registry.example.com/ghcr/kubewarden/policies/pod-privileged:v0.2.5",
+            "registry://backup.example.com/kubewarden/policies/pod-privileged:v0.2.5",
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5",
+        ]
+    )]
+    #[case::not_mirrored_registry(
+        "registry://quay.io/kubewarden/policies/pod-privileged:v0.2.5",
+        vec!["registry://quay.io/kubewarden/policies/pod-privileged:v0.2.5"]
+    )]
+    #[case::https_uri(
+        "https://ghcr.io/kubewarden/policies/policy.wasm",
+        vec!["https://ghcr.io/kubewarden/policies/policy.wasm"]
+    )]
+    fn registry_mirrors_candidates(#[case] uri: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(mirrors().candidates(uri), expected);
+    }
+
+    #[test]
+    fn registry_mirrors_from_sources_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let sources_path = dir.path().join("sources.yaml");
+        fs::write(
+            &sources_path,
+            r#"
+insecure_sources:
+  - "localhost:5000"
+mirrors:
+  ghcr.io:
+    - "localhost:5000/ghcr"
+"#,
+        )
+        .unwrap();
+
+        let mirrors = RegistryMirrors::from_sources_file(&sources_path).unwrap();
+        assert_eq!(
+            mirrors.candidates("registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13"),
+            vec![
+                "registry://localhost:5000/ghcr/kubewarden/tests/safe-labels:v0.1.13",
+                "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+            ]
+        );
+    }
+}
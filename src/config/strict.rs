@@ -0,0 +1,387 @@
+//! Strict validation of the configuration files and of the policy settings.
+//!
+//! The configuration files are parsed by policy-fetcher and policy-evaluator,
+//! which silently ignore unknown fields. Because of that, a typo inside of a
+//! field name leads to a configuration that is quietly not applied.
+//!
+//! The files are validated against the JSON Schemas shipped inside of the
+//! `schemas` directory, the ones printed by `kwctl schema`. The settings are
+//! validated against the settings JSON Schema embedded into the metadata of
+//! the policy, when there's one.
+
+use std::{fmt, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use jsonschema::error::ValidationErrorKind;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+use crate::schema::{METADATA_SCHEMA, SOURCES_SCHEMA, VERIFICATION_CONFIG_SCHEMA};
+
+/// The configuration files checked in strict mode
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ConfigFile {
+    Sources,
+    VerificationConfig,
+    Metadata,
+}
+
+impl ConfigFile {
+    fn schema(self) -> &'static str {
+        match self {
+            ConfigFile::Sources => SOURCES_SCHEMA,
+            ConfigFile::VerificationConfig => VERIFICATION_CONFIG_SCHEMA,
+            ConfigFile::Metadata => METADATA_SCHEMA,
+        }
+    }
+}
+
+/// Returns whether the user asked to skip the strict validation via
+/// `--lenient`
+pub(crate) fn is_lenient(matches: &ArgMatches) -> bool {
+    matches
+        .try_get_one::<bool>("lenient")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(false)
+}
+
+/// Fails when the configuration file at `path` does not match the schema of
+/// its kind. The error reports the line and the column of every violation.
+pub(crate) fn validate(kind: ConfigFile, path: &Path) -> Result<()> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+    let violations = check(kind, &contents);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} is not valid:\n  {}\nUse --lenient to skip the validation",
+        path.display(),
+        violations.join("\n  ")
+    ))
+}
+
+/// Validates the YAML document against the schema of its kind, returning the
+/// violations prefixed with their line and column
+pub(crate) fn check(kind: ConfigFile, contents: &str) -> Vec<String> {
+    if contents.trim().is_empty() {
+        return Vec::new();
+    }
+    let document: Value = match serde_yaml::from_str(contents) {
+        Ok(document) => document,
+        Err(e) => return vec![e.to_string()],
+    };
+    let schema: Value =
+        serde_json::from_str(kind.schema()).expect("the shipped schemas are valid JSON");
+    let validator =
+        jsonschema::validator_for(&schema).expect("the shipped schemas are valid JSON Schemas");
+    violations(&validator, &document, |path, key| {
+        position(contents, path, key).map(|(line, column)| format!("line {line} column {column}"))
+    })
+}
+
+/// Validates the settings of a policy against its settings JSON Schema,
+/// returning the violations prefixed with the path of the offending value
+pub(crate) fn check_settings(schema: &Value, settings: &Value) -> Result<Vec<String>> {
+    let validator = jsonschema::validator_for(schema)
+        .map_err(|e| anyhow!("invalid settings JSON Schema inside of the metadata: {}", e))?;
+    Ok(violations(&validator, settings, |path, key| {
+        let pointer: String = path
+            .iter()
+            .map(String::as_str)
+            .chain(key)
+            .map(|segment| format!("/{segment}"))
+            .collect();
+        (!pointer.is_empty()).then_some(pointer)
+    }))
+}
+
+/// Describes every violation of the instance, `locate` gives the location of
+/// the node at the path, or of the key of the mapping at the path
+fn violations(
+    validator: &jsonschema::Validator,
+    instance: &Value,
+    locate: impl Fn(&[String], Option<&str>) -> Option<String>,
+) -> Vec<String> {
+    validator
+        .iter_errors(instance)
+        .map(|error| {
+            let path = segments(&error.instance_path.to_string());
+            // point to the first unexpected field, rather than to the
+            // mapping holding it
+            let key = match &error.kind {
+                ValidationErrorKind::AdditionalProperties { unexpected } => {
+                    unexpected.first().map(String::as_str)
+                }
+                _ => None,
+            };
+            match locate(&path, key) {
+                Some(location) => format!("{location}: {error}"),
+                None => error.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Splits a JSON pointer, like `/allOf/0/anotations`, into its segments
+fn segments(pointer: &str) -> Vec<String> {
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+/// Returns the line and the column of the node at `path` inside of the YAML
+/// document, or of the key `key` of the mapping at `path`
+fn position(contents: &str, path: &[String], key: Option<&str>) -> Option<(usize, usize)> {
+    let error = Locate { path, key }
+        .deserialize(serde_yaml::Deserializer::from_str(contents))
+        .err()
+        .filter(|error| error.to_string().contains(FOUND))?;
+    let location = error.location()?;
+    Some((location.line(), location.column()))
+}
+
+const FOUND: &str = "the located node";
+
+/// Walks the document down to the located node and fails there: the parser
+/// attaches the position of the node to the error
+struct Locate<'a> {
+    path: &'a [String],
+    key: Option<&'a str>,
+}
+
+impl<'de> DeserializeSeed<'de> for Locate<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+// the scalars are only reached once the path is exhausted, the default
+// implementations fail on them
+impl<'de> Visitor<'de> for Locate<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(FOUND)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let (wanted, rest) = match (self.path.split_first(), self.key) {
+            (Some((segment, rest)), _) => (segment.as_str(), Some(rest)),
+            (None, Some(key)) => (key, None),
+            (None, None) => return Err(de::Error::custom(FOUND)),
+        };
+        while let Some(found) = map.next_key_seed(Key {
+            wanted,
+            fail: rest.is_none(),
+        })? {
+            match rest {
+                Some(path) if found => {
+                    return map.next_value_seed(Locate {
+                        path,
+                        key: self.key,
+                    })
+                }
+                _ => map.next_value::<IgnoredAny>().map(drop)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((segment, path)) = self.path.split_first() else {
+            return Err(de::Error::custom(FOUND));
+        };
+        let Ok(index) = segment.parse::<usize>() else {
+            return Ok(());
+        };
+        for _ in 0..index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Ok(());
+            }
+        }
+        seq.next_element_seed(Locate {
+            path,
+            key: self.key,
+        })
+        .map(drop)
+    }
+}
+
+/// Tells whether the key of a mapping is the wanted one, failing on it when
+/// the key is the located node
+struct Key<'a> {
+    wanted: &'a str,
+    fail: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for Key<'_> {
+    type Value = bool;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Key<'_> {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a mapping key")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<bool, E> {
+        match key == self.wanted {
+            true if self.fail => Err(E::custom(FOUND)),
+            found => Ok(found),
+        }
+    }
+
+    fn visit_bool<E: de::Error>(self, key: bool) -> Result<bool, E> {
+        self.visit_str(&key.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, key: i64) -> Result<bool, E> {
+        self.visit_str(&key.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, key: u64) -> Result<bool, E> {
+        self.visit_str(&key.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, key: f64) -> Result<bool, E> {
+        self.visit_str(&key.to_string())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<bool, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(false)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<bool, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case::sources(
+        ConfigFile::Sources,
+        "insecure_sources:\n  - localhost:5000\nsource_authorities:\n  registry.lan:\n    - type: Path\n      path: /ca.pem\nmirrors:\n  ghcr.io:\n    - localhost:5000/ghcr\n"
+    )]
+    #[case::empty_sources(ConfigFile::Sources, "\n")]
+    #[case::verification_config(
+        ConfigFile::VerificationConfig,
+        include_str!("../../tests/data/sigstore/verification-config.yml")
+    )]
+    #[case::keyless_verification_config(
+        ConfigFile::VerificationConfig,
+        include_str!("../../tests/data/sigstore/verification-config-keyless.yml")
+    )]
+    #[case::regexp_verification_config(
+        ConfigFile::VerificationConfig,
+        "apiVersion: v1\nallOf:\n  - kind: genericIssuer\n    issuerRegexp: https://token\\.actions\\.githubusercontent\\.com\n    subject:\n      regexp: https://github\\.com/kubewarden/.*\n"
+    )]
+    #[case::metadata(
+        ConfigFile::Metadata,
+        include_str!("../../tests/data/rego-annotate/metadata-correct.yml")
+    )]
+    #[case::artifacthub_metadata(
+        ConfigFile::Metadata,
+        include_str!("../../tests/data/artifacthub/metadata.yml")
+    )]
+    fn valid_files_are_accepted(#[case] kind: ConfigFile, #[case] contents: &str) {
+        assert_eq!(check(kind, contents), Vec::<String>::new());
+    }
+
+    #[rstest]
+    #[case::sources(
+        ConfigFile::Sources,
+        "insecure_source:\n  - localhost:5000\n",
+        "insecure_source",
+        "line 1 column 1"
+    )]
+    #[case::nested_field(
+        ConfigFile::Sources,
+        "credential_providers:\n  - registries: [ghcr.io]\n    command: login\n    tmeout: 5\n",
+        "tmeout",
+        "line 4 column 5"
+    )]
+    // the fields of the variants are reported at the position of the
+    // enclosing mapping, since none of the variants matches
+    #[case::source_authority(
+        ConfigFile::Sources,
+        "source_authorities:\n  registry.lan:\n    - type: Path\n      pth: /ca.pem\n",
+        "pth",
+        "line 3 column 7"
+    )]
+    #[case::signature(
+        ConfigFile::VerificationConfig,
+        "apiVersion: v1\nallOf:\n  - kind: pubKey\n    key: KEY\n    anotations:\n      env: prod\n",
+        "anotations",
+        "line 3 column 5"
+    )]
+    #[case::metadata(
+        ConfigFile::Metadata,
+        "rules:\n  - apiGroups: [\"\"]\n    resource: [\"pods\"]\nmutating: false\n",
+        "resource",
+        "line 3 column 5"
+    )]
+    #[case::value(
+        ConfigFile::Metadata,
+        "rules:\n  - operations: [CREATE, PATCH]\n",
+        "PATCH",
+        "line 2 column 26"
+    )]
+    fn violations_are_located(
+        #[case] kind: ConfigFile,
+        #[case] contents: &str,
+        #[case] culprit: &str,
+        #[case] location: &str,
+    ) {
+        let violations = check(kind, contents);
+        assert_eq!(violations.len(), 1, "{violations:?}");
+        assert!(
+            violations[0].starts_with(&format!("{location}: ")) && violations[0].contains(culprit),
+            "{violations:?}"
+        );
+    }
+
+    #[test]
+    fn settings_violations() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "replicas": { "type": "integer" }
+            },
+            "additionalProperties": false
+        });
+
+        assert_eq!(
+            check_settings(&schema, &json!({ "replicas": 3 })).unwrap(),
+            Vec::<String>::new()
+        );
+        let violations =
+            check_settings(&schema, &json!({ "replicas": "3", "replica": 3 })).unwrap();
+        assert_eq!(violations.len(), 2, "{violations:?}");
+        assert!(violations.iter().any(|v| v.starts_with("/replicas: ")));
+        assert!(violations.iter().any(|v| v.starts_with("/replica: ")));
+    }
+}
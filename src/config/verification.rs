@@ -1,22 +1,54 @@
-use std::{collections::BTreeMap, convert::TryInto, fs, path::Path, sync::Arc};
+use std::{collections::BTreeMap, fs, path::Path, sync::Arc, time::Duration};
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 use policy_evaluator::policy_fetcher::{
-    sigstore::{
-        self,
-        trust::{ManualTrustRoot, TrustRoot},
-    },
-    store::DEFAULT_ROOT,
+    sigstore::trust::ManualTrustRoot,
     verify::config::{read_verification_file, LatestVerificationConfig, Signature, Subject},
 };
 use tracing::{debug, info};
 
-use crate::{verify::VerificationAnnotations, KWCTL_VERIFICATION_CONFIG};
+use crate::{
+    config::{
+        certificate_identity::{
+            extract_certificate_identities, has_signatures, CertificateIdentity, Matcher,
+        },
+        fulcio_chain::read_fulcio_certs,
+        strict::{is_lenient, validate, ConfigFile},
+    },
+    kms::KmsKey,
+    trust_root,
+    verify::VerificationAnnotations,
+    KWCTL_VERIFICATION_CONFIG,
+};
+
+/// Reads a public key, either from a PEM file or from a KMS
+pub(crate) fn read_public_key(key: &str) -> Result<String> {
+    match KmsKey::parse(key)? {
+        Some(kms_key) => kms_key.public_key(),
+        None => {
+            fs::read_to_string(key).map_err(|e| anyhow!("could not read file {}: {:?}", key, e))
+        }
+    }
+}
+
+/// Requirements a policy must satisfy to be trusted
+#[derive(Debug, Default)]
+pub(crate) struct VerificationOptions {
+    /// Signatures verified by policy-fetcher
+    pub(crate) config: Option<LatestVerificationConfig>,
+    /// Keyless signatures whose certificate identity, or OIDC issuer, is
+    /// matched via a regular expression. Verified by kwctl, all of them must
+    /// be satisfied.
+    pub(crate) certificate_identities: Vec<CertificateIdentity>,
+    /// Where the requirements come from, either the path of the
+    /// verification config file or the command line flags
+    pub(crate) origin: String,
+}
 
 pub(crate) fn build_verification_options(
     matches: &ArgMatches,
-) -> Result<Option<LatestVerificationConfig>> {
+) -> Result<Option<VerificationOptions>> {
     if let Some(verification_config) = build_verification_options_from_flags(matches)? {
         // flags present, built configmap from them:
         if matches.contains_id("verification-config-path") {
@@ -28,27 +60,82 @@ pub(crate) fn build_verification_options(
     }
     if let Some(verification_config_path) = matches.get_one::<String>("verification-config-path") {
         // config flag present, read it:
-        Ok(Some(read_verification_file(Path::new(
-            &verification_config_path,
-        ))?))
+        let verification_config_path = Path::new(verification_config_path);
+        if !is_lenient(matches) {
+            validate(ConfigFile::VerificationConfig, verification_config_path)?;
+        }
+        Ok(Some(read_verification_config(verification_config_path)?))
     } else {
-        let verification_config_path = DEFAULT_ROOT.config_dir().join(KWCTL_VERIFICATION_CONFIG);
+        let verification_config_path =
+            crate::store_profile::config_dir().join(KWCTL_VERIFICATION_CONFIG);
         if Path::exists(&verification_config_path) {
             // default config flag present, read it:
             info!(path = ?verification_config_path, "Default verification config present, using it");
-            Ok(Some(read_verification_file(&verification_config_path)?))
+            if !is_lenient(matches) {
+                validate(ConfigFile::VerificationConfig, &verification_config_path)?;
+            }
+            Ok(Some(read_verification_config(&verification_config_path)?))
         } else {
             Ok(None)
         }
     }
 }
 
-/// Takes clap flags and builds a Some(LatestVerificationConfig) containing all
+/// Reads the verification config file. The signatures relying on regular
+/// expressions are not known to policy-fetcher, they are extracted before
+/// handing the rest of the file over to it.
+fn read_verification_config(path: &Path) -> Result<VerificationOptions> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+    let mut document: serde_yaml::Value = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("cannot parse {}: {}", path.display(), e))?;
+    let certificate_identities = extract_certificate_identities(&mut document)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    if certificate_identities.is_empty() {
+        return Ok(VerificationOptions {
+            config: Some(read_verification_file(path)?),
+            certificate_identities,
+            origin: path.display().to_string(),
+        });
+    }
+
+    if document
+        .get("apiVersion")
+        .and_then(serde_yaml::Value::as_str)
+        != Some("v1")
+    {
+        return Err(anyhow!(
+            "{}: unsupported verification config apiVersion, expected v1",
+            path.display()
+        ));
+    }
+    let config = if has_signatures(&document) {
+        if let serde_yaml::Value::Mapping(mapping) = &mut document {
+            mapping.remove("apiVersion");
+        }
+        Some(
+            serde_yaml::from_value::<LatestVerificationConfig>(document)
+                .map_err(|e| anyhow!("cannot parse {}: {}", path.display(), e))?,
+        )
+    } else {
+        None
+    };
+
+    Ok(VerificationOptions {
+        config,
+        certificate_identities,
+        origin: path.display().to_string(),
+    })
+}
+
+/// Takes clap flags and builds a Some(VerificationOptions) containing all
 /// passed pub keys and annotations in LatestVerificationConfig.AllOf.
+/// Certificate identities and issuers given via regular expressions are
+/// kept inside of VerificationOptions.certificate_identities.
 /// If no verification flags where used, it returns a None.
-fn build_verification_options_from_flags(
+pub(crate) fn build_verification_options_from_flags(
     matches: &ArgMatches,
-) -> Result<Option<LatestVerificationConfig>> {
+) -> Result<Option<VerificationOptions>> {
     let key_files: Option<Vec<String>> = matches
         .get_many::<String>("verification-key")
         .map(|items| items.into_iter().map(|i| i.to_string()).collect());
@@ -78,6 +165,10 @@ fn build_verification_options_from_flags(
     let cert_oidc_issuer: Option<String> = matches
         .get_many::<String>("cert-oidc-issuer")
         .map(|items| items.into_iter().map(|i| i.to_string()).collect());
+    let cert_identity_regexp = matches.get_one::<String>("cert-identity-regexp");
+    let cert_oidc_issuer_regexp = matches.get_one::<String>("cert-oidc-issuer-regexp");
+    let cert_identity_given = cert_email.is_some() || cert_identity_regexp.is_some();
+    let cert_issuer_given = cert_oidc_issuer.is_some() || cert_oidc_issuer_regexp.is_some();
 
     let github_owner: Option<String> = matches
         .get_many::<String>("github-owner")
@@ -88,8 +179,8 @@ fn build_verification_options_from_flags(
 
     if key_files.is_none()
         && annotations.is_none()
-        && cert_email.is_none()
-        && cert_oidc_issuer.is_none()
+        && !cert_identity_given
+        && !cert_issuer_given
         && github_owner.is_none()
         && github_repo.is_none()
     {
@@ -97,11 +188,7 @@ fn build_verification_options_from_flags(
         return Ok(None);
     }
 
-    if key_files.is_none()
-        && cert_email.is_none()
-        && cert_oidc_issuer.is_none()
-        && github_owner.is_none()
-        && annotations.is_some()
+    if key_files.is_none() && !cert_issuer_given && github_owner.is_none() && annotations.is_some()
     {
         return Err(anyhow!(
             "Intending to verify annotations, but no verification keys, OIDC issuer or GitHub owner were passed"
@@ -115,20 +202,33 @@ fn build_verification_options_from_flags(
     }
 
     let mut signatures: Vec<Signature> = Vec::new();
+    let mut certificate_identities: Vec<CertificateIdentity> = Vec::new();
 
-    if (cert_email.is_some() && cert_oidc_issuer.is_none())
-        || (cert_email.is_none() && cert_oidc_issuer.is_some())
-    {
+    if cert_identity_given != cert_issuer_given {
         return Err(anyhow!(
-            "Intending to verify OIDC issuer, but no email or issuer were provided. You must pass the email and OIDC issuer to be validated together "
+            "Intending to verify OIDC issuer, but no email or issuer were provided. You must pass the email (or identity regexp) and OIDC issuer (or issuer regexp) to be validated together "
         ));
-    } else if cert_email.is_some() && cert_oidc_issuer.is_some() {
+    } else if let (Some(email), Some(issuer)) = (&cert_email, &cert_oidc_issuer) {
         let sig = Signature::GenericIssuer {
-            issuer: cert_oidc_issuer.unwrap(),
-            subject: Subject::Equal(cert_email.unwrap()),
+            issuer: issuer.to_owned(),
+            subject: Subject::Equal(email.to_owned()),
             annotations: annotations.clone(),
         };
         signatures.push(sig)
+    } else if cert_identity_given {
+        let subject = match cert_identity_regexp {
+            Some(pattern) => Matcher::regexp(pattern)?,
+            None => Matcher::Equal(cert_email.expect("identity was given")),
+        };
+        let issuer = match cert_oidc_issuer_regexp {
+            Some(pattern) => Matcher::regexp(pattern)?,
+            None => Matcher::Equal(cert_oidc_issuer.expect("issuer was given")),
+        };
+        certificate_identities.push(CertificateIdentity {
+            issuer,
+            subject,
+            annotations: annotations.clone(),
+        });
     }
 
     if let Some(repo_owner) = github_owner {
@@ -143,9 +243,7 @@ fn build_verification_options_from_flags(
     for key_path in key_files.iter().flatten() {
         let sig = Signature::PubKey {
             owner: None,
-            key: fs::read_to_string(key_path)
-                .map_err(|e| anyhow!("could not read file {}: {:?}", key_path, e))?
-                .to_string(),
+            key: read_public_key(key_path)?,
             annotations: annotations.clone(),
         };
         signatures.push(sig);
@@ -155,29 +253,29 @@ fn build_verification_options_from_flags(
     } else {
         Some(signatures)
     };
-    let verification_config = LatestVerificationConfig {
-        all_of: signatures_all_of,
+    let config = signatures_all_of.map(|all_of| LatestVerificationConfig {
+        all_of: Some(all_of),
         any_of: None,
-    };
-    Ok(Some(verification_config))
+    });
+    Ok(Some(VerificationOptions {
+        config,
+        certificate_identities,
+        origin: "command line flags".to_string(),
+    }))
 }
 
 pub(crate) async fn build_sigstore_trust_root(
     matches: ArgMatches,
 ) -> Result<Option<Arc<ManualTrustRoot<'static>>>> {
-    use sigstore::registry::Certificate;
-
     if matches.contains_id("fulcio-cert-path") || matches.contains_id("rekor-public-key-path") {
-        let mut fulcio_certs: Vec<Certificate> = vec![];
-        if let Some(items) = matches.get_many::<String>("fulcio-cert-path") {
-            for item in items {
-                let data = fs::read(item)?;
-                let cert = Certificate {
-                    data,
-                    encoding: sigstore::registry::CertificateEncoding::Pem,
-                };
-                fulcio_certs.push(cert);
-            }
+        let fulcio_cert_paths: Vec<&String> = matches
+            .get_many::<String>("fulcio-cert-path")
+            .map(|paths| paths.collect())
+            .unwrap_or_default();
+        let fulcio_certs = if fulcio_cert_paths.is_empty() {
+            vec![]
+        } else {
+            read_fulcio_certs(&fulcio_cert_paths)?
         };
 
         let mut rekor_public_keys: Vec<Vec<u8>> = vec![];
@@ -196,42 +294,59 @@ pub(crate) async fn build_sigstore_trust_root(
         }
         debug!("building Sigstore trust root from flags");
         Ok(Some(Arc::new(ManualTrustRoot {
-            fulcio_certs: fulcio_certs
-                .iter()
-                .map(|c| {
-                    let cert: sigstore::registry::Certificate = c.to_owned();
-                    cert.try_into()
-                        .expect("could not convert certificate to CertificateDer")
-                })
-                .collect(),
+            fulcio_certs,
             rekor_keys: rekor_public_keys,
             ..Default::default()
         })))
+    } else if is_offline(&matches) {
+        Ok(Some(Arc::new(trust_root::load_cached()?)))
     } else {
-        debug!("building Sigstore trust root from Sigstore's TUF repository");
-        let checkout_path = DEFAULT_ROOT.config_dir().join("fulcio_and_rekor_data");
-        if !Path::exists(&checkout_path) {
-            fs::create_dir_all(checkout_path.clone())?
-        }
+        Ok(
+            trust_root::fetch_with_options(&build_network_options(&matches)?)
+                .await?
+                .map(Arc::new),
+        )
+    }
+}
 
-        let repo = sigstore::trust::sigstore::SigstoreTrustRoot::new(Some(checkout_path.as_path()))
-            .await?;
-        let fulcio_certs: Vec<rustls_pki_types::CertificateDer> = repo
-            .fulcio_certs()
-            .expect("no fulcio certs found inside of TUF repository")
-            .into_iter()
-            .map(|c| c.into_owned())
-            .collect();
-        let manual_root = ManualTrustRoot {
-            fulcio_certs,
-            rekor_keys: repo
-                .rekor_keys()
-                .expect("no rekor keys found inside of TUF repository")
-                .iter()
-                .map(|k| k.to_vec())
-                .collect(),
-            ..Default::default()
-        };
-        Ok(Some(Arc::new(manual_root)))
+/// Timeout, retries and behavior on failure of the interactions with the
+/// Sigstore infrastructure, given via the `--sigstore-*` flags
+pub(crate) fn build_network_options(matches: &ArgMatches) -> Result<trust_root::NetworkOptions> {
+    let mut options = trust_root::NetworkOptions::default();
+    if let Some(timeout) = matches
+        .try_get_one::<u64>("sigstore-timeout")
+        .ok()
+        .flatten()
+    {
+        if *timeout == 0 {
+            return Err(anyhow!("--sigstore-timeout must be greater than zero"));
+        }
+        options.timeout = Duration::from_secs(*timeout);
     }
+    if let Some(retries) = matches
+        .try_get_one::<u32>("sigstore-retries")
+        .ok()
+        .flatten()
+    {
+        options.retries = *retries;
+    }
+    if let Some(on_unreachable) = matches
+        .try_get_one::<String>("sigstore-unreachable")
+        .ok()
+        .flatten()
+    {
+        options.on_unreachable = trust_root::OnUnreachable::try_from(on_unreachable.as_str())?;
+    }
+    Ok(options)
+}
+
+/// Returns whether the user asked to not reach the Sigstore infrastructure
+/// via `--offline`
+fn is_offline(matches: &ArgMatches) -> bool {
+    matches
+        .try_get_one::<bool>("offline")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(false)
 }
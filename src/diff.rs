@@ -0,0 +1,366 @@
+//! Comparison of two policies, usually two releases of the same policy.
+//!
+//! `kwctl diff` reports, as Markdown, the changes of the metadata, of the
+//! annotations, of the settings JSON Schema and of the size of the module.
+//! When a directory of requests is given, the requests are evaluated by both
+//! the policies and the ones leading to different outcomes are reported.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    admission_response::AdmissionResponse,
+    evaluation_context::EvaluationContext,
+    policy_evaluator::{PolicyEvaluator, PolicySettings},
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_fetcher::sources::Sources,
+    policy_metadata::Metadata,
+};
+
+use crate::{
+    backend::BackendDetector,
+    changelog::{annotation_changes, fields, metadata_changes, section, settings_schema_diff},
+    command::run::{
+        evaluator::{build_validate_request, has_raw_policy_type},
+        policy_execution_mode::determine_execution_mode,
+    },
+    config::sources::RegistryMirrors,
+};
+
+/// A policy compared by `kwctl diff`
+pub(crate) struct DiffedPolicy {
+    pub(crate) uri: String,
+    wasm_path: PathBuf,
+    /// The metadata of the policy, the defaults for policies not annotated
+    metadata: Metadata,
+    annotated: bool,
+    size: u64,
+}
+
+impl DiffedPolicy {
+    /// Looks for the policy inside of the store, or on disk, pulling it into
+    /// the store when missing
+    pub(crate) async fn fetch(
+        uri_or_sha_prefix: &str,
+        sources: Option<&Sources>,
+        mirrors: &RegistryMirrors,
+    ) -> Result<Self> {
+        let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_owned())?;
+        let uri = crate::version_constraints::resolve(&uri, sources).await?;
+        let wasm_path = crate::pull::local_path(&uri, sources, mirrors).await?;
+        let size = fs::metadata(&wasm_path)
+            .map_err(|e| anyhow!("cannot access {}: {}", wasm_path.display(), e))?
+            .len();
+        let metadata = Metadata::from_path(&wasm_path)
+            .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+
+        Ok(DiffedPolicy {
+            uri,
+            wasm_path,
+            annotated: metadata.is_some(),
+            metadata: metadata.unwrap_or_default(),
+            size,
+        })
+    }
+
+    fn evaluator(&self) -> Result<PolicyEvaluator> {
+        let execution_mode = determine_execution_mode(
+            self.annotated.then_some(&self.metadata),
+            None,
+            BackendDetector::default(),
+            &self.wasm_path,
+        )?;
+        // the requests are evaluated without access to the host
+        // capabilities
+        let eval_ctx = EvaluationContext {
+            policy_id: self.uri.to_owned(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: BTreeSet::new(),
+        };
+        Ok(PolicyEvaluatorBuilder::new()
+            .policy_file(&self.wasm_path)?
+            .execution_mode(execution_mode)
+            .enable_wasmtime_cache()
+            .build_pre()?
+            .rehydrate(&eval_ctx)?)
+    }
+}
+
+/// Outcome of the evaluation of a request
+#[derive(Debug, PartialEq)]
+pub(crate) enum Verdict {
+    Accepted,
+    /// Holds the patch returned by the policy
+    Mutated(String),
+    Rejected(String),
+    Failed(String),
+}
+
+impl From<AdmissionResponse> for Verdict {
+    fn from(response: AdmissionResponse) -> Self {
+        match (response.allowed, response.patch) {
+            (true, None) => Verdict::Accepted,
+            (true, Some(patch)) => Verdict::Mutated(patch),
+            (false, _) => Verdict::Rejected(
+                response
+                    .status
+                    .and_then(|status| status.message)
+                    .unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Accepted => write!(f, "accepted"),
+            Verdict::Mutated(_) => write!(f, "mutated"),
+            Verdict::Rejected(message) => write!(f, "rejected: {message}"),
+            Verdict::Failed(e) => write!(f, "failed: {e}"),
+        }
+    }
+}
+
+/// Outcomes of the evaluation of a request by the two policies
+pub(crate) struct Evaluation {
+    pub(crate) request: String,
+    pub(crate) old: Verdict,
+    pub(crate) new: Verdict,
+}
+
+/// Evaluates all the JSON requests of `requests_dir` with both the policies
+pub(crate) fn evaluate(
+    old: &DiffedPolicy,
+    new: &DiffedPolicy,
+    requests_dir: &Path,
+    settings: &PolicySettings,
+    raw: bool,
+) -> Result<Vec<Evaluation>> {
+    let mut requests: Vec<PathBuf> = fs::read_dir(requests_dir)
+        .map_err(|e| anyhow!("cannot read {}: {}", requests_dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    requests.sort();
+    if requests.is_empty() {
+        return Err(anyhow!(
+            "no JSON request found inside of {}",
+            requests_dir.display()
+        ));
+    }
+
+    let mut old_evaluator = old.evaluator()?;
+    let mut new_evaluator = new.evaluator()?;
+    let mut evaluations = Vec::new();
+    for path in requests {
+        let request: serde_json::Value = serde_json::from_slice(
+            &fs::read(&path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?,
+        )
+        .map_err(|e| anyhow!("cannot parse request {}: {}", path.display(), e))?;
+        let verdict = |policy: &DiffedPolicy, evaluator: &mut PolicyEvaluator| {
+            let raw = raw || (policy.annotated && has_raw_policy_type(Some(&policy.metadata)));
+            match build_validate_request(&request, raw) {
+                Ok(request) => Verdict::from(evaluator.validate(request, settings)),
+                Err(e) => Verdict::Failed(e.to_string()),
+            }
+        };
+        evaluations.push(Evaluation {
+            request: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            old: verdict(old, &mut old_evaluator),
+            new: verdict(new, &mut new_evaluator),
+        });
+    }
+    Ok(evaluations)
+}
+
+/// Renders the differences between the two policies as Markdown
+pub(crate) fn render(
+    old: &DiffedPolicy,
+    new: &DiffedPolicy,
+    evaluations: Option<&[Evaluation]>,
+) -> Result<String> {
+    let mut diff = format!("# {} → {}\n", old.uri, new.uri);
+
+    section(
+        &mut diff,
+        "Metadata",
+        &metadata_changes(&fields(&old.metadata)?, &fields(&new.metadata)?),
+    );
+    section(
+        &mut diff,
+        "Annotations",
+        &annotation_changes(
+            &old.metadata.annotations.clone().unwrap_or_default(),
+            &new.metadata.annotations.clone().unwrap_or_default(),
+        ),
+    );
+
+    let _ = write!(diff, "\n## Settings JSON Schema\n\n");
+    match settings_schema_diff(&old.metadata, &new.metadata)? {
+        Some(schema_diff) => {
+            let _ = write!(diff, "```diff\n{schema_diff}```\n");
+        }
+        None => diff.push_str("No changes.\n"),
+    }
+
+    let _ = write!(
+        diff,
+        "\n## Module size\n\n{} → {} ({})\n",
+        humansize::format_size(old.size, humansize::DECIMAL),
+        humansize::format_size(new.size, humansize::DECIMAL),
+        size_change(old.size, new.size)
+    );
+
+    if let Some(evaluations) = evaluations {
+        let changes: Vec<String> = evaluations
+            .iter()
+            .filter(|evaluation| evaluation.old != evaluation.new)
+            .map(|evaluation| {
+                format!(
+                    "`{}`: {} → {}",
+                    evaluation.request, evaluation.old, evaluation.new
+                )
+            })
+            .collect();
+        section(&mut diff, "Evaluation", &changes);
+        let _ = writeln!(
+            diff,
+            "\n{} out of {} requests evaluated differently.",
+            changes.len(),
+            evaluations.len()
+        );
+    }
+
+    Ok(diff)
+}
+
+fn size_change(old: u64, new: u64) -> String {
+    let delta = humansize::format_size(old.abs_diff(new), humansize::DECIMAL);
+    if new >= old {
+        format!("+{delta}")
+    } else {
+        format!("-{delta}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+
+    fn policy(uri: &str, size: u64, metadata: &str) -> DiffedPolicy {
+        DiffedPolicy {
+            uri: uri.to_string(),
+            wasm_path: PathBuf::from("policy.wasm"),
+            metadata: serde_yaml::from_str(metadata).unwrap(),
+            annotated: true,
+            size,
+        }
+    }
+
+    fn response(allowed: bool, message: Option<&str>, patch: Option<&str>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed,
+            patch: patch.map(str::to_string),
+            status: message.map(|message| AdmissionResponseStatus {
+                message: Some(message.to_string()),
+                code: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::accepted(response(true, None, None), Verdict::Accepted)]
+    #[case::mutated(response(true, None, Some("W10=")), Verdict::Mutated("W10=".to_string()))]
+    #[case::rejected(
+        response(false, Some("privileged containers are not allowed"), None),
+        Verdict::Rejected("privileged containers are not allowed".to_string())
+    )]
+    fn verdicts(#[case] response: AdmissionResponse, #[case] expected: Verdict) {
+        assert_eq!(Verdict::from(response), expected);
+    }
+
+    #[rstest]
+    #[case::grown(1_000, 1_500, "+500 B")]
+    #[case::shrunk(2_000_000, 1_000_000, "-1 MB")]
+    #[case::unchanged(1_000, 1_000, "+0 B")]
+    fn module_size_change(#[case] old: u64, #[case] new: u64, #[case] expected: &str) {
+        assert_eq!(size_change(old, new), expected);
+    }
+
+    #[test]
+    fn diff() {
+        let old = policy(
+            "registry://ghcr.io/kubewarden/policies/psp:v1.3.0",
+            1_000_000,
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE"]
+mutating: false
+annotations:
+  io.kubewarden.policy.title: psp
+  io.kubewarden.policy.settings-schema: '{"type": "object", "properties": {}}'
+"#,
+        );
+        let new = policy(
+            "registry://ghcr.io/kubewarden/policies/psp:v1.4.0",
+            1_200_000,
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE"]
+mutating: true
+annotations:
+  io.kubewarden.policy.title: psp
+  io.kubewarden.policy.severity: high
+  io.kubewarden.policy.settings-schema: '{"type": "object", "properties": {"strict": {"type": "boolean"}}}'
+"#,
+        );
+        let evaluations = [
+            Evaluation {
+                request: "privileged-pod.json".to_string(),
+                old: Verdict::Accepted,
+                new: Verdict::Rejected("privileged".to_string()),
+            },
+            Evaluation {
+                request: "unprivileged-pod.json".to_string(),
+                old: Verdict::Accepted,
+                new: Verdict::Accepted,
+            },
+        ];
+
+        let diff = render(&old, &new, Some(&evaluations)).unwrap();
+        for expected in [
+            "# registry://ghcr.io/kubewarden/policies/psp:v1.3.0 → registry://ghcr.io/kubewarden/policies/psp:v1.4.0",
+            "- `mutating`: `false` → `true`",
+            "- Added `io.kubewarden.policy.severity`: high",
+            "+  \"properties\": {\n+    \"strict\": {",
+            "(+200 kB)",
+            "- `privileged-pod.json`: accepted → rejected: privileged",
+            "1 out of 2 requests evaluated differently.",
+        ] {
+            assert!(diff.contains(expected), "{expected} not found in:\n{diff}");
+        }
+        assert!(!diff.contains("unprivileged-pod.json"));
+        assert!(!diff.contains("Changed `io.kubewarden.policy.settings-schema`"));
+    }
+}
@@ -0,0 +1,681 @@
+//! Fuzzing of a policy, run by `kwctl fuzz`.
+//!
+//! The objects of a seed admission request are mutated: their fields are
+//! dropped, their values are replaced with values of other types and huge
+//! strings are injected. The policy evaluates every mutated request twice,
+//! the requests making it fail (like when it panics), time out or return
+//! different responses are reported.
+//!
+//! The settings of the policy can be fuzzed instead: they are generated from
+//! the settings JSON Schema of the policy, breaking it half of the times, and
+//! the settings making the policy fail or accepted despite breaking the
+//! schema are reported.
+
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    admission_response::AdmissionResponse,
+    evaluation_context::EvaluationContext,
+    policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre, PolicySettings, ValidateRequest},
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_fetcher::sources::Sources,
+    policy_metadata::Metadata,
+};
+use serde_json::Value;
+
+use crate::{
+    backend::BackendDetector,
+    command::run::{
+        evaluator::{build_validate_request, has_raw_policy_type},
+        policy_execution_mode::determine_execution_mode,
+    },
+    config::sources::RegistryMirrors,
+    diff::Verdict,
+    inspect::settings_schema,
+};
+
+mod settings;
+
+/// Length of the strings injected into the requests
+const HUGE_STRING_LENGTH: usize = 1024 * 1024;
+
+/// Mutations applied to a request, at most
+const MAX_MUTATIONS: usize = 3;
+
+pub(crate) struct FuzzOptions {
+    pub(crate) iterations: u64,
+    /// Seed of the mutations, the same seed produces the same requests
+    pub(crate) seed: u64,
+    /// Time granted to each evaluation
+    pub(crate) timeout: Duration,
+    /// Whether the seed request is a raw one
+    pub(crate) raw: bool,
+    /// Directory receiving the requests that made the policy misbehave
+    pub(crate) output_dir: Option<PathBuf>,
+}
+
+/// A seed changing at every run, used when none is given
+pub(crate) fn random_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// SplitMix64, good enough to pick the mutations
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number between 0 and `n`, excluded
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FindingKind {
+    /// The policy failed to evaluate the request, like when it panics
+    Failure,
+    Timeout,
+    /// Two evaluations of the same request returned different responses
+    Inconsistency,
+    /// The policy accepted settings breaking its settings JSON Schema
+    InvalidSettingsAccepted,
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindingKind::Failure => write!(f, "failure"),
+            FindingKind::Timeout => write!(f, "timeout"),
+            FindingKind::Inconsistency => write!(f, "inconsistency"),
+            FindingKind::InvalidSettingsAccepted => write!(f, "invalid-settings-accepted"),
+        }
+    }
+}
+
+/// A request, or settings, that made the policy misbehave
+pub(crate) struct Finding {
+    pub(crate) kind: FindingKind,
+    pub(crate) iteration: u64,
+    /// The mutations applied to the seed request, or the description of the
+    /// generated settings
+    pub(crate) mutations: Vec<String>,
+    pub(crate) detail: String,
+    /// The request, or the settings when they are fuzzed
+    pub(crate) input: Value,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] iteration {} ({}): {}",
+            self.kind,
+            self.iteration,
+            self.mutations.join(", "),
+            self.detail
+        )
+    }
+}
+
+/// The policy to fuzz, ready to be instantiated by the evaluation threads
+pub(crate) struct FuzzedPolicy {
+    uri: String,
+    raw: bool,
+    settings_schema: Option<Value>,
+    policy_evaluator_pre: Arc<PolicyEvaluatorPre>,
+}
+
+impl FuzzedPolicy {
+    /// Looks for the policy inside of the store, or on disk, pulling it into
+    /// the store when missing
+    pub(crate) async fn fetch(
+        uri_or_sha_prefix: &str,
+        sources: Option<&Sources>,
+        mirrors: &RegistryMirrors,
+    ) -> Result<Self> {
+        let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_owned())?;
+        let uri = crate::version_constraints::resolve(&uri, sources).await?;
+        let wasm_path = crate::pull::local_path(&uri, sources, mirrors).await?;
+        let metadata = Metadata::from_path(&wasm_path)
+            .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+        let execution_mode = determine_execution_mode(
+            metadata.as_ref(),
+            None,
+            BackendDetector::default(),
+            &wasm_path,
+        )?;
+        Ok(FuzzedPolicy {
+            raw: has_raw_policy_type(metadata.as_ref()),
+            settings_schema: metadata
+                .as_ref()
+                .map(settings_schema)
+                .transpose()?
+                .flatten(),
+            policy_evaluator_pre: Arc::new(
+                PolicyEvaluatorBuilder::new()
+                    .policy_file(&wasm_path)?
+                    .execution_mode(execution_mode)
+                    .enable_wasmtime_cache()
+                    .build_pre()?,
+            ),
+            uri,
+        })
+    }
+}
+
+/// The policies are evaluated without access to the host capabilities
+fn rehydrate(policy_evaluator_pre: &PolicyEvaluatorPre, uri: &str) -> Result<PolicyEvaluator> {
+    let eval_ctx = EvaluationContext {
+        policy_id: uri.to_owned(),
+        callback_channel: None,
+        ctx_aware_resources_allow_list: BTreeSet::new(),
+    };
+    Ok(policy_evaluator_pre.rehydrate(&eval_ctx)?)
+}
+
+/// A request to evaluate with the settings, which are validated beforehand
+/// when `validate_settings` is set
+struct Job {
+    request: ValidateRequest,
+    settings: PolicySettings,
+    validate_settings: bool,
+}
+
+enum Evaluation {
+    Response(AdmissionResponse),
+    /// The policy rejected the settings, holds the reason
+    InvalidSettings(String),
+    Timeout,
+    Failure(String),
+}
+
+/// Thread evaluating the requests, so that the evaluations taking too long
+/// can be abandoned
+struct Worker {
+    requests: mpsc::Sender<Job>,
+    responses: mpsc::Receiver<Result<Evaluation, String>>,
+}
+
+impl Worker {
+    fn spawn(policy: &FuzzedPolicy) -> Self {
+        let (requests, requests_rx) = mpsc::channel::<Job>();
+        let (responses_tx, responses) = mpsc::channel();
+        let policy_evaluator_pre = policy.policy_evaluator_pre.clone();
+        let uri = policy.uri.clone();
+        thread::spawn(move || {
+            let mut evaluator = match rehydrate(&policy_evaluator_pre, &uri) {
+                Ok(evaluator) => evaluator,
+                Err(e) => {
+                    let _ = responses_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            for job in requests_rx {
+                let settings_validation_response = job
+                    .validate_settings
+                    .then(|| evaluator.validate_settings(&job.settings));
+                let evaluation = match settings_validation_response {
+                    Some(response) if !response.valid => {
+                        Evaluation::InvalidSettings(response.message.unwrap_or_default())
+                    }
+                    _ => Evaluation::Response(evaluator.validate(job.request, &job.settings)),
+                };
+                if responses_tx.send(Ok(evaluation)).is_err() {
+                    break;
+                }
+            }
+        });
+        Worker {
+            requests,
+            responses,
+        }
+    }
+}
+
+/// Evaluates the job. When the evaluation times out, the worker is replaced
+/// with a new one.
+fn evaluate(
+    worker: &mut Worker,
+    policy: &FuzzedPolicy,
+    job: Job,
+    timeout: Duration,
+) -> Result<Evaluation> {
+    if worker.requests.send(job).is_err() {
+        *worker = Worker::spawn(policy);
+        return Ok(Evaluation::Failure(
+            "the evaluation thread terminated".to_string(),
+        ));
+    }
+    match worker.responses.recv_timeout(timeout) {
+        Ok(Ok(evaluation)) => Ok(evaluation),
+        // the policy cannot be instantiated, no request can be evaluated
+        Ok(Err(e)) => Err(anyhow!("cannot instantiate the policy: {}", e)),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            *worker = Worker::spawn(policy);
+            Ok(Evaluation::Timeout)
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            *worker = Worker::spawn(policy);
+            Ok(Evaluation::Failure(
+                "the evaluation thread terminated".to_string(),
+            ))
+        }
+    }
+}
+
+/// Policies failing to evaluate a request, like when they panic, reject it
+/// with an internal server error
+fn failure_message(response: &AdmissionResponse) -> Option<String> {
+    let status = response.status.as_ref()?;
+    (!response.allowed && status.code == Some(500))
+        .then(|| status.message.clone().unwrap_or_default())
+}
+
+fn timeout_message(timeout: Duration) -> String {
+    format!(
+        "the evaluation took longer than {} seconds",
+        timeout.as_secs_f64()
+    )
+}
+
+/// Mutates the seed request `options.iterations` times, returning the
+/// requests that made the policy misbehave. Fails when the settings are not
+/// valid.
+pub(crate) fn fuzz(
+    policy: &FuzzedPolicy,
+    seed_request: &Value,
+    settings: &PolicySettings,
+    options: &FuzzOptions,
+) -> Result<Vec<Finding>> {
+    let raw = options.raw || policy.raw;
+    let roots = mutation_roots(seed_request, raw);
+    if roots.is_empty() {
+        return Err(anyhow!(
+            "the seed request has no object to mutate, the request must hold 'object' or 'oldObject'"
+        ));
+    }
+
+    let mut worker = Worker::spawn(policy);
+    let job = |request| Job {
+        request,
+        settings: settings.clone(),
+        validate_settings: false,
+    };
+    match evaluate(
+        &mut worker,
+        policy,
+        Job {
+            validate_settings: true,
+            ..job(build_validate_request(seed_request, raw)?)
+        },
+        options.timeout,
+    )? {
+        Evaluation::Response(response) if failure_message(&response).is_none() => {}
+        Evaluation::InvalidSettings(message) => {
+            return Err(anyhow!("Provided settings are not valid: {}", message))
+        }
+        _ => {
+            return Err(anyhow!(
+                "the policy cannot evaluate the seed request, fuzzing requires a request the policy evaluates successfully"
+            ))
+        }
+    }
+
+    let mut rng = Rng(options.seed);
+    let mut findings = Vec::new();
+    for iteration in 1..=options.iterations {
+        let mut request = seed_request.clone();
+        let mutations: Vec<String> = (0..=rng.below(MAX_MUTATIONS))
+            .filter_map(|_| mutate(&mut request, &roots, &mut rng))
+            .collect();
+        // the requests that are not admission requests anymore are skipped,
+        // they would be rejected by the API server
+        let Ok(validate_request) = build_validate_request(&request, raw) else {
+            continue;
+        };
+
+        let finding = |kind, detail| Finding {
+            kind,
+            iteration,
+            mutations: mutations.clone(),
+            detail,
+            input: request.clone(),
+        };
+        let first = match evaluate(
+            &mut worker,
+            policy,
+            job(validate_request.clone()),
+            options.timeout,
+        )? {
+            Evaluation::Response(response) => response,
+            Evaluation::Timeout => {
+                findings.push(finding(
+                    FindingKind::Timeout,
+                    timeout_message(options.timeout),
+                ));
+                continue;
+            }
+            Evaluation::Failure(e) | Evaluation::InvalidSettings(e) => {
+                findings.push(finding(FindingKind::Failure, e));
+                continue;
+            }
+        };
+        if let Some(message) = failure_message(&first) {
+            findings.push(finding(FindingKind::Failure, message));
+            continue;
+        }
+
+        let first = Verdict::from(first);
+        let second = match evaluate(&mut worker, policy, job(validate_request), options.timeout)? {
+            Evaluation::Response(response) => Verdict::from(response),
+            Evaluation::Timeout => Verdict::Failed("timed out".to_string()),
+            Evaluation::Failure(e) | Evaluation::InvalidSettings(e) => Verdict::Failed(e),
+        };
+        if first != second {
+            findings.push(finding(
+                FindingKind::Inconsistency,
+                format!("{first}, then {second}"),
+            ));
+        }
+    }
+
+    if let Some(output_dir) = &options.output_dir {
+        write_findings(&findings, output_dir)?;
+    }
+    Ok(findings)
+}
+
+/// Evaluates the request with settings generated from the settings JSON
+/// Schema of the policy `options.iterations` times, returning the settings
+/// that made the policy misbehave
+pub(crate) fn fuzz_settings(
+    policy: &FuzzedPolicy,
+    request: &Value,
+    options: &FuzzOptions,
+) -> Result<Vec<Finding>> {
+    let schema = policy.settings_schema.as_ref().ok_or_else(|| {
+        anyhow!("the policy has no settings JSON Schema, embed one via `kwctl annotate --settings-schema`")
+    })?;
+    let generator = settings::Generator::new(schema)?;
+    let validate_request = build_validate_request(request, options.raw || policy.raw)?;
+
+    let mut worker = Worker::spawn(policy);
+    let mut rng = Rng(options.seed);
+    let mut findings = Vec::new();
+    for iteration in 1..=options.iterations {
+        // half of the settings break the schema
+        let (settings, violation) = match (rng.below(2) == 0)
+            .then(|| generator.invalid(&mut rng))
+            .flatten()
+        {
+            Some((settings, violation)) => (settings, Some(violation)),
+            None => (generator.valid(&mut rng), None),
+        };
+        let finding = |kind, detail| Finding {
+            kind,
+            iteration,
+            mutations: vec![violation
+                .clone()
+                .unwrap_or_else(|| "valid settings".to_string())],
+            detail,
+            input: settings.clone(),
+        };
+
+        let job = Job {
+            request: validate_request.clone(),
+            settings: PolicySettings::try_from(&settings).map_err(anyhow::Error::msg)?,
+            validate_settings: true,
+        };
+        match evaluate(&mut worker, policy, job, options.timeout)? {
+            Evaluation::Response(response) => {
+                if let Some(message) = failure_message(&response) {
+                    findings.push(finding(FindingKind::Failure, message));
+                } else if violation.is_some() {
+                    findings.push(finding(
+                        FindingKind::InvalidSettingsAccepted,
+                        "the policy accepted the settings".to_string(),
+                    ));
+                }
+            }
+            Evaluation::InvalidSettings(_) => {}
+            Evaluation::Timeout => {
+                findings.push(finding(
+                    FindingKind::Timeout,
+                    timeout_message(options.timeout),
+                ));
+            }
+            Evaluation::Failure(e) => findings.push(finding(FindingKind::Failure, e)),
+        }
+    }
+
+    if let Some(output_dir) = &options.output_dir {
+        write_findings(&findings, output_dir)?;
+    }
+    Ok(findings)
+}
+
+/// Saves the requests, or the settings, of the findings as JSON files, which
+/// can be evaluated again via `kwctl run`
+fn write_findings(findings: &[Finding], output_dir: &Path) -> Result<()> {
+    fs::create_dir_all(output_dir)
+        .map_err(|e| anyhow!("cannot create {}: {}", output_dir.display(), e))?;
+    for finding in findings {
+        let path = output_dir.join(format!("{}-{}.json", finding.kind, finding.iteration));
+        fs::write(&path, serde_json::to_string_pretty(&finding.input)?)
+            .map_err(|e| anyhow!("cannot write {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// JSON pointers of the objects of the admission request that are mutated.
+/// Raw requests are mutated as a whole.
+fn mutation_roots(request: &Value, raw: bool) -> Vec<String> {
+    if raw {
+        return vec![String::new()];
+    }
+    let prefix = if request.get("kind").and_then(Value::as_str) == Some("AdmissionReview") {
+        "/request"
+    } else {
+        ""
+    };
+    ["object", "oldObject"]
+        .iter()
+        .map(|field| format!("{prefix}/{field}"))
+        .filter(|pointer| request.pointer(pointer).is_some_and(Value::is_object))
+        .collect()
+}
+
+/// JSON pointers of all the values below `pointer`, excluded
+fn descendants(value: &Value, pointer: &str, pointers: &mut Vec<String>) {
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| (key.replace('~', "~0").replace('/', "~1"), value))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (index.to_string(), value))
+            .collect(),
+        _ => return,
+    };
+    for (token, child) in children {
+        let child_pointer = format!("{pointer}/{token}");
+        descendants(child, &child_pointer, pointers);
+        pointers.push(child_pointer);
+    }
+}
+
+/// Applies a random mutation to a random value below one of the roots,
+/// returning its description
+fn mutate(request: &mut Value, roots: &[String], rng: &mut Rng) -> Option<String> {
+    let root = &roots[rng.below(roots.len())];
+    let mut pointers = Vec::new();
+    descendants(request.pointer(root)?, root, &mut pointers);
+    if pointers.is_empty() {
+        return None;
+    }
+    let pointer = pointers.swap_remove(rng.below(pointers.len()));
+
+    match rng.below(3) {
+        0 => {
+            let (parent, token) = pointer.rsplit_once('/')?;
+            let token = token.replace("~1", "/").replace("~0", "~");
+            match request.pointer_mut(parent)? {
+                Value::Object(fields) => {
+                    fields.remove(&token);
+                }
+                Value::Array(items) => {
+                    items.remove(token.parse::<usize>().ok()?);
+                }
+                _ => return None,
+            }
+            Some(format!("dropped {pointer}"))
+        }
+        1 => {
+            let value = request.pointer_mut(&pointer)?;
+            let candidates: Vec<Value> = [
+                Value::Null,
+                Value::Bool(true),
+                Value::from(-1),
+                Value::from(u64::MAX),
+                Value::from(""),
+                Value::Array(vec![]),
+                Value::Object(Default::default()),
+            ]
+            .into_iter()
+            .filter(|candidate| json_type(candidate) != json_type(value))
+            .collect();
+            let replacement = candidates[rng.below(candidates.len())].clone();
+            let description = format!(
+                "turned {pointer} from {} into {}",
+                json_type(value),
+                json_type(&replacement)
+            );
+            *value = replacement;
+            Some(description)
+        }
+        _ => {
+            *request.pointer_mut(&pointer)? = Value::from("x".repeat(HUGE_STRING_LENGTH));
+            Some(format!("injected a huge string into {pointer}"))
+        }
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn seed_request() -> Value {
+        json!({
+            "uid": "1299d386-525b-4032-98ae-1949f69f9cfc",
+            "kind": {"group": "", "version": "v1", "kind": "Pod"},
+            "resource": {"group": "", "version": "v1", "resource": "pods"},
+            "operation": "CREATE",
+            "userInfo": {"username": "alice"},
+            "object": {
+                "metadata": {"name": "nginx", "labels": {"app.kubernetes.io/name": "nginx"}},
+                "spec": {"containers": [{"name": "nginx", "image": "nginx"}]}
+            }
+        })
+    }
+
+    #[rstest]
+    #[case::admission_request(seed_request(), false, vec!["/object"])]
+    #[case::admission_review(
+        json!({"kind": "AdmissionReview", "request": seed_request()}),
+        false,
+        vec!["/request/object"]
+    )]
+    #[case::raw(json!({"user": "alice"}), true, vec![""])]
+    #[case::no_object(json!({"uid": "1"}), false, vec![])]
+    fn roots(#[case] request: Value, #[case] raw: bool, #[case] expected: Vec<&str>) {
+        assert_eq!(mutation_roots(&request, raw), expected);
+    }
+
+    #[test]
+    fn pointers_are_escaped() {
+        let request = seed_request();
+        let mut pointers = Vec::new();
+        descendants(&request["object"], "/object", &mut pointers);
+        assert!(pointers.contains(&"/object/metadata/labels/app.kubernetes.io~1name".to_string()));
+        assert!(pointers.contains(&"/object/spec/containers/0/image".to_string()));
+        for pointer in pointers {
+            assert!(request.pointer(&pointer).is_some(), "{pointer}");
+        }
+    }
+
+    #[test]
+    fn mutations_are_reproducible() {
+        let roots = vec!["/object".to_string()];
+        let mutated = |seed| {
+            let mut request = seed_request();
+            let mut rng = Rng(seed);
+            let mutations: Vec<String> = (0..10)
+                .filter_map(|_| mutate(&mut request, &roots, &mut rng))
+                .collect();
+            (request, mutations)
+        };
+
+        let (request, mutations) = mutated(42);
+        assert_eq!(mutated(42), (request.clone(), mutations.clone()));
+        assert!(!mutations.is_empty());
+        assert_ne!(request["object"], seed_request()["object"]);
+        // only the object is mutated
+        assert_eq!(request["userInfo"], json!({"username": "alice"}));
+    }
+
+    fn response(allowed: bool, message: &str, code: u16) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed,
+            status: Some(AdmissionResponseStatus {
+                message: Some(message.to_string()),
+                code: Some(code),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::internal_error(
+        response(false, "wasm trap: unreachable", 500),
+        Some("wasm trap: unreachable")
+    )]
+    #[case::rejection(response(false, "privileged containers are not allowed", 400), None)]
+    #[case::accepted(AdmissionResponse { allowed: true, ..Default::default() }, None)]
+    fn failures(#[case] response: AdmissionResponse, #[case] expected: Option<&str>) {
+        assert_eq!(failure_message(&response).as_deref(), expected);
+    }
+}
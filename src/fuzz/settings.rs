@@ -0,0 +1,530 @@
+//! Settings generated from the settings JSON Schema of a policy, for
+//! `kwctl fuzz --fuzz-settings`.
+//!
+//! Only the keywords describing the shape of the values are understood:
+//! `type`, `properties`, `required`, `additionalProperties`, `items`,
+//! `enum`, `const`, the bounds of numbers, strings and arrays, and the
+//! references to `$defs` and `definitions`. The invalid settings break
+//! exactly one of these keywords, the subschemas combined via `anyOf`,
+//! `oneOf`, `not` or `if` are never broken, since another branch could
+//! accept the value.
+
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+
+use super::Rng;
+
+/// Recursion limit, reached by recursive schemas
+const MAX_DEPTH: usize = 8;
+
+/// Keywords making a subschema too complex to be broken reliably
+const OPAQUE_KEYWORDS: &[&str] = &["allOf", "anyOf", "oneOf", "not", "if", "dependentSchemas"];
+
+/// Schema accepting any value, for the arrays without `items`
+static ANY: Value = Value::Bool(true);
+
+const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+
+pub(super) struct Generator<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Generator<'a> {
+    /// Fails when the schema does not describe an object, the only settings
+    /// accepted by the policies
+    pub(super) fn new(schema: &'a Value) -> Result<Self> {
+        let generator = Generator { root: schema };
+        match generator.types(schema).as_slice() {
+            ["object"] => Ok(generator),
+            _ => Err(anyhow!(
+                "the settings JSON Schema of the policy must describe an object"
+            )),
+        }
+    }
+
+    /// Settings valid according to the schema
+    pub(super) fn valid(&self, rng: &mut Rng) -> Value {
+        self.valid_value(self.root, rng, 0)
+    }
+
+    /// Settings breaking exactly one keyword of the schema, together with
+    /// the description of the broken keyword. None when no keyword can be
+    /// broken.
+    pub(super) fn invalid(&self, rng: &mut Rng) -> Option<(Value, String)> {
+        self.invalid_value(self.root, "settings", rng, 0)
+    }
+
+    /// Follows the local references, `$defs` and `definitions`
+    fn resolve(&self, schema: &'a Value) -> &'a Value {
+        let mut schema = schema;
+        for _ in 0..MAX_DEPTH {
+            match schema
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix('#'))
+                .and_then(|pointer| self.root.pointer(pointer))
+            {
+                Some(target) => schema = target,
+                None => break,
+            }
+        }
+        schema
+    }
+
+    /// The types allowed by the schema, guessed from its keywords when not
+    /// given
+    fn types(&self, schema: &'a Value) -> Vec<&'a str> {
+        let schema = self.resolve(schema);
+        match schema.get("type") {
+            Some(Value::String(t)) => return vec![t.as_str()],
+            Some(Value::Array(types)) => return types.iter().filter_map(Value::as_str).collect(),
+            _ => {}
+        }
+        if ["properties", "required", "additionalProperties"]
+            .iter()
+            .any(|keyword| schema.get(keyword).is_some())
+        {
+            vec!["object"]
+        } else if schema.get("items").is_some() {
+            vec!["array"]
+        } else {
+            vec![]
+        }
+    }
+
+    fn valid_value(&self, schema: &'a Value, rng: &mut Rng, depth: usize) -> Value {
+        let schema = self.resolve(schema);
+        if let Some(value) = schema.get("const") {
+            return value.clone();
+        }
+        if let Some(Value::Array(values)) = schema.get("enum") {
+            if !values.is_empty() {
+                return values[rng.below(values.len())].clone();
+            }
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(Value::Array(branches)) = schema.get(keyword) {
+                if !branches.is_empty() {
+                    return self.valid_value(&branches[rng.below(branches.len())], rng, depth + 1);
+                }
+            }
+        }
+
+        let types = self.types(schema);
+        let value_type = if types.is_empty() {
+            "string"
+        } else {
+            types[rng.below(types.len())]
+        };
+        match value_type {
+            "object" => {
+                let mut object = Map::new();
+                if depth >= MAX_DEPTH {
+                    return Value::Object(object);
+                }
+                let required = required(schema);
+                if let Some(Value::Object(properties)) = schema.get("properties") {
+                    for (name, property) in properties {
+                        // the optional properties are set half of the times
+                        if required.contains(&name.as_str()) || rng.below(2) == 0 {
+                            object.insert(name.clone(), self.valid_value(property, rng, depth + 1));
+                        }
+                    }
+                }
+                Value::Object(object)
+            }
+            "array" => {
+                let min = count(schema, "minItems").unwrap_or(0);
+                let max = count(schema, "maxItems").unwrap_or(min + 3).max(min);
+                let length = if depth >= MAX_DEPTH {
+                    0
+                } else {
+                    min + rng.below(max.min(min + 3) - min + 1)
+                };
+                let items = schema.get("items").unwrap_or(&ANY);
+                Value::Array(
+                    (0..length)
+                        .map(|_| self.valid_value(items, rng, depth + 1))
+                        .collect(),
+                )
+            }
+            "integer" | "number" => {
+                let (low, high) = bounds(schema, value_type == "integer");
+                if value_type == "integer" {
+                    let (low, high) = (low.ceil() as i64, high.floor() as i64);
+                    if high <= low {
+                        return Value::from(low);
+                    }
+                    Value::from(low + rng.below((high - low + 1) as usize) as i64)
+                } else {
+                    Value::from(low + (high - low) * (rng.next() as f64 / u64::MAX as f64))
+                }
+            }
+            "string" => {
+                // the values matching a pattern or a format cannot be
+                // generated, the examples given by the schema are used
+                if schema.get("pattern").is_some() || schema.get("format").is_some() {
+                    if let Some(example) = schema.get("default").or_else(|| {
+                        schema
+                            .get("examples")
+                            .and_then(Value::as_array)
+                            .and_then(|examples| examples.first())
+                    }) {
+                        return example.clone();
+                    }
+                }
+                let min = count(schema, "minLength").unwrap_or(0);
+                let max = count(schema, "maxLength").unwrap_or(min + 8).max(min);
+                let length = min + rng.below(max.min(min + 8) - min + 1);
+                Value::from(
+                    (0..length)
+                        .map(|_| ALPHABET[rng.below(ALPHABET.len())] as char)
+                        .collect::<String>(),
+                )
+            }
+            "boolean" => Value::Bool(rng.below(2) == 0),
+            _ => Value::Null,
+        }
+    }
+
+    fn invalid_value(
+        &self,
+        schema: &'a Value,
+        path: &str,
+        rng: &mut Rng,
+        depth: usize,
+    ) -> Option<(Value, String)> {
+        let schema = self.resolve(schema);
+        if depth >= MAX_DEPTH
+            || OPAQUE_KEYWORDS
+                .iter()
+                .any(|keyword| schema.get(keyword).is_some())
+        {
+            return None;
+        }
+
+        let mut violations = self.violations(schema, path, rng, depth);
+        // the settings must stay an object
+        if depth == 0 {
+            violations.retain(|(value, _)| value.is_object());
+        }
+
+        // either a keyword of this schema is broken, or the one of a property
+        if self.types(schema) == ["object"] && (violations.is_empty() || rng.below(2) == 0) {
+            if let Some(Value::Object(properties)) = schema.get("properties") {
+                let names: Vec<&String> = properties.keys().collect();
+                if !names.is_empty() {
+                    let name = names[rng.below(names.len())];
+                    if let Some((value, description)) = self.invalid_value(
+                        &properties[name],
+                        &format!("{path}.{name}"),
+                        rng,
+                        depth + 1,
+                    ) {
+                        let mut object = self.valid_value(schema, rng, depth);
+                        object.as_object_mut()?.insert(name.clone(), value);
+                        return Some((object, description));
+                    }
+                }
+            }
+        }
+
+        if violations.is_empty() {
+            None
+        } else {
+            Some(violations.swap_remove(rng.below(violations.len())))
+        }
+    }
+
+    /// Values breaking one of the keywords of the schema
+    fn violations(
+        &self,
+        schema: &'a Value,
+        path: &str,
+        rng: &mut Rng,
+        depth: usize,
+    ) -> Vec<(Value, String)> {
+        let mut violations = Vec::new();
+
+        let types = self.types(schema);
+        if !types.is_empty() {
+            let wrong_types: Vec<(&str, Value)> = [
+                ("null", Value::Null),
+                ("boolean", Value::Bool(true)),
+                ("integer", Value::from(42)),
+                ("number", Value::from(1.5)),
+                ("string", Value::from("kwctl")),
+                ("array", Value::Array(vec![])),
+                ("object", Value::Object(Map::new())),
+            ]
+            .into_iter()
+            // integers are numbers too
+            .filter(|(t, _)| !types.contains(t) && !(*t == "integer" && types.contains(&"number")))
+            .collect();
+            if !wrong_types.is_empty() {
+                let (t, value) = wrong_types[rng.below(wrong_types.len())].clone();
+                violations.push((
+                    value,
+                    format!("{path} is a {t}, instead of a {}", types.join(" or ")),
+                ));
+            }
+        }
+
+        let allowed: Option<Vec<&Value>> = match (schema.get("const"), schema.get("enum")) {
+            (Some(value), _) => Some(vec![value]),
+            (None, Some(Value::Array(values))) => Some(values.iter().collect()),
+            _ => None,
+        };
+        if let Some(allowed) = allowed {
+            if let Some(value) = [Value::from("kwctl-fuzz"), Value::from(-42), Value::Null]
+                .into_iter()
+                .find(|value| !allowed.contains(&value))
+            {
+                violations.push((value, format!("{path} is not one of the allowed values")));
+            }
+            // the other keywords don't matter, the allowed values are given
+            return violations;
+        }
+
+        let integer = types == ["integer"];
+        if types.contains(&"integer") || types.contains(&"number") {
+            let number = |value: f64| {
+                if integer {
+                    Value::from(value as i64)
+                } else {
+                    Value::from(value)
+                }
+            };
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+                violations.push((
+                    number((minimum - 1.0).floor()),
+                    format!("{path} is below its minimum, {minimum}"),
+                ));
+            }
+            if let Some(minimum) = schema.get("exclusiveMinimum").and_then(Value::as_f64) {
+                violations.push((
+                    number(minimum.floor()),
+                    format!("{path} is not above its exclusive minimum, {minimum}"),
+                ));
+            }
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+                violations.push((
+                    number((maximum + 1.0).ceil()),
+                    format!("{path} is above its maximum, {maximum}"),
+                ));
+            }
+            if let Some(maximum) = schema.get("exclusiveMaximum").and_then(Value::as_f64) {
+                violations.push((
+                    number(maximum.ceil()),
+                    format!("{path} is not below its exclusive maximum, {maximum}"),
+                ));
+            }
+        }
+
+        if types.contains(&"string") {
+            if let Some(min) = count(schema, "minLength").filter(|min| *min > 0) {
+                violations.push((
+                    Value::from("x".repeat(min - 1)),
+                    format!("{path} is shorter than {min} characters"),
+                ));
+            }
+            if let Some(max) = count(schema, "maxLength") {
+                violations.push((
+                    Value::from("x".repeat(max + 1)),
+                    format!("{path} is longer than {max} characters"),
+                ));
+            }
+        }
+
+        if types.contains(&"array") {
+            let items = schema.get("items").unwrap_or(&ANY);
+            let array = |length: usize, rng: &mut Rng| {
+                Value::Array(
+                    (0..length)
+                        .map(|_| self.valid_value(items, rng, depth + 1))
+                        .collect(),
+                )
+            };
+            if let Some(min) = count(schema, "minItems").filter(|min| *min > 0) {
+                violations.push((
+                    array(min - 1, rng),
+                    format!("{path} has less than {min} items"),
+                ));
+            }
+            if let Some(max) = count(schema, "maxItems") {
+                violations.push((
+                    array(max + 1, rng),
+                    format!("{path} has more than {max} items"),
+                ));
+            }
+        }
+
+        if types == ["object"] {
+            for name in required(schema) {
+                let mut object = self.valid_value(schema, rng, depth);
+                if let Some(object) = object.as_object_mut() {
+                    object.remove(name);
+                }
+                violations.push((object, format!("{path}.{name} is missing")));
+            }
+            if schema.get("additionalProperties") == Some(&Value::Bool(false)) {
+                let mut object = self.valid_value(schema, rng, depth);
+                if let Some(object) = object.as_object_mut() {
+                    object.insert("kwctlFuzz".to_string(), Value::Bool(true));
+                }
+                violations.push((
+                    object,
+                    format!("{path}.kwctlFuzz is not one of the properties"),
+                ));
+            }
+        }
+
+        violations
+    }
+}
+
+fn required(schema: &Value) -> Vec<&str> {
+    schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|required| required.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default()
+}
+
+fn count(schema: &Value, keyword: &str) -> Option<usize> {
+    schema
+        .get(keyword)
+        .and_then(Value::as_u64)
+        .map(|count| count as usize)
+}
+
+/// The range of the valid numbers, a hundred wide when unbounded
+fn bounds(schema: &Value, integer: bool) -> (f64, f64) {
+    // the smallest step above an exclusive bound
+    let step = if integer { 1.0 } else { f64::EPSILON };
+    let low = schema.get("minimum").and_then(Value::as_f64).or_else(|| {
+        schema
+            .get("exclusiveMinimum")
+            .and_then(Value::as_f64)
+            .map(|minimum| minimum + step)
+    });
+    let high = schema.get("maximum").and_then(Value::as_f64).or_else(|| {
+        schema
+            .get("exclusiveMaximum")
+            .and_then(Value::as_f64)
+            .map(|maximum| maximum - step)
+    });
+    match (low, high) {
+        (Some(low), Some(high)) => (low, high.max(low)),
+        (Some(low), None) => (low, low + 100.0),
+        (None, Some(high)) => (high - 100.0, high),
+        (None, None) => (-50.0, 50.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "replicas": {"type": "integer", "minimum": 1, "maximum": 5},
+                "mode": {"enum": ["monitor", "protect"]},
+                "labels": {
+                    "type": "array",
+                    "items": {"$ref": "#/$defs/label"},
+                    "maxItems": 2
+                }
+            },
+            "required": ["replicas"],
+            "additionalProperties": false,
+            "$defs": {
+                "label": {"type": "string", "minLength": 1, "maxLength": 3}
+            }
+        })
+    }
+
+    #[test]
+    fn valid_settings() {
+        let schema = schema();
+        let generator = Generator::new(&schema).unwrap();
+        for seed in 0..50 {
+            let settings = generator.valid(&mut Rng(seed));
+            let replicas = settings["replicas"].as_i64().unwrap();
+            assert!((1..=5).contains(&replicas), "{settings}");
+            if let Some(mode) = settings.get("mode") {
+                assert!(mode == "monitor" || mode == "protect", "{settings}");
+            }
+            if let Some(labels) = settings.get("labels") {
+                let labels = labels.as_array().unwrap();
+                assert!(labels.len() <= 2, "{settings}");
+                for label in labels {
+                    assert!(
+                        (1..=3).contains(&label.as_str().unwrap().len()),
+                        "{settings}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_settings_stay_objects() {
+        let schema = schema();
+        let generator = Generator::new(&schema).unwrap();
+        for seed in 0..50 {
+            let (settings, description) = generator.invalid(&mut Rng(seed)).unwrap();
+            assert!(settings.is_object(), "{description}: {settings}");
+            assert!(description.starts_with("settings"), "{description}");
+        }
+    }
+
+    #[rstest]
+    #[case::missing(
+        json!({"type": "object", "properties": {"a": {"const": 1}}, "required": ["a"]}),
+        json!({}),
+        "settings.a is missing"
+    )]
+    #[case::additional_property(
+        json!({"type": "object", "additionalProperties": false}),
+        json!({"kwctlFuzz": true}),
+        "settings.kwctlFuzz is not one of the properties"
+    )]
+    #[case::below_minimum(
+        json!({"type": "object", "properties": {"a": {"type": "integer", "minimum": 3}}, "required": ["a"]}),
+        json!({"a": 2}),
+        "settings.a is below its minimum, 3"
+    )]
+    fn single_violations(
+        #[case] schema: Value,
+        #[case] expected: Value,
+        #[case] description: &str,
+    ) {
+        let generator = Generator::new(&schema).unwrap();
+        let found = (0..50)
+            .filter_map(|seed| generator.invalid(&mut Rng(seed)))
+            .any(|(settings, found)| settings == expected && found == description);
+        assert!(found, "{description} never generated");
+    }
+
+    #[rstest]
+    #[case::not_an_object(json!({"type": "string"}))]
+    #[case::no_type(json!({"description": "anything"}))]
+    fn settings_must_be_objects(#[case] schema: Value) {
+        assert!(Generator::new(&schema).is_err());
+    }
+
+    #[test]
+    fn combined_schemas_are_not_broken() {
+        let schema = json!({
+            "type": "object",
+            "anyOf": [{"required": ["a"]}, {"required": ["b"]}]
+        });
+        let generator = Generator::new(&schema).unwrap();
+        assert!((0..20).all(|seed| generator.invalid(&mut Rng(seed)).is_none()));
+    }
+}
@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_metadata::{ContextAwareResource, Metadata};
+
+use crate::config::policy_definition::{ContextAwareConfiguration, PolicyDefinition};
+
+/// Output formats of the graph
+pub(crate) enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl TryFrom<&str> for GraphFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            unknown => Err(anyhow!("Invalid output format '{}'", unknown)),
+        }
+    }
+}
+
+/// Host capabilities a policy can use
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Capability {
+    Kubernetes,
+    OciManifest,
+    OciVerify,
+    Dns,
+    Crypto,
+}
+
+impl Capability {
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Self::Kubernetes => "kubernetes: read cluster resources",
+            Self::OciManifest => "oci: fetch manifests",
+            Self::OciVerify => "oci: verify signatures",
+            Self::Dns => "net: DNS lookups",
+            Self::Crypto => "crypto: verify certificates",
+        }
+    }
+}
+
+// Operations of the host capabilities, as invoked by the Kubewarden SDKs.
+// Finding them inside of the module means the policy can perform them.
+const CAPABILITY_OPERATIONS: &[(&str, Capability)] = &[
+    ("list_resources_by_namespace", Capability::Kubernetes),
+    ("list_resources_all", Capability::Kubernetes),
+    ("get_resource", Capability::Kubernetes),
+    ("v1/manifest_digest", Capability::OciManifest),
+    ("v1/oci_manifest", Capability::OciManifest),
+    ("v1/verify", Capability::OciVerify),
+    ("v2/verify", Capability::OciVerify),
+    ("v1/dns_lookup_host", Capability::Dns),
+    ("v1/is_certificate_trusted", Capability::Crypto),
+];
+
+/// Detects the host capabilities used by a policy, looking for the names of
+/// their operations inside of the WebAssembly module
+pub(crate) fn detect_capabilities(wasm: &[u8]) -> BTreeSet<Capability> {
+    CAPABILITY_OPERATIONS
+        .iter()
+        .filter(|(operation, _)| {
+            wasm.windows(operation.len())
+                .any(|window| window == operation.as_bytes())
+        })
+        .map(|(_, capability)| *capability)
+        .collect()
+}
+
+/// A policy, with the host capabilities it uses and the cluster resources it
+/// interacts with
+#[derive(Debug, Default)]
+struct PolicyNode {
+    name: String,
+    capabilities: BTreeSet<Capability>,
+    /// Resources read at evaluation time (context aware policies)
+    reads: BTreeSet<String>,
+    /// Resources whose admission requests are evaluated by the policy
+    validates: BTreeSet<String>,
+}
+
+fn context_aware_resource_name(resource: &ContextAwareResource) -> String {
+    format!("{}/{}", resource.api_version, resource.kind)
+}
+
+impl PolicyNode {
+    /// Builds the node of the policy stored at `wasm_path`. When `granted` is
+    /// given, it replaces the context aware resources declared by the metadata
+    /// of the policy, like the allow list of a Kubewarden Custom Resource does.
+    fn new(
+        name: String,
+        wasm_path: &Path,
+        granted: Option<&BTreeSet<ContextAwareResource>>,
+    ) -> Result<Self> {
+        let wasm = fs::read(wasm_path)
+            .map_err(|e| anyhow!("cannot read policy {}: {}", wasm_path.display(), e))?;
+        let metadata = Metadata::from_path(wasm_path)
+            .map_err(|e| anyhow!("cannot read the metadata of {}: {}", name, e))?;
+
+        let mut node = PolicyNode {
+            name,
+            capabilities: detect_capabilities(&wasm),
+            ..Default::default()
+        };
+        if let Some(metadata) = &metadata {
+            for rule in &metadata.rules {
+                for group in &rule.api_groups {
+                    for version in &rule.api_versions {
+                        for resource in &rule.resources {
+                            let group = if group.is_empty() { "core" } else { group };
+                            node.validates
+                                .insert(format!("{group}/{version}/{resource}"));
+                        }
+                    }
+                }
+            }
+        }
+        let declared = metadata.map(|metadata| metadata.context_aware_resources);
+        node.reads = granted
+            .or(declared.as_ref())
+            .into_iter()
+            .flatten()
+            .map(context_aware_resource_name)
+            .collect();
+        if !node.reads.is_empty() {
+            node.capabilities.insert(Capability::Kubernetes);
+        }
+
+        Ok(node)
+    }
+}
+
+/// Policies and the host capabilities and cluster resources they use
+#[derive(Debug, Default)]
+pub(crate) struct Graph {
+    policies: Vec<PolicyNode>,
+}
+
+impl Graph {
+    /// Graph of all the policies of the local store
+    pub(crate) fn from_store() -> Result<Self> {
+        let mut policies = crate::store_profile::store()
+            .list()?
+            .into_iter()
+            .map(|policy| PolicyNode::new(policy.uri, &policy.local_path, None))
+            .collect::<Result<Vec<_>>>()?;
+        policies.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(Self { policies })
+    }
+
+    /// Graph of the policies referenced by Kubewarden Custom Resources. The
+    /// policies must have been pulled already. The resources read by the
+    /// policies are the ones allowed by the Custom Resources.
+    pub(crate) fn from_policy_definitions(policy_definitions: &[PolicyDefinition]) -> Result<Self> {
+        let mut policies = Vec::new();
+        for policy_definition in policy_definitions {
+            match policy_definition {
+                PolicyDefinition::Policy {
+                    id,
+                    uri,
+                    ctx_aware_cfg,
+                    ..
+                } => {
+                    let granted = match ctx_aware_cfg {
+                        ContextAwareConfiguration::AllowList(allow_list) => Some(allow_list),
+                        _ => None,
+                    };
+                    policies.push(PolicyNode::new(
+                        format!("{id} ({uri})"),
+                        &local_path(uri)?,
+                        granted,
+                    )?);
+                }
+                PolicyDefinition::PolicyGroup {
+                    id, policy_members, ..
+                } => {
+                    let members: BTreeMap<_, _> = policy_members.iter().collect();
+                    for (member_id, member) in members {
+                        policies.push(PolicyNode::new(
+                            format!("{id}/{member_id} ({})", member.uri),
+                            &local_path(&member.uri)?,
+                            Some(&member.settings.ctx_aware_resources_allow_list),
+                        )?);
+                    }
+                }
+            }
+        }
+
+        Ok(Self { policies })
+    }
+
+    pub(crate) fn render(&self, format: &GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Mermaid => self.to_mermaid(),
+        }
+    }
+
+    /// All the edges of the graph: policy index, label, target
+    fn edges(&self) -> Vec<(usize, &'static str, String)> {
+        let mut edges = Vec::new();
+        for (index, policy) in self.policies.iter().enumerate() {
+            for capability in &policy.capabilities {
+                edges.push((index, "uses", capability.label().to_string()));
+            }
+            for resource in &policy.reads {
+                edges.push((index, "reads", resource.to_owned()));
+            }
+            for resource in &policy.validates {
+                edges.push((index, "validates", resource.to_owned()));
+            }
+        }
+        edges
+    }
+
+    fn targets(&self) -> BTreeSet<String> {
+        self.edges()
+            .into_iter()
+            .map(|(_, _, target)| target)
+            .collect()
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph policies {\n  rankdir=LR;\n");
+        for policy in &self.policies {
+            let _ = writeln!(dot, "  {} [shape=box];", dot_id(&policy.name));
+        }
+        for target in self.targets() {
+            let _ = writeln!(dot, "  {} [shape=ellipse];", dot_id(&target));
+        }
+        for (index, label, target) in self.edges() {
+            let _ = writeln!(
+                dot,
+                "  {} -> {} [label=\"{}\"];",
+                dot_id(&self.policies[index].name),
+                dot_id(&target),
+                label
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        let mut mermaid = String::from("flowchart LR\n");
+        for (index, policy) in self.policies.iter().enumerate() {
+            let _ = writeln!(
+                mermaid,
+                "  policy{}[\"{}\"]",
+                index,
+                mermaid_label(&policy.name)
+            );
+        }
+        let targets: BTreeMap<String, usize> = self
+            .targets()
+            .into_iter()
+            .enumerate()
+            .map(|(index, target)| (target, index))
+            .collect();
+        for (target, index) in &targets {
+            let _ = writeln!(
+                mermaid,
+                "  target{}([\"{}\"])",
+                index,
+                mermaid_label(target)
+            );
+        }
+        for (index, label, target) in self.edges() {
+            let _ = writeln!(
+                mermaid,
+                "  policy{} -- {} --> target{}",
+                index, label, targets[&target]
+            );
+        }
+        mermaid
+    }
+}
+
+fn local_path(uri: &str) -> Result<PathBuf> {
+    crate::utils::wasm_path(uri)
+        .map_err(|e| anyhow!("cannot find policy {}: {}. Pull it first", uri, e))
+}
+
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn mermaid_label(name: &str) -> String {
+    name.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::no_host_calls(b"\0asm policy without host calls".to_vec(), vec![])]
+    #[case::sigstore(
+        b"\0asm kubewarden oci v2/verify v1/manifest_digest".to_vec(),
+        vec![Capability::OciManifest, Capability::OciVerify]
+    )]
+    #[case::context_aware(
+        b"\0asm kubewarden kubernetes get_resource net v1/dns_lookup_host".to_vec(),
+        vec![Capability::Kubernetes, Capability::Dns]
+    )]
+    fn capabilities_detection(#[case] wasm: Vec<u8>, #[case] expected: Vec<Capability>) {
+        assert_eq!(
+            detect_capabilities(&wasm),
+            expected.into_iter().collect::<BTreeSet<_>>()
+        );
+    }
+
+    fn graph() -> Graph {
+        Graph {
+            policies: vec![PolicyNode {
+                name: "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0"
+                    .to_string(),
+                capabilities: BTreeSet::from([Capability::OciVerify]),
+                reads: BTreeSet::from(["v1/Namespace".to_string()]),
+                validates: BTreeSet::from(["core/v1/pods".to_string()]),
+            }],
+        }
+    }
+
+    #[test]
+    fn dot_output() {
+        assert_eq!(
+            graph().render(&GraphFormat::Dot),
+            r#"digraph policies {
+  rankdir=LR;
+  "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0" [shape=box];
+  "core/v1/pods" [shape=ellipse];
+  "oci: verify signatures" [shape=ellipse];
+  "v1/Namespace" [shape=ellipse];
+  "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0" -> "oci: verify signatures" [label="uses"];
+  "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0" -> "v1/Namespace" [label="reads"];
+  "registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0" -> "core/v1/pods" [label="validates"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn mermaid_output() {
+        assert_eq!(
+            graph().render(&GraphFormat::Mermaid),
+            r#"flowchart LR
+  policy0["registry://ghcr.io/kubewarden/policies/verify-image-signatures:v0.3.0"]
+  target0(["core/v1/pods"])
+  target1(["oci: verify signatures"])
+  target2(["v1/Namespace"])
+  policy0 -- uses --> target1
+  policy0 -- reads --> target2
+  policy0 -- validates --> target0
+"#
+        );
+    }
+}
@@ -1,10 +1,7 @@
 use anyhow::Result;
 use clap::crate_version;
 use itertools::Itertools;
-use policy_evaluator::{
-    burrego,
-    policy_fetcher::store::{Store, DEFAULT_ROOT},
-};
+use policy_evaluator::burrego;
 
 pub(crate) fn info() -> Result<()> {
     let builtins: String = burrego::get_builtins()
@@ -13,7 +10,7 @@ pub(crate) fn info() -> Result<()> {
         .map(|builtin| format!("  - {builtin}"))
         .join("\n");
 
-    let store = Store::default();
+    let store = crate::store_profile::store();
 
     println!(
         r#"kwctl version: {}
@@ -28,8 +25,8 @@ kwctl cache directory: {}
         crate_version!(),
         builtins,
         store.root.to_string_lossy(),
-        DEFAULT_ROOT.config_dir().to_string_lossy(),
-        crate::scaffold::DEFAULT_KWCTL_CACHE.to_string_lossy(),
+        crate::store_profile::config_dir().to_string_lossy(),
+        crate::store_profile::kwctl_cache_dir().to_string_lossy(),
     );
 
     Ok(())
@@ -11,14 +11,11 @@ use policy_evaluator::{
     constants::*,
     policy_evaluator::PolicyExecutionMode,
     policy_fetcher::{
-        oci_client::{
-            manifest::{OciImageManifest, OciManifest},
-            secrets::RegistryAuth,
-        },
+        oci_client::manifest::{OciImageManifest, OciManifest},
         registry::Registry,
         sigstore::{
             cosign::{ClientBuilder, CosignCapabilities},
-            registry::{oci_reference::OciReference, Auth, ClientConfig},
+            registry::{oci_reference::OciReference, ClientConfig},
         },
         sources::Sources,
     },
@@ -27,21 +24,67 @@ use policy_evaluator::{
 use prettytable::{format::FormatBuilder, row, Table};
 use termimad::{terminal_size, FmtText, MadSkin};
 
+use crate::{
+    annotations::SETTINGS_SCHEMA_ANNOTATION,
+    config::{registry_auth::sigstore_auth, sources::RegistryMirrors},
+    provenance::Provenance,
+    wasm_interface::WasmInterface,
+};
+
+/// Where the inspected policy is looked for
+pub(crate) enum Location<'a> {
+    /// The local store or the filesystem
+    Local,
+    /// The registry, the metadata is fetched without pulling the policy
+    /// whenever its OCI manifest holds it
+    Remote(&'a RegistryMirrors),
+}
+
 pub(crate) async fn inspect(
     uri_or_sha_prefix: &str,
+    location: Location<'_>,
     output: OutputType,
     sources: Option<Sources>,
     no_color: bool,
     no_signatures: bool,
+    settings_schema_only: bool,
 ) -> Result<()> {
-    let uri = crate::utils::map_path_to_uri(uri_or_sha_prefix)?;
-    let wasm_path = crate::utils::wasm_path(&uri)?;
+    if let (OutputType::Json, false) = (&output, settings_schema_only) {
+        return Err(anyhow!(
+            "the json output format is available only together with --settings-schema-only"
+        ));
+    }
     let metadata_printer = MetadataPrinter::from(&output);
 
-    let metadata = Metadata::from_path(&wasm_path)
-        .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+    let (uri, wasm_path, metadata) = match location {
+        Location::Local => {
+            let uri = crate::utils::map_path_to_uri(uri_or_sha_prefix)?;
+            let wasm_path = crate::utils::wasm_path(&uri)?;
+            let metadata = Metadata::from_path(&wasm_path)
+                .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+            (uri, Some(wasm_path), metadata)
+        }
+        Location::Remote(mirrors) => {
+            if !uri_or_sha_prefix.starts_with("registry://") {
+                return Err(anyhow!(
+                    "--remote requires a registry:// URI, got '{}'",
+                    uri_or_sha_prefix
+                ));
+            }
+            let metadata =
+                crate::changelog::fetch_metadata(uri_or_sha_prefix, sources.as_ref(), mirrors)
+                    .await?;
+            (uri_or_sha_prefix.to_string(), None, Some(metadata))
+        }
+    };
 
     match metadata {
+        Some(metadata) if settings_schema_only => {
+            let schema = settings_schema(&metadata)?.ok_or_else(|| {
+                anyhow!("the metadata of '{}' does not embed a settings JSON Schema", uri)
+            })?;
+            return print_settings_schema(&schema, &output);
+        }
         Some(metadata) => metadata_printer.print(&metadata, no_color)?,
         None => return Err(anyhow!(
             "No Kubewarden metadata found inside of '{}'.\nPolicies can be annotated with the `kwctl annotate` command.",
@@ -49,6 +92,25 @@ pub(crate) async fn inspect(
         )),
     };
 
+    // the provenance refers to the copy of the policy inside of the store
+    let provenance = wasm_path
+        .as_ref()
+        .and_then(|_| crate::provenance::load().remove(&uri));
+    if let (OutputType::Pretty, Some(provenance)) = (&output, provenance) {
+        println!();
+        print_provenance(&provenance);
+    }
+
+    if let (OutputType::Pretty, Some(wasm_path)) = (&output, &wasm_path) {
+        let wasm = std::fs::read(wasm_path)
+            .map_err(|e| anyhow!("cannot read {}: {}", wasm_path.display(), e))?;
+        let interface = WasmInterface::parse(&wasm)?;
+        println!();
+        interface.print();
+        println!();
+        interface.print_sizes();
+    }
+
     if no_signatures {
         return Ok(());
     }
@@ -63,11 +125,7 @@ pub(crate) async fn inspect(
         }
         Err(error) => {
             println!();
-            if error
-                .to_string()
-                .as_str()
-                .starts_with("OCI API error: manifest unknown on")
-            {
+            if is_unsigned(&error) {
                 println!("No sigstore signatures found");
             } else {
                 println!("Cannot determine if the policy has been signed. There was an error while attempting to fetch its signatures from the remote registry: {error} ")
@@ -78,8 +136,55 @@ pub(crate) async fn inspect(
     Ok(())
 }
 
+/// The JSON Schema of the settings embedded into the metadata, if any
+pub(crate) fn settings_schema(metadata: &Metadata) -> Result<Option<serde_json::Value>> {
+    metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(SETTINGS_SCHEMA_ANNOTATION))
+        .map(|schema| {
+            serde_json::from_str(schema)
+                .map_err(|e| anyhow!("invalid settings JSON Schema inside of the metadata: {}", e))
+        })
+        .transpose()
+}
+
+/// Dumps just the settings JSON Schema, for `--settings-schema-only`
+fn print_settings_schema(schema: &serde_json::Value, output: &OutputType) -> Result<()> {
+    match output {
+        OutputType::Yaml => print!("{}", serde_yaml::to_string(schema)?),
+        OutputType::Json | OutputType::Pretty => {
+            println!("{}", serde_json::to_string_pretty(schema)?)
+        }
+    }
+    Ok(())
+}
+
+/// Prints where the policy of the store comes from, see [`crate::provenance`]
+fn print_provenance(provenance: &Provenance) {
+    let mut table = Table::new();
+    table.set_format(FormatBuilder::new().padding(0, 1).build());
+    table.add_row(row![Fmbl -> "Provenance"]);
+    table.add_row(row![Fgbl -> "source:", provenance.source]);
+    table.add_row(row![
+        Fgbl -> "pulled:",
+        crate::timestamps::format_unix(provenance.pulled).unwrap_or_default()
+    ]);
+    table.add_row(row![Fgbl -> "sha256:", provenance.digest]);
+    table.add_row(row![
+        Fgbl -> "signatures:",
+        provenance
+            .verification
+            .as_ref()
+            .map_or("not verified".to_string(), ToString::to_string)
+    ]);
+    table.printstd();
+}
+
 pub(crate) enum OutputType {
     Yaml,
+    /// Available only together with `--settings-schema-only`
+    Json,
     Pretty,
 }
 
@@ -89,6 +194,7 @@ impl TryFrom<Option<&str>> for OutputType {
     fn try_from(value: Option<&str>) -> Result<Self, Self::Error> {
         match value {
             Some("yaml") => Ok(Self::Yaml),
+            Some("json") => Ok(Self::Json),
             None => Ok(Self::Pretty),
             Some(unknown) => Err(anyhow!("Invalid output format '{}'", unknown)),
         }
@@ -103,7 +209,7 @@ enum MetadataPrinter {
 impl From<&OutputType> for MetadataPrinter {
     fn from(output_type: &OutputType) -> Self {
         match output_type {
-            OutputType::Yaml => Self::Yaml,
+            OutputType::Yaml | OutputType::Json => Self::Yaml,
             OutputType::Pretty => Self::Pretty,
         }
     }
@@ -127,6 +233,7 @@ impl MetadataPrinter {
                     println!();
                 }
                 self.print_metadata_usage(metadata, no_color);
+                self.print_metadata_settings_schema(metadata, no_color)?;
                 Ok(())
             }
         }
@@ -177,6 +284,7 @@ impl MetadataPrinter {
         }
 
         let _usage = annotations.remove(KUBEWARDEN_ANNOTATION_POLICY_USAGE);
+        let _settings_schema = annotations.remove(SETTINGS_SCHEMA_ANNOTATION);
         if !annotations.is_empty() {
             table.add_row(row![]);
             table.add_row(row![Fmbl -> "Annotations"]);
@@ -251,6 +359,24 @@ impl MetadataPrinter {
         self.render_markdown(&fenced_usage, no_color);
     }
 
+    fn print_metadata_settings_schema(&self, metadata: &Metadata, no_color: bool) -> Result<()> {
+        let Some(schema) = settings_schema(metadata)? else {
+            return Ok(());
+        };
+
+        // Quick hack to print a colorized "Settings" section, with the same
+        // style as the other sections we print
+        println!();
+        let mut table = Table::new();
+        table.set_format(FormatBuilder::new().padding(0, 1).build());
+        table.add_row(row![Fmbl -> "Settings JSON Schema"]);
+        table.printstd();
+
+        let text = format!("```json\n{}\n```", serde_json::to_string_pretty(&schema)?);
+        self.render_markdown(&text, no_color);
+        Ok(())
+    }
+
     fn render_markdown(&self, text: &str, no_color: bool) {
         let mut skin: MadSkin = if no_color || !io::stdout().is_terminal() {
             MadSkin::no_style()
@@ -277,7 +403,7 @@ enum SignaturesPrinter {
 impl From<&OutputType> for SignaturesPrinter {
     fn from(output_type: &OutputType) -> Self {
         match output_type {
-            OutputType::Yaml => Self::Yaml,
+            OutputType::Yaml | OutputType::Json => Self::Yaml,
             OutputType::Pretty => Self::Pretty,
         }
     }
@@ -320,7 +446,15 @@ impl SignaturesPrinter {
     }
 }
 
-async fn fetch_signatures_manifest(
+/// Returns whether fetching the signatures failed because the policy has not
+/// been signed
+pub(crate) fn is_unsigned(error: &anyhow::Error) -> bool {
+    error
+        .to_string()
+        .starts_with("OCI API error: manifest unknown on")
+}
+
+pub(crate) async fn fetch_signatures_manifest(
     uri: &str,
     sources: Option<Sources>,
 ) -> Result<Option<OciImageManifest>> {
@@ -333,11 +467,7 @@ async fn fetch_signatures_manifest(
         .strip_prefix("registry://")
         .ok_or_else(|| anyhow!("invalid uri"))?;
     let image_ref = OciReference::from_str(image_name)?;
-    let auth = match Registry::auth(image_name) {
-        RegistryAuth::Anonymous => Auth::Anonymous,
-        RegistryAuth::Basic(username, password) => Auth::Basic(username, password),
-        RegistryAuth::Bearer(token) => Auth::Bearer(token),
-    };
+    let auth = sigstore_auth(image_name)?;
 
     let (cosign_signature_image, _source_image_digest) =
         client.triangulate(&image_ref, &auth).await?;
@@ -351,3 +481,41 @@ async fn fetch_signatures_manifest(
         _ => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use std::collections::BTreeMap;
+
+    fn metadata(annotations: &[(&str, &str)]) -> Metadata {
+        Metadata {
+            annotations: Some(
+                annotations
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::embedded(
+        &[(SETTINGS_SCHEMA_ANNOTATION, r#"{"type": "object"}"#)],
+        Some(serde_json::json!({"type": "object"}))
+    )]
+    #[case::missing(&[(KUBEWARDEN_ANNOTATION_POLICY_TITLE, "psp")], None)]
+    fn settings_schema_lookup(
+        #[case] annotations: &[(&str, &str)],
+        #[case] expected: Option<serde_json::Value>,
+    ) {
+        assert_eq!(settings_schema(&metadata(annotations)).unwrap(), expected);
+    }
+
+    #[test]
+    fn invalid_settings_schema() {
+        let metadata = metadata(&[(SETTINGS_SCHEMA_ANNOTATION, "{not json")]);
+        assert!(settings_schema(&metadata).is_err());
+    }
+}
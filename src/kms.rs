@@ -0,0 +1,602 @@
+//! Keys stored inside of a Key Management Service, referenced by the URIs
+//! cosign uses:
+//!
+//! - `awskms://[ENDPOINT]/<key id, alias or ARN>`
+//! - `gcpkms://projects/<project>/locations/<location>/keyRings/<key ring>/cryptoKeys/<key>/versions/<version>`
+//! - `azurekms://<vault>.vault.azure.net/<key>`
+//! - `hashivault://<key>`, a key of the transit secrets engine of Vault
+//!
+//! The services are reached through their command line tools, `aws`,
+//! `gcloud`, `az` and `vault`, which take care of the authentication: the
+//! credentials already configured for them are used. The keys must be ECDSA
+//! P-256 keys, like the ones generated by cosign, or P-384 keys: the signing
+//! scheme is read from the public key.
+
+use std::{env, path::Path, process::Command};
+
+use anyhow::{anyhow, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use policy_evaluator::policy_fetcher::sigstore::crypto::SigningScheme;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha384};
+use tracing::debug;
+use x509_parser::{
+    oid_registry::{OID_EC_P256, OID_NIST_EC_P384},
+    prelude::FromDer,
+    x509::SubjectPublicKeyInfo,
+};
+
+/// Mount path of the transit secrets engine, like for cosign
+const TRANSIT_PATH_ENV: &str = "TRANSIT_SECRET_ENGINE_PATH";
+const DEFAULT_TRANSIT_PATH: &str = "transit";
+
+const GCP_KEY_FORMAT: &str = "gcpkms://projects/<project>/locations/<location>/keyRings/<key ring>/cryptoKeys/<key>/versions/<version>";
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum KmsKey {
+    Aws {
+        /// Endpoint of an AWS compatible KMS, like localstack
+        endpoint: Option<String>,
+        key_id: String,
+    },
+    Gcp {
+        /// Resource name of the key, without its version
+        key: String,
+        version: String,
+    },
+    Azure {
+        vault: String,
+        name: String,
+    },
+    Vault {
+        name: String,
+    },
+}
+
+impl KmsKey {
+    /// Parses the reference of a key stored inside of a KMS. The other
+    /// references, like the paths of PEM files, give `None`.
+    pub(crate) fn parse(key: &str) -> Result<Option<Self>> {
+        let invalid = |format: &str| anyhow!("invalid KMS key {}, expected {}", key, format);
+        if let Some(reference) = key.strip_prefix("awskms://") {
+            let (endpoint, key_id) = reference
+                .split_once('/')
+                .filter(|(_, key_id)| !key_id.is_empty())
+                .ok_or_else(|| invalid("awskms://[ENDPOINT]/<key id, alias or ARN>"))?;
+            return Ok(Some(KmsKey::Aws {
+                endpoint: (!endpoint.is_empty()).then(|| endpoint.to_string()),
+                key_id: key_id.to_string(),
+            }));
+        }
+        if let Some(reference) = key.strip_prefix("gcpkms://") {
+            let (key, version) = reference
+                .rsplit_once("/versions/")
+                .or_else(|| reference.rsplit_once("/cryptoKeyVersions/"))
+                .filter(|(key, version)| {
+                    key.starts_with("projects/")
+                        && key.contains("/cryptoKeys/")
+                        && !version.is_empty()
+                })
+                .ok_or_else(|| invalid(GCP_KEY_FORMAT))?;
+            return Ok(Some(KmsKey::Gcp {
+                key: key.to_string(),
+                version: version.to_string(),
+            }));
+        }
+        if let Some(reference) = key.strip_prefix("azurekms://") {
+            let (vault, name) = reference
+                .split_once('/')
+                .and_then(|(host, name)| Some((host.split('.').next()?, name)))
+                .filter(|(vault, name)| !vault.is_empty() && !name.is_empty())
+                .ok_or_else(|| invalid("azurekms://<vault>.vault.azure.net/<key>"))?;
+            return Ok(Some(KmsKey::Azure {
+                vault: vault.to_string(),
+                name: name.to_string(),
+            }));
+        }
+        if let Some(name) = key.strip_prefix("hashivault://") {
+            if name.is_empty() || name.contains('/') {
+                return Err(invalid("hashivault://<key>"));
+            }
+            return Ok(Some(KmsKey::Vault {
+                name: name.to_string(),
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Fetches the PEM encoded public key
+    pub(crate) fn public_key(&self) -> Result<String> {
+        let workdir = tempfile::tempdir()?;
+        let output_file = workdir.path().join("key.pem");
+        let (tool, args) = self.public_key_command(&output_file);
+        let output = run(tool, &args)?;
+        let public_key = match self {
+            KmsKey::Aws { .. } => pem::encode(&pem::Pem::new(
+                "PUBLIC KEY",
+                decode_base64(&String::from_utf8_lossy(&output))?,
+            )),
+            KmsKey::Gcp { .. } | KmsKey::Azure { .. } => std::fs::read_to_string(&output_file)
+                .map_err(|e| anyhow!("{} returned no public key: {}", tool, e))?,
+            KmsKey::Vault { .. } => vault_public_key(&serde_json::from_slice(&output)?)?,
+        };
+        debug!(key = ?self, "public key fetched from the KMS");
+        Ok(public_key)
+    }
+
+    /// Reads the signing scheme of the key from its public key
+    pub(crate) fn signing_scheme(&self) -> Result<SigningScheme> {
+        public_key_scheme(&self.public_key()?)
+    }
+
+    /// Signs the digest of the payload, computed with the hash function of
+    /// the scheme, returning the ASN.1 DER encoded ECDSA signature
+    pub(crate) fn sign(&self, scheme: &SigningScheme, payload: &[u8]) -> Result<Vec<u8>> {
+        let workdir = tempfile::tempdir()?;
+        let (digest, bits) = digest(scheme, payload)?;
+        let (input_file, signature_file) = (
+            workdir.path().join("input"),
+            workdir.path().join("signature"),
+        );
+        // gcloud computes the digest by itself
+        std::fs::write(
+            &input_file,
+            match self {
+                KmsKey::Gcp { .. } => payload,
+                _ => digest.as_slice(),
+            },
+        )?;
+        let (tool, args) = self.sign_command(&digest, bits, payload, &input_file, &signature_file);
+        let output = run(tool, &args)?;
+        match self {
+            KmsKey::Aws { .. } => decode_base64(&String::from_utf8_lossy(&output)),
+            KmsKey::Gcp { .. } => std::fs::read(&signature_file)
+                .map_err(|e| anyhow!("{} returned no signature: {}", tool, e)),
+            KmsKey::Azure { .. } => {
+                p1363_to_der(&decode_base64(&String::from_utf8_lossy(&output))?)
+            }
+            KmsKey::Vault { .. } => vault_signature(&serde_json::from_slice(&output)?),
+        }
+    }
+
+    fn public_key_command(&self, output_file: &Path) -> (&'static str, Vec<String>) {
+        let output_file = output_file.to_string_lossy().to_string();
+        match self {
+            KmsKey::Aws { endpoint, key_id } => (
+                "aws",
+                aws_args(
+                    endpoint,
+                    &["kms", "get-public-key", "--key-id", key_id],
+                    "PublicKey",
+                ),
+            ),
+            KmsKey::Gcp { key, version } => (
+                "gcloud",
+                strings(&[
+                    "kms",
+                    "keys",
+                    "versions",
+                    "get-public-key",
+                    version,
+                    "--key",
+                    key,
+                    "--output-file",
+                    &output_file,
+                ]),
+            ),
+            KmsKey::Azure { vault, name } => (
+                "az",
+                strings(&[
+                    "keyvault",
+                    "key",
+                    "download",
+                    "--vault-name",
+                    vault,
+                    "--name",
+                    name,
+                    "--encoding",
+                    "PEM",
+                    "--file",
+                    &output_file,
+                ]),
+            ),
+            KmsKey::Vault { name } => (
+                "vault",
+                strings(&[
+                    "read",
+                    "-format=json",
+                    &format!("{}/keys/{}", transit_path(), name),
+                ]),
+            ),
+        }
+    }
+
+    /// The command signing the digest, computed with the SHA-2 function
+    /// producing `bits` bits
+    fn sign_command(
+        &self,
+        digest: &[u8],
+        bits: u16,
+        payload: &[u8],
+        input_file: &Path,
+        signature_file: &Path,
+    ) -> (&'static str, Vec<String>) {
+        match self {
+            KmsKey::Aws { endpoint, key_id } => (
+                "aws",
+                aws_args(
+                    endpoint,
+                    &[
+                        "kms",
+                        "sign",
+                        "--key-id",
+                        key_id,
+                        "--message",
+                        &format!("fileb://{}", input_file.display()),
+                        "--message-type",
+                        "DIGEST",
+                        "--signing-algorithm",
+                        &format!("ECDSA_SHA_{bits}"),
+                    ],
+                    "Signature",
+                ),
+            ),
+            KmsKey::Gcp { key, version } => (
+                "gcloud",
+                strings(&[
+                    "kms",
+                    "asymmetric-sign",
+                    "--version",
+                    version,
+                    "--key",
+                    key,
+                    "--digest-algorithm",
+                    &format!("sha{bits}"),
+                    "--input-file",
+                    &input_file.to_string_lossy(),
+                    "--signature-file",
+                    &signature_file.to_string_lossy(),
+                ]),
+            ),
+            KmsKey::Azure { vault, name } => (
+                "az",
+                strings(&[
+                    "keyvault",
+                    "key",
+                    "sign",
+                    "--vault-name",
+                    vault,
+                    "--name",
+                    name,
+                    "--algorithm",
+                    &format!("ES{bits}"),
+                    "--digest",
+                    &STANDARD.encode(digest),
+                    "--query",
+                    "result",
+                    "--output",
+                    "tsv",
+                ]),
+            ),
+            KmsKey::Vault { name } => (
+                "vault",
+                strings(&[
+                    "write",
+                    "-format=json",
+                    &format!("{}/sign/{}/sha2-{}", transit_path(), name, bits),
+                    &format!("input={}", STANDARD.encode(payload)),
+                ]),
+            ),
+        }
+    }
+}
+
+/// Reads the signing scheme of the PEM encoded public key, from the curve of
+/// the ECDSA key
+fn public_key_scheme(public_key: &str) -> Result<SigningScheme> {
+    let invalid = |e: String| anyhow!("the KMS returned an invalid public key: {}", e);
+    let pem = pem::parse(public_key).map_err(|e| invalid(e.to_string()))?;
+    let (_, spki) =
+        SubjectPublicKeyInfo::from_der(pem.contents()).map_err(|e| invalid(e.to_string()))?;
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.as_oid().ok());
+    match curve {
+        Some(curve) if curve == OID_EC_P256 => Ok(SigningScheme::ECDSA_P256_SHA256_ASN1),
+        Some(curve) if curve == OID_NIST_EC_P384 => Ok(SigningScheme::ECDSA_P384_SHA384_ASN1),
+        _ => Err(anyhow!(
+            "unsupported KMS key, only ECDSA P-256 and P-384 keys can sign policies"
+        )),
+    }
+}
+
+/// Computes the digest of the payload with the hash function of the scheme,
+/// returning it with the number of bits of the function
+fn digest(scheme: &SigningScheme, payload: &[u8]) -> Result<(Vec<u8>, u16)> {
+    match scheme {
+        SigningScheme::ECDSA_P256_SHA256_ASN1 => Ok((Sha256::digest(payload).to_vec(), 256)),
+        SigningScheme::ECDSA_P384_SHA384_ASN1 => Ok((Sha384::digest(payload).to_vec(), 384)),
+        _ => Err(anyhow!(
+            "unsupported signing scheme, the KMS keys sign with ECDSA P-256 or P-384"
+        )),
+    }
+}
+
+fn strings(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+fn aws_args(endpoint: &Option<String>, args: &[&str], query: &str) -> Vec<String> {
+    let mut args = strings(args);
+    if let Some(endpoint) = endpoint {
+        args.extend(strings(&["--endpoint-url", &format!("https://{endpoint}")]));
+    }
+    args.extend(strings(&["--query", query, "--output", "text"]));
+    args
+}
+
+fn transit_path() -> String {
+    env::var(TRANSIT_PATH_ENV).unwrap_or_else(|_| DEFAULT_TRANSIT_PATH.to_string())
+}
+
+fn run(tool: &str, args: &[String]) -> Result<Vec<u8>> {
+    debug!(tool, ?args, "reaching the KMS");
+    let output = Command::new(tool).args(args).output().map_err(|e| {
+        anyhow!(
+            "cannot run {}, which is required to use the keys of its KMS: {}",
+            tool,
+            e
+        )
+    })?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} failed: {}",
+            tool,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>> {
+    let value = value.trim();
+    STANDARD
+        .decode(value)
+        .or_else(|_| URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')))
+        .map_err(|e| anyhow!("the KMS returned invalid base64 data: {}", e))
+}
+
+/// Reads the public key of the latest version of a transit key
+fn vault_public_key(response: &Value) -> Result<String> {
+    let latest_version = response
+        .pointer("/data/latest_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("Vault returned no key version"))?;
+    response
+        .pointer(&format!("/data/keys/{latest_version}/public_key"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| {
+            anyhow!("the transit key has no public key, it must be an ecdsa-p256 or ecdsa-p384 key")
+        })
+}
+
+/// Reads the signature of the transit engine, formatted as
+/// `vault:v<version>:<base64 signature>`
+fn vault_signature(response: &Value) -> Result<Vec<u8>> {
+    let signature = response
+        .pointer("/data/signature")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Vault returned no signature"))?;
+    let (_, signature) = signature
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("unexpected Vault signature {}", signature))?;
+    decode_base64(signature)
+}
+
+/// Encodes a signature made of the concatenation of r and s, like the ones
+/// of Azure Key Vault, as an ASN.1 DER sequence of two integers
+fn p1363_to_der(signature: &[u8]) -> Result<Vec<u8>> {
+    // the integers of P-256 and P-384 signatures
+    if signature.len() != 64 && signature.len() != 96 {
+        return Err(anyhow!(
+            "unexpected ECDSA signature of {} bytes",
+            signature.len()
+        ));
+    }
+    let integer = |bytes: &[u8]| {
+        let first = bytes
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(bytes.len() - 1);
+        let bytes = &bytes[first..];
+        // the integers are signed, a leading zero keeps them positive
+        let padded = bytes[0] & 0x80 != 0;
+        let mut der = vec![0x02, (bytes.len() + usize::from(padded)) as u8];
+        if padded {
+            der.push(0);
+        }
+        der.extend_from_slice(bytes);
+        der
+    };
+    let (r, s) = signature.split_at(signature.len() / 2);
+    let sequence = [integer(r), integer(s)].concat();
+    Ok([vec![0x30, sequence.len() as u8], sequence].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[rstest]
+    #[case::aws(
+        "awskms:///arn:aws:kms:us-east-2:111122223333:alias/cosign",
+        Some(KmsKey::Aws { endpoint: None, key_id: "arn:aws:kms:us-east-2:111122223333:alias/cosign".to_string() })
+    )]
+    #[case::aws_endpoint(
+        "awskms://localhost:4566/alias/cosign",
+        Some(KmsKey::Aws { endpoint: Some("localhost:4566".to_string()), key_id: "alias/cosign".to_string() })
+    )]
+    #[case::gcp(
+        "gcpkms://projects/p/locations/global/keyRings/r/cryptoKeys/k/versions/1",
+        Some(KmsKey::Gcp { key: "projects/p/locations/global/keyRings/r/cryptoKeys/k".to_string(), version: "1".to_string() })
+    )]
+    #[case::gcp_resource_name(
+        "gcpkms://projects/p/locations/global/keyRings/r/cryptoKeys/k/cryptoKeyVersions/2",
+        Some(KmsKey::Gcp { key: "projects/p/locations/global/keyRings/r/cryptoKeys/k".to_string(), version: "2".to_string() })
+    )]
+    #[case::azure(
+        "azurekms://kubewarden.vault.azure.net/cosign",
+        Some(KmsKey::Azure { vault: "kubewarden".to_string(), name: "cosign".to_string() })
+    )]
+    #[case::vault("hashivault://cosign", Some(KmsKey::Vault { name: "cosign".to_string() }))]
+    #[case::file("cosign.pub", None)]
+    #[case::absolute_file("/etc/kwctl/cosign.pub", None)]
+    fn references(#[case] key: &str, #[case] expected: Option<KmsKey>) {
+        assert_eq!(KmsKey::parse(key).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::aws_without_key("awskms://")]
+    #[case::gcp_without_version("gcpkms://projects/p/locations/global/keyRings/r/cryptoKeys/k")]
+    #[case::azure_without_key("azurekms://kubewarden.vault.azure.net")]
+    #[case::vault_without_key("hashivault://")]
+    fn invalid_references(#[case] key: &str) {
+        assert!(KmsKey::parse(key).is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let key = KmsKey::parse("awskms://localhost:4566/alias/cosign")
+            .unwrap()
+            .unwrap();
+        let (tool, args) = key.sign_command(
+            b"digest",
+            256,
+            b"payload",
+            Path::new("/tmp/input"),
+            Path::new("/tmp/signature"),
+        );
+        assert_eq!(tool, "aws");
+        assert_eq!(
+            args,
+            [
+                "kms",
+                "sign",
+                "--key-id",
+                "alias/cosign",
+                "--message",
+                "fileb:///tmp/input",
+                "--message-type",
+                "DIGEST",
+                "--signing-algorithm",
+                "ECDSA_SHA_256",
+                "--endpoint-url",
+                "https://localhost:4566",
+                "--query",
+                "Signature",
+                "--output",
+                "text",
+            ]
+        );
+
+        let key = KmsKey::parse(
+            "gcpkms://projects/p/locations/global/keyRings/r/cryptoKeys/k/versions/1",
+        )
+        .unwrap()
+        .unwrap();
+        let (tool, args) = key.sign_command(
+            b"digest",
+            384,
+            b"payload",
+            Path::new("/tmp/input"),
+            Path::new("/tmp/signature"),
+        );
+        assert_eq!(tool, "gcloud");
+        assert_eq!(args[6..8], ["--digest-algorithm", "sha384"]);
+
+        let (tool, args) = key.public_key_command(Path::new("/tmp/key.pem"));
+        assert_eq!(tool, "gcloud");
+        assert_eq!(
+            args,
+            [
+                "kms",
+                "keys",
+                "versions",
+                "get-public-key",
+                "1",
+                "--key",
+                "projects/p/locations/global/keyRings/r/cryptoKeys/k",
+                "--output-file",
+                "/tmp/key.pem",
+            ]
+        );
+    }
+
+    #[test]
+    fn vault_responses() {
+        let response = json!({"data": {
+            "latest_version": 2,
+            "keys": {
+                "1": {"public_key": "old"},
+                "2": {"public_key": "-----BEGIN PUBLIC KEY-----\n"},
+            },
+        }});
+        assert_eq!(
+            vault_public_key(&response).unwrap(),
+            "-----BEGIN PUBLIC KEY-----\n"
+        );
+
+        let response = json!({"data": {"signature": "vault:v2:MEUCIQ=="}});
+        assert_eq!(
+            vault_signature(&response).unwrap(),
+            [0x30, 0x45, 0x02, 0x21]
+        );
+    }
+
+    #[rstest]
+    #[case::p256(
+        include_str!("../tests/data/sigstore/cosign1.pub"),
+        Some(SigningScheme::ECDSA_P256_SHA256_ASN1)
+    )]
+    #[case::p384(
+        "-----BEGIN PUBLIC KEY-----\nMHYwEAYHKoZIzj0CAQYFK4EEACIDYgAEyyiAOkSvH3J2GNyhypcbi6Ga8ZOYjeyz\nJ7Y493TTJ5n8VjW2OqFMQ+dyuqxG4WDeof21I6pJbP1HbFtLmzFr0OlV9R53G3JR\n77kjVcZ2cMo9jNHNmTVCwDDrvdi1Cznc\n-----END PUBLIC KEY-----\n",
+        Some(SigningScheme::ECDSA_P384_SHA384_ASN1)
+    )]
+    #[case::rsa(
+        "-----BEGIN PUBLIC KEY-----\nMIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDa6exI4U4wN7JTAmrikOi4yocT\n5abKfeWTZ8lt/44ygQjAqOiOzPo0QdZX3iZ77qT5kVFsG+7WE/NgadSW0ItT/TU+\nTB4NcniwU4tB7Ar/Mz16r6zWNQ/dDpIrkw7JpRVo0jxf51P3Xuntv7gpn9lRZzyk\nIBCW6m3kFlVzFDRtgwIDAQAB\n-----END PUBLIC KEY-----\n",
+        None
+    )]
+    fn public_key_schemes(#[case] public_key: &str, #[case] expected: Option<SigningScheme>) {
+        let scheme = public_key_scheme(public_key);
+        match expected {
+            Some(expected) => {
+                assert_eq!(format!("{:?}", scheme.unwrap()), format!("{:?}", expected))
+            }
+            None => assert!(scheme.is_err()),
+        }
+    }
+
+    #[rstest]
+    #[case::positive_integers([0x01; 32], [0x02; 32], [
+        &[0x30, 0x44, 0x02, 0x20][..], &[0x01; 32], &[0x02, 0x20], &[0x02; 32],
+    ].concat())]
+    #[case::padded_integers([0x80; 32], [0xff; 32], [
+        &[0x30, 0x46, 0x02, 0x21, 0x00][..], &[0x80; 32], &[0x02, 0x21, 0x00], &[0xff; 32],
+    ].concat())]
+    #[case::leading_zeros(
+        {let mut r = [0x01; 32]; r[0] = 0; r},
+        [0x02; 32],
+        [&[0x30, 0x43, 0x02, 0x1f][..], &[0x01; 31], &[0x02, 0x20], &[0x02; 32]].concat()
+    )]
+    fn p1363_signatures(#[case] r: [u8; 32], #[case] s: [u8; 32], #[case] der: Vec<u8>) {
+        assert_eq!(p1363_to_der(&[r, s].concat()).unwrap(), der);
+    }
+}
@@ -0,0 +1,598 @@
+//! Linting of the Kubewarden metadata.
+//!
+//! `kwctl lint` checks a metadata file, or the metadata embedded into an
+//! annotated policy, before the policy reaches policy-server. Besides the
+//! checks performed by `kwctl annotate`, it validates the metadata file against
+//! the metadata schema, looks for
+//! rules not matching the `mutating` flag, for malformed context aware
+//! resources, and for missing or malformed annotations.
+
+use std::{fmt, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    constants::*,
+    policy_evaluator::PolicyExecutionMode,
+    policy_metadata::{Metadata, Operation, PolicyType},
+    validator::Validate,
+    ProtocolVersion,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    annotations::POLICY_VERSION_ANNOTATION,
+    config::strict::{self, ConfigFile},
+};
+
+// Annotations every policy must have, shown by `kwctl inspect` and by
+// Artifact Hub
+const REQUIRED_ANNOTATIONS: &[&str] = &[
+    KUBEWARDEN_ANNOTATION_POLICY_TITLE,
+    KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION,
+    KUBEWARDEN_ANNOTATION_POLICY_SOURCE,
+];
+
+const RECOMMENDED_ANNOTATIONS: &[&str] = &[
+    KUBEWARDEN_ANNOTATION_POLICY_AUTHOR,
+    KUBEWARDEN_ANNOTATION_POLICY_URL,
+    KUBEWARDEN_ANNOTATION_POLICY_LICENSE,
+];
+
+const URL_ANNOTATIONS: &[&str] = &[
+    KUBEWARDEN_ANNOTATION_POLICY_URL,
+    KUBEWARDEN_ANNOTATION_POLICY_SOURCE,
+];
+
+const SEMVER_ANNOTATIONS: &[&str] = &[
+    POLICY_VERSION_ANNOTATION,
+    KUBEWARDEN_ANNOTATION_KWCTL_VERSION,
+];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found inside of the metadata
+#[derive(Debug, PartialEq)]
+pub(crate) struct Finding {
+    pub(crate) severity: Severity,
+    pub(crate) message: String,
+}
+
+impl Finding {
+    fn error(message: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// Lints the metadata file, or the metadata embedded into the policy when
+/// `path` is a WebAssembly module. Prints the findings, and fails when any
+/// of them is an error.
+pub(crate) fn lint(path: &Path) -> Result<()> {
+    let contents = fs::read(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+
+    let mut findings = Vec::new();
+    let metadata = if contents.starts_with(b"\0asm") {
+        Metadata::from_path(path)
+            .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?
+            .ok_or_else(|| {
+                anyhow!(
+                    "No Kubewarden metadata found inside of '{}'",
+                    path.display()
+                )
+            })
+            .map(Some)?
+    } else {
+        let contents = String::from_utf8(contents)
+            .map_err(|e| anyhow!("{} is not a YAML file: {}", path.display(), e))?;
+        let violations = strict::check(ConfigFile::Metadata, &contents);
+        let schema_valid = violations.is_empty();
+        findings.extend(violations.into_iter().map(Finding::error));
+        let document: serde_yaml::Value = serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("cannot parse metadata {}: {}", path.display(), e))?;
+        let errors = type_errors(&document);
+        if errors.is_empty() {
+            Some(serde_yaml::from_value(document)?)
+        } else {
+            // the schema already reports most of the invalid values
+            if schema_valid {
+                findings.extend(errors.into_iter().map(Finding::error));
+            }
+            None
+        }
+    };
+    if let Some(metadata) = metadata {
+        findings.extend(check(&metadata)?);
+    }
+
+    for finding in &findings {
+        println!("{finding}");
+    }
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+    println!(
+        "{}: {} errors, {} warnings",
+        path.display(),
+        errors,
+        findings.len() - errors
+    );
+    if errors > 0 {
+        return Err(anyhow!("the metadata of {} is not valid", path.display()));
+    }
+    Ok(())
+}
+
+/// Semantic checks of the metadata
+fn check(metadata: &Metadata) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    // the protocol version is detected by `kwctl annotate`, metadata files
+    // do not hold it
+    let mut annotated = metadata.clone();
+    annotated
+        .protocol_version
+        .get_or_insert(match annotated.execution_mode {
+            PolicyExecutionMode::KubewardenWapc => ProtocolVersion::V1,
+            _ => ProtocolVersion::Unknown,
+        });
+    if let Err(e) = annotated.validate() {
+        findings.push(Finding::error(e.to_string()));
+    }
+
+    let fields = serde_json::to_value(metadata)?;
+    let rules = fields
+        .get("rules")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    findings.extend(check_rules(
+        &rules,
+        metadata.mutating,
+        metadata.policy_type == PolicyType::Raw,
+    ));
+    findings.extend(check_context_aware_resources(
+        fields
+            .get("contextAwareResources")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+    ));
+    findings.extend(check_annotations(
+        &metadata.annotations.clone().unwrap_or_default(),
+    ));
+    Ok(findings)
+}
+
+/// Checks the values of the fields of a metadata document, reporting the
+/// path of every invalid one, like
+/// ``rules[0].operations[1]: unknown variant `PATCH`, expected one of ...``.
+/// The fields unknown to the metadata schema are left to [`strict::check`].
+pub(crate) fn type_errors(document: &serde_yaml::Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    let Some(fields) = document.as_mapping() else {
+        errors.push("the metadata must be a mapping".to_string());
+        return errors;
+    };
+    for (key, value) in fields {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        // all the fields are optional
+        if value.is_null() {
+            continue;
+        }
+        match key {
+            "protocolVersion" => expect::<ProtocolVersion>(&mut errors, key, value),
+            "mutating" | "backgroundAudit" | "contextAware" => {
+                expect::<bool>(&mut errors, key, value)
+            }
+            "executionMode" => expect::<PolicyExecutionMode>(&mut errors, key, value),
+            "policyType" => expect::<PolicyType>(&mut errors, key, value),
+            "minimumKubewardenVersion" => expect::<semver::Version>(&mut errors, key, value),
+            "annotations" => match value.as_mapping() {
+                Some(annotations) => {
+                    for (name, value) in annotations {
+                        let path = format!("{key}.{}", name.as_str().unwrap_or_default());
+                        expect::<String>(&mut errors, &path, value);
+                    }
+                }
+                None => errors.push(format!("{key}: expected a mapping")),
+            },
+            "rules" => each(&mut errors, key, value, |errors, path, rule| {
+                fields_of(
+                    errors,
+                    path,
+                    rule,
+                    |errors, field, path, value| match field {
+                        "apiGroups" | "apiVersions" | "resources" => {
+                            each(errors, path, value, |errors, path, value| {
+                                expect::<String>(errors, path, value)
+                            })
+                        }
+                        "operations" => each(errors, path, value, |errors, path, value| {
+                            expect::<Operation>(errors, path, value)
+                        }),
+                        _ => {}
+                    },
+                )
+            }),
+            "contextAwareResources" => each(&mut errors, key, value, |errors, path, resource| {
+                fields_of(errors, path, resource, |errors, field, path, value| {
+                    if matches!(field, "apiVersion" | "kind") {
+                        expect::<String>(errors, path, value)
+                    }
+                })
+            }),
+            _ => {}
+        }
+    }
+    errors
+}
+
+fn expect<T: DeserializeOwned>(errors: &mut Vec<String>, path: &str, value: &serde_yaml::Value) {
+    if let Err(e) = serde_yaml::from_value::<T>(value.clone()) {
+        errors.push(format!("{path}: {e}"));
+    }
+}
+
+/// Checks every item of the sequence at `path`
+fn each(
+    errors: &mut Vec<String>,
+    path: &str,
+    value: &serde_yaml::Value,
+    check: impl Fn(&mut Vec<String>, &str, &serde_yaml::Value),
+) {
+    match value.as_sequence() {
+        Some(items) => {
+            for (index, item) in items.iter().enumerate() {
+                check(errors, &format!("{path}[{index}]"), item);
+            }
+        }
+        None => errors.push(format!("{path}: expected a sequence")),
+    }
+}
+
+/// Checks every field of the mapping at `path`
+fn fields_of(
+    errors: &mut Vec<String>,
+    path: &str,
+    value: &serde_yaml::Value,
+    check: impl Fn(&mut Vec<String>, &str, &str, &serde_yaml::Value),
+) {
+    match value.as_mapping() {
+        Some(fields) => {
+            for (field, value) in fields {
+                if let Some(field) = field.as_str() {
+                    check(errors, field, &format!("{path}.{field}"), value);
+                }
+            }
+        }
+        None => errors.push(format!("{path}: expected a mapping")),
+    }
+}
+
+fn strings(rule: &Value, field: &str) -> Vec<String> {
+    rule.get(field)
+        .and_then(Value::as_array)
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn check_rules(rules: &[Value], mutating: bool, raw: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if rules.is_empty() {
+        if !raw {
+            findings.push(Finding::error(
+                "no rules defined, the policy would never be evaluated",
+            ));
+        }
+        return findings;
+    }
+
+    for (index, rule) in rules.iter().enumerate() {
+        for field in ["apiVersions", "resources", "operations"] {
+            if strings(rule, field).is_empty() {
+                findings.push(Finding::error(format!("rule {index}: `{field}` is empty")));
+            }
+        }
+        let operations = strings(rule, "operations");
+        if operations.iter().any(|operation| operation == "*") && operations.len() > 1 {
+            findings.push(Finding::warning(format!(
+                "rule {index}: `*` already covers the other operations"
+            )));
+        }
+    }
+
+    // DELETE and CONNECT requests carry no object that can be mutated
+    let mutable = rules.iter().any(|rule| {
+        strings(rule, "operations")
+            .iter()
+            .any(|operation| matches!(operation.as_str(), "*" | "CREATE" | "UPDATE"))
+    });
+    if mutating && !mutable {
+        findings.push(Finding::warning(
+            "the policy is mutating, but its rules match only DELETE or CONNECT operations, which cannot be mutated",
+        ));
+    }
+    findings
+}
+
+fn check_context_aware_resources(resources: &[Value]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for resource in resources {
+        let api_version = resource
+            .get("apiVersion")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let kind = resource
+            .get("kind")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if api_version.is_empty() || kind.is_empty() {
+            findings.push(Finding::error(format!(
+                "context aware resource `{api_version}/{kind}`: both `apiVersion` and `kind` are required"
+            )));
+            continue;
+        }
+        if api_version.split('/').count() > 2 || api_version.split('/').any(str::is_empty) {
+            findings.push(Finding::error(format!(
+                "context aware resource `{api_version}/{kind}`: `{api_version}` is not a valid apiVersion, like `v1` or `apps/v1`"
+            )));
+        }
+        if !kind.starts_with(|c: char| c.is_ascii_uppercase()) {
+            findings.push(Finding::warning(format!(
+                "context aware resource `{api_version}/{kind}`: kinds are capitalized, like `Namespace`"
+            )));
+        }
+    }
+    findings
+}
+
+fn check_annotations(annotations: &std::collections::BTreeMap<String, String>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for annotation in REQUIRED_ANNOTATIONS {
+        if annotations
+            .get(*annotation)
+            .is_none_or(|value| value.trim().is_empty())
+        {
+            findings.push(Finding::error(format!(
+                "missing required annotation `{annotation}`"
+            )));
+        }
+    }
+    for annotation in RECOMMENDED_ANNOTATIONS {
+        if !annotations.contains_key(*annotation) {
+            findings.push(Finding::warning(format!(
+                "missing recommended annotation `{annotation}`"
+            )));
+        }
+    }
+    for annotation in URL_ANNOTATIONS {
+        if let Some(value) = annotations.get(*annotation) {
+            let valid = url::Url::parse(value)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+            if !valid {
+                findings.push(Finding::error(format!(
+                    "annotation `{annotation}`: `{value}` is not a valid http(s) URL"
+                )));
+            }
+        }
+    }
+    for annotation in SEMVER_ANNOTATIONS {
+        if let Some(value) = annotations.get(*annotation) {
+            if semver::Version::parse(value).is_err() {
+                findings.push(Finding::error(format!(
+                    "annotation `{annotation}`: `{value}` is not a valid semantic version"
+                )));
+            }
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn metadata(yaml: &str) -> Metadata {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    const ANNOTATIONS: &str = r#"
+annotations:
+  io.kubewarden.policy.title: safe-labels
+  io.kubewarden.policy.description: Enforce labels
+  io.kubewarden.policy.author: Kubewarden developers
+  io.kubewarden.policy.url: https://github.com/kubewarden/safe-labels-policy
+  io.kubewarden.policy.source: https://github.com/kubewarden/safe-labels-policy
+  io.kubewarden.policy.license: Apache-2.0
+  io.kubewarden.policy.version: 1.0.0
+"#;
+
+    #[test]
+    fn valid_metadata() {
+        let metadata = metadata(&format!(
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE", "UPDATE"]
+mutating: true
+contextAwareResources:
+- apiVersion: v1
+  kind: Namespace
+{ANNOTATIONS}"#
+        ));
+        assert_eq!(check(&metadata).unwrap(), vec![]);
+    }
+
+    #[rstest]
+    #[case::no_rules(
+        "rules: []",
+        Finding::error("no rules defined, the policy would never be evaluated")
+    )]
+    #[case::mutating_delete(
+        r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["DELETE"]
+mutating: true"#,
+        Finding::warning("the policy is mutating, but its rules match only DELETE or CONNECT operations, which cannot be mutated")
+    )]
+    #[case::redundant_operations(
+        r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["*", "CREATE"]"#,
+        Finding::warning("rule 0: `*` already covers the other operations")
+    )]
+    #[case::invalid_api_version(
+        r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE"]
+contextAwareResources:
+- apiVersion: apps/v1/beta
+  kind: Deployment"#,
+        Finding::error("context aware resource `apps/v1/beta/Deployment`: `apps/v1/beta` is not a valid apiVersion, like `v1` or `apps/v1`")
+    )]
+    #[case::lowercase_kind(
+        r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE"]
+contextAwareResources:
+- apiVersion: v1
+  kind: namespace"#,
+        Finding::warning(
+            "context aware resource `v1/namespace`: kinds are capitalized, like `Namespace`"
+        )
+    )]
+    fn semantic_checks(#[case] yaml: &str, #[case] expected: Finding) {
+        let findings = check(&metadata(&format!("{yaml}\n{ANNOTATIONS}"))).unwrap();
+        assert!(
+            findings.contains(&expected),
+            "{expected} not found in {findings:?}"
+        );
+    }
+
+    #[rstest]
+    #[case::unknown_operation(
+        r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["CREATE", "PATCH"]
+"#,
+        "rules[0].operations[1]: unknown variant `PATCH`"
+    )]
+    #[case::not_a_list(
+        "rules:\n- resources: pods\n",
+        "rules[0].resources: expected a sequence"
+    )]
+    #[case::not_a_bool("mutating: maybe\n", "mutating: invalid type")]
+    #[case::execution_mode("executionMode: rego\n", "executionMode: unknown variant `rego`")]
+    #[case::version("minimumKubewardenVersion: \"1.2\"\n", "minimumKubewardenVersion: ")]
+    #[case::annotation(
+        "annotations:\n  io.kubewarden.policy.tags: [a, b]\n",
+        "annotations.io.kubewarden.policy.tags: invalid type"
+    )]
+    #[case::resource(
+        "contextAwareResources:\n- apiVersion: v1\n  kind: [Namespace]\n",
+        "contextAwareResources[0].kind: invalid type"
+    )]
+    fn path_based_errors(#[case] yaml: &str, #[case] expected: &str) {
+        let errors = type_errors(&serde_yaml::from_str(yaml).unwrap());
+        assert!(
+            errors.iter().any(|error| error.starts_with(expected)),
+            "{expected} not found in {errors:?}"
+        );
+    }
+
+    #[test]
+    fn valid_document_types() {
+        let document = serde_yaml::from_str(&format!(
+            r#"
+rules:
+- apiGroups: [""]
+  apiVersions: ["v1"]
+  resources: ["pods"]
+  operations: ["*"]
+mutating: false
+executionMode: kubewarden-wapc
+minimumKubewardenVersion: 1.10.0
+{ANNOTATIONS}"#
+        ))
+        .unwrap();
+        assert_eq!(type_errors(&document), Vec::<String>::new());
+    }
+
+    #[rstest]
+    #[case::missing_title(
+        &[(KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION, "d"), (KUBEWARDEN_ANNOTATION_POLICY_SOURCE, "https://github.com/acme/policy")],
+        Finding::error("missing required annotation `io.kubewarden.policy.title`")
+    )]
+    #[case::missing_license(
+        &[],
+        Finding::warning("missing recommended annotation `io.kubewarden.policy.license`")
+    )]
+    #[case::invalid_url(
+        &[(KUBEWARDEN_ANNOTATION_POLICY_URL, "github.com/acme/policy")],
+        Finding::error("annotation `io.kubewarden.policy.url`: `github.com/acme/policy` is not a valid http(s) URL")
+    )]
+    #[case::invalid_version(
+        &[(POLICY_VERSION_ANNOTATION, "v1.0")],
+        Finding::error("annotation `io.kubewarden.policy.version`: `v1.0` is not a valid semantic version")
+    )]
+    fn annotation_checks(#[case] annotations: &[(&str, &str)], #[case] expected: Finding) {
+        let annotations = annotations
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let findings = check_annotations(&annotations);
+        assert!(
+            findings.contains(&expected),
+            "{expected} not found in {findings:?}"
+        );
+    }
+}
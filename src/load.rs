@@ -1,20 +1,81 @@
 use anyhow::{anyhow, Result};
 use flate2::read::GzDecoder;
-use policy_evaluator::policy_fetcher::store::Store;
-use std::fs::File;
+use policy_evaluator::policy_fetcher::{policy::Policy, store::Store};
+use std::{fs::File, io::Read};
 use tar::Archive;
+use tracing::debug;
 
-// load policies inside the tarball provided by source_path into the default store
+// load policies inside the tarball provided by source_path into the store
 pub(crate) fn load(source_path: &str) -> Result<()> {
-    let default_store = Store::default();
-    let destination_path = default_store.root;
+    let default_store = crate::store_profile::store();
     let tar_gz =
         File::open(source_path).map_err(|e| anyhow!("cannot open file {}: {}", source_path, e))?;
     let tar = GzDecoder::new(tar_gz);
     let mut archive = Archive::new(tar);
-    archive
-        .unpack(destination_path)
+    let policies = unpack(&mut archive, &default_store)
         .map_err(|e| anyhow!("cannot unpack file {}: {}", source_path, e))?;
+    // the policies replaced by the ones of the tarball are recorded as loaded
+    // from it, `kwctl store verify` would report them as corrupted otherwise
+    for policy in &policies {
+        crate::provenance::record(policy, source_path);
+    }
 
     Ok(())
 }
+
+// unpacks the archive into the store, returning the policies found inside of it
+fn unpack<R: Read>(archive: &mut Archive<R>, store: &Store) -> Result<Vec<Policy>> {
+    std::fs::create_dir_all(&store.root)?;
+    let mut policies = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        if !entry.unpack_in(&store.root)? {
+            debug!(path, "skipping entry, not inside of the store");
+            continue;
+        }
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        match crate::store_sync::stored_policy(store, path.trim_start_matches("./")) {
+            Ok(Some(policy)) => policies.push(policy),
+            _ => debug!(path, "not a policy, its provenance is not recorded"),
+        }
+    }
+    Ok(policies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpacked_policies() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            (
+                "registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+                b"\0asm",
+            ),
+            ("README", b"data"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, &data[..]).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path());
+
+        let policies = unpack(&mut Archive::new(data.as_slice()), &store).unwrap();
+
+        assert_eq!(policies.len(), 1);
+        assert_eq!(
+            policies[0].uri,
+            "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13"
+        );
+        assert_eq!(std::fs::read(&policies[0].local_path).unwrap(), b"\0asm");
+        assert!(dir.path().join("README").exists());
+    }
+}
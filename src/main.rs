@@ -1,13 +1,22 @@
 use std::{
-    collections::HashMap, convert::TryFrom, env, fs, io::prelude::*, path::PathBuf, str::FromStr,
+    collections::HashMap,
+    convert::TryFrom,
+    env,
+    ffi::OsString,
+    fs,
+    io::prelude::*,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
 use clap::ArgMatches;
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use policy_evaluator::policy_fetcher::{registry::Registry, store::DEFAULT_ROOT, PullDestination};
-use tracing::{debug, info};
+use policy_evaluator::policy_fetcher::{registry::Registry, sources::Sources, PullDestination};
+use tracing::{debug, info, warn};
 use tracing_subscriber::{
     filter::{EnvFilter, LevelFilter},
     fmt,
@@ -16,8 +25,12 @@ use tracing_subscriber::{
 
 use crate::{
     config::{
-        sources::remote_server_options,
-        verification::{build_sigstore_trust_root, build_verification_options},
+        registry_auth::{provider_registry_credentials, registry_credentials},
+        sources::{registry_mirrors, remote_server_options, RegistryMirrors},
+        verification::{
+            build_sigstore_trust_root, build_verification_options,
+            build_verification_options_from_flags,
+        },
     },
     load::load,
     save::save,
@@ -25,39 +38,105 @@ use crate::{
 };
 
 mod annotate;
+mod annotations;
+mod attestations;
 mod backend;
 mod callback_handler;
+mod changelog;
 mod cli;
 mod command;
 mod completions;
 mod config;
+mod diff;
+mod fuzz;
+mod graph;
 mod info;
 mod inspect;
+mod kms;
+mod lint;
 mod load;
+mod metrics;
+mod migrate;
+mod mirror_health;
+mod oci_layout;
+mod optimize;
+mod plugins;
 mod policies;
+mod provenance;
 mod pull;
 mod push;
 mod rm;
 mod save;
 mod scaffold;
+mod schema;
+mod settings_schema;
+mod sign;
+mod store_dedup;
+mod store_gc;
+mod store_integrity;
+mod store_mode;
+mod store_profile;
+mod store_sync;
+mod test_report;
+mod test_suite;
+mod timestamps;
+mod trust_root;
+mod updates;
 mod utils;
 mod verify;
+mod version;
+mod version_constraints;
+mod wasm_interface;
 
 pub(crate) const KWCTL_VERIFICATION_CONFIG: &str = "verification-config.yml";
 
 lazy_static! {
     pub(crate) static ref KWCTL_DEFAULT_VERIFICATION_CONFIG_PATH: String = {
-        DEFAULT_ROOT
-            .config_dir()
+        store_profile::config_dir()
             .join(KWCTL_VERIFICATION_CONFIG)
             .display()
             .to_string()
     };
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let matches = cli::build_cli().get_matches();
+
+    // must happen before any other thread is started
+    timestamps::init(*matches.get_one::<bool>("utc").unwrap_or(&false));
+    store_profile::init(
+        matches
+            .get_one::<String>("store-profile")
+            .map(String::as_str),
+    )?;
+    store_mode::init(
+        *matches.get_one::<bool>("store-read-only").unwrap_or(&false),
+        matches
+            .get_one::<String>("store-overlay")
+            .map(PathBuf::from),
+    );
+    version_constraints::init(matches.get_one::<String>("lockfile").map(PathBuf::from));
+    // the exported Docker configuration is removed once kwctl is done
+    let _docker_config = config::registry_auth::init(&matches)?;
+    let (verbose, no_color) = init_output(&matches);
+    if let Some(proxy) = matches.get_one::<String>("proxy") {
+        config::proxy::set_proxy(proxy)?;
+    }
+    // the CA bundle is removed once kwctl is done
+    let _ca_bundle = matches
+        .get_many::<String>("ca-cert")
+        .map(|paths| config::ca_certs::add_ca_certificates(&paths.collect::<Vec<_>>()))
+        .transpose()?;
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(matches, verbose, no_color))
+}
+
+/// Sets up the colors of the output and the logging, returning whether the
+/// output is verbose and whether the colors are disabled
+fn init_output(matches: &ArgMatches) -> (bool, bool) {
     let mut term_color_support = "dumb".to_string();
 
     if let Ok(val) = env::var("TERM") {
@@ -109,9 +188,58 @@ async fn main() -> Result<()> {
         )
         .init();
 
+    (verbose, no_color)
+}
+
+async fn run(matches: ArgMatches, verbose: bool, no_color: bool) -> Result<ExitCode> {
+    // the plugins are external subcommands, run as child processes. Their exit
+    // code becomes the one of kwctl, once its resources have been released
+    if let Some((command, plugin_matches)) = matches.subcommand() {
+        if cli::build_cli().find_subcommand(command).is_none() {
+            let args = plugin_matches
+                .get_many::<OsString>("")
+                .map(|args| args.cloned().collect())
+                .unwrap_or_default();
+            return plugins::exec(command, args, verbose, no_color);
+        }
+    }
+
+    run_command(&matches, no_color)
+        .await
+        .map(|()| ExitCode::SUCCESS)
+}
+
+async fn run_command(matches: &ArgMatches, no_color: bool) -> Result<()> {
     match matches.subcommand_name() {
-        Some("policies") => policies::list(),
+        Some("policies") => {
+            if let Some(matches) = matches.subcommand_matches("policies") {
+                let verify_remote = matches
+                    .get_one::<bool>("verify-remote")
+                    .unwrap_or(&false)
+                    .to_owned();
+                let check_updates = matches
+                    .get_one::<bool>("check-updates")
+                    .unwrap_or(&false)
+                    .to_owned();
+                let sources = if verify_remote || check_updates {
+                    remote_server_options(matches)?
+                } else {
+                    None
+                };
+                let output = policies::OutputFormat::try_from(
+                    matches.get_one::<String>("output").unwrap().as_str(),
+                )?;
+                policies::list(verify_remote, check_updates, sources.as_ref(), output).await?;
+            }
+            Ok(())
+        }
         Some("info") => info::info(),
+        Some("version") => {
+            if let Some(matches) = matches.subcommand_matches("version") {
+                version::version(matches.get_one::<String>("output").map(|s| s.as_str()))?;
+            }
+            Ok(())
+        }
         Some("pull") => {
             if let Some(matches) = matches.subcommand_matches("pull") {
                 let uri = matches.get_one::<String>("uri").unwrap();
@@ -122,72 +250,327 @@ async fn main() -> Result<()> {
                     Some(destination) => PullDestination::LocalFile(destination),
                     None => PullDestination::MainStore,
                 };
+                registry_credentials(matches, uri)?;
                 pull_command(uri, destination, matches).await?
             };
             Ok(())
         }
+        Some("update") => {
+            if let Some(matches) = matches.subcommand_matches("update") {
+                update_command(matches).await?
+            };
+            Ok(())
+        }
         Some("verify") => {
             if let Some(matches) = matches.subcommand_matches("verify") {
+                if *matches.get_one::<bool>("store").unwrap_or(&false) {
+                    let sources = remote_server_options(matches)?;
+                    let mirrors = registry_mirrors(matches)?;
+                    let verification_options = build_verification_options(matches)?
+                        .ok_or_else(|| anyhow!("could not retrieve sigstore options"))?;
+                    let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
+                    return verify::verify_store(
+                        sources.as_ref(),
+                        &mirrors,
+                        &verification_options,
+                        sigstore_trust_root,
+                    )
+                    .await;
+                }
                 let uri = matches.get_one::<String>("uri").unwrap();
+                registry_credentials(matches, uri)?;
                 let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let uri = &version_constraints::resolve(uri, sources.as_ref()).await?;
                 let verification_options = build_verification_options(matches)?
                     .ok_or_else(|| anyhow!("could not retrieve sigstore options"))?;
+
+                if !uri.starts_with("registry://") {
+                    let uri = crate::utils::map_path_to_uri(uri)?;
+                    let signatures: Vec<String> = matches
+                        .get_many::<String>("signature")
+                        .map(|signatures| signatures.cloned().collect())
+                        .unwrap_or_default();
+                    let report = verify::detached::verify(
+                        &uri,
+                        sources.as_ref(),
+                        &verification_options,
+                        &signatures,
+                    )
+                    .await
+                    .map_err(|e| anyhow!("Policy {} cannot be validated\n{:?}", uri, e))?;
+                    if matches.get_one::<String>("output").map(|s| s.as_str()) == Some("json") {
+                        serde_json::to_writer(std::io::stdout(), &report)?;
+                    }
+                    return Ok(());
+                }
+
                 let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
-                verify::verify(
+                let verified_manifest_digest = verify::verify(
                     uri,
                     sources.as_ref(),
+                    &mirrors,
                     &verification_options,
                     sigstore_trust_root.clone(),
                 )
                 .await
                 .map_err(|e| anyhow!("Policy {} cannot be validated\n{:?}", uri, e))?;
+
+                if matches.contains_id("attestation") {
+                    let requirements = provenance_requirements(matches)?;
+                    attestations::verify_provenance(
+                        uri,
+                        &verified_manifest_digest,
+                        &requirements,
+                        sources.as_ref(),
+                    )
+                    .await
+                    .map_err(|e| {
+                        anyhow!("Provenance of policy {} cannot be validated\n{:?}", uri, e)
+                    })?;
+                }
+
+                if matches.get_one::<String>("output").map(|s| s.as_str()) == Some("json") {
+                    let report = verify::report(
+                        uri,
+                        sources.as_ref(),
+                        &mirrors,
+                        &verification_options,
+                        sigstore_trust_root,
+                        &verified_manifest_digest,
+                    )
+                    .await?;
+                    serde_json::to_writer(std::io::stdout(), &report)?;
+                }
             };
             Ok(())
         }
         Some("push") => {
             if let Some(matches) = matches.subcommand_matches("push") {
-                let sources = remote_server_options(matches)?;
+                if let Some(prefix) = matches.get_one::<String>("store") {
+                    return push_store(matches, prefix).await;
+                }
                 let wasm_uri =
                     crate::utils::map_path_to_uri(matches.get_one::<String>("policy").unwrap())?;
                 let wasm_path = crate::utils::wasm_path(wasm_uri.as_str())?;
+                let to_registry_uri = |u: &String| {
+                    if u.starts_with("registry://") {
+                        u.clone()
+                    } else {
+                        format!("registry://{u}")
+                    }
+                };
                 let uri = matches
                     .get_one::<String>("uri")
-                    .map(|u| {
-                        if u.starts_with("registry://") {
-                            u.clone()
-                        } else {
-                            format!("registry://{u}")
-                        }
-                    })
+                    .map(to_registry_uri)
                     .unwrap();
+                let destinations: Vec<String> = std::iter::once(uri.clone())
+                    .chain(
+                        matches
+                            .get_many::<String>("also-push")
+                            .unwrap_or_default()
+                            .map(to_registry_uri),
+                    )
+                    .unique()
+                    .collect();
+
+                // the credentials given on the command line are meant only for
+                // the main destination
+                registry_credentials(matches, &uri)?;
+                for destination in &destinations[1..] {
+                    provider_registry_credentials(matches, destination)?;
+                }
+                let sources = remote_server_options(matches)?;
 
                 debug!(
                     policy = wasm_path.to_string_lossy().to_string().as_str(),
-                    destination = uri.as_str(),
+                    destinations = ?destinations,
                     "policy push"
                 );
 
                 let force = matches.contains_id("force");
+                let if_not_exists = matches
+                    .get_one::<bool>("if-not-exists")
+                    .unwrap_or(&false)
+                    .to_owned();
+                let dry_run = matches
+                    .get_one::<bool>("dry-run")
+                    .unwrap_or(&false)
+                    .to_owned();
 
-                let immutable_ref = push::push(wasm_path, &uri, sources.as_ref(), force).await?;
+                let annotations = crate::utils::parse_annotations(
+                    matches.get_many::<String>("annotation").unwrap_or_default(),
+                )?;
+                let mut attestations = Vec::new();
+                if let Some(path) = matches.get_one::<String>("attach-sbom") {
+                    attestations.push(attestations::Attestation::sbom(Path::new(path))?);
+                }
+                if let Some(path) = matches.get_one::<String>("attach-provenance") {
+                    attestations.push(attestations::Attestation::provenance(Path::new(path))?);
+                }
+                let signing_method = push_signing_method(matches)?;
+                let sign_annotations = crate::utils::parse_annotations(
+                    matches
+                        .get_many::<String>("sign-annotation")
+                        .unwrap_or_default(),
+                )?;
+
+                let optimize_level = matches
+                    .get_one::<String>("optimize-level")
+                    .map(|level| optimize::OptimizeLevel::try_from(level.as_str()))
+                    .transpose()?;
+                let upload =
+                    push::PolicyUpload::new(wasm_path, force, &annotations, optimize_level)?;
+
+                // all the destinations are checked before pushing, to avoid
+                // publishing the policy only to some of them
+                if if_not_exists {
+                    for destination in &destinations {
+                        push::ensure_tag_not_taken(&upload, destination, sources.as_ref()).await?;
+                    }
+                }
+
+                if dry_run {
+                    let plans = destinations
+                        .iter()
+                        .map(|destination| upload.plan(destination))
+                        .collect::<Result<Vec<_>>>()?;
+                    return print_push_plans(
+                        &plans,
+                        matches.get_one::<String>("output").map(|s| s.as_str()),
+                    );
+                }
+
+                let mut pushed: Vec<push::PushedPolicy> = Vec::new();
+                for destination in &destinations {
+                    let pushed_policy = push_to_destination(
+                        &upload,
+                        destination,
+                        signing_method
+                            .as_ref()
+                            .map(|method| (method, &sign_annotations)),
+                        &attestations,
+                        sources.as_ref(),
+                    )
+                    .await
+                    .map_err(|e| {
+                        if pushed.is_empty() {
+                            e
+                        } else {
+                            anyhow!(
+                                "{}\nThe policy has already been pushed to: {}",
+                                e,
+                                pushed.iter().map(|p| p.immutable_ref.as_str()).join(", ")
+                            )
+                        }
+                    })?;
+                    pushed.push(pushed_policy);
+                }
 
                 match matches.get_one::<String>("output").map(|s| s.as_str()) {
                     Some("json") => {
-                        let mut response: HashMap<&str, String> = HashMap::new();
-                        response.insert("immutable_ref", immutable_ref);
+                        let main = &pushed[0];
+                        let mut response: HashMap<&str, serde_json::Value> = HashMap::new();
+                        response.insert("immutable_ref", main.immutable_ref.clone().into());
+                        if !main.referrers.is_empty() {
+                            response.insert("referrers", main.referrers.clone().into());
+                        }
+                        response.insert("destinations", serde_json::to_value(&pushed)?);
                         serde_json::to_writer(std::io::stdout(), &response)?
                     }
                     _ => {
-                        println!("Policy successfully pushed: {immutable_ref}");
+                        for pushed_policy in pushed {
+                            println!(
+                                "Policy successfully pushed: {}",
+                                pushed_policy.immutable_ref
+                            );
+                            for referrer in pushed_policy.referrers {
+                                println!("Attestation attached: {referrer}");
+                            }
+                        }
                     }
                 }
             };
             Ok(())
         }
+        Some("sign") => {
+            if let Some(matches) = matches.subcommand_matches("sign") {
+                let uri = matches.get_one::<String>("uri").unwrap();
+                if !uri.starts_with("registry://") {
+                    return Err(anyhow!("only registry:// policies can be signed: {}", uri));
+                }
+                registry_credentials(matches, uri)?;
+                let sources = remote_server_options(matches)?;
+                let annotations = crate::utils::parse_annotations(
+                    matches.get_many::<String>("annotation").unwrap_or_default(),
+                )?;
+
+                let method = match matches.get_one::<String>("key") {
+                    Some(key) => sign::SigningMethod::key(
+                        key,
+                        matches
+                            .get_one::<String>("key-password")
+                            .map(String::as_str),
+                    )?,
+                    None => sign::SigningMethod::Keyless(keyless_options(matches, "")?),
+                };
+                match sign::sign(uri, &method, &annotations, sources.as_ref()).await? {
+                    Some(signature) => println!("Policy successfully signed: {signature}"),
+                    None => {
+                        println!("Policy not signed: the Sigstore infrastructure cannot be reached")
+                    }
+                }
+            }
+            Ok(())
+        }
+        Some("schema") => {
+            if let Some(matches) = matches.subcommand_matches("schema") {
+                schema::schema(matches.get_one::<String>("kind").unwrap())?;
+            }
+            Ok(())
+        }
         Some("rm") => {
             if let Some(matches) = matches.subcommand_matches("rm") {
-                let uri_or_sha_prefix = matches.get_one::<String>("uri_or_sha_prefix").unwrap();
-                rm::rm(uri_or_sha_prefix)?;
+                let targets: Vec<String> = matches
+                    .get_many::<String>("uri_or_sha_prefix")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect();
+                let options = rm::RmOptions {
+                    all: matches.get_one::<bool>("all").unwrap_or(&false).to_owned(),
+                    dry_run: matches
+                        .get_one::<bool>("dry-run")
+                        .unwrap_or(&false)
+                        .to_owned(),
+                    interactive: matches
+                        .get_one::<bool>("interactive")
+                        .unwrap_or(&false)
+                        .to_owned(),
+                };
+                if !options.dry_run {
+                    store_mode::ensure_writable("remove policies")?;
+                }
+                let report = rm::rm_many(&targets, &options)?;
+                if options.dry_run {
+                    for (uri, size) in &report.removed {
+                        println!(
+                            "Would remove {uri} ({})",
+                            humansize::format_size(*size, humansize::DECIMAL)
+                        );
+                    }
+                    println!(
+                        "{} policies would be removed, {} would be reclaimed",
+                        report.removed.len(),
+                        humansize::format_size(report.reclaimed, humansize::DECIMAL)
+                    );
+                    return Ok(());
+                }
+                store_dedup::prune()?;
+                println!(
+                    "{} policies removed, {} reclaimed",
+                    report.removed.len(),
+                    humansize::format_size(report.reclaimed, humansize::DECIMAL)
+                );
             }
             Ok(())
         }
@@ -197,6 +580,84 @@ async fn main() -> Result<()> {
                 .expect("run subcommand not found");
             cli::run::exec(run_arg).await
         }
+        Some("validate") => {
+            let validate_arg = matches
+                .subcommand_matches("validate")
+                .expect("validate subcommand not found");
+            cli::validate::exec(validate_arg).await
+        }
+        Some("audit") => {
+            let audit_arg = matches
+                .subcommand_matches("audit")
+                .expect("audit subcommand not found");
+            cli::audit::exec(audit_arg).await
+        }
+        Some("serve") => {
+            let serve_arg = matches
+                .subcommand_matches("serve")
+                .expect("serve subcommand not found");
+            cli::serve::exec(serve_arg).await
+        }
+        Some("migrate") => {
+            if let Some(matches) = matches.subcommand_matches("migrate") {
+                if let Some(matches) = matches.subcommand_matches("gatekeeper") {
+                    let push = matches.get_one::<String>("push").map(|uri| {
+                        if uri.starts_with("registry://") {
+                            uri.clone()
+                        } else {
+                            format!("registry://{uri}")
+                        }
+                    });
+                    if let Some(uri) = &push {
+                        registry_credentials(matches, uri)?;
+                    }
+                    // the registry is contacted only to push the policy
+                    let sources = if push.is_some() {
+                        remote_server_options(matches)?
+                    } else {
+                        None
+                    };
+                    let options = migrate::GatekeeperOptions {
+                        constraint_template: matches
+                            .get_one::<String>("constraint-template")
+                            .unwrap()
+                            .into(),
+                        constraint: matches.get_one::<String>("constraint").unwrap().into(),
+                        output_dir: matches.get_one::<String>("output-dir").map(|p| p.into()),
+                        module: matches.get_one::<String>("module").cloned(),
+                        annotate: matches.get_flag("annotate"),
+                        push,
+                    };
+                    migrate::gatekeeper(&options, sources.as_ref()).await?;
+                }
+            }
+            Ok(())
+        }
+        Some("lint") => {
+            if let Some(matches) = matches.subcommand_matches("lint") {
+                let path = matches.get_one::<String>("path").unwrap();
+                lint::lint(Path::new(path))?;
+            }
+            Ok(())
+        }
+        Some("graph") => {
+            if let Some(matches) = matches.subcommand_matches("graph") {
+                let format = graph::GraphFormat::try_from(
+                    matches
+                        .get_one::<String>("output")
+                        .expect("output has a default value")
+                        .as_str(),
+                )?;
+                let graph = match matches.get_one::<String>("yaml_file") {
+                    Some(yaml_file) => graph::Graph::from_policy_definitions(
+                        &config::policy_definition::PolicyDefinition::from_yaml_file(yaml_file)?,
+                    )?,
+                    None => graph::Graph::from_store()?,
+                };
+                print!("{}", graph.render(&format));
+            }
+            Ok(())
+        }
         Some("bench") => {
             let bench_arg = matches
                 .subcommand_matches("bench")
@@ -209,18 +670,67 @@ async fn main() -> Result<()> {
                     .get_one::<String>("wasm-path")
                     .map(|output| PathBuf::from_str(output).unwrap())
                     .unwrap();
+                // the output path is missing only with --validate-only
+                let destination = if matches
+                    .get_one::<bool>("in-place")
+                    .unwrap_or(&false)
+                    .to_owned()
+                {
+                    Some(wasm_path.clone())
+                } else {
+                    matches
+                        .get_one::<String>("output-path")
+                        .map(|output| PathBuf::from_str(output).unwrap())
+                };
+                if matches
+                    .get_one::<bool>("strip")
+                    .unwrap_or(&false)
+                    .to_owned()
+                {
+                    return annotate::strip_annotation(
+                        wasm_path,
+                        destination.expect("the output path is required with --strip"),
+                    );
+                }
                 let metadata_file = matches
                     .get_one::<String>("metadata-path")
-                    .map(|output| PathBuf::from_str(output).unwrap())
-                    .unwrap();
-                let destination = matches
-                    .get_one::<String>("output-path")
-                    .map(|output| PathBuf::from_str(output).unwrap())
-                    .unwrap();
+                    .map(|output| PathBuf::from_str(output).unwrap());
+                let overrides = annotate::Overrides {
+                    set: matches
+                        .get_many::<String>("set")
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect(),
+                    mutating: matches
+                        .get_one::<bool>("mutating")
+                        .unwrap_or(&false)
+                        .to_owned(),
+                    rules: matches
+                        .get_many::<String>("rule")
+                        .unwrap_or_default()
+                        .cloned()
+                        .collect(),
+                    settings_schema: matches
+                        .get_one::<String>("settings-schema")
+                        .map(|path| settings_schema::load(Path::new(path)))
+                        .transpose()?,
+                };
                 let usage_file = matches
                     .get_one::<String>("usage-path")
                     .map(|output| PathBuf::from_str(output).unwrap());
-                annotate::write_annotation(wasm_path, metadata_file, destination, usage_file)?;
+                let optimize_level = matches
+                    .get_one::<String>("optimize-level")
+                    .map(|level| optimize::OptimizeLevel::try_from(level.as_str()))
+                    .transpose()?;
+                annotate::write_annotation(
+                    wasm_path,
+                    metadata_file,
+                    overrides,
+                    destination,
+                    usage_file,
+                    !config::strict::is_lenient(matches),
+                    optimize_level,
+                )?;
             }
             Ok(())
         }
@@ -231,19 +741,48 @@ async fn main() -> Result<()> {
                     matches.get_one::<String>("output").map(|s| s.as_str()),
                 )?;
                 let sources = remote_server_options(matches)?;
+                let uri_or_sha_prefix =
+                    &version_constraints::resolve(uri_or_sha_prefix, sources.as_ref()).await?;
                 let no_signatures = !matches
                     .get_one::<bool>("show-signatures")
                     .unwrap_or(&false)
                     .to_owned();
-                inspect::inspect(uri_or_sha_prefix, output, sources, no_color, no_signatures)
-                    .await?;
+                let settings_schema_only = matches
+                    .get_one::<bool>("settings-schema-only")
+                    .unwrap_or(&false)
+                    .to_owned();
+                let mirrors = registry_mirrors(matches)?;
+                let location = if matches
+                    .get_one::<bool>("remote")
+                    .unwrap_or(&false)
+                    .to_owned()
+                {
+                    inspect::Location::Remote(&mirrors)
+                } else {
+                    inspect::Location::Local
+                };
+                inspect::inspect(
+                    uri_or_sha_prefix,
+                    location,
+                    output,
+                    sources,
+                    no_color,
+                    no_signatures,
+                    settings_schema_only,
+                )
+                .await?;
             };
             Ok(())
         }
         Some("scaffold") => {
             if let Some(matches) = matches.subcommand_matches("scaffold") {
-                if let Some(_matches) = matches.subcommand_matches("verification-config") {
-                    println!("{}", scaffold::verification_config()?);
+                if let Some(matches) = matches.subcommand_matches("verification-config") {
+                    println!(
+                        "{}",
+                        scaffold::verification_config(build_verification_options_from_flags(
+                            matches
+                        )?)?
+                    );
                 }
             }
             if let Some(matches) = matches.subcommand_matches("scaffold") {
@@ -258,9 +797,6 @@ async fn main() -> Result<()> {
                             )
                         })?;
 
-                    if artifacthub_matches.get_one::<String>("version").is_some() {
-                        tracing::warn!("The 'version' flag is deprecated and will be removed in a future release. The value of the `io.kubewarden.policy.version` field in the policy metadata file is used instead.");
-                    }
                     let questions_file = artifacthub_matches
                         .get_one::<String>("questions-path")
                         .map(|output| PathBuf::from_str(output).unwrap())
@@ -272,7 +808,10 @@ async fn main() -> Result<()> {
                                 "questions.yaml",
                             ])
                         });
-                    let content = scaffold::artifacthub(metadata_file, questions_file)?;
+                    let version = artifacthub_matches
+                        .get_one::<String>("version")
+                        .map(String::as_str);
+                    let content = scaffold::artifacthub(metadata_file, questions_file, version)?;
                     if let Some(output) = artifacthub_matches.get_one::<String>("output") {
                         let output_path = PathBuf::from_str(output)?;
                         fs::write(output_path, content)?;
@@ -292,11 +831,14 @@ async fn main() -> Result<()> {
                     let vap_file: PathBuf = matches.get_one::<String>("policy").unwrap().into();
                     let vap_binding_file: PathBuf =
                         matches.get_one::<String>("binding").unwrap().into();
+                    let params_file: Option<PathBuf> =
+                        matches.get_one::<String>("params").map(|p| p.into());
 
                     scaffold::vap(
                         cel_policy_uri.as_str(),
                         vap_file.as_path(),
                         vap_binding_file.as_path(),
+                        params_file.as_deref(),
                     )?;
                 };
             }
@@ -318,9 +860,37 @@ async fn main() -> Result<()> {
                         None
                     };
 
-                    scaffold::admission_request(operation, object_path, old_object_path).await?;
+                    let live_object = matches
+                        .get_one::<String>("from-k8s")
+                        .map(|reference| {
+                            scaffold::LiveObject::parse(
+                                reference,
+                                matches.get_one::<String>("namespace").cloned(),
+                            )
+                        })
+                        .transpose()?;
+
+                    scaffold::admission_request(
+                        operation,
+                        object_path,
+                        old_object_path,
+                        live_object,
+                    )
+                    .await?;
                 };
             }
+            if let Some(matches) = matches.subcommand_matches("scaffold") {
+                if let Some(matches) = matches.subcommand_matches("policy") {
+                    let language: scaffold::PolicyLanguage = matches
+                        .get_one::<String>("language")
+                        .unwrap()
+                        .parse()
+                        .map_err(|e| anyhow!("Error parsing language: {}", e))?;
+                    let name = matches.get_one::<String>("name").unwrap();
+                    let output_dir = matches.get_one::<String>("output-dir").map(PathBuf::from);
+                    scaffold::policy(language, name, output_dir)?;
+                }
+            }
 
             Ok(())
         }
@@ -333,9 +903,11 @@ async fn main() -> Result<()> {
         Some("digest") => {
             if let Some(matches) = matches.subcommand_matches("digest") {
                 let uri = matches.get_one::<String>("uri").unwrap();
+                registry_credentials(matches, uri)?;
                 let sources = remote_server_options(matches)?;
-                let registry = Registry::new();
-                let digest = registry.manifest_digest(uri, sources.as_ref()).await?;
+                let mirrors = registry_mirrors(matches)?;
+                let uri = &version_constraints::resolve(uri, sources.as_ref()).await?;
+                let digest = digest_command(uri, sources.as_ref(), &mirrors).await?;
                 println!("{uri}@{digest}");
             }
             Ok(())
@@ -349,8 +921,313 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
+        Some("store") => {
+            if let Some(matches) = matches.subcommand_matches("store") {
+                if let Some(matches) = matches.subcommand_matches("gc") {
+                    return store_gc(matches);
+                }
+                if matches.subcommand_matches("verify").is_some() {
+                    return store_integrity::verify();
+                }
+                if let Some(matches) = matches.subcommand_matches("export") {
+                    let policies: Vec<String> = matches
+                        .get_many::<String>("policies")
+                        .map(|policies| policies.cloned().collect())
+                        .unwrap_or_default();
+                    let dir = matches.get_one::<String>("oci-layout").unwrap();
+                    let report = oci_layout::export(
+                        Path::new(dir),
+                        &oci_layout::select_policies(&policies)?,
+                    )?;
+                    for reference in &report.exported {
+                        println!("Policy exported: {reference}");
+                    }
+                    for uri in &report.skipped {
+                        println!("Policy skipped, not pulled from a registry: {uri}");
+                    }
+                    println!("{} policies exported to {}", report.exported.len(), dir);
+                    return Ok(());
+                }
+                if matches.subcommand_matches("dedup").is_some() {
+                    store_mode::ensure_writable("deduplicate the store")?;
+                    let report = store_dedup::dedup_store()?;
+                    println!(
+                        "{} policies deduplicated, {} saved",
+                        report.policies,
+                        humansize::format_size(report.saved, humansize::DECIMAL)
+                    );
+                    return Ok(());
+                }
+                if matches.subcommand_matches("pull").is_some() {
+                    store_mode::ensure_writable("pull policies from another store")?;
+                }
+                let (report, direction) = match matches.subcommand() {
+                    Some(("push", matches)) => (
+                        store_sync::push(matches.get_one::<String>("target").unwrap())?,
+                        "pushed",
+                    ),
+                    Some(("pull", matches)) => (
+                        store_sync::pull(matches.get_one::<String>("target").unwrap())?,
+                        "pulled",
+                    ),
+                    _ => unreachable!("store subcommand is required"),
+                };
+                for policy in &report.transferred {
+                    println!("Policy {direction}: {policy}");
+                }
+                println!(
+                    "{} policies {}, {} already in sync",
+                    report.transferred.len(),
+                    direction,
+                    report.up_to_date
+                );
+            }
+            Ok(())
+        }
+        Some("changelog") => {
+            if let Some(matches) = matches.subcommand_matches("changelog") {
+                let repository = matches.get_one::<String>("repository").unwrap();
+                let from = matches.get_one::<String>("from").unwrap();
+                let to = matches.get_one::<String>("to").unwrap();
+                let from_uri = changelog::tagged_uri(repository, from)?;
+                let to_uri = changelog::tagged_uri(repository, to)?;
+
+                registry_credentials(matches, &from_uri)?;
+                let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let old = changelog::fetch_metadata(&from_uri, sources.as_ref(), &mirrors).await?;
+                let new = changelog::fetch_metadata(&to_uri, sources.as_ref(), &mirrors).await?;
+                print!("{}", changelog::render(repository, from, to, &old, &new)?);
+            }
+            Ok(())
+        }
+        Some("diff") => {
+            if let Some(matches) = matches.subcommand_matches("diff") {
+                let old_uri = matches.get_one::<String>("old_uri_or_sha_prefix").unwrap();
+                let new_uri = matches.get_one::<String>("new_uri_or_sha_prefix").unwrap();
+
+                registry_credentials(matches, old_uri)?;
+                let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let old = diff::DiffedPolicy::fetch(old_uri, sources.as_ref(), &mirrors).await?;
+                let new = diff::DiffedPolicy::fetch(new_uri, sources.as_ref(), &mirrors).await?;
+                let evaluations = matches
+                    .get_one::<String>("requests-dir")
+                    .map(|requests_dir| {
+                        diff::evaluate(
+                            &old,
+                            &new,
+                            Path::new(requests_dir),
+                            &config::policy_definition::settings_from_cli(matches)?,
+                            matches.get_one::<bool>("raw").unwrap_or(&false).to_owned(),
+                        )
+                    })
+                    .transpose()?;
+                print!("{}", diff::render(&old, &new, evaluations.as_deref())?);
+            }
+            Ok(())
+        }
+        Some("test") => {
+            if let Some(matches) = matches.subcommand_matches("test") {
+                let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let options = test_suite::RunOptions {
+                    update_snapshots: matches
+                        .get_one::<bool>("update-snapshots")
+                        .unwrap_or(&false)
+                        .to_owned(),
+                    filter: matches
+                        .get_one::<String>("filter")
+                        .map(|filter| {
+                            regex::Regex::new(filter)
+                                .map_err(|e| anyhow!("invalid filter '{}': {}", filter, e))
+                        })
+                        .transpose()?,
+                    fail_fast: matches
+                        .get_one::<bool>("fail-fast")
+                        .unwrap_or(&false)
+                        .to_owned(),
+                    jobs: matches.get_one::<usize>("jobs").copied().unwrap_or(1),
+                };
+                let report_output = test_report::report_output(matches)?;
+
+                let start = Instant::now();
+                let mut suites = Vec::new();
+                for path in matches.get_many::<String>("suites").unwrap_or_default() {
+                    let path = Path::new(path);
+                    let suite = test_suite::load(path)?;
+                    if !options.selects_any(&suite) {
+                        continue;
+                    }
+                    registry_credentials(matches, suite.policy())?;
+                    suites
+                        .push(test_suite::prepare(path, suite, sources.as_ref(), &mirrors).await?);
+                }
+                let test_run = tokio::task::block_in_place(|| test_suite::run(&suites, &options));
+
+                let (mut failed, mut total) = (0, 0);
+                for outcome in &test_run.suites {
+                    print!("{}", outcome.render());
+                    failed += outcome.failed();
+                    total += outcome.total();
+                }
+                if let Some(report_output) = &report_output {
+                    let reports: Vec<_> = test_run
+                        .suites
+                        .iter()
+                        .map(|outcome| outcome.report())
+                        .collect();
+                    test_report::write(&reports, report_output)?;
+                }
+                if let Some(metrics_path) = matches.get_one::<String>("metrics-path") {
+                    test_run
+                        .metrics(start.elapsed())
+                        .write(Path::new(metrics_path))?;
+                }
+                let mut summary = format!("\n{} passed, {} failed", total - failed, failed);
+                if test_run.filtered_out > 0 {
+                    summary.push_str(&format!(", {} filtered out", test_run.filtered_out));
+                }
+                if test_run.not_run > 0 {
+                    summary.push_str(&format!(", {} not run", test_run.not_run));
+                }
+                println!("{summary}");
+                if failed > 0 {
+                    return Err(anyhow!("{} out of {} test cases failed", failed, total));
+                }
+                if total == 0 {
+                    return Err(anyhow!("no test case matches the filter"));
+                }
+            }
+            Ok(())
+        }
+        Some("fuzz") => {
+            if let Some(matches) = matches.subcommand_matches("fuzz") {
+                let uri = matches.get_one::<String>("policy").unwrap();
+                let seed_request_path = matches.get_one::<String>("seed-request").unwrap();
+                let seed_request: serde_json::Value =
+                    serde_json::from_str(&fs::read_to_string(seed_request_path).map_err(|e| {
+                        anyhow!("cannot read seed request {}: {}", seed_request_path, e)
+                    })?)
+                    .map_err(|e| {
+                        anyhow!("cannot parse seed request {}: {}", seed_request_path, e)
+                    })?;
+                let options = fuzz::FuzzOptions {
+                    iterations: matches
+                        .get_one::<u64>("iterations")
+                        .copied()
+                        .unwrap_or(1000),
+                    seed: matches
+                        .get_one::<u64>("seed")
+                        .copied()
+                        .unwrap_or_else(fuzz::random_seed),
+                    timeout: Duration::from_secs(
+                        matches.get_one::<u64>("timeout").copied().unwrap_or(2),
+                    ),
+                    raw: matches.get_one::<bool>("raw").unwrap_or(&false).to_owned(),
+                    output_dir: matches.get_one::<String>("output-dir").map(PathBuf::from),
+                };
+
+                registry_credentials(matches, uri)?;
+                let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let policy = fuzz::FuzzedPolicy::fetch(uri, sources.as_ref(), &mirrors).await?;
+                let findings = if matches
+                    .get_one::<bool>("fuzz-settings")
+                    .unwrap_or(&false)
+                    .to_owned()
+                {
+                    tokio::task::block_in_place(|| {
+                        fuzz::fuzz_settings(&policy, &seed_request, &options)
+                    })?
+                } else {
+                    let settings = config::policy_definition::settings_from_cli(matches)?;
+                    tokio::task::block_in_place(|| {
+                        fuzz::fuzz(&policy, &seed_request, &settings, &options)
+                    })?
+                };
+
+                for finding in &findings {
+                    println!("{finding}");
+                }
+                println!(
+                    "\n{} requests evaluated with seed {}, {} misbehaviors found",
+                    options.iterations,
+                    options.seed,
+                    findings.len()
+                );
+                if !findings.is_empty() {
+                    return Err(anyhow!(
+                        "the policy misbehaved on {} requests",
+                        findings.len()
+                    ));
+                }
+            }
+            Ok(())
+        }
+        Some("sources") => {
+            if let Some(Some(matches)) = matches
+                .subcommand_matches("sources")
+                .map(|matches| matches.subcommand_matches("probe"))
+            {
+                let uri = matches.get_one::<String>("uri").unwrap();
+                if !uri.starts_with("registry://") {
+                    return Err(anyhow!(
+                        "only registry:// policies can be served by mirrors"
+                    ));
+                }
+                registry_credentials(matches, uri)?;
+                let sources = remote_server_options(matches)?;
+                let mirrors = registry_mirrors(matches)?;
+                let probes = mirror_health::probe(uri, sources.as_ref(), &mirrors).await;
+                mirror_health::print_probes(&probes);
+                if probes.iter().all(|probe| probe.outcome.is_err()) {
+                    return Err(anyhow!("none of the sources of {} is available", uri));
+                }
+            }
+            Ok(())
+        }
+        Some("trust-root") => {
+            if let Some(matches) = matches.subcommand_matches("trust-root") {
+                let status = match matches.subcommand() {
+                    Some(("update", matches)) => {
+                        let source = if *matches.get_one::<bool>("reset").unwrap_or(&false) {
+                            Some(trust_root::TufSource::PublicGood)
+                        } else {
+                            matches.get_one::<String>("tuf-mirror").map(|url| {
+                                trust_root::TufSource::Mirror {
+                                    url: url.to_owned(),
+                                    root: PathBuf::from(
+                                        matches.get_one::<String>("tuf-root").unwrap(),
+                                    ),
+                                }
+                            })
+                        };
+                        trust_root::update(source).await?
+                    }
+                    Some(("status", _)) => trust_root::status()?,
+                    _ => unreachable!("trust-root subcommand is required"),
+                };
+                print!("{status}");
+            }
+            Ok(())
+        }
         Some("load") => {
             if let Some(matches) = matches.subcommand_matches("load") {
+                store_mode::ensure_writable("load policies")?;
+                if let Some(dir) = matches.get_one::<String>("oci-layout") {
+                    let report = oci_layout::import(
+                        Path::new(dir),
+                        matches.get_one::<String>("repository").map(String::as_str),
+                    )?;
+                    for uri in &report.imported {
+                        println!("Policy loaded: {uri}");
+                    }
+                    for (name, reason) in &report.skipped {
+                        println!("Skipped {name}: {reason}");
+                    }
+                    return Ok(());
+                }
                 let input = matches.get_one::<String>("input").unwrap();
                 load(input)?;
             }
@@ -367,15 +1244,31 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
-        Some(command) => Err(anyhow!("unknown subcommand: {}", command)),
-        None => {
+        Some(_) | None => {
             // NOTE: this should not happen due to
-            // SubcommandRequiredElseHelp setting
+            // SubcommandRequiredElseHelp setting, and the plugins being run
+            // before
             unreachable!();
         }
     }
 }
 
+fn provenance_requirements(matches: &ArgMatches) -> Result<attestations::ProvenanceRequirements> {
+    let keys = matches
+        .get_many::<String>("verification-key")
+        .ok_or_else(|| {
+            anyhow!("the provenance signature can be verified only with --verification-key")
+        })?
+        .map(|key| config::verification::read_public_key(key).map(String::into_bytes))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(attestations::ProvenanceRequirements {
+        keys,
+        builder_id: matches.get_one::<String>("builder-id").unwrap().to_owned(),
+        source_repository: matches.get_one::<String>("source-repository").cloned(),
+    })
+}
+
 // Check if the policy is already present in the local store, and if not, pull it from the remote server.
 async fn pull_if_needed(uri_or_sha_prefix: &str, matches: &ArgMatches) -> Result<()> {
     match crate::utils::get_wasm_path(uri_or_sha_prefix) {
@@ -398,6 +1291,8 @@ async fn pull_command(
     matches: &ArgMatches,
 ) -> Result<()> {
     let sources = remote_server_options(matches)?;
+    let mirrors = registry_mirrors(matches)?;
+    let uri = &version_constraints::resolve(uri, sources.as_ref()).await?;
 
     let verification_options = build_verification_options(matches)?;
     let mut verified_manifest_digest: Option<String> = None;
@@ -409,6 +1304,7 @@ async fn pull_command(
             verify::verify(
                 uri,
                 sources.as_ref(),
+                &mirrors,
                 verification_options.as_ref().unwrap(),
                 sigstore_trust_root.clone(),
             )
@@ -417,31 +1313,473 @@ async fn pull_command(
         );
     }
 
-    let policy = pull::pull(uri, sources.as_ref(), destination).await?;
+    let policy = pull::pull(uri, sources.as_ref(), &mirrors, destination).await?;
 
-    if verification_options.is_some() {
+    if let Some(verification_options) = verification_options {
         let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
-        return verify::verify_local_checksum(
+        let result = verify::verify_local_checksum(
             &policy,
             sources.as_ref(),
             &verified_manifest_digest.unwrap(),
             sigstore_trust_root.clone(),
         )
         .await;
+        provenance::record_verification(uri, result.is_ok(), &verification_options.origin);
+        return result;
+    }
+    Ok(())
+}
+
+// Fetches the manifest digest of the policy, trying the registry mirrors first
+// and falling back to the upstream registry.
+async fn digest_command(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Result<String> {
+    let registry = &Registry::new();
+    mirror_health::first_successful(
+        &mirrors.candidates(uri),
+        "fetch digest",
+        |candidate| async move { Ok(registry.manifest_digest(&candidate, sources).await?) },
+    )
+    .await
+}
+
+/// Pulls the newest release of the policies of the store whose URI matches
+/// the given pattern, see [`updates`]. The previous releases are removed,
+/// unless `--keep` is given.
+async fn update_command(matches: &ArgMatches) -> Result<()> {
+    let pattern = matches.get_one::<String>("pattern");
+    let constraint = matches
+        .get_one::<String>("constraint")
+        .map(|constraint| {
+            semver::VersionReq::parse(constraint)
+                .map_err(|e| anyhow!("invalid version constraint '{}': {}", constraint, e))
+        })
+        .transpose()?;
+    let dry_run = matches
+        .get_one::<bool>("dry-run")
+        .unwrap_or(&false)
+        .to_owned();
+    let keep = matches.get_one::<bool>("keep").unwrap_or(&false).to_owned();
+    if !dry_run {
+        store_mode::ensure_writable("update policies")?;
+    }
+    let sources = remote_server_options(matches)?;
+
+    let mut outcomes = Vec::new();
+    for policy in store_mode::list()? {
+        if pattern.is_some_and(|pattern| !updates::matches_pattern(&policy.uri, pattern)) {
+            continue;
+        }
+        let Some((reference, current)) = updates::current_version(&policy.uri) else {
+            continue;
+        };
+        registry_credentials(matches, &policy.uri)?;
+        let outcome = match updates::list_tags(&reference, sources.as_ref()).await {
+            Ok(tags) => match updates::newest_release(&current, &tags, constraint.as_ref()) {
+                None => updates::Outcome::UpToDate,
+                Some(tag) if dry_run => {
+                    updates::Outcome::Available(updates::release_uri(&policy.uri, tag))
+                }
+                Some(tag) => {
+                    let uri = updates::release_uri(&policy.uri, tag);
+                    match pull_command(&uri, PullDestination::MainStore, matches).await {
+                        Ok(()) if keep => updates::Outcome::Updated(uri),
+                        Ok(()) => match rm::rm(&policy.uri) {
+                            Ok(()) => updates::Outcome::Updated(uri),
+                            Err(e) => updates::Outcome::Failed(format!(
+                                "{uri} pulled, but the previous release cannot be removed: {e}"
+                            )),
+                        },
+                        Err(e) => updates::Outcome::Failed(e.to_string()),
+                    }
+                }
+            },
+            Err(e) => updates::Outcome::Failed(e.to_string()),
+        };
+        outcomes.push((policy.uri, outcome));
+    }
+    if !dry_run && !keep {
+        store_dedup::prune()?;
+    }
+
+    if outcomes.is_empty() {
+        info!("no policy of the store has been pulled via a semantic version tag matching the given pattern");
+        return Ok(());
+    }
+    updates::report(&outcomes)
+}
+
+/// Pushes all the policies of the local store pulled from a registry under
+/// `prefix`, preserving their repository paths and tags
+async fn push_store(matches: &ArgMatches, prefix: &str) -> Result<()> {
+    let prefix = if prefix.starts_with("registry://") {
+        prefix.to_string()
+    } else {
+        format!("registry://{prefix}")
+    };
+    registry_credentials(matches, &prefix)?;
+    let sources = remote_server_options(matches)?;
+
+    let force = matches.contains_id("force");
+    let if_not_exists = matches
+        .get_one::<bool>("if-not-exists")
+        .unwrap_or(&false)
+        .to_owned();
+    let dry_run = matches
+        .get_one::<bool>("dry-run")
+        .unwrap_or(&false)
+        .to_owned();
+    let annotations = crate::utils::parse_annotations(
+        matches.get_many::<String>("annotation").unwrap_or_default(),
+    )?;
+    let signing_method = push_signing_method(matches)?;
+    let sign_annotations = crate::utils::parse_annotations(
+        matches
+            .get_many::<String>("sign-annotation")
+            .unwrap_or_default(),
+    )?;
+
+    let mut pushed: Vec<push::PushedPolicy> = Vec::new();
+    let mut plans: Vec<push::PushPlan> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for policy in store_mode::list()? {
+        let Some(destination) = push::store_mirror_uri(&policy.uri, &prefix) else {
+            warn!(
+                policy = policy.uri.as_str(),
+                "skipping policy, only the policies pulled from a registry by tag can be mirrored"
+            );
+            continue;
+        };
+
+        let upload =
+            match push::PolicyUpload::new(policy.local_path.clone(), force, &annotations, None) {
+                Ok(upload) => upload,
+                Err(e) => {
+                    errors.push(format!("  - {}: {}", policy.uri, e));
+                    continue;
+                }
+            };
+
+        if dry_run {
+            let plan = async {
+                if if_not_exists {
+                    push::ensure_tag_not_taken(&upload, &destination, sources.as_ref()).await?;
+                }
+                upload.plan(&destination)
+            }
+            .await;
+            match plan {
+                Ok(mut plan) => {
+                    plan.source = Some(policy.uri.clone());
+                    plans.push(plan);
+                }
+                Err(e) => errors.push(format!("  - {}: {}", policy.uri, e)),
+            }
+            continue;
+        }
+
+        let result = async {
+            if if_not_exists {
+                push::ensure_tag_not_taken(&upload, &destination, sources.as_ref()).await?;
+            }
+            push_to_destination(
+                &upload,
+                &destination,
+                signing_method
+                    .as_ref()
+                    .map(|method| (method, &sign_annotations)),
+                &[],
+                sources.as_ref(),
+            )
+            .await
+        }
+        .await;
+
+        match result {
+            Ok(mut pushed_policy) => {
+                pushed_policy.source = Some(policy.uri.clone());
+                pushed.push(pushed_policy);
+            }
+            Err(e) => errors.push(format!("  - {}: {}", policy.uri, e)),
+        }
+    }
+
+    let output = matches.get_one::<String>("output").map(|s| s.as_str());
+    if dry_run {
+        print_push_plans(&plans, output)?;
+    } else {
+        match output {
+            Some("json") => serde_json::to_writer(std::io::stdout(), &pushed)?,
+            _ => {
+                for pushed_policy in &pushed {
+                    println!(
+                        "Policy {} successfully pushed: {}",
+                        pushed_policy.source.as_deref().unwrap_or_default(),
+                        pushed_policy.immutable_ref
+                    );
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} policies cannot be pushed:\n{}",
+            errors.len(),
+            errors.join("\n")
+        ))
+    }
+}
+
+fn print_push_plans(plans: &[push::PushPlan], output: Option<&str>) -> Result<()> {
+    match output {
+        Some("json") => serde_json::to_writer(std::io::stdout(), plans)?,
+        _ => {
+            println!("Dry run, nothing has been pushed");
+            for plan in plans {
+                print!("{plan}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How the pushed policies are signed, if they are
+fn push_signing_method(matches: &ArgMatches) -> Result<Option<sign::SigningMethod<'_>>> {
+    if let Some(key) = matches.get_one::<String>("sign-key") {
+        Ok(Some(sign::SigningMethod::key(
+            key,
+            matches
+                .get_one::<String>("sign-key-password")
+                .map(String::as_str),
+        )?))
+    } else if matches.get_flag("sign-keyless") {
+        Ok(Some(sign::SigningMethod::Keyless(keyless_options(
+            matches, "sign-",
+        )?)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Reads the keyless signing flags, named with the given prefix
+fn keyless_options(matches: &ArgMatches, prefix: &str) -> Result<sign::KeylessOptions> {
+    let value = |name: &str| {
+        matches
+            .get_one::<String>(&format!("{prefix}{name}"))
+            .cloned()
+    };
+    let url = |name: &str| {
+        value(name)
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| anyhow!("keyless signing requires the --{}{} URL", prefix, name))
+    };
+    Ok(sign::KeylessOptions {
+        identity_token: value("identity-token"),
+        credential_providers: config::sources::credential_providers(matches)?,
+        network: config::verification::build_network_options(matches)?,
+        fulcio_url: url("fulcio-url")?,
+        rekor_url: url("rekor-url")?,
+    })
+}
+
+/// Pushes the policy to a single destination, then signs it and attaches the
+/// attestations to it
+async fn push_to_destination(
+    upload: &push::PolicyUpload,
+    destination: &str,
+    signing: Option<(&sign::SigningMethod<'_>, &HashMap<String, String>)>,
+    attestations: &[attestations::Attestation],
+    sources: Option<&Sources>,
+) -> Result<push::PushedPolicy> {
+    let immutable_ref = push::push(upload, destination, sources).await?;
+
+    if let Some((method, annotations)) = signing {
+        sign::sign(&immutable_ref, method, annotations, sources)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Policy pushed as {}, but it cannot be signed: {}",
+                    immutable_ref,
+                    e
+                )
+            })?;
     }
+
+    let referrers = if attestations.is_empty() {
+        Vec::new()
+    } else {
+        attestations::attach(&immutable_ref, attestations, sources)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Policy pushed as {}, but the attestations cannot be attached: {}",
+                    immutable_ref,
+                    e
+                )
+            })?
+    };
+
+    Ok(push::PushedPolicy {
+        source: None,
+        uri: destination.to_string(),
+        immutable_ref,
+        referrers,
+    })
+}
+
+fn store_gc(matches: &ArgMatches) -> Result<()> {
+    let filters = store_gc::GcFilters {
+        older_than: matches
+            .get_one::<String>("older-than")
+            .map(|age| store_gc::parse_age(age))
+            .transpose()?,
+        unused: matches
+            .get_one::<bool>("unused")
+            .unwrap_or(&false)
+            .to_owned(),
+        keep_latest: matches.get_one::<usize>("keep-latest").copied(),
+    };
+    let dry_run = matches
+        .get_one::<bool>("dry-run")
+        .unwrap_or(&false)
+        .to_owned();
+
+    if !dry_run {
+        store_mode::ensure_writable("remove policies")?;
+    }
+    let report = store_gc::gc(&filters, dry_run)?;
+    let action = if dry_run { "Would remove" } else { "Removed" };
+    for (uri, size) in &report.removed {
+        println!(
+            "{action} {uri} ({})",
+            humansize::format_size(*size, humansize::DECIMAL)
+        );
+    }
+    println!(
+        "{} policies {}, {} {}",
+        report.removed.len(),
+        if dry_run {
+            "would be removed"
+        } else {
+            "removed"
+        },
+        humansize::format_size(report.reclaimed, humansize::DECIMAL),
+        if dry_run {
+            "would be reclaimed"
+        } else {
+            "reclaimed"
+        }
+    );
     Ok(())
 }
 
+/// Fast path of `scaffold manifest` for registry policies missing from the
+/// local store: the metadata is read from the annotations of the OCI
+/// manifest, without pulling the policy. Returns `None` when the policy must
+/// be pulled instead.
+async fn lazy_metadata(
+    uri_or_sha_prefix: &str,
+    matches: &ArgMatches,
+) -> Result<Option<policy_evaluator::policy_metadata::Metadata>> {
+    if !uri_or_sha_prefix.starts_with("registry://")
+        || crate::utils::get_wasm_path(uri_or_sha_prefix).is_ok()
+    {
+        return Ok(None);
+    }
+    let uri = uri_or_sha_prefix;
+    registry_credentials(matches, uri)?;
+    let sources = remote_server_options(matches)?;
+    let mirrors = registry_mirrors(matches)?;
+
+    // the manifest must be the one whose signatures have been verified
+    let candidates = match build_verification_options(matches)? {
+        Some(verification_options) => {
+            let sigstore_trust_root = build_sigstore_trust_root(matches.to_owned()).await?;
+            let verified_manifest_digest = verify::verify(
+                uri,
+                sources.as_ref(),
+                &mirrors,
+                &verification_options,
+                sigstore_trust_root,
+            )
+            .await
+            .map_err(|e| anyhow!("Policy {} cannot be validated\n{:?}", uri, e))?;
+            mirror_health::ordered_candidates(uri, sources.as_ref(), &mirrors)
+                .await
+                .into_iter()
+                .map(|candidate| format!("{candidate}@{verified_manifest_digest}"))
+                .collect()
+        }
+        None => mirror_health::ordered_candidates(uri, sources.as_ref(), &mirrors).await,
+    };
+
+    let metadata = scaffold::remote_metadata(&candidates, sources.as_ref()).await?;
+    if metadata.is_none() {
+        info!(
+            policy = uri,
+            "the OCI manifest does not contain the policy metadata, pulling the policy"
+        );
+    }
+    Ok(metadata)
+}
+
 /*
  * Scaffold a manifest from a policy.
  * This function will pull the policy if it is not already present in the local store.
  */
+/// Resolves the policy and reads its metadata, pulling the policy when the
+/// metadata cannot be read from the registry
+async fn manifest_metadata(
+    uri_or_sha_prefix: &str,
+    matches: &ArgMatches,
+) -> Result<(String, policy_evaluator::policy_metadata::Metadata)> {
+    let sources = remote_server_options(matches)?;
+    let uri_or_sha_prefix =
+        &version_constraints::resolve(uri_or_sha_prefix, sources.as_ref()).await?;
+
+    match lazy_metadata(uri_or_sha_prefix, matches).await? {
+        Some(metadata) => Ok((uri_or_sha_prefix.to_owned(), metadata)),
+        None => {
+            pull_if_needed(uri_or_sha_prefix, matches).await?;
+            scaffold::local_metadata(uri_or_sha_prefix)
+        }
+    }
+}
+
 async fn scaffold_manifest_command(matches: &ArgMatches) -> Result<()> {
-    let uri_or_sha_prefix = matches.get_one::<String>("uri_or_sha_prefix").unwrap();
+    if let Some(requirements_path) = matches.get_one::<String>("from-file") {
+        return scaffold_batch_manifest_command(matches, Path::new(requirements_path)).await;
+    }
+
+    let resource_type: scaffold::ManifestType =
+        matches.get_one::<String>("type").unwrap().parse()?;
+    if resource_type.is_group() {
+        return scaffold_group_manifest_command(matches, resource_type).await;
+    }
+    if matches.contains_id("expression") {
+        return Err(anyhow!("'expression' can be used only with policy groups"));
+    }
+    if matches
+        .get_many::<String>("uri_or_sha_prefix")
+        .is_some_and(|uris| uris.len() > 1)
+        || matches
+            .get_many::<String>("settings-path")
+            .is_some_and(|paths| paths.len() > 1)
+    {
+        return Err(anyhow!(
+            "only policy groups can be scaffolded from more than one policy"
+        ));
+    }
 
-    pull_if_needed(uri_or_sha_prefix, matches).await?;
+    let uri_or_sha_prefix = matches.get_one::<String>("uri_or_sha_prefix").unwrap();
+    let (uri, metadata) = manifest_metadata(uri_or_sha_prefix, matches).await?;
 
-    let resource_type = matches.get_one::<String>("type").unwrap();
     if matches.contains_id("settings-path") && matches.contains_id("settings-json") {
         return Err(anyhow!(
             "'settings-path' and 'settings-json' cannot be used at the same time"
@@ -462,16 +1800,159 @@ async fn scaffold_manifest_command(matches: &ArgMatches) -> Result<()> {
     };
     let policy_title = matches.get_one::<String>("title").cloned();
 
-    let allow_context_aware_resources = matches
-        .get_one::<bool>("allow-context-aware")
-        .unwrap_or(&false)
-        .to_owned();
+    let allow_context_aware_resources = context_aware_manifest(matches);
 
     scaffold::manifest(
-        uri_or_sha_prefix,
-        resource_type.parse()?,
+        uri,
+        metadata,
+        resource_type,
         settings.as_deref(),
         policy_title.as_deref(),
         allow_context_aware_resources,
+        manifest_options(matches)?,
+        &manifest_output(matches),
+    )
+}
+
+/// The Kubernetes resources declared by the policy metadata are granted to the
+/// policy, unless the user opts out
+fn context_aware_manifest(matches: &ArgMatches) -> bool {
+    if matches
+        .get_one::<bool>("allow-context-aware")
+        .unwrap_or(&false)
+        .to_owned()
+    {
+        warn!("The `--allow-context-aware` flag is deprecated: the Kubernetes resources declared by the policy metadata are granted by default");
+    }
+    !matches
+        .get_one::<bool>("no-context-aware")
+        .unwrap_or(&false)
+        .to_owned()
+}
+
+fn manifest_output(matches: &ArgMatches) -> scaffold::ManifestOutput {
+    match matches
+        .get_one::<String>("output-format")
+        .map(|format| format.as_str())
+    {
+        Some("kustomize") => scaffold::ManifestOutput::Kustomize(
+            matches.get_one::<String>("output-dir").unwrap().into(),
+        ),
+        Some("helm") => scaffold::ManifestOutput::Helm,
+        _ => scaffold::ManifestOutput::Yaml,
+    }
+}
+
+fn manifest_options(matches: &ArgMatches) -> Result<scaffold::ManifestOptions> {
+    let selector = |id: &str| {
+        matches
+            .get_one::<String>(id)
+            .map(|selector| scaffold::label_selector::parse(selector))
+            .transpose()
+    };
+
+    Ok(scaffold::ManifestOptions {
+        policy_server: matches.get_one::<String>("policy-server").cloned(),
+        mode: matches.get_one::<String>("mode").cloned(),
+        failure_policy: matches.get_one::<String>("failure-policy").cloned(),
+        background_audit: matches.get_one::<bool>("background-audit").copied(),
+        namespace_selector: selector("namespace-selector")?,
+        object_selector: selector("object-selector")?,
+    })
+}
+
+async fn scaffold_batch_manifest_command(
+    matches: &ArgMatches,
+    requirements_path: &Path,
+) -> Result<()> {
+    let resource_type: scaffold::ManifestType = matches
+        .get_one::<String>("type")
+        .map(|resource_type| resource_type.parse())
+        .transpose()?
+        .unwrap_or(scaffold::ManifestType::ClusterAdmissionPolicy);
+    if resource_type.is_group() {
+        return Err(anyhow!(
+            "policy groups cannot be scaffolded from a requirements file"
+        ));
+    }
+
+    let mut policies = Vec::new();
+    for requirement in scaffold::read_requirements(requirements_path)? {
+        let (uri, metadata) = manifest_metadata(&requirement.uri, matches).await?;
+        policies.push((requirement, uri, metadata));
+    }
+
+    scaffold::batch_manifest(
+        policies,
+        resource_type,
+        context_aware_manifest(matches),
+        manifest_options(matches)?,
+        &manifest_output(matches),
+    )
+}
+
+async fn scaffold_group_manifest_command(
+    matches: &ArgMatches,
+    resource_type: scaffold::ManifestType,
+) -> Result<()> {
+    if matches.contains_id("settings-json") {
+        return Err(anyhow!(
+            "'settings-json' cannot be used with policy groups, use 'settings-path NAME=PATH' instead"
+        ));
+    }
+
+    let mut settings: HashMap<String, serde_yaml::Mapping> = HashMap::new();
+    for arg in matches
+        .get_many::<String>("settings-path")
+        .unwrap_or_default()
+    {
+        let (name, path) = arg.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "invalid settings-path '{}': policy groups expect NAME=PATH",
+                arg
+            )
+        })?;
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Error reading settings from {}: {}", path, e))?;
+        let policy_settings = serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("Error parsing settings from {}: {}", path, e))?;
+        settings.insert(name.to_owned(), policy_settings);
+    }
+
+    let mut members = Vec::new();
+    for arg in matches.get_many::<String>("uri_or_sha_prefix").unwrap() {
+        let (name, uri_or_sha_prefix) = scaffold::group_member(arg);
+        let (uri, metadata) = manifest_metadata(&uri_or_sha_prefix, matches).await?;
+        members.push(scaffold::GroupMember {
+            settings: settings.remove(&name).unwrap_or_default(),
+            name,
+            uri,
+            metadata,
+        });
+    }
+    if let Some(name) = settings.keys().next() {
+        return Err(anyhow!(
+            "settings given for '{}', which is not a policy of the group",
+            name
+        ));
+    }
+
+    let expression = matches.get_one::<String>("expression").unwrap();
+    let message = matches
+        .get_one::<String>("message")
+        .map(|message| message.as_str())
+        .unwrap_or("The request has been rejected by the policy group");
+    let policy_title = matches.get_one::<String>("title").cloned();
+    let allow_context_aware_resources = context_aware_manifest(matches);
+
+    scaffold::group_manifest(
+        members,
+        resource_type,
+        expression,
+        message,
+        policy_title.as_deref(),
+        allow_context_aware_resources,
+        manifest_options(matches)?,
+        &manifest_output(matches),
     )
 }
@@ -0,0 +1,283 @@
+//! Metrics written by the batch commands, and served by `kwctl serve`, using
+//! the OpenMetrics text format.
+//!
+//! They summarize the outcome of a run, allowing CI systems to track it over
+//! time without parsing the logs.
+
+use std::{collections::BTreeMap, fmt::Write, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MetricType {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+}
+
+type Labels = BTreeMap<&'static str, String>;
+
+#[derive(Debug)]
+enum Sample {
+    Value(f64),
+    /// The cumulative counts of the observations lower than, or equal to,
+    /// the upper bounds of the buckets
+    Histogram {
+        buckets: Vec<(f64, u64)>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Debug)]
+struct MetricFamily {
+    metric_type: MetricType,
+    help: &'static str,
+    samples: Vec<(Labels, Sample)>,
+}
+
+/// A set of metric families, rendered in the order they have been created
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    families: Vec<(&'static str, MetricFamily)>,
+}
+
+impl Metrics {
+    /// Adds a sample to a counter. The `_total` suffix is appended to the name
+    /// of the samples.
+    pub(crate) fn counter(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        value: f64,
+    ) {
+        self.sample(
+            MetricType::Counter,
+            name,
+            help,
+            labels,
+            Sample::Value(value),
+        );
+    }
+
+    pub(crate) fn gauge(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        value: f64,
+    ) {
+        self.sample(MetricType::Gauge, name, help, labels, Sample::Value(value));
+    }
+
+    /// Adds a sample to a histogram. `buckets` are the upper bounds of the
+    /// buckets, with the cumulative counts of the observations falling inside
+    /// of them. The `+Inf` bucket is added.
+    pub(crate) fn histogram(
+        &mut self,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        buckets: &[(f64, u64)],
+        sum: f64,
+        count: u64,
+    ) {
+        self.sample(
+            MetricType::Histogram,
+            name,
+            help,
+            labels,
+            Sample::Histogram {
+                buckets: buckets.to_vec(),
+                sum,
+                count,
+            },
+        );
+    }
+
+    fn sample(
+        &mut self,
+        metric_type: MetricType,
+        name: &'static str,
+        help: &'static str,
+        labels: &[(&'static str, &str)],
+        value: Sample,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (*name, value.to_string()))
+            .collect();
+        match self
+            .families
+            .iter_mut()
+            .find(|(family_name, _)| *family_name == name)
+        {
+            Some((_, family)) => {
+                debug_assert_eq!(family.metric_type, metric_type);
+                family.samples.push((labels, value));
+            }
+            None => self.families.push((
+                name,
+                MetricFamily {
+                    metric_type,
+                    help,
+                    samples: vec![(labels, value)],
+                },
+            )),
+        }
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let mut output = String::new();
+        for (name, family) in &self.families {
+            let _ = writeln!(output, "# TYPE {} {}", name, family.metric_type.as_str());
+            let _ = writeln!(output, "# HELP {} {}", name, family.help);
+            if name.ends_with("_seconds") {
+                let _ = writeln!(output, "# UNIT {name} seconds");
+            }
+            let suffix = match family.metric_type {
+                MetricType::Counter => "_total",
+                MetricType::Gauge | MetricType::Histogram => "",
+            };
+            for (labels, sample) in &family.samples {
+                match sample {
+                    Sample::Value(value) => {
+                        write_sample(&mut output, &format!("{name}{suffix}"), labels, *value)
+                    }
+                    Sample::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let bucket_name = format!("{name}_bucket");
+                        let bounds = buckets
+                            .iter()
+                            .map(|(bound, bucket_count)| (bound.to_string(), *bucket_count))
+                            .chain([("+Inf".to_string(), *count)]);
+                        for (bound, bucket_count) in bounds {
+                            let mut labels = labels.clone();
+                            labels.insert("le", bound);
+                            write_sample(&mut output, &bucket_name, &labels, bucket_count as f64);
+                        }
+                        write_sample(&mut output, &format!("{name}_count"), labels, *count as f64);
+                        write_sample(&mut output, &format!("{name}_sum"), labels, *sum);
+                    }
+                }
+            }
+        }
+        output.push_str("# EOF\n");
+        output
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.render())
+            .map_err(|e| anyhow!("cannot write metrics to {}: {}", path.display(), e))
+    }
+}
+
+fn write_sample(output: &mut String, name: &str, labels: &Labels, value: f64) {
+    let _ = write!(output, "{name}");
+    if !labels.is_empty() {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let _ = write!(output, "{{{labels}}}");
+    }
+    let _ = writeln!(output, " {value}");
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openmetrics_rendering() {
+        let mut metrics = Metrics::default();
+        metrics.counter(
+            "kwctl_bench_policies",
+            "Policies benchmarked",
+            &[("result", "passed")],
+            2.0,
+        );
+        metrics.gauge(
+            "kwctl_bench_duration_seconds",
+            "Duration of the whole run",
+            &[],
+            12.5,
+        );
+        metrics.counter(
+            "kwctl_bench_policies",
+            "Policies benchmarked",
+            &[("result", "failed")],
+            0.0,
+        );
+
+        assert_eq!(
+            metrics.render(),
+            r#"# TYPE kwctl_bench_policies counter
+# HELP kwctl_bench_policies Policies benchmarked
+kwctl_bench_policies_total{result="passed"} 2
+kwctl_bench_policies_total{result="failed"} 0
+# TYPE kwctl_bench_duration_seconds gauge
+# HELP kwctl_bench_duration_seconds Duration of the whole run
+# UNIT kwctl_bench_duration_seconds seconds
+kwctl_bench_duration_seconds 12.5
+# EOF
+"#
+        );
+    }
+
+    #[test]
+    fn histograms() {
+        let mut metrics = Metrics::default();
+        metrics.histogram(
+            "kwctl_latency_milliseconds",
+            "Latency",
+            &[("policy_name", "psp")],
+            &[(5.0, 1), (10.0, 3)],
+            21.5,
+            4,
+        );
+
+        assert_eq!(
+            metrics.render(),
+            r#"# TYPE kwctl_latency_milliseconds histogram
+# HELP kwctl_latency_milliseconds Latency
+kwctl_latency_milliseconds_bucket{le="5",policy_name="psp"} 1
+kwctl_latency_milliseconds_bucket{le="10",policy_name="psp"} 3
+kwctl_latency_milliseconds_bucket{le="+Inf",policy_name="psp"} 4
+kwctl_latency_milliseconds_count{policy_name="psp"} 4
+kwctl_latency_milliseconds_sum{policy_name="psp"} 21.5
+# EOF
+"#
+        );
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(
+            escape_label_value("file:///tmp/\"odd\"\\policy.wasm\n"),
+            r#"file:///tmp/\"odd\"\\policy.wasm\n"#
+        );
+    }
+}
@@ -0,0 +1,2 @@
+mod gatekeeper;
+pub(crate) use gatekeeper::{gatekeeper, GatekeeperOptions};
@@ -0,0 +1,714 @@
+//! Migration of the Gatekeeper policies, made of a ConstraintTemplate and of
+//! one of its Constraints, to Kubewarden.
+//!
+//! The Rego of the ConstraintTemplate is written untouched next to a
+//! `metadata.yml` file running it in the `gatekeeper` execution mode, which
+//! gives the settings of the policy to the Rego as `input.parameters`, like
+//! Gatekeeper does with the parameters of the Constraints. The match section
+//! of the Constraint becomes the rules and the selectors of a
+//! ClusterAdmissionPolicy.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{
+    LabelSelector, LabelSelectorRequirement, ObjectMeta,
+};
+use policy_evaluator::{
+    kube::{api::ApiResource, core::GroupVersionKind},
+    policy_fetcher::sources::Sources,
+    policy_metadata::Rule,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tar::Archive;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::{
+    annotate,
+    annotations::SETTINGS_SCHEMA_ANNOTATION,
+    command::run::explain::opa,
+    push,
+    scaffold::kubewarden_crds::{ClusterAdmissionPolicy, ClusterAdmissionPolicySpec},
+};
+
+/// The target of the ConstraintTemplates validating the admission requests
+const GATEKEEPER_TARGET: &str = "admission.k8s.gatekeeper.sh";
+
+/// The label Kubernetes sets on every namespace, holding its name
+const NAMESPACE_NAME_LABEL: &str = "kubernetes.io/metadata.name";
+
+/// Gatekeeper validates the creation and the update of the resources
+const OPERATIONS: &[&str] = &["CREATE", "UPDATE"];
+
+pub(crate) struct GatekeeperOptions {
+    pub(crate) constraint_template: PathBuf,
+    pub(crate) constraint: PathBuf,
+    /// Directory where the Rego files and the metadata are written, named
+    /// after the ConstraintTemplate by default
+    pub(crate) output_dir: Option<PathBuf>,
+    pub(crate) module: Option<String>,
+    /// Build the Rego with opa and annotate the resulting module
+    pub(crate) annotate: bool,
+    /// Registry URI the annotated module is pushed to
+    pub(crate) push: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ConstraintTemplate {
+    metadata: ObjectMeta,
+    spec: ConstraintTemplateSpec,
+}
+
+#[derive(Deserialize)]
+struct ConstraintTemplateSpec {
+    crd: Crd,
+    #[serde(default)]
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Crd {
+    spec: CrdSpec,
+}
+
+#[derive(Deserialize)]
+struct CrdSpec {
+    names: Names,
+    validation: Option<Validation>,
+}
+
+#[derive(Deserialize)]
+struct Names {
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct Validation {
+    #[serde(rename = "openAPIV3Schema")]
+    open_api_v3_schema: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    target: String,
+    rego: Option<String>,
+    #[serde(default)]
+    libs: Vec<String>,
+    /// The sources of the engines, used by the newer ConstraintTemplates
+    /// instead of `rego` and `libs`
+    #[serde(default)]
+    code: Vec<Code>,
+}
+
+#[derive(Deserialize)]
+struct Code {
+    engine: String,
+    source: Value,
+}
+
+/// The source of the `Rego` engine
+#[derive(Deserialize)]
+struct RegoSource {
+    rego: String,
+    #[serde(default)]
+    libs: Vec<String>,
+    version: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Constraint {
+    kind: String,
+    metadata: ObjectMeta,
+    #[serde(default)]
+    spec: ConstraintSpec,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConstraintSpec {
+    enforcement_action: Option<String>,
+    #[serde(default, rename = "match")]
+    match_: Match,
+    #[serde(default)]
+    parameters: serde_yaml::Mapping,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Match {
+    #[serde(default)]
+    kinds: Vec<Kinds>,
+    #[serde(default)]
+    namespaces: Vec<String>,
+    #[serde(default)]
+    excluded_namespaces: Vec<String>,
+    label_selector: Option<LabelSelector>,
+    namespace_selector: Option<LabelSelector>,
+    scope: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Kinds {
+    #[serde(default)]
+    api_groups: Vec<String>,
+    #[serde(default)]
+    kinds: Vec<String>,
+}
+
+/// The Rego of a ConstraintTemplate
+struct RegoPolicy {
+    rego: String,
+    libs: Vec<String>,
+    /// Whether the Rego uses the v1 syntax, Gatekeeper defaults to the v0 one
+    v1: bool,
+}
+
+impl RegoPolicy {
+    fn from_template(template: &ConstraintTemplate) -> Result<Self> {
+        let target = template
+            .spec
+            .targets
+            .iter()
+            .find(|target| target.target == GATEKEEPER_TARGET)
+            .ok_or_else(|| anyhow!("the ConstraintTemplate has no {} target", GATEKEEPER_TARGET))?;
+        if let Some(rego) = &target.rego {
+            return Ok(RegoPolicy {
+                rego: rego.clone(),
+                libs: target.libs.clone(),
+                v1: false,
+            });
+        }
+
+        let code = target
+            .code
+            .iter()
+            .find(|code| code.engine == "Rego")
+            .ok_or_else(|| {
+                anyhow!(
+                    "the ConstraintTemplate has no Rego code, the other engines are not supported"
+                )
+            })?;
+        let source: RegoSource = serde_json::from_value(code.source.clone())
+            .map_err(|e| anyhow!("invalid Rego source: {}", e))?;
+        Ok(RegoPolicy {
+            rego: source.rego,
+            libs: source.libs,
+            v1: source.version.as_deref() == Some("v1"),
+        })
+    }
+
+    /// The rule evaluated by Kubewarden: the `violation` set of the package
+    fn entrypoint(&self) -> Result<String> {
+        let package = self
+            .rego
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("package "))
+            .map(str::trim)
+            .ok_or_else(|| anyhow!("the Rego of the ConstraintTemplate has no package"))?;
+        Ok(format!("{}/violation", package.replace('.', "/")))
+    }
+
+    /// The Rego files, named relative to the output directory
+    fn files(&self) -> Vec<(String, &str)> {
+        std::iter::once(("policy.rego".to_string(), self.rego.as_str()))
+            .chain(
+                self.libs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, lib)| (format!("lib-{index}.rego"), lib.as_str())),
+            )
+            .collect()
+    }
+}
+
+fn read<T: DeserializeOwned>(path: &Path, kind: &str) -> Result<T> {
+    let file = File::open(path).map_err(|e| anyhow!("cannot open {}: {}", path.display(), e))?;
+    serde_yaml::from_reader(file)
+        .map_err(|e| anyhow!("cannot convert {} into a {}: {}", path.display(), kind, e))
+}
+
+/// Writes the Rego files and the metadata of the policy, then prints the
+/// ClusterAdmissionPolicy of the Constraint. With `annotate` or `push` the
+/// policy is also built.
+pub(crate) async fn gatekeeper(
+    options: &GatekeeperOptions,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let template: ConstraintTemplate = read(&options.constraint_template, "ConstraintTemplate")?;
+    let constraint: Constraint = read(&options.constraint, "Constraint")?;
+    let policy = RegoPolicy::from_template(&template)?;
+    if policy
+        .files()
+        .iter()
+        .any(|(_, rego)| rego.contains("data.inventory"))
+    {
+        warn!("The Rego reads the objects replicated by Gatekeeper (data.inventory), which Kubewarden does not provide. The policy must be adapted to the context-aware capabilities of Kubewarden.");
+    }
+
+    let output_dir = options
+        .output_dir
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(template.metadata.name.as_deref().unwrap_or(".")));
+    fs::create_dir_all(&output_dir)
+        .map_err(|e| anyhow!("cannot create {}: {}", output_dir.display(), e))?;
+    for (name, rego) in policy.files() {
+        fs::write(output_dir.join(&name), rego)
+            .map_err(|e| anyhow!("cannot write {}: {}", name, e))?;
+    }
+    let metadata = metadata(&template, &rules(&constraint.spec.match_.kinds)?)?;
+    fs::write(
+        output_dir.join("metadata.yml"),
+        serde_yaml::to_string(&metadata)?,
+    )
+    .map_err(|e| anyhow!("cannot write metadata.yml: {}", e))?;
+    info!(
+        output_dir = output_dir.display().to_string().as_str(),
+        "policy written"
+    );
+
+    let annotated_path = (options.annotate || options.push.is_some())
+        .then(|| build(&policy, &output_dir))
+        .transpose()?;
+    let module = match (&options.push, &options.module, annotated_path) {
+        (Some(uri), _, Some(annotated_path)) => {
+            let upload = push::PolicyUpload::new(annotated_path, false, &HashMap::new(), None)?;
+            let immutable_ref = push::push(&upload, uri, sources).await?;
+            info!(immutable_ref = immutable_ref.as_str(), "policy pushed");
+            uri.clone()
+        }
+        (_, Some(module), _) => module.clone(),
+        (_, None, Some(annotated_path)) => Url::from_file_path(annotated_path.canonicalize()?)
+            .map_err(|_| anyhow!("cannot build the URI of {}", annotated_path.display()))?
+            .to_string(),
+        (_, None, None) => {
+            return Err(anyhow!(
+                "the module of the policy is unknown, use --module, --annotate or --push"
+            ))
+        }
+    };
+
+    let cluster_admission_policy =
+        convert_constraint_to_cluster_admission_policy(&module, &template, constraint)?;
+    serde_yaml::to_writer(std::io::stdout(), &cluster_admission_policy)?;
+
+    Ok(())
+}
+
+fn metadata(template: &ConstraintTemplate, rules: &[Rule]) -> Result<Value> {
+    let mut annotations = serde_json::Map::new();
+    annotations.insert(
+        "io.kubewarden.policy.title".to_string(),
+        template.metadata.name.clone().unwrap_or_default().into(),
+    );
+    if let Some(description) = template
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get("description"))
+    {
+        annotations.insert(
+            "io.kubewarden.policy.description".to_string(),
+            description.clone().into(),
+        );
+    }
+    // the schema of the parameters validates the settings
+    if let Some(schema) = template
+        .spec
+        .crd
+        .spec
+        .validation
+        .as_ref()
+        .and_then(|validation| validation.open_api_v3_schema.as_ref())
+    {
+        annotations.insert(
+            SETTINGS_SCHEMA_ANNOTATION.to_string(),
+            serde_json::to_string(schema)?.into(),
+        );
+    }
+
+    Ok(json!({
+        "rules": rules,
+        "mutating": false,
+        "executionMode": "gatekeeper",
+        "backgroundAudit": true,
+        "annotations": annotations,
+    }))
+}
+
+/// Builds the Rego files with `opa build`, then annotates the module with the
+/// metadata. Returns the path of the annotated module.
+fn build(policy: &RegoPolicy, output_dir: &Path) -> Result<PathBuf> {
+    let opa = opa()?;
+    let entrypoint = policy.entrypoint()?;
+    let bundle = tempfile::Builder::new().suffix(".tar.gz").tempfile()?;
+
+    let mut command = Command::new(&opa);
+    command
+        .args(["build", "-t", "wasm", "-e", entrypoint.as_str(), "-o"])
+        .arg(bundle.path());
+    if !policy.v1 {
+        command.arg("--v0-compatible");
+    }
+    for (name, _) in policy.files() {
+        command.arg(output_dir.join(name));
+    }
+    debug!(opa = %opa.display(), entrypoint = entrypoint.as_str(), "building policy");
+    let result = command
+        .output()
+        .map_err(|e| anyhow!("cannot run {}: {}", opa.display(), e))?;
+    if !result.status.success() {
+        return Err(anyhow!(
+            "opa build failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+
+    let wasm_path = output_dir.join("policy.wasm");
+    extract_wasm(bundle.path(), &wasm_path)?;
+    let annotated_path = output_dir.join("annotated-policy.wasm");
+    annotate::write_annotation(
+        wasm_path,
+        Some(output_dir.join("metadata.yml")),
+        annotate::Overrides::default(),
+        Some(annotated_path.clone()),
+        None,
+        true,
+        None,
+    )?;
+    Ok(annotated_path)
+}
+
+/// Extracts the module out of the bundle built by opa
+fn extract_wasm(bundle: &Path, destination: &Path) -> Result<()> {
+    let file =
+        File::open(bundle).map_err(|e| anyhow!("cannot open {}: {}", bundle.display(), e))?;
+    let mut archive = Archive::new(GzDecoder::new(file));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        if path.strip_prefix("/").unwrap_or(&path) == Path::new("policy.wasm") {
+            entry.unpack(destination)?;
+            return Ok(());
+        }
+    }
+    Err(anyhow!("the bundle built by opa contains no policy.wasm"))
+}
+
+/// The resource of the kind, the plural is guessed from the kind
+fn plural(kind: &str) -> String {
+    if kind == "*" {
+        return kind.to_string();
+    }
+    ApiResource::from_gvk(&GroupVersionKind::gvk("", "v1", kind)).plural
+}
+
+/// Gatekeeper matches the kinds of all the versions of the groups
+fn rules(kinds: &[Kinds]) -> Result<Vec<Rule>> {
+    if kinds.is_empty() {
+        return Ok(vec![serde_json::from_value(json!({
+            "apiGroups": ["*"],
+            "apiVersions": ["*"],
+            "resources": ["*"],
+            "operations": OPERATIONS,
+        }))?]);
+    }
+    kinds
+        .iter()
+        .map(|kinds| {
+            let api_groups = if kinds.api_groups.is_empty() {
+                vec!["*".to_string()]
+            } else {
+                kinds.api_groups.clone()
+            };
+            let resources: Vec<String> = if kinds.kinds.is_empty() {
+                vec!["*".to_string()]
+            } else {
+                kinds.kinds.iter().map(|kind| plural(kind)).collect()
+            };
+            serde_json::from_value(json!({
+                "apiGroups": api_groups,
+                "apiVersions": ["*"],
+                "resources": resources,
+                "operations": OPERATIONS,
+            }))
+            .map_err(|e| anyhow!("error converting the kinds of the Constraint into rules: {e}"))
+        })
+        .collect()
+}
+
+/// The namespaces of the Constraint are selected by their name label,
+/// together with its namespace selector
+fn namespace_selector(constraint_match: &Match) -> Option<LabelSelector> {
+    let mut selector = constraint_match
+        .namespace_selector
+        .clone()
+        .unwrap_or_default();
+    let mut match_expressions = selector.match_expressions.take().unwrap_or_default();
+    for (operator, namespaces) in [
+        ("In", &constraint_match.namespaces),
+        ("NotIn", &constraint_match.excluded_namespaces),
+    ] {
+        let (prefixes, names): (Vec<&str>, Vec<&str>) = namespaces
+            .iter()
+            .map(String::as_str)
+            .partition(|namespace| namespace.ends_with('*'));
+        if !prefixes.is_empty() {
+            warn!(
+                "Kubewarden cannot select the namespaces by prefix, {} will be ignored.",
+                prefixes.join(", ")
+            );
+        }
+        if !names.is_empty() {
+            match_expressions.push(LabelSelectorRequirement {
+                key: NAMESPACE_NAME_LABEL.to_string(),
+                operator: operator.to_string(),
+                values: Some(names.iter().map(|name| name.to_string()).collect()),
+            });
+        }
+    }
+
+    selector.match_expressions = (!match_expressions.is_empty()).then_some(match_expressions);
+    (selector.match_labels.is_some() || selector.match_expressions.is_some()).then_some(selector)
+}
+
+/// The Constraints denying the requests map to the protect mode, which is the
+/// default one. The dryrun and warn actions only report the violations, like
+/// the monitor mode does.
+fn mode(enforcement_action: Option<&str>) -> Result<Option<String>> {
+    match enforcement_action.unwrap_or("deny") {
+        "deny" => Ok(None),
+        action @ ("dryrun" | "warn") => {
+            warn!("The Constraint does not deny the requests ({action}), the policy will be deployed in monitor mode.");
+            Ok(Some("monitor".to_string()))
+        }
+        action => Err(anyhow!("unsupported enforcementAction {action}")),
+    }
+}
+
+fn convert_constraint_to_cluster_admission_policy(
+    module: &str,
+    template: &ConstraintTemplate,
+    constraint: Constraint,
+) -> Result<ClusterAdmissionPolicy> {
+    let template_kind = &template.spec.crd.spec.names.kind;
+    if constraint.kind != *template_kind {
+        return Err(anyhow!(
+            "the Constraint is a {}, while the ConstraintTemplate defines {}",
+            constraint.kind,
+            template_kind
+        ));
+    }
+
+    let constraint_match = &constraint.spec.match_;
+    if constraint_match.kinds.is_empty() {
+        warn!("The Constraint matches all the kinds, the policy will evaluate all the resources.");
+    }
+    if constraint_match
+        .scope
+        .as_deref()
+        .is_some_and(|scope| scope != "*")
+    {
+        warn!("The scope of the Constraint is not supported by Kubewarden. It will be ignored.");
+    }
+    if constraint_match.name.is_some() {
+        warn!("The name matched by the Constraint is not supported by Kubewarden. It will be ignored.");
+    }
+
+    Ok(ClusterAdmissionPolicy {
+        api_version: "policies.kubewarden.io/v1".to_string(),
+        kind: "ClusterAdmissionPolicy".to_string(),
+        metadata: ObjectMeta {
+            name: constraint.metadata.name.clone(),
+            ..Default::default()
+        },
+        spec: ClusterAdmissionPolicySpec {
+            module: module.to_string(),
+            settings: constraint.spec.parameters.clone(),
+            rules: rules(&constraint_match.kinds)?,
+            mutating: false,
+            background_audit: true,
+            mode: mode(constraint.spec.enforcement_action.as_deref())?,
+            namespace_selector: namespace_selector(constraint_match),
+            object_selector: constraint_match.label_selector.clone(),
+            ..Default::default()
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    const MODULE: &str = "registry://ghcr.io/kubewarden/tests/k8srequiredlabels:v0.1.0";
+
+    fn test_data(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("data")
+            .join(path)
+    }
+
+    fn load<T: DeserializeOwned>(path: &str) -> T {
+        read(&test_data(path), "test data").unwrap()
+    }
+
+    #[test]
+    fn from_constraint_to_cluster_admission_policy() {
+        let template: ConstraintTemplate = load("gatekeeper/constraint-template.yml");
+        let constraint: Constraint = load("gatekeeper/constraint.yml");
+
+        let cluster_admission_policy =
+            convert_constraint_to_cluster_admission_policy(MODULE, &template, constraint).unwrap();
+
+        assert_eq!(
+            cluster_admission_policy.metadata.name.as_deref(),
+            Some("ns-must-have-owner")
+        );
+        assert_eq!(cluster_admission_policy.spec.module, MODULE);
+        assert_eq!(
+            cluster_admission_policy.spec.settings["labels"],
+            serde_yaml::to_value(["owner"]).unwrap()
+        );
+        assert_eq!(
+            cluster_admission_policy.spec.mode.as_deref(),
+            Some("monitor")
+        );
+
+        let rules = serde_json::to_value(&cluster_admission_policy.spec.rules).unwrap();
+        assert_eq!(rules[0]["apiGroups"], json!([""]));
+        assert_eq!(rules[0]["resources"], json!(["namespaces"]));
+        assert_eq!(rules[1]["apiGroups"], json!(["apps"]));
+        assert_eq!(
+            rules[1]["resources"],
+            json!(["deployments", "statefulsets"])
+        );
+        assert_eq!(rules[1]["apiVersions"], json!(["*"]));
+
+        // the namespaces excluded by prefix are ignored
+        let namespace_selector = cluster_admission_policy.spec.namespace_selector.unwrap();
+        assert_eq!(
+            namespace_selector.match_expressions.unwrap(),
+            vec![LabelSelectorRequirement {
+                key: NAMESPACE_NAME_LABEL.to_string(),
+                operator: "NotIn".to_string(),
+                values: Some(vec!["kube-system".to_string()]),
+            }]
+        );
+        assert_eq!(
+            cluster_admission_policy
+                .spec
+                .object_selector
+                .unwrap()
+                .match_labels
+                .unwrap()["app.kubernetes.io/managed-by"],
+            "helm"
+        );
+    }
+
+    #[test]
+    fn constraint_of_another_template() {
+        let template: ConstraintTemplate = load("gatekeeper/constraint-template-v1.yml");
+        let constraint: Constraint = load("gatekeeper/constraint.yml");
+
+        let error = convert_constraint_to_cluster_admission_policy(MODULE, &template, constraint)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("K8sDisallowedTags"), "{error}");
+    }
+
+    #[rstest]
+    #[case::rego(
+        "gatekeeper/constraint-template.yml",
+        "k8srequiredlabels/violation",
+        false,
+        2
+    )]
+    #[case::code(
+        "gatekeeper/constraint-template-v1.yml",
+        "k8s/disallowedtags/violation",
+        true,
+        1
+    )]
+    fn rego_of_the_template(
+        #[case] path: &str,
+        #[case] entrypoint: &str,
+        #[case] v1: bool,
+        #[case] files: usize,
+    ) {
+        let template: ConstraintTemplate = load(path);
+        let policy = RegoPolicy::from_template(&template).unwrap();
+
+        assert_eq!(policy.entrypoint().unwrap(), entrypoint);
+        assert_eq!(policy.v1, v1);
+        assert_eq!(policy.files().len(), files);
+    }
+
+    #[test]
+    fn metadata_of_the_template() {
+        let template: ConstraintTemplate = load("gatekeeper/constraint-template.yml");
+
+        let metadata = metadata(&template, &rules(&[]).unwrap()).unwrap();
+        assert_eq!(metadata["executionMode"], "gatekeeper");
+        assert_eq!(metadata["rules"][0]["resources"], json!(["*"]));
+        assert_eq!(
+            metadata["annotations"]["io.kubewarden.policy.title"],
+            "k8srequiredlabels"
+        );
+        let schema: Value = serde_json::from_str(
+            metadata["annotations"][SETTINGS_SCHEMA_ANNOTATION]
+                .as_str()
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(schema["properties"]["labels"]["type"], "array");
+    }
+
+    #[rstest]
+    #[case(None, None)]
+    #[case(Some("deny"), None)]
+    #[case(Some("dryrun"), Some("monitor"))]
+    #[case(Some("warn"), Some("monitor"))]
+    fn enforcement_actions(#[case] action: Option<&str>, #[case] expected: Option<&str>) {
+        assert_eq!(mode(action).unwrap().as_deref(), expected);
+    }
+
+    #[test]
+    fn unsupported_enforcement_action() {
+        assert!(mode(Some("scoped")).is_err());
+    }
+
+    #[test]
+    fn wasm_of_the_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = dir.path().join("bundle.tar.gz");
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            File::create(&bundle).unwrap(),
+            flate2::Compression::default(),
+        ));
+        // opa writes absolute paths, which tar::Builder refuses to set
+        for (path, data) in [("/data.json", &b"{}"[..]), ("/policy.wasm", &b"\0asm"[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let destination = dir.path().join("policy.wasm");
+        extract_wasm(&bundle, &destination).unwrap();
+        assert_eq!(fs::read(destination).unwrap(), b"\0asm");
+    }
+}
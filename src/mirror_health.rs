@@ -0,0 +1,409 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    future::Future,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use policy_evaluator::policy_fetcher::{registry::Registry, sources::Sources};
+use prettytable::{format, row, Table};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+
+use crate::config::sources::RegistryMirrors;
+
+const MIRRORS_HEALTH_FILE: &str = "mirrors-health.json";
+
+/// How long the outcome of a probe is trusted before probing again
+const HEALTH_TTL_SECONDS: i64 = 300;
+
+/// Probes taking longer than this mark the endpoint as unavailable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of the last probe of a registry endpoint
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct Health {
+    /// Latency of the probe, missing when the endpoint was unavailable
+    latency_ms: Option<u64>,
+    /// Unix timestamp of the probe
+    checked_at: i64,
+}
+
+/// Health of the registry endpoints, keyed by endpoint: the registry host,
+/// followed by the path prefix of mirrors
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct HealthState(BTreeMap<String, Health>);
+
+fn health_state_path() -> PathBuf {
+    crate::store_profile::cache_dir().join(MIRRORS_HEALTH_FILE)
+}
+
+impl HealthState {
+    /// The state is only an optimization, a missing or broken file is
+    /// treated as an empty state
+    fn load() -> Self {
+        fs::read(health_state_path())
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let path = health_state_path();
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_vec(self)?));
+        if let Err(e) = result {
+            warn!(path = %path.display(), error = %e, "cannot save the health of the mirrors");
+        }
+    }
+
+    fn fresh(&self, endpoint: &str, now: i64) -> Option<&Health> {
+        self.0
+            .get(endpoint)
+            .filter(|health| now - health.checked_at < HEALTH_TTL_SECONDS)
+    }
+}
+
+/// Registry endpoint serving `candidate`, the mirror of `uri`
+fn endpoint(candidate: &str, uri: &str) -> String {
+    let repository = uri
+        .strip_prefix("registry://")
+        .and_then(|reference| reference.split_once('/'))
+        .map(|(_, repository)| repository)
+        .unwrap_or_default();
+    candidate
+        .trim_start_matches("registry://")
+        .strip_suffix(repository)
+        .unwrap_or(candidate)
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Outcome of the probe of a candidate URI
+pub(crate) struct Probe {
+    pub(crate) candidate: String,
+    pub(crate) endpoint: String,
+    /// Latency of the endpoint, or the reason it's unavailable
+    pub(crate) outcome: Result<Duration, String>,
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.outcome {
+            Ok(latency) => write!(f, "available ({} ms)", latency.as_millis()),
+            Err(e) => write!(f, "unavailable: {e}"),
+        }
+    }
+}
+
+/// Fetches the manifest digest of every candidate, concurrently. The
+/// outcomes are recorded into the health state, which is then saved.
+async fn probe_candidates(
+    uri: &str,
+    candidates: &[String],
+    sources: Option<&Sources>,
+    state: &mut HealthState,
+) -> Vec<Probe> {
+    let registry = Registry::new();
+    let probes = join_all(candidates.iter().map(|candidate| {
+        let registry = &registry;
+        async move {
+            let start = Instant::now();
+            let outcome = match tokio::time::timeout(
+                PROBE_TIMEOUT,
+                registry.manifest_digest(candidate, sources),
+            )
+            .await
+            {
+                Ok(Ok(_)) => Ok(start.elapsed()),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!(
+                    "timed out after {} seconds",
+                    PROBE_TIMEOUT.as_secs()
+                )),
+            };
+            debug!(candidate = candidate.as_str(), ?outcome, "mirror probed");
+            Probe {
+                candidate: candidate.to_owned(),
+                endpoint: endpoint(candidate, uri),
+                outcome,
+            }
+        }
+    }))
+    .await;
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    for probe in &probes {
+        state.0.insert(
+            probe.endpoint.clone(),
+            Health {
+                latency_ms: probe
+                    .outcome
+                    .as_ref()
+                    .ok()
+                    .map(|latency| latency.as_millis() as u64),
+                checked_at: now,
+            },
+        );
+    }
+    state.save();
+
+    probes
+}
+
+/// Probes all the sources of `uri`, ignoring the cached health state.
+/// Returns the probes in the order the sources would be tried by `pull`.
+pub(crate) async fn probe(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Vec<Probe> {
+    let candidates = mirrors.candidates(uri);
+    let mut state = HealthState::load();
+    let mut probes = probe_candidates(uri, &candidates, sources, &mut state).await;
+    let order = order(
+        uri,
+        candidates,
+        &state,
+        OffsetDateTime::now_utc().unix_timestamp(),
+    );
+    probes.sort_by_key(|probe| {
+        order
+            .iter()
+            .position(|candidate| *candidate == probe.candidate)
+    });
+    probes
+}
+
+/// Prints the outcome of the probes as a table, followed by the source that
+/// would be used by `pull`
+pub(crate) fn print_probes(probes: &[Probe]) {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Endpoint", "Status"]);
+    for probe in probes {
+        table.add_row(row![probe.endpoint, probe]);
+    }
+    table.printstd();
+
+    if let Some(probe) = probes.iter().find(|probe| probe.outcome.is_ok()) {
+        println!("Policy would be pulled from {}", probe.candidate);
+    }
+}
+
+/// Sorts the mirrors by availability and latency: the available ones first,
+/// the fastest first, then the ones without a recent probe, in the configured
+/// order, then the unavailable ones. The upstream URI is always the last
+/// candidate.
+fn order(uri: &str, candidates: Vec<String>, state: &HealthState, now: i64) -> Vec<String> {
+    let (mut mirrors, upstream): (Vec<String>, Vec<String>) = candidates
+        .into_iter()
+        .partition(|candidate| candidate != uri);
+    // stable sort, the configured order is kept for ties
+    mirrors.sort_by_key(
+        |candidate| match state.fresh(&endpoint(candidate, uri), now) {
+            Some(Health {
+                latency_ms: Some(latency),
+                ..
+            }) => (0, *latency),
+            None => (1, 0),
+            Some(_) => (2, 0),
+        },
+    );
+    mirrors.extend(upstream);
+    mirrors
+}
+
+/// Returns the URIs that can be used to fetch the given policy, like
+/// [`RegistryMirrors::candidates`], with the mirrors sorted by their health.
+///
+/// When there are multiple mirrors, the ones without a recent probe are
+/// probed first.
+pub(crate) async fn ordered_candidates(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Vec<String> {
+    let candidates = mirrors.candidates(uri);
+    // with a single mirror there's nothing to sort: the mirror is tried
+    // first anyway, and the upstream registry last
+    if candidates.len() <= 2 {
+        return candidates;
+    }
+
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let mut state = HealthState::load();
+    let stale: Vec<String> = candidates
+        .iter()
+        .filter(|candidate| {
+            *candidate != uri && state.fresh(&endpoint(candidate, uri), now).is_none()
+        })
+        .cloned()
+        .collect();
+    if !stale.is_empty() {
+        probe_candidates(uri, &stale, sources, &mut state).await;
+    }
+
+    order(uri, candidates, &state, now)
+}
+
+/// Runs `attempt` against the candidates returned by
+/// [`RegistryMirrors::candidates`] or [`ordered_candidates`], until one of
+/// them succeeds. The mirrors failing are skipped, the error of the upstream
+/// registry, which is the last candidate, is returned together with the ones
+/// of the mirrors.
+///
+/// `action` describes the attempt inside of the warnings, e.g. "pull policy".
+pub(crate) async fn first_successful<T, F, Fut>(
+    candidates: &[String],
+    action: &str,
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let Some((upstream, mirrors)) = candidates.split_last() else {
+        return Err(anyhow!("cannot {}: no source available", action));
+    };
+    let mut errors: Vec<String> = Vec::new();
+
+    for mirror in mirrors {
+        match attempt(mirror.clone()).await {
+            Ok(outcome) => return Ok(outcome),
+            Err(e) => {
+                warn!(mirror = mirror.as_str(), error = %e, "cannot {} using mirror, trying next source", action);
+                errors.push(format!("  - {mirror}: {e}"));
+            }
+        }
+    }
+
+    attempt(upstream.clone()).await.map_err(|e| {
+        if errors.is_empty() {
+            e
+        } else {
+            anyhow!("{}\nMirrors failures:\n{}", e, errors.join("\n"))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const UPSTREAM: &str = "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5";
+    const NOW: i64 = 1_700_000_000;
+
+    fn candidates() -> Vec<String> {
+        vec![
+            "registry://registry.example.com/ghcr/kubewarden/policies/pod-privileged:v0.2.5"
+                .to_string(),
+            "registry://backup.example.com/kubewarden/policies/pod-privileged:v0.2.5".to_string(),
+            "registry://eu.example.com/kubewarden/policies/pod-privileged:v0.2.5".to_string(),
+            UPSTREAM.to_string(),
+        ]
+    }
+
+    fn health(latency_ms: Option<u64>, age: i64) -> Health {
+        Health {
+            latency_ms,
+            checked_at: NOW - age,
+        }
+    }
+
+    #[rstest]
+    #[case::mirror_with_prefix(
+        "registry://registry.example.com/ghcr/kubewarden/policies/pod-privileged:v0.2.5",
+        "registry.example.com/ghcr"
+    )]
+    #[case::mirror(
+        "registry://backup.example.com/kubewarden/policies/pod-privileged:v0.2.5",
+        "backup.example.com"
+    )]
+    #[case::upstream(UPSTREAM, "ghcr.io")]
+    fn candidate_endpoint(#[case] candidate: &str, #[case] expected: &str) {
+        assert_eq!(endpoint(candidate, UPSTREAM), expected);
+    }
+
+    #[test]
+    fn mirrors_are_sorted_by_health() {
+        let state = HealthState(BTreeMap::from([
+            ("registry.example.com/ghcr".to_string(), health(None, 10)),
+            ("backup.example.com".to_string(), health(Some(250), 10)),
+            ("eu.example.com".to_string(), health(Some(40), 10)),
+        ]));
+
+        assert_eq!(
+            order(UPSTREAM, candidates(), &state, NOW),
+            vec![
+                "registry://eu.example.com/kubewarden/policies/pod-privileged:v0.2.5",
+                "registry://backup.example.com/kubewarden/policies/pod-privileged:v0.2.5",
+                "registry://registry.example.com/ghcr/kubewarden/policies/pod-privileged:v0.2.5",
+                UPSTREAM,
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_health_keeps_configured_order() {
+        let state = HealthState(BTreeMap::from([
+            (
+                "registry.example.com/ghcr".to_string(),
+                health(None, HEALTH_TTL_SECONDS + 1),
+            ),
+            (
+                "eu.example.com".to_string(),
+                health(Some(40), HEALTH_TTL_SECONDS + 1),
+            ),
+            // the upstream registry is never moved before the mirrors
+            ("ghcr.io".to_string(), health(Some(1), 10)),
+        ]));
+
+        assert_eq!(order(UPSTREAM, candidates(), &state, NOW), candidates());
+    }
+
+    #[rstest]
+    #[case::mirror("registry://registry.example.com", Ok(1))]
+    #[case::upstream("registry://ghcr.io", Ok(4))]
+    #[case::nothing("registry://nowhere", Err(4))]
+    #[tokio::test]
+    async fn first_successful_candidate(
+        #[case] available: &str,
+        #[case] expected: std::result::Result<usize, usize>,
+    ) {
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let attempts = &attempts;
+
+        let outcome = first_successful(&candidates(), "pull policy", |candidate| async move {
+            attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if candidate.starts_with(available) {
+                Ok(candidate)
+            } else {
+                Err(anyhow!("{} is not available", candidate))
+            }
+        })
+        .await;
+
+        let attempts = attempts.load(std::sync::atomic::Ordering::SeqCst);
+        match expected {
+            Ok(expected) => {
+                assert!(outcome.unwrap().starts_with(available));
+                assert_eq!(attempts, expected);
+            }
+            Err(expected) => {
+                // the error of the upstream registry comes first
+                let error = outcome.unwrap_err().to_string();
+                assert!(error.starts_with(&format!("{UPSTREAM} is not available")));
+                assert!(error.contains("Mirrors failures:"));
+                assert_eq!(attempts, expected);
+            }
+        }
+    }
+}
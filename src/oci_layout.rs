@@ -0,0 +1,444 @@
+//! Export of the policies of the store as an OCI image layout.
+//!
+//! The layout is the directory structure defined by the OCI image spec: an
+//! `oci-layout` marker, the `index.json` listing the manifests, and the
+//! content-addressed `blobs`. It can be consumed by stock tooling, like
+//! `oras cp --from-oci-layout` or `skopeo copy oci:`, to copy the policies
+//! into any registry.
+//!
+//! Each policy is exported with the same manifest `kwctl push` would push:
+//! the Wasm module as only layer, and the metadata of the policy as
+//! annotations. The manifest is listed inside of the index under the image
+//! reference of the policy, like `ghcr.io/kubewarden/policies/safe-labels:v1.0.0`.
+//!
+//! Layouts produced by kwctl or by other tools can be imported into the
+//! store. The digests of the manifests and of the Wasm modules are validated
+//! while importing them.
+
+use std::{collections::BTreeMap, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    policy_fetcher::{
+        oci_client::{
+            annotations::ORG_OPENCONTAINERS_IMAGE_REF_NAME,
+            manifest::{
+                OciDescriptor, OciImageManifest, OciManifest, OCI_IMAGE_MEDIA_TYPE,
+                WASM_CONFIG_MEDIA_TYPE, WASM_LAYER_MEDIA_TYPE,
+            },
+            Reference,
+        },
+        policy::Policy,
+        store::PolicyPath,
+    },
+    policy_metadata::Metadata,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+const INDEX_FILE: &str = "index.json";
+const OCI_INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+// The Wasm config pushed by policy-fetcher is empty
+const WASM_CONFIG_DATA: &[u8] = b"{}";
+
+/// Outcome of the export
+pub(crate) struct ExportReport {
+    /// Image references of the exported policies
+    pub(crate) exported: Vec<String>,
+    /// URIs of the policies not pulled from a registry, which have no image
+    /// reference to be listed under
+    pub(crate) skipped: Vec<String>,
+}
+
+/// Image reference the policy is listed under, `None` for the policies not
+/// pulled from a registry
+fn image_reference(uri: &str) -> Option<String> {
+    let image = uri.strip_prefix("registry://")?;
+    Reference::from_str(image)
+        .ok()
+        .map(|reference| reference.whole())
+}
+
+/// Writes `data` under the blobs of the layout, returning its descriptor
+fn write_blob(dir: &Path, media_type: &str, data: &[u8]) -> Result<OciDescriptor> {
+    let hex = format!("{:x}", Sha256::digest(data));
+    let path = dir.join("blobs").join("sha256").join(&hex);
+    // blobs are content-addressed, an existing one holds the same data
+    if !path.exists() {
+        fs::create_dir_all(dir.join("blobs").join("sha256"))?;
+        fs::write(&path, data).map_err(|e| anyhow!("cannot write {}: {}", path.display(), e))?;
+    }
+    Ok(OciDescriptor {
+        media_type: media_type.to_string(),
+        digest: format!("sha256:{hex}"),
+        size: data.len() as i64,
+        ..Default::default()
+    })
+}
+
+/// The manifests already listed inside of the index of the layout
+fn read_index(dir: &Path) -> Result<Vec<Value>> {
+    let path = dir.join(INDEX_FILE);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let index: Value = serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| anyhow!("invalid OCI index {}: {}", path.display(), e))?;
+    Ok(index
+        .get("manifests")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default())
+}
+
+fn ref_name(entry: &Value) -> Option<&str> {
+    entry
+        .get("annotations")?
+        .get(ORG_OPENCONTAINERS_IMAGE_REF_NAME)?
+        .as_str()
+}
+
+/// Index listing `exported`, replacing the entries of `existing` listed under
+/// the same references
+fn merge_index(existing: Vec<Value>, exported: Vec<Value>) -> Value {
+    let mut manifests: Vec<Value> = existing
+        .into_iter()
+        .filter(|entry| {
+            ref_name(entry).is_none_or(|name| {
+                exported
+                    .iter()
+                    .all(|new_entry| ref_name(new_entry) != Some(name))
+            })
+        })
+        .collect();
+    manifests.extend(exported);
+    json!({
+        "schemaVersion": 2,
+        "mediaType": OCI_INDEX_MEDIA_TYPE,
+        "manifests": manifests,
+    })
+}
+
+/// Exports the policies into the OCI image layout at `dir`, which is created
+/// when missing. The policies already exported into the layout are kept,
+/// unless they are listed under the same references.
+pub(crate) fn export(dir: &Path, policies: &[Policy]) -> Result<ExportReport> {
+    let layout_path = dir.join(OCI_LAYOUT_FILE);
+    if dir.exists() && fs::read_dir(dir)?.next().is_some() && !layout_path.exists() {
+        return Err(anyhow!(
+            "{} is neither empty nor an OCI image layout",
+            dir.display()
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    fs::write(
+        &layout_path,
+        serde_json::to_vec(&json!({ "imageLayoutVersion": OCI_LAYOUT_VERSION }))?,
+    )?;
+
+    let mut report = ExportReport {
+        exported: Vec::new(),
+        skipped: Vec::new(),
+    };
+    let mut entries = Vec::new();
+    for policy in policies {
+        let Some(reference) = image_reference(&policy.uri) else {
+            warn!(
+                policy = policy.uri.as_str(),
+                "policy not pulled from a registry, skipping it"
+            );
+            report.skipped.push(policy.uri.clone());
+            continue;
+        };
+
+        let module = fs::read(&policy.local_path)
+            .map_err(|e| anyhow!("cannot read {}: {}", policy.local_path.display(), e))?;
+        let annotations = match Metadata::from_path(&policy.local_path)? {
+            Some(metadata) => crate::push::metadata_annotations(&metadata)?,
+            None => BTreeMap::new(),
+        };
+        let manifest = OciImageManifest {
+            media_type: Some(OCI_IMAGE_MEDIA_TYPE.to_string()),
+            config: write_blob(dir, WASM_CONFIG_MEDIA_TYPE, WASM_CONFIG_DATA)?,
+            layers: vec![write_blob(dir, WASM_LAYER_MEDIA_TYPE, &module)?],
+            annotations: (!annotations.is_empty()).then_some(annotations),
+            ..Default::default()
+        };
+        let manifest = write_blob(
+            dir,
+            OCI_IMAGE_MEDIA_TYPE,
+            &serde_json::to_vec(&OciManifest::Image(manifest))?,
+        )?;
+
+        info!(
+            policy = policy.uri.as_str(),
+            digest = manifest.digest,
+            "policy exported"
+        );
+        entries.push(json!({
+            "mediaType": manifest.media_type,
+            "digest": manifest.digest,
+            "size": manifest.size,
+            "annotations": { ORG_OPENCONTAINERS_IMAGE_REF_NAME: reference },
+        }));
+        report.exported.push(reference);
+    }
+
+    let index = merge_index(read_index(dir)?, entries);
+    fs::write(dir.join(INDEX_FILE), serde_json::to_vec_pretty(&index)?)?;
+    Ok(report)
+}
+
+/// Outcome of the import
+pub(crate) struct ImportReport {
+    /// URIs of the imported policies
+    pub(crate) imported: Vec<String>,
+    /// Entries of the index that are not policies, with the reason
+    pub(crate) skipped: Vec<(String, String)>,
+}
+
+/// Reads the blob referenced by `digest`, validating its content
+fn read_blob(dir: &Path, digest: &str) -> Result<Vec<u8>> {
+    let hex = digest
+        .strip_prefix("sha256:")
+        .ok_or_else(|| anyhow!("unsupported digest algorithm: {}", digest))?;
+    let path = dir.join("blobs").join("sha256").join(hex);
+    let data = fs::read(&path).map_err(|e| anyhow!("cannot read blob {}: {}", digest, e))?;
+    let actual = format!("{:x}", Sha256::digest(&data));
+    if actual != hex {
+        return Err(anyhow!(
+            "blob {} is corrupted, its digest is sha256:{}",
+            digest,
+            actual
+        ));
+    }
+    Ok(data)
+}
+
+/// URI of the store the policy listed under `ref_name` is imported as. Image
+/// references are used as they are, while bare tags, like the ones written by
+/// `oras` and `skopeo`, are appended to `repository`.
+fn store_uri(ref_name: &str, repository: Option<&str>) -> Result<String> {
+    let image = if ref_name.contains('/') {
+        ref_name.to_string()
+    } else {
+        let repository = repository.ok_or_else(|| {
+            anyhow!(
+                "'{}' is not an image reference, use --repository to import it",
+                ref_name
+            )
+        })?;
+        let repository = repository.strip_prefix("registry://").unwrap_or(repository);
+        if ref_name.starts_with("sha256:") {
+            format!("{repository}@{ref_name}")
+        } else {
+            format!("{repository}:{ref_name}")
+        }
+    };
+    let reference = Reference::from_str(&image)
+        .map_err(|e| anyhow!("invalid image reference {}: {}", image, e))?;
+    Ok(format!("registry://{}", reference.whole()))
+}
+
+/// The Wasm module of the policy whose manifest is referenced by `digest`,
+/// `None` when the manifest is not the one of a policy
+fn read_policy(dir: &Path, digest: &str) -> Result<Option<Vec<u8>>> {
+    let manifest: OciImageManifest = serde_json::from_slice(&read_blob(dir, digest)?)
+        .map_err(|e| anyhow!("invalid manifest {}: {}", digest, e))?;
+    match manifest
+        .layers
+        .iter()
+        .find(|layer| layer.media_type == WASM_LAYER_MEDIA_TYPE)
+    {
+        Some(layer) => read_blob(dir, &layer.digest).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Imports the policies of the OCI image layout at `dir` into the store. The
+/// entries of the index that are not policies are skipped, while invalid
+/// policies make the import fail.
+pub(crate) fn import(dir: &Path, repository: Option<&str>) -> Result<ImportReport> {
+    if !dir.join(OCI_LAYOUT_FILE).exists() {
+        return Err(anyhow!(
+            "{} is not an OCI image layout, the {} file is missing",
+            dir.display(),
+            OCI_LAYOUT_FILE
+        ));
+    }
+
+    let store = crate::store_profile::store();
+    let mut report = ImportReport {
+        imported: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for entry in read_index(dir)? {
+        let digest = entry
+            .get("digest")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("an entry of the OCI index has no digest"))?;
+        let name = ref_name(&entry).unwrap_or(digest).to_string();
+        let media_type = entry
+            .get("mediaType")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if media_type != OCI_IMAGE_MEDIA_TYPE {
+            report
+                .skipped
+                .push((name, format!("unsupported media type '{media_type}'")));
+            continue;
+        }
+        let Some(module) =
+            read_policy(dir, digest).map_err(|e| anyhow!("cannot import {}: {}", name, e))?
+        else {
+            report
+                .skipped
+                .push((name, "not a Kubewarden policy, no Wasm layer".to_string()));
+            continue;
+        };
+        let uri = store_uri(&name, repository)?;
+
+        let path = store.policy_full_path(&uri, PolicyPath::PrefixAndFilename)?;
+        crate::store_sync::write_atomically(&path, &module)?;
+        if let Err(e) = crate::store_dedup::dedup(&path) {
+            warn!(policy = uri.as_str(), error = %e, "cannot deduplicate policy");
+        }
+        // same syntax used by skopeo to reference an image of a layout
+        crate::provenance::record(
+            &Policy {
+                uri: uri.clone(),
+                local_path: path,
+            },
+            &format!("oci:{}:{}", dir.display(), name),
+        );
+        info!(policy = uri.as_str(), "policy imported");
+        report.imported.push(uri);
+    }
+    Ok(report)
+}
+
+/// The policies of the store to export: the given ones, or all of them
+pub(crate) fn select_policies(policies: &[String]) -> Result<Vec<Policy>> {
+    if policies.is_empty() {
+        return crate::store_mode::list();
+    }
+    policies
+        .iter()
+        .map(|policy| {
+            let uri = crate::utils::get_uri(policy)?;
+            crate::store_mode::find_policy(&uri)?
+                .ok_or_else(|| anyhow!("cannot find policy {}", policy))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    #[rstest]
+    #[case::registry(
+        "registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0",
+        Some("ghcr.io/kubewarden/policies/safe-labels:v1.0.0")
+    )]
+    #[case::https("https://example.com/policy.wasm", None)]
+    fn reference_of_policy(#[case] uri: &str, #[case] expected: Option<&str>) {
+        assert_eq!(image_reference(uri).as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case::reference(
+        "ghcr.io/kubewarden/policies/safe-labels:v1.0.0",
+        None,
+        Some("registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0")
+    )]
+    #[case::tag(
+        "v1.0.0",
+        Some("registry://registry.example.com/safe-labels"),
+        Some("registry://registry.example.com/safe-labels:v1.0.0")
+    )]
+    #[case::tag_without_repository("v1.0.0", None, None)]
+    fn uri_of_imported_policy(
+        #[case] ref_name: &str,
+        #[case] repository: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(store_uri(ref_name, repository).ok().as_deref(), expected);
+    }
+
+    #[test]
+    fn corrupted_blobs_are_rejected() {
+        let layout = tempdir().unwrap();
+        let descriptor = write_blob(layout.path(), WASM_LAYER_MEDIA_TYPE, b"module").unwrap();
+        assert_eq!(
+            read_blob(layout.path(), &descriptor.digest).unwrap(),
+            b"module"
+        );
+
+        let hex = descriptor.digest.trim_start_matches("sha256:");
+        fs::write(layout.path().join("blobs/sha256").join(hex), b"tampered").unwrap();
+        assert!(read_blob(layout.path(), &descriptor.digest).is_err());
+    }
+
+    #[test]
+    fn exported_references_replace_existing_ones() {
+        let entry = |name: &str, digest: &str| {
+            json!({
+                "digest": digest,
+                "annotations": { ORG_OPENCONTAINERS_IMAGE_REF_NAME: name },
+            })
+        };
+        let index = merge_index(
+            vec![entry("a:v1", "sha256:1"), entry("b:v1", "sha256:2")],
+            vec![entry("a:v1", "sha256:3")],
+        );
+        assert_eq!(
+            index["manifests"],
+            json!([entry("b:v1", "sha256:2"), entry("a:v1", "sha256:3")])
+        );
+    }
+
+    #[test]
+    fn policies_are_exported_as_oci_image_layout() {
+        let store = tempdir().unwrap();
+        let module = store.path().join("policy.wasm");
+        fs::write(&module, b"\0asm\x01\0\0\0").unwrap();
+        let layout = tempdir().unwrap();
+
+        let report = export(
+            layout.path(),
+            &[
+                Policy {
+                    uri: "registry://ghcr.io/kubewarden/policies/safe-labels:v1.0.0".to_string(),
+                    local_path: module.clone(),
+                },
+                Policy {
+                    uri: "https://example.com/policy.wasm".to_string(),
+                    local_path: module,
+                },
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(
+            report.exported,
+            vec!["ghcr.io/kubewarden/policies/safe-labels:v1.0.0"]
+        );
+        assert_eq!(report.skipped, vec!["https://example.com/policy.wasm"]);
+        let index: Value =
+            serde_json::from_slice(&fs::read(layout.path().join(INDEX_FILE)).unwrap()).unwrap();
+        let digest = index["manifests"][0]["digest"].as_str().unwrap();
+        let manifest_path = layout
+            .path()
+            .join("blobs/sha256")
+            .join(digest.trim_start_matches("sha256:"));
+        let manifest: Value = serde_json::from_slice(&fs::read(manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest["layers"][0]["mediaType"], WASM_LAYER_MEDIA_TYPE);
+        assert!(layout.path().join(OCI_LAYOUT_FILE).exists());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::{
+    evaluation_context::EvaluationContext, policy_evaluator::PolicyExecutionMode,
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+};
+use tracing::debug;
+
+use crate::utils::find_in_path;
+
+/// Environment variable holding the path of the wasm-opt binary to use,
+/// for example the one shipped with the binaryen release vendored by a
+/// build pipeline. When not set, wasm-opt is looked up in `PATH`.
+const WASM_OPT_ENV: &str = "KWCTL_WASM_OPT";
+
+// The WebAssembly proposals enabled by the Wasmtime configuration of
+// policy-evaluator. wasm-opt rejects the modules using proposals that are
+// not enabled.
+const WASM_FEATURES: &[&str] = &[
+    "--enable-bulk-memory",
+    "--enable-mutable-globals",
+    "--enable-nontrapping-float-to-int",
+    "--enable-sign-ext",
+    "--enable-multivalue",
+    "--enable-reference-types",
+    "--enable-simd",
+];
+
+// The instantiation time is the best one of this many runs, to reduce noise
+const INSTANTIATION_RUNS: usize = 3;
+
+/// Optimization levels of wasm-opt, passed as `-O<level>`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum OptimizeLevel {
+    O1,
+    O2,
+    O3,
+    O4,
+    /// Optimize for size
+    Os,
+    /// Optimize aggressively for size
+    Oz,
+}
+
+impl TryFrom<&str> for OptimizeLevel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "1" => Ok(Self::O1),
+            "2" => Ok(Self::O2),
+            "3" => Ok(Self::O3),
+            "4" => Ok(Self::O4),
+            "s" => Ok(Self::Os),
+            "z" => Ok(Self::Oz),
+            unknown => Err(anyhow!("Invalid optimization level '{}'", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for OptimizeLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            Self::O1 => "-O1",
+            Self::O2 => "-O2",
+            Self::O3 => "-O3",
+            Self::O4 => "-O4",
+            Self::Os => "-Os",
+            Self::Oz => "-Oz",
+        };
+        write!(f, "{level}")
+    }
+}
+
+/// Size and instantiation time of a policy, before and after the
+/// optimization
+pub(crate) struct OptimizationReport {
+    level: OptimizeLevel,
+    size_before: u64,
+    size_after: u64,
+    instantiation_before: Duration,
+    instantiation_after: Duration,
+}
+
+/// Relative change from `before` to `after`, like `-12.5%`
+fn delta(before: f64, after: f64) -> String {
+    if before == 0.0 {
+        return "n/a".to_string();
+    }
+    format!("{:+.1}%", (after - before) / before * 100.0)
+}
+
+impl fmt::Display for OptimizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Policy optimized with wasm-opt {}", self.level)?;
+        writeln!(
+            f,
+            "  size: {} -> {} ({})",
+            humansize::format_size(self.size_before, humansize::DECIMAL),
+            humansize::format_size(self.size_after, humansize::DECIMAL),
+            delta(self.size_before as f64, self.size_after as f64)
+        )?;
+        write!(
+            f,
+            "  instantiation time: {} ms -> {} ms ({})",
+            self.instantiation_before.as_millis(),
+            self.instantiation_after.as_millis(),
+            delta(
+                self.instantiation_before.as_secs_f64(),
+                self.instantiation_after.as_secs_f64()
+            )
+        )
+    }
+}
+
+fn wasm_opt_binary_name() -> String {
+    format!("wasm-opt{}", env::consts::EXE_SUFFIX)
+}
+
+fn wasm_opt() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(WASM_OPT_ENV) {
+        let path = PathBuf::from(path);
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(anyhow!(
+                "cannot find wasm-opt at {}, set via {}",
+                path.display(),
+                WASM_OPT_ENV
+            ))
+        };
+    }
+
+    find_in_path(&wasm_opt_binary_name()).ok_or_else(|| {
+        anyhow!(
+            "cannot find wasm-opt: install binaryen or set {} to the path of wasm-opt",
+            WASM_OPT_ENV
+        )
+    })
+}
+
+/// Time needed to compile and instantiate the policy, the best one of a few
+/// runs
+fn instantiation_time(wasm_path: &Path, execution_mode: PolicyExecutionMode) -> Result<Duration> {
+    let eval_ctx = EvaluationContext::default();
+    let mut best = Duration::MAX;
+    for _ in 0..INSTANTIATION_RUNS {
+        let start = Instant::now();
+        PolicyEvaluatorBuilder::new()
+            .policy_file(wasm_path)?
+            .execution_mode(execution_mode)
+            .build_pre()?
+            .rehydrate(&eval_ctx)?;
+        best = best.min(start.elapsed());
+    }
+    Ok(best)
+}
+
+/// Optimizes the policy at `input` with wasm-opt, writing the result to
+/// `output`. The custom sections, like the metadata of the policy, are
+/// preserved by wasm-opt.
+pub(crate) fn optimize(
+    input: &Path,
+    output: &Path,
+    level: OptimizeLevel,
+    execution_mode: PolicyExecutionMode,
+) -> Result<OptimizationReport> {
+    let wasm_opt = wasm_opt()?;
+    debug!(wasm_opt = %wasm_opt.display(), %level, "optimizing policy");
+
+    let result = Command::new(&wasm_opt)
+        .arg(level.to_string())
+        .args(WASM_FEATURES)
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .output()
+        .map_err(|e| anyhow!("cannot run {}: {}", wasm_opt.display(), e))?;
+    if !result.status.success() {
+        return Err(anyhow!(
+            "wasm-opt failed: {}",
+            String::from_utf8_lossy(&result.stderr).trim()
+        ));
+    }
+
+    Ok(OptimizationReport {
+        level,
+        size_before: std::fs::metadata(input)?.len(),
+        size_after: std::fs::metadata(output)?.len(),
+        instantiation_before: instantiation_time(input, execution_mode)?,
+        instantiation_after: instantiation_time(output, execution_mode)
+            .map_err(|e| anyhow!("the optimized policy cannot be instantiated: {}", e))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::find_in_paths;
+    use rstest::rstest;
+    use tempfile::tempdir;
+
+    #[rstest]
+    #[case::speed("2", OptimizeLevel::O2, "-O2")]
+    #[case::size("z", OptimizeLevel::Oz, "-Oz")]
+    fn optimize_level(#[case] value: &str, #[case] expected: OptimizeLevel, #[case] flag: &str) {
+        let level = OptimizeLevel::try_from(value).unwrap();
+        assert_eq!(level, expected);
+        assert_eq!(level.to_string(), flag);
+    }
+
+    #[rstest]
+    #[case::shrunk(200.0, 150.0, "-25.0%")]
+    #[case::grown(100.0, 101.0, "+1.0%")]
+    #[case::empty(0.0, 0.0, "n/a")]
+    fn relative_delta(#[case] before: f64, #[case] after: f64, #[case] expected: &str) {
+        assert_eq!(delta(before, after), expected);
+    }
+
+    #[test]
+    fn wasm_opt_is_found_in_path() {
+        let empty = tempdir().unwrap();
+        let binaryen = tempdir().unwrap();
+        let binary = binaryen.path().join(wasm_opt_binary_name());
+        std::fs::write(&binary, "").unwrap();
+
+        let paths = env::join_paths([empty.path(), binaryen.path()]).unwrap();
+        assert_eq!(find_in_paths(&wasm_opt_binary_name(), &paths), Some(binary));
+
+        let paths = env::join_paths([empty.path()]).unwrap();
+        assert_eq!(find_in_paths(&wasm_opt_binary_name(), &paths), None);
+    }
+}
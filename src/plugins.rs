@@ -0,0 +1,99 @@
+use std::{
+    env,
+    ffi::OsString,
+    process::{self, ExitCode},
+};
+
+use anyhow::{anyhow, Result};
+use clap::crate_version;
+use tracing::debug;
+
+use crate::utils::find_in_path;
+
+/// Version of the environment handshake between kwctl and its plugins. To be
+/// bumped when the variables exported to the plugins change in an
+/// incompatible way.
+const PLUGIN_API_VERSION: &str = "v1";
+
+fn plugin_binary_name(name: &str) -> String {
+    let binary = format!("kwctl-{name}");
+    if cfg!(windows) {
+        format!("{binary}.exe")
+    } else {
+        binary
+    }
+}
+
+/// Runs the `kwctl-<name>` binary found inside of `$PATH`, passing it `args`.
+///
+/// The plugin inherits the standard streams of kwctl, together with a set of
+/// `KWCTL_*` environment variables describing the kwctl installation. kwctl
+/// returns the exit code of the plugin, for kwctl to exit with once its own
+/// resources have been released.
+pub(crate) fn exec(
+    name: &str,
+    args: Vec<OsString>,
+    verbose: bool,
+    no_color: bool,
+) -> Result<ExitCode> {
+    let binary = plugin_binary_name(name);
+    let plugin = find_in_path(&binary).ok_or_else(|| {
+        anyhow!(
+            "unknown subcommand: {}. No '{}' plugin found inside of $PATH",
+            name,
+            binary
+        )
+    })?;
+    debug!(plugin = %plugin.display(), ?args, "running plugin");
+
+    let status = process::Command::new(&plugin)
+        .args(args)
+        .envs(handshake_env(verbose, no_color)?)
+        .status()
+        .map_err(|e| anyhow!("cannot run plugin {}: {}", plugin.display(), e))?;
+
+    // a plugin terminated by a signal has no exit code
+    Ok(ExitCode::from(exit_code(status.code())))
+}
+
+fn exit_code(code: Option<i32>) -> u8 {
+    code.and_then(|code| u8::try_from(code).ok()).unwrap_or(1)
+}
+
+/// The environment variables exported to the plugins
+fn handshake_env(verbose: bool, no_color: bool) -> Result<Vec<(&'static str, OsString)>> {
+    Ok(vec![
+        ("KWCTL_PLUGIN_API_VERSION", PLUGIN_API_VERSION.into()),
+        ("KWCTL_BIN", env::current_exe()?.into()),
+        ("KWCTL_VERSION", crate_version!().into()),
+        (
+            "KWCTL_STORE_PATH",
+            crate::store_profile::store().root.into(),
+        ),
+        (
+            "KWCTL_CONFIG_DIR",
+            crate::store_profile::config_dir().into(),
+        ),
+        (
+            "KWCTL_CACHE_DIR",
+            crate::store_profile::kwctl_cache_dir().into(),
+        ),
+        ("KWCTL_VERBOSE", verbose.to_string().into()),
+        ("KWCTL_NO_COLOR", no_color.to_string().into()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::success(Some(0), 0)]
+    #[case::failure(Some(3), 3)]
+    #[case::signal(None, 1)]
+    #[case::out_of_range(Some(-1), 1)]
+    fn plugin_exit_codes(#[case] code: Option<i32>, #[case] expected: u8) {
+        assert_eq!(exit_code(code), expected);
+    }
+}
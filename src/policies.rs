@@ -1,62 +1,620 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+};
+
 use anyhow::{anyhow, Result};
 use policy_evaluator::{
-    policy_fetcher::{policy::Policy, store::Store},
+    policy_fetcher::{
+        oci_client::manifest::{OciImageManifest, OciManifest},
+        policy::Policy,
+        registry::Registry,
+        sources::Sources,
+    },
     policy_metadata::Metadata as PolicyMetadata,
 };
 use prettytable::{format, row, Table};
+use serde::Serialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::{
+    inspect::{fetch_signatures_manifest, is_unsigned},
+    provenance::{Provenance, Verification},
+    push::KWCTL_ANNOTATION_POLICY_METADATA,
+    timestamps, updates,
+};
+
+// File of the cache directory keeping track of when the policies have been
+// last evaluated
+const USAGE_FILE: &str = "policies-usage.json";
+
+/// State of a policy of the store compared to the one currently referenced
+/// by its remote URI
+#[derive(Debug, PartialEq)]
+enum RemoteStatus {
+    UpToDate,
+    /// Holds the metadata fields changed by the remote policy, `None` when
+    /// its OCI manifest doesn't carry the metadata
+    Stale(Option<Vec<String>>),
+    /// Only policies pulled from a registry can be checked
+    NotApplicable,
+    Error(String),
+}
+
+impl std::fmt::Display for RemoteStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteStatus::UpToDate => write!(f, "up to date"),
+            RemoteStatus::Stale(None) => write!(f, "stale"),
+            RemoteStatus::Stale(Some(changes)) if changes.is_empty() => {
+                write!(f, "stale: same metadata")
+            }
+            RemoteStatus::Stale(Some(changes)) => {
+                write!(f, "stale: {} changed", changes.join(", "))
+            }
+            RemoteStatus::NotApplicable => write!(f, "n/a"),
+            RemoteStatus::Error(e) => write!(f, "error: {e}"),
+        }
+    }
+}
+
+/// Whether the policy, as currently referenced by its remote URI, has been
+/// signed with Sigstore
+#[derive(Debug, PartialEq)]
+enum SignatureStatus {
+    Signed(usize),
+    Unsigned,
+    /// Only policies pulled from a registry can be checked
+    NotApplicable,
+    Error(String),
+}
+
+impl std::fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureStatus::Signed(1) => write!(f, "1 signature"),
+            SignatureStatus::Signed(signatures) => write!(f, "{signatures} signatures"),
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+            SignatureStatus::NotApplicable => write!(f, "n/a"),
+            SignatureStatus::Error(e) => write!(f, "error: {e}"),
+        }
+    }
+}
+
+/// Whether a release newer than the one of the policy has been published
+#[derive(Debug, PartialEq)]
+enum UpdateStatus {
+    UpToDate,
+    /// Holds the tag of the newest release
+    Available(String),
+    /// Only policies pulled from a registry via a semver tag can be checked
+    NotApplicable,
+    Error(String),
+}
 
-pub(crate) fn list() -> Result<()> {
-    if policy_list()?.is_empty() {
-        return Ok(());
+impl std::fmt::Display for UpdateStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateStatus::UpToDate => write!(f, "up to date"),
+            UpdateStatus::Available(tag) => write!(f, "{tag} available"),
+            UpdateStatus::NotApplicable => write!(f, "n/a"),
+            UpdateStatus::Error(e) => write!(f, "error: {e}"),
+        }
     }
+}
+
+/// Output formats of the listing
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum OutputFormat {
+    Text,
+    Wide,
+    Json,
+    Yaml,
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(Self::Text),
+            "wide" => Ok(Self::Wide),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            unknown => Err(anyhow!("Invalid output format '{}'", unknown)),
+        }
+    }
+}
+
+/// A policy of the store, as reported by the structured outputs
+#[derive(Debug, Serialize)]
+struct PolicyEntry {
+    uri: String,
+    /// SHA-256 of the Wasm module
+    digest: String,
+    size: u64,
+    local_path: String,
+    /// Missing when the policy has no metadata
+    mutating: Option<bool>,
+    context_aware: bool,
+    /// Missing when the policy has no metadata
+    execution_mode: Option<String>,
+    /// When the policy has been written into the store
+    pulled: Option<String>,
+    /// URI the policy has been pulled from, missing when its provenance has
+    /// not been recorded
+    source: Option<String>,
+    /// Outcome of the last verification of the signatures of the policy,
+    /// missing when they have never been verified
+    verification: Option<Verification>,
+    /// When the policy has been last evaluated by `run` or `bench`, missing
+    /// when it has never been evaluated
+    last_used: Option<String>,
+    /// Set only when `--verify-remote` is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote: Option<String>,
+    /// The metadata fields changed by the remote policy, set only when
+    /// `--verify-remote` is given and the local copy is stale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_changes: Option<Vec<String>>,
+    /// Set only when `--verify-remote` is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signatures: Option<String>,
+    /// Set only when `--check-updates` is given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update: Option<String>,
+}
+
+impl PolicyEntry {
+    fn new(
+        policy: &Policy,
+        usage: &BTreeMap<String, i64>,
+        provenance: Option<&Provenance>,
+    ) -> Result<Self> {
+        let metadata = PolicyMetadata::from_path(&policy.local_path)
+            .map_err(|e| anyhow!("error processing metadata of policy {}: {:?}", policy, e))?;
+        let filesystem_metadata = fs::metadata(&policy.local_path)?;
+
+        Ok(Self {
+            uri: policy.uri.clone(),
+            digest: policy.digest()?,
+            size: filesystem_metadata.len(),
+            local_path: policy.local_path.display().to_string(),
+            mutating: metadata.as_ref().map(|metadata| metadata.mutating),
+            context_aware: metadata
+                .as_ref()
+                .is_some_and(|metadata| !metadata.context_aware_resources.is_empty()),
+            execution_mode: metadata
+                .as_ref()
+                .map(|metadata| metadata.execution_mode.to_string()),
+            // the modification time is a fallback for the policies without
+            // provenance, it's changed when the policy is copied around
+            pulled: match provenance {
+                Some(provenance) => timestamps::format_unix(provenance.pulled),
+                None => filesystem_metadata
+                    .modified()
+                    .ok()
+                    .map(|modified| timestamps::format(modified.into())),
+            },
+            source: provenance.map(|provenance| provenance.source.clone()),
+            verification: provenance.and_then(|provenance| provenance.verification.clone()),
+            last_used: usage
+                .get(&policy.uri)
+                .and_then(|timestamp| timestamps::format_unix(*timestamp)),
+            remote: None,
+            remote_changes: None,
+            signatures: None,
+            update: None,
+        })
+    }
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Lists the policies of the store.
+///
+/// When `verify_remote` is set, the digest of each policy is compared
+/// against the one of the policy currently referenced by its remote URI, and
+/// the signatures of the remote policy are looked up. When `check_updates`
+/// is set, the tags of the repository of each policy are looked up for newer
+/// releases.
+pub(crate) async fn list(
+    verify_remote: bool,
+    check_updates: bool,
+    sources: Option<&Sources>,
+    output: OutputFormat,
+) -> Result<()> {
+    let registry = Registry::new();
+    let usage = load_usage();
+    let provenance = crate::provenance::load();
+    let mut stale_policies = 0;
+    let mut outdated_policies = 0;
+    let mut entries = Vec::new();
+    for policy in policy_list()? {
+        let mut entry = PolicyEntry::new(&policy, &usage, provenance.get(&policy.uri))?;
+        if verify_remote {
+            let status = remote_status(&registry, &policy, &entry.digest, sources).await;
+            entry.remote = Some(status.to_string());
+            if let RemoteStatus::Stale(changes) = status {
+                stale_policies += 1;
+                entry.remote_changes = changes;
+            }
+            entry.signatures = Some(signature_status(&policy, sources).await.to_string());
+        }
+        if check_updates {
+            let status = update_status(&policy, sources).await;
+            if matches!(status, UpdateStatus::Available(_)) {
+                outdated_policies += 1;
+            }
+            entry.update = Some(status.to_string());
+        }
+        entries.push(entry);
+    }
+
+    match output {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        OutputFormat::Yaml => print!("{}", serde_yaml::to_string(&entries)?),
+        OutputFormat::Text | OutputFormat::Wide => {
+            if !entries.is_empty() {
+                print_table(
+                    &entries,
+                    verify_remote,
+                    check_updates,
+                    output == OutputFormat::Wide,
+                );
+            }
+        }
+    }
+
+    if stale_policies > 0 {
+        warn!(
+            "{} policies differ from the ones currently referenced by their remote URI, pull them again to update the local copies",
+            stale_policies
+        );
+    }
+    if outdated_policies > 0 {
+        warn!(
+            "{} policies have newer releases available, pull them to update",
+            outdated_policies
+        );
+    }
+    Ok(())
+}
+
+fn print_table(entries: &[PolicyEntry], verify_remote: bool, check_updates: bool, wide: bool) {
     let mut table = Table::new();
     table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
-    table.set_titles(row![
+    let mut titles = row![
         "Policy",
         "Mutating",
         "Context aware",
+        "Execution mode",
         "SHA-256",
-        "Size"
-    ]);
-    for policy in policy_list()? {
-        let (mutating, context_aware) = if let Some(policy_metadata) =
-            PolicyMetadata::from_path(&policy.local_path)
-                .map_err(|e| anyhow!("error processing metadata of policy {}: {:?}", policy, e))?
-        {
-            let mutating = if policy_metadata.mutating {
-                "yes"
-            } else {
-                "no"
-            };
-
-            let context_aware = if policy_metadata.context_aware_resources.is_empty() {
-                "no"
-            } else {
-                "yes"
-            };
-
-            (mutating, context_aware)
-        } else {
-            ("unknown", "no")
-        };
-
-        let mut sha256sum = policy.digest()?;
-        sha256sum.truncate(12);
-
-        let policy_filesystem_metadata = std::fs::metadata(&policy.local_path)?;
-
-        table.add_row(row![
-            format!("{policy}"),
-            mutating,
-            context_aware,
+        "Size",
+        "Pulled",
+        "Last used"
+    ];
+    if wide {
+        titles.add_cell(prettytable::Cell::new("Path"));
+        titles.add_cell(prettytable::Cell::new("Source"));
+        titles.add_cell(prettytable::Cell::new("Verification"));
+    }
+    if verify_remote {
+        titles.add_cell(prettytable::Cell::new("Remote"));
+        if wide {
+            titles.add_cell(prettytable::Cell::new("Signatures"));
+        }
+    }
+    if check_updates {
+        titles.add_cell(prettytable::Cell::new("Update"));
+    }
+    table.set_titles(titles);
+
+    for entry in entries {
+        let mut sha256sum = entry.digest.clone();
+        if !wide {
+            sha256sum.truncate(12);
+        }
+        let mut row = row![
+            entry.uri,
+            entry.mutating.map_or("unknown", yes_no),
+            yes_no(entry.context_aware),
+            entry.execution_mode.as_deref().unwrap_or("unknown"),
             sha256sum,
-            humansize::format_size(policy_filesystem_metadata.len(), humansize::DECIMAL),
-        ]);
+            humansize::format_size(entry.size, humansize::DECIMAL),
+            entry.pulled.as_deref().unwrap_or("unknown"),
+            entry.last_used.as_deref().unwrap_or("never"),
+        ];
+        if wide {
+            row.add_cell(prettytable::Cell::new(&entry.local_path));
+            row.add_cell(prettytable::Cell::new(
+                entry.source.as_deref().unwrap_or("unknown"),
+            ));
+            row.add_cell(prettytable::Cell::new(
+                &entry
+                    .verification
+                    .as_ref()
+                    .map_or("not verified".to_string(), Verification::to_string),
+            ));
+        }
+        if let Some(remote) = &entry.remote {
+            row.add_cell(prettytable::Cell::new(remote));
+        }
+        if let (true, Some(signatures)) = (wide, &entry.signatures) {
+            row.add_cell(prettytable::Cell::new(signatures));
+        }
+        if let Some(update) = &entry.update {
+            row.add_cell(prettytable::Cell::new(update));
+        }
+        table.add_row(row);
     }
     table.printstd();
-    Ok(())
+}
+
+fn usage_path() -> PathBuf {
+    crate::store_profile::cache_dir().join(USAGE_FILE)
+}
+
+/// When the policies have been last evaluated, keyed by URI
+pub(crate) fn load_usage() -> BTreeMap<String, i64> {
+    fs::read(usage_path())
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// The usage is only informational, failures are logged and ignored
+fn save_usage(usage: &BTreeMap<String, i64>) {
+    let path = usage_path();
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_vec(usage)?));
+    if let Err(e) = result {
+        warn!(path = %path.display(), error = %e, "cannot record the usage of the policies");
+    }
+}
+
+/// Records that the given policies are being evaluated
+pub(crate) fn record_usage<'a>(uris: impl IntoIterator<Item = &'a String>) {
+    let mut usage = load_usage();
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    for uri in uris {
+        usage.insert(uri.to_owned(), now);
+    }
+    save_usage(&usage);
+}
+
+/// Drops the usage of the given policies, once they have been removed
+pub(crate) fn forget_usage<'a>(uris: impl IntoIterator<Item = &'a String>) {
+    let mut usage = load_usage();
+    for uri in uris {
+        usage.remove(uri);
+    }
+    save_usage(&usage);
 }
 
 fn policy_list() -> Result<Vec<Policy>> {
-    Store::default().list().map_err(anyhow::Error::new)
+    crate::store_mode::list()
+}
+
+async fn signature_status(policy: &Policy, sources: Option<&Sources>) -> SignatureStatus {
+    if !policy.uri.starts_with("registry://") {
+        return SignatureStatus::NotApplicable;
+    }
+
+    match fetch_signatures_manifest(&policy.uri, sources.cloned()).await {
+        Ok(Some(manifest)) => SignatureStatus::Signed(manifest.layers.len()),
+        Ok(None) => SignatureStatus::Error("unexpected OCI manifest type".to_string()),
+        Err(e) if is_unsigned(&e) => SignatureStatus::Unsigned,
+        Err(e) => SignatureStatus::Error(e.to_string()),
+    }
+}
+
+async fn remote_status(
+    registry: &Registry,
+    policy: &Policy,
+    local_digest: &str,
+    sources: Option<&Sources>,
+) -> RemoteStatus {
+    if !policy.uri.starts_with("registry://") {
+        return RemoteStatus::NotApplicable;
+    }
+
+    let manifest = match registry.manifest(&policy.uri, sources).await {
+        Ok(OciManifest::Image(manifest)) => manifest,
+        Ok(_) => return RemoteStatus::Error("unexpected OCI manifest type".to_string()),
+        Err(e) => return RemoteStatus::Error(e.to_string()),
+    };
+    let local_metadata = match PolicyMetadata::from_path(&policy.local_path) {
+        Ok(metadata) => metadata,
+        Err(e) => return RemoteStatus::Error(format!("cannot read the local metadata: {e}")),
+    };
+    compare_with_manifest(local_digest, local_metadata.as_ref(), &manifest)
+}
+
+async fn update_status(policy: &Policy, sources: Option<&Sources>) -> UpdateStatus {
+    let Some((reference, current)) = updates::current_version(&policy.uri) else {
+        return UpdateStatus::NotApplicable;
+    };
+
+    match updates::list_tags(&reference, sources).await {
+        Ok(tags) => updates::newest_release(&current, &tags, None)
+            .map_or(UpdateStatus::UpToDate, |tag| {
+                UpdateStatus::Available(tag.to_string())
+            }),
+        Err(e) => UpdateStatus::Error(e.to_string()),
+    }
+}
+
+// The digest of the Wasm layer is the SHA-256 of the policy, the same value
+// computed for the local copy. The metadata of the stale policies is compared
+// with the one carried by the manifest, when pushed by kwctl
+fn compare_with_manifest(
+    local_digest: &str,
+    local_metadata: Option<&PolicyMetadata>,
+    manifest: &OciImageManifest,
+) -> RemoteStatus {
+    match crate::utils::wasm_layer_digest(manifest) {
+        Some(digest) if digest == format!("sha256:{local_digest}") => RemoteStatus::UpToDate,
+        Some(_) => RemoteStatus::Stale(remote_metadata(manifest).map(|remote_metadata| {
+            metadata_changes(&to_value(local_metadata), &to_value(Some(&remote_metadata)))
+        })),
+        None => RemoteStatus::Error("the OCI manifest has no Wasm layer".to_string()),
+    }
+}
+
+/// The metadata carried by the manifest, `None` when missing or invalid
+fn remote_metadata(manifest: &OciImageManifest) -> Option<PolicyMetadata> {
+    let metadata = manifest
+        .annotations
+        .as_ref()?
+        .get(KWCTL_ANNOTATION_POLICY_METADATA)?;
+    serde_json::from_str(metadata)
+        .map_err(|e| warn!(error = %e, "cannot parse the metadata annotation of the remote policy"))
+        .ok()
+}
+
+// both the metadata are serialized by the same code, so that only the actual
+// changes are reported
+fn to_value(metadata: Option<&PolicyMetadata>) -> Value {
+    metadata
+        .and_then(|metadata| serde_json::to_value(metadata).ok())
+        .unwrap_or_default()
+}
+
+/// The metadata fields that differ, sorted. The annotations are compared one
+/// by one, and reported as `annotations.<name>`.
+fn metadata_changes(local: &Value, remote: &Value) -> Vec<String> {
+    let fields = |value: &Value| value.as_object().cloned().unwrap_or_default();
+    let (local, remote) = (fields(local), fields(remote));
+    let mut changes = Vec::new();
+    for key in local.keys().chain(remote.keys()).collect::<BTreeSet<_>>() {
+        if key == "annotations" {
+            let (local, remote) = (
+                fields(local.get(key).unwrap_or(&Value::Null)),
+                fields(remote.get(key).unwrap_or(&Value::Null)),
+            );
+            for annotation in local.keys().chain(remote.keys()).collect::<BTreeSet<_>>() {
+                if local.get(annotation) != remote.get(annotation) {
+                    changes.push(format!("annotations.{annotation}"));
+                }
+            }
+        } else if local.get(key) != remote.get(key) {
+            changes.push(key.to_owned());
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::policy_fetcher::oci_client::manifest::{
+        OciDescriptor, WASM_LAYER_MEDIA_TYPE,
+    };
+    use rstest::rstest;
+
+    const LOCAL_DIGEST: &str = "61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c";
+
+    fn manifest(media_type: &str, digest: &str) -> OciImageManifest {
+        OciImageManifest {
+            layers: vec![OciDescriptor {
+                media_type: media_type.to_string(),
+                digest: digest.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[rstest]
+    #[case::single_signature(SignatureStatus::Signed(1), "1 signature")]
+    #[case::multiple_signatures(SignatureStatus::Signed(3), "3 signatures")]
+    #[case::unsigned(SignatureStatus::Unsigned, "unsigned")]
+    fn signature_status_display(#[case] status: SignatureStatus, #[case] expected: &str) {
+        assert_eq!(status.to_string(), expected);
+    }
+
+    #[rstest]
+    #[case::up_to_date(
+        manifest(WASM_LAYER_MEDIA_TYPE, &format!("sha256:{LOCAL_DIGEST}")),
+        RemoteStatus::UpToDate
+    )]
+    #[case::stale(
+        manifest(
+            WASM_LAYER_MEDIA_TYPE,
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000"
+        ),
+        RemoteStatus::Stale(None)
+    )]
+    #[case::no_wasm_layer(
+        manifest("application/vnd.oci.image.layer.v1.tar", &format!("sha256:{LOCAL_DIGEST}")),
+        RemoteStatus::Error("the OCI manifest has no Wasm layer".to_string())
+    )]
+    fn compare_local_digest_with_manifest(
+        #[case] manifest: OciImageManifest,
+        #[case] expected: RemoteStatus,
+    ) {
+        assert_eq!(
+            compare_with_manifest(LOCAL_DIGEST, None, &manifest),
+            expected
+        );
+    }
+
+    fn metadata(mutating: bool, version: &str) -> PolicyMetadata {
+        serde_json::from_value(serde_json::json!({
+            "rules": [],
+            "mutating": mutating,
+            "annotations": {
+                "io.kubewarden.policy.title": "safe-labels",
+                "io.kubewarden.policy.version": version,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn stale_policy_metadata_changes() {
+        let mut manifest = manifest(
+            WASM_LAYER_MEDIA_TYPE,
+            "sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        );
+        manifest.annotations = Some(BTreeMap::from([(
+            KWCTL_ANNOTATION_POLICY_METADATA.to_string(),
+            serde_json::to_string(&metadata(true, "0.2.0")).unwrap(),
+        )]));
+
+        assert_eq!(
+            compare_with_manifest(LOCAL_DIGEST, Some(&metadata(false, "0.1.0")), &manifest),
+            RemoteStatus::Stale(Some(vec![
+                "annotations.io.kubewarden.policy.version".to_string(),
+                "mutating".to_string(),
+            ]))
+        );
+        assert_eq!(
+            compare_with_manifest(LOCAL_DIGEST, Some(&metadata(true, "0.2.0")), &manifest),
+            RemoteStatus::Stale(Some(vec![]))
+        );
+    }
+
+    #[rstest]
+    #[case::no_metadata(RemoteStatus::Stale(None), "stale")]
+    #[case::same_metadata(RemoteStatus::Stale(Some(vec![])), "stale: same metadata")]
+    #[case::changes(
+        RemoteStatus::Stale(Some(vec!["mutating".to_string(), "rules".to_string()])),
+        "stale: mutating, rules changed"
+    )]
+    fn remote_status_display(#[case] status: RemoteStatus, #[case] expected: &str) {
+        assert_eq!(status.to_string(), expected);
+    }
 }
@@ -0,0 +1,153 @@
+//! Provenance of the policies of the local store.
+//!
+//! For every policy written into the store, kwctl records inside of the
+//! cache directory where the policy has been pulled from, when, its SHA-256
+//! digest, and whether its signatures have been verified, together with the
+//! verification config used. The records are shown by `kwctl policies` and
+//! `kwctl inspect`, and their digests are checked by `kwctl store verify`.
+
+use std::{collections::BTreeMap, fmt, fs, path::PathBuf};
+
+use policy_evaluator::policy_fetcher::policy::Policy;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::warn;
+
+// File of the cache directory holding the provenance of the policies
+const PROVENANCE_FILE: &str = "policies-provenance.json";
+
+/// Provenance of a policy of the store
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Provenance {
+    /// URI the policy has been pulled from, either the URI of the policy or
+    /// the one of the registry mirror that served it
+    pub(crate) source: String,
+    /// When the policy has been pulled, as a Unix timestamp
+    pub(crate) pulled: i64,
+    /// SHA-256 of the Wasm module, as pulled
+    pub(crate) digest: String,
+    /// Missing when the signatures of the policy have never been verified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) verification: Option<Verification>,
+}
+
+/// Outcome of the last verification of the signatures of a policy
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Verification {
+    pub(crate) verified: bool,
+    /// The verification config used, either the path of the config file or
+    /// the command line flags
+    pub(crate) config: String,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.verified {
+            write!(f, "verified with {}", self.config)
+        } else {
+            write!(f, "failed with {}", self.config)
+        }
+    }
+}
+
+fn provenance_path() -> PathBuf {
+    crate::store_profile::cache_dir().join(PROVENANCE_FILE)
+}
+
+/// The provenance of the policies of the store, keyed by URI
+pub(crate) fn load() -> BTreeMap<String, Provenance> {
+    fs::read(provenance_path())
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Failing to record the provenance only leaves the policies without it,
+/// failures are logged and ignored
+fn save(records: &BTreeMap<String, Provenance>) {
+    let path = provenance_path();
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_vec(records)?));
+    if let Err(e) = result {
+        warn!(path = %path.display(), error = %e, "cannot record the provenance of the policies");
+    }
+}
+
+/// Records the provenance of the policy, just written into the store from
+/// `source`. Any previous verification outcome is dropped, it refers to the
+/// policy being replaced.
+pub(crate) fn record(policy: &Policy, source: &str) {
+    let digest = match policy.digest() {
+        Ok(digest) => digest,
+        Err(e) => {
+            warn!(policy = policy.uri.as_str(), error = %e, "cannot record the provenance of the policy");
+            return;
+        }
+    };
+    let mut records = load();
+    records.insert(
+        policy.uri.to_owned(),
+        Provenance {
+            source: source.to_string(),
+            pulled: OffsetDateTime::now_utc().unix_timestamp(),
+            digest,
+            verification: None,
+        },
+    );
+    save(&records);
+}
+
+/// Records the outcome of the verification of the signatures of a policy of
+/// the store. Nothing is recorded for the policies without a provenance,
+/// like the ones pulled to a file outside of the store.
+pub(crate) fn record_verification(uri: &str, verified: bool, config: &str) {
+    let mut records = load();
+    let Some(provenance) = records.get_mut(uri) else {
+        return;
+    };
+    provenance.verification = Some(Verification {
+        verified,
+        config: config.to_string(),
+    });
+    save(&records);
+}
+
+/// Drops the provenance of the given policies, once they have been removed
+pub(crate) fn forget<'a>(uris: impl IntoIterator<Item = &'a String>) {
+    let mut records = load();
+    for uri in uris {
+        records.remove(uri);
+    }
+    save(&records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::verified(true, "verified with /home/user/verification-config.yml")]
+    #[case::failed(false, "failed with /home/user/verification-config.yml")]
+    fn verification_display(#[case] verified: bool, #[case] expected: &str) {
+        let verification = Verification {
+            verified,
+            config: "/home/user/verification-config.yml".to_string(),
+        };
+        assert_eq!(verification.to_string(), expected);
+    }
+
+    #[test]
+    fn provenance_without_verification() {
+        let provenance: Provenance = serde_json::from_str(
+            r#"{"source":"registry://mirror.example.com/policies/psp:v1","pulled":1716283800,"digest":"abc"}"#,
+        )
+        .unwrap();
+        assert_eq!(provenance.verification, None);
+        assert!(!serde_json::to_string(&provenance)
+            .unwrap()
+            .contains("verification"));
+    }
+}
@@ -1,12 +1,144 @@
-use std::time::Duration;
+use std::{path::PathBuf, time::Duration};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use indicatif::{ProgressBar, ProgressStyle};
 use policy_evaluator::policy_fetcher::{
-    fetch_policy, policy::Policy, sources::Sources, PullDestination,
+    fetch_policy,
+    policy::Policy,
+    sources::Sources,
+    store::{PolicyPath, Store},
+    PullDestination,
 };
+use tracing::warn;
 
+use crate::{
+    config::sources::RegistryMirrors, mirror_health, provenance, store_dedup, store_mode,
+    store_profile, utils::LookupError,
+};
+
+/// Pulls the policy, trying the registry mirrors first and falling back to
+/// the upstream URI. The mirrors are tried from the healthiest one, see
+/// [`mirror_health::ordered_candidates`].
+///
+/// When a policy pulled from a mirror is saved into the main store, it's
+/// saved under the path of the upstream URI. This allows later lookups
+/// done with the upstream URI to find it.
+///
+/// The main store is the one of the selected profile, see [`store_profile`].
+/// The policies saved into the main store are deduplicated, see
+/// [`store_dedup`]. When the main store is read-only, the policies found
+/// inside of it are not pulled again, the other ones are pulled into the
+/// overlay, see [`store_mode`]. The provenance of the policies saved into a
+/// store is recorded, see [`provenance`].
 pub(crate) async fn pull(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+    destination: PullDestination,
+) -> Result<Policy> {
+    if !matches!(destination, PullDestination::MainStore) {
+        return pull_candidates(uri, sources, mirrors, destination)
+            .await
+            .map(|(policy, _)| policy);
+    }
+    if store_mode::is_read_only() {
+        if let Some(policy) = store_mode::find_policy(uri)? {
+            return Ok(policy);
+        }
+        let overlay = store_mode::overlay().ok_or_else(|| {
+            anyhow!(
+                "policy {} is missing from the read-only store. Use --store-overlay to pull it into a writable directory",
+                uri
+            )
+        })?;
+        let (policy, source) =
+            pull_candidates(uri, sources, mirrors, PullDestination::Store(overlay.root)).await?;
+        provenance::record(&policy, &source);
+        return Ok(policy);
+    }
+
+    let store = store_profile::store();
+    store_dedup::detach(&store.policy_full_path(uri, PolicyPath::PrefixAndFilename)?)?;
+    let (policy, source) =
+        pull_candidates(uri, sources, mirrors, PullDestination::Store(store.root)).await?;
+    if let Err(e) = store_dedup::dedup(&policy.local_path) {
+        warn!(policy = uri, error = %e, "cannot deduplicate policy");
+    }
+    provenance::record(&policy, &source);
+    Ok(policy)
+}
+
+/// Returns the path of the module of the policy, looking for it inside of the
+/// store, or on disk, and pulling it into the main store when missing
+pub(crate) async fn local_path(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Result<PathBuf> {
+    match crate::utils::wasm_path(uri) {
+        Ok(wasm_path) => Ok(wasm_path),
+        Err(LookupError::PolicyMissing(_)) => {
+            Ok(pull(uri, sources, mirrors, PullDestination::MainStore)
+                .await?
+                .local_path)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Pulls the policy from the first candidate available, returns it together
+/// with the URI it has been pulled from
+async fn pull_candidates(
+    uri: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+    destination: PullDestination,
+) -> Result<(Policy, String)> {
+    let candidates = mirror_health::ordered_candidates(uri, sources, mirrors).await;
+
+    mirror_health::first_successful(&candidates, "pull policy", |candidate| {
+        let destination = candidate_destination(&destination, uri, &candidate);
+        async move {
+            let policy = pull_from(&candidate, sources, destination?).await?;
+            // the policies pulled from a mirror are known by the upstream URI
+            Ok((
+                Policy {
+                    uri: uri.to_string(),
+                    local_path: policy.local_path,
+                },
+                candidate,
+            ))
+        }
+    })
+    .await
+}
+
+// Where the policy pulled from `candidate` is saved: the policies pulled from
+// a mirror are saved at the location of the upstream ones
+fn candidate_destination(
+    destination: &PullDestination,
+    uri: &str,
+    candidate: &str,
+) -> Result<PullDestination> {
+    Ok(match destination {
+        PullDestination::MainStore if candidate == uri => PullDestination::MainStore,
+        PullDestination::MainStore => store_destination(&store_profile::store(), uri)?,
+        PullDestination::Store(root) if candidate == uri => PullDestination::Store(root.clone()),
+        PullDestination::Store(root) => store_destination(&Store::new(root), uri)?,
+        PullDestination::LocalFile(path) => PullDestination::LocalFile(path.clone()),
+    })
+}
+
+// Location inside of the store where the policy identified by `uri` is saved
+fn store_destination(store: &Store, uri: &str) -> Result<PullDestination> {
+    let path = store.policy_full_path(uri, PolicyPath::PrefixAndFilename)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(PullDestination::LocalFile(path))
+}
+
+async fn pull_from(
     uri: &str,
     sources: Option<&Sources>,
     destination: PullDestination,
@@ -1,46 +1,465 @@
-use std::{collections::BTreeMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::{anyhow, Result};
 use policy_evaluator::{
     constants::KUBEWARDEN_ANNOTATION_POLICY_SOURCE,
     policy_fetcher::{
-        oci_client::annotations::ORG_OPENCONTAINERS_IMAGE_SOURCE, registry::Registry,
+        oci_client::{
+            annotations::ORG_OPENCONTAINERS_IMAGE_SOURCE,
+            errors::{OciDistributionError, OciErrorCode},
+            manifest::{
+                OciImageManifest, OciManifest, OCI_IMAGE_MEDIA_TYPE, WASM_LAYER_MEDIA_TYPE,
+            },
+            secrets::RegistryAuth,
+            Client, Reference,
+        },
+        registry::Registry,
+        sigstore::registry::ClientConfig,
         sources::Sources,
     },
     policy_metadata::Metadata,
 };
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use tracing::{debug, warn};
 
-use crate::backend::BackendDetector;
+use crate::{
+    annotate::optimize_annotated, backend::BackendDetector, config::registry_auth::registry_auth,
+    optimize::OptimizeLevel, utils::wasm_layer_digest,
+};
+
+/// OCI manifest annotation holding the whole metadata of the policy, as JSON.
+/// Allows to read the metadata without downloading the Wasm module.
+pub(crate) const KWCTL_ANNOTATION_POLICY_METADATA: &str = "io.kubewarden.policy.metadata";
+
+// How many times the upload of the policy is attempted before giving up
+const PUSH_ATTEMPTS: u32 = 3;
+
+/// Size of the chunks of the policy checksummed on their own, locating the
+/// bytes changed by the registry when the pushed policy differs from the
+/// local one
+const CHECKSUM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A policy ready to be pushed to one or more registries
+pub(crate) struct PolicyUpload {
+    policy: Vec<u8>,
+    annotations: Option<BTreeMap<String, String>>,
+    /// Digest of the Wasm module, prefixed by `sha256:`
+    digest: String,
+}
+
+impl PolicyUpload {
+    pub(crate) fn new(
+        wasm_path: PathBuf,
+        force: bool,
+        extra_annotations: &HashMap<String, String>,
+        optimize_level: Option<OptimizeLevel>,
+    ) -> Result<Self> {
+        let metadata = Metadata::from_path(&wasm_path)?;
+
+        if metadata.is_none() {
+            if force {
+                let backend_detector = BackendDetector::default();
+                if can_be_force_pushed_without_metadata(backend_detector, wasm_path.clone())? {
+                    eprintln!("Warning: pushing a non-annotated policy!");
+                } else {
+                    return Err(anyhow!("Rego policies cannot be pushed without metadata"));
+                }
+            } else {
+                return Err(anyhow!("Cannot push a policy that is not annotated. Use `annotate` command or `push --force`"));
+            }
+        }
+
+        let oci_annotations = match &metadata {
+            Some(metadata) => metadata_annotations(metadata)?,
+            None => BTreeMap::new(),
+        };
+        let annotations = merge_annotations(oci_annotations, extra_annotations)?;
+
+        // the metadata of the optimized policy, hence the annotations, are
+        // the ones of the local policy
+        let policy = match (optimize_level, &metadata) {
+            (None, _) => {
+                fs::read(&wasm_path).map_err(|e| anyhow!("Cannot open policy file: {:?}", e))?
+            }
+            (Some(_), None) => {
+                return Err(anyhow!("Cannot optimize a policy that is not annotated"));
+            }
+            (Some(level), Some(metadata)) => {
+                let (policy, report) = optimize_annotated(&wasm_path, metadata, level)?;
+                eprintln!("{report}");
+                policy
+            }
+        };
+        let digest = format!("sha256:{:x}", Sha256::digest(&policy));
+
+        Ok(Self {
+            policy,
+            annotations,
+            digest,
+        })
+    }
+}
+
+/// What a push would upload, reported instead of pushing when `--dry-run` is set
+#[derive(Serialize)]
+pub(crate) struct PushPlan {
+    /// URI of the policy inside of the store, set when mirroring the store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    uri: String,
+    digest: String,
+    size: usize,
+    annotations: BTreeMap<String, String>,
+    /// Kind of credentials that would be used to authenticate against the registry
+    auth: &'static str,
+}
+
+impl PolicyUpload {
+    /// Describes the push of the policy to `uri`, resolving the registry
+    /// credentials without contacting the registry
+    pub(crate) fn plan(&self, uri: &str) -> Result<PushPlan> {
+        let image = uri.strip_prefix("registry://").unwrap_or(uri);
+        let auth = match registry_auth(image)? {
+            RegistryAuth::Anonymous => "anonymous",
+            RegistryAuth::Basic(_, _) => "basic",
+            RegistryAuth::Bearer(_) => "bearer",
+        };
+
+        Ok(PushPlan {
+            source: None,
+            uri: uri.to_string(),
+            digest: self.digest.clone(),
+            size: self.policy.len(),
+            annotations: self.annotations.clone().unwrap_or_default(),
+            auth,
+        })
+    }
+}
+
+impl std::fmt::Display for PushPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(source) = &self.source {
+            writeln!(f, "{source} -> {}", self.uri)?;
+        } else {
+            writeln!(f, "{}", self.uri)?;
+        }
+        writeln!(f, "  digest: {}", self.digest)?;
+        writeln!(
+            f,
+            "  size: {}",
+            humansize::format_size(self.size, humansize::DECIMAL)
+        )?;
+        writeln!(f, "  auth: {}", self.auth)?;
+        if !self.annotations.is_empty() {
+            writeln!(f, "  annotations:")?;
+            for (key, value) in &self.annotations {
+                writeln!(f, "    {key}: {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of the push of a policy to one of the destinations
+#[derive(Serialize)]
+pub(crate) struct PushedPolicy {
+    /// URI of the policy inside of the store, set when mirroring the store
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    pub(crate) uri: String,
+    pub(crate) immutable_ref: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) referrers: Vec<String>,
+}
 
+/// Pushes the policy to `uri`, returning the immutable reference of the
+/// pushed policy
 pub(crate) async fn push(
-    wasm_path: PathBuf,
+    upload: &PolicyUpload,
     uri: &str,
     sources: Option<&Sources>,
-    force: bool,
 ) -> Result<String> {
-    let metadata = Metadata::from_path(&wasm_path)?;
+    let registry = Registry::new();
+    let mut attempt = 1;
+    let immutable_ref = loop {
+        match registry
+            .push(&upload.policy, uri, sources, upload.annotations.clone())
+            .await
+        {
+            Ok(immutable_ref) => break immutable_ref,
+            Err(e) => {
+                let e = anyhow::Error::new(e);
+                if attempt >= PUSH_ATTEMPTS || !is_transient(&e) {
+                    return Err(e);
+                }
+                warn!(attempt, error = %e, "cannot push policy, retrying");
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+                attempt += 1;
+            }
+        }
+    };
 
-    if metadata.is_none() {
-        if force {
-            let backend_detector = BackendDetector::default();
-            if can_be_force_pushed_without_metadata(backend_detector, wasm_path.clone())? {
-                eprintln!("Warning: pushing a non-annotated policy!");
-            } else {
-                return Err(anyhow!("Rego policies cannot be pushed without metadata"));
+    verify_pushed_policy(upload, &immutable_ref, sources).await?;
+
+    Ok(immutable_ref)
+}
+
+/// Whether the push failed because of an error that can go away by itself:
+/// server errors, rate limiting, timeouts and dropped connections. Pushing
+/// again after the other errors, like authentication ones, fails the same way.
+fn is_transient(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<OciDistributionError>() {
+            return matches!(
+                e,
+                OciDistributionError::ServerError { code, .. } if *code == 429 || *code >= 500
+            );
+        }
+        if let Some(e) = e.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|status| status.is_server_error());
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::UnexpectedEof
+            );
+        }
+        false
+    })
+}
+
+/// Returns the URI, under the `prefix` registry location, of the policy pulled
+/// from `uri`. The repository path and the tag of the policy are preserved.
+///
+/// `None` is returned for the policies that have not been pulled from a
+/// registry by tag.
+pub(crate) fn store_mirror_uri(uri: &str, prefix: &str) -> Option<String> {
+    let image = uri.strip_prefix("registry://")?;
+    let reference = Reference::from_str(image).ok()?;
+    let tag = reference.tag()?;
+    let prefix = prefix.strip_prefix("registry://").unwrap_or(prefix);
+
+    Some(format!(
+        "registry://{}/{}:{}",
+        prefix.trim_end_matches('/'),
+        reference.repository(),
+        tag
+    ))
+}
+
+/// Refuses to push when `uri` already references a different policy, which
+/// prevents released policy versions from being re-tagged by mistake.
+///
+/// Pushing the same policy again is allowed, making the operation idempotent.
+pub(crate) async fn ensure_tag_not_taken(
+    upload: &PolicyUpload,
+    uri: &str,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let manifest = match Registry::new().manifest(uri, sources).await {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            if is_manifest_unknown(&e) {
+                debug!(uri, "the tag doesn't exist yet");
+                return Ok(());
             }
-        } else {
-            return Err(anyhow!("Cannot push a policy that is not annotated. Use `annotate` command or `push --force`"));
+            return Err(anyhow!(
+                "cannot check whether {} already exists: {}",
+                uri,
+                e
+            ));
+        }
+    };
+
+    let remote_digest = match &manifest {
+        OciManifest::Image(manifest) => wasm_layer_digest(manifest),
+        _ => None,
+    };
+    check_tag_not_taken(uri, remote_digest, &upload.digest)
+}
+
+/// Whether the registry reported the manifest as missing, either via a 404
+/// status or via a MANIFEST_UNKNOWN (or NAME_UNKNOWN, for new repositories)
+/// error. The other errors, like authentication ones, are not about the tag.
+pub(crate) fn is_manifest_unknown(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<OciDistributionError>())
+        .any(|e| match e {
+            OciDistributionError::ImageManifestNotFoundError(_) => true,
+            OciDistributionError::ServerError { code, .. } => *code == 404,
+            OciDistributionError::RegistryError { envelope, .. } => {
+                envelope.errors.iter().any(|e| {
+                    matches!(
+                        e.code,
+                        OciErrorCode::ManifestUnknown | OciErrorCode::NameUnknown
+                    )
+                })
+            }
+            _ => false,
+        })
+}
+
+fn check_tag_not_taken(uri: &str, remote_digest: Option<&str>, local_digest: &str) -> Result<()> {
+    match remote_digest {
+        Some(digest) if digest == local_digest => {
+            debug!(uri, "the tag already references the policy being pushed");
+            Ok(())
         }
+        Some(digest) => Err(anyhow!(
+            "{} already exists and references a different policy ({}), refusing to overwrite it",
+            uri,
+            digest
+        )),
+        None => Err(anyhow!(
+            "{} already exists and doesn't reference a policy, refusing to overwrite it",
+            uri
+        )),
     }
+}
+
+/// Fetches back the pushed policy by the immutable reference returned by the
+/// push, ensuring the registry stored the manifest and the policy as they were
+/// uploaded. Some proxies recompress the layers, which silently breaks the
+/// signatures of the policy.
+async fn verify_pushed_policy(
+    upload: &PolicyUpload,
+    immutable_ref: &str,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let image = immutable_ref
+        .strip_prefix("registry://")
+        .unwrap_or(immutable_ref);
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let pushed_manifest_digest = reference
+        .digest()
+        .ok_or_else(|| anyhow!("{} is not an immutable reference", immutable_ref))?;
+    let auth = registry_auth(image)?;
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let client = Client::new(client_config.into());
+
+    let (manifest, _) = client
+        .pull_manifest_raw(&reference, &auth, &[OCI_IMAGE_MEDIA_TYPE])
+        .await?;
+    let manifest_digest = format!("sha256:{:x}", Sha256::digest(&manifest));
+    if manifest_digest != pushed_manifest_digest {
+        return Err(anyhow!(
+            "integrity check failed: the registry serves manifest {} for {}",
+            manifest_digest,
+            immutable_ref
+        ));
+    }
+    let manifest: OciImageManifest = serde_json::from_slice(&manifest).map_err(|e| {
+        anyhow!(
+            "integrity check failed: {} doesn't reference an OCI image manifest: {}",
+            immutable_ref,
+            e
+        )
+    })?;
+    check_wasm_layer_digest(wasm_layer_digest(&manifest), &upload.digest)
+        .map_err(|e| anyhow!("integrity check of {} failed: {}", immutable_ref, e))?;
+
+    let layer = manifest
+        .layers
+        .iter()
+        .find(|layer| layer.media_type == WASM_LAYER_MEDIA_TYPE)
+        .expect("the Wasm layer has just been checked");
+    let mut pushed_policy = Vec::new();
+    client
+        .pull_blob(&reference, layer, &mut pushed_policy)
+        .await?;
+    check_chunks(&upload.policy, &pushed_policy)
+        .map_err(|e| anyhow!("integrity check of {} failed: {}", immutable_ref, e))?;
+
+    debug!(immutable_ref, "integrity of the pushed policy verified");
+    Ok(())
+}
+
+/// The SHA-256 of every chunk of the data
+fn chunk_checksums(data: &[u8]) -> Vec<String> {
+    data.chunks(CHECKSUM_CHUNK_SIZE)
+        .map(|chunk| format!("{:x}", Sha256::digest(chunk)))
+        .collect()
+}
+
+/// Compares the policy served by the registry with the local one chunk by
+/// chunk, reporting the first chunk that differs
+fn check_chunks(local: &[u8], remote: &[u8]) -> Result<()> {
+    if local.len() != remote.len() {
+        return Err(anyhow!(
+            "the registry serves a policy of {} bytes, {} bytes were pushed",
+            remote.len(),
+            local.len()
+        ));
+    }
+    let corrupted = chunk_checksums(local)
+        .into_iter()
+        .zip(chunk_checksums(remote))
+        .position(|(local, remote)| local != remote);
+    match corrupted {
+        Some(chunk) => {
+            let start = chunk * CHECKSUM_CHUNK_SIZE;
+            let end = (start + CHECKSUM_CHUNK_SIZE).min(local.len());
+            Err(anyhow!(
+                "the bytes {}-{} of the policy served by the registry differ from the pushed ones",
+                start,
+                end - 1
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+fn check_wasm_layer_digest(remote_digest: Option<&str>, local_digest: &str) -> Result<()> {
+    match remote_digest {
+        Some(digest) if digest == local_digest => Ok(()),
+        Some(digest) => Err(anyhow!(
+            "the registry stores the policy with digest {}, expected {}. The policy has been modified after the upload",
+            digest,
+            local_digest
+        )),
+        None => Err(anyhow!("the OCI manifest has no Wasm layer")),
+    }
+}
 
-    let annotations = metadata.and_then(|meta| meta.annotations.map(build_oci_annotations));
+/// Adds the annotations provided by the user to the ones coming from the
+/// policy metadata. The annotations provided by the user take precedence.
+fn merge_annotations(
+    mut annotations: BTreeMap<String, String>,
+    extra_annotations: &HashMap<String, String>,
+) -> Result<Option<BTreeMap<String, String>>> {
+    for (key, value) in extra_annotations {
+        if value.lines().count() > 1 {
+            return Err(anyhow!(
+                "annotation {} is a multi-line string, which is not supported by the OCI specification",
+                key
+            ));
+        }
+        if let Some(previous) = annotations.insert(key.to_owned(), value.to_owned()) {
+            debug!(
+                annotation = key,
+                previous, "annotation from the policy metadata overridden"
+            );
+        }
+    }
 
-    let policy = fs::read(&wasm_path).map_err(|e| anyhow!("Cannot open policy file: {:?}", e))?;
-    Registry::new()
-        .push(&policy, uri, sources, annotations)
-        .await
-        .map_err(anyhow::Error::new)
+    Ok((!annotations.is_empty()).then_some(annotations))
 }
 
 fn can_be_force_pushed_without_metadata(
@@ -56,6 +475,21 @@ fn can_be_force_pushed_without_metadata(
 
 /// Augment the annotations with the `org.opencontainers.image.source`
 /// annotation, if the `io.kubewarden.policy.source` annotation is present.
+/// Annotations of the OCI manifest of a policy: the single-line annotations
+/// of its metadata, and the whole metadata as JSON
+pub(crate) fn metadata_annotations(metadata: &Metadata) -> Result<BTreeMap<String, String>> {
+    let mut annotations = metadata
+        .annotations
+        .clone()
+        .map(build_oci_annotations)
+        .unwrap_or_default();
+    annotations.insert(
+        KWCTL_ANNOTATION_POLICY_METADATA.to_string(),
+        serde_json::to_string(metadata)?,
+    );
+    Ok(annotations)
+}
+
 fn build_oci_annotations(annotations: BTreeMap<String, String>) -> BTreeMap<String, String> {
     // filter all the multi-line annotations, they are not supported by the OCI spec
     let mut annotations: BTreeMap<String, String> = annotations
@@ -94,6 +528,7 @@ mod tests {
         KUBEWARDEN_ANNOTATION_POLICY_DESCRIPTION, KUBEWARDEN_ANNOTATION_POLICY_URL,
         KUBEWARDEN_ANNOTATION_POLICY_USAGE,
     };
+    use rstest::rstest;
 
     #[test]
     fn test_build_oci_annotations_propagate_policy_source() {
@@ -171,4 +606,205 @@ mod tests {
             policy_source
         );
     }
+
+    #[test]
+    fn test_merge_annotations() {
+        let annotations = BTreeMap::from([
+            (
+                KUBEWARDEN_ANNOTATION_POLICY_SOURCE.to_string(),
+                "https://github.com/kubewarden/policy".to_string(),
+            ),
+            (
+                ORG_OPENCONTAINERS_IMAGE_SOURCE.to_string(),
+                "https://github.com/kubewarden/policy".to_string(),
+            ),
+        ]);
+        let extra_annotations = HashMap::from([
+            (
+                ORG_OPENCONTAINERS_IMAGE_SOURCE.to_string(),
+                "https://github.com/acme/policy".to_string(),
+            ),
+            (
+                "org.opencontainers.image.revision".to_string(),
+                "7e5b2c1".to_string(),
+            ),
+        ]);
+
+        let actual = merge_annotations(annotations, &extra_annotations)
+            .unwrap()
+            .unwrap();
+        assert_eq!(actual.len(), 3);
+        assert_eq!(
+            actual.get(ORG_OPENCONTAINERS_IMAGE_SOURCE).unwrap(),
+            "https://github.com/acme/policy"
+        );
+        assert_eq!(
+            actual.get("org.opencontainers.image.revision").unwrap(),
+            "7e5b2c1"
+        );
+
+        assert!(merge_annotations(BTreeMap::new(), &HashMap::new())
+            .unwrap()
+            .is_none());
+        assert!(merge_annotations(
+            BTreeMap::new(),
+            &HashMap::from([("license".to_string(), "Apache-2.0\nMIT".to_string())])
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_push_plan_display() {
+        let plan = PushPlan {
+            source: Some("registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13".to_string()),
+            uri: "registry://internal.example.com/kubewarden/tests/safe-labels:v0.1.13".to_string(),
+            digest: "sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c"
+                .to_string(),
+            size: 1_500_000,
+            annotations: BTreeMap::from([(
+                KUBEWARDEN_ANNOTATION_POLICY_URL.to_string(),
+                "https://github.com/kubewarden/safe-labels-policy".to_string(),
+            )]),
+            auth: "anonymous",
+        };
+
+        assert_eq!(
+            plan.to_string(),
+            "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13 -> registry://internal.example.com/kubewarden/tests/safe-labels:v0.1.13
+  digest: sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c
+  size: 1.50 MB
+  auth: anonymous
+  annotations:
+    io.kubewarden.policy.url: https://github.com/kubewarden/safe-labels-policy
+"
+        );
+    }
+
+    #[rstest]
+    #[case::tag(
+        "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+        Some("registry://internal.example.com/kubewarden/kubewarden/tests/safe-labels:v0.1.13")
+    )]
+    #[case::port(
+        "registry://localhost:5000/safe-labels:v0.1.13",
+        Some("registry://internal.example.com/kubewarden/safe-labels:v0.1.13")
+    )]
+    #[case::digest(
+        "registry://ghcr.io/kubewarden/tests/safe-labels@sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c",
+        None
+    )]
+    #[case::https("https://example.com/policy.wasm", None)]
+    fn test_store_mirror_uri(#[case] uri: &str, #[case] expected: Option<&str>) {
+        for prefix in [
+            "registry://internal.example.com/kubewarden/",
+            "internal.example.com/kubewarden",
+        ] {
+            assert_eq!(store_mirror_uri(uri, prefix).as_deref(), expected);
+        }
+    }
+
+    #[test]
+    fn test_check_tag_not_taken() {
+        let uri = "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13";
+        let local_digest =
+            "sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c";
+
+        assert!(check_tag_not_taken(uri, Some(local_digest), local_digest).is_ok());
+        assert!(check_tag_not_taken(
+            uri,
+            Some("sha256:0000000000000000000000000000000000000000000000000000000000000000"),
+            local_digest
+        )
+        .is_err());
+        assert!(check_tag_not_taken(uri, None, local_digest).is_err());
+    }
+
+    fn registry_error(code: &str) -> OciDistributionError {
+        OciDistributionError::RegistryError {
+            envelope: serde_json::from_value(serde_json::json!({
+                "errors": [{"code": code, "message": "registry error"}]
+            }))
+            .unwrap(),
+            url: "https://ghcr.io/v2/kubewarden/tests/safe-labels/manifests/v0.1.13".to_string(),
+        }
+    }
+
+    fn server_error(code: u16) -> OciDistributionError {
+        OciDistributionError::ServerError {
+            code,
+            url: "https://ghcr.io/v2/kubewarden/tests/safe-labels/manifests/v0.1.13".to_string(),
+            message: "server error".to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case::manifest_unknown(registry_error("MANIFEST_UNKNOWN"), true)]
+    #[case::name_unknown(registry_error("NAME_UNKNOWN"), true)]
+    #[case::not_found(server_error(404), true)]
+    #[case::denied(registry_error("DENIED"), false)]
+    #[case::unauthorized(server_error(401), false)]
+    #[case::server_failure(server_error(503), false)]
+    #[case::authentication(
+        OciDistributionError::AuthenticationFailure("invalid credentials".to_string()),
+        false
+    )]
+    fn test_is_manifest_unknown(#[case] error: OciDistributionError, #[case] unknown: bool) {
+        let error = anyhow::Error::from(error).context("cannot fetch the manifest");
+        assert_eq!(is_manifest_unknown(&error), unknown);
+    }
+
+    #[rstest]
+    #[case::server_failure(server_error(503).into(), true)]
+    #[case::rate_limited(server_error(429).into(), true)]
+    #[case::connection_reset(std::io::Error::from(ErrorKind::ConnectionReset).into(), true)]
+    #[case::timeout(std::io::Error::from(ErrorKind::TimedOut).into(), true)]
+    #[case::unauthorized(server_error(401).into(), false)]
+    #[case::denied(registry_error("DENIED").into(), false)]
+    #[case::authentication(
+        OciDistributionError::AuthenticationFailure("invalid credentials".to_string()).into(),
+        false
+    )]
+    fn test_is_transient(#[case] error: anyhow::Error, #[case] transient: bool) {
+        let error = error.context("cannot push the policy");
+        assert_eq!(is_transient(&error), transient);
+    }
+
+    #[rstest]
+    #[case::identical(vec![1; CHECKSUM_CHUNK_SIZE + 10], None)]
+    #[case::changed_chunk(
+        {
+            let mut remote = vec![1; CHECKSUM_CHUNK_SIZE + 10];
+            remote[CHECKSUM_CHUNK_SIZE + 3] = 0;
+            remote
+        },
+        Some("the bytes 1048576-1048585 of the policy served by the registry differ from the pushed ones")
+    )]
+    #[case::truncated(
+        vec![1; CHECKSUM_CHUNK_SIZE],
+        Some("the registry serves a policy of 1048576 bytes, 1048586 bytes were pushed")
+    )]
+    fn test_check_chunks(#[case] remote: Vec<u8>, #[case] error: Option<&str>) {
+        let local = vec![1; CHECKSUM_CHUNK_SIZE + 10];
+        assert_eq!(
+            check_chunks(&local, &remote)
+                .map_err(|e| e.to_string())
+                .err()
+                .as_deref(),
+            error
+        );
+    }
+
+    #[test]
+    fn test_check_wasm_layer_digest() {
+        let local_digest =
+            "sha256:61ef63621fa5be8e422881d96d05edfef810992fbf9468e35d1fa5ae815bd97c";
+
+        assert!(check_wasm_layer_digest(Some(local_digest), local_digest).is_ok());
+        assert!(check_wasm_layer_digest(
+            Some("sha256:0000000000000000000000000000000000000000000000000000000000000000"),
+            local_digest
+        )
+        .is_err());
+        assert!(check_wasm_layer_digest(None, local_digest).is_err());
+    }
 }
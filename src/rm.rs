@@ -1,13 +1,135 @@
 use anyhow::{anyhow, Result};
-use policy_evaluator::policy_fetcher::store::{PolicyPath, Store};
-use std::path::PathBuf;
+use policy_evaluator::policy_fetcher::store::PolicyPath;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+use tracing::warn;
 
 use crate::utils::LookupError;
 
+/// How the policies selected by `kwctl rm` are removed
+#[derive(Debug, Default)]
+pub(crate) struct RmOptions {
+    /// Select all the policies of the store
+    pub(crate) all: bool,
+    /// Report the policies that would be removed, without removing them
+    pub(crate) dry_run: bool,
+    /// Ask for confirmation before removing each policy
+    pub(crate) interactive: bool,
+}
+
+/// Outcome of the removal of many policies
+pub(crate) struct RmReport {
+    /// URI and size of the removed policies
+    pub(crate) removed: Vec<(String, u64)>,
+    /// Space freed, the modules shared with the policies that are kept are
+    /// not freed
+    pub(crate) reclaimed: u64,
+}
+
+fn is_glob(target: &str) -> bool {
+    target.contains(['*', '?'])
+}
+
+/// Whether the URI matches the whole glob pattern: `*` matches any sequence
+/// of characters, `?` any single character
+fn glob_matches(pattern: &str, uri: &str) -> bool {
+    let pattern: String = pattern
+        .chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect();
+    Regex::new(&format!("^{pattern}$")).is_ok_and(|pattern| pattern.is_match(uri))
+}
+
+/// Asks on the terminal whether the policy must be removed, anything but
+/// `y` or `yes` keeps it
+fn confirm(uri: &str, size: u64) -> Result<bool> {
+    eprint!(
+        "Remove {} ({})? [y/N] ",
+        uri,
+        humansize::format_size(size, humansize::DECIMAL)
+    );
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Removes the policies matching the targets, which are URIs, SHA prefixes
+/// or glob patterns matched against the URIs. All the policies of the store
+/// are selected when `options.all` is set.
+///
+/// Missing policies given by URI or SHA prefix are reported as errors before
+/// removing anything, while patterns matching no policy are only warned
+/// about.
+pub(crate) fn rm_many(targets: &[String], options: &RmOptions) -> Result<RmReport> {
+    let store = crate::store_profile::store();
+    let policies = store.list()?;
+
+    let mut selected: BTreeMap<String, PathBuf> = BTreeMap::new();
+    if options.all {
+        selected.extend(
+            policies
+                .into_iter()
+                .map(|policy| (policy.uri, policy.local_path)),
+        );
+    } else {
+        for target in targets {
+            if is_glob(target) {
+                let matching: Vec<_> = policies
+                    .iter()
+                    .filter(|policy| glob_matches(target, &policy.uri))
+                    .collect();
+                if matching.is_empty() {
+                    warn!(pattern = target.as_str(), "no policy matches the pattern");
+                }
+                selected.extend(
+                    matching
+                        .into_iter()
+                        .map(|policy| (policy.uri.clone(), policy.local_path.clone())),
+                );
+                continue;
+            }
+            let uri = crate::utils::get_uri(target)?;
+            let policy = store
+                .get_policy_by_uri(&uri)?
+                .ok_or_else(|| anyhow!(LookupError::PolicyMissing(uri)))?;
+            selected.insert(policy.uri, policy.local_path);
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut paths = Vec::new();
+    for (uri, path) in selected {
+        let size = fs::metadata(&path)?.len();
+        if options.interactive && !confirm(&uri, size)? {
+            continue;
+        }
+        removed.push((uri, size));
+        paths.push(path);
+    }
+
+    let reclaimed = crate::store_dedup::reclaimable(&paths)?;
+    if !options.dry_run {
+        for (uri, _) in &removed {
+            rm(uri)?;
+        }
+    }
+    Ok(RmReport { removed, reclaimed })
+}
+
 pub(crate) fn rm(uri_or_sha_prefix: &str) -> Result<()> {
     let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_string())?;
 
-    let store = Store::default();
+    let store = crate::store_profile::store();
 
     if store.get_policy_by_uri(&uri)?.is_none() {
         return Err(anyhow!(LookupError::PolicyMissing(uri)));
@@ -16,6 +138,7 @@ pub(crate) fn rm(uri_or_sha_prefix: &str) -> Result<()> {
     let policy_path = store.policy_full_path(&uri, PolicyPath::PrefixAndFilename)?;
     std::fs::remove_file(&policy_path)
         .map_err(|err| anyhow!("could not delete policy {}: {}", uri, err))?;
+    crate::provenance::forget([&uri]);
 
     // Given a policy in the store, try to cleanup all intermediate
     // directories up to the store root, from the innermost to the
@@ -48,3 +171,36 @@ pub(crate) fn rm(uri_or_sha_prefix: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::family(
+        "registry://ghcr.io/kubewarden/*:v1.*",
+        "registry://ghcr.io/kubewarden/policies/psp:v1.2.0",
+        true
+    )]
+    #[case::other_major(
+        "registry://ghcr.io/kubewarden/*:v1.*",
+        "registry://ghcr.io/kubewarden/policies/psp:v2.0.0",
+        false
+    )]
+    #[case::single_char(
+        "registry://ghcr.io/kubewarden/policies/psp:v1.?.0",
+        "registry://ghcr.io/kubewarden/policies/psp:v1.2.0",
+        true
+    )]
+    #[case::anchored(
+        "registry://ghcr.io/kubewarden/*",
+        "https://example.com/registry://ghcr.io/kubewarden/psp.wasm",
+        false
+    )]
+    #[case::any_char("registry://ghcr?io/*", "registry://ghcr.io/kubewarden/psp:v1", true)]
+    #[case::literal_dot("registry://ghcr.io/*", "registry://ghcrxio/kubewarden/psp:v1", false)]
+    fn glob_matching(#[case] pattern: &str, #[case] uri: &str, #[case] expected: bool) {
+        assert_eq!(glob_matches(pattern, uri), expected);
+    }
+}
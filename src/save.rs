@@ -1,11 +1,11 @@
 use anyhow::{anyhow, Result};
 use flate2::write::GzEncoder;
 use flate2::Compression;
-use policy_evaluator::policy_fetcher::store::{PolicyPath, Store};
+use policy_evaluator::policy_fetcher::store::PolicyPath;
 use std::fs::File;
 
 // saves all policies in a tarball with the name provided as output.
-// policies must be inside the default store.
+// policies must be inside the store.
 pub(crate) fn save(policies: Vec<&String>, output: &str) -> Result<()> {
     let tar_gz =
         File::create(output).map_err(|e| anyhow!("cannot create file {}: {}", output, e))?;
@@ -13,7 +13,7 @@ pub(crate) fn save(policies: Vec<&String>, output: &str) -> Result<()> {
     let mut tar = tar::Builder::new(enc);
 
     for policy in policies {
-        let store = Store::default();
+        let store = crate::store_profile::store();
         let uri = crate::utils::map_path_to_uri(policy.as_str())?;
         let wasm_path = crate::utils::wasm_path(&uri)
             .map_err(|e| anyhow!("cannot find policy {}: {}", policy, e))?;
@@ -1,7 +1,15 @@
-mod kubewarden_crds;
+pub(crate) mod kubewarden_crds;
+
+pub(crate) mod label_selector;
+
+mod manifest_output;
+pub(crate) use manifest_output::ManifestOutput;
 
 mod manifest;
-pub(crate) use manifest::manifest;
+pub(crate) use manifest::{
+    batch_manifest, group_manifest, group_member, local_metadata, manifest, read_requirements,
+    remote_metadata, GroupMember, ManifestOptions, ManifestType,
+};
 
 mod vap;
 pub(crate) use vap::vap;
@@ -12,6 +20,9 @@ pub(crate) use verification_config::verification_config;
 mod artifacthub;
 pub(crate) use artifacthub::artifacthub;
 
+mod policy;
+pub(crate) use policy::{policy, Language as PolicyLanguage};
+
 mod admission_request;
 pub(crate) use admission_request::Operation as AdmissionRequestOperation;
-pub(crate) use admission_request::{admission_request, DEFAULT_KWCTL_CACHE};
+pub(crate) use admission_request::{admission_request, LiveObject};
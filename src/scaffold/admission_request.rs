@@ -8,26 +8,23 @@ use std::{
 };
 
 use anyhow::{anyhow, Result};
-use directories::ProjectDirs;
 use k8s_openapi::{
     api::authentication::v1::UserInfo,
     apimachinery::pkg::{apis::meta::v1::APIResource, runtime::RawExtension},
 };
 use kube::api::DynamicObject;
-use lazy_static::lazy_static;
 use policy_evaluator::admission_request::{
     AdmissionRequest, GroupVersionKind, GroupVersionResource,
 };
 use policy_evaluator::kube;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::{debug, info, warn};
 
-lazy_static! {
-    pub static ref DEFAULT_ROOT: ProjectDirs =
-        ProjectDirs::from("io.kubewarden", "", "kubewarden").unwrap();
-    pub static ref DEFAULT_KWCTL_CACHE: PathBuf = DEFAULT_ROOT.cache_dir().join("kwctl");
-    pub static ref RESOURCE_CATALOG_FILE: PathBuf =
-        DEFAULT_KWCTL_CACHE.join("resource_catalog.json");
+/// File caching the API resources of the cluster, inside of the cache directory
+/// of the selected store
+fn resource_catalog_file() -> PathBuf {
+    crate::store_profile::kwctl_cache_dir().join("resource_catalog.json")
 }
 
 const FALLBACK_API_RESOURCE_PLURAL_NAME: &str = "this-is-the-plural-name-of-the-resource-this-information-is-not-used-by-policies-and-requires-a-connection-to-an-api-server-to-be-obtained";
@@ -62,6 +59,77 @@ impl Display for Operation {
     }
 }
 
+impl Operation {
+    /// The options sent by kubectl together with the request
+    fn options(&self) -> serde_json::Value {
+        match self {
+            Operation::Create => serde_json::json!({
+                "apiVersion": "meta.k8s.io/v1",
+                "kind": "CreateOptions",
+                "fieldManager": "kubectl-create",
+            }),
+            Operation::Update => serde_json::json!({
+                "apiVersion": "meta.k8s.io/v1",
+                "kind": "UpdateOptions",
+                "fieldManager": "kubectl-edit",
+            }),
+            Operation::Delete => serde_json::json!({
+                "apiVersion": "meta.k8s.io/v1",
+                "kind": "DeleteOptions",
+                "propagationPolicy": "Background",
+            }),
+        }
+    }
+}
+
+/// A random version 4 UUID, like the ones assigned by the API server to the
+/// requests
+fn request_uid(object: &serde_json::Value) -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = Sha256::new();
+    hasher.update(object.to_string());
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    let mut bytes: [u8; 16] = hasher.finalize()[..16].try_into().unwrap();
+    // version 4, variant RFC 4122
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// An object of the cluster, referenced like `deployment/web`
+pub(crate) struct LiveObject {
+    kind: String,
+    name: String,
+    /// The default namespace of the kubeconfig is used when missing
+    namespace: Option<String>,
+}
+
+impl LiveObject {
+    pub(crate) fn parse(reference: &str, namespace: Option<String>) -> Result<Self> {
+        let (kind, name) = reference
+            .split_once('/')
+            .filter(|(kind, name)| !kind.is_empty() && !name.is_empty() && !name.contains('/'))
+            .ok_or_else(|| anyhow!("invalid object '{}', expected KIND/NAME", reference))?;
+        Ok(LiveObject {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            namespace,
+        })
+    }
+}
+
 #[derive(Debug, Default)]
 enum ApiResourceCatalogRestoredFrom {
     #[default]
@@ -207,6 +275,43 @@ impl ApiResourceCatalog {
         self.resources.get(&Self::gvk_to_string(gvk))
     }
 
+    /// Finds a resource the way kubectl does: by kind, plural name, singular
+    /// name or short name, ignoring the case. The name can be qualified by the
+    /// group, like `deployments.apps`. The resources of the core group win
+    /// over the ones of the other groups.
+    fn find(&self, name: &str) -> Option<(kube::api::GroupVersionKind, APIResource)> {
+        let name = name.to_lowercase();
+        let (name, group) = match name.split_once('.') {
+            Some((name, group)) => (name.to_string(), Some(group.to_string())),
+            None => (name, None),
+        };
+        self.resources
+            .iter()
+            .filter_map(|(key, resource)| {
+                let mut parts = key.splitn(3, '|');
+                let gvk = kube::api::GroupVersionKind {
+                    group: parts.next()?.to_string(),
+                    version: parts.next()?.to_string(),
+                    kind: parts.next()?.to_string(),
+                };
+                Some((gvk, resource))
+            })
+            .filter(|(gvk, resource)| {
+                group.as_ref().is_none_or(|group| *group == gvk.group)
+                    && (resource.kind.to_lowercase() == name
+                        || resource.name == name
+                        || resource.singular_name == name
+                        || resource
+                            .short_names
+                            .as_ref()
+                            .is_some_and(|short_names| short_names.contains(&name)))
+            })
+            .min_by(|(a, _), (b, _)| {
+                (!a.group.is_empty(), &a.group).cmp(&(!b.group.is_empty(), &b.group))
+            })
+            .map(|(gvk, resource)| (gvk, resource.clone()))
+    }
+
     /// Refresh the catalog by querying the Kubernetes API server.
     /// This applies only if the catalog was built from the cache.
     pub async fn refresh<F, Fut>(&mut self, build_kubeclient_fn: F) -> Result<()>
@@ -256,20 +361,44 @@ pub(crate) async fn admission_request(
     operation: Operation,
     object: Option<PathBuf>,
     old_object: Option<PathBuf>,
+    live_object: Option<LiveObject>,
 ) -> Result<()> {
+    if let Some(live_object) = live_object {
+        validate_live_params(&operation, object.as_ref(), old_object.as_ref())?;
+        let output = scaffold_from_cluster(
+            resource_catalog_file(),
+            build_kube_client,
+            operation,
+            live_object,
+            object,
+        )
+        .await?;
+        println!("{}", output);
+        return Ok(());
+    }
     validate_params(&operation, object.as_ref(), old_object.as_ref())?;
 
     let output = match operation {
         Operation::Create => {
-            scaffold_create(
-                RESOURCE_CATALOG_FILE.to_path_buf(),
+            scaffold_create(resource_catalog_file(), build_kube_client, object.unwrap()).await?
+        }
+        Operation::Update => {
+            scaffold_update(
+                resource_catalog_file(),
                 build_kube_client,
                 object.unwrap(),
+                old_object.unwrap(),
+            )
+            .await?
+        }
+        Operation::Delete => {
+            scaffold_delete(
+                resource_catalog_file(),
+                build_kube_client,
+                object.or(old_object).unwrap(),
             )
             .await?
         }
-        Operation::Update => todo!(),
-        Operation::Delete => todo!(),
     };
 
     println!("{}", output);
@@ -298,32 +427,211 @@ fn validate_params(
                 anyhow::bail!("UPDATE operation requires an old_object");
             }
         }
-        Operation::Delete => {
-            if object_path.is_some() {
-                anyhow::bail!("DELETE operation does not require an object");
+        // the object being deleted can be given either as object or as
+        // old_object
+        Operation::Delete => match (object_path, old_object_path) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("DELETE operation requires either an object or an old_object")
             }
-            if old_object_path.is_none() {
-                anyhow::bail!("DELETE operation requires an old_object");
-            }
-        }
+            (None, None) => anyhow::bail!("DELETE operation requires the object being deleted"),
+            _ => {}
+        },
     }
 
     Ok(())
 }
 
+/// The object fetched from the cluster is the object of CREATE requests, and
+/// the old object of UPDATE and DELETE requests. UPDATE requests take the
+/// new object from a file.
+fn validate_live_params(
+    operation: &Operation,
+    object_path: Option<&PathBuf>,
+    old_object_path: Option<&PathBuf>,
+) -> Result<()> {
+    if old_object_path.is_some() {
+        anyhow::bail!("the old_object is fetched from the cluster");
+    }
+    match operation {
+        Operation::Update if object_path.is_none() => {
+            anyhow::bail!("UPDATE operation requires an object")
+        }
+        Operation::Create | Operation::Delete if object_path.is_some() => {
+            anyhow::bail!("{} operation does not require an object", operation)
+        }
+        _ => Ok(()),
+    }
+}
+
 async fn scaffold_create<F, Fut>(
     resource_catalog_file: PathBuf,
     kube_client: F,
     object_path: PathBuf,
 ) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let object = read_object(&object_path)?;
+
+    build_admission_request(
+        resource_catalog_file,
+        kube_client,
+        Operation::Create,
+        object,
+        None,
+    )
+    .await
+}
+
+async fn scaffold_update<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    object_path: PathBuf,
+    old_object_path: PathBuf,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let object = read_object(&object_path)?;
+    let old_object = read_object(&old_object_path)?;
+    ensure_same_object(
+        &object,
+        &old_object,
+        &object_path.to_string_lossy(),
+        &old_object_path.to_string_lossy(),
+    )?;
+
+    build_admission_request(
+        resource_catalog_file,
+        kube_client,
+        Operation::Update,
+        object,
+        Some(old_object),
+    )
+    .await
+}
+
+async fn scaffold_delete<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    object_path: PathBuf,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let object = read_object(&object_path)?;
+
+    build_admission_request(
+        resource_catalog_file,
+        kube_client,
+        Operation::Delete,
+        object,
+        None,
+    )
+    .await
+}
+
+/// The objects of UPDATE requests are two revisions of the same object
+fn ensure_same_object(
+    object: &DynamicObject,
+    old_object: &DynamicObject,
+    object_source: &str,
+    old_object_source: &str,
+) -> Result<()> {
+    if object.types != old_object.types {
+        return Err(anyhow!(
+            "objects defined inside of {} and {} have different types: {:?} and {:?}",
+            object_source,
+            old_object_source,
+            object.types,
+            old_object.types
+        ));
+    }
+    if object.metadata.name != old_object.metadata.name
+        || object.metadata.namespace != old_object.metadata.namespace
+    {
+        return Err(anyhow!(
+            "objects defined inside of {} and {} must have the same name and namespace",
+            object_source,
+            old_object_source,
+        ));
+    }
+    Ok(())
+}
+
+async fn scaffold_from_cluster<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    operation: Operation,
+    live_object: LiveObject,
+    object_path: Option<PathBuf>,
+) -> Result<String>
 where
     F: FnOnce() -> Fut + Clone,
     Fut: Future<Output = Result<kube::Client>>,
 {
     let mut resource_catalog =
-        ApiResourceCatalog::new(resource_catalog_file, kube_client.clone()).await;
+        ApiResourceCatalog::new(resource_catalog_file.clone(), kube_client.clone()).await;
+    let found = match resource_catalog.find(&live_object.kind) {
+        Some(found) => Some(found),
+        None => {
+            // Try to refresh the catalog and lookup again
+            resource_catalog.refresh(kube_client.clone()).await?;
+            if let Err(err) = resource_catalog.save(resource_catalog_file.clone()) {
+                warn!(?err, "Failed to save resource catalog");
+            }
+            resource_catalog.find(&live_object.kind)
+        }
+    };
+    let (gvk, resource) =
+        found.ok_or_else(|| anyhow!("unknown resource type {}", live_object.kind))?;
+
+    let client = kube_client.clone()().await?;
+    let api_resource = kube::api::ApiResource::from_gvk_with_plural(&gvk, &resource.name);
+    let api: kube::Api<DynamicObject> = if !resource.namespaced {
+        kube::Api::all_with(client, &api_resource)
+    } else if let Some(namespace) = &live_object.namespace {
+        kube::Api::namespaced_with(client, namespace, &api_resource)
+    } else {
+        kube::Api::default_namespaced_with(client, &api_resource)
+    };
+    let reference = format!("{}/{}", live_object.kind, live_object.name);
+    let fetched = api
+        .get(&live_object.name)
+        .await
+        .map_err(|err| anyhow!("failed to fetch {} from the cluster: {}", reference, err))?;
 
-    let file = File::open(object_path.clone()).map_err(|err| {
+    match operation {
+        Operation::Update => {
+            let object_path = object_path.ok_or(anyhow!("UPDATE operation requires an object"))?;
+            let object = read_object(&object_path)?;
+            ensure_same_object(
+                &object,
+                &fetched,
+                &object_path.to_string_lossy(),
+                &format!("the cluster ({reference})"),
+            )?;
+            build_admission_request(
+                resource_catalog_file,
+                kube_client,
+                Operation::Update,
+                object,
+                Some(fetched),
+            )
+            .await
+        }
+        operation => {
+            build_admission_request(resource_catalog_file, kube_client, operation, fetched, None)
+                .await
+        }
+    }
+}
+
+fn read_object(object_path: &PathBuf) -> Result<DynamicObject> {
+    let file = File::open(object_path).map_err(|err| {
         anyhow!(
             "failed to open object file {}: {}",
             object_path.to_string_lossy(),
@@ -338,10 +646,38 @@ where
         )
     })?;
 
-    let object_type_meta = object.clone().types.ok_or(anyhow!(
-        "object defined inside of {} is missing types",
-        object_path.to_string_lossy()
-    ))?;
+    if object.types.is_none() {
+        return Err(anyhow!(
+            "object defined inside of {} is missing types",
+            object_path.to_string_lossy()
+        ));
+    }
+
+    Ok(object)
+}
+
+/// Build the AdmissionRequest. The `object` is used to compute the
+/// kind, resource, name and namespace of the request. For DELETE operations
+/// `object` is the object being deleted, which Kubernetes sends as old
+/// object.
+async fn build_admission_request<F, Fut>(
+    resource_catalog_file: PathBuf,
+    kube_client: F,
+    operation: Operation,
+    object: DynamicObject,
+    old_object: Option<DynamicObject>,
+) -> Result<String>
+where
+    F: FnOnce() -> Fut + Clone,
+    Fut: Future<Output = Result<kube::Client>>,
+{
+    let mut resource_catalog =
+        ApiResourceCatalog::new(resource_catalog_file.clone(), kube_client.clone()).await;
+
+    let object_type_meta = object
+        .clone()
+        .types
+        .ok_or(anyhow!("object is missing types"))?;
 
     let kube_gvk: kube::api::GroupVersionKind = object_type_meta.try_into()?;
     let api_resource = match resource_catalog.lookup(&kube_gvk) {
@@ -349,7 +685,7 @@ where
         None => {
             // Try to refresh the catalog and lookup again
             if resource_catalog.refresh(kube_client).await.is_ok() {
-                if let Err(err) = resource_catalog.save(object_path) {
+                if let Err(err) = resource_catalog.save(resource_catalog_file) {
                     warn!(?err, "Failed to save resource catalog");
                 }
                 resource_catalog.lookup(&kube_gvk)
@@ -394,10 +730,15 @@ where
     };
 
     let object_json = serde_json::to_value(object.clone())?;
+    let old_object_json = old_object.map(serde_json::to_value).transpose()?;
+    let uid = request_uid(&object_json);
+    let (object_json, old_object_json) = match operation {
+        Operation::Delete => (None, Some(object_json)),
+        _ => (Some(object_json), old_object_json),
+    };
 
     let request = AdmissionRequest {
-        // hard-coded UID
-        uid: "705ab4f5-6393-11e8-b7cc-42010a800002".to_string(),
+        uid,
         kind: object_kind.clone(),
         request_kind: Some(object_kind),
         resource: object_gvr.clone(),
@@ -406,16 +747,20 @@ where
         request_sub_resource: None,
         name: object.metadata.name,
         namespace,
-        operation: Operation::Create.to_string(),
+        operation: operation.to_string(),
         user_info: UserInfo {
-            username: Some("test-user".to_string()),
-            groups: Some(vec!["system:masters".to_string()]),
+            // the user of the clusters created by kind and kubeadm
+            username: Some("kubernetes-admin".to_string()),
+            groups: Some(vec![
+                "kubeadm:cluster-admins".to_string(),
+                "system:authenticated".to_string(),
+            ]),
             ..Default::default()
         },
-        object: Some(RawExtension(object_json)),
-        old_object: None,
-        dry_run: None,
-        options: None,
+        options: Some(RawExtension(operation.options())),
+        object: object_json.map(RawExtension),
+        old_object: old_object_json.map(RawExtension),
+        dry_run: Some(false),
     };
 
     let output = serde_json::to_string_pretty(&request)?;
@@ -446,6 +791,7 @@ mod tests {
     #[case::update_without_old_object(Operation::Update, Some(PathBuf::from_str("new").unwrap()), None, false)]
     #[case::update_without_object_and_old_object(Operation::Update, None, None, false)]
     #[case::delete_with_right_params(Operation::Delete, None, Some(PathBuf::from_str("old").unwrap()), true)]
+    #[case::delete_with_object_only(Operation::Delete, Some(PathBuf::from_str("old").unwrap()), None, true)]
     #[case::delete_with_object(Operation::Delete, Some(PathBuf::from_str("not expected").unwrap()), Some(PathBuf::from_str("old").unwrap()), false)]
     #[case::delete_without_old_object(Operation::Delete, None, None, false)]
     #[case::delete_without_object_and_old_object(Operation::Delete, None, None, false)]
@@ -802,4 +1148,253 @@ mod tests {
             assert!(catalog.lookup(&gvk).is_some());
         }
     }
+
+    fn write_object_file(dir: &std::path::Path, name: &str, raw_object: &str) -> PathBuf {
+        let object_filepath = dir.join(name);
+        let mut object_file = File::create(&object_filepath).expect("failed to create object file");
+        object_file
+            .write_all(raw_object.as_bytes())
+            .expect("failed to write object file");
+        object_filepath
+    }
+
+    const NAMESPACE_WITH_LABELS_YAML: &str = r#"
+        apiVersion: v1
+        kind: Namespace
+        metadata:
+          name: my-namespace
+          labels:
+            environment: production"#;
+
+    const OTHER_NAMESPACE_YAML: &str = r#"
+        apiVersion: v1
+        kind: Namespace
+        metadata:
+          name: my-other-namespace"#;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_update_operation() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_filepath = tempdir.path().join("resource_catalog.json");
+        build_basic_catalog()
+            .save(catalog_filepath.clone())
+            .expect("failed to save catalog");
+
+        let object_filepath =
+            write_object_file(tempdir.path(), "new.yaml", NAMESPACE_WITH_LABELS_YAML);
+        let old_object_filepath = write_object_file(tempdir.path(), "old.yaml", NAMESPACE_YAML);
+
+        let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        expect_no_request(handle).await;
+
+        let build_mock_kube_client = || async { Ok(kube::Client::new(mocksvc, "default")) };
+        let output = scaffold_update(
+            catalog_filepath,
+            build_mock_kube_client,
+            object_filepath,
+            old_object_filepath,
+        )
+        .await
+        .expect("scaffold failed");
+
+        let admission_request: AdmissionRequest =
+            serde_json::from_str(&output).expect("failed to parse output");
+        assert_eq!(admission_request.operation, "UPDATE");
+        assert_eq!(admission_request.name, Some("my-namespace".to_string()));
+        assert_eq!(
+            admission_request.object,
+            Some(RawExtension(
+                serde_yaml::from_str(NAMESPACE_WITH_LABELS_YAML).unwrap()
+            ))
+        );
+        assert_eq!(
+            admission_request.old_object,
+            Some(RawExtension(serde_yaml::from_str(NAMESPACE_YAML).unwrap()))
+        );
+    }
+
+    #[rstest]
+    #[case::different_names(OTHER_NAMESPACE_YAML)]
+    #[case::different_types(SERVICE_YAML)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_update_operation_with_unrelated_objects(#[case] raw_old_object: &str) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_filepath = tempdir.path().join("resource_catalog.json");
+        build_basic_catalog()
+            .save(catalog_filepath.clone())
+            .expect("failed to save catalog");
+
+        let object_filepath = write_object_file(tempdir.path(), "new.yaml", NAMESPACE_YAML);
+        let old_object_filepath = write_object_file(tempdir.path(), "old.yaml", raw_old_object);
+
+        let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        expect_no_request(handle).await;
+
+        let build_mock_kube_client = || async { Ok(kube::Client::new(mocksvc, "default")) };
+        let result = scaffold_update(
+            catalog_filepath,
+            build_mock_kube_client,
+            object_filepath,
+            old_object_filepath,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_delete_operation() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_filepath = tempdir.path().join("resource_catalog.json");
+        build_basic_catalog()
+            .save(catalog_filepath.clone())
+            .expect("failed to save catalog");
+
+        let object_filepath = write_object_file(tempdir.path(), "old.yaml", NAMESPACE_YAML);
+
+        let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        expect_no_request(handle).await;
+
+        let build_mock_kube_client = || async { Ok(kube::Client::new(mocksvc, "default")) };
+        let output = scaffold_delete(catalog_filepath, build_mock_kube_client, object_filepath)
+            .await
+            .expect("scaffold failed");
+
+        let admission_request: AdmissionRequest =
+            serde_json::from_str(&output).expect("failed to parse output");
+        assert_eq!(admission_request.operation, "DELETE");
+        assert_eq!(admission_request.object, None);
+        assert_eq!(
+            admission_request.old_object,
+            Some(RawExtension(serde_yaml::from_str(NAMESPACE_YAML).unwrap()))
+        );
+        assert_eq!(admission_request.dry_run, Some(false));
+        assert_eq!(
+            admission_request.options.unwrap().0["kind"],
+            serde_json::Value::from("DeleteOptions")
+        );
+    }
+
+    #[test]
+    fn request_uids() {
+        let object = serde_json::json!({"kind": "Namespace"});
+        let uid = request_uid(&object);
+        let groups: Vec<usize> = uid.split('-').map(str::len).collect();
+        assert_eq!(groups, vec![8, 4, 4, 4, 12]);
+        assert_eq!(&uid[14..15], "4");
+        assert_ne!(uid, request_uid(&object));
+    }
+
+    fn build_catalog_with_deployments() -> ApiResourceCatalog {
+        let mut catalog = build_basic_catalog();
+        catalog.resources.insert(
+            "apps|v1|Deployment".to_string(),
+            APIResource {
+                name: "deployments".to_owned(),
+                singular_name: "deployment".to_owned(),
+                short_names: Some(vec!["deploy".to_owned()]),
+                namespaced: true,
+                kind: "Deployment".to_owned(),
+                ..Default::default()
+            },
+        );
+        catalog
+    }
+
+    #[rstest]
+    #[case::kind("Deployment", Some("apps|v1|Deployment"))]
+    #[case::plural("deployments", Some("apps|v1|Deployment"))]
+    #[case::singular("deployment", Some("apps|v1|Deployment"))]
+    #[case::short_name("deploy", Some("apps|v1|Deployment"))]
+    #[case::group("deployments.apps", Some("apps|v1|Deployment"))]
+    #[case::wrong_group("deployments.batch", None)]
+    #[case::core("ns", None)]
+    #[case::core_singular("namespace", Some("|v1|Namespace"))]
+    fn find_resource(#[case] name: &str, #[case] expected: Option<&str>) {
+        let catalog = build_catalog_with_deployments();
+        let found = catalog
+            .find(name)
+            .map(|(gvk, _)| ApiResourceCatalog::gvk_to_string(&gvk));
+        assert_eq!(found.as_deref(), expected);
+    }
+
+    #[rstest]
+    #[case::valid("deployment/web", true)]
+    #[case::missing_name("deployment/", false)]
+    #[case::missing_kind("web", false)]
+    #[case::too_many_segments("apps/deployment/web", false)]
+    fn live_object_reference(#[case] reference: &str, #[case] valid: bool) {
+        assert_eq!(LiveObject::parse(reference, None).is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case::create(Operation::Create, None, None, true)]
+    #[case::create_with_object(Operation::Create, Some(PathBuf::from_str("new").unwrap()), None, false)]
+    #[case::update(Operation::Update, Some(PathBuf::from_str("new").unwrap()), None, true)]
+    #[case::update_without_object(Operation::Update, None, None, false)]
+    #[case::update_with_old_object(Operation::Update, Some(PathBuf::from_str("new").unwrap()), Some(PathBuf::from_str("old").unwrap()), false)]
+    #[case::delete(Operation::Delete, None, None, true)]
+    fn test_validate_live_params(
+        #[case] operation: Operation,
+        #[case] object_path: Option<PathBuf>,
+        #[case] old_object_path: Option<PathBuf>,
+        #[case] valid: bool,
+    ) {
+        assert_eq!(
+            valid,
+            validate_live_params(&operation, object_path.as_ref(), old_object_path.as_ref())
+                .is_ok()
+        );
+    }
+
+    const DEPLOYMENT_YAML: &str = r#"
+        apiVersion: apps/v1
+        kind: Deployment
+        metadata:
+          name: web
+          namespace: prod
+        spec:
+          replicas: 3"#;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scaffold_delete_from_cluster() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let catalog_filepath = tempdir.path().join("resource_catalog.json");
+        build_catalog_with_deployments()
+            .save(catalog_filepath.clone())
+            .expect("failed to save catalog");
+
+        let (mocksvc, handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        tokio::spawn(async move {
+            let mut handle = handle;
+            let (request, send) = handle.next_request().await.expect("service not called");
+            assert_eq!(request.method(), http::Method::GET);
+            assert_eq!(
+                request.uri().path(),
+                "/apis/apps/v1/namespaces/prod/deployments/web"
+            );
+            let deployment: serde_json::Value = serde_yaml::from_str(DEPLOYMENT_YAML).unwrap();
+            send_response(send, deployment);
+        });
+
+        let build_mock_kube_client = || async { Ok(kube::Client::new(mocksvc, "default")) };
+        let output = scaffold_from_cluster(
+            catalog_filepath,
+            build_mock_kube_client,
+            Operation::Delete,
+            LiveObject::parse("deploy/web", Some("prod".to_string())).unwrap(),
+            None,
+        )
+        .await
+        .expect("scaffold failed");
+
+        let admission_request: AdmissionRequest =
+            serde_json::from_str(&output).expect("failed to parse output");
+        assert_eq!(admission_request.operation, "DELETE");
+        assert_eq!(admission_request.namespace, Some("prod".to_string()));
+        assert_eq!(admission_request.resource.resource, "deployments");
+        assert_eq!(
+            admission_request.old_object,
+            Some(RawExtension(serde_yaml::from_str(DEPLOYMENT_YAML).unwrap()))
+        );
+    }
 }
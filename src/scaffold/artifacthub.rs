@@ -6,9 +6,15 @@ use std::{
 };
 use time::OffsetDateTime;
 
+use crate::annotations::POLICY_VERSION_ANNOTATION;
+
+/// Generates the `artifacthub-pkg.yml` of the policy. The version of the
+/// package is taken from the `io.kubewarden.policy.version` annotation, unless
+/// given via `version`.
 pub(crate) fn artifacthub(
     metadata_path: PathBuf,
     questions_path: Option<PathBuf>,
+    version: Option<&str>,
 ) -> Result<String> {
     let comment_header = r#"# Kubewarden Artifacthub Package config
 #
@@ -19,8 +25,16 @@ pub(crate) fn artifacthub(
 
     let metadata_file =
         File::open(metadata_path).map_err(|e| anyhow!("Error opening metadata file: {}", e))?;
-    let metadata: Metadata = serde_yaml::from_reader(&metadata_file)
+    let mut metadata: Metadata = serde_yaml::from_reader(&metadata_file)
         .map_err(|e| anyhow!("Error unmarshalling metadata {}", e))?;
+    if let Some(version) = version {
+        semver::Version::parse(version)
+            .map_err(|e| anyhow!("invalid version '{}': {}", version, e))?;
+        metadata
+            .annotations
+            .get_or_insert_with(Default::default)
+            .insert(POLICY_VERSION_ANNOTATION.to_string(), version.to_string());
+    }
     let questions = questions_path
         .map(|path| {
             fs::read_to_string(path).map_err(|e| anyhow!("Error reading questions file: {}", e))
@@ -36,3 +50,32 @@ pub(crate) fn artifacthub(
         serde_yaml::to_string(&kubewarden_artifacthub_pkg)?
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn package(version: Option<&str>) -> Result<serde_yaml::Value> {
+        let metadata_path = PathBuf::from("tests/data/artifacthub/metadata.yml");
+        let content = artifacthub(metadata_path, None, version)?;
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    #[rstest]
+    #[case::from_metadata(None, "0.2.0")]
+    #[case::overridden(Some("1.2.0"), "1.2.0")]
+    fn package_version(#[case] version: Option<&str>, #[case] expected: &str) {
+        let package = package(version).unwrap();
+        assert_eq!(package["version"], serde_yaml::Value::from(expected));
+    }
+
+    #[test]
+    fn invalid_version() {
+        let error = package(Some("v1.2")).unwrap_err();
+        assert!(
+            error.to_string().contains("invalid version 'v1.2'"),
+            "{error}"
+        );
+    }
+}
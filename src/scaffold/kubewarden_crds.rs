@@ -1,7 +1,7 @@
 use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
 use policy_evaluator::policy_metadata::{ContextAwareResource, Rule};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -15,6 +15,8 @@ pub(crate) struct ClusterAdmissionPolicy {
 #[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct ClusterAdmissionPolicySpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_server: Option<String>,
     pub module: String,
     pub settings: serde_yaml::Mapping,
     pub rules: Vec<Rule>,
@@ -50,9 +52,11 @@ pub(crate) struct AdmissionPolicy {
     pub spec: AdmissionPolicySpec,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct AdmissionPolicySpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_server: Option<String>,
     pub module: String,
     pub settings: serde_yaml::Mapping,
     pub rules: Vec<Rule>,
@@ -61,4 +65,62 @@ pub(crate) struct AdmissionPolicySpec {
     // This is needed as a temporary fix for https://github.com/kubewarden/kubewarden-controller/issues/395
     #[serde(skip_serializing_if = "is_true")]
     pub background_audit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    // AdmissionPolicies are namespaced, hence they have no namespace selector
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_selector: Option<LabelSelector>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ClusterAdmissionPolicyGroup {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: PolicyGroupSpec,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AdmissionPolicyGroup {
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: ObjectMeta,
+    pub spec: PolicyGroupSpec,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PolicyGroupSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy_server: Option<String>,
+    pub rules: Vec<Rule>,
+    pub policies: BTreeMap<String, PolicyGroupMember>,
+    pub expression: String,
+    pub message: String,
+    // Skip serialization when this is true, which is the default case.
+    #[serde(skip_serializing_if = "is_true")]
+    pub background_audit: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_policy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    // Only ClusterAdmissionPolicyGroups can select namespaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_selector: Option<LabelSelector>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_selector: Option<LabelSelector>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PolicyGroupMember {
+    pub module: String,
+    pub settings: serde_yaml::Mapping,
+    // Only the members of a ClusterAdmissionPolicyGroup can access cluster resources
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub context_aware_resources: BTreeSet<ContextAwareResource>,
 }
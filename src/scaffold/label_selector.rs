@@ -0,0 +1,151 @@
+//! Label selectors written like the ones of `kubectl --selector`, e.g.
+//! `environment=production,tier in (frontend,backend),!deprecated`.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement};
+use regex::Regex;
+
+pub(crate) fn parse(selector: &str) -> Result<LabelSelector> {
+    let set_requirement = Regex::new(r"^(\S+)\s+(in|notin)\s*\((.*)\)$").expect("invalid regex");
+    let mut match_labels: BTreeMap<String, String> = BTreeMap::new();
+    let mut match_expressions: Vec<LabelSelectorRequirement> = Vec::new();
+
+    for requirement in requirements(selector) {
+        let requirement = requirement.trim();
+        let invalid = || {
+            anyhow!(
+                "invalid label selector '{}': cannot parse '{}'",
+                selector,
+                requirement
+            )
+        };
+
+        if let Some(captures) = set_requirement.captures(requirement) {
+            let values: Vec<String> = captures[3]
+                .split(',')
+                .map(|value| value.trim().to_string())
+                .collect();
+            if values.iter().any(String::is_empty) {
+                return Err(invalid());
+            }
+            let operator = if &captures[2] == "in" { "In" } else { "NotIn" };
+            match_expressions.push(expression(&captures[1], operator, Some(values)));
+        } else if let Some(key) = requirement.strip_prefix('!') {
+            match_expressions.push(expression(key.trim(), "DoesNotExist", None));
+        } else if let Some((key, value)) = requirement.split_once("!=") {
+            match_expressions.push(expression(
+                key.trim(),
+                "NotIn",
+                Some(vec![value.trim().to_string()]),
+            ));
+        } else if let Some((key, value)) = requirement
+            .split_once("==")
+            .or_else(|| requirement.split_once('='))
+        {
+            let (key, value) = (key.trim(), value.trim());
+            if key.is_empty() {
+                return Err(invalid());
+            }
+            if let Some(existing) = match_labels.insert(key.to_string(), value.to_string()) {
+                if existing != value {
+                    return Err(anyhow!(
+                        "invalid label selector '{}': the label {} cannot be both {} and {}",
+                        selector,
+                        key,
+                        existing,
+                        value
+                    ));
+                }
+            }
+        } else {
+            match_expressions.push(expression(requirement, "Exists", None));
+        }
+    }
+
+    if match_expressions
+        .iter()
+        .any(|expression| expression.key.is_empty() || expression.key.contains(char::is_whitespace))
+    {
+        return Err(anyhow!("invalid label selector '{}'", selector));
+    }
+
+    Ok(LabelSelector {
+        match_labels: (!match_labels.is_empty()).then_some(match_labels),
+        match_expressions: (!match_expressions.is_empty()).then_some(match_expressions),
+    })
+}
+
+fn expression(key: &str, operator: &str, values: Option<Vec<String>>) -> LabelSelectorRequirement {
+    LabelSelectorRequirement {
+        key: key.to_string(),
+        operator: operator.to_string(),
+        values,
+    }
+}
+
+/// Splits the selector at the commas that are not inside of the values of
+/// an `in` or `notin` requirement
+fn requirements(selector: &str) -> Vec<&str> {
+    let mut requirements = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, c) in selector.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(&selector[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    requirements.push(&selector[start..]);
+    requirements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn parse_selector() {
+        let selector =
+            parse("environment=production, tier in (frontend, backend),!deprecated,team!=ops,app")
+                .unwrap();
+
+        assert_eq!(
+            selector.match_labels,
+            Some(BTreeMap::from([(
+                "environment".to_string(),
+                "production".to_string()
+            )]))
+        );
+        assert_eq!(
+            selector.match_expressions,
+            Some(vec![
+                expression(
+                    "tier",
+                    "In",
+                    Some(vec!["frontend".to_string(), "backend".to_string()])
+                ),
+                expression("deprecated", "DoesNotExist", None),
+                expression("team", "NotIn", Some(vec!["ops".to_string()])),
+                expression("app", "Exists", None),
+            ])
+        );
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::empty_requirement("environment=production,")]
+    #[case::empty_key("=production")]
+    #[case::empty_value("tier in (frontend,)")]
+    #[case::conflicting_labels("environment=production,environment=staging")]
+    fn invalid_selector(#[case] selector: &str) {
+        assert!(parse(selector).is_err(), "{selector}");
+    }
+}
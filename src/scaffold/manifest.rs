@@ -1,29 +1,52 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     convert::TryFrom,
+    fs,
+    path::Path,
     str::FromStr,
 };
 
 use anyhow::{anyhow, Result};
 use hostname_validator::is_valid;
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{LabelSelector, ObjectMeta};
 use policy_evaluator::{
     constants::{
         KUBEWARDEN_ANNOTATION_POLICY_CATEGORY, KUBEWARDEN_ANNOTATION_POLICY_SEVERITY,
         KUBEWARDEN_ANNOTATION_POLICY_TITLE,
     },
+    policy_fetcher::{oci_client::manifest::OciManifest, registry::Registry, sources::Sources},
     policy_metadata::Metadata,
     validator::Validate,
 };
+use regex::Regex;
+use serde::Deserialize;
 use tracing::warn;
 
-use crate::scaffold::kubewarden_crds::{
-    AdmissionPolicy, AdmissionPolicySpec, ClusterAdmissionPolicy, ClusterAdmissionPolicySpec,
+use crate::{
+    push::KWCTL_ANNOTATION_POLICY_METADATA,
+    scaffold::kubewarden_crds::{
+        AdmissionPolicy, AdmissionPolicyGroup, AdmissionPolicySpec, ClusterAdmissionPolicy,
+        ClusterAdmissionPolicyGroup, ClusterAdmissionPolicySpec, PolicyGroupMember,
+        PolicyGroupSpec,
+    },
+    scaffold::manifest_output::{self, ManifestOutput},
 };
 
+#[derive(Clone, Copy)]
 pub(crate) enum ManifestType {
     ClusterAdmissionPolicy,
     AdmissionPolicy,
+    ClusterAdmissionPolicyGroup,
+    AdmissionPolicyGroup,
+}
+
+impl ManifestType {
+    pub(crate) fn is_group(&self) -> bool {
+        matches!(
+            self,
+            ManifestType::ClusterAdmissionPolicyGroup | ManifestType::AdmissionPolicyGroup
+        )
+    }
 }
 
 impl FromStr for ManifestType {
@@ -33,17 +56,57 @@ impl FromStr for ManifestType {
         match value {
             "ClusterAdmissionPolicy" => Ok(ManifestType::ClusterAdmissionPolicy),
             "AdmissionPolicy" => Ok(ManifestType::AdmissionPolicy),
+            "ClusterAdmissionPolicyGroup" => Ok(ManifestType::ClusterAdmissionPolicyGroup),
+            "AdmissionPolicyGroup" => Ok(ManifestType::AdmissionPolicyGroup),
             _ => Err(anyhow!("unknown manifest type")),
         }
     }
 }
 
+/// Deployment options of the generated resources. The ones that are not
+/// given are left to the defaults of the Kubewarden controller.
+#[derive(Clone, Default)]
+pub(crate) struct ManifestOptions {
+    pub policy_server: Option<String>,
+    pub mode: Option<String>,
+    pub failure_policy: Option<String>,
+    pub background_audit: Option<bool>,
+    pub namespace_selector: Option<LabelSelector>,
+    pub object_selector: Option<LabelSelector>,
+}
+
+impl ManifestOptions {
+    /// Background audit defaults to what the policies support, and cannot be
+    /// enabled for the policies that do not support it
+    fn background_audit(&self, supported: bool) -> Result<bool> {
+        match self.background_audit {
+            Some(true) if !supported => Err(anyhow!(
+                "background audit cannot be enabled: the policy does not support it"
+            )),
+            Some(enabled) => Ok(enabled),
+            None => Ok(supported),
+        }
+    }
+
+    /// Namespaced resources cannot select namespaces
+    fn ensure_no_namespace_selector(&self, kind: &str) -> Result<()> {
+        if self.namespace_selector.is_some() {
+            return Err(anyhow!(
+                "{} is namespaced, a namespace selector can be used only with the cluster-wide resources",
+                kind
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 struct ScaffoldPolicyData {
     pub uri: String,
     policy_title: Option<String>,
     metadata: Metadata,
     settings: serde_yaml::Mapping,
+    options: ManifestOptions,
 }
 
 impl TryFrom<ScaffoldPolicyData> for ClusterAdmissionPolicy {
@@ -56,12 +119,19 @@ impl TryFrom<ScaffoldPolicyData> for ClusterAdmissionPolicy {
             kind: String::from("ClusterAdmissionPolicy"),
             metadata: build_objmetadata(data.clone()),
             spec: ClusterAdmissionPolicySpec {
+                policy_server: data.options.policy_server.clone(),
                 module: data.uri,
                 settings: data.settings,
                 rules: data.metadata.rules.clone(),
                 mutating: data.metadata.mutating,
-                background_audit: data.metadata.background_audit,
+                background_audit: data
+                    .options
+                    .background_audit(data.metadata.background_audit)?,
                 context_aware_resources: data.metadata.context_aware_resources,
+                failure_policy: data.options.failure_policy,
+                mode: data.options.mode,
+                namespace_selector: data.options.namespace_selector,
+                object_selector: data.options.object_selector,
                 ..Default::default()
             },
         })
@@ -73,16 +143,24 @@ impl TryFrom<ScaffoldPolicyData> for AdmissionPolicy {
 
     fn try_from(data: ScaffoldPolicyData) -> Result<Self, Self::Error> {
         data.metadata.validate()?;
+        data.options
+            .ensure_no_namespace_selector("AdmissionPolicy")?;
         Ok(AdmissionPolicy {
             api_version: String::from("policies.kubewarden.io/v1"),
             kind: String::from("AdmissionPolicy"),
             metadata: build_objmetadata(data.clone()),
             spec: AdmissionPolicySpec {
+                policy_server: data.options.policy_server.clone(),
                 module: data.uri,
                 settings: data.settings,
                 rules: data.metadata.rules.clone(),
                 mutating: data.metadata.mutating,
-                background_audit: data.metadata.background_audit,
+                background_audit: data
+                    .options
+                    .background_audit(data.metadata.background_audit)?,
+                failure_policy: data.options.failure_policy,
+                mode: data.options.mode,
+                object_selector: data.options.object_selector,
             },
         })
     }
@@ -135,13 +213,8 @@ fn validate_policy_title(title: &str) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn manifest(
-    uri_or_sha_prefix: &str,
-    resource_type: ManifestType,
-    settings: Option<&str>,
-    policy_title: Option<&str>,
-    allow_context_aware_resources: bool,
-) -> Result<()> {
+/// Reads the metadata of a policy of the local store
+pub(crate) fn local_metadata(uri_or_sha_prefix: &str) -> Result<(String, Metadata)> {
     let uri = crate::utils::get_uri(&uri_or_sha_prefix.to_owned())?;
     let wasm_path = crate::utils::wasm_path(&uri)?;
 
@@ -152,6 +225,63 @@ pub(crate) fn manifest(
                 uri)
         )?;
 
+    Ok((uri, metadata))
+}
+
+/// Reads the metadata of a policy from the annotations of its OCI manifest,
+/// without downloading the Wasm module. Returns `None` when the policy has
+/// been pushed without the metadata annotation.
+pub(crate) async fn remote_metadata(
+    candidates: &[String],
+    sources: Option<&Sources>,
+) -> Result<Option<Metadata>> {
+    let registry = Registry::new();
+    let mut errors: Vec<String> = Vec::new();
+
+    for candidate in candidates {
+        let manifest = match registry.manifest(candidate, sources).await {
+            Ok(OciManifest::Image(manifest)) => manifest,
+            Ok(OciManifest::ImageIndex(_)) => {
+                return Err(anyhow!("{} is an image index, not a policy", candidate))
+            }
+            Err(e) => {
+                warn!(source = candidate.as_str(), error = %e, "cannot fetch the OCI manifest, trying next source");
+                errors.push(format!("  - {candidate}: {e}"));
+                continue;
+            }
+        };
+        return manifest
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(KWCTL_ANNOTATION_POLICY_METADATA))
+            .map(|metadata| {
+                serde_json::from_str(metadata).map_err(|e| {
+                    anyhow!(
+                        "cannot parse the metadata annotation of {}: {}",
+                        candidate,
+                        e
+                    )
+                })
+            })
+            .transpose();
+    }
+
+    Err(anyhow!(
+        "cannot fetch the OCI manifest of the policy:\n{}",
+        errors.join("\n")
+    ))
+}
+
+pub(crate) fn manifest(
+    uri: String,
+    metadata: Metadata,
+    resource_type: ManifestType,
+    settings: Option<&str>,
+    policy_title: Option<&str>,
+    allow_context_aware_resources: bool,
+    options: ManifestOptions,
+    output: &ManifestOutput,
+) -> Result<()> {
     let settings_yml: serde_yaml::Mapping = serde_yaml::from_str(settings.unwrap_or("{}"))?;
 
     let policy_title = get_policy_title_from_cli_or_metadata(policy_title, &metadata);
@@ -166,16 +296,89 @@ pub(crate) fn manifest(
         policy_title,
         metadata,
         settings: settings_yml,
+        options,
     };
 
     let resource =
         generate_yaml_resource(scaffold_data, resource_type, allow_context_aware_resources)?;
 
-    let stdout = std::io::stdout();
-    let out = stdout.lock();
-    serde_yaml::to_writer(out, &resource)?;
+    manifest_output::write(&[resource], output)
+}
 
-    Ok(())
+/// A policy listed inside of the file given via `--from-file`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PolicyRequirement {
+    pub uri: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub settings: serde_yaml::Mapping,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RequirementsFile {
+    policies: Vec<PolicyRequirement>,
+}
+
+/// Reads the policies listed by a requirements file:
+///
+/// ```yaml
+/// policies:
+///   - uri: registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13
+///     title: safe-labels
+///     settings:
+///       denied_labels: [foo]
+/// ```
+pub(crate) fn read_requirements(path: &Path) -> Result<Vec<PolicyRequirement>> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+    let requirements: RequirementsFile = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("cannot parse {}: {}", path.display(), e))?;
+    if requirements.policies.is_empty() {
+        return Err(anyhow!("{} does not list any policy", path.display()));
+    }
+    Ok(requirements.policies)
+}
+
+/// Generates the manifests of all the policies of a requirements file, each
+/// one given with its resolved URI and its metadata
+pub(crate) fn batch_manifest(
+    policies: Vec<(PolicyRequirement, String, Metadata)>,
+    resource_type: ManifestType,
+    allow_context_aware_resources: bool,
+    options: ManifestOptions,
+    output: &ManifestOutput,
+) -> Result<()> {
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    let mut resources = Vec::new();
+    for (requirement, uri, metadata) in policies {
+        let policy_title =
+            get_policy_title_from_cli_or_metadata(requirement.title.as_deref(), &metadata);
+        if let Some(title) = &policy_title {
+            validate_policy_title(title)?;
+            if !names.insert(title.to_owned()) {
+                return Err(anyhow!(
+                    "more policies are named '{}', give them different titles",
+                    title
+                ));
+            }
+        }
+
+        let scaffold_data = ScaffoldPolicyData {
+            uri,
+            policy_title,
+            metadata,
+            settings: requirement.settings,
+            options: options.clone(),
+        };
+        resources.push(
+            generate_yaml_resource(scaffold_data, resource_type, allow_context_aware_resources)
+                .map_err(|e| anyhow!("{}: {}", requirement.uri, e))?,
+        );
+    }
+
+    manifest_output::write(&resources, output)
 }
 
 fn get_policy_title_from_cli_or_metadata(
@@ -209,8 +412,8 @@ fn generate_yaml_resource(
                     warn!("Carefully review the contents of the `contextAwareResources` attribute for abuses.");
                 } else {
                     warn!("Policy requires access to Kubernetes resources at evaluation time. For safety reasons, the `contextAwareResources` attribute has been left empty.");
-                    warn!("Carefully review which types of Kubernetes resources the policy needs via the `inspect` command an populate the `contextAwareResources` accordingly.");
-                    warn!("Otherwise, invoke the `scaffold` command using the `--allow-context-aware` flag.");
+                    warn!("The policy will not work until the `contextAwareResources` attribute is populated: review which types of Kubernetes resources the policy needs via the `inspect` command.");
+                    warn!("Otherwise, invoke the `scaffold` command without the `--no-context-aware` flag.");
 
                     scaffold_data.metadata.context_aware_resources = BTreeSet::new();
                 }
@@ -220,17 +423,226 @@ fn generate_yaml_resource(
                 .map_err(|e| anyhow!("{}", e))
         }
         ManifestType::AdmissionPolicy => {
+            if !scaffold_data.metadata.context_aware_resources.is_empty() {
+                warn!("Policy requires access to Kubernetes resources at evaluation time, which is allowed only to ClusterAdmissionPolicies. The policy will not work as an AdmissionPolicy.");
+            }
             serde_yaml::to_value(AdmissionPolicy::try_from(scaffold_data)?)
                 .map_err(|e| anyhow!("{}", e))
         }
+        ManifestType::ClusterAdmissionPolicyGroup | ManifestType::AdmissionPolicyGroup => Err(
+            anyhow!("a policy group cannot be generated from a single policy"),
+        ),
+    }
+}
+
+/// A policy of a group. Its name identifies it inside of the expression of
+/// the group, which calls it as `name()`.
+pub(crate) struct GroupMember {
+    pub name: String,
+    pub uri: String,
+    pub metadata: Metadata,
+    pub settings: serde_yaml::Mapping,
+}
+
+/// Splits a `NAME=URI` argument into the name and the URI of a group member.
+/// Without a name, the policy is named after the last segment of its URI:
+/// `registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13` becomes
+/// `safe_labels`.
+pub(crate) fn group_member(arg: &str) -> (String, String) {
+    if let Some((name, uri)) = arg.split_once('=') {
+        if is_group_member_name(name) {
+            return (name.to_string(), uri.to_string());
+        }
     }
+
+    let name = arg.rsplit('/').next().unwrap_or(arg);
+    let name = name.split([':', '@']).next().unwrap_or(name);
+    let name = name.strip_suffix(".wasm").unwrap_or(name);
+    (name.replace(['-', '.'], "_"), arg.to_string())
+}
+
+/// The names of the members are called by the CEL expression of the group,
+/// hence they must be valid CEL identifiers
+fn is_group_member_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The policies called by the expression of a group, like `a` and `b` for
+/// `a() && !b()`
+fn called_policies(expression: &str) -> BTreeSet<String> {
+    let call = Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)\s*\(\s*\)").expect("invalid regex");
+    call.captures_iter(expression)
+        .map(|captures| captures[1].to_string())
+        .collect()
+}
+
+pub(crate) fn group_manifest(
+    members: Vec<GroupMember>,
+    resource_type: ManifestType,
+    expression: &str,
+    message: &str,
+    policy_title: Option<&str>,
+    allow_context_aware_resources: bool,
+    options: ManifestOptions,
+    output: &ManifestOutput,
+) -> Result<()> {
+    let resource = generate_group_yaml_resource(
+        members,
+        resource_type,
+        expression,
+        message,
+        policy_title,
+        allow_context_aware_resources,
+        options,
+    )?;
+
+    manifest_output::write(&[resource], output)
+}
+
+fn generate_group_yaml_resource(
+    members: Vec<GroupMember>,
+    resource_type: ManifestType,
+    expression: &str,
+    message: &str,
+    policy_title: Option<&str>,
+    allow_context_aware_resources: bool,
+    options: ManifestOptions,
+) -> Result<serde_yaml::Value> {
+    if members.is_empty() {
+        return Err(anyhow!("a policy group must contain at least one policy"));
+    }
+    if expression.trim().is_empty() {
+        return Err(anyhow!("the expression of a policy group cannot be empty"));
+    }
+    if let Some(title) = policy_title {
+        validate_policy_title(title)?;
+    }
+
+    if let ManifestType::AdmissionPolicyGroup = resource_type {
+        options.ensure_no_namespace_selector("AdmissionPolicyGroup")?;
+    }
+
+    let mut spec = PolicyGroupSpec {
+        policy_server: options.policy_server.clone(),
+        expression: expression.to_string(),
+        message: message.to_string(),
+        background_audit: true,
+        failure_policy: options.failure_policy.clone(),
+        mode: options.mode.clone(),
+        namespace_selector: options.namespace_selector.clone(),
+        object_selector: options.object_selector.clone(),
+        ..Default::default()
+    };
+    for member in members {
+        if !is_group_member_name(&member.name) {
+            return Err(anyhow!(
+                "invalid policy name '{}': use letters, digits and '_', or name the policy via NAME=URI",
+                member.name
+            ));
+        }
+        member.metadata.validate()?;
+        if member.metadata.mutating {
+            return Err(anyhow!(
+                "{} is a mutating policy, policy groups can only contain validating policies",
+                member.uri
+            ));
+        }
+
+        // the group is evaluated when any of its policies is interested in the request
+        for rule in member.metadata.rules {
+            if !spec.rules.contains(&rule) {
+                spec.rules.push(rule);
+            }
+        }
+        spec.background_audit &= member.metadata.background_audit;
+
+        let mut context_aware_resources = member.metadata.context_aware_resources;
+        if !context_aware_resources.is_empty() {
+            match resource_type {
+                ManifestType::ClusterAdmissionPolicyGroup if allow_context_aware_resources => {
+                    warn!(policy = member.name.as_str(), "Policy has been granted access to the Kubernetes resources mentioned by its metadata.");
+                    warn!("Carefully review the contents of the `contextAwareResources` attribute for abuses.");
+                }
+                ManifestType::ClusterAdmissionPolicyGroup => {
+                    warn!(policy = member.name.as_str(), "Policy requires access to Kubernetes resources at evaluation time. For safety reasons, the `contextAwareResources` attribute has been left empty.");
+                    context_aware_resources = BTreeSet::new();
+                }
+                _ => {
+                    warn!(policy = member.name.as_str(), "Policy requires access to Kubernetes resources at evaluation time, which is allowed only inside of a ClusterAdmissionPolicyGroup.");
+                    context_aware_resources = BTreeSet::new();
+                }
+            }
+        }
+
+        let group_member = PolicyGroupMember {
+            module: member.uri,
+            settings: member.settings,
+            context_aware_resources,
+        };
+        if spec
+            .policies
+            .insert(member.name.clone(), group_member)
+            .is_some()
+        {
+            return Err(anyhow!(
+                "the group contains more policies named '{}', name them via NAME=URI",
+                member.name
+            ));
+        }
+    }
+
+    spec.background_audit = options.background_audit(spec.background_audit)?;
+
+    let called = called_policies(expression);
+    if let Some(name) = called
+        .iter()
+        .find(|name| !spec.policies.contains_key(*name))
+    {
+        return Err(anyhow!(
+            "the expression calls {}(), which is not a policy of the group",
+            name
+        ));
+    }
+    for name in spec.policies.keys().filter(|name| !called.contains(*name)) {
+        warn!(
+            policy = name.as_str(),
+            "Policy is not used by the expression of the group"
+        );
+    }
+
+    let metadata = ObjectMeta {
+        name: policy_title.map(String::from),
+        ..Default::default()
+    };
+    let resource = match resource_type {
+        ManifestType::ClusterAdmissionPolicyGroup => {
+            serde_yaml::to_value(ClusterAdmissionPolicyGroup {
+                api_version: String::from("policies.kubewarden.io/v1"),
+                kind: String::from("ClusterAdmissionPolicyGroup"),
+                metadata,
+                spec,
+            })
+        }
+        ManifestType::AdmissionPolicyGroup => serde_yaml::to_value(AdmissionPolicyGroup {
+            api_version: String::from("policies.kubewarden.io/v1"),
+            kind: String::from("AdmissionPolicyGroup"),
+            metadata,
+            spec,
+        }),
+        ManifestType::ClusterAdmissionPolicy | ManifestType::AdmissionPolicy => {
+            return Err(anyhow!("the manifest type is not a policy group"))
+        }
+    };
+    resource.map_err(|e| anyhow!("{}", e))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use policy_evaluator::policy_metadata::ContextAwareResource;
+    use policy_evaluator::policy_metadata::{ContextAwareResource, Rule};
+    use rstest::rstest;
 
     fn mock_metadata_with_no_annotations() -> Metadata {
         Metadata {
@@ -329,6 +741,7 @@ mod tests {
             policy_title: Some("test".to_string()),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let obj_metadata = build_objmetadata(scaffold_data);
@@ -344,6 +757,7 @@ mod tests {
             policy_title: Some("test".to_string()),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let obj_metadata = build_objmetadata(scaffold_data);
@@ -380,6 +794,7 @@ mod tests {
             policy_title: get_policy_title_from_cli_or_metadata(Some(policy_title), &metadata),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let out = serde_yaml::to_string(
@@ -410,6 +825,7 @@ mod tests {
             policy_title: get_policy_title_from_cli_or_metadata(Some(policy_title), &metadata),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let out = serde_yaml::to_string(
@@ -444,6 +860,7 @@ mod tests {
             policy_title: get_policy_title_from_cli_or_metadata(Some(policy_title), &metadata),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let resource =
@@ -474,6 +891,7 @@ mod tests {
             policy_title: get_policy_title_from_cli_or_metadata(Some(policy_title), &metadata),
             metadata,
             settings: Default::default(),
+            options: Default::default(),
         };
 
         let resource =
@@ -486,6 +904,292 @@ mod tests {
         assert!(context_aware_resources.is_none());
     }
 
+    fn group_member_with(name: &str, metadata: Metadata) -> GroupMember {
+        GroupMember {
+            name: name.to_string(),
+            uri: format!("registry://ghcr.io/kubewarden/tests/{name}:v1.0.0"),
+            metadata,
+            settings: Default::default(),
+        }
+    }
+
+    fn validating_metadata(rules: Vec<Rule>) -> Metadata {
+        let mut metadata = mock_metadata_with_no_annotations();
+        metadata.protocol_version = Some(policy_evaluator::ProtocolVersion::V1);
+        metadata.rules = rules;
+        metadata
+    }
+
+    fn rule(resource: &str) -> Rule {
+        serde_yaml::from_str(&format!(
+            "{{apiGroups: [''], apiVersions: [v1], resources: [{resource}], operations: [CREATE]}}"
+        ))
+        .expect("invalid rule")
+    }
+
+    #[rstest]
+    #[case::named(
+        "labels=registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13",
+        "labels",
+        "registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13"
+    )]
+    #[case::from_uri(
+        "registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13",
+        "safe_labels",
+        "registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13"
+    )]
+    #[case::digest(
+        "registry://ghcr.io/kubewarden/policies/safe-labels@sha256:0123",
+        "safe_labels",
+        "registry://ghcr.io/kubewarden/policies/safe-labels@sha256:0123"
+    )]
+    #[case::file(
+        "file:///tmp/pod-privileged.wasm",
+        "pod_privileged",
+        "file:///tmp/pod-privileged.wasm"
+    )]
+    fn group_member_names(#[case] arg: &str, #[case] name: &str, #[case] uri: &str) {
+        assert_eq!(group_member(arg), (name.to_string(), uri.to_string()));
+    }
+
+    #[test]
+    fn scaffold_policy_group() {
+        let members = vec![
+            group_member_with("privileged", validating_metadata(vec![rule("pods")])),
+            group_member_with(
+                "labels",
+                validating_metadata(vec![rule("pods"), rule("services")]),
+            ),
+        ];
+
+        let resource = generate_group_yaml_resource(
+            members,
+            ManifestType::ClusterAdmissionPolicyGroup,
+            "privileged() && labels()",
+            "the request is rejected",
+            Some("my-group"),
+            false,
+            Default::default(),
+        )
+        .expect("cannot create the policy group");
+
+        assert_eq!(resource["kind"], "ClusterAdmissionPolicyGroup");
+        assert_eq!(resource["metadata"]["name"], "my-group");
+        assert_eq!(resource["spec"]["expression"], "privileged() && labels()");
+        assert_eq!(
+            resource["spec"]["rules"].as_sequence().unwrap().len(),
+            2,
+            "the rules of the policies are merged"
+        );
+        assert_eq!(
+            resource["spec"]["policies"]["labels"]["module"],
+            "registry://ghcr.io/kubewarden/tests/labels:v1.0.0"
+        );
+        assert!(resource["spec"]["policies"]["privileged"]
+            .get("settings")
+            .is_some());
+    }
+
+    #[rstest]
+    #[case::unknown_policy("privileged() && missing()", "missing()")]
+    #[case::empty_expression(" ", "cannot be empty")]
+    fn invalid_policy_group_expression(#[case] expression: &str, #[case] error: &str) {
+        let members = vec![group_member_with(
+            "privileged",
+            validating_metadata(vec![rule("pods")]),
+        )];
+
+        let result = generate_group_yaml_resource(
+            members,
+            ManifestType::AdmissionPolicyGroup,
+            expression,
+            "the request is rejected",
+            None,
+            false,
+            Default::default(),
+        );
+        assert!(result.unwrap_err().to_string().contains(error));
+    }
+
+    #[test]
+    fn policy_group_cannot_contain_mutating_policies() {
+        let mut metadata = validating_metadata(vec![rule("pods")]);
+        metadata.mutating = true;
+
+        let result = generate_group_yaml_resource(
+            vec![group_member_with("mutating", metadata)],
+            ManifestType::ClusterAdmissionPolicyGroup,
+            "mutating()",
+            "the request is rejected",
+            None,
+            false,
+            Default::default(),
+        );
+        assert!(result.unwrap_err().to_string().contains("mutating policy"));
+    }
+
+    fn scaffold_data_with_options(options: ManifestOptions) -> ScaffoldPolicyData {
+        let mut metadata = mock_metadata_with_title("test");
+        metadata.protocol_version = Some(policy_evaluator::ProtocolVersion::V1);
+        ScaffoldPolicyData {
+            uri: "not_relevant".to_string(),
+            policy_title: Some("test".to_string()),
+            metadata,
+            settings: Default::default(),
+            options,
+        }
+    }
+
+    #[test]
+    fn scaffold_cluster_admission_policy_with_options() {
+        let options = ManifestOptions {
+            policy_server: Some("reserved".to_string()),
+            mode: Some("monitor".to_string()),
+            failure_policy: Some("Ignore".to_string()),
+            background_audit: Some(false),
+            namespace_selector: Some(
+                crate::scaffold::label_selector::parse("environment=production").unwrap(),
+            ),
+            object_selector: Some(crate::scaffold::label_selector::parse("!deprecated").unwrap()),
+        };
+
+        let resource = generate_yaml_resource(
+            scaffold_data_with_options(options),
+            ManifestType::ClusterAdmissionPolicy,
+            false,
+        )
+        .expect("Cannot create yaml resource");
+
+        let spec = &resource["spec"];
+        assert_eq!(spec["policyServer"], "reserved");
+        assert_eq!(spec["mode"], "monitor");
+        assert_eq!(spec["failurePolicy"], "Ignore");
+        assert_eq!(spec["backgroundAudit"], false);
+        assert_eq!(
+            spec["namespaceSelector"]["matchLabels"]["environment"],
+            "production"
+        );
+        assert_eq!(
+            spec["objectSelector"]["matchExpressions"][0]["operator"],
+            "DoesNotExist"
+        );
+    }
+
+    #[test]
+    fn admission_policy_cannot_select_namespaces() {
+        let options = ManifestOptions {
+            namespace_selector: Some(
+                crate::scaffold::label_selector::parse("environment=production").unwrap(),
+            ),
+            ..Default::default()
+        };
+
+        let result = generate_yaml_resource(
+            scaffold_data_with_options(options),
+            ManifestType::AdmissionPolicy,
+            false,
+        );
+        assert!(result.unwrap_err().to_string().contains("namespaced"));
+    }
+
+    #[test]
+    fn background_audit_requires_policy_support() {
+        let options = ManifestOptions {
+            background_audit: Some(true),
+            ..Default::default()
+        };
+        let mut scaffold_data = scaffold_data_with_options(options);
+        scaffold_data.metadata.background_audit = false;
+
+        assert!(
+            ClusterAdmissionPolicy::try_from(scaffold_data).is_err(),
+            "the policy does not support background audit"
+        );
+    }
+
+    #[test]
+    fn scaffold_policy_group_with_context_aware_resources() {
+        let mut metadata = validating_metadata(vec![rule("pods")]);
+        metadata.context_aware_resources = BTreeSet::from([ContextAwareResource {
+            api_version: "v1".to_string(),
+            kind: "Namespace".to_string(),
+        }]);
+
+        for (resource_type, allow_context_aware_resources, expected) in [
+            (ManifestType::ClusterAdmissionPolicyGroup, true, true),
+            (ManifestType::ClusterAdmissionPolicyGroup, false, false),
+            (ManifestType::AdmissionPolicyGroup, true, false),
+        ] {
+            let resource = generate_group_yaml_resource(
+                vec![group_member_with("namespaces", metadata.clone())],
+                resource_type,
+                "namespaces()",
+                "the request is rejected",
+                None,
+                allow_context_aware_resources,
+                Default::default(),
+            )
+            .expect("cannot create the policy group");
+
+            assert_eq!(
+                resource["spec"]["policies"]["namespaces"]
+                    .get("contextAwareResources")
+                    .is_some(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn read_requirements_file() {
+        let requirements = read_requirements(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/manifest-requirements.yml"),
+        )
+        .unwrap();
+
+        assert_eq!(requirements.len(), 2);
+        assert!(requirements[0].title.is_none());
+        assert!(requirements[0].settings.is_empty());
+        assert_eq!(requirements[1].title.as_deref(), Some("no-foo-labels"));
+        assert!(requirements[1].settings.contains_key("denied_labels"));
+    }
+
+    #[rstest]
+    #[case::empty("policies: []\n")]
+    #[case::unknown_field(
+        "policies:\n  - uri: registry://example.com/policy:v1\n    mode: monitor\n"
+    )]
+    fn invalid_requirements_file(#[case] contents: &str) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("policies.yml");
+        fs::write(&path, contents).unwrap();
+        assert!(read_requirements(&path).is_err());
+    }
+
+    #[test]
+    fn batch_manifest_rejects_duplicated_names() {
+        let requirement = |title: &str| PolicyRequirement {
+            uri: "not_relevant".to_string(),
+            title: Some(title.to_string()),
+            settings: Default::default(),
+        };
+        let mut metadata = mock_metadata_with_no_annotations();
+        metadata.protocol_version = Some(policy_evaluator::ProtocolVersion::V1);
+        let policies = vec![
+            (requirement("test"), "a".to_string(), metadata.clone()),
+            (requirement("test"), "b".to_string(), metadata),
+        ];
+
+        let result = batch_manifest(
+            policies,
+            ManifestType::ClusterAdmissionPolicy,
+            false,
+            Default::default(),
+            &ManifestOutput::Yaml,
+        );
+        assert!(result.unwrap_err().to_string().contains("more policies"));
+    }
+
     #[test]
     fn test_manifest_with_invalid_policy_title() {
         // Test the validation function directly
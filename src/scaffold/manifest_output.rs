@@ -0,0 +1,438 @@
+//! Formats of the resources generated by `kwctl scaffold manifest`, so that
+//! they can be added to GitOps repositories managed via Kustomize or Helm.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde_yaml::{Mapping, Value};
+
+use crate::store_sync::write_atomically;
+
+const KUSTOMIZATION_FILE: &str = "kustomization.yaml";
+
+/// Where and how the generated resource is written
+pub(crate) enum ManifestOutput {
+    /// The manifest, printed to the standard output
+    Yaml,
+    /// A kustomization directory, holding the manifest and listing it among
+    /// its resources
+    Kustomize(PathBuf),
+    /// The values of the kubewarden-defaults chart deploying the policy,
+    /// printed to the standard output
+    Helm,
+}
+
+/// Writes the resources. More resources are printed as a multi-document
+/// YAML, and their chart values are merged together.
+pub(crate) fn write(resources: &[Value], output: &ManifestOutput) -> Result<()> {
+    match output {
+        ManifestOutput::Yaml => {
+            if let [resource] = resources {
+                let stdout = std::io::stdout();
+                let out = stdout.lock();
+                serde_yaml::to_writer(out, resource)?;
+            } else {
+                for resource in resources {
+                    print!("---\n{}", serde_yaml::to_string(resource)?);
+                }
+            }
+        }
+        ManifestOutput::Kustomize(directory) => {
+            for resource in resources {
+                let path = write_kustomization(resource, directory)?;
+                println!("{} added to {}", path.display(), directory.display());
+            }
+        }
+        ManifestOutput::Helm => {
+            let values = chart_values(resources)?;
+            let stdout = std::io::stdout();
+            let out = stdout.lock();
+            serde_yaml::to_writer(out, &values)?;
+        }
+    }
+    Ok(())
+}
+
+fn resource_name(resource: &Value) -> Option<&str> {
+    resource
+        .get("metadata")
+        .and_then(|metadata| metadata.get("name"))
+        .and_then(Value::as_str)
+}
+
+/// Writes the manifest into the directory and adds it to the resources of
+/// its kustomization, which is created when missing. Returns the path of the
+/// manifest.
+fn write_kustomization(resource: &Value, directory: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(directory)
+        .map_err(|e| anyhow!("cannot create {}: {}", directory.display(), e))?;
+
+    let file_name = format!(
+        "{}.yaml",
+        resource_name(resource).unwrap_or("generated-policy")
+    );
+    let manifest_path = directory.join(&file_name);
+    write_atomically(&manifest_path, serde_yaml::to_string(resource)?.as_bytes())?;
+
+    let kustomization_path = directory.join(KUSTOMIZATION_FILE);
+    let mut kustomization: Value = match fs::read_to_string(&kustomization_path) {
+        Ok(contents) => serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("cannot parse {}: {}", kustomization_path.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Value::Mapping(Mapping::from_iter([
+            (
+                "apiVersion".into(),
+                "kustomize.config.k8s.io/v1beta1".into(),
+            ),
+            ("kind".into(), "Kustomization".into()),
+        ])),
+        Err(e) => {
+            return Err(anyhow!(
+                "cannot read {}: {}",
+                kustomization_path.display(),
+                e
+            ))
+        }
+    };
+
+    let kustomization_mapping = kustomization.as_mapping_mut().ok_or_else(|| {
+        anyhow!(
+            "{} is not a valid kustomization",
+            kustomization_path.display()
+        )
+    })?;
+    if !kustomization_mapping.contains_key("resources") {
+        kustomization_mapping.insert("resources".into(), Value::Sequence(vec![]));
+    }
+    let Some(Value::Sequence(resources)) = kustomization_mapping.get_mut("resources") else {
+        return Err(anyhow!(
+            "the resources of {} are not a list",
+            kustomization_path.display()
+        ));
+    };
+    let file_name = Value::from(file_name);
+    if !resources.contains(&file_name) {
+        resources.push(file_name);
+    }
+    write_atomically(
+        &kustomization_path,
+        serde_yaml::to_string(&kustomization)?.as_bytes(),
+    )?;
+
+    Ok(manifest_path)
+}
+
+/// A policy deployed by the kubewarden-defaults chart, from the
+/// `recommendedPolicies.<key>` values
+struct ChartPolicy {
+    key: &'static str,
+    /// Repository of the module, relative to the registry set by the
+    /// `global.cattle.systemDefaultRegistry` value
+    repository: &'static str,
+    /// The settings exposed as values, the other ones are fixed by the chart
+    settings: &'static [&'static str],
+}
+
+/// The recommended policies of the kubewarden-defaults chart, as laid out by
+/// its `values.yaml`
+const CHART_POLICIES: &[ChartPolicy] = &[
+    ChartPolicy {
+        key: "allowPrivilegeEscalationPolicy",
+        repository: "kubewarden/policies/allow-privilege-escalation-psp",
+        settings: &[],
+    },
+    ChartPolicy {
+        key: "hostNamespacePolicy",
+        repository: "kubewarden/policies/host-namespaces-psp",
+        settings: &[],
+    },
+    ChartPolicy {
+        key: "podPrivilegedPolicy",
+        repository: "kubewarden/policies/pod-privileged",
+        settings: &[],
+    },
+    ChartPolicy {
+        key: "hostPathsPolicy",
+        repository: "kubewarden/policies/hostpaths-psp",
+        settings: &["paths"],
+    },
+    ChartPolicy {
+        key: "capabilitiesPolicy",
+        repository: "kubewarden/policies/capabilities-psp",
+        settings: &[
+            "allowed_capabilities",
+            "required_drop_capabilities",
+            "default_add_capabilities",
+        ],
+    },
+    ChartPolicy {
+        key: "userGroupPolicy",
+        repository: "kubewarden/policies/user-group-psp",
+        settings: &["run_as_user", "run_as_group", "supplemental_groups"],
+    },
+];
+
+/// Registry the chart pulls the policies from, unless
+/// `global.cattle.systemDefaultRegistry` is set
+const CHART_DEFAULT_REGISTRY: &str = "ghcr.io";
+
+/// Values of the kubewarden-defaults chart deploying a recommended policy
+#[derive(Debug, PartialEq)]
+struct HelmValues {
+    key: &'static str,
+    registry: String,
+    policy: Mapping,
+}
+
+/// The kubewarden-defaults chart deploys only its recommended policies, from
+/// the `recommendedPolicies.<name>Policy` values: the `pod-privileged` policy
+/// is deployed from `recommendedPolicies.podPrivilegedPolicy`. The values hold
+/// the name of the policy, the repository and the tag of its module, and only
+/// the settings exposed by the chart.
+fn helm_values(resource: &Value) -> Result<HelmValues> {
+    if resource.get("kind").and_then(Value::as_str) != Some("ClusterAdmissionPolicy") {
+        return Err(anyhow!(
+            "the kubewarden-defaults chart deploys only ClusterAdmissionPolicies"
+        ));
+    }
+    let name = resource_name(resource)
+        .ok_or_else(|| anyhow!("the policy has no name, set one via --title"))?;
+    let spec = resource
+        .get("spec")
+        .ok_or_else(|| anyhow!("the policy has no spec"))?;
+    let module = spec
+        .get("module")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("the policy has no module"))?;
+
+    let reference = module.trim_start_matches("registry://");
+    let (registry, path) = reference
+        .split_once('/')
+        .ok_or_else(|| anyhow!("cannot find the registry of the module {}", module))?;
+    let (repository, tag) = path
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("the module {} has no tag", module))?;
+    let chart_policy = CHART_POLICIES
+        .iter()
+        .find(|chart_policy| chart_policy.repository == repository)
+        .ok_or_else(|| {
+            anyhow!(
+                "the kubewarden-defaults chart cannot deploy {}, it deploys only its recommended policies: {}",
+                module,
+                CHART_POLICIES
+                    .iter()
+                    .map(|chart_policy| chart_policy.repository)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+
+    let mut policy = Mapping::new();
+    policy.insert(
+        "module".into(),
+        Value::Mapping(Mapping::from_iter([
+            ("repository".into(), repository.into()),
+            ("tag".into(), tag.into()),
+        ])),
+    );
+    policy.insert("name".into(), name.into());
+    if let Some(settings) = spec.get("settings").and_then(Value::as_mapping) {
+        for (setting, value) in settings {
+            let exposed = setting
+                .as_str()
+                .filter(|setting| chart_policy.settings.contains(setting))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "the kubewarden-defaults chart does not expose the setting {} of {}",
+                        serde_yaml::to_string(setting).unwrap_or_default().trim(),
+                        chart_policy.key
+                    )
+                })?;
+            policy.insert(exposed.into(), value.clone());
+        }
+    }
+
+    Ok(HelmValues {
+        key: chart_policy.key,
+        registry: registry.to_string(),
+        policy,
+    })
+}
+
+/// Values of the kubewarden-defaults chart deploying all the policies. The
+/// chart pulls all of them from the same registry.
+fn chart_values(resources: &[Value]) -> Result<Value> {
+    let mut registry: Option<String> = None;
+    let mut recommended_policies = Mapping::new();
+    for resource in resources {
+        let values = helm_values(resource)?;
+        match &registry {
+            Some(registry) if *registry != values.registry => {
+                return Err(anyhow!(
+                    "the kubewarden-defaults chart pulls all the policies from the same registry, found both {} and {}",
+                    registry,
+                    values.registry
+                ));
+            }
+            Some(_) => {}
+            None => registry = Some(values.registry),
+        }
+        recommended_policies.insert(values.key.into(), Value::Mapping(values.policy));
+    }
+
+    let mut chart_values = Mapping::from_iter([(
+        "recommendedPolicies".into(),
+        Value::Mapping(recommended_policies),
+    )]);
+    if let Some(registry) = registry.filter(|registry| registry != CHART_DEFAULT_REGISTRY) {
+        chart_values.insert(
+            "global".into(),
+            Value::Mapping(Mapping::from_iter([(
+                "cattle".into(),
+                Value::Mapping(Mapping::from_iter([(
+                    "systemDefaultRegistry".into(),
+                    registry.into(),
+                )])),
+            )])),
+        );
+    }
+    Ok(Value::Mapping(chart_values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn cluster_admission_policy(name: &str) -> Value {
+        cluster_admission_policy_of(
+            name,
+            "registry://ghcr.io/kubewarden/policies/capabilities-psp:v0.1.13",
+        )
+    }
+
+    fn cluster_admission_policy_of(name: &str, module: &str) -> Value {
+        serde_yaml::from_str(&format!(
+            r#"
+apiVersion: policies.kubewarden.io/v1
+kind: ClusterAdmissionPolicy
+metadata:
+  name: {name}
+spec:
+  module: {module}
+  settings:
+    required_drop_capabilities: [ALL]
+  rules: []
+  mutating: false
+"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn helm_values_of_a_policy() {
+        let values = helm_values(&cluster_admission_policy("drop-capabilities")).unwrap();
+        assert_eq!(values.key, "capabilitiesPolicy");
+        assert_eq!(values.registry, "ghcr.io");
+        assert_eq!(
+            Value::Mapping(values.policy),
+            serde_yaml::from_str::<Value>(
+                r#"
+module:
+  repository: kubewarden/policies/capabilities-psp
+  tag: v0.1.13
+name: drop-capabilities
+required_drop_capabilities: [ALL]
+"#
+            )
+            .unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::admission_policy(
+        "AdmissionPolicy",
+        "registry://ghcr.io/kubewarden/policies/capabilities-psp:v0.1.13"
+    )]
+    #[case::not_recommended(
+        "ClusterAdmissionPolicy",
+        "registry://ghcr.io/kubewarden/policies/safe-labels:v0.1.13"
+    )]
+    #[case::setting_not_exposed(
+        "ClusterAdmissionPolicy",
+        "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5"
+    )]
+    #[case::no_tag(
+        "ClusterAdmissionPolicy",
+        "registry://ghcr.io/kubewarden/policies/capabilities-psp"
+    )]
+    fn helm_values_of_unsupported_policies(#[case] kind: &str, #[case] module: &str) {
+        let mut resource = cluster_admission_policy_of("policy", module);
+        resource["kind"] = kind.into();
+        assert!(helm_values(&resource).is_err());
+    }
+
+    #[test]
+    fn chart_values_of_a_mirror() {
+        let mut escalation = cluster_admission_policy_of(
+            "no-privilege-escalation",
+            "registry://mirror.example.com/kubewarden/policies/allow-privilege-escalation-psp:v0.2.6",
+        );
+        // the chart exposes no settings of this policy
+        escalation["spec"]["settings"] = Value::Mapping(Mapping::new());
+        let values = chart_values(&[
+            cluster_admission_policy_of(
+                "drop-capabilities",
+                "registry://mirror.example.com/kubewarden/policies/capabilities-psp:v0.1.13",
+            ),
+            escalation,
+        ])
+        .unwrap();
+        assert_eq!(
+            values["global"]["cattle"]["systemDefaultRegistry"],
+            "mirror.example.com"
+        );
+        assert_eq!(
+            values["recommendedPolicies"]["allowPrivilegeEscalationPolicy"]["module"]["tag"],
+            "v0.2.6"
+        );
+        assert_eq!(
+            values["recommendedPolicies"]["capabilitiesPolicy"]["name"],
+            "drop-capabilities"
+        );
+    }
+
+    #[test]
+    fn chart_values_require_a_single_registry() {
+        let values = chart_values(&[
+            cluster_admission_policy("drop-capabilities"),
+            cluster_admission_policy_of(
+                "capabilities",
+                "registry://mirror.example.com/kubewarden/policies/capabilities-psp:v0.1.13",
+            ),
+        ]);
+        assert!(values.is_err());
+    }
+
+    #[test]
+    fn kustomization_lists_the_manifests() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let directory = tempdir.path().join("policies");
+
+        for name in ["safe-labels", "pod-privileged", "safe-labels"] {
+            write_kustomization(&cluster_admission_policy(name), &directory).unwrap();
+        }
+
+        let kustomization: Value =
+            serde_yaml::from_str(&fs::read_to_string(directory.join(KUSTOMIZATION_FILE)).unwrap())
+                .unwrap();
+        assert_eq!(kustomization["kind"], "Kustomization");
+        assert_eq!(
+            kustomization["resources"],
+            serde_yaml::from_str::<Value>("[safe-labels.yaml, pod-privileged.yaml]").unwrap()
+        );
+        assert!(directory.join("pod-privileged.yaml").exists());
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+
+/// Languages of the policy projects we can scaffold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Language {
+    Rust,
+    Go,
+    Rego,
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(Language::Rust),
+            "go" => Ok(Language::Go),
+            "rego" => Ok(Language::Rego),
+            _ => Err(format!("Invalid language: {}", s)),
+        }
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Language::Rust => write!(f, "rust"),
+            Language::Go => write!(f, "go"),
+            Language::Rego => write!(f, "rego"),
+        }
+    }
+}
+
+/// Files shared by all the languages, as path and contents
+const COMMON_FILES: &[(&str, &str)] = &[
+    (
+        "README.md",
+        include_str!("../../templates/policy/common/README.md"),
+    ),
+    (
+        ".gitignore",
+        include_str!("../../templates/policy/common/gitignore"),
+    ),
+    (
+        "metadata.yml",
+        include_str!("../../templates/policy/common/metadata.yml"),
+    ),
+    (
+        "test_data/pod_creation.json",
+        include_str!("../../templates/policy/common/pod_creation.json"),
+    ),
+    (
+        "test_data/settings.json",
+        include_str!("../../templates/policy/common/settings.json"),
+    ),
+];
+
+const RUST_FILES: &[(&str, &str)] = &[
+    (
+        "Cargo.toml",
+        include_str!("../../templates/policy/rust/Cargo.toml.tmpl"),
+    ),
+    (
+        "Makefile",
+        include_str!("../../templates/policy/rust/Makefile"),
+    ),
+    (
+        "src/lib.rs",
+        include_str!("../../templates/policy/rust/src/lib.rs"),
+    ),
+    (
+        "src/settings.rs",
+        include_str!("../../templates/policy/rust/src/settings.rs"),
+    ),
+];
+
+const GO_FILES: &[(&str, &str)] = &[
+    ("go.mod", include_str!("../../templates/policy/go/go.mod")),
+    (
+        "Makefile",
+        include_str!("../../templates/policy/go/Makefile"),
+    ),
+    ("main.go", include_str!("../../templates/policy/go/main.go")),
+    (
+        "settings.go",
+        include_str!("../../templates/policy/go/settings.go"),
+    ),
+    (
+        "validate.go",
+        include_str!("../../templates/policy/go/validate.go"),
+    ),
+    (
+        "validate_test.go",
+        include_str!("../../templates/policy/go/validate_test.go"),
+    ),
+];
+
+const REGO_FILES: &[(&str, &str)] = &[
+    (
+        "Makefile",
+        include_str!("../../templates/policy/rego/Makefile"),
+    ),
+    (
+        "policy.rego",
+        include_str!("../../templates/policy/rego/policy.rego"),
+    ),
+    (
+        "policy_test.rego",
+        include_str!("../../templates/policy/rego/policy_test.rego"),
+    ),
+];
+
+impl Language {
+    fn files(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::Rust => RUST_FILES,
+            Language::Go => GO_FILES,
+            Language::Rego => REGO_FILES,
+        }
+    }
+
+    fn execution_mode(&self) -> &'static str {
+        match self {
+            Language::Rust | Language::Go => "kubewarden-wapc",
+            Language::Rego => "opa",
+        }
+    }
+}
+
+/// Policy names are used as crate and Go module names, hence they are made
+/// of lowercase letters, digits and dashes, starting with a letter
+fn validate_name(name: &str) -> Result<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(anyhow!(
+            "invalid policy name '{}': only lowercase letters, digits and dashes are allowed, starting with a letter",
+            name
+        ));
+    }
+    Ok(())
+}
+
+fn render(template: &str, language: Language, name: &str) -> String {
+    template
+        .replace("{{name}}", name)
+        .replace("{{crate_name}}", &name.replace('-', "_"))
+        .replace("{{execution_mode}}", language.execution_mode())
+}
+
+/// Generates a policy project into `output_dir`, which defaults to a
+/// directory named after the policy. The directory must not exist or be
+/// empty.
+pub(crate) fn policy(language: Language, name: &str, output_dir: Option<PathBuf>) -> Result<()> {
+    validate_name(name)?;
+    let output_dir = output_dir.unwrap_or_else(|| PathBuf::from(name));
+    if fs::read_dir(&output_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(anyhow!(
+            "cannot scaffold the policy into {}: the directory is not empty",
+            output_dir.display()
+        ));
+    }
+
+    for (path, template) in COMMON_FILES.iter().chain(language.files()) {
+        let path = output_dir.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| anyhow!("cannot create {}: {}", parent.display(), e))?;
+        }
+        fs::write(&path, render(template, language, name))
+            .map_err(|e| anyhow!("cannot write {}: {}", path.display(), e))?;
+    }
+
+    println!(
+        "{} policy {} scaffolded into {}",
+        language,
+        name,
+        output_dir.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::policy_metadata::Metadata;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::rust(Language::Rust, &["Cargo.toml", "src/lib.rs", "src/settings.rs"])]
+    #[case::go(Language::Go, &["go.mod", "main.go", "validate.go", "settings.go"])]
+    #[case::rego(Language::Rego, &["policy.rego", "policy_test.rego"])]
+    fn scaffold_policy(#[case] language: Language, #[case] expected: &[&str]) {
+        let tempdir = tempfile::tempdir().unwrap();
+        let output_dir = tempdir.path().join("my-policy");
+        policy(language, "my-policy", Some(output_dir.clone())).unwrap();
+
+        for path in expected.iter().chain(&[
+            "Makefile",
+            "README.md",
+            "metadata.yml",
+            "test_data/pod_creation.json",
+        ]) {
+            assert!(output_dir.join(path).exists(), "{path} is missing");
+        }
+
+        let metadata: Metadata =
+            serde_yaml::from_str(&fs::read_to_string(output_dir.join("metadata.yml")).unwrap())
+                .unwrap();
+        assert_eq!(
+            metadata
+                .annotations
+                .unwrap()
+                .get("io.kubewarden.policy.title"),
+            Some(&"my-policy".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(metadata.execution_mode).unwrap(),
+            serde_json::Value::from(language.execution_mode())
+        );
+    }
+
+    #[test]
+    fn crate_name_is_rendered() {
+        assert_eq!(
+            render("{{name}} {{crate_name}}", Language::Rust, "my-policy"),
+            "my-policy my_policy"
+        );
+    }
+
+    #[rstest]
+    #[case::valid("my-policy2", true)]
+    #[case::uppercase("MyPolicy", false)]
+    #[case::leading_digit("2-policy", false)]
+    #[case::underscore("my_policy", false)]
+    #[case::empty("", false)]
+    fn policy_names(#[case] name: &str, #[case] valid: bool) {
+        assert_eq!(validate_name(name).is_ok(), valid);
+    }
+
+    #[test]
+    fn non_empty_directory_is_rejected() {
+        let tempdir = tempfile::tempdir().unwrap();
+        fs::write(tempdir.path().join("file"), "").unwrap();
+        assert!(policy(
+            Language::Rego,
+            "my-policy",
+            Some(tempdir.path().to_path_buf())
+        )
+        .is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Result};
-use k8s_openapi::api::admissionregistration::v1::{
-    ValidatingAdmissionPolicy, ValidatingAdmissionPolicyBinding,
+use k8s_openapi::{
+    api::admissionregistration::v1::{ValidatingAdmissionPolicy, ValidatingAdmissionPolicyBinding},
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
 };
 use policy_evaluator::{policy_fetcher::oci_client::Reference, policy_metadata::Rule};
 use std::{collections::BTreeSet, convert::TryFrom, fs::File, path::Path};
@@ -8,7 +9,12 @@ use tracing::warn;
 
 use crate::scaffold::kubewarden_crds::{ClusterAdmissionPolicy, ClusterAdmissionPolicySpec};
 
-pub(crate) fn vap(cel_policy_module: &str, vap_path: &Path, binding_path: &Path) -> Result<()> {
+pub(crate) fn vap(
+    cel_policy_module: &str,
+    vap_path: &Path,
+    binding_path: &Path,
+    params_path: Option<&Path>,
+) -> Result<()> {
     let vap_file = File::open(vap_path)
         .map_err(|e| anyhow!("cannot open {}: #{e}", vap_path.to_str().unwrap()))?;
     let binding_file = File::open(binding_path)
@@ -21,6 +27,15 @@ pub(crate) fn vap(cel_policy_module: &str, vap_path: &Path, binding_path: &Path)
             anyhow!("cannot convert given data into a ValidatingAdmissionPolicyBinding: #{e}")
         })?;
 
+    let params: Option<serde_yaml::Value> = params_path
+        .map(|params_path| {
+            let params_file = File::open(params_path)
+                .map_err(|e| anyhow!("cannot open {}: #{e}", params_path.display()))?;
+            serde_yaml::from_reader(params_file)
+                .map_err(|e| anyhow!("cannot parse {}: #{e}", params_path.display()))
+        })
+        .transpose()?;
+
     match cel_policy_module.parse::<Reference>() {
         Ok(cel_policy_ref) => match cel_policy_ref.tag() {
             None | Some("latest") => {
@@ -36,7 +51,7 @@ pub(crate) fn vap(cel_policy_module: &str, vap_path: &Path, binding_path: &Path)
     }
 
     let cluster_admission_policy =
-        convert_vap_to_cluster_admission_policy(cel_policy_module, vap, vap_binding)?;
+        convert_vap_to_cluster_admission_policy(cel_policy_module, vap, vap_binding, params)?;
 
     serde_yaml::to_writer(std::io::stdout(), &cluster_admission_policy)?;
 
@@ -47,23 +62,52 @@ fn convert_vap_to_cluster_admission_policy(
     cel_policy_module: &str,
     vap: ValidatingAdmissionPolicy,
     vap_binding: ValidatingAdmissionPolicyBinding,
+    params: Option<serde_yaml::Value>,
 ) -> anyhow::Result<ClusterAdmissionPolicy> {
     let vap_spec = vap.spec.unwrap_or_default();
+    let binding_spec = vap_binding.spec.unwrap_or_default();
     if vap_spec.audit_annotations.is_some() {
         warn!("auditAnnotations are not supported by Kubewarden's CEL policy yet. They will be ignored.");
     }
     if vap_spec.match_conditions.is_some() {
         warn!("matchConditions are not supported by Kubewarden's CEL policy yet. They will be ignored.");
     }
-    if vap_spec.param_kind.is_some() {
-        // It's not safe to skip this, the policy will definitely not work.
-        return Err(anyhow!(
-            "paramKind is not supported by Kubewarden's CEL policy yet"
-        ));
-    }
 
     let mut settings = serde_yaml::Mapping::new();
 
+    // migrate the parameters: Kubewarden has no paramRef, the object
+    // referenced by the binding is embedded into the settings instead
+    match (vap_spec.param_kind, params) {
+        (Some(param_kind), Some(params)) => {
+            let kind = params.get("kind").and_then(serde_yaml::Value::as_str);
+            if let (Some(expected), Some(kind)) = (param_kind.kind.as_deref(), kind) {
+                if expected != kind {
+                    return Err(anyhow!(
+                        "the parameters are a {kind}, while the paramKind of the policy is {expected}"
+                    ));
+                }
+            }
+            settings.insert("params".into(), params);
+        }
+        (Some(param_kind), None) => {
+            // It's not safe to skip this, the policy will definitely not work.
+            let param_ref = binding_spec
+                .param_ref
+                .as_ref()
+                .and_then(|param_ref| param_ref.name.clone())
+                .unwrap_or_default();
+            return Err(anyhow!(
+                "the policy has a paramKind ({}), provide the {} object referenced by the binding with --params",
+                param_kind.kind.unwrap_or_default(),
+                param_ref
+            ));
+        }
+        (None, Some(_)) => {
+            warn!("The policy has no paramKind, the parameters will be ignored.");
+        }
+        (None, None) => {}
+    }
+
     // migrate CEL variables
     if let Some(vap_variables) = vap_spec.variables {
         let vap_variables: Vec<serde_yaml::Value> = vap_variables
@@ -82,16 +126,28 @@ fn convert_vap_to_cluster_admission_policy(
         settings.insert("validations".into(), kw_cel_validations.into());
     }
 
-    // VAP specifies the namespace selector inside of the binding
-    let namespace_selector = vap_binding
-        .spec
-        .unwrap_or_default()
-        .match_resources
-        .unwrap_or_default()
-        .namespace_selector;
-
-    // VAP rules are specified inside of the VAP object
+    // VAP rules are specified inside of the VAP object, the binding can only
+    // restrict them further
     let vap_match_constraints = vap_spec.match_constraints.unwrap_or_default();
+    let binding_match_resources = binding_spec.match_resources.unwrap_or_default();
+    if vap_match_constraints.exclude_resource_rules.is_some()
+        || binding_match_resources.exclude_resource_rules.is_some()
+    {
+        warn!("excludeResourceRules are not supported by Kubewarden. They will be ignored.");
+    }
+    if binding_match_resources.resource_rules.is_some() {
+        warn!("The resourceRules of the binding are ignored, the ones of the policy are used.");
+    }
+
+    // both the policy and the binding can select namespaces and objects
+    let namespace_selector = merge_selectors(
+        vap_match_constraints.namespace_selector,
+        binding_match_resources.namespace_selector,
+    )?;
+    let object_selector = merge_selectors(
+        vap_match_constraints.object_selector,
+        binding_match_resources.object_selector,
+    )?;
     let match_policy = vap_match_constraints.match_policy;
     let rules = vap_match_constraints
         .resource_rules
@@ -107,16 +163,17 @@ fn convert_vap_to_cluster_admission_policy(
         kind: "ClusterAdmissionPolicy".to_string(),
         metadata: vap_binding.metadata,
         spec: ClusterAdmissionPolicySpec {
+            policy_server: None,
             module: cel_policy_module.to_string(),
             namespace_selector,
             match_policy,
             rules,
-            object_selector: vap_match_constraints.object_selector,
+            object_selector,
             mutating: false,
             background_audit: true,
             context_aware_resources: BTreeSet::new(),
             failure_policy: vap_spec.failure_policy,
-            mode: None, // VAP policies are always in protect mode, which is the default for KW
+            mode: mode(binding_spec.validation_actions.unwrap_or_default()),
             settings,
         },
     };
@@ -124,6 +181,51 @@ fn convert_vap_to_cluster_admission_policy(
     Ok(cluster_admission_policy)
 }
 
+/// Bindings that deny the requests map to the protect mode, which is the
+/// default one. The Warn and Audit actions only report the violations, like
+/// the monitor mode does.
+fn mode(validation_actions: Vec<String>) -> Option<String> {
+    if validation_actions.is_empty() || validation_actions.iter().any(|a| a == "Deny") {
+        return None;
+    }
+    warn!(
+        "The binding does not deny the requests ({}), the policy will be deployed in monitor mode.",
+        validation_actions.join(", ")
+    );
+    Some("monitor".to_string())
+}
+
+/// A resource must match both the selectors: their labels and expressions
+/// are combined
+fn merge_selectors(
+    vap_selector: Option<LabelSelector>,
+    binding_selector: Option<LabelSelector>,
+) -> Result<Option<LabelSelector>> {
+    let (vap_selector, binding_selector) = match (vap_selector, binding_selector) {
+        (Some(vap_selector), Some(binding_selector)) => (vap_selector, binding_selector),
+        (vap_selector, binding_selector) => return Ok(vap_selector.or(binding_selector)),
+    };
+
+    let mut match_labels = vap_selector.match_labels.unwrap_or_default();
+    for (key, value) in binding_selector.match_labels.unwrap_or_default() {
+        if let Some(existing) = match_labels.get(&key) {
+            if *existing != value {
+                return Err(anyhow!(
+                    "the policy and the binding select different values for the label {key}: {existing} and {value}"
+                ));
+            }
+        }
+        match_labels.insert(key, value);
+    }
+    let mut match_expressions = vap_selector.match_expressions.unwrap_or_default();
+    match_expressions.extend(binding_selector.match_expressions.unwrap_or_default());
+
+    Ok(Some(LabelSelector {
+        match_labels: (!match_labels.is_empty()).then_some(match_labels),
+        match_expressions: (!match_expressions.is_empty()).then_some(match_expressions),
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +276,7 @@ mod tests {
             CEL_POLICY_MODULE,
             vap.clone(),
             vap_binding.clone(),
+            None,
         )
         .unwrap();
 
@@ -229,4 +332,103 @@ mod tests {
                 .contains_key("variables"));
         }
     }
+
+    fn load<T: serde::de::DeserializeOwned>(path: &str) -> T {
+        serde_yaml::from_reader(File::open(test_data(path)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn vap_with_params() {
+        let vap: ValidatingAdmissionPolicy = load("vap/vap-with-params.yml");
+        let vap_binding: ValidatingAdmissionPolicyBinding = load("vap/vap-binding-with-params.yml");
+        let params: serde_yaml::Value = load("vap/vap-params.yml");
+
+        let cluster_admission_policy = convert_vap_to_cluster_admission_policy(
+            CEL_POLICY_MODULE,
+            vap,
+            vap_binding,
+            Some(params.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(params, cluster_admission_policy.spec.settings["params"]);
+        assert_eq!(
+            Some("monitor".to_string()),
+            cluster_admission_policy.spec.mode
+        );
+        let namespace_selector = cluster_admission_policy.spec.namespace_selector.unwrap();
+        assert_eq!(
+            namespace_selector.match_labels.unwrap()["kubernetes.io/metadata.name"],
+            "default"
+        );
+        assert_eq!(
+            namespace_selector.match_expressions.unwrap()[0].key,
+            "environment"
+        );
+        assert_eq!(
+            cluster_admission_policy
+                .spec
+                .object_selector
+                .unwrap()
+                .match_labels
+                .unwrap()["app.kubernetes.io/managed-by"],
+            "helm"
+        );
+    }
+
+    #[test]
+    fn vap_with_params_requires_the_parameters() {
+        let vap: ValidatingAdmissionPolicy = load("vap/vap-with-params.yml");
+        let vap_binding: ValidatingAdmissionPolicyBinding = load("vap/vap-binding-with-params.yml");
+
+        let error =
+            convert_vap_to_cluster_admission_policy(CEL_POLICY_MODULE, vap, vap_binding, None)
+                .err()
+                .unwrap();
+        assert!(
+            error.to_string().contains("replica-limit-params"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn vap_with_params_of_the_wrong_kind() {
+        let vap: ValidatingAdmissionPolicy = load("vap/vap-with-params.yml");
+        let vap_binding: ValidatingAdmissionPolicyBinding = load("vap/vap-binding-with-params.yml");
+        let params = serde_yaml::from_str("kind: Secret").unwrap();
+
+        assert!(convert_vap_to_cluster_admission_policy(
+            CEL_POLICY_MODULE,
+            vap,
+            vap_binding,
+            Some(params)
+        )
+        .is_err());
+    }
+
+    #[rstest]
+    #[case::none(&[], None)]
+    #[case::deny(&["Deny"], None)]
+    #[case::deny_and_audit(&["Deny", "Audit"], None)]
+    #[case::warn(&["Warn"], Some("monitor"))]
+    #[case::warn_and_audit(&["Warn", "Audit"], Some("monitor"))]
+    fn validation_actions(#[case] actions: &[&str], #[case] expected: Option<&str>) {
+        assert_eq!(
+            mode(actions.iter().map(|a| a.to_string()).collect()),
+            expected.map(String::from)
+        );
+    }
+
+    #[test]
+    fn conflicting_selectors() {
+        let selector = |value: &str| {
+            Some(LabelSelector {
+                match_labels: Some([("team".to_string(), value.to_string())].into()),
+                match_expressions: None,
+            })
+        };
+        assert!(merge_selectors(selector("a"), selector("a")).is_ok());
+        assert!(merge_selectors(selector("a"), selector("b")).is_err());
+        assert_eq!(merge_selectors(None, selector("a")).unwrap(), selector("a"));
+    }
 }
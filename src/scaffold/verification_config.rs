@@ -1,17 +1,97 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use policy_evaluator::policy_fetcher::verify::config::{
     LatestVerificationConfig, Signature, VersionedVerificationConfig,
 };
 
-pub(crate) fn verification_config() -> Result<String> {
-    let mut comment_header = r#"# Default Kubewarden verification config
+use crate::config::{certificate_identity::CertificateIdentity, verification::VerificationOptions};
+
+/// Outputs the verification config requiring the signatures given via the
+/// verification flags, or the default one requiring the signatures of the
+/// Kubewarden infrastructure when no flag is given
+pub(crate) fn verification_config(options: Option<VerificationOptions>) -> Result<String> {
+    match options {
+        Some(options) => verification_config_from_flags(options),
+        None => default_verification_config(),
+    }
+}
+
+fn default_verification_config() -> Result<String> {
+    let comment_header = comment_header(
+        r#"# Default Kubewarden verification config
 #
 # With this config, the only valid policies are those signed by Kubewarden
-# infrastructure.
+# infrastructure."#,
+    );
+
+    let kubewarden_verification_config =
+        VersionedVerificationConfig::V1(LatestVerificationConfig {
+            all_of: Some(vec![Signature::GithubAction {
+                owner: "kubewarden".to_string(),
+                repo: None,
+                annotations: None,
+            }]),
+            any_of: None,
+        });
+
+    Ok(format!(
+        "{}\n{}",
+        comment_header,
+        serde_yaml::to_string(&kubewarden_verification_config)?
+    ))
+}
+
+fn verification_config_from_flags(options: VerificationOptions) -> Result<String> {
+    let comment_header = comment_header(
+        r#"# Kubewarden verification config
+#
+# With this config, the only valid policies are those carrying all the
+# signatures listed below."#,
+    );
+
+    let config = options.config.unwrap_or(LatestVerificationConfig {
+        all_of: None,
+        any_of: None,
+    });
+    let mut document = serde_yaml::to_value(VersionedVerificationConfig::V1(config))?;
+    let mapping = document
+        .as_mapping_mut()
+        .ok_or_else(|| anyhow!("cannot serialize the verification config"))?;
+    for key in ["allOf", "anyOf"] {
+        if mapping.get(key).is_some_and(serde_yaml::Value::is_null) {
+            mapping.remove(key);
+        }
+    }
+
+    // signatures matching certificates via regular expressions are not known
+    // to policy-fetcher, they are added as kwctl reads them
+    if !options.certificate_identities.is_empty() {
+        if !mapping.contains_key("allOf") {
+            mapping.insert("allOf".into(), serde_yaml::Value::Sequence(vec![]));
+        }
+        if let Some(serde_yaml::Value::Sequence(signatures)) = mapping.get_mut("allOf") {
+            signatures.extend(
+                options
+                    .certificate_identities
+                    .iter()
+                    .map(CertificateIdentity::to_signature),
+            );
+        }
+    }
+
+    Ok(format!(
+        "{}\n{}",
+        comment_header,
+        serde_yaml::to_string(&document)?
+    ))
+}
+
+fn comment_header(description: &str) -> String {
+    let mut comment_header = format!(
+        r#"{description}
 #
 # This config can be saved to its default location (for this OS) with:
 #   kwctl scaffold verification-config > "#
-        .to_string();
+    );
 
     comment_header.push_str(
         crate::KWCTL_DEFAULT_VERIFICATION_CONFIG_PATH
@@ -25,20 +105,52 @@ pub(crate) fn verification_config() -> Result<String> {
 # See https://docs.kubewarden.io/next/howtos/security-hardening/secure-supply-chain
 # for more Sigstore verification options."#,
     );
+    comment_header
+}
 
-    let kubewarden_verification_config =
-        VersionedVerificationConfig::V1(LatestVerificationConfig {
-            all_of: Some(vec![Signature::GithubAction {
-                owner: "kubewarden".to_string(),
-                repo: None,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::certificate_identity::Matcher;
+
+    #[test]
+    fn verification_config_from_flags_keeps_regexp_identities() {
+        let options = VerificationOptions {
+            config: Some(LatestVerificationConfig {
+                all_of: Some(vec![Signature::GithubAction {
+                    owner: "my-org".to_string(),
+                    repo: Some("my-policy".to_string()),
+                    annotations: None,
+                }]),
+                any_of: None,
+            }),
+            certificate_identities: vec![CertificateIdentity {
+                issuer: Matcher::Equal("https://token.actions.githubusercontent.com".to_string()),
+                subject: Matcher::regexp("https://github.com/my-org/.*").unwrap(),
                 annotations: None,
-            }]),
-            any_of: None,
-        });
+            }],
+            origin: "command line flags".to_string(),
+        };
 
-    Ok(format!(
-        "{}\n{}",
-        comment_header,
-        serde_yaml::to_string(&kubewarden_verification_config)?
-    ))
+        let config = verification_config(Some(options)).unwrap();
+        let document: serde_yaml::Value = serde_yaml::from_str(&config).unwrap();
+
+        assert_eq!(document["apiVersion"], "v1");
+        assert!(document.get("anyOf").is_none());
+        let all_of = document["allOf"].as_sequence().unwrap();
+        assert_eq!(all_of.len(), 2);
+        assert_eq!(all_of[0]["owner"], "my-org");
+        assert_eq!(all_of[1]["kind"], "genericIssuer");
+        assert_eq!(
+            all_of[1]["subject"]["regexp"],
+            "https://github.com/my-org/.*"
+        );
+    }
+
+    #[test]
+    fn default_verification_config_trusts_kubewarden() {
+        let config = verification_config(None).unwrap();
+        assert!(config.starts_with("# Default Kubewarden verification config"));
+        assert!(config.contains("owner: kubewarden"));
+    }
 }
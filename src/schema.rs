@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Result};
+
+pub(crate) const METADATA_SCHEMA: &str = include_str!("../schemas/metadata.schema.json");
+pub(crate) const SOURCES_SCHEMA: &str = include_str!("../schemas/sources.schema.json");
+pub(crate) const VERIFICATION_CONFIG_SCHEMA: &str =
+    include_str!("../schemas/verification-config.schema.json");
+const TEST_SUITE_SCHEMA: &str = include_str!("../schemas/test-suite.schema.json");
+
+/// Returns the JSON Schema describing the given kind of configuration file
+fn schema_for(kind: &str) -> Result<&'static str> {
+    match kind {
+        "metadata" => Ok(METADATA_SCHEMA),
+        "sources" => Ok(SOURCES_SCHEMA),
+        "verification-config" => Ok(VERIFICATION_CONFIG_SCHEMA),
+        "test-suite" => Ok(TEST_SUITE_SCHEMA),
+        _ => Err(anyhow!("unknown configuration file kind: {}", kind)),
+    }
+}
+
+/// Prints the JSON Schema describing the given kind of configuration file
+pub(crate) fn schema(kind: &str) -> Result<()> {
+    print!("{}", schema_for(kind)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::Value;
+
+    fn validation_errors(kind: &str, config: &str) -> Vec<String> {
+        let schema: Value = serde_json::from_str(schema_for(kind).unwrap()).unwrap();
+        let validator = jsonschema::validator_for(&schema).unwrap();
+        let config: Value = serde_yaml::from_str(config).unwrap();
+        validator
+            .iter_errors(&config)
+            .map(|error| error.to_string())
+            .collect()
+    }
+
+    #[rstest]
+    #[case::metadata(
+        "metadata",
+        include_str!("../tests/data/rego-annotate/metadata-correct.yml")
+    )]
+    #[case::sources("sources", include_str!("../tests/data/sources.yml"))]
+    #[case::verification_config(
+        "verification-config",
+        include_str!("../tests/data/sigstore/verification-config.yml")
+    )]
+    #[case::test_suite(
+        "test-suite",
+        include_str!("../tests/data/pod-privileged-test-suite.yml")
+    )]
+    fn schema_validates_config_file(#[case] kind: &str, #[case] config: &str) {
+        let errors = validation_errors(kind, config);
+        assert!(errors.is_empty(), "{kind} sample is not valid: {errors:?}");
+    }
+
+    #[rstest]
+    #[case::metadata_unknown_operation(
+        "metadata",
+        "rules:\n- apiGroups: [\"\"]\n  operations: [PATCH]\n"
+    )]
+    #[case::sources_unknown_field("sources", "insecure_sources: [localhost:5000]\nmirror: {}\n")]
+    #[case::sources_authority_without_path(
+        "sources",
+        "source_authorities:\n  registry.example.com:\n    - type: Path\n"
+    )]
+    #[case::sources_credential_provider_serving_nothing(
+        "sources",
+        "credential_providers:\n  - command: /usr/local/bin/corp-login\n"
+    )]
+    #[case::test_suite_without_policy(
+        "test-suite",
+        "tests:\n- name: accepted\n  request: pod.json\n  expect:\n    allowed: true\n"
+    )]
+    #[case::test_case_without_expectation(
+        "test-suite",
+        "policy: policy.wasm\ntests:\n- name: accepted\n  request: pod.json\n"
+    )]
+    #[case::test_case_misspelled_field(
+        "test-suite",
+        "policy: policy.wasm\ntests:\n- name: accepted\n  request: pod.json\n  expect:\n    allowed: true\n    patch_snapshot: patch.json\n"
+    )]
+    fn schema_rejects_invalid_config_file(#[case] kind: &str, #[case] config: &str) {
+        assert!(!validation_errors(kind, config).is_empty());
+    }
+}
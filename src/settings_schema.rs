@@ -0,0 +1,259 @@
+//! JSON Schema of the settings of a policy, embedded into its metadata by
+//! `kwctl annotate --settings-schema`.
+//!
+//! The schema is checked against the structure defined by the JSON Schema
+//! specification: every keyword must hold a value of the right type, and the
+//! subschemas are checked recursively. The errors report the path of the
+//! offending keyword, like `properties.replicas.type`.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+const TYPES: &[&str] = &[
+    "array", "boolean", "integer", "null", "number", "object", "string",
+];
+
+/// Keywords holding a subschema
+const SCHEMA_KEYWORDS: &[&str] = &[
+    "additionalItems",
+    "additionalProperties",
+    "contains",
+    "else",
+    "if",
+    "not",
+    "propertyNames",
+    "then",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+];
+
+/// Keywords holding a map of subschemas
+const SCHEMA_MAP_KEYWORDS: &[&str] = &[
+    "$defs",
+    "definitions",
+    "dependentSchemas",
+    "patternProperties",
+    "properties",
+];
+
+/// Keywords holding a non-empty list of subschemas
+const SCHEMA_LIST_KEYWORDS: &[&str] = &["allOf", "anyOf", "oneOf", "prefixItems"];
+
+const NUMBER_KEYWORDS: &[&str] = &["exclusiveMaximum", "exclusiveMinimum", "maximum", "minimum"];
+
+const COUNT_KEYWORDS: &[&str] = &[
+    "maxContains",
+    "maxItems",
+    "maxLength",
+    "maxProperties",
+    "minContains",
+    "minItems",
+    "minLength",
+    "minProperties",
+];
+
+const STRING_KEYWORDS: &[&str] = &[
+    "$comment",
+    "$id",
+    "$ref",
+    "$schema",
+    "description",
+    "format",
+    "title",
+];
+
+/// Reads the JSON Schema at `path`, failing when it is not a valid JSON
+/// Schema
+pub(crate) fn load(path: &Path) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read settings JSON Schema {}: {}", path.display(), e))?;
+    let schema: Value = serde_json::from_str(&contents).map_err(|e| {
+        anyhow!(
+            "cannot parse settings JSON Schema {}: {}",
+            path.display(),
+            e
+        )
+    })?;
+    let errors = errors(&schema);
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "{} is not a valid JSON Schema:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        ));
+    }
+    Ok(schema)
+}
+
+/// The errors of the schema, each one prefixed by the path of the offending
+/// keyword
+pub(crate) fn errors(schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    check(schema, "", &mut errors);
+    errors
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+fn check(schema: &Value, path: &str, errors: &mut Vec<String>) {
+    let keywords = match schema {
+        Value::Bool(_) => return,
+        Value::Object(keywords) => keywords,
+        _ => {
+            let path = if path.is_empty() { "schema" } else { path };
+            errors.push(format!("{path}: expected an object or a boolean"));
+            return;
+        }
+    };
+
+    for (keyword, value) in keywords {
+        let path = join(path, keyword);
+        let mut error = |message: &str| errors.push(format!("{path}: {message}"));
+        match keyword.as_str() {
+            "type" => {
+                let valid = |name: &Value| name.as_str().is_some_and(|name| TYPES.contains(&name));
+                let is_valid = match value {
+                    Value::Array(names) => !names.is_empty() && names.iter().all(valid),
+                    name => valid(name),
+                };
+                if !is_valid {
+                    error(&format!(
+                        "expected one of {} or a list of them",
+                        TYPES.join(", ")
+                    ));
+                }
+            }
+            "required" => {
+                if !value
+                    .as_array()
+                    .is_some_and(|names| names.iter().all(Value::is_string))
+                {
+                    error("expected a list of strings");
+                }
+            }
+            "enum" => {
+                if !value.is_array() {
+                    error("expected a list");
+                }
+            }
+            "pattern" => match value.as_str() {
+                Some(pattern) => {
+                    if let Err(e) = regex::Regex::new(pattern) {
+                        error(&format!("invalid regular expression: {e}"));
+                    }
+                }
+                None => error("expected a string"),
+            },
+            "multipleOf" => {
+                if !value.as_f64().is_some_and(|number| number > 0.0) {
+                    error("expected a number greater than 0");
+                }
+            }
+            "uniqueItems" | "readOnly" | "writeOnly" | "deprecated" => {
+                if !value.is_boolean() {
+                    error("expected a boolean");
+                }
+            }
+            "items" => match value {
+                // draft 7 allows a list of schemas
+                Value::Array(schemas) => check_list(schemas, &path, errors),
+                schema => check(schema, &path, errors),
+            },
+            keyword if SCHEMA_KEYWORDS.contains(&keyword) => check(value, &path, errors),
+            keyword if SCHEMA_MAP_KEYWORDS.contains(&keyword) => match value.as_object() {
+                Some(schemas) => {
+                    for (name, schema) in schemas {
+                        check(schema, &join(&path, name), errors);
+                    }
+                }
+                None => error("expected an object"),
+            },
+            keyword if SCHEMA_LIST_KEYWORDS.contains(&keyword) => match value.as_array() {
+                Some(schemas) if !schemas.is_empty() => check_list(schemas, &path, errors),
+                _ => error("expected a non-empty list of schemas"),
+            },
+            keyword if NUMBER_KEYWORDS.contains(&keyword) => {
+                if !value.is_number() {
+                    error("expected a number");
+                }
+            }
+            keyword if COUNT_KEYWORDS.contains(&keyword) => {
+                if !value.is_u64() {
+                    error("expected a non-negative integer");
+                }
+            }
+            keyword if STRING_KEYWORDS.contains(&keyword) => {
+                if !value.is_string() {
+                    error("expected a string");
+                }
+            }
+            // unknown keywords are ignored, as mandated by the specification
+            _ => {}
+        }
+    }
+}
+
+fn check_list(schemas: &[Value], path: &str, errors: &mut Vec<String>) {
+    for (index, schema) in schemas.iter().enumerate() {
+        check(schema, &format!("{path}[{index}]"), errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+    use serde_json::json;
+
+    #[test]
+    fn valid_schema() {
+        let schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "required": ["replicas"],
+            "properties": {
+                "replicas": {"type": "integer", "minimum": 1},
+                "name": {"type": ["string", "null"], "pattern": "^[a-z]+$"},
+                "labels": {
+                    "type": "array",
+                    "items": {"type": "string"},
+                    "uniqueItems": true
+                },
+                "mode": {"enum": ["audit", "enforce"]}
+            },
+            "additionalProperties": false,
+            "x-kubewarden-ui": {"order": 1}
+        });
+        assert_eq!(errors(&schema), Vec::<String>::new());
+    }
+
+    #[rstest]
+    #[case::not_an_object(json!("object"), "schema: expected an object or a boolean")]
+    #[case::unknown_type(
+        json!({"properties": {"replicas": {"type": "int"}}}),
+        "properties.replicas.type: expected one of"
+    )]
+    #[case::required(json!({"required": "replicas"}), "required: expected a list of strings")]
+    #[case::pattern(
+        json!({"properties": {"name": {"pattern": "("}}}),
+        "properties.name.pattern: invalid regular expression"
+    )]
+    #[case::subschema_list(
+        json!({"anyOf": [{"type": "string"}, 1]}),
+        "anyOf[1]: expected an object or a boolean"
+    )]
+    #[case::count(json!({"minLength": -1}), "minLength: expected a non-negative integer")]
+    fn invalid_schema(#[case] schema: Value, #[case] expected: &str) {
+        let errors = errors(&schema);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(errors[0].starts_with(expected), "{}", errors[0]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use policy_evaluator::policy_fetcher::{
+    oci_client::{
+        client::{Config, ImageLayer},
+        manifest::{
+            OciImageManifest, IMAGE_CONFIG_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE,
+            OCI_IMAGE_MEDIA_TYPE,
+        },
+        Client, Reference,
+    },
+    sigstore::{
+        cosign::{
+            constraint::AnnotationMarker, ClientBuilder, Constraint, CosignCapabilities,
+            SignatureLayer,
+        },
+        crypto::{SigStoreKeyPair, SigningScheme},
+        registry::{oci_reference::OciReference, ClientConfig},
+    },
+    sources::Sources,
+};
+use tracing::{debug, info};
+
+use crate::{
+    config::registry_auth::{registry_auth, sigstore_auth},
+    kms::KmsKey,
+    push::is_manifest_unknown,
+};
+
+mod keyless;
+
+pub(crate) use keyless::KeylessOptions;
+use keyless::KeylessSigner;
+
+/// Media type of the cosign signatures produced and verified by kwctl
+pub(crate) const SIGSTORE_OCI_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+const SIGSTORE_SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+
+/// How the policies are signed
+#[derive(Debug)]
+pub(crate) enum SigningMethod<'a> {
+    /// A private key, like the ones generated by `cosign generate-key-pair`
+    Key {
+        path: &'a Path,
+        password: Option<&'a str>,
+    },
+    /// A key stored inside of a KMS, referenced by a URI like
+    /// `awskms:///alias/cosign`
+    Kms(KmsKey),
+    /// An ephemeral key certified by Fulcio, the signature being recorded by
+    /// Rekor
+    Keyless(KeylessOptions),
+}
+
+impl<'a> SigningMethod<'a> {
+    /// The signing method of the key given via the command line: the path of
+    /// a private key, or the URI of a KMS key
+    pub(crate) fn key(key: &'a str, password: Option<&'a str>) -> Result<Self> {
+        Ok(match KmsKey::parse(key)? {
+            Some(kms_key) => SigningMethod::Kms(kms_key),
+            None => SigningMethod::Key {
+                path: Path::new(key),
+                password,
+            },
+        })
+    }
+}
+
+/// Signs the policy referenced by `uri`, and pushes the signature to the
+/// registry, next to the policy.
+///
+/// The signature is cosign-compatible: it's stored inside of the
+/// `sha256-<digest>.sig` tag, using the simple signing format. The given
+/// annotations are added to the signed payload. Like cosign, the signature is
+/// appended to the ones already stored inside of the tag.
+///
+/// Returns the reference of the signature image. `None` is returned when the
+/// policy is left unsigned, because Fulcio or Rekor cannot be reached and the
+/// keyless options tell to warn.
+pub(crate) async fn sign(
+    uri: &str,
+    method: &SigningMethod<'_>,
+    annotations: &HashMap<String, String>,
+    sources: Option<&Sources>,
+) -> Result<Option<String>> {
+    let image_name = uri.strip_prefix("registry://").unwrap_or(uri);
+    let image_ref = OciReference::from_str(image_name)?;
+    let auth = sigstore_auth(image_name)?;
+
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let mut client = ClientBuilder::default()
+        .with_oci_client_config(client_config)
+        .build()?;
+    let (signature_image, source_image_digest) = client.triangulate(&image_ref, &auth).await?;
+
+    let mut signature_layer = SignatureLayer::new_unsigned(&image_ref, &source_image_digest)?;
+    if !annotations.is_empty() {
+        AnnotationMarker {
+            annotations: annotations.to_owned(),
+        }
+        .add_constraint(&mut signature_layer)?;
+    }
+    let payload = &signature_layer.raw_data;
+
+    let (signature, mut layer_annotations) = match method {
+        SigningMethod::Key { path, password } => {
+            let signature = read_signing_key(path, *password)?
+                .to_sigstore_signer(&SigningScheme::default())
+                .map_err(|e| anyhow!("cannot use signing key {}: {}", path.display(), e))?
+                .sign(payload)
+                .map_err(|e| anyhow!("cannot sign the policy: {}", e))?;
+            (signature, BTreeMap::new())
+        }
+        SigningMethod::Kms(kms_key) => {
+            let scheme = kms_key.signing_scheme()?;
+            (kms_key.sign(&scheme, payload)?, BTreeMap::new())
+        }
+        SigningMethod::Keyless(options) => {
+            let Some(signer) = KeylessSigner::new(options).await? else {
+                return Ok(None);
+            };
+            let signature = signer.sign(payload)?;
+            let Some(annotations) = signer.log(payload, &signature).await? else {
+                return Ok(None);
+            };
+            (signature, annotations)
+        }
+    };
+    layer_annotations.insert(
+        SIGSTORE_SIGNATURE_ANNOTATION.to_string(),
+        STANDARD.encode(signature),
+    );
+
+    debug!(
+        signature = signature_image.whole().as_str(),
+        digest = source_image_digest.as_str(),
+        "pushing policy signature"
+    );
+    append_signature(
+        &signature_image.whole(),
+        ImageLayer::new(
+            payload.clone().into(),
+            SIGSTORE_OCI_MEDIA_TYPE.to_string(),
+            Some(layer_annotations),
+        ),
+        sources,
+    )
+    .await?;
+    info!(
+        signature = signature_image.whole().as_str(),
+        "policy signed"
+    );
+
+    Ok(Some(signature_image.whole()))
+}
+
+/// Pushes the signature image made of the signatures it already holds, if
+/// any, and of the new one. Replacing the signature image would drop the
+/// signatures produced by other parties.
+async fn append_signature(
+    signature_image: &str,
+    layer: ImageLayer,
+    sources: Option<&Sources>,
+) -> Result<()> {
+    let reference = Reference::from_str(signature_image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", signature_image, e))?;
+    let auth = registry_auth(signature_image)?;
+
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let client = Client::new(client_config.into());
+    let (mut layers, config) = match client
+        .pull_manifest_raw(
+            &reference,
+            &auth,
+            &[OCI_IMAGE_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE],
+        )
+        .await
+    {
+        Ok((manifest, _)) => {
+            let manifest: OciImageManifest = serde_json::from_slice(&manifest)?;
+            let mut layers = Vec::with_capacity(manifest.layers.len() + 1);
+            for descriptor in &manifest.layers {
+                let mut data = Vec::new();
+                client.pull_blob(&reference, descriptor, &mut data).await?;
+                layers.push(ImageLayer::new(
+                    data.into(),
+                    descriptor.media_type.clone(),
+                    descriptor.annotations.clone(),
+                ));
+            }
+            let mut config = Vec::new();
+            client
+                .pull_blob(&reference, &manifest.config, &mut config)
+                .await?;
+            debug!(
+                signature = signature_image,
+                count = layers.len(),
+                "appending to the existing signatures"
+            );
+            (
+                layers,
+                Config::new(config.into(), manifest.config.media_type.clone(), None),
+            )
+        }
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            if !is_manifest_unknown(&e) {
+                return Err(anyhow!(
+                    "cannot fetch the signatures stored inside of {}: {}",
+                    signature_image,
+                    e
+                ));
+            }
+            (
+                Vec::new(),
+                Config::new(
+                    b"{}".to_vec().into(),
+                    IMAGE_CONFIG_MEDIA_TYPE.to_string(),
+                    None,
+                ),
+            )
+        }
+    };
+    layers.push(layer);
+
+    client
+        .push(&reference, &layers, config, &auth, None)
+        .await
+        .map_err(|e| anyhow!("cannot push the signature to {}: {}", signature_image, e))?;
+    Ok(())
+}
+
+// Reads a private key generated by `cosign generate-key-pair`, or a plain PEM
+// encoded private key
+fn read_signing_key(key_path: &Path, password: Option<&str>) -> Result<SigStoreKeyPair> {
+    let key = fs::read(key_path)
+        .map_err(|e| anyhow!("cannot read signing key {}: {}", key_path.display(), e))?;
+    let tag = pem::parse(&key)
+        .map_err(|e| anyhow!("cannot parse signing key {}: {}", key_path.display(), e))?
+        .tag()
+        .to_owned();
+
+    let key_pair = if tag.contains("ENCRYPTED") {
+        SigStoreKeyPair::from_encrypted_pem(&key, password.unwrap_or_default().as_bytes())
+    } else {
+        SigStoreKeyPair::from_pem(&key)
+    };
+    key_pair.map_err(|e| anyhow!("cannot load signing key {}: {}", key_path.display(), e))
+}
@@ -0,0 +1,433 @@
+//! Keyless signing, like `cosign sign` without a key.
+//!
+//! The policy is signed with an ephemeral key. Fulcio certifies the key for
+//! the OIDC identity of the signer, and the signature is recorded inside of
+//! the Rekor transparency log. The certificate chain and the Rekor bundle are
+//! stored next to the signature, which can then be verified offline.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use policy_evaluator::policy_fetcher::sigstore::crypto::{SigStoreSigner, SigningScheme};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tracing::{debug, warn};
+
+use crate::{
+    config::credential_provider::CredentialProviders,
+    trust_root::{backoff, NetworkOptions, OnUnreachable},
+};
+
+const SIGSTORE_CERT_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+const SIGSTORE_CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+const SIGSTORE_BUNDLE_ANNOTATION: &str = "dev.sigstore.cosign/bundle";
+
+// Set by GitHub Actions to the jobs granted the `id-token: write` permission
+const GITHUB_TOKEN_REQUEST_URL: &str = "ACTIONS_ID_TOKEN_REQUEST_URL";
+const GITHUB_TOKEN_REQUEST_TOKEN: &str = "ACTIONS_ID_TOKEN_REQUEST_TOKEN";
+
+#[derive(Debug, Clone)]
+pub(crate) struct KeylessOptions {
+    /// OIDC identity token of the signer. When missing, it's obtained from
+    /// the Sigstore credential provider, or the one of the GitHub Actions job
+    /// is requested
+    pub(crate) identity_token: Option<String>,
+    pub(crate) credential_providers: CredentialProviders,
+    /// Timeout, retries and behavior on failure of the requests made to
+    /// Fulcio and Rekor
+    pub(crate) network: NetworkOptions,
+    pub(crate) fulcio_url: String,
+    pub(crate) rekor_url: String,
+}
+
+/// An ephemeral key, together with the certificate chain issued by Fulcio
+pub(crate) struct KeylessSigner {
+    signer: SigStoreSigner,
+    /// PEM encoded certificates, the one of the key first
+    chain: Vec<String>,
+    rekor_url: String,
+    client: reqwest::Client,
+    network: NetworkOptions,
+}
+
+impl KeylessSigner {
+    /// Generates the ephemeral key and has it certified by Fulcio. `None` is
+    /// returned when Fulcio cannot be reached and the options tell to warn.
+    pub(crate) async fn new(options: &KeylessOptions) -> Result<Option<Self>> {
+        let client = reqwest::Client::builder()
+            .timeout(options.network.timeout)
+            .build()
+            .map_err(|e| anyhow!("cannot create the HTTP client: {}", e))?;
+        let token = match &options.identity_token {
+            Some(token) => token.clone(),
+            None => match options.credential_providers.identity_token()? {
+                Some(token) => token,
+                None => github_identity_token(&client).await?,
+            },
+        };
+        let subject = token_subject(&token)?;
+
+        let signer = SigningScheme::default()
+            .create_signer()
+            .map_err(|e| anyhow!("cannot generate the ephemeral signing key: {}", e))?;
+        let public_key = signer
+            .to_sigstore_keypair()
+            .and_then(|key_pair| key_pair.public_key_to_pem())
+            .map_err(|e| anyhow!("cannot encode the ephemeral public key: {}", e))?;
+        // proves to Fulcio the ownership of the key
+        let proof_of_possession = signer
+            .sign(subject.as_bytes())
+            .map_err(|e| anyhow!("cannot sign the identity of the signer: {}", e))?;
+
+        let request = json!({
+            "credentials": {"oidcIdentityToken": token},
+            "publicKeyRequest": {
+                "publicKey": {"algorithm": "ECDSA", "content": public_key},
+                "proofOfPossession": STANDARD.encode(proof_of_possession),
+            },
+        });
+        let Some(response) = post_json(
+            &client,
+            &format!(
+                "{}/api/v2/signingCert",
+                options.fulcio_url.trim_end_matches('/')
+            ),
+            &request,
+            &options.network,
+        )
+        .await
+        .map_err(|e| anyhow!("cannot get a signing certificate from Fulcio: {}", e))?
+        else {
+            return Ok(None);
+        };
+        let chain = certificate_chain(&response)?;
+        debug!(subject, "signing certificate issued by Fulcio");
+
+        Ok(Some(KeylessSigner {
+            signer,
+            chain,
+            rekor_url: options.rekor_url.trim_end_matches('/').to_string(),
+            client,
+            network: options.network.clone(),
+        }))
+    }
+
+    pub(crate) fn sign(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.signer
+            .sign(payload)
+            .map_err(|e| anyhow!("cannot sign the policy: {}", e))
+    }
+
+    /// Records the signature inside of Rekor, returning the annotations of
+    /// the signature layer holding the certificates and the Rekor bundle.
+    /// `None` is returned when Rekor cannot be reached and the options tell
+    /// to warn.
+    pub(crate) async fn log(
+        &self,
+        payload: &[u8],
+        signature: &[u8],
+    ) -> Result<Option<BTreeMap<String, String>>> {
+        let request = json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": {
+                    "hash": {"algorithm": "sha256", "value": format!("{:x}", Sha256::digest(payload))},
+                },
+                "signature": {
+                    "content": STANDARD.encode(signature),
+                    "publicKey": {"content": STANDARD.encode(&self.chain[0])},
+                },
+            },
+        });
+        let Some(response) = post_json(
+            &self.client,
+            &format!("{}/api/v1/log/entries", self.rekor_url),
+            &request,
+            &self.network,
+        )
+        .await
+        .map_err(|e| anyhow!("cannot record the signature inside of Rekor: {}", e))?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(BTreeMap::from([
+            (SIGSTORE_CERT_ANNOTATION.to_string(), self.chain[0].clone()),
+            (
+                SIGSTORE_CHAIN_ANNOTATION.to_string(),
+                self.chain[1..].concat(),
+            ),
+            (
+                SIGSTORE_BUNDLE_ANNOTATION.to_string(),
+                rekor_bundle(&response)?.to_string(),
+            ),
+        ])))
+    }
+}
+
+/// Requests the identity token of the GitHub Actions job, for Sigstore
+async fn github_identity_token(client: &reqwest::Client) -> Result<String> {
+    let (Ok(url), Ok(token)) = (
+        std::env::var(GITHUB_TOKEN_REQUEST_URL),
+        std::env::var(GITHUB_TOKEN_REQUEST_TOKEN),
+    ) else {
+        return Err(anyhow!(
+            "keyless signing requires an OIDC identity token: set SIGSTORE_ID_TOKEN, declare a Sigstore credential provider, or run inside of a GitHub Actions job with the 'id-token: write' permission"
+        ));
+    };
+    let response = client
+        .get(format!("{url}&audience=sigstore"))
+        .bearer_auth(token)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| anyhow!("cannot request the identity token of the job: {}", e))?
+        .bytes()
+        .await?;
+    let response: Value = serde_json::from_slice(&response)?;
+    response
+        .get("value")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("GitHub returned no identity token"))
+}
+
+/// The identity Fulcio certifies: the email of the signer, or the subject of
+/// the token for the workload identities
+fn token_subject(token: &str) -> Result<String> {
+    let claims = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("the identity token is not a JWT"))?;
+    let claims: Value = serde_json::from_slice(
+        &URL_SAFE_NO_PAD
+            .decode(claims.trim_end_matches('='))
+            .map_err(|e| anyhow!("cannot decode the claims of the identity token: {}", e))?,
+    )
+    .map_err(|e| anyhow!("cannot parse the claims of the identity token: {}", e))?;
+    claims
+        .get("email")
+        .or_else(|| claims.get("sub"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("the identity token has neither an email nor a subject"))
+}
+
+/// Why a request to Fulcio or Rekor failed
+enum Failure {
+    /// The server cannot be reached, or cannot serve the request right now
+    Unreachable(anyhow::Error),
+    /// The server rejected the request
+    Rejected(anyhow::Error),
+}
+
+/// Posts the request, retrying the attempts failing because the server
+/// cannot be reached. When it stays unreachable, either an error is
+/// returned, or `None` when the options tell to warn.
+async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+    network: &NetworkOptions,
+) -> Result<Option<Value>> {
+    let mut attempt = 0;
+    let error = loop {
+        let error = match try_post_json(client, url, body).await {
+            Ok(response) => return Ok(Some(response)),
+            Err(Failure::Rejected(e)) => return Err(e),
+            Err(Failure::Unreachable(e)) => e,
+        };
+        if attempt >= network.retries {
+            break error;
+        }
+        attempt += 1;
+        warn!(attempt, url, error = %error, "cannot reach the Sigstore infrastructure, retrying");
+        tokio::time::sleep(backoff(attempt)).await;
+    };
+
+    match network.on_unreachable {
+        OnUnreachable::Fail => Err(anyhow!(
+            "{} after {} attempts. Raise --sigstore-timeout or --sigstore-retries on slow networks, or use '--sigstore-unreachable warn' to continue without signing",
+            error,
+            attempt + 1
+        )),
+        OnUnreachable::Warn => {
+            warn!(url, error = %error, "cannot reach the Sigstore infrastructure, the policy is not signed");
+            Ok(None)
+        }
+    }
+}
+
+async fn try_post_json(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+) -> std::result::Result<Value, Failure> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(reqwest::header::ACCEPT, "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| Failure::Unreachable(e.into()))?;
+    let status = response.status();
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| Failure::Unreachable(e.into()))?;
+    if !status.is_success() {
+        let error = anyhow!("{}: {}", status, String::from_utf8_lossy(&body));
+        return Err(
+            if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                Failure::Unreachable(error)
+            } else {
+                Failure::Rejected(error)
+            },
+        );
+    }
+    serde_json::from_slice(&body).map_err(|e| Failure::Rejected(e.into()))
+}
+
+/// Reads the certificates issued by Fulcio, whether the SCT is embedded into
+/// the certificate or not
+fn certificate_chain(response: &Value) -> Result<Vec<String>> {
+    let chain: Vec<String> = [
+        "signedCertificateEmbeddedSct",
+        "signedCertificateDetachedSct",
+    ]
+    .iter()
+    .find_map(|kind| {
+        response
+            .pointer(&format!("/{kind}/chain/certificates"))
+            .and_then(Value::as_array)
+    })
+    .ok_or_else(|| anyhow!("Fulcio returned no certificate chain"))?
+    .iter()
+    .map(|certificate| {
+        certificate
+            .as_str()
+            .map(|certificate| {
+                // the annotations hold the certificates one per line
+                format!("{}\n", certificate.trim_end())
+            })
+            .ok_or_else(|| anyhow!("Fulcio returned an invalid certificate"))
+    })
+    .collect::<Result<_>>()?;
+    if chain.is_empty() {
+        return Err(anyhow!("Fulcio returned an empty certificate chain"));
+    }
+    Ok(chain)
+}
+
+/// Builds the bundle cosign stores next to the signature from the entry
+/// created by Rekor, to verify the signature without reaching Rekor
+fn rekor_bundle(response: &Value) -> Result<Value> {
+    let entry = response
+        .as_object()
+        .and_then(|entries| entries.values().next())
+        .ok_or_else(|| anyhow!("Rekor returned no log entry"))?;
+    let field = |pointer: &str| {
+        entry
+            .pointer(pointer)
+            .cloned()
+            .ok_or_else(|| anyhow!("the Rekor log entry has no {}", pointer))
+    };
+    Ok(json!({
+        "SignedEntryTimestamp": field("/verification/signedEntryTimestamp")?,
+        "Payload": {
+            "body": field("/body")?,
+            "integratedTime": field("/integratedTime")?,
+            "logIndex": field("/logIndex")?,
+            "logID": field("/logID")?,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn token(claims: Value) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[rstest]
+    #[case::email(
+        json!({"sub": "1234", "email": "developer@example.com"}),
+        "developer@example.com"
+    )]
+    #[case::workload(
+        json!({"sub": "repo:kubewarden/policies:ref:refs/tags/v1.0.0"}),
+        "repo:kubewarden/policies:ref:refs/tags/v1.0.0"
+    )]
+    fn token_subjects(#[case] claims: Value, #[case] subject: &str) {
+        assert_eq!(token_subject(&token(claims)).unwrap(), subject);
+    }
+
+    #[rstest]
+    #[case::not_a_jwt("opaque-token")]
+    #[case::no_subject(&token(json!({"iss": "https://accounts.google.com"})))]
+    fn invalid_tokens(#[case] jwt: &str) {
+        assert!(token_subject(jwt).is_err());
+    }
+
+    #[rstest]
+    #[case::embedded_sct("signedCertificateEmbeddedSct")]
+    #[case::detached_sct("signedCertificateDetachedSct")]
+    fn certificate_chains(#[case] kind: &str) {
+        let mut response = json!({});
+        response[kind] = json!({"chain": {"certificates": [
+            "-----BEGIN CERTIFICATE-----\nleaf\n-----END CERTIFICATE-----\n",
+            "-----BEGIN CERTIFICATE-----\nroot\n-----END CERTIFICATE-----",
+        ]}});
+
+        let chain = certificate_chain(&response).unwrap();
+        assert_eq!(
+            chain,
+            [
+                "-----BEGIN CERTIFICATE-----\nleaf\n-----END CERTIFICATE-----\n",
+                "-----BEGIN CERTIFICATE-----\nroot\n-----END CERTIFICATE-----\n",
+            ]
+        );
+        response[kind] = json!({"chain": {"certificates": []}});
+        assert!(certificate_chain(&response).is_err());
+    }
+
+    #[test]
+    fn rekor_bundles() {
+        let response = json!({
+            "24296fb24b8ad77a": {
+                "body": "eyJhcGlWZXJzaW9uIjoiMC4wLjEifQ==",
+                "integratedTime": 1700000000,
+                "logID": "c0d23d6ad406973f9559f3ba2d1ca01f84147d8ffc5b8445c224f98b9591801d",
+                "logIndex": 42,
+                "verification": {"signedEntryTimestamp": "MEUCIQ=="},
+            }
+        });
+
+        assert_eq!(
+            rekor_bundle(&response).unwrap(),
+            json!({
+                "SignedEntryTimestamp": "MEUCIQ==",
+                "Payload": {
+                    "body": "eyJhcGlWZXJzaW9uIjoiMC4wLjEifQ==",
+                    "integratedTime": 1700000000,
+                    "logIndex": 42,
+                    "logID": "c0d23d6ad406973f9559f3ba2d1ca01f84147d8ffc5b8445c224f98b9591801d",
+                },
+            })
+        );
+        assert!(rekor_bundle(&json!({})).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Deduplication of the policies of the local store.
+//!
+//! The layout of the store is owned by policy-fetcher: each policy is saved
+//! under a path derived from its URI. Because of that, the same module
+//! pulled under several tags, or from several registries and mirrors, is
+//! saved multiple times.
+//!
+//! kwctl keeps a content-addressed copy of each module under
+//! `<cache dir>/blobs/sha256/<digest>`, and replaces the policies of the
+//! store with hard links to it. Hard links are transparent to policy-fetcher
+//! and to all the other readers of the store. A blob is removed once no
+//! policy of the store links to it anymore. The links share their
+//! modification time: the pull time of a deduplicated policy is the one of
+//! the latest pull of its module.
+//!
+//! The deduplication is best-effort: when hard links are not available, for
+//! example on platforms other than Unix or when the store and the blobs are
+//! on different file systems, the policies are left untouched.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+fn blobs_dir() -> PathBuf {
+    crate::store_profile::cache_dir()
+        .join("blobs")
+        .join("sha256")
+}
+
+/// Identity of the file and how many hard links point to it, `None` when
+/// they cannot be read on this platform
+#[cfg(unix)]
+fn links(metadata: &fs::Metadata) -> Option<((u64, u64), u64)> {
+    Some(((metadata.dev(), metadata.ino()), metadata.nlink()))
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &fs::Metadata) -> Option<((u64, u64), u64)> {
+    None
+}
+
+/// Replaces the policy at `path` with a hard link to the blob holding the
+/// same module, creating the blob when it's missing. Returns the space
+/// saved.
+pub(crate) fn dedup(path: &Path) -> Result<u64> {
+    let metadata = fs::metadata(path)?;
+    let Some((id, count)) = links(&metadata) else {
+        return Ok(0);
+    };
+    // only the deduplication creates hard links
+    if count > 1 {
+        return Ok(0);
+    }
+
+    let data = fs::read(path)?;
+    let blob = blobs_dir().join(format!("{:x}", Sha256::digest(&data)));
+    match fs::metadata(&blob) {
+        Ok(blob_metadata) if links(&blob_metadata).map(|(id, _)| id) == Some(id) => Ok(0),
+        Ok(_) => {
+            // the link is created next to the policy and then moved over it,
+            // the policy is never missing from the store
+            let temporary = path.with_extension("kwctl-dedup");
+            let result =
+                fs::hard_link(&blob, &temporary).and_then(|_| fs::rename(&temporary, path));
+            if let Err(e) = result {
+                let _ = fs::remove_file(&temporary);
+                debug!(policy = %path.display(), error = %e, "cannot deduplicate policy");
+                return Ok(0);
+            }
+            // the links share the modification time, which is reported as
+            // the pull time of the policies: keep the one of the latest pull
+            fs::File::options()
+                .write(true)
+                .open(path)?
+                .set_modified(metadata.modified()?)?;
+            Ok(metadata.len())
+        }
+        Err(_) => {
+            fs::create_dir_all(blobs_dir())?;
+            if let Err(e) = fs::hard_link(path, &blob) {
+                debug!(policy = %path.display(), error = %e, "cannot deduplicate policy");
+            }
+            Ok(0)
+        }
+    }
+}
+
+/// Unlinks the policy at `path` when it shares its module with other
+/// policies. Must be done before overwriting the policy, which would
+/// otherwise overwrite the module of all of them.
+pub(crate) fn detach(path: &Path) -> Result<()> {
+    match fs::metadata(path).ok().as_ref().and_then(links) {
+        Some((_, count)) if count > 1 => {
+            fs::remove_file(path).map_err(|e| anyhow!("cannot remove {}: {}", path.display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the blobs no policy links to anymore. Returns the space freed.
+pub(crate) fn prune() -> Result<u64> {
+    let entries = match fs::read_dir(blobs_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(0),
+    };
+
+    let mut freed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if let Some((_, 1)) = links(&metadata) {
+            fs::remove_file(entry.path())?;
+            freed += metadata.len();
+        }
+    }
+    Ok(freed)
+}
+
+/// Space freed by removing the policies at `paths`: the modules shared with
+/// policies that are kept are not freed
+pub(crate) fn reclaimable(paths: &[PathBuf]) -> Result<u64> {
+    let mut reclaimable = 0;
+    // links to the same module: size, links from the store, links removed
+    let mut shared: HashMap<(u64, u64), (u64, u64, u64)> = HashMap::new();
+    for path in paths {
+        let metadata = fs::metadata(path)?;
+        match links(&metadata) {
+            Some((id, count)) if count > 1 => {
+                // one of the links is the blob
+                shared.entry(id).or_insert((metadata.len(), count - 1, 0)).2 += 1;
+            }
+            _ => reclaimable += metadata.len(),
+        }
+    }
+    reclaimable += shared
+        .values()
+        .filter(|(_, links, removed)| removed >= links)
+        .map(|(size, _, _)| size)
+        .sum::<u64>();
+    Ok(reclaimable)
+}
+
+/// Outcome of the deduplication of the whole store
+pub(crate) struct DedupReport {
+    pub(crate) policies: usize,
+    pub(crate) saved: u64,
+}
+
+/// Deduplicates all the policies of the local store, like the ones pulled
+/// before the deduplication has been introduced
+pub(crate) fn dedup_store() -> Result<DedupReport> {
+    let policies = crate::store_profile::store().list()?;
+    let mut saved = 0;
+    for policy in &policies {
+        saved += dedup(&policy.local_path)?;
+    }
+    Ok(DedupReport {
+        policies: policies.len(),
+        saved,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn shared_modules_are_reclaimed_only_when_all_links_are_removed() {
+        let dir = tempdir().unwrap();
+        let blob = dir.path().join("blob");
+        fs::write(&blob, [0u8; 100]).unwrap();
+        let first = dir.path().join("first.wasm");
+        let second = dir.path().join("second.wasm");
+        fs::hard_link(&blob, &first).unwrap();
+        fs::hard_link(&blob, &second).unwrap();
+        let unique = dir.path().join("unique.wasm");
+        fs::write(&unique, [0u8; 10]).unwrap();
+
+        assert_eq!(reclaimable(&[first.clone(), unique.clone()]).unwrap(), 10);
+        assert_eq!(reclaimable(&[first, second, unique]).unwrap(), 110);
+    }
+
+    #[test]
+    fn detached_policy_does_not_alter_shared_module() {
+        let dir = tempdir().unwrap();
+        let blob = dir.path().join("blob");
+        fs::write(&blob, "module").unwrap();
+        let policy = dir.path().join("policy.wasm");
+        fs::hard_link(&blob, &policy).unwrap();
+
+        detach(&policy).unwrap();
+        fs::write(&policy, "new module").unwrap();
+
+        assert_eq!(fs::read_to_string(&blob).unwrap(), "module");
+    }
+}
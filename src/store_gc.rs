@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::oci_client::Reference;
+use time::OffsetDateTime;
+
+use crate::store_dedup;
+
+/// Which policies are removed by the garbage collection. A policy is removed
+/// when it matches all the given filters and it's not one of the latest
+/// policies of its repository kept by `keep_latest`.
+#[derive(Debug, Default)]
+pub(crate) struct GcFilters {
+    /// Remove the policies neither pulled nor evaluated within this time
+    pub(crate) older_than: Option<Duration>,
+    /// Remove the policies never evaluated by `run` or `bench`
+    pub(crate) unused: bool,
+    /// Keep this many of the most recently pulled policies of each
+    /// repository
+    pub(crate) keep_latest: Option<usize>,
+}
+
+impl GcFilters {
+    fn is_empty(&self) -> bool {
+        self.older_than.is_none() && !self.unused && self.keep_latest.is_none()
+    }
+}
+
+/// Parses ages like `90d`, `2w`, `12h` or `30m`
+pub(crate) fn parse_age(value: &str) -> Result<Duration> {
+    let invalid = || {
+        anyhow!(
+            "invalid age '{}', expected a number followed by m (minutes), h (hours), d (days) or w (weeks)",
+            value
+        )
+    };
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let amount: u64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// A policy of the store
+#[derive(Debug)]
+struct StoredPolicy {
+    uri: String,
+    path: PathBuf,
+    size: u64,
+    /// Unix timestamp of when the policy has been pulled
+    pulled: i64,
+    /// Unix timestamp of when the policy has been last evaluated
+    last_used: Option<i64>,
+}
+
+impl StoredPolicy {
+    /// The registry repository of the policy, or its whole URI for the
+    /// policies not pulled from a registry
+    fn repository(&self) -> String {
+        self.uri
+            .strip_prefix("registry://")
+            .and_then(|image| Reference::from_str(image).ok())
+            .map(|reference| format!("{}/{}", reference.registry(), reference.repository()))
+            .unwrap_or_else(|| self.uri.clone())
+    }
+
+    fn last_activity(&self) -> i64 {
+        self.last_used
+            .map_or(self.pulled, |used| used.max(self.pulled))
+    }
+}
+
+/// Selects the policies to remove
+fn select(policies: Vec<StoredPolicy>, filters: &GcFilters, now: i64) -> Vec<StoredPolicy> {
+    let mut repositories: BTreeMap<String, Vec<StoredPolicy>> = BTreeMap::new();
+    for policy in policies {
+        repositories
+            .entry(policy.repository())
+            .or_default()
+            .push(policy);
+    }
+
+    let mut selected = Vec::new();
+    for (_, mut policies) in repositories {
+        // the most recently pulled first
+        policies.sort_by(|a, b| b.pulled.cmp(&a.pulled).then_with(|| a.uri.cmp(&b.uri)));
+        let kept = filters.keep_latest.unwrap_or(0);
+        selected.extend(policies.into_iter().skip(kept).filter(|policy| {
+            let old = filters.older_than.is_none_or(|age| {
+                now - policy.last_activity() > i64::try_from(age.as_secs()).unwrap_or(i64::MAX)
+            });
+            let unused = !filters.unused || policy.last_used.is_none();
+            old && unused
+        }));
+    }
+    selected.sort_by(|a, b| a.uri.cmp(&b.uri));
+    selected
+}
+
+/// Outcome of the garbage collection
+pub(crate) struct GcReport {
+    /// URI and size of the removed policies
+    pub(crate) removed: Vec<(String, u64)>,
+    /// Space freed, the modules shared with the policies that are kept are
+    /// not freed
+    pub(crate) reclaimed: u64,
+}
+
+/// Removes the policies of the local store selected by `filters`. Nothing is
+/// removed when `dry_run` is set.
+pub(crate) fn gc(filters: &GcFilters, dry_run: bool) -> Result<GcReport> {
+    if filters.is_empty() {
+        return Err(anyhow!(
+            "at least one of --older-than, --unused and --keep-latest must be given"
+        ));
+    }
+
+    let usage = crate::policies::load_usage();
+    let provenance = crate::provenance::load();
+    let mut policies = Vec::new();
+    for policy in crate::store_profile::store().list()? {
+        let metadata = fs::metadata(&policy.local_path)?;
+        // the modification time is a fallback for the policies without
+        // provenance, it's shared by the modules deduplicated via hard links
+        let pulled = match provenance.get(&policy.uri) {
+            Some(provenance) => provenance.pulled,
+            None => metadata
+                .modified()
+                .map(|modified| OffsetDateTime::from(modified).unix_timestamp())
+                .unwrap_or_default(),
+        };
+        policies.push(StoredPolicy {
+            last_used: usage.get(&policy.uri).copied(),
+            uri: policy.uri,
+            path: policy.local_path,
+            size: metadata.len(),
+            pulled,
+        });
+    }
+
+    let selected = select(
+        policies,
+        filters,
+        OffsetDateTime::now_utc().unix_timestamp(),
+    );
+    let reclaimed = store_dedup::reclaimable(
+        &selected
+            .iter()
+            .map(|policy| policy.path.clone())
+            .collect::<Vec<_>>(),
+    )?;
+    if !dry_run {
+        for policy in &selected {
+            crate::rm::rm(&policy.uri)?;
+        }
+        crate::policies::forget_usage(selected.iter().map(|policy| &policy.uri));
+        store_dedup::prune()?;
+    }
+
+    Ok(GcReport {
+        reclaimed,
+        removed: selected
+            .into_iter()
+            .map(|policy| (policy.uri, policy.size))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const NOW: i64 = 1_700_000_000;
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn policy(uri: &str, pulled_days_ago: i64, used_days_ago: Option<i64>) -> StoredPolicy {
+        StoredPolicy {
+            uri: uri.to_string(),
+            path: PathBuf::from(uri),
+            size: 1000,
+            pulled: NOW - pulled_days_ago * DAY,
+            last_used: used_days_ago.map(|days| NOW - days * DAY),
+        }
+    }
+
+    fn policies() -> Vec<StoredPolicy> {
+        vec![
+            policy(
+                "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.1.0",
+                200,
+                None,
+            ),
+            policy(
+                "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.0",
+                100,
+                Some(5),
+            ),
+            policy(
+                "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.3.0",
+                10,
+                None,
+            ),
+            policy("https://example.com/policy.wasm", 120, Some(100)),
+        ]
+    }
+
+    fn uris(policies: Vec<StoredPolicy>) -> Vec<String> {
+        policies.into_iter().map(|policy| policy.uri).collect()
+    }
+
+    #[rstest]
+    #[case::ninety_days("90d", 90 * 24 * 60 * 60)]
+    #[case::two_weeks("2w", 14 * 24 * 60 * 60)]
+    #[case::minutes("30m", 30 * 60)]
+    fn valid_age(#[case] value: &str, #[case] seconds: u64) {
+        assert_eq!(parse_age(value).unwrap(), Duration::from_secs(seconds));
+    }
+
+    #[rstest]
+    #[case::missing_unit("90")]
+    #[case::unknown_unit("90y")]
+    #[case::empty("")]
+    fn invalid_age(#[case] value: &str) {
+        assert!(parse_age(value).is_err());
+    }
+
+    #[rstest]
+    #[case::older_than(
+        GcFilters { older_than: Some(Duration::from_secs(90 * DAY as u64)), ..Default::default() },
+        vec![
+            "https://example.com/policy.wasm",
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.1.0",
+        ]
+    )]
+    #[case::unused(
+        GcFilters { unused: true, ..Default::default() },
+        vec![
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.1.0",
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.3.0",
+        ]
+    )]
+    #[case::keep_latest(
+        GcFilters { keep_latest: Some(1), ..Default::default() },
+        vec![
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.1.0",
+            "registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.0",
+        ]
+    )]
+    #[case::combined(
+        GcFilters { unused: true, keep_latest: Some(1), ..Default::default() },
+        vec!["registry://ghcr.io/kubewarden/policies/pod-privileged:v0.1.0"]
+    )]
+    fn selection(#[case] filters: GcFilters, #[case] expected: Vec<&str>) {
+        assert_eq!(uris(select(policies(), &filters, NOW)), expected);
+    }
+}
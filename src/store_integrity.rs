@@ -0,0 +1,114 @@
+//! Integrity of the policies of the local store.
+//!
+//! The SHA-256 digest of every policy pulled into the store is recorded with
+//! its provenance, see [`crate::provenance`]. `kwctl store verify` hashes the
+//! policies again and reports the ones that have been corrupted, or tampered
+//! with, since they have been pulled.
+
+use std::{fmt, fs};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::policy::Policy;
+use prettytable::{format, row, Table};
+use sha2::{Digest, Sha256};
+
+fn sha256(policy: &Policy) -> Result<String> {
+    let data = fs::read(&policy.local_path)
+        .map_err(|e| anyhow!("cannot read {}: {}", policy.local_path.display(), e))?;
+    Ok(format!("{:x}", Sha256::digest(data)))
+}
+
+/// Integrity of a policy of the store
+#[derive(Debug, PartialEq)]
+enum Integrity {
+    Intact,
+    /// The policy differs from the one pulled, holds the recorded digest
+    Corrupted(String),
+    /// The policy has been pulled by an older version of kwctl, or copied
+    /// into the store by other means
+    Unrecorded,
+    Unreadable(String),
+}
+
+impl fmt::Display for Integrity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Integrity::Intact => write!(f, "intact"),
+            Integrity::Corrupted(expected) => write!(f, "corrupted: expected sha256:{expected}"),
+            Integrity::Unrecorded => write!(f, "unknown: digest not recorded at pull time"),
+            Integrity::Unreadable(e) => write!(f, "unreadable: {e}"),
+        }
+    }
+}
+
+fn check(digest: &str, recorded: Option<&String>) -> Integrity {
+    match recorded {
+        None => Integrity::Unrecorded,
+        Some(recorded) if digest == recorded => Integrity::Intact,
+        Some(recorded) => Integrity::Corrupted(recorded.to_owned()),
+    }
+}
+
+/// Hashes all the policies of the store, comparing them against the digests
+/// recorded at pull time. Prints the outcome as a table, and fails when any
+/// policy is corrupted or cannot be read.
+pub(crate) fn verify() -> Result<()> {
+    let policies = crate::store_mode::list()?;
+    let recorded = crate::provenance::load();
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "SHA-256", "Integrity"]);
+
+    let mut failures = 0;
+    for policy in &policies {
+        let (mut digest, integrity) = match sha256(policy) {
+            Ok(digest) => {
+                let integrity = check(
+                    &digest,
+                    recorded
+                        .get(&policy.uri)
+                        .map(|provenance| &provenance.digest),
+                );
+                (digest, integrity)
+            }
+            Err(e) => (String::new(), Integrity::Unreadable(e.to_string())),
+        };
+        if matches!(
+            integrity,
+            Integrity::Corrupted(_) | Integrity::Unreadable(_)
+        ) {
+            failures += 1;
+        }
+        digest.truncate(12);
+        table.add_row(row![policy.uri, digest, integrity]);
+    }
+    table.printstd();
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} out of {} policies are corrupted or unreadable, remove them with `kwctl rm` and pull them again",
+            failures,
+            policies.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::intact("abc", Some("abc"), Integrity::Intact)]
+    #[case::corrupted("abc", Some("def"), Integrity::Corrupted("def".to_string()))]
+    #[case::unrecorded("abc", None, Integrity::Unrecorded)]
+    fn integrity(
+        #[case] digest: &str,
+        #[case] recorded: Option<&str>,
+        #[case] expected: Integrity,
+    ) {
+        let recorded = recorded.map(str::to_string);
+        assert_eq!(check(digest, recorded.as_ref()), expected);
+    }
+}
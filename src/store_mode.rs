@@ -0,0 +1,108 @@
+//! Read-only mode of the local policy store.
+//!
+//! A store pre-seeded with the policies needed by a CI pipeline can be shared
+//! by many jobs, for example by mounting it read-only into their containers.
+//! With `--store-read-only` kwctl never writes into the store: pulling a
+//! policy found inside of the store only checks its presence, and the
+//! commands removing policies are refused.
+//!
+//! The policies missing from the store cause an error, unless a writable
+//! overlay directory is given via `--store-overlay`. The missing policies are
+//! then pulled into the overlay, and looked up there after the shared store.
+
+use std::{path::PathBuf, sync::OnceLock};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::{
+    policy::Policy,
+    store::{errors::StoreError, Store},
+};
+
+static MODE: OnceLock<StoreMode> = OnceLock::new();
+
+#[derive(Debug, Default)]
+enum StoreMode {
+    #[default]
+    Writable,
+    ReadOnly {
+        overlay: Option<PathBuf>,
+    },
+}
+
+/// Selects the mode of the local store, must be called before accessing the
+/// store
+pub(crate) fn init(read_only: bool, overlay: Option<PathBuf>) {
+    let mode = if read_only {
+        StoreMode::ReadOnly { overlay }
+    } else {
+        StoreMode::Writable
+    };
+    let _ = MODE.set(mode);
+}
+
+fn mode() -> &'static StoreMode {
+    MODE.get_or_init(StoreMode::default)
+}
+
+pub(crate) fn is_read_only() -> bool {
+    matches!(mode(), StoreMode::ReadOnly { .. })
+}
+
+/// Store holding the policies pulled while the main store is read-only
+pub(crate) fn overlay() -> Option<Store> {
+    match mode() {
+        StoreMode::ReadOnly {
+            overlay: Some(overlay),
+        } => Some(Store::new(overlay)),
+        _ => None,
+    }
+}
+
+/// The overlay, once a policy has been pulled into it
+fn existing_overlay() -> Option<Store> {
+    overlay().filter(|overlay| overlay.root.exists())
+}
+
+/// Fails when the store is read-only, `action` describes the refused
+/// operation
+pub(crate) fn ensure_writable(action: &str) -> Result<()> {
+    if is_read_only() {
+        return Err(anyhow!(
+            "cannot {}: the policy store is read-only (--store-read-only)",
+            action
+        ));
+    }
+    Ok(())
+}
+
+/// Looks for the policy inside of the main store, then inside of the
+/// overlay
+pub(crate) fn find_policy(uri: &str) -> Result<Option<Policy>, StoreError> {
+    if let Some(policy) = crate::store_profile::store().get_policy_by_uri(uri)? {
+        return Ok(Some(policy));
+    }
+    match existing_overlay() {
+        Some(overlay) => overlay.get_policy_by_uri(uri),
+        None => Ok(None),
+    }
+}
+
+/// Like [`find_policy`], looking up the policy by the prefix of its SHA-256
+pub(crate) fn find_policy_by_sha_prefix(sha_prefix: &str) -> Result<Option<Policy>, StoreError> {
+    if let Some(policy) = crate::store_profile::store().get_policy_by_sha_prefix(sha_prefix)? {
+        return Ok(Some(policy));
+    }
+    match existing_overlay() {
+        Some(overlay) => overlay.get_policy_by_sha_prefix(sha_prefix),
+        None => Ok(None),
+    }
+}
+
+/// All the policies of the main store and of the overlay
+pub(crate) fn list() -> Result<Vec<Policy>> {
+    let mut policies = crate::store_profile::store().list()?;
+    if let Some(overlay) = existing_overlay() {
+        policies.extend(overlay.list()?);
+    }
+    Ok(policies)
+}
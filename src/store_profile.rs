@@ -0,0 +1,100 @@
+//! Named stores, selected via `--store-profile` or `KWCTL_STORE`.
+//!
+//! Each named store is a self-contained directory holding its own policies,
+//! its own configuration files, like `sources.yaml` and the default
+//! verification config, and its own cache. Pipelines sharing a machine can
+//! keep their policies and their defaults isolated, for example a
+//! "prod-verified" store and a "dev-scratch" one.
+//!
+//! A name is resolved to a directory inside of the kwctl cache directory,
+//! while a path, containing a path separator, is used as is. Without a
+//! profile, the default store and the default directories are used.
+
+use std::{
+    path::{Path, PathBuf, MAIN_SEPARATOR},
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::store::{Store, DEFAULT_ROOT};
+
+static PROFILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+// Directory, inside of the kwctl cache directory, holding the named stores
+const STORES_DIR: &str = "stores";
+
+/// Selects the store to use, must be called before accessing the store
+pub(crate) fn init(profile: Option<&str>) -> Result<()> {
+    let root = profile.map(resolve).transpose()?;
+    let _ = PROFILE.set(root);
+    Ok(())
+}
+
+/// Directory of the named store, given either its name or its path
+fn resolve(profile: &str) -> Result<PathBuf> {
+    if profile.contains('/') || profile.contains(MAIN_SEPARATOR) || profile.starts_with('.') {
+        return Ok(PathBuf::from(profile));
+    }
+    if profile.is_empty()
+        || !profile
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "invalid store name '{}': only letters, digits, '-' and '_' are allowed. Use a path to select a store outside of the kwctl cache directory",
+            profile
+        ));
+    }
+    Ok(DEFAULT_ROOT.cache_dir().join(STORES_DIR).join(profile))
+}
+
+/// Directory of the selected named store, `None` for the default store
+pub(crate) fn root() -> Option<&'static Path> {
+    PROFILE.get().and_then(Option::as_deref)
+}
+
+/// The store holding the policies
+pub(crate) fn store() -> Store {
+    match root() {
+        Some(root) => Store::new(&root.join("store")),
+        None => Store::default(),
+    }
+}
+
+/// Directory holding the configuration files, like `sources.yaml`
+pub(crate) fn config_dir() -> PathBuf {
+    match root() {
+        Some(root) => root.join("config"),
+        None => DEFAULT_ROOT.config_dir().to_path_buf(),
+    }
+}
+
+/// Directory holding the state kwctl keeps about the policies of the store
+pub(crate) fn cache_dir() -> PathBuf {
+    match root() {
+        Some(root) => root.join("cache"),
+        None => DEFAULT_ROOT.cache_dir().to_path_buf(),
+    }
+}
+
+/// Directory holding the caches of kwctl, like the API resources of the
+/// clusters used by `kwctl scaffold admission-request`
+pub(crate) fn kwctl_cache_dir() -> PathBuf {
+    cache_dir().join("kwctl")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::name("prod-verified", Some(DEFAULT_ROOT.cache_dir().join("stores/prod-verified")))]
+    #[case::relative_path("./ci-store", Some(PathBuf::from("./ci-store")))]
+    #[case::absolute_path("/var/lib/kwctl", Some(PathBuf::from("/var/lib/kwctl")))]
+    #[case::invalid_name("prod verified", None)]
+    #[case::empty("", None)]
+    fn profile_resolution(#[case] profile: &str, #[case] expected: Option<PathBuf>) {
+        assert_eq!(resolve(profile).ok(), expected);
+    }
+}
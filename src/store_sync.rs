@@ -0,0 +1,543 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::{
+    policy::Policy,
+    store::{PolicyPath, Store},
+};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+use url::Url;
+
+/// Prefix of the directories where the files are copied into the remote
+/// store, before being renamed into place
+const TMP_PREFIX: &str = ".kwctl-sync.";
+
+/// Location of a policy store on another machine, reachable via ssh
+#[derive(Debug, PartialEq)]
+struct SshStore {
+    /// `[user@]host`, as expected by the ssh client
+    destination: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl SshStore {
+    fn parse(target: &str) -> Result<Self> {
+        let url = Url::parse(target).map_err(|e| anyhow!("invalid target {}: {}", target, e))?;
+        if url.scheme() != "ssh" {
+            return Err(anyhow!(
+                "invalid target {}, expected ssh://[user@]host[:port]/path",
+                target
+            ));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow!("invalid target {}, the host is missing", target))?;
+        let destination = if url.username().is_empty() {
+            host.to_string()
+        } else {
+            format!("{}@{}", url.username(), host)
+        };
+        let path = url.path().trim_end_matches('/');
+        if path.is_empty() {
+            return Err(anyhow!("invalid target {}, the path is missing", target));
+        }
+
+        Ok(Self {
+            destination,
+            port: url.port(),
+            path: path.to_string(),
+        })
+    }
+
+    /// Runs `script` on the remote machine via the system ssh client, which
+    /// takes care of authentication and host key verification
+    fn command(&self, script: &str) -> Command {
+        let mut command = Command::new("ssh");
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg(&self.destination).arg("--").arg(script);
+        command
+    }
+
+    fn run(&self, script: &str, stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+        debug!(
+            destination = self.destination.as_str(),
+            script, "running remote command"
+        );
+        let mut child = self
+            .command(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("cannot run ssh: {}", e))?;
+        let mut child_stdin = child.stdin.take().expect("stdin is piped");
+        if let Some(data) = stdin {
+            child_stdin.write_all(data)?;
+        }
+        drop(child_stdin);
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "remote command on {} failed ({}): {}",
+                self.destination,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(output.stdout)
+    }
+
+    /// Digests of all the files of the remote store, keyed by their path
+    /// relative to the root of the store
+    fn digests(&self) -> Result<BTreeMap<String, String>> {
+        let script = format!(
+            "if [ -d {path} ]; then cd {path} && find . -type f -exec sha256sum {{}} +; fi",
+            path = shell_quote(&self.path)
+        );
+        let output = String::from_utf8(self.run(&script, None)?)?;
+        parse_sha256sum(&output)
+    }
+
+    /// Copies the files, given as a tar archive, into the remote store with a
+    /// single ssh session. Every file is renamed into place only once all of
+    /// them have been written. Returns the digests of the copied files.
+    fn upload(
+        &self,
+        relative_paths: &[String],
+        archive: &[u8],
+    ) -> Result<BTreeMap<String, String>> {
+        let files = relative_paths
+            .iter()
+            .map(|path| shell_quote(path))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let script = format!(
+            r#"set -e
+mkdir -p {path}
+cd {path}
+tmp=$(mktemp -d {TMP_PREFIX}XXXXXX)
+trap 'rm -rf "$tmp"' EXIT
+tar -xf - -C "$tmp"
+(cd "$tmp" && find . -type f) | while IFS= read -r file; do
+  mkdir -p "$(dirname "$file")"
+  mv "$tmp/$file" "$file"
+done
+sha256sum -- {files}"#,
+            path = shell_quote(&self.path)
+        );
+        let output = String::from_utf8(self.run(&script, Some(archive))?)?;
+        parse_sha256sum(&output)
+    }
+
+    /// Reads the files from the remote store as a tar archive, with a single
+    /// ssh session
+    fn download(&self, relative_paths: &[String]) -> Result<Vec<u8>> {
+        let script = format!("cd {} && tar -cf - -T -", shell_quote(&self.path));
+        let list = relative_paths.iter().fold(String::new(), |mut list, path| {
+            list.push_str(path);
+            list.push('\n');
+            list
+        });
+        self.run(&script, Some(list.as_bytes()))
+    }
+}
+
+/// Outcome of a synchronization
+pub(crate) struct SyncReport {
+    pub(crate) transferred: Vec<String>,
+    pub(crate) up_to_date: usize,
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Parses the output of `sha256sum`, run from the root of the store
+fn parse_sha256sum(output: &str) -> Result<BTreeMap<String, String>> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (digest, path) = line
+                .split_once("  ")
+                .ok_or_else(|| anyhow!("unexpected sha256sum output: {}", line))?;
+            Ok((
+                path.trim_start_matches("./").to_string(),
+                digest.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Digests of the policies of the local store, keyed by their path relative to
+/// the root of the store, the same used by `save` and `load`
+fn local_digests(store: &Store) -> Result<BTreeMap<String, String>> {
+    store
+        .list()?
+        .into_iter()
+        .map(|policy| {
+            let relative_path = store
+                .policy_path(&policy.uri, PolicyPath::PrefixAndFilename)
+                .map_err(|e| anyhow!("cannot find path for policy {}: {}", policy.uri, e))?;
+            let data = fs::read(&policy.local_path).map_err(|e| {
+                anyhow!("cannot read policy {}: {}", policy.local_path.display(), e)
+            })?;
+            Ok((
+                relative_path.to_string_lossy().to_string(),
+                sha256_hex(&data),
+            ))
+        })
+        .collect()
+}
+
+/// The files of `source` that are missing from `destination`, or whose
+/// content is different
+fn files_to_transfer(
+    source: &BTreeMap<String, String>,
+    destination: &BTreeMap<String, String>,
+) -> Vec<String> {
+    source
+        .iter()
+        .filter(|(path, digest)| destination.get(*path) != Some(*digest))
+        .map(|(path, _)| path.to_owned())
+        .collect()
+}
+
+/// The URI of the policy stored at `relative_path`, `None` for the files that
+/// are not policies, like the leftovers of interrupted transfers. Paths
+/// escaping the store are rejected.
+fn policy_uri(store: &Store, relative_path: &str) -> Result<Option<String>> {
+    let path = Path::new(relative_path);
+    if relative_path.contains('\n')
+        || path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "refusing to sync {}, the path is not inside of the store",
+            relative_path
+        ));
+    }
+    let Some((scheme, rest)) = relative_path.split_once('/') else {
+        return Ok(None);
+    };
+    if !matches!(scheme, "registry" | "https" | "http") {
+        return Ok(None);
+    }
+    let uri = format!("{scheme}://{rest}");
+    let maps_back = store
+        .policy_path(&uri, PolicyPath::PrefixAndFilename)
+        .is_ok_and(|policy_path| policy_path == path);
+    Ok(maps_back.then_some(uri))
+}
+
+/// The policy stored at `relative_path`, `None` for the files that are not
+/// policies
+pub(crate) fn stored_policy(store: &Store, relative_path: &str) -> Result<Option<Policy>> {
+    Ok(policy_uri(store, relative_path)?.map(|uri| Policy {
+        uri,
+        local_path: store.root.join(relative_path),
+    }))
+}
+
+/// Only the policies of the remote store are synced
+fn remote_policies(
+    store: &Store,
+    digests: BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>> {
+    let mut policies = BTreeMap::new();
+    for (relative_path, digest) in digests {
+        if policy_uri(store, &relative_path)?.is_some() {
+            policies.insert(relative_path, digest);
+        } else {
+            debug!(path = relative_path.as_str(), "skipping file, not a policy");
+        }
+    }
+    Ok(policies)
+}
+
+/// Copies the policies of the local store that are missing, or different, on
+/// the remote store
+pub(crate) fn push(target: &str) -> Result<SyncReport> {
+    let remote = SshStore::parse(target)?;
+    let store = crate::store_profile::store();
+    let local = local_digests(&store)?;
+    let remote_digests = remote_policies(&store, remote.digests()?)?;
+    let transfers = files_to_transfer(&local, &remote_digests);
+
+    if !transfers.is_empty() {
+        let mut archive = tar::Builder::new(Vec::new());
+        for relative_path in &transfers {
+            archive.append_path_with_name(store.root.join(relative_path), relative_path)?;
+        }
+        let digests = remote.upload(&transfers, &archive.into_inner()?)?;
+        for relative_path in &transfers {
+            if digests.get(relative_path) != Some(&local[relative_path]) {
+                return Err(anyhow!(
+                    "{} has been corrupted while being copied to {}",
+                    relative_path,
+                    target
+                ));
+            }
+            info!(policy = relative_path.as_str(), "policy pushed");
+        }
+    }
+
+    Ok(SyncReport {
+        up_to_date: local.len() - transfers.len(),
+        transferred: transfers,
+    })
+}
+
+/// Copies the policies of the remote store that are missing, or different, on
+/// the local store
+pub(crate) fn pull(target: &str) -> Result<SyncReport> {
+    let remote = SshStore::parse(target)?;
+    let store = crate::store_profile::store();
+    let remote_digests = remote_policies(&store, remote.digests()?)?;
+    let transfers = files_to_transfer(&remote_digests, &local_digests(&store)?);
+
+    if !transfers.is_empty() {
+        let files = read_archive(&remote.download(&transfers)?, &transfers)?;
+        for relative_path in &transfers {
+            let data = files.get(relative_path).ok_or_else(|| {
+                anyhow!(
+                    "{} is missing from the files copied from {}",
+                    relative_path,
+                    target
+                )
+            })?;
+            if sha256_hex(data) != remote_digests[relative_path] {
+                return Err(anyhow!(
+                    "{} has been corrupted while being copied from {}",
+                    relative_path,
+                    target
+                ));
+            }
+        }
+        for relative_path in &transfers {
+            write_atomically(&store.root.join(relative_path), &files[relative_path])?;
+            if let Some(policy) = stored_policy(&store, relative_path)? {
+                // the digest has just been checked against the remote one,
+                // the policy is recorded as pulled from the remote store
+                crate::provenance::record(
+                    &policy,
+                    &format!("{}/{}", target.trim_end_matches('/'), relative_path),
+                );
+            }
+            info!(policy = relative_path.as_str(), "policy pulled");
+        }
+    }
+
+    Ok(SyncReport {
+        up_to_date: remote_digests.len() - transfers.len(),
+        transferred: transfers,
+    })
+}
+
+/// The contents of the expected files of the tar archive, keyed by their
+/// path. The other entries are ignored.
+fn read_archive(archive: &[u8], expected: &[String]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for entry in tar::Archive::new(archive).entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.to_string_lossy().to_string();
+        let path = path.trim_start_matches("./");
+        if !expected.iter().any(|expected| expected == path) {
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        files.insert(path.to_string(), data);
+    }
+    Ok(files)
+}
+
+/// Writes the file through a temporary file renamed over it: readers never
+/// see a partially written policy, and the modules shared by deduplicated
+/// policies are left untouched
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(PathBuf::new);
+    fs::create_dir_all(&parent)?;
+    let mut file = tempfile::NamedTempFile::new_in(&parent)?;
+    file.write_all(data)?;
+    file.persist(path)
+        .map_err(|e| anyhow!("cannot write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::host(
+        "ssh://jump-host/var/lib/kwctl/store",
+        "jump-host",
+        None,
+        "/var/lib/kwctl/store"
+    )]
+    #[case::user_and_port(
+        "ssh://kubewarden@10.0.0.4:2222/home/kubewarden/.cache/kubewarden/store/",
+        "kubewarden@10.0.0.4",
+        Some(2222),
+        "/home/kubewarden/.cache/kubewarden/store"
+    )]
+    fn parse_ssh_store(
+        #[case] target: &str,
+        #[case] destination: &str,
+        #[case] port: Option<u16>,
+        #[case] path: &str,
+    ) {
+        assert_eq!(
+            SshStore::parse(target).unwrap(),
+            SshStore {
+                destination: destination.to_string(),
+                port,
+                path: path.to_string(),
+            }
+        );
+    }
+
+    #[rstest]
+    #[case::wrong_scheme("https://jump-host/store")]
+    #[case::missing_path("ssh://jump-host")]
+    #[case::not_an_url("jump-host:/store")]
+    fn parse_invalid_ssh_store(#[case] target: &str) {
+        assert!(SshStore::parse(target).is_err());
+    }
+
+    #[test]
+    fn only_missing_and_changed_files_are_transferred() {
+        let remote = parse_sha256sum(
+            "aaaa  ./registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13\nbbbb  ./registry/ghcr.io/kubewarden/tests/pod-privileged:v0.2.5\n",
+        )
+        .unwrap();
+        let local = BTreeMap::from([
+            (
+                "registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13".to_string(),
+                "aaaa".to_string(),
+            ),
+            (
+                "registry/ghcr.io/kubewarden/tests/pod-privileged:v0.2.5".to_string(),
+                "cccc".to_string(),
+            ),
+            (
+                "https/example.com/policy.wasm".to_string(),
+                "dddd".to_string(),
+            ),
+        ]);
+
+        assert_eq!(
+            files_to_transfer(&local, &remote),
+            vec![
+                "https/example.com/policy.wasm",
+                "registry/ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::registry(
+        "registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+        Some("registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13")
+    )]
+    #[case::https(
+        "https/example.com/policy.wasm",
+        Some("https://example.com/policy.wasm")
+    )]
+    #[case::leftover(
+        ".kwctl-sync.a1b2c3/registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+        None
+    )]
+    #[case::not_a_policy("README", None)]
+    fn policy_uris(#[case] relative_path: &str, #[case] expected: Option<&str>) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path());
+        assert_eq!(
+            policy_uri(&store, relative_path).unwrap().as_deref(),
+            expected
+        );
+    }
+
+    #[test]
+    fn stored_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path());
+        let policy = stored_policy(
+            &store,
+            "registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            policy.uri,
+            "registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13"
+        );
+        assert_eq!(
+            policy.local_path,
+            dir.path()
+                .join("registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13")
+        );
+        assert!(stored_policy(&store, "README").unwrap().is_none());
+    }
+
+    #[rstest]
+    #[case::parent("registry/../../.bashrc")]
+    #[case::absolute("/etc/cron.d/kwctl")]
+    fn paths_outside_of_the_store(#[case] relative_path: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Store::new(dir.path());
+        assert!(policy_uri(&store, relative_path).is_err());
+    }
+
+    #[test]
+    fn archive_entries() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in [
+            (
+                "registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13",
+                b"\0asm",
+            ),
+            ("other", b"data"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, &data[..]).unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+
+        let expected = vec!["registry/ghcr.io/kubewarden/tests/safe-labels:v0.1.13".to_string()];
+        let files = read_archive(&archive, &expected).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[&expected[0]], b"\0asm");
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("/it's/here"), r"'/it'\''s/here'");
+    }
+}
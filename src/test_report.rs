@@ -0,0 +1,393 @@
+//! Reports of the test cases evaluated by `kwctl test` and `kwctl run`, and
+//! of the resources evaluated by `kwctl audit`, written for the test
+//! summaries of CI systems.
+//!
+//! JUnit XML is understood by GitLab, Jenkins and the GitHub actions
+//! publishing test results, TAP by most of the other tools. SARIF is ingested
+//! by GitHub code scanning and by the security dashboards: only the failed
+//! cases are reported, as the findings of the tool.
+
+use std::{fmt::Write, fs, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, Result};
+use clap::ArgMatches;
+use serde_json::json;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ReportFormat {
+    Junit,
+    Tap,
+    Sarif,
+}
+
+impl TryFrom<&str> for ReportFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "junit" => Ok(Self::Junit),
+            "tap" => Ok(Self::Tap),
+            "sarif" => Ok(Self::Sarif),
+            _ => Err(anyhow!("unknown report format: {}", value)),
+        }
+    }
+}
+
+/// Where and how the report is written
+pub(crate) struct ReportOutput {
+    pub(crate) path: PathBuf,
+    pub(crate) format: ReportFormat,
+}
+
+/// The report requested via `--report-path` and `--report-format`
+pub(crate) fn report_output(matches: &ArgMatches) -> Result<Option<ReportOutput>> {
+    let Some(path) = matches.get_one::<String>("report-path") else {
+        return Ok(None);
+    };
+    let format = ReportFormat::try_from(
+        matches
+            .get_one::<String>("report-format")
+            .map(String::as_str)
+            .unwrap_or("junit"),
+    )?;
+    Ok(Some(ReportOutput {
+        path: PathBuf::from(path),
+        format,
+    }))
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum CaseResult {
+    Passed,
+    /// The outcome is not the expected one
+    Failed(String),
+    /// The case could not be evaluated
+    Error(String),
+}
+
+/// Where the evaluated object is defined, like the manifest of an audited
+/// resource
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Location {
+    pub(crate) path: String,
+    /// Starting from 1, missing when the whole file is the object
+    pub(crate) line: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TestCaseReport {
+    pub(crate) name: String,
+    pub(crate) result: CaseResult,
+    pub(crate) duration: Duration,
+    pub(crate) location: Option<Location>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TestSuiteReport {
+    pub(crate) name: String,
+    pub(crate) cases: Vec<TestCaseReport>,
+}
+
+impl TestSuiteReport {
+    fn count(&self, filter: impl Fn(&CaseResult) -> bool) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| filter(&case.result))
+            .count()
+    }
+
+    fn failures(&self) -> usize {
+        self.count(|result| matches!(result, CaseResult::Failed(_)))
+    }
+
+    fn errors(&self) -> usize {
+        self.count(|result| matches!(result, CaseResult::Error(_)))
+    }
+
+    fn duration(&self) -> Duration {
+        self.cases.iter().map(|case| case.duration).sum()
+    }
+}
+
+pub(crate) fn write(suites: &[TestSuiteReport], output: &ReportOutput) -> Result<()> {
+    let report = match output.format {
+        ReportFormat::Junit => junit(suites),
+        ReportFormat::Tap => tap(suites),
+        ReportFormat::Sarif => sarif(suites),
+    };
+    fs::write(&output.path, report).map_err(|e| {
+        anyhow!(
+            "cannot write test report to {}: {}",
+            output.path.display(),
+            e
+        )
+    })
+}
+
+fn junit(suites: &[TestSuiteReport]) -> String {
+    let mut report = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        report,
+        "<testsuites name=\"kwctl\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+        suites.iter().map(|suite| suite.cases.len()).sum::<usize>(),
+        suites.iter().map(TestSuiteReport::failures).sum::<usize>(),
+        suites.iter().map(TestSuiteReport::errors).sum::<usize>(),
+        suites
+            .iter()
+            .map(TestSuiteReport::duration)
+            .sum::<Duration>()
+            .as_secs_f64()
+    );
+    for suite in suites {
+        let _ = writeln!(
+            report,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">",
+            escape_xml(&suite.name),
+            suite.cases.len(),
+            suite.failures(),
+            suite.errors(),
+            suite.duration().as_secs_f64()
+        );
+        for case in &suite.cases {
+            let _ = write!(
+                report,
+                "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                escape_xml(&case.name),
+                escape_xml(&suite.name),
+                case.duration.as_secs_f64()
+            );
+            let (element, message) = match &case.result {
+                CaseResult::Passed => {
+                    report.push_str("/>\n");
+                    continue;
+                }
+                CaseResult::Failed(message) => ("failure", message),
+                CaseResult::Error(message) => ("error", message),
+            };
+            let _ = writeln!(
+                report,
+                ">\n      <{element} message=\"{}\">{}</{element}>\n    </testcase>",
+                escape_xml(message.lines().next().unwrap_or_default()),
+                escape_xml(message)
+            );
+        }
+        report.push_str("  </testsuite>\n");
+    }
+    report.push_str("</testsuites>\n");
+    report
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// TAP version 13, the failures are reported as YAML diagnostics
+fn tap(suites: &[TestSuiteReport]) -> String {
+    let cases: Vec<(&TestSuiteReport, &TestCaseReport)> = suites
+        .iter()
+        .flat_map(|suite| suite.cases.iter().map(move |case| (suite, case)))
+        .collect();
+
+    let mut report = format!("TAP version 13\n1..{}\n", cases.len());
+    for (index, (suite, case)) in cases.iter().enumerate() {
+        // `#` starts the directives of TAP
+        let description = format!("{} - {}", suite.name, case.name).replace('#', "\\#");
+        let (severity, message) = match &case.result {
+            CaseResult::Passed => {
+                let _ = writeln!(report, "ok {} - {}", index + 1, description);
+                continue;
+            }
+            CaseResult::Failed(message) => ("fail", message),
+            CaseResult::Error(message) => ("error", message),
+        };
+        let _ = writeln!(report, "not ok {} - {}", index + 1, description);
+        let _ = writeln!(report, "  ---\n  severity: {severity}\n  message: |-");
+        for line in message.lines() {
+            let _ = writeln!(report, "    {line}");
+        }
+        report.push_str("  ...\n");
+    }
+    report
+}
+
+/// SARIF 2.1.0, with a rule for every suite and a result for every failed
+/// case. The cases that could not be evaluated are reported as warnings.
+fn sarif(suites: &[TestSuiteReport]) -> String {
+    let rules: Vec<_> = suites
+        .iter()
+        .map(|suite| {
+            json!({
+                "id": suite.name,
+                "shortDescription": {"text": suite.name},
+            })
+        })
+        .collect();
+    let mut results = Vec::new();
+    for (rule_index, suite) in suites.iter().enumerate() {
+        for case in &suite.cases {
+            let (level, message) = match &case.result {
+                CaseResult::Passed => continue,
+                CaseResult::Failed(message) => ("error", message),
+                CaseResult::Error(message) => ("warning", message),
+            };
+            let mut result = json!({
+                "ruleId": suite.name,
+                "ruleIndex": rule_index,
+                "level": level,
+                "message": {"text": format!("{}: {}", case.name, message)},
+            });
+            if let Some(location) = &case.location {
+                let mut physical_location = json!({
+                    "artifactLocation": {"uri": location.path.replace('\\', "/")},
+                });
+                if let Some(line) = location.line {
+                    physical_location["region"] = json!({"startLine": line});
+                }
+                result["locations"] = json!([{"physicalLocation": physical_location}]);
+            }
+            results.push(result);
+        }
+    }
+
+    let report = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "kwctl",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/kubewarden/kwctl",
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    });
+    let mut report = serde_json::to_string_pretty(&report).unwrap_or_default();
+    report.push('\n');
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suites() -> Vec<TestSuiteReport> {
+        vec![TestSuiteReport {
+            name: "tests/pod-privileged.yml".to_string(),
+            cases: vec![
+                TestCaseReport {
+                    name: "unprivileged pods are accepted".to_string(),
+                    result: CaseResult::Passed,
+                    duration: Duration::from_millis(12),
+                    location: None,
+                },
+                TestCaseReport {
+                    name: "privileged pods are rejected #1".to_string(),
+                    result: CaseResult::Failed(
+                        "expected the request to be rejected, it was accepted\n<no message>"
+                            .to_string(),
+                    ),
+                    duration: Duration::from_millis(8),
+                    location: Some(Location {
+                        path: "tests/pod-privileged.yml".to_string(),
+                        line: Some(14),
+                    }),
+                },
+                TestCaseReport {
+                    name: "broken request".to_string(),
+                    result: CaseResult::Error("cannot parse request".to_string()),
+                    duration: Duration::ZERO,
+                    location: None,
+                },
+            ],
+        }]
+    }
+
+    #[test]
+    fn junit_report() {
+        assert_eq!(
+            junit(&suites()),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="kwctl" tests="3" failures="1" errors="1" time="0.020">
+  <testsuite name="tests/pod-privileged.yml" tests="3" failures="1" errors="1" time="0.020">
+    <testcase name="unprivileged pods are accepted" classname="tests/pod-privileged.yml" time="0.012"/>
+    <testcase name="privileged pods are rejected #1" classname="tests/pod-privileged.yml" time="0.008">
+      <failure message="expected the request to be rejected, it was accepted">expected the request to be rejected, it was accepted
+&lt;no message&gt;</failure>
+    </testcase>
+    <testcase name="broken request" classname="tests/pod-privileged.yml" time="0.000">
+      <error message="cannot parse request">cannot parse request</error>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn tap_report() {
+        assert_eq!(
+            tap(&suites()),
+            r#"TAP version 13
+1..3
+ok 1 - tests/pod-privileged.yml - unprivileged pods are accepted
+not ok 2 - tests/pod-privileged.yml - privileged pods are rejected \#1
+  ---
+  severity: fail
+  message: |-
+    expected the request to be rejected, it was accepted
+    <no message>
+  ...
+not ok 3 - tests/pod-privileged.yml - broken request
+  ---
+  severity: error
+  message: |-
+    cannot parse request
+  ...
+"#
+        );
+    }
+
+    #[test]
+    fn sarif_report() {
+        let report: serde_json::Value = serde_json::from_str(&sarif(&suites())).unwrap();
+        let run = &report["runs"][0];
+        assert_eq!(report["version"], "2.1.0");
+        assert_eq!(
+            run["tool"]["driver"]["rules"][0]["id"],
+            "tests/pod-privileged.yml"
+        );
+        assert_eq!(
+            run["results"],
+            json!([
+                {
+                    "ruleId": "tests/pod-privileged.yml",
+                    "ruleIndex": 0,
+                    "level": "error",
+                    "message": {
+                        "text": "privileged pods are rejected #1: expected the request to be rejected, it was accepted\n<no message>"
+                    },
+                    "locations": [{
+                        "physicalLocation": {
+                            "artifactLocation": {"uri": "tests/pod-privileged.yml"},
+                            "region": {"startLine": 14}
+                        }
+                    }]
+                },
+                {
+                    "ruleId": "tests/pod-privileged.yml",
+                    "ruleIndex": 0,
+                    "level": "warning",
+                    "message": {"text": "broken request: cannot parse request"}
+                }
+            ])
+        );
+    }
+}
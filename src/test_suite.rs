@@ -0,0 +1,864 @@
+//! Declarative test suites of a policy, run by `kwctl test`.
+//!
+//! A suite is a YAML file naming the policy, its settings and a list of test
+//! cases. Every case evaluates a request fixture and describes the expected
+//! outcome: whether the request is allowed, and optionally the rejection
+//! message and the JSON patch returned by the policy.
+//!
+//! ```yaml
+//! policy: registry://ghcr.io/kubewarden/policies/pod-privileged:v0.2.5
+//! settings: {}
+//! tests:
+//! - name: privileged pods are rejected
+//!   request: requests/privileged-pod.json
+//!   expect:
+//!     allowed: false
+//!     message: "Privileged container is not allowed"
+//! ```
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine as _};
+use policy_evaluator::{
+    admission_response::AdmissionResponse,
+    evaluation_context::EvaluationContext,
+    policy_evaluator::{PolicyEvaluator, PolicyEvaluatorPre, PolicySettings},
+    policy_evaluator_builder::PolicyEvaluatorBuilder,
+    policy_fetcher::sources::Sources,
+    policy_metadata::Metadata,
+};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::metrics::Metrics;
+
+use crate::{
+    backend::BackendDetector,
+    changelog::line_diff,
+    command::run::{
+        evaluator::{build_validate_request, has_raw_policy_type},
+        policy_execution_mode::determine_execution_mode,
+    },
+    config::sources::RegistryMirrors,
+    store_sync::write_atomically,
+    test_report::{CaseResult, Location, TestCaseReport, TestSuiteReport},
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub(crate) struct TestSuite {
+    /// URI or SHA prefix of the policy, or path of its module relative to
+    /// the suite
+    policy: String,
+    /// Settings of the policy, used by the cases that don't provide their own
+    #[serde(default)]
+    settings: Value,
+    /// Whether the requests are raw ones
+    #[serde(default)]
+    raw: bool,
+    tests: Vec<TestCase>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TestCase {
+    name: String,
+    /// JSON file holding the request, relative to the suite
+    request: PathBuf,
+    /// Settings replacing the ones of the suite
+    settings: Option<Value>,
+    expect: Expectation,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct Expectation {
+    allowed: bool,
+    /// Message of the rejection, compared as is
+    message: Option<String>,
+    /// JSON patch returned by the policy, an empty list when the request is
+    /// not mutated
+    patch: Option<Value>,
+    /// JSON file holding the patch returned by the policy, relative to the
+    /// suite. Recorded by `kwctl test --update-snapshots`
+    patch_snapshot: Option<PathBuf>,
+}
+
+/// Outcome of a test case, failed when at least a failure is recorded
+pub(crate) struct CaseOutcome {
+    name: String,
+    failures: Vec<String>,
+    /// The patch snapshot written by the case, when it changed
+    updated_snapshot: Option<PathBuf>,
+    /// Whether the case could not be evaluated, the failure being the
+    /// evaluation error
+    error: bool,
+    duration: Duration,
+}
+
+/// Outcome of all the cases of a suite
+pub(crate) struct SuiteOutcome {
+    path: PathBuf,
+    policy: String,
+    cases: Vec<CaseOutcome>,
+}
+
+impl SuiteOutcome {
+    pub(crate) fn failed(&self) -> usize {
+        self.cases
+            .iter()
+            .filter(|case| !case.failures.is_empty())
+            .count()
+    }
+
+    pub(crate) fn total(&self) -> usize {
+        self.cases.len()
+    }
+
+    /// The report of the suite, named after its path
+    pub(crate) fn report(&self) -> TestSuiteReport {
+        TestSuiteReport {
+            name: self.path.display().to_string(),
+            cases: self
+                .cases
+                .iter()
+                .map(|case| TestCaseReport {
+                    name: case.name.clone(),
+                    result: match (case.failures.is_empty(), case.error) {
+                        (true, _) => CaseResult::Passed,
+                        (false, true) => CaseResult::Error(case.failures.join("\n")),
+                        (false, false) => CaseResult::Failed(case.failures.join("\n")),
+                    },
+                    duration: case.duration,
+                    location: Some(Location {
+                        path: self.path.display().to_string(),
+                        line: None,
+                    }),
+                })
+                .collect(),
+        }
+    }
+
+    /// Lists the cases, followed by the failures of the failed ones
+    pub(crate) fn render(&self) -> String {
+        let mut report = format!("{} ({})\n", self.path.display(), self.policy);
+        for case in &self.cases {
+            if case.failures.is_empty() {
+                let _ = match &case.updated_snapshot {
+                    Some(snapshot) => writeln!(
+                        report,
+                        "  ok      {} (snapshot {} updated)",
+                        case.name,
+                        snapshot.display()
+                    ),
+                    None => writeln!(report, "  ok      {}", case.name),
+                };
+                continue;
+            }
+            let _ = writeln!(report, "  FAILED  {}", case.name);
+            for failure in &case.failures {
+                for line in failure.lines() {
+                    let _ = writeln!(report, "          {line}");
+                }
+            }
+        }
+        report
+    }
+}
+
+impl TestSuite {
+    pub(crate) fn policy(&self) -> &str {
+        &self.policy
+    }
+}
+
+/// Reads the suite at `path`
+pub(crate) fn load(path: &Path) -> Result<TestSuite> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("cannot read test suite {}: {}", path.display(), e))?;
+    let suite: TestSuite = serde_yaml::from_str(&contents)
+        .map_err(|e| anyhow!("cannot parse test suite {}: {}", path.display(), e))?;
+    if suite.tests.is_empty() {
+        return Err(anyhow!("test suite {} has no test", path.display()));
+    }
+    if let Some(case) = suite
+        .tests
+        .iter()
+        .find(|case| case.expect.patch.is_some() && case.expect.patch_snapshot.is_some())
+    {
+        return Err(anyhow!(
+            "test suite {}: the test '{}' cannot expect both a patch and a patch snapshot",
+            path.display(),
+            case.name
+        ));
+    }
+    Ok(suite)
+}
+
+/// Options of `kwctl test`
+pub(crate) struct RunOptions {
+    /// Record the patch snapshots instead of comparing them
+    pub(crate) update_snapshots: bool,
+    /// Only the cases whose name matches the regular expression are run
+    pub(crate) filter: Option<Regex>,
+    /// Stop running the cases once one fails
+    pub(crate) fail_fast: bool,
+    /// Number of cases evaluated in parallel
+    pub(crate) jobs: usize,
+}
+
+impl RunOptions {
+    fn selects(&self, case: &TestCase) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.is_match(&case.name))
+    }
+
+    /// Whether at least a case of the suite is going to be run
+    pub(crate) fn selects_any(&self, suite: &TestSuite) -> bool {
+        suite.tests.iter().any(|case| self.selects(case))
+    }
+}
+
+/// A suite whose policy has been fetched, ready to be run
+pub(crate) struct PreparedSuite {
+    path: PathBuf,
+    suite: TestSuite,
+    uri: String,
+    raw: bool,
+    policy_evaluator_pre: PolicyEvaluatorPre,
+}
+
+/// Fetches the policy of the suite read from `path`, pulling it into the
+/// store when missing
+pub(crate) async fn prepare(
+    path: &Path,
+    suite: TestSuite,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+) -> Result<PreparedSuite> {
+    // the fixtures, and the policy when it's a local file, are relative to
+    // the suite
+    let policy_path = base_dir(path).join(&suite.policy);
+    let policy = if policy_path.is_file() {
+        policy_path.to_string_lossy().into_owned()
+    } else {
+        suite.policy.clone()
+    };
+    let uri = crate::utils::get_uri(&policy)?;
+    let uri = crate::version_constraints::resolve(&uri, sources).await?;
+    let wasm_path = crate::pull::local_path(&uri, sources, mirrors).await?;
+    let metadata = Metadata::from_path(&wasm_path)
+        .map_err(|e| anyhow!("Error parsing policy metadata: {}", e))?;
+    let execution_mode = determine_execution_mode(
+        metadata.as_ref(),
+        None,
+        BackendDetector::default(),
+        &wasm_path,
+    )?;
+    let policy_evaluator_pre = PolicyEvaluatorBuilder::new()
+        .policy_file(&wasm_path)?
+        .execution_mode(execution_mode)
+        .enable_wasmtime_cache()
+        .build_pre()?;
+
+    Ok(PreparedSuite {
+        path: path.to_path_buf(),
+        raw: suite.raw || has_raw_policy_type(metadata.as_ref()),
+        suite,
+        uri,
+        policy_evaluator_pre,
+    })
+}
+
+fn base_dir(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+impl PreparedSuite {
+    /// The policies are evaluated without access to the host capabilities
+    fn evaluator(&self) -> Result<PolicyEvaluator> {
+        let eval_ctx = EvaluationContext {
+            policy_id: self.uri.to_owned(),
+            callback_channel: None,
+            ctx_aware_resources_allow_list: BTreeSet::new(),
+        };
+        Ok(self.policy_evaluator_pre.rehydrate(&eval_ctx)?)
+    }
+}
+
+/// Outcome of all the suites
+pub(crate) struct TestRun {
+    pub(crate) suites: Vec<SuiteOutcome>,
+    /// Cases excluded by the filter
+    pub(crate) filtered_out: usize,
+    /// Cases skipped after a failure, because of `fail_fast`
+    pub(crate) not_run: usize,
+}
+
+impl TestRun {
+    /// Summary of the run, written via `--metrics-path`
+    pub(crate) fn metrics(&self, duration: Duration) -> Metrics {
+        let mut metrics = Metrics::default();
+        for outcome in &self.suites {
+            let suite = outcome.path.display().to_string();
+            for (result, cases) in [
+                ("passed", outcome.total() - outcome.failed()),
+                ("failed", outcome.failed()),
+            ] {
+                metrics.counter(
+                    "kwctl_test_cases",
+                    "Test cases run, by suite and result",
+                    &[("suite", &suite), ("result", result)],
+                    cases as f64,
+                );
+            }
+        }
+        for (reason, cases) in [
+            ("filtered_out", self.filtered_out),
+            ("not_run", self.not_run),
+        ] {
+            metrics.counter(
+                "kwctl_test_skipped_cases",
+                "Test cases skipped, by reason",
+                &[("reason", reason)],
+                cases as f64,
+            );
+        }
+        for outcome in &self.suites {
+            metrics.gauge(
+                "kwctl_test_suite_duration_seconds",
+                "Time spent evaluating the test cases of the suite",
+                &[("suite", &outcome.path.display().to_string())],
+                outcome
+                    .cases
+                    .iter()
+                    .map(|case| case.duration)
+                    .sum::<Duration>()
+                    .as_secs_f64(),
+            );
+        }
+        metrics.gauge(
+            "kwctl_test_duration_seconds",
+            "Duration of the whole run, preparation of the policies included",
+            &[],
+            duration.as_secs_f64(),
+        );
+        metrics
+    }
+}
+
+/// Runs the selected cases of the suites, spreading them across
+/// `options.jobs` threads. Every thread evaluates the cases with its own
+/// instances of the policies.
+pub(crate) fn run(suites: &[PreparedSuite], options: &RunOptions) -> TestRun {
+    let selected: Vec<(usize, &TestCase)> = suites
+        .iter()
+        .enumerate()
+        .flat_map(|(index, prepared)| prepared.suite.tests.iter().map(move |case| (index, case)))
+        .filter(|(_, case)| options.selects(case))
+        .collect();
+    let total: usize = suites
+        .iter()
+        .map(|prepared| prepared.suite.tests.len())
+        .sum();
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let outcomes: Mutex<Vec<Option<CaseOutcome>>> =
+        Mutex::new(selected.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..options.jobs.clamp(1, selected.len().max(1)) {
+            scope.spawn(|| {
+                let mut evaluators: HashMap<usize, Result<PolicyEvaluator, String>> =
+                    HashMap::new();
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((suite_index, case)) = selected.get(index) else {
+                        break;
+                    };
+                    let prepared = &suites[*suite_index];
+                    let evaluator = evaluators
+                        .entry(*suite_index)
+                        .or_insert_with(|| prepared.evaluator().map_err(|e| e.to_string()));
+                    let outcome = match evaluator {
+                        Ok(evaluator) => run_case(
+                            case,
+                            &prepared.suite.settings,
+                            prepared.raw,
+                            base_dir(&prepared.path),
+                            options.update_snapshots,
+                            evaluator,
+                        ),
+                        Err(e) => CaseOutcome {
+                            name: case.name.clone(),
+                            failures: vec![e.clone()],
+                            updated_snapshot: None,
+                            error: true,
+                            duration: Duration::ZERO,
+                        },
+                    };
+                    if options.fail_fast && !outcome.failures.is_empty() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    outcomes.lock().expect("poisoned lock")[index] = Some(outcome);
+                }
+            });
+        }
+    });
+
+    let outcomes = outcomes.into_inner().expect("poisoned lock");
+    let not_run = outcomes.iter().filter(|outcome| outcome.is_none()).count();
+    let mut suite_outcomes: Vec<SuiteOutcome> = suites
+        .iter()
+        .map(|prepared| SuiteOutcome {
+            path: prepared.path.clone(),
+            policy: prepared.uri.clone(),
+            cases: vec![],
+        })
+        .collect();
+    for ((suite_index, _), outcome) in selected.iter().zip(outcomes) {
+        if let Some(outcome) = outcome {
+            suite_outcomes[*suite_index].cases.push(outcome);
+        }
+    }
+
+    TestRun {
+        suites: suite_outcomes
+            .into_iter()
+            .filter(|outcome| !outcome.cases.is_empty())
+            .collect(),
+        filtered_out: total - selected.len(),
+        not_run,
+    }
+}
+
+fn run_case(
+    case: &TestCase,
+    suite_settings: &Value,
+    raw: bool,
+    base_dir: &Path,
+    update_snapshots: bool,
+    evaluator: &mut PolicyEvaluator,
+) -> CaseOutcome {
+    let start = Instant::now();
+    let mut outcome = CaseOutcome {
+        name: case.name.clone(),
+        failures: vec![],
+        updated_snapshot: None,
+        error: false,
+        duration: Duration::ZERO,
+    };
+    let response = match evaluate_case(case, suite_settings, raw, base_dir, evaluator) {
+        Ok(response) => response,
+        Err(e) => {
+            outcome.failures.push(e.to_string());
+            outcome.error = true;
+            outcome.duration = start.elapsed();
+            return outcome;
+        }
+    };
+    outcome.duration = start.elapsed();
+
+    outcome.failures = check(&case.expect, &response);
+    if let Some(snapshot) = &case.expect.patch_snapshot {
+        let snapshot = base_dir.join(snapshot);
+        if update_snapshots {
+            match update_snapshot(&snapshot, &response) {
+                Ok(true) => outcome.updated_snapshot = Some(snapshot),
+                Ok(false) => {}
+                Err(e) => outcome.failures.push(e.to_string()),
+            }
+        } else if let Some(failure) =
+            check_snapshot(&snapshot, &response).unwrap_or_else(|e| Some(e.to_string()))
+        {
+            outcome.failures.push(failure);
+        }
+    }
+    outcome
+}
+
+fn evaluate_case(
+    case: &TestCase,
+    suite_settings: &Value,
+    raw: bool,
+    base_dir: &Path,
+    evaluator: &mut PolicyEvaluator,
+) -> Result<AdmissionResponse> {
+    let settings = PolicySettings::try_from(case.settings.as_ref().unwrap_or(suite_settings))
+        .map_err(|e| anyhow!("invalid settings: {}", e))?;
+    let settings_validation_response = evaluator.validate_settings(&settings);
+    if !settings_validation_response.valid {
+        return Err(anyhow!(
+            "invalid settings: {}",
+            settings_validation_response.message.unwrap_or_default()
+        ));
+    }
+
+    let request_path = base_dir.join(&case.request);
+    let request: Value = serde_json::from_slice(
+        &fs::read(&request_path)
+            .map_err(|e| anyhow!("cannot read {}: {}", request_path.display(), e))?,
+    )
+    .map_err(|e| anyhow!("cannot parse request {}: {}", request_path.display(), e))?;
+    let request = build_validate_request(&request, raw)?;
+
+    Ok(evaluator.validate(request, &settings))
+}
+
+fn outcome(allowed: bool) -> &'static str {
+    if allowed {
+        "accepted"
+    } else {
+        "rejected"
+    }
+}
+
+/// Compares the response of the policy with the expected one, returning the
+/// differences
+fn check(expect: &Expectation, response: &AdmissionResponse) -> Vec<String> {
+    let mut failures = Vec::new();
+    let message = response
+        .status
+        .as_ref()
+        .and_then(|status| status.message.clone())
+        .unwrap_or_default();
+
+    if expect.allowed != response.allowed {
+        let mut failure = format!(
+            "expected the request to be {}, it was {}",
+            outcome(expect.allowed),
+            outcome(response.allowed)
+        );
+        if !response.allowed && !message.is_empty() {
+            let _ = write!(failure, ": {message}");
+        }
+        failures.push(failure);
+    }
+
+    if let Some(expected_message) = &expect.message {
+        if *expected_message != message {
+            failures.push(format!(
+                "unexpected message:\n{}",
+                line_diff(expected_message, &message)
+            ));
+        }
+    }
+
+    if let Some(expected_patch) = &expect.patch {
+        match patch(response) {
+            Ok(patch) => failures.extend(patch_difference(expected_patch, &patch)),
+            Err(e) => failures.push(e.to_string()),
+        }
+    }
+
+    failures
+}
+
+fn patch_difference(expected: &Value, actual: &Value) -> Option<String> {
+    (expected != actual).then(|| {
+        format!(
+            "unexpected patch:\n{}",
+            line_diff(&pretty(expected), &pretty(actual))
+        )
+    })
+}
+
+fn pretty(patch: &Value) -> String {
+    serde_json::to_string_pretty(patch).unwrap_or_default()
+}
+
+fn read_snapshot(snapshot: &Path) -> Result<Value> {
+    serde_json::from_slice(
+        &fs::read(snapshot).map_err(|e| anyhow!("cannot read {}: {}", snapshot.display(), e))?,
+    )
+    .map_err(|e| anyhow!("cannot parse patch snapshot {}: {}", snapshot.display(), e))
+}
+
+/// Compares the patch returned by the policy with the snapshot, returning
+/// their difference
+fn check_snapshot(snapshot: &Path, response: &AdmissionResponse) -> Result<Option<String>> {
+    if !snapshot.exists() {
+        return Ok(Some(format!(
+            "the patch snapshot {} does not exist, record it with --update-snapshots",
+            snapshot.display()
+        )));
+    }
+    Ok(patch_difference(
+        &read_snapshot(snapshot)?,
+        &patch(response)?,
+    ))
+}
+
+/// Records the patch returned by the policy into the snapshot. Returns
+/// whether the snapshot has been written, which happens only when it changed
+fn update_snapshot(snapshot: &Path, response: &AdmissionResponse) -> Result<bool> {
+    let patch = patch(response)?;
+    if snapshot.exists() && read_snapshot(snapshot).is_ok_and(|recorded| recorded == patch) {
+        return Ok(false);
+    }
+    if let Some(parent) = snapshot.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| anyhow!("cannot create {}: {}", parent.display(), e))?;
+    }
+    write_atomically(snapshot, format!("{}\n", pretty(&patch)).as_bytes())?;
+    Ok(true)
+}
+
+/// The JSON patch returned by the policy, an empty one when the request is
+/// not mutated
+fn patch(response: &AdmissionResponse) -> Result<Value> {
+    let Some(patch) = &response.patch else {
+        return Ok(Value::Array(vec![]));
+    };
+    let patch = general_purpose::STANDARD
+        .decode(patch)
+        .map_err(|e| anyhow!("cannot decode the patch returned by the policy: {e}"))?;
+    serde_json::from_slice(&patch)
+        .map_err(|e| anyhow!("the policy returned an invalid JSON patch: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::admission_response::AdmissionResponseStatus;
+    use rstest::rstest;
+    use serde_json::json;
+
+    fn response(allowed: bool, message: Option<&str>, patch: Option<Value>) -> AdmissionResponse {
+        AdmissionResponse {
+            allowed,
+            patch: patch.map(|patch| general_purpose::STANDARD.encode(patch.to_string())),
+            status: message.map(|message| AdmissionResponseStatus {
+                message: Some(message.to_string()),
+                code: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn expectation(expect: &str) -> Expectation {
+        serde_yaml::from_str(expect).unwrap()
+    }
+
+    #[rstest]
+    #[case::accepted("allowed: true", response(true, None, None))]
+    #[case::rejected(
+        "{allowed: false, message: privileged containers are not allowed}",
+        response(false, Some("privileged containers are not allowed"), None)
+    )]
+    #[case::message_not_checked("allowed: false", response(false, Some("denied"), None))]
+    #[case::not_mutated("{allowed: true, patch: []}", response(true, None, None))]
+    #[case::mutated(
+        "{allowed: true, patch: [{op: add, path: /metadata/labels, value: {}}]}",
+        response(true, None, Some(json!([{"op": "add", "path": "/metadata/labels", "value": {}}])))
+    )]
+    fn passing_checks(#[case] expect: &str, #[case] response: AdmissionResponse) {
+        assert_eq!(check(&expectation(expect), &response), Vec::<String>::new());
+    }
+
+    #[rstest]
+    #[case::accepted(
+        "allowed: false",
+        response(true, None, None),
+        "expected the request to be rejected, it was accepted"
+    )]
+    #[case::rejected(
+        "allowed: true",
+        response(false, Some("denied"), None),
+        "expected the request to be accepted, it was rejected: denied"
+    )]
+    #[case::message(
+        "{allowed: false, message: privileged}",
+        response(false, Some("denied"), None),
+        "unexpected message:\n+denied\n-privileged\n"
+    )]
+    #[case::patch(
+        "{allowed: true, patch: []}",
+        response(true, None, Some(json!([{"op": "remove", "path": "/spec"}]))),
+        "unexpected patch:\n+[\n+  {\n+    \"op\": \"remove\",\n+    \"path\": \"/spec\"\n+  }\n+]\n-[]\n"
+    )]
+    fn failing_checks(
+        #[case] expect: &str,
+        #[case] response: AdmissionResponse,
+        #[case] expected: &str,
+    ) {
+        assert_eq!(check(&expectation(expect), &response), vec![expected]);
+    }
+
+    #[test]
+    fn load_suite() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let path = tempdir.path().join("suite.yml");
+        fs::write(
+            &path,
+            r#"
+policy: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+tests:
+- name: privileged pods are rejected
+  request: privileged-pod.json
+  settings:
+    skip_init_containers: true
+  expect:
+    allowed: false
+"#,
+        )
+        .unwrap();
+
+        let suite = load(&path).unwrap();
+        assert!(suite.settings.is_null());
+        assert_eq!(suite.tests[0].request, PathBuf::from("privileged-pod.json"));
+        assert!(!suite.tests[0].expect.allowed);
+
+        fs::write(&path, "policy: policy.wasm\ntests: []\n").unwrap();
+        assert!(load(&path).is_err());
+
+        fs::write(
+            &path,
+            r#"
+policy: policy.wasm
+tests:
+- name: patch and snapshot
+  request: pod.json
+  expect:
+    allowed: true
+    patch: []
+    patchSnapshot: pod-patch.json
+"#,
+        )
+        .unwrap();
+        assert!(load(&path).is_err());
+    }
+
+    #[rstest]
+    #[case::no_filter(None, true)]
+    #[case::matching_filter(Some("^privileged"), true)]
+    #[case::other_filter(Some("unprivileged"), false)]
+    fn filter_cases(#[case] filter: Option<&str>, #[case] expected: bool) {
+        let suite: TestSuite = serde_yaml::from_str(
+            r#"
+policy: policy.wasm
+tests:
+- name: privileged pods are rejected
+  request: privileged-pod.json
+  expect:
+    allowed: false
+"#,
+        )
+        .unwrap();
+        let options = RunOptions {
+            update_snapshots: false,
+            filter: filter.map(|filter| Regex::new(filter).unwrap()),
+            fail_fast: false,
+            jobs: 1,
+        };
+        assert_eq!(options.selects_any(&suite), expected);
+    }
+
+    #[test]
+    fn patch_snapshots() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let snapshot = tempdir.path().join("snapshots").join("pod.json");
+        let patch = json!([{"op": "add", "path": "/metadata/labels", "value": {}}]);
+        let mutated = response(true, None, Some(patch.clone()));
+
+        assert!(check_snapshot(&snapshot, &mutated)
+            .unwrap()
+            .unwrap()
+            .contains("record it with --update-snapshots"));
+
+        assert!(update_snapshot(&snapshot, &mutated).unwrap());
+        assert!(!update_snapshot(&snapshot, &mutated).unwrap());
+        assert_eq!(read_snapshot(&snapshot).unwrap(), patch);
+        assert_eq!(check_snapshot(&snapshot, &mutated).unwrap(), None);
+
+        let not_mutated = response(true, None, None);
+        assert!(check_snapshot(&snapshot, &not_mutated)
+            .unwrap()
+            .unwrap()
+            .starts_with("unexpected patch:"));
+        assert!(update_snapshot(&snapshot, &not_mutated).unwrap());
+        assert_eq!(read_snapshot(&snapshot).unwrap(), json!([]));
+    }
+
+    fn outcome() -> SuiteOutcome {
+        SuiteOutcome {
+            path: PathBuf::from("suite.yml"),
+            policy: "file:///policy.wasm".to_string(),
+            cases: vec![
+                CaseOutcome {
+                    name: "accepted".to_string(),
+                    failures: vec![],
+                    updated_snapshot: None,
+                    error: false,
+                    duration: Duration::ZERO,
+                },
+                CaseOutcome {
+                    name: "mutated".to_string(),
+                    failures: vec![],
+                    updated_snapshot: Some(PathBuf::from("mutated.json")),
+                    error: false,
+                    duration: Duration::ZERO,
+                },
+                CaseOutcome {
+                    name: "rejected".to_string(),
+                    failures: vec!["unexpected message:\n-foo\n+bar\n".to_string()],
+                    updated_snapshot: None,
+                    error: false,
+                    duration: Duration::from_millis(250),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn render_outcome() {
+        let outcome = outcome();
+        assert_eq!(outcome.failed(), 1);
+        assert_eq!(
+            outcome.render(),
+            "suite.yml (file:///policy.wasm)\n  ok      accepted\n  ok      mutated (snapshot mutated.json updated)\n  FAILED  rejected\n          unexpected message:\n          -foo\n          +bar\n"
+        );
+
+        let report = outcome.report();
+        assert_eq!(report.name, "suite.yml");
+        assert_eq!(report.cases[1].result, CaseResult::Passed);
+        assert_eq!(
+            report.cases[2].result,
+            CaseResult::Failed("unexpected message:\n-foo\n+bar\n".to_string())
+        );
+    }
+
+    #[test]
+    fn run_metrics() {
+        let test_run = TestRun {
+            suites: vec![outcome()],
+            filtered_out: 2,
+            not_run: 0,
+        };
+
+        let metrics = test_run.metrics(Duration::from_secs(3)).render();
+        for sample in [
+            "kwctl_test_cases_total{result=\"passed\",suite=\"suite.yml\"} 2\n",
+            "kwctl_test_cases_total{result=\"failed\",suite=\"suite.yml\"} 1\n",
+            "kwctl_test_skipped_cases_total{reason=\"filtered_out\"} 2\n",
+            "kwctl_test_suite_duration_seconds{suite=\"suite.yml\"} 0.25\n",
+            "kwctl_test_duration_seconds 3\n",
+        ] {
+            assert!(metrics.contains(sample), "{sample} missing from {metrics}");
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Formatting of the timestamps printed by kwctl.
+//!
+//! Timestamps are always printed as RFC3339, which is both human readable and
+//! stable for machine parsing. They are shown in the local time zone, unless
+//! `--utc` is given.
+
+use std::sync::OnceLock;
+
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+static OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+/// Selects the time zone of the printed timestamps.
+///
+/// The local offset can be soundly read only while the process is single
+/// threaded, hence this must be called before starting the async runtime.
+/// UTC is used when the local offset cannot be determined.
+pub(crate) fn init(utc: bool) {
+    let offset = if utc {
+        UtcOffset::UTC
+    } else {
+        UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC)
+    };
+    let _ = OFFSET.set(offset);
+}
+
+fn offset() -> UtcOffset {
+    OFFSET.get().copied().unwrap_or(UtcOffset::UTC)
+}
+
+pub(crate) fn format(time: OffsetDateTime) -> String {
+    format_with_offset(time, offset())
+}
+
+/// Formats a Unix timestamp, expressed in seconds
+pub(crate) fn format_unix(timestamp: i64) -> Option<String> {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()
+        .map(format)
+}
+
+fn format_with_offset(time: OffsetDateTime, offset: UtcOffset) -> String {
+    let time = time.to_offset(offset);
+    time.format(&Rfc3339)
+        .unwrap_or_else(|_| time.unix_timestamp().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::utc(UtcOffset::UTC, "2024-05-21T09:30:00Z")]
+    #[case::east(UtcOffset::from_hms(2, 0, 0).unwrap(), "2024-05-21T11:30:00+02:00")]
+    #[case::west(UtcOffset::from_hms(-5, -30, 0).unwrap(), "2024-05-21T04:00:00-05:30")]
+    fn rfc3339_formatting(#[case] offset: UtcOffset, #[case] expected: &str) {
+        let time = OffsetDateTime::from_unix_timestamp(1716283800).unwrap();
+        assert_eq!(format_with_offset(time, offset), expected);
+    }
+}
@@ -0,0 +1,730 @@
+//! Management of the Sigstore trust root used to verify keyless signatures.
+//!
+//! The Fulcio certificates and the Rekor keys are obtained from a TUF
+//! repository: the public good instance of Sigstore by default, or a
+//! self-hosted mirror configured via `kwctl trust-root update --tuf-mirror`.
+//! The last trust root obtained is cached inside of the kwctl configuration
+//! directory, it is used by `--offline` and reported by `kwctl trust-root
+//! status`.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::TryStreamExt;
+use policy_evaluator::policy_fetcher::sigstore::{
+    self,
+    trust::{ManualTrustRoot, TrustRoot},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::timestamps;
+
+// Directory, inside of the kwctl configuration directory, caching the Sigstore
+// trust root
+const SIGSTORE_TRUST_ROOT_CACHE_DIR: &str = "sigstore-trust-root";
+// Files, inside of the cache directory, describing the TUF mirror to use
+const TUF_SOURCE_FILE: &str = "tuf-source.json";
+const TUF_ROOT_FILE: &str = "tuf-root.json";
+// Target of the TUF repository holding the trust root
+const TRUSTED_ROOT_TARGET: &str = "trusted_root.json";
+
+/// Directory holding the Fulcio certificates and the Rekor keys obtained the
+/// last time the Sigstore TUF repository has been reached
+pub(crate) fn cache_dir() -> PathBuf {
+    crate::store_profile::config_dir().join(SIGSTORE_TRUST_ROOT_CACHE_DIR)
+}
+
+/// A self-hosted TUF repository serving the Sigstore trust root
+#[derive(Debug, Deserialize, Serialize)]
+struct TufMirror {
+    /// Base URL of the TUF metadata. The targets are expected to be served
+    /// from the `targets` directory
+    url: String,
+}
+
+impl TufMirror {
+    /// The mirror configured by `kwctl trust-root update`, together with its
+    /// trusted `root.json`
+    fn read_with_root(dir: &Path) -> Result<Option<(Self, Vec<u8>)>> {
+        let Some(mirror) = Self::read(dir)? else {
+            return Ok(None);
+        };
+        let root_path = dir.join(TUF_ROOT_FILE);
+        let root = fs::read(&root_path).map_err(|e| {
+            anyhow!(
+                "cannot read the root of the TUF mirror {}: {}",
+                root_path.display(),
+                e
+            )
+        })?;
+        Ok(Some((mirror, root)))
+    }
+
+    fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(TUF_SOURCE_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read(&path)
+            .map_err(|e| anyhow!("cannot read TUF mirror {}: {}", path.display(), e))?;
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    fn metadata_url(&self) -> Result<Url> {
+        let url = if self.url.ends_with('/') {
+            self.url.to_owned()
+        } else {
+            format!("{}/", self.url)
+        };
+        Url::parse(&url).map_err(|e| anyhow!("invalid TUF mirror {}: {}", self.url, e))
+    }
+
+    fn targets_url(&self) -> Result<Url> {
+        Ok(self.metadata_url()?.join("targets/")?)
+    }
+
+    /// Fetches the trust root from the mirror. The TUF metadata is verified
+    /// starting from the given `root.json`
+    async fn fetch(&self, root: &[u8]) -> Result<ManualTrustRoot<'static>> {
+        let repository =
+            tough::RepositoryLoader::new(&root, self.metadata_url()?, self.targets_url()?)
+                .load()
+                .await
+                .map_err(|e| anyhow!("cannot load TUF repository {}: {}", self.url, e))?;
+        let target = tough::TargetName::new(TRUSTED_ROOT_TARGET)?;
+        let stream = repository.read_target(&target).await?.ok_or_else(|| {
+            anyhow!(
+                "TUF repository {} has no {} target",
+                self.url,
+                TRUSTED_ROOT_TARGET
+            )
+        })?;
+        let trusted_root: Vec<u8> = stream
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .map_err(|e| anyhow!("cannot read {}: {}", TRUSTED_ROOT_TARGET, e))?;
+
+        parse_trusted_root(&trusted_root)
+    }
+}
+
+/// The subset of the Sigstore `trusted_root.json` document used by kwctl
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TrustedRoot {
+    #[serde(default)]
+    tlogs: Vec<TransparencyLog>,
+    #[serde(default)]
+    certificate_authorities: Vec<CertificateAuthority>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransparencyLog {
+    public_key: RawBytes,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CertificateAuthority {
+    cert_chain: CertChain,
+}
+
+#[derive(Deserialize)]
+struct CertChain {
+    certificates: Vec<RawBytes>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBytes {
+    raw_bytes: String,
+}
+
+impl RawBytes {
+    fn decode(&self) -> Result<Vec<u8>> {
+        STANDARD
+            .decode(&self.raw_bytes)
+            .map_err(|e| anyhow!("invalid rawBytes inside of {}: {}", TRUSTED_ROOT_TARGET, e))
+    }
+}
+
+fn parse_trusted_root(contents: &[u8]) -> Result<ManualTrustRoot<'static>> {
+    let trusted_root: TrustedRoot = serde_json::from_slice(contents)
+        .map_err(|e| anyhow!("invalid {}: {}", TRUSTED_ROOT_TARGET, e))?;
+
+    let fulcio_certs = trusted_root
+        .certificate_authorities
+        .iter()
+        .flat_map(|authority| &authority.cert_chain.certificates)
+        .map(|cert| cert.decode().map(rustls_pki_types::CertificateDer::from))
+        .collect::<Result<Vec<_>>>()?;
+    let rekor_keys = trusted_root
+        .tlogs
+        .iter()
+        .map(|tlog| tlog.public_key.decode())
+        .collect::<Result<Vec<_>>>()?;
+    if fulcio_certs.is_empty() || rekor_keys.is_empty() {
+        return Err(anyhow!(
+            "{} must provide both Fulcio certificates and Rekor keys",
+            TRUSTED_ROOT_TARGET
+        ));
+    }
+
+    Ok(ManualTrustRoot {
+        fulcio_certs,
+        rekor_keys,
+        ..Default::default()
+    })
+}
+
+/// Fetches the trust root from the public good instance of Sigstore
+async fn fetch_public_good() -> Result<ManualTrustRoot<'static>> {
+    let checkout_path = crate::store_profile::config_dir().join("fulcio_and_rekor_data");
+    if !Path::exists(&checkout_path) {
+        fs::create_dir_all(checkout_path.clone())?
+    }
+
+    let repo =
+        sigstore::trust::sigstore::SigstoreTrustRoot::new(Some(checkout_path.as_path())).await?;
+    let fulcio_certs: Vec<rustls_pki_types::CertificateDer> = repo
+        .fulcio_certs()
+        .map_err(|e| {
+            anyhow!(
+                "no Fulcio certificates found inside of the TUF repository: {}",
+                e
+            )
+        })?
+        .into_iter()
+        .map(|c| c.into_owned())
+        .collect();
+    let rekor_keys = repo
+        .rekor_keys()
+        .map_err(|e| anyhow!("no Rekor keys found inside of the TUF repository: {}", e))?
+        .iter()
+        .map(|k| k.to_vec())
+        .collect();
+    Ok(ManualTrustRoot {
+        fulcio_certs,
+        rekor_keys,
+        ..Default::default()
+    })
+}
+
+async fn fetch_from(dir: &Path) -> Result<ManualTrustRoot<'static>> {
+    fetch_from_source(TufMirror::read_with_root(dir)?.as_ref()).await
+}
+
+/// Fetches the trust root from the given mirror, or from the public good
+/// instance of Sigstore
+async fn fetch_from_source(
+    mirror: Option<&(TufMirror, Vec<u8>)>,
+) -> Result<ManualTrustRoot<'static>> {
+    match mirror {
+        Some((mirror, root)) => {
+            debug!(
+                url = mirror.url,
+                "building Sigstore trust root from TUF mirror"
+            );
+            mirror.fetch(root).await
+        }
+        None => {
+            debug!("building Sigstore trust root from Sigstore's TUF repository");
+            fetch_public_good().await
+        }
+    }
+}
+
+/// Fetches the trust root from the configured TUF repository. A copy is
+/// cached, to be used by `--offline`
+pub(crate) async fn fetch() -> Result<ManualTrustRoot<'static>> {
+    let dir = cache_dir();
+    let trust_root = fetch_from(&dir).await?;
+    if let Err(e) = save_trust_root(&dir, &trust_root) {
+        warn!(error = %e, "cannot cache the Sigstore trust root");
+    }
+    Ok(trust_root)
+}
+
+/// What to do when the TUF repository cannot be reached
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum OnUnreachable {
+    /// Fail the command
+    #[default]
+    Fail,
+    /// Warn and continue with the cached trust root, when available. Without
+    /// it, keyless signatures cannot be verified. Without Fulcio or Rekor,
+    /// the policies are left unsigned.
+    Warn,
+}
+
+impl TryFrom<&str> for OnUnreachable {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value {
+            "fail" => Ok(Self::Fail),
+            "warn" => Ok(Self::Warn),
+            unknown => Err(anyhow!("Invalid unreachable behavior '{}'", unknown)),
+        }
+    }
+}
+
+/// Limits of the interactions with the Sigstore infrastructure: the TUF
+/// repository serving the trust root, and the Fulcio and Rekor instances of
+/// keyless signing
+#[derive(Clone, Debug)]
+pub(crate) struct NetworkOptions {
+    /// Time granted to each attempt. The TUF clients do not expose distinct
+    /// connect and read timeouts, the timeout covers the whole attempt.
+    pub(crate) timeout: Duration,
+    /// Attempts made after the first failed one
+    pub(crate) retries: u32,
+    pub(crate) on_unreachable: OnUnreachable,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            retries: 2,
+            on_unreachable: OnUnreachable::Fail,
+        }
+    }
+}
+
+/// Time waited before the given retry, doubled at each retry
+pub(crate) fn backoff(retry: u32) -> Duration {
+    Duration::from_secs(2u64.saturating_pow(retry.min(6)))
+}
+
+/// Like [`fetch`], bounding each attempt with a timeout and retrying the
+/// failed ones. When the TUF repository stays unreachable, either an error
+/// explaining how to proceed is returned, or the cached trust root is used.
+/// `None` is returned when warning without a cached trust root.
+pub(crate) async fn fetch_with_options(
+    options: &NetworkOptions,
+) -> Result<Option<ManualTrustRoot<'static>>> {
+    let mut attempt = 0;
+    let error = loop {
+        let error = match tokio::time::timeout(options.timeout, fetch()).await {
+            Ok(Ok(trust_root)) => return Ok(Some(trust_root)),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {} seconds", options.timeout.as_secs()),
+        };
+        if attempt >= options.retries {
+            break error;
+        }
+        attempt += 1;
+        warn!(attempt, error = %error, "cannot fetch the Sigstore trust root, retrying");
+        tokio::time::sleep(backoff(attempt)).await;
+    };
+
+    match options.on_unreachable {
+        OnUnreachable::Fail => Err(anyhow!(
+            "cannot fetch the Sigstore trust root (Fulcio certificates and Rekor keys) after {} attempts: {}. Use --offline to rely on the trust root cached by a previous run, raise --sigstore-timeout or --sigstore-retries on slow networks, or use '--sigstore-unreachable warn' to continue without it",
+            attempt + 1,
+            error
+        )),
+        OnUnreachable::Warn => match load_cached() {
+            Ok(trust_root) => {
+                warn!(
+                    error = %error,
+                    "cannot fetch the Sigstore trust root, using the cached one"
+                );
+                Ok(Some(trust_root))
+            }
+            Err(_) => {
+                warn!(
+                    error = %error,
+                    "cannot fetch the Sigstore trust root and none is cached, keyless signatures cannot be verified"
+                );
+                Ok(None)
+            }
+        },
+    }
+}
+
+/// The trust root obtained the last time the TUF repository has been reached
+pub(crate) fn load_cached() -> Result<ManualTrustRoot<'static>> {
+    let dir = cache_dir();
+    debug!(path = ?dir, "building Sigstore trust root from the local cache");
+    load_trust_root(&dir)
+}
+
+/// Where the trust root is obtained from
+pub(crate) enum TufSource {
+    /// The public good instance of Sigstore
+    PublicGood,
+    /// A self-hosted TUF repository, with the path of its trusted `root.json`
+    Mirror { url: String, root: PathBuf },
+}
+
+/// Changes the TUF repository the trust root is obtained from, when a source
+/// is given, then refreshes the cached trust root
+pub(crate) async fn update(source: Option<TufSource>) -> Result<Status> {
+    update_in(&cache_dir(), source).await
+}
+
+async fn update_in(dir: &Path, source: Option<TufSource>) -> Result<Status> {
+    let mirror = match &source {
+        Some(TufSource::Mirror { url, root }) => {
+            let mirror = TufMirror { url: url.clone() };
+            // fail early on malformed URLs
+            mirror.targets_url()?;
+            let root = fs::read(root)
+                .map_err(|e| anyhow!("cannot read TUF root {}: {}", root.display(), e))?;
+            Some((mirror, root))
+        }
+        Some(TufSource::PublicGood) => None,
+        None => TufMirror::read_with_root(dir)?,
+    };
+
+    // the source is changed only once it served a valid trust root, a failed
+    // update keeps the previous one
+    let trust_root = fetch_from_source(mirror.as_ref()).await?;
+    fs::create_dir_all(dir)?;
+    if source.is_some() {
+        match &mirror {
+            Some((mirror, root)) => {
+                fs::write(dir.join(TUF_ROOT_FILE), root)?;
+                fs::write(dir.join(TUF_SOURCE_FILE), serde_json::to_vec(mirror)?)?;
+            }
+            None => {
+                for file in [TUF_SOURCE_FILE, TUF_ROOT_FILE] {
+                    let path = dir.join(file);
+                    if path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+        }
+    }
+    save_trust_root(dir, &trust_root)?;
+    status_of(dir)
+}
+
+/// Summary of the cached trust root
+pub(crate) struct Status {
+    source: String,
+    cache_dir: PathBuf,
+    last_update: Option<String>,
+    fulcio_certs: Vec<String>,
+    rekor_keys: Vec<String>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Source: {}", self.source)?;
+        writeln!(f, "Cache: {}", self.cache_dir.display())?;
+        writeln!(
+            f,
+            "Last update: {}",
+            self.last_update.as_deref().unwrap_or("never")
+        )?;
+        writeln!(f, "Fulcio certificates: {}", self.fulcio_certs.len())?;
+        for fingerprint in &self.fulcio_certs {
+            writeln!(f, "  sha256:{fingerprint}")?;
+        }
+        writeln!(f, "Rekor keys: {}", self.rekor_keys.len())?;
+        for fingerprint in &self.rekor_keys {
+            writeln!(f, "  sha256:{fingerprint}")?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn status() -> Result<Status> {
+    status_of(&cache_dir())
+}
+
+fn status_of(dir: &Path) -> Result<Status> {
+    let source = match TufMirror::read(dir)? {
+        Some(mirror) => format!("TUF mirror {}", mirror.url),
+        None => "Sigstore public good instance".to_string(),
+    };
+    let trust_root = load_trust_root(dir).ok();
+    let last_update = trust_root
+        .as_ref()
+        .and_then(|_| last_update(dir))
+        .map(timestamps::format);
+    let fingerprint = |data: &[u8]| format!("{:x}", Sha256::digest(data));
+
+    Ok(Status {
+        source,
+        cache_dir: dir.to_path_buf(),
+        last_update,
+        fulcio_certs: trust_root
+            .iter()
+            .flat_map(|trust_root| &trust_root.fulcio_certs)
+            .map(|cert| fingerprint(cert.as_ref()))
+            .collect(),
+        rekor_keys: trust_root
+            .iter()
+            .flat_map(|trust_root| &trust_root.rekor_keys)
+            .map(|key| fingerprint(key.as_slice()))
+            .collect(),
+    })
+}
+
+/// Modification time of the oldest cached file, which is when the cache has
+/// been written the last time
+fn last_update(dir: &Path) -> Option<OffsetDateTime> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_cached_fulcio_cert(path) || is_cached_rekor_key(path))
+        .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .min()
+        .map(OffsetDateTime::from)
+}
+
+fn save_trust_root(dir: &Path, trust_root: &ManualTrustRoot) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_cached_fulcio_cert(&path) || is_cached_rekor_key(&path) {
+            fs::remove_file(path)?;
+        }
+    }
+
+    for (index, cert) in trust_root.fulcio_certs.iter().enumerate() {
+        fs::write(
+            dir.join(format!("fulcio-{index}.pem")),
+            pem::encode(&pem::Pem::new("CERTIFICATE", cert.as_ref().to_vec())),
+        )?;
+    }
+    for (index, key) in trust_root.rekor_keys.iter().enumerate() {
+        fs::write(
+            dir.join(format!("rekor-{index}.pub")),
+            pem::encode(&pem::Pem::new("PUBLIC KEY", key.to_owned())),
+        )?;
+    }
+    Ok(())
+}
+
+fn load_trust_root(dir: &Path) -> Result<ManualTrustRoot<'static>> {
+    let missing = || {
+        anyhow!(
+            "no Sigstore trust root cached inside of {}. Run `kwctl trust-root update` with network access, or provide both --fulcio-cert-path and --rekor-public-key-path",
+            dir.display()
+        )
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|_| missing())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    paths.sort();
+
+    let mut fulcio_certs = Vec::new();
+    let mut rekor_keys = Vec::new();
+    for path in paths {
+        if is_cached_fulcio_cert(&path) {
+            let cert = pem::parse(fs::read(&path)?)?;
+            fulcio_certs.push(rustls_pki_types::CertificateDer::from(
+                cert.contents().to_vec(),
+            ));
+        } else if is_cached_rekor_key(&path) {
+            rekor_keys.push(pem::parse(fs::read(&path)?)?.contents().to_vec());
+        }
+    }
+    if fulcio_certs.is_empty() || rekor_keys.is_empty() {
+        return Err(missing());
+    }
+
+    Ok(ManualTrustRoot {
+        fulcio_certs,
+        rekor_keys,
+        ..Default::default()
+    })
+}
+
+fn cached_file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+fn is_cached_fulcio_cert(path: &Path) -> bool {
+    let name = cached_file_name(path);
+    name.starts_with("fulcio-") && name.ends_with(".pem")
+}
+
+fn is_cached_rekor_key(path: &Path) -> bool {
+    let name = cached_file_name(path);
+    name.starts_with("rekor-") && name.ends_with(".pub")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn cached_trust_root_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let trust_root = ManualTrustRoot {
+            fulcio_certs: vec![rustls_pki_types::CertificateDer::from(vec![48, 3, 2, 1, 0])],
+            rekor_keys: vec![vec![1, 2, 3], vec![4, 5, 6]],
+            ..Default::default()
+        };
+
+        save_trust_root(dir.path(), &trust_root).unwrap();
+        let loaded = load_trust_root(dir.path()).unwrap();
+
+        assert_eq!(loaded.fulcio_certs, trust_root.fulcio_certs);
+        assert_eq!(loaded.rekor_keys, trust_root.rekor_keys);
+    }
+
+    #[rstest]
+    #[case::fail("fail", OnUnreachable::Fail)]
+    #[case::warn("warn", OnUnreachable::Warn)]
+    fn on_unreachable(#[case] value: &str, #[case] expected: OnUnreachable) {
+        assert_eq!(OnUnreachable::try_from(value).unwrap(), expected);
+    }
+
+    #[test]
+    fn retries_back_off() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(2), Duration::from_secs(4));
+        assert_eq!(backoff(100), Duration::from_secs(64));
+    }
+
+    #[test]
+    fn missing_cached_trust_root() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load_trust_root(&dir.path().join("missing")).is_err());
+        assert!(load_trust_root(dir.path()).is_err());
+    }
+
+    #[rstest]
+    #[case::no_trailing_slash("https://tuf.example.com/sigstore")]
+    #[case::trailing_slash("https://tuf.example.com/sigstore/")]
+    fn tuf_mirror_urls(#[case] url: &str) {
+        let mirror = TufMirror {
+            url: url.to_string(),
+        };
+        assert_eq!(
+            mirror.metadata_url().unwrap().as_str(),
+            "https://tuf.example.com/sigstore/"
+        );
+        assert_eq!(
+            mirror.targets_url().unwrap().as_str(),
+            "https://tuf.example.com/sigstore/targets/"
+        );
+    }
+
+    #[test]
+    fn trusted_root_parsing() {
+        let trusted_root = format!(
+            r#"{{
+  "mediaType": "application/vnd.dev.sigstore.trustedroot+json;version=0.1",
+  "tlogs": [
+    {{
+      "baseUrl": "https://rekor.example.com",
+      "hashAlgorithm": "SHA2_256",
+      "publicKey": {{ "rawBytes": "{key}", "keyDetails": "PKIX_ECDSA_P256_SHA_256" }}
+    }}
+  ],
+  "certificateAuthorities": [
+    {{
+      "uri": "https://fulcio.example.com",
+      "certChain": {{ "certificates": [{{ "rawBytes": "{leaf}" }}, {{ "rawBytes": "{root}" }}] }}
+    }}
+  ],
+  "ctlogs": []
+}}"#,
+            key = STANDARD.encode([1, 2, 3]),
+            leaf = STANDARD.encode([4, 5]),
+            root = STANDARD.encode([6, 7]),
+        );
+
+        let trust_root = parse_trusted_root(trusted_root.as_bytes()).unwrap();
+
+        assert_eq!(trust_root.rekor_keys, vec![vec![1, 2, 3]]);
+        assert_eq!(
+            trust_root.fulcio_certs,
+            vec![
+                rustls_pki_types::CertificateDer::from(vec![4, 5]),
+                rustls_pki_types::CertificateDer::from(vec![6, 7]),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case::no_rekor_keys(
+        r#"{"certificateAuthorities": [{"certChain": {"certificates": [{"rawBytes": "AQI="}]}}]}"#
+    )]
+    #[case::invalid_base64(r#"{"tlogs": [{"publicKey": {"rawBytes": "not base64!"}}]}"#)]
+    #[case::not_json("<html></html>")]
+    fn invalid_trusted_root(#[case] trusted_root: &str) {
+        assert!(parse_trusted_root(trusted_root.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn status_of_mirror() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join(TUF_SOURCE_FILE),
+            r#"{"url": "https://tuf.example.com"}"#,
+        )
+        .unwrap();
+
+        let status = status_of(dir.path()).unwrap();
+        assert_eq!(status.source, "TUF mirror https://tuf.example.com");
+        assert!(status.last_update.is_none());
+
+        save_trust_root(
+            dir.path(),
+            &ManualTrustRoot {
+                fulcio_certs: vec![rustls_pki_types::CertificateDer::from(vec![48, 0])],
+                rekor_keys: vec![vec![1, 2, 3]],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let status = status_of(dir.path()).unwrap();
+        assert!(status.last_update.is_some());
+        assert_eq!(
+            status.rekor_keys,
+            vec![format!("{:x}", Sha256::digest([1, 2, 3]))]
+        );
+        assert_eq!(status.fulcio_certs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_update_keeps_the_previous_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let previous = r#"{"url":"https://tuf.example.com"}"#;
+        fs::write(dir.path().join(TUF_SOURCE_FILE), previous).unwrap();
+        fs::write(dir.path().join(TUF_ROOT_FILE), "previous root").unwrap();
+        let root = dir.path().join("new-root.json");
+        fs::write(&root, "not a TUF root").unwrap();
+
+        let source = TufSource::Mirror {
+            url: "http://127.0.0.1:1/".to_string(),
+            root,
+        };
+        assert!(update_in(dir.path(), Some(source)).await.is_err());
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join(TUF_SOURCE_FILE)).unwrap(),
+            previous
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join(TUF_ROOT_FILE)).unwrap(),
+            "previous root"
+        );
+        assert!(load_trust_root(dir.path()).is_err());
+    }
+}
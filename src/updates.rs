@@ -0,0 +1,199 @@
+//! Newer releases of the policies of the store.
+//!
+//! Only the policies pulled from a registry via a tag that is a semantic
+//! version, with or without the `v` prefix, can be updated. The tags of their
+//! repository are compared as semantic versions, pre-releases are taken into
+//! account only for the policies pulled via a pre-release tag.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use policy_evaluator::policy_fetcher::{
+    oci_client::{Client, Reference},
+    sigstore::registry::ClientConfig,
+    sources::Sources,
+};
+use prettytable::{format, row, Table};
+use regex::Regex;
+use semver::{Version, VersionReq};
+
+use crate::config::registry_auth::registry_auth;
+
+/// The reference and the version of a policy pulled from a registry via a
+/// semver tag, `None` for all the other policies
+pub(crate) fn current_version(uri: &str) -> Option<(Reference, Version)> {
+    let reference = Reference::from_str(uri.strip_prefix("registry://")?).ok()?;
+    if reference.digest().is_some() {
+        return None;
+    }
+    let version = parse_version(reference.tag()?)?;
+    Some((reference, version))
+}
+
+/// The tags of the repository of the policy
+pub(crate) async fn list_tags(
+    reference: &Reference,
+    sources: Option<&Sources>,
+) -> Result<Vec<String>> {
+    let auth = registry_auth(&reference.whole())?;
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let client = Client::new(client_config.into());
+    Ok(client.list_tags(reference, &auth, None, None).await?.tags)
+}
+
+/// Parses a tag as a semantic version, the `v` prefix is optional
+pub(crate) fn parse_version(tag: &str) -> Option<Version> {
+    Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// The tag of the newest release published after `current`, satisfying
+/// `constraint` when given
+pub(crate) fn newest_release<'a>(
+    current: &Version,
+    tags: &'a [String],
+    constraint: Option<&VersionReq>,
+) -> Option<&'a str> {
+    tags.iter()
+        .filter_map(|tag| parse_version(tag).map(|version| (version, tag)))
+        .filter(|(version, _)| {
+            version > current
+                && (version.pre.is_empty() || !current.pre.is_empty())
+                && constraint.is_none_or(|constraint| constraint.matches(version))
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag.as_str())
+}
+
+/// URI of the release of the policy tagged with `tag`
+pub(crate) fn release_uri(uri: &str, tag: &str) -> String {
+    let repository = uri
+        .rsplit_once(':')
+        .map_or(uri, |(repository, _)| repository);
+    format!("{repository}:{tag}")
+}
+
+/// Whether the URI of the policy matches the pattern given to `kwctl update`,
+/// `*` matches any sequence of characters
+pub(crate) fn matches_pattern(uri: &str, pattern: &str) -> bool {
+    let pattern = pattern.split('*').map(regex::escape).join(".*");
+    Regex::new(&pattern).is_ok_and(|pattern| pattern.is_match(uri))
+}
+
+/// Outcome of the update of a policy of the store
+pub(crate) enum Outcome {
+    UpToDate,
+    /// Holds the URI of the newest release, not pulled because of
+    /// `--dry-run`
+    Available(String),
+    /// Holds the URI of the newest release, pulled into the store
+    Updated(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::UpToDate => write!(f, "up to date"),
+            Outcome::Available(uri) => write!(f, "would update to {uri}"),
+            Outcome::Updated(uri) => write!(f, "updated to {uri}"),
+            Outcome::Failed(e) => write!(f, "failed: {e}"),
+        }
+    }
+}
+
+/// Prints the outcome of `kwctl update` as a table, and fails when any
+/// policy cannot be updated
+pub(crate) fn report(outcomes: &[(String, Outcome)]) -> Result<()> {
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "Update"]);
+    for (uri, outcome) in outcomes {
+        table.add_row(row![uri, outcome]);
+    }
+    table.printstd();
+
+    let failures = outcomes
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} out of {} policies cannot be updated",
+            failures,
+            outcomes.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::newer_patch("v0.2.5", &["v0.2.4", "v0.2.5", "v0.2.6"], None, Some("v0.2.6"))]
+    #[case::semver_ordering("v0.2.5", &["v0.10.0", "v0.9.1", "latest"], None, Some("v0.10.0"))]
+    #[case::without_prefix("1.0.0", &["1.0.0", "1.1.0"], None, Some("1.1.0"))]
+    #[case::up_to_date("v0.2.5", &["v0.2.4", "v0.2.5", "latest"], None, None)]
+    #[case::pre_releases_skipped("v0.2.5", &["v0.2.5", "v0.3.0-rc1"], None, None)]
+    #[case::from_pre_release("v0.3.0-rc1", &["v0.3.0-rc1", "v0.3.0-rc2"], None, Some("v0.3.0-rc2"))]
+    #[case::constraint("v0.2.5", &["v0.2.6", "v0.3.0", "v1.0.0"], Some("~0.2"), Some("v0.2.6"))]
+    #[case::constraint_not_met("v0.2.5", &["v1.0.0"], Some("<1.0.0"), None)]
+    fn newest_release_lookup(
+        #[case] current: &str,
+        #[case] tags: &[&str],
+        #[case] constraint: Option<&str>,
+        #[case] expected: Option<&str>,
+    ) {
+        let current = parse_version(current).unwrap();
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        let constraint = constraint.map(|constraint| VersionReq::parse(constraint).unwrap());
+        assert_eq!(
+            newest_release(&current, &tags, constraint.as_ref()),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::tagged("registry://ghcr.io/kubewarden/policies/psp:v0.1.0", true)]
+    #[case::not_semver("registry://ghcr.io/kubewarden/policies/psp:latest", false)]
+    #[case::digest(
+        "registry://ghcr.io/kubewarden/policies/psp@sha256:0000000000000000000000000000000000000000000000000000000000000000",
+        false
+    )]
+    #[case::not_registry("https://example.com/psp.wasm", false)]
+    fn versioned_policies(#[case] uri: &str, #[case] expected: bool) {
+        assert_eq!(current_version(uri).is_some(), expected);
+    }
+
+    #[rstest]
+    #[case::substring("registry://ghcr.io/kubewarden/policies/psp:v0.1.0", "psp", true)]
+    #[case::wildcard(
+        "registry://ghcr.io/kubewarden/policies/psp:v0.1.0",
+        "ghcr.io/*/psp",
+        true
+    )]
+    #[case::no_match(
+        "registry://ghcr.io/kubewarden/policies/psp:v0.1.0",
+        "safe-labels",
+        false
+    )]
+    #[case::regex_chars_escaped(
+        "registry://ghcr.io/kubewarden/policies/psp:v0.1.0",
+        "ghcr?io",
+        false
+    )]
+    fn pattern_matching(#[case] uri: &str, #[case] pattern: &str, #[case] expected: bool) {
+        assert_eq!(matches_pattern(uri, pattern), expected);
+    }
+
+    #[test]
+    fn release_uri_replaces_tag() {
+        assert_eq!(
+            release_uri("registry://localhost:5000/policies/psp:v0.1.0", "v0.2.0"),
+            "registry://localhost:5000/policies/psp:v0.2.0"
+        );
+    }
+}
@@ -1,9 +1,14 @@
 use anyhow::{anyhow, Result};
 use policy_evaluator::policy_evaluator::PolicyExecutionMode;
-use policy_evaluator::policy_fetcher::oci_client::Reference;
-use policy_evaluator::policy_fetcher::store::{errors::StoreError, Store};
+use policy_evaluator::policy_fetcher::oci_client::{
+    manifest::{OciImageManifest, WASM_LAYER_MEDIA_TYPE},
+    Reference,
+};
+use policy_evaluator::policy_fetcher::store::errors::StoreError;
 use regex::Regex;
 use serde_json::json;
+use std::collections::HashMap;
+use std::ffi::OsStr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use url::Url;
@@ -36,8 +41,7 @@ pub(crate) fn map_path_to_uri(uri_or_sha_prefix: &str) -> std::result::Result<St
 
         Ok(Url::from_file_path(path).unwrap().to_string())
     } else {
-        let store = Store::default();
-        if let Some(policy) = store.get_policy_by_sha_prefix(uri_or_sha_prefix)? {
+        if let Some(policy) = crate::store_mode::find_policy_by_sha_prefix(uri_or_sha_prefix)? {
             Ok(policy.uri.clone())
         } else {
             Err(LookupError::PolicyMissing(uri_or_sha_prefix.to_string()))
@@ -65,8 +69,7 @@ pub(crate) fn wasm_path(uri: &str) -> std::result::Result<PathBuf, LookupError>
             .to_file_path()
             .map_err(|_| LookupError::UrlToStringConversionError()),
         "http" | "https" | "registry" => {
-            let store = Store::default();
-            let policy = store.get_policy_by_uri(uri)?;
+            let policy = crate::store_mode::find_policy(uri)?;
 
             if let Some(policy) = policy {
                 Ok(policy.local_path)
@@ -99,11 +102,48 @@ pub(crate) fn find_file_matching_file(possible_names: &[&str]) -> Option<PathBuf
         .find(|path| path.exists())
 }
 
+/// Looks for an executable named `binary` inside of the directories listed by `$PATH`
+pub(crate) fn find_in_path(binary: &str) -> Option<PathBuf> {
+    find_in_paths(binary, &std::env::var_os("PATH")?)
+}
+
+/// Looks for an executable named `binary` inside of the directories of
+/// `paths`, formatted like the `PATH` environment variable
+pub(crate) fn find_in_paths(binary: &str, paths: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(paths)
+        .map(|dir| dir.join(binary))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Returns the digest of the layer holding the Wasm module of the policy.
+///
+/// The digest is the SHA-256 of the policy, prefixed by `sha256:`.
+pub(crate) fn wasm_layer_digest(manifest: &OciImageManifest) -> Option<&str> {
+    manifest
+        .layers
+        .iter()
+        .find(|layer| layer.media_type == WASM_LAYER_MEDIA_TYPE)
+        .map(|layer| layer.digest.as_str())
+}
+
+/// Parses annotations provided on the command line in `KEY=VALUE` format
+pub(crate) fn parse_annotations<'a>(
+    items: impl Iterator<Item = &'a String>,
+) -> Result<HashMap<String, String>> {
+    items
+        .map(|item| {
+            item.split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .ok_or_else(|| anyhow!("invalid annotation '{}', expected KEY=VALUE", item))
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
-
     use super::*;
+    use rstest::rstest;
 
     #[test]
     fn test_map_path_to_uri_remote_scheme() -> Result<()> {
@@ -157,4 +197,46 @@ mod tests {
         let actual = new_policy_execution_mode_from_str("test");
         assert!(actual.is_err(),);
     }
+
+    #[rstest]
+    #[case::valid(
+        &["env=prod", "url=https://example.com?a=b"],
+        Some(HashMap::from([
+            ("env".to_string(), "prod".to_string()),
+            ("url".to_string(), "https://example.com?a=b".to_string()),
+        ]))
+    )]
+    #[case::empty_value(&["env="], Some(HashMap::from([("env".to_string(), String::new())])))]
+    #[case::missing_separator(&["env"], None)]
+    #[case::missing_key(&["=prod"], None)]
+    fn test_parse_annotations(
+        #[case] items: &[&str],
+        #[case] expected: Option<HashMap<String, String>>,
+    ) {
+        let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+        assert_eq!(parse_annotations(items.iter()).ok(), expected);
+    }
+
+    #[test]
+    fn test_find_in_paths() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        // directories named like the binary are skipped
+        std::fs::create_dir(first.path().join("kwctl-plugin")).unwrap();
+        std::fs::write(second.path().join("kwctl-plugin"), "").unwrap();
+        std::fs::write(first.path().join("other"), "").unwrap();
+        std::fs::write(second.path().join("other"), "").unwrap();
+
+        let paths = std::env::join_paths([first.path(), second.path()]).unwrap();
+        assert_eq!(
+            find_in_paths("kwctl-plugin", &paths),
+            Some(second.path().join("kwctl-plugin"))
+        );
+        // the first directory holding the binary wins
+        assert_eq!(
+            find_in_paths("other", &paths),
+            Some(first.path().join("other"))
+        );
+        assert_eq!(find_in_paths("missing", &paths), None);
+    }
 }
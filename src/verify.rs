@@ -1,35 +1,512 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use policy_evaluator::policy_fetcher::{
     policy::Policy,
-    sigstore::trust::ManualTrustRoot,
+    sigstore::{
+        cosign::{
+            signature_layers::{CertificateSubject, SignatureLayer},
+            verification_constraint::{
+                AnnotationVerifier, CertificateVerifier, PublicKeyVerifier, VerificationConstraint,
+            },
+            ClientBuilder, CosignCapabilities,
+        },
+        crypto::SigningScheme,
+        registry::{oci_reference::OciReference, ClientConfig},
+        trust::ManualTrustRoot,
+    },
     sources::Sources,
-    verify::{config::LatestVerificationConfig, Verifier},
+    store::Store,
+    verify::{
+        config::{Signature, Subject},
+        Verifier,
+    },
 };
+use prettytable::{format, row, Table};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
+use std::str::FromStr;
 use std::sync::Arc;
-use tracing::{debug, info};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{
+        certificate_identity::{CertificateIdentity, Matcher},
+        registry_auth::sigstore_auth,
+        sources::RegistryMirrors,
+        verification::VerificationOptions,
+    },
+    mirror_health, timestamps,
+};
+
+pub(crate) mod detached;
 
 pub(crate) type VerificationAnnotations = BTreeMap<String, String>;
 
 pub(crate) async fn verify(
     url: &str,
     sources: Option<&Sources>,
-    verification_config: &LatestVerificationConfig,
+    mirrors: &RegistryMirrors,
+    verification_options: &VerificationOptions,
     sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
 ) -> Result<String> {
     debug!(
         policy = url,
         ?sources,
-        ?verification_config,
+        ?verification_options,
         "Verifying policy"
     );
-    let mut verifier = Verifier::new(sources.cloned(), sigstore_trust_root).await?;
-    let verified_manifest_digest = verifier.verify(url, verification_config).await?;
+    let verifier = Verifier::new(sources.cloned(), sigstore_trust_root.clone()).await?;
 
+    // The signatures are looked up on the mirrors first, falling back to the
+    // upstream registry
+    // the candidates are verified one after the other, the lock is never
+    // contended
+    let verifier = &Mutex::new(verifier);
+    let verified_manifest_digest =
+        mirror_health::first_successful(&mirrors.candidates(url), "verify policy", |candidate| {
+            let sigstore_trust_root = sigstore_trust_root.clone();
+            async move {
+                verify_candidate(
+                    &mut *verifier.lock().await,
+                    &candidate,
+                    sources,
+                    verification_options,
+                    sigstore_trust_root,
+                )
+                .await
+            }
+        })
+        .await?;
     info!("Policy successfully verified");
     Ok(verified_manifest_digest)
 }
 
+/// Verifies the policy against the signatures handled by policy-fetcher, and
+/// against the certificate identities handled by kwctl. Returns the verified
+/// manifest digest.
+async fn verify_candidate(
+    verifier: &mut Verifier,
+    url: &str,
+    sources: Option<&Sources>,
+    verification_options: &VerificationOptions,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+) -> Result<String> {
+    let mut verified_manifest_digest = None;
+    if let Some(verification_config) = &verification_options.config {
+        verified_manifest_digest = Some(verifier.verify(url, verification_config).await?);
+    }
+
+    if !verification_options.certificate_identities.is_empty() {
+        let digest = verify_certificate_identities(
+            url,
+            sources,
+            &verification_options.certificate_identities,
+            sigstore_trust_root,
+        )
+        .await?;
+        if verified_manifest_digest
+            .as_ref()
+            .is_some_and(|verified| *verified != digest)
+        {
+            return Err(anyhow!(
+                "the manifest digest of {} changed while it was being verified",
+                url
+            ));
+        }
+        verified_manifest_digest = Some(digest);
+    }
+
+    verified_manifest_digest.ok_or_else(|| anyhow!("no signatures to verify were provided"))
+}
+
+/// Checks that every certificate identity is satisfied by at least one of the
+/// trusted signatures of the policy. Returns the manifest digest of the
+/// policy.
+async fn verify_certificate_identities(
+    url: &str,
+    sources: Option<&Sources>,
+    certificate_identities: &[CertificateIdentity],
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+) -> Result<String> {
+    let (trusted_layers, source_image_digest) =
+        trusted_signature_layers(url, sources, sigstore_trust_root).await?;
+
+    for certificate_identity in certificate_identities {
+        if !trusted_layers
+            .iter()
+            .any(|layer| certificate_identity.is_satisfied_by(layer))
+        {
+            return Err(anyhow!(
+                "no trusted signature matches the certificate {}",
+                certificate_identity
+            ));
+        }
+        debug!(%certificate_identity, "certificate identity verified");
+    }
+
+    Ok(source_image_digest)
+}
+
+/// Fetches the signatures of the policy whose certificates and Rekor bundles
+/// can be trusted, together with the manifest digest of the policy
+async fn trusted_signature_layers(
+    url: &str,
+    sources: Option<&Sources>,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+) -> Result<(Vec<SignatureLayer>, String)> {
+    let image_name = url.strip_prefix("registry://").ok_or_else(|| {
+        anyhow!(
+            "{} is not hosted on a registry, its signatures cannot be verified",
+            url
+        )
+    })?;
+    let client_config: ClientConfig = sources.cloned().unwrap_or_default().into();
+    let mut client_builder = ClientBuilder::default().with_oci_client_config(client_config);
+    if let Some(trust_root) = sigstore_trust_root {
+        client_builder = client_builder.with_trust_repository(trust_root.as_ref())?;
+    }
+    let mut client = client_builder.build()?;
+    let image_ref = OciReference::from_str(image_name)?;
+    let auth = sigstore_auth(image_name)?;
+
+    let (cosign_signature_image, source_image_digest) =
+        client.triangulate(&image_ref, &auth).await?;
+    let trusted_layers = client
+        .trusted_signature_layers(&auth, &source_image_digest, &cosign_signature_image)
+        .await?;
+
+    Ok((trusted_layers, source_image_digest))
+}
+
+/// Evidence of a successful verification
+#[derive(Debug, Serialize)]
+pub(crate) struct VerificationReport {
+    policy: String,
+    /// Where the signatures have been found: the policy, or one of its mirrors
+    source: String,
+    manifest_digest: String,
+    signatures: Vec<SignatureReport>,
+}
+
+/// A signature satisfying at least one of the verification requirements
+#[derive(Debug, Serialize)]
+struct SignatureReport {
+    /// Digest of the signature layer
+    digest: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    certificate: Option<CertificateReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rekor_log_index: Option<i64>,
+    /// When the signature has been added to the Rekor transparency log
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rekor_integrated_time: Option<String>,
+    annotations: BTreeMap<String, serde_json::Value>,
+    /// The keys and the certificate identities the signature matched
+    matched: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CertificateReport {
+    subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    issuer: Option<String>,
+}
+
+impl SignatureReport {
+    fn new(layer: &SignatureLayer, matched: Vec<String>) -> Self {
+        Self {
+            digest: layer.oci_digest.to_owned(),
+            certificate: layer.certificate_signature.as_ref().map(|certificate| {
+                CertificateReport {
+                    subject: match &certificate.subject {
+                        CertificateSubject::Email(email) => email.to_owned(),
+                        CertificateSubject::Uri(uri) => uri.to_owned(),
+                    },
+                    issuer: certificate.issuer.to_owned(),
+                }
+            }),
+            rekor_log_index: layer.bundle.as_ref().map(|bundle| bundle.payload.log_index),
+            rekor_integrated_time: layer
+                .bundle
+                .as_ref()
+                .and_then(|bundle| timestamps::format_unix(bundle.payload.integrated_time)),
+            annotations: layer
+                .simple_signing
+                .optional
+                .as_ref()
+                .map(|optional| optional.extra.clone().into_iter().collect())
+                .unwrap_or_default(),
+            matched,
+        }
+    }
+}
+
+/// A verification requirement, described by a label, and the check telling
+/// whether a signature satisfies it
+type Requirement<'a> = (String, Box<dyn Fn(&SignatureLayer) -> bool + 'a>);
+
+fn has_annotations(layer: &SignatureLayer, annotations: &Option<VerificationAnnotations>) -> bool {
+    annotations.as_ref().is_none_or(|annotations| {
+        AnnotationVerifier {
+            annotations: annotations.to_owned(),
+        }
+        .verify(layer)
+        .unwrap_or(false)
+    })
+}
+
+fn key_fingerprint(key: &str) -> String {
+    let der = pem::parse(key)
+        .map(|key| key.contents().to_vec())
+        .unwrap_or_else(|_| key.as_bytes().to_vec());
+    format!("sha256:{:x}", Sha256::digest(der))
+}
+
+fn requirement<'a>(signature: &Signature) -> Result<Requirement<'a>> {
+    let requirement: Requirement = match signature {
+        Signature::PubKey {
+            owner,
+            key,
+            annotations,
+        } => {
+            let verifier = PublicKeyVerifier::new(key.as_bytes(), &SigningScheme::default())?;
+            let label = match owner {
+                Some(owner) => format!("key {} ({})", key_fingerprint(key), owner),
+                None => format!("key {}", key_fingerprint(key)),
+            };
+            let annotations = annotations.clone();
+            (
+                label,
+                Box::new(move |layer: &SignatureLayer| {
+                    verifier.verify(layer).unwrap_or(false) && has_annotations(layer, &annotations)
+                }),
+            )
+        }
+        Signature::GenericIssuer {
+            issuer,
+            subject,
+            annotations,
+        } => {
+            let certificate_identity = CertificateIdentity {
+                issuer: Matcher::Equal(issuer.to_owned()),
+                subject: match subject {
+                    Subject::Equal(subject) => Matcher::Equal(subject.to_string()),
+                    Subject::UrlPrefix(prefix) => Matcher::UrlPrefix(prefix.to_string()),
+                },
+                annotations: annotations.clone(),
+            };
+            (
+                format!("certificate with {certificate_identity}"),
+                Box::new(move |layer: &SignatureLayer| certificate_identity.is_satisfied_by(layer)),
+            )
+        }
+        Signature::GithubAction {
+            owner,
+            repo,
+            annotations,
+        } => {
+            let label = match repo {
+                Some(repo) => format!("GitHub Actions of {owner}/{repo}"),
+                None => format!("GitHub Actions of {owner}"),
+            };
+            let (owner, repo, annotations) = (owner.clone(), repo.clone(), annotations.clone());
+            (
+                label,
+                Box::new(move |layer: &SignatureLayer| {
+                    let Some(certificate) = &layer.certificate_signature else {
+                        return false;
+                    };
+                    let repository_matches = certificate
+                        .github_workflow_repository
+                        .as_deref()
+                        .and_then(|repository| repository.split_once('/'))
+                        .is_some_and(|(repository_owner, repository_name)| {
+                            repository_owner == owner
+                                && repo.as_deref().is_none_or(|repo| repo == repository_name)
+                        });
+                    repository_matches && has_annotations(layer, &annotations)
+                }),
+            )
+        }
+        Signature::Url {
+            url, annotations, ..
+        } => {
+            let (url, annotations) = (url.to_string(), annotations.clone());
+            (
+                format!("certificate with URL {url}"),
+                Box::new(move |layer: &SignatureLayer| {
+                    layer.certificate_signature.as_ref().is_some_and(|certificate| {
+                        matches!(&certificate.subject, CertificateSubject::Uri(uri) if *uri == url)
+                    }) && has_annotations(layer, &annotations)
+                }),
+            )
+        }
+        Signature::Certificate {
+            certificate,
+            require_rekor_bundle,
+            annotations,
+            ..
+        } => {
+            let verifier =
+                CertificateVerifier::from_pem(certificate.as_bytes(), *require_rekor_bundle, None)?;
+            let annotations = annotations.clone();
+            (
+                format!("certificate {}", key_fingerprint(certificate)),
+                Box::new(move |layer: &SignatureLayer| {
+                    verifier.verify(layer).unwrap_or(false) && has_annotations(layer, &annotations)
+                }),
+            )
+        }
+    };
+    Ok(requirement)
+}
+
+/// Builds the report of a successful verification, listing the signatures
+/// that satisfied the verification requirements
+pub(crate) async fn report(
+    url: &str,
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+    verification_options: &VerificationOptions,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+    verified_manifest_digest: &str,
+) -> Result<VerificationReport> {
+    let mut requirements: Vec<Requirement> = verification_options
+        .config
+        .iter()
+        .flat_map(|config| {
+            config
+                .all_of
+                .iter()
+                .flatten()
+                .chain(config.any_of.iter().flat_map(|any_of| &any_of.signatures))
+        })
+        .map(requirement)
+        .collect::<Result<_>>()?;
+    for certificate_identity in &verification_options.certificate_identities {
+        requirements.push((
+            format!("certificate with {certificate_identity}"),
+            Box::new(|layer: &SignatureLayer| certificate_identity.is_satisfied_by(layer)),
+        ));
+    }
+
+    let mut last_error = None;
+    for candidate in mirrors.candidates(url) {
+        let (layers, manifest_digest) = match trusted_signature_layers(
+            &candidate,
+            sources,
+            sigstore_trust_root.clone(),
+        )
+        .await
+        {
+            Ok(signatures) => signatures,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+        if manifest_digest != verified_manifest_digest {
+            return Err(anyhow!(
+                "the manifest digest of {} changed while it was being verified",
+                url
+            ));
+        }
+        let signatures = layers
+            .iter()
+            .filter_map(|layer| {
+                let matched: Vec<String> = requirements
+                    .iter()
+                    .filter(|(_, check)| check(layer))
+                    .map(|(label, _)| label.to_owned())
+                    .collect();
+                (!matched.is_empty()).then(|| SignatureReport::new(layer, matched))
+            })
+            .collect();
+
+        return Ok(VerificationReport {
+            policy: url.to_string(),
+            source: candidate,
+            manifest_digest,
+            signatures,
+        });
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow!("cannot find the signatures of {}", url)))
+}
+
+/// Outcome of the verification of a policy of the local store
+#[derive(Debug)]
+enum StoreVerification {
+    Verified,
+    Failed(String),
+    /// Only policies pulled from a registry can be verified
+    Skipped,
+}
+
+impl std::fmt::Display for StoreVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreVerification::Verified => write!(f, "verified"),
+            StoreVerification::Failed(e) => write!(f, "failed: {e}"),
+            StoreVerification::Skipped => write!(f, "skipped: not pulled from a registry"),
+        }
+    }
+}
+
+/// Verifies all the policies of the local store pulled from a registry: their
+/// signatures, and the local copies against the verified manifest digests.
+/// Prints the outcome as a table, and fails when any policy is not verified.
+pub(crate) async fn verify_store(
+    sources: Option<&Sources>,
+    mirrors: &RegistryMirrors,
+    verification_options: &VerificationOptions,
+    sigstore_trust_root: Option<Arc<ManualTrustRoot<'static>>>,
+) -> Result<()> {
+    let policies = crate::store_mode::list()?;
+    let mut table = Table::new();
+    table.set_format(*format::consts::FORMAT_NO_LINESEP_WITH_TITLE);
+    table.set_titles(row!["Policy", "Verification"]);
+
+    let mut failures = 0;
+    for policy in &policies {
+        let outcome = if policy.uri.starts_with("registry://") {
+            let verification = async {
+                let digest = verify(
+                    &policy.uri,
+                    sources,
+                    mirrors,
+                    verification_options,
+                    sigstore_trust_root.clone(),
+                )
+                .await?;
+                verify_local_checksum(policy, sources, &digest, sigstore_trust_root.clone()).await
+            };
+            match verification.await {
+                Ok(()) => StoreVerification::Verified,
+                Err(e) => {
+                    failures += 1;
+                    warn!(policy = policy.uri.as_str(), error = %e, "policy verification failed");
+                    StoreVerification::Failed(e.to_string())
+                }
+            }
+        } else {
+            StoreVerification::Skipped
+        };
+        table.add_row(row![policy.uri, outcome]);
+    }
+    table.printstd();
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "{} out of {} policies cannot be verified",
+            failures,
+            policies.len()
+        ));
+    }
+    Ok(())
+}
+
 pub(crate) async fn verify_local_checksum(
     policy: &Policy,
     sources: Option<&Sources>,
@@ -0,0 +1,394 @@
+//! Verification of the policies distributed outside of OCI registries, via
+//! `file://` and `https://` URIs, signed with detached signatures.
+//!
+//! The signatures are the ones produced by `cosign sign-blob --key`: either a
+//! base64 encoded signature (`--output-signature`), or a cosign bundle
+//! (`--bundle`). Keyless signatures are not supported, they would require the
+//! certificate chain to be verified against the Fulcio roots.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use policy_evaluator::policy_fetcher::{
+    fetch_policy,
+    sigstore::crypto::{CosignVerificationKey, Signature as CryptoSignature},
+    sources::Sources,
+    verify::config::Signature,
+    PullDestination,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info};
+use url::Url;
+
+use super::{key_fingerprint, SignatureReport, VerificationReport};
+use crate::config::verification::VerificationOptions;
+
+// Extensions of the signatures looked up next to the policy, when none is
+// given explicitly
+const SIGNATURE_EXTENSIONS: [&str; 2] = ["sig", "bundle"];
+
+/// A `cosign sign-blob --bundle` document
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CosignBundle {
+    base64_signature: String,
+    #[serde(default)]
+    cert: String,
+}
+
+/// A detached signature, and the file it has been read from
+struct DetachedSignature {
+    source: String,
+    signature: Vec<u8>,
+    digest: String,
+}
+
+impl DetachedSignature {
+    fn parse(source: &str, contents: &[u8]) -> Result<Self> {
+        let base64_signature = match serde_json::from_slice::<CosignBundle>(contents) {
+            Ok(bundle) if !bundle.cert.is_empty() => {
+                return Err(anyhow!(
+                    "{} is a keyless signature, only signatures produced with a key are supported for policies outside of registries",
+                    source
+                ))
+            }
+            Ok(bundle) => bundle.base64_signature,
+            Err(_) => String::from_utf8_lossy(contents).trim().to_string(),
+        };
+        let signature = STANDARD
+            .decode(&base64_signature)
+            .map_err(|e| anyhow!("{} is not a valid signature: {}", source, e))?;
+
+        Ok(Self {
+            source: source.to_string(),
+            signature,
+            digest: sha256_digest(contents),
+        })
+    }
+}
+
+fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{:x}", Sha256::digest(data))
+}
+
+/// The keys the detached signatures must be produced with
+#[derive(Debug, PartialEq)]
+struct KeyRequirements {
+    all_of: Vec<String>,
+    any_of: Vec<String>,
+    minimum_matches: usize,
+}
+
+impl KeyRequirements {
+    fn new(verification_options: &VerificationOptions) -> Result<Self> {
+        if !verification_options.certificate_identities.is_empty() {
+            return Err(keyless_not_supported());
+        }
+        let config = verification_options
+            .config
+            .as_ref()
+            .ok_or_else(|| anyhow!("no signatures to verify were provided"))?;
+
+        let all_of = config
+            .all_of
+            .iter()
+            .flatten()
+            .map(required_key)
+            .collect::<Result<Vec<_>>>()?;
+        let (any_of, minimum_matches) = match &config.any_of {
+            Some(any_of) => (
+                any_of
+                    .signatures
+                    .iter()
+                    .map(required_key)
+                    .collect::<Result<Vec<_>>>()?,
+                usize::from(any_of.minimum_matches),
+            ),
+            None => (Vec::new(), 0),
+        };
+
+        Ok(Self {
+            all_of,
+            any_of,
+            minimum_matches,
+        })
+    }
+}
+
+fn keyless_not_supported() -> anyhow::Error {
+    anyhow!(
+        "only signatures produced with a key can be verified for policies outside of registries"
+    )
+}
+
+fn required_key(signature: &Signature) -> Result<String> {
+    match signature {
+        Signature::PubKey {
+            key, annotations, ..
+        } => {
+            if annotations.as_ref().is_some_and(|a| !a.is_empty()) {
+                return Err(anyhow!(
+                    "detached signatures carry no annotations, they cannot be verified for policies outside of registries"
+                ));
+            }
+            Ok(key.to_owned())
+        }
+        _ => Err(keyless_not_supported()),
+    }
+}
+
+/// Returns the labels of the keys that produced the signature
+fn matching_keys(
+    keys: &[String],
+    signature: &DetachedSignature,
+    policy: &[u8],
+) -> Result<Vec<String>> {
+    let mut matched = Vec::new();
+    for key in keys {
+        let verification_key = CosignVerificationKey::try_from_pem(key.as_bytes())?;
+        if verification_key
+            .verify_signature(CryptoSignature::Raw(&signature.signature), policy)
+            .is_ok()
+        {
+            matched.push(format!("key {}", key_fingerprint(key)));
+        }
+    }
+    Ok(matched)
+}
+
+/// Checks the detached signatures against the key requirements, returning
+/// the signatures that matched at least one key
+fn check_signatures(
+    requirements: &KeyRequirements,
+    signatures: &[DetachedSignature],
+    policy: &[u8],
+) -> Result<Vec<SignatureReport>> {
+    let mut reports = Vec::new();
+    let mut all_of_matched = vec![false; requirements.all_of.len()];
+    let mut any_of_matched = vec![false; requirements.any_of.len()];
+    for signature in signatures {
+        let mut matched = Vec::new();
+        for (keys, key_matched) in [
+            (&requirements.all_of, &mut all_of_matched),
+            (&requirements.any_of, &mut any_of_matched),
+        ] {
+            for (index, key) in keys.iter().enumerate() {
+                let labels = matching_keys(std::slice::from_ref(key), signature, policy)?;
+                if !labels.is_empty() {
+                    key_matched[index] = true;
+                    matched.extend(labels);
+                }
+            }
+        }
+        debug!(
+            signature = signature.source,
+            ?matched,
+            "detached signature checked"
+        );
+        if !matched.is_empty() {
+            matched.dedup();
+            reports.push(SignatureReport {
+                digest: signature.digest.to_owned(),
+                certificate: None,
+                rekor_log_index: None,
+                rekor_integrated_time: None,
+                annotations: Default::default(),
+                matched,
+            });
+        }
+    }
+
+    if let Some(index) = all_of_matched.iter().position(|matched| !matched) {
+        return Err(anyhow!(
+            "no signature produced with key {}",
+            key_fingerprint(&requirements.all_of[index])
+        ));
+    }
+    let any_of_count = any_of_matched.iter().filter(|matched| **matched).count();
+    if any_of_count < requirements.minimum_matches {
+        return Err(anyhow!(
+            "{} signatures match the anyOf keys, {} are required",
+            any_of_count,
+            requirements.minimum_matches
+        ));
+    }
+
+    Ok(reports)
+}
+
+/// Reads a `file://` or `https://` resource
+async fn read(uri: &str, sources: Option<&Sources>, download_dir: &Path) -> Result<Vec<u8>> {
+    if let Some(path) = uri.strip_prefix("file://") {
+        let path = Url::parse(uri)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .unwrap_or_else(|| PathBuf::from(path));
+        return fs::read(&path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e));
+    }
+    if !uri.starts_with("https://") && !uri.starts_with("http://") {
+        return Err(anyhow!(
+            "{} is not supported, policies can be verified via registry://, https:// and file:// URIs",
+            uri
+        ));
+    }
+
+    let destination = download_dir.join(sha256_digest(uri.as_bytes()).replace(':', "-"));
+    fetch_policy(
+        uri,
+        PullDestination::LocalFile(destination.clone()),
+        sources,
+    )
+    .await
+    .map_err(|e| anyhow!("cannot download {}: {}", uri, e))?;
+    fs::read(&destination).map_err(|e| anyhow!("cannot read {}: {}", uri, e))
+}
+
+/// Verifies the policy at `uri` against detached signatures. When no
+/// signature is given, the `.sig` and `.bundle` files next to the policy are
+/// used.
+pub(crate) async fn verify(
+    uri: &str,
+    sources: Option<&Sources>,
+    verification_options: &VerificationOptions,
+    signature_uris: &[String],
+) -> Result<VerificationReport> {
+    let requirements = KeyRequirements::new(verification_options)?;
+    let download_dir = tempfile::tempdir()?;
+    let policy = read(uri, sources, download_dir.path()).await?;
+
+    let mut signatures = Vec::new();
+    if signature_uris.is_empty() {
+        for extension in SIGNATURE_EXTENSIONS {
+            let signature_uri = format!("{uri}.{extension}");
+            match read(&signature_uri, sources, download_dir.path()).await {
+                Ok(contents) => {
+                    signatures.push(DetachedSignature::parse(&signature_uri, &contents)?)
+                }
+                Err(e) => {
+                    debug!(signature = signature_uri, error = %e, "detached signature not found")
+                }
+            }
+        }
+        if signatures.is_empty() {
+            return Err(anyhow!(
+                "cannot find {uri}.sig nor {uri}.bundle, provide the signatures via --signature"
+            ));
+        }
+    } else {
+        for signature_uri in signature_uris {
+            let signature_uri = crate::utils::map_path_to_uri(signature_uri)?;
+            let contents = read(&signature_uri, sources, download_dir.path()).await?;
+            signatures.push(DetachedSignature::parse(&signature_uri, &contents)?);
+        }
+    }
+
+    let reports = check_signatures(&requirements, &signatures, &policy)?;
+    info!("Policy successfully verified");
+
+    Ok(VerificationReport {
+        policy: uri.to_string(),
+        source: signatures
+            .iter()
+            .map(|signature| signature.source.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        manifest_digest: sha256_digest(&policy),
+        signatures: reports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use policy_evaluator::policy_fetcher::sigstore::crypto::{SigStoreKeyPair, SigningScheme};
+    use rstest::rstest;
+
+    const POLICY: &[u8] = b"\0asm policy";
+
+    fn key(name: &str) -> String {
+        fs::read_to_string(format!("tests/data/sigstore/{name}.pub")).unwrap()
+    }
+
+    fn sign(name: &str, data: &[u8]) -> String {
+        let signer = SigStoreKeyPair::from_encrypted_pem(
+            &fs::read(format!("tests/data/sigstore/{name}.key")).unwrap(),
+            b"kubewarden",
+        )
+        .unwrap()
+        .to_sigstore_signer(&SigningScheme::default())
+        .unwrap();
+        STANDARD.encode(signer.sign(data).unwrap())
+    }
+
+    fn requirements(all_of: &[&str], any_of: &[&str], minimum_matches: usize) -> KeyRequirements {
+        KeyRequirements {
+            all_of: all_of.iter().map(|name| key(name)).collect(),
+            any_of: any_of.iter().map(|name| key(name)).collect(),
+            minimum_matches,
+        }
+    }
+
+    #[rstest]
+    #[case::signature(sign("cosign1", POLICY))]
+    #[case::bundle(format!(
+        r#"{{"base64Signature": "{}", "cert": "", "rekorBundle": null}}"#,
+        sign("cosign1", POLICY)
+    ))]
+    fn detached_signature_formats(#[case] contents: String) {
+        let signature = DetachedSignature::parse("policy.wasm.sig", contents.as_bytes()).unwrap();
+        let reports =
+            check_signatures(&requirements(&["cosign1"], &[], 0), &[signature], POLICY).unwrap();
+
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].matched,
+            vec![format!("key {}", key_fingerprint(&key("cosign1")))]
+        );
+    }
+
+    #[test]
+    fn keyless_bundles_are_rejected() {
+        let bundle = r#"{"base64Signature": "AQID", "cert": "LS0tLS1CRUdJTi=="}"#;
+        assert!(DetachedSignature::parse("policy.wasm.bundle", bundle.as_bytes()).is_err());
+    }
+
+    #[rstest]
+    #[case::all_of_satisfied(requirements(&["cosign1", "cosign2"], &[], 0), true)]
+    #[case::all_of_missing_key(requirements(&["cosign1", "cosign3"], &[], 0), false)]
+    #[case::any_of_satisfied(requirements(&[], &["cosign2", "cosign3"], 1), true)]
+    #[case::any_of_not_enough(requirements(&[], &["cosign1", "cosign3"], 2), false)]
+    fn key_requirements(#[case] requirements: KeyRequirements, #[case] valid: bool) {
+        let signatures = [
+            DetachedSignature::parse("policy.wasm.sig", sign("cosign1", POLICY).as_bytes())
+                .unwrap(),
+            DetachedSignature::parse("policy.wasm.bundle", sign("cosign2", POLICY).as_bytes())
+                .unwrap(),
+        ];
+
+        assert_eq!(
+            check_signatures(&requirements, &signatures, POLICY).is_ok(),
+            valid
+        );
+    }
+
+    #[test]
+    fn tampered_policy() {
+        let signatures =
+            [
+                DetachedSignature::parse("policy.wasm.sig", sign("cosign1", POLICY).as_bytes())
+                    .unwrap(),
+            ];
+
+        assert!(check_signatures(
+            &requirements(&["cosign1"], &[], 0),
+            &signatures,
+            b"\0asm tampered policy"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use clap::crate_version;
+use policy_evaluator::policy_fetcher::oci_client::manifest::{
+    WASM_CONFIG_MEDIA_TYPE, WASM_LAYER_MEDIA_TYPE,
+};
+use serde::Serialize;
+
+use crate::sign::SIGSTORE_OCI_MEDIA_TYPE;
+
+/// Build information of kwctl, meant to be consumed by bug reports and
+/// wrapper tooling
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionInfo {
+    version: &'static str,
+    git_commit: &'static str,
+    build_date: String,
+    rustc_version: &'static str,
+    features: Vec<&'static str>,
+    wasmtime_version: &'static str,
+    media_types: Vec<&'static str>,
+}
+
+impl VersionInfo {
+    fn new() -> Self {
+        let build_date = env!("KWCTL_BUILD_TIMESTAMP")
+            .parse::<i64>()
+            .ok()
+            .and_then(crate::timestamps::format_unix)
+            .unwrap_or_else(|| "unknown".to_string());
+
+        Self {
+            version: crate_version!(),
+            git_commit: env!("KWCTL_GIT_COMMIT"),
+            build_date,
+            rustc_version: env!("KWCTL_RUSTC_VERSION"),
+            features: env!("KWCTL_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+            wasmtime_version: env!("KWCTL_WASMTIME_VERSION"),
+            media_types: vec![
+                WASM_CONFIG_MEDIA_TYPE,
+                WASM_LAYER_MEDIA_TYPE,
+                SIGSTORE_OCI_MEDIA_TYPE,
+            ],
+        }
+    }
+}
+
+pub(crate) fn version(output: Option<&str>) -> Result<()> {
+    let info = VersionInfo::new();
+
+    match output {
+        Some("json") => println!("{}", serde_json::to_string_pretty(&info)?),
+        _ => {
+            let features = if info.features.is_empty() {
+                "none".to_string()
+            } else {
+                info.features.join(", ")
+            };
+            println!(
+                r#"kwctl version: {}
+Git commit: {}
+Build date: {}
+Rust compiler: {}
+Enabled features: {}
+Wasmtime version: {}
+Supported media types:
+{}"#,
+                info.version,
+                info.git_commit,
+                info.build_date,
+                info.rustc_version,
+                features,
+                info.wasmtime_version,
+                info.media_types
+                    .iter()
+                    .map(|media_type| format!("  - {media_type}"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+    }
+
+    Ok(())
+}
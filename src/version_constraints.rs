@@ -0,0 +1,183 @@
+//! Semantic version constraints used as tags of policy URIs.
+//!
+//! A policy can be referenced by a constraint instead of a tag, like
+//! `registry://ghcr.io/kubewarden/policies/safe-labels:^1.2`. The constraint
+//! is resolved to the highest version, among the tags of the repository, that
+//! satisfies it. GitOps repositories can then reference policies whose
+//! version floats within bounds.
+//!
+//! The resolutions are recorded into the lockfile given via `--lockfile`,
+//! which keeps track of the policies actually used.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Result};
+use policy_evaluator::policy_fetcher::{oci_client::Reference, sources::Sources};
+use semver::VersionReq;
+use tracing::info;
+
+use crate::updates;
+
+static LOCKFILE: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Selects the lockfile recording the resolved constraints
+pub(crate) fn init(lockfile: Option<PathBuf>) {
+    let _ = LOCKFILE.set(lockfile);
+}
+
+fn lockfile() -> Option<&'static Path> {
+    LOCKFILE.get().and_then(Option::as_deref)
+}
+
+/// Splits a registry URI whose tag is a version constraint into its
+/// repository and its constraint, `None` for all the other URIs.
+///
+/// Tags are made only of letters, digits, `_`, `.` and `-`, anything else
+/// makes the tag a constraint.
+pub(crate) fn split(uri: &str) -> Option<(&str, &str)> {
+    if !uri.starts_with("registry://") || uri.contains('@') {
+        return None;
+    }
+    let (repository, tag) = uri.rsplit_once(':')?;
+    if tag.contains('/')
+        || tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        return None;
+    }
+    Some((repository, tag))
+}
+
+/// The URI without its version constraint, when any
+pub(crate) fn without_constraint(uri: &str) -> &str {
+    split(uri).map_or(uri, |(repository, _)| repository)
+}
+
+/// Resolves the version constraint used as tag of the URI, returns the URI
+/// unchanged when its tag is not a constraint
+pub(crate) async fn resolve(uri: &str, sources: Option<&Sources>) -> Result<String> {
+    let Some((repository, constraint)) = split(uri) else {
+        return Ok(uri.to_string());
+    };
+    let requirement = VersionReq::parse(constraint)
+        .map_err(|e| anyhow!("invalid version constraint '{}': {}", constraint, e))?;
+    let image = repository.strip_prefix("registry://").unwrap_or(repository);
+    let reference = Reference::from_str(image)
+        .map_err(|e| anyhow!("cannot parse image reference {}: {}", image, e))?;
+    let tags = updates::list_tags(&reference, sources)
+        .await
+        .map_err(|e| anyhow!("cannot list the tags of {}: {}", repository, e))?;
+    let tag = highest_match(&requirement, &tags).ok_or_else(|| {
+        anyhow!(
+            "no tag of {} satisfies the version constraint '{}'",
+            repository,
+            constraint
+        )
+    })?;
+
+    let resolved = format!("{repository}:{tag}");
+    info!(
+        policy = uri,
+        resolved = resolved.as_str(),
+        "version constraint resolved"
+    );
+    if let Some(lockfile) = lockfile() {
+        lock(lockfile, uri, &resolved)?;
+    }
+    Ok(resolved)
+}
+
+/// The tag of the highest version satisfying the requirement. Pre-releases
+/// match only the requirements mentioning a pre-release of the same version.
+fn highest_match<'a>(requirement: &VersionReq, tags: &'a [String]) -> Option<&'a str> {
+    tags.iter()
+        .filter_map(|tag| updates::parse_version(tag).map(|version| (version, tag)))
+        .filter(|(version, _)| requirement.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag.as_str())
+}
+
+/// Records the resolution into the lockfile, keeping the other entries
+fn lock(lockfile: &Path, uri: &str, resolved: &str) -> Result<()> {
+    let mut entries: BTreeMap<String, String> = match fs::read_to_string(lockfile) {
+        Ok(contents) => serde_yaml::from_str(&contents)
+            .map_err(|e| anyhow!("cannot parse lockfile {}: {}", lockfile.display(), e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => {
+            return Err(anyhow!(
+                "cannot read lockfile {}: {}",
+                lockfile.display(),
+                e
+            ))
+        }
+    };
+    entries.insert(uri.to_string(), resolved.to_string());
+    fs::write(lockfile, serde_yaml::to_string(&entries)?)
+        .map_err(|e| anyhow!("cannot write lockfile {}: {}", lockfile.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::caret("registry://ghcr.io/kubewarden/policies/psp:^1.2", Some(("registry://ghcr.io/kubewarden/policies/psp", "^1.2")))]
+    #[case::range("registry://localhost:5000/psp:>=1.2, <2", Some(("registry://localhost:5000/psp", ">=1.2, <2")))]
+    #[case::wildcard("registry://ghcr.io/kubewarden/policies/psp:*", Some(("registry://ghcr.io/kubewarden/policies/psp", "*")))]
+    #[case::tag("registry://ghcr.io/kubewarden/policies/psp:v1.2.0", None)]
+    #[case::port_without_tag("registry://localhost:5000/psp", None)]
+    #[case::digest("registry://ghcr.io/kubewarden/policies/psp@sha256:0000", None)]
+    #[case::not_registry("https://example.com/psp.wasm", None)]
+    fn constraint_detection(#[case] uri: &str, #[case] expected: Option<(&str, &str)>) {
+        assert_eq!(split(uri), expected);
+    }
+
+    #[rstest]
+    #[case::caret("^1.2", &["v1.1.0", "v1.2.0", "v1.4.1", "v2.0.0"], Some("v1.4.1"))]
+    #[case::tilde("~1.2", &["v1.2.0", "v1.2.3", "v1.3.0"], Some("v1.2.3"))]
+    #[case::upper_bound(">=1.2, <1.4", &["1.2.0", "1.3.9", "1.4.0"], Some("1.3.9"))]
+    #[case::pre_releases_skipped("^1.2", &["v1.2.0", "v1.3.0-rc1"], Some("v1.2.0"))]
+    #[case::no_match("^3", &["v1.2.0", "latest"], None)]
+    fn constraint_resolution(
+        #[case] constraint: &str,
+        #[case] tags: &[&str],
+        #[case] expected: Option<&str>,
+    ) {
+        let requirement = VersionReq::parse(constraint).unwrap();
+        let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+        assert_eq!(highest_match(&requirement, &tags), expected);
+    }
+
+    #[test]
+    fn lockfile_entries_are_merged() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let lockfile = tempdir.path().join("kwctl.lock");
+        lock(&lockfile, "registry://a:^1", "registry://a:v1.2.0").unwrap();
+        lock(&lockfile, "registry://b:~2.1", "registry://b:v2.1.3").unwrap();
+        lock(&lockfile, "registry://a:^1", "registry://a:v1.3.0").unwrap();
+
+        let entries: BTreeMap<String, String> =
+            serde_yaml::from_str(&fs::read_to_string(&lockfile).unwrap()).unwrap();
+        assert_eq!(
+            entries,
+            BTreeMap::from([
+                (
+                    "registry://a:^1".to_string(),
+                    "registry://a:v1.3.0".to_string()
+                ),
+                (
+                    "registry://b:~2.1".to_string(),
+                    "registry://b:v2.1.3".to_string()
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Interface of the WebAssembly module of a policy.
+//!
+//! `kwctl inspect` reports the host functions imported by the module, the
+//! functions it exports, the interface it is built against and the Kubewarden
+//! host capabilities it calls. Operators can then review the privileges
+//! needed by a third-party policy before deploying it.
+//!
+//! The size of every section of the module is reported too, flagging the
+//! unusually large ones, so that policy authors can tell why their policy
+//! grew.
+
+use std::{collections::BTreeSet, fmt};
+
+use anyhow::{anyhow, Result};
+use prettytable::{format::FormatBuilder, row, Table};
+use wasmparser::{ExternalKind, Parser, Payload, TypeRef};
+
+use crate::graph::{detect_capabilities, Capability};
+
+/// Interface the WebAssembly module is built against
+#[derive(Debug, PartialEq)]
+pub(crate) enum InterfaceKind {
+    Wapc,
+    Wasi,
+    Opa,
+    Unknown,
+}
+
+impl fmt::Display for InterfaceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterfaceKind::Wapc => write!(f, "waPC"),
+            InterfaceKind::Wasi => write!(f, "WASI"),
+            InterfaceKind::Opa => write!(f, "OPA (Rego)"),
+            InterfaceKind::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Interface of the WebAssembly module of a policy
+#[derive(Debug)]
+pub(crate) struct WasmInterface {
+    pub(crate) kind: InterfaceKind,
+    /// Host functions imported by the module, as `module::name`
+    pub(crate) imports: Vec<String>,
+    /// Functions exported by the module
+    pub(crate) exports: Vec<String>,
+    pub(crate) capabilities: BTreeSet<Capability>,
+    /// Sections of the module, the largest first
+    pub(crate) sections: Vec<Section>,
+    /// Size of the whole module
+    pub(crate) size: u64,
+}
+
+// Sections smaller than this are never flagged as unusually large
+const LARGE_SECTION_SIZE: u64 = 1_000_000;
+
+/// A section of the WebAssembly module
+#[derive(Debug, PartialEq)]
+pub(crate) struct Section {
+    pub(crate) name: String,
+    pub(crate) size: u64,
+}
+
+impl Section {
+    /// A section is unusually large when it exceeds 1 MB and takes at least
+    /// a quarter of the module
+    fn is_large(&self, module_size: u64) -> bool {
+        self.size >= LARGE_SECTION_SIZE && self.size * 4 >= module_size
+    }
+
+    /// Hint about the content of an unusually large section
+    fn hint(&self, kind: &InterfaceKind) -> &'static str {
+        match self.name.as_str() {
+            "code" => "unusually large, check the dependencies of the policy",
+            "data" if *kind == InterfaceKind::Opa => {
+                "unusually large, holds the data of the Rego bundle"
+            }
+            "data" => "unusually large, holds the static data of the policy",
+            name if name.starts_with("custom: .debug") || name == "custom: name" => {
+                "unusually large, debug information not needed at runtime"
+            }
+            _ => "unusually large",
+        }
+    }
+}
+
+fn section_name(id: u8) -> &'static str {
+    match id {
+        1 => "type",
+        2 => "import",
+        3 => "function",
+        4 => "table",
+        5 => "memory",
+        6 => "global",
+        7 => "export",
+        8 => "start",
+        9 => "element",
+        10 => "code",
+        11 => "data",
+        12 => "data count",
+        13 => "tag",
+        _ => "unknown",
+    }
+}
+
+impl WasmInterface {
+    /// Parses the import and export sections of the module
+    pub(crate) fn parse(wasm: &[u8]) -> Result<Self> {
+        let mut imports = Vec::new();
+        let mut exports = Vec::new();
+        let mut sections = Vec::new();
+        for payload in Parser::new(0).parse_all(wasm) {
+            let payload = payload.map_err(|e| anyhow!("cannot parse WebAssembly file: {}", e))?;
+            if let Some((id, range)) = payload.as_section() {
+                let name = match &payload {
+                    Payload::CustomSection(section) => format!("custom: {}", section.name()),
+                    _ => section_name(id).to_string(),
+                };
+                sections.push(Section {
+                    name,
+                    size: range.len() as u64,
+                });
+            }
+            match payload {
+                Payload::ImportSection(section) => {
+                    for import in section {
+                        let import = import.map_err(|e| {
+                            anyhow!("cannot parse WebAssembly import section: {}", e)
+                        })?;
+                        if let TypeRef::Func(_) = import.ty {
+                            imports.push(format!("{}::{}", import.module, import.name));
+                        }
+                    }
+                }
+                Payload::ExportSection(section) => {
+                    for export in section {
+                        let export = export.map_err(|e| {
+                            anyhow!("cannot parse WebAssembly export section: {}", e)
+                        })?;
+                        if export.kind == ExternalKind::Func {
+                            exports.push(export.name.to_string());
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        sections.sort_by(|a, b| b.size.cmp(&a.size));
+        Ok(WasmInterface {
+            kind: interface_kind(&imports, &exports),
+            imports,
+            exports,
+            capabilities: detect_capabilities(wasm),
+            sections,
+            size: wasm.len() as u64,
+        })
+    }
+
+    /// Prints the size of the sections as the "Size" section of `kwctl inspect`
+    pub(crate) fn print_sizes(&self) {
+        let mut table = Table::new();
+        table.set_format(FormatBuilder::new().padding(0, 1).build());
+        table.add_row(row![Fmbl -> "Size"]);
+        table.add_row(row![
+            Fgbl -> "module:",
+            humansize::format_size(self.size, humansize::DECIMAL)
+        ]);
+        for section in &self.sections {
+            let mut size = format!(
+                "{} ({}%)",
+                humansize::format_size(section.size, humansize::DECIMAL),
+                section.size * 100 / self.size.max(1)
+            );
+            if section.is_large(self.size) {
+                size = format!("{size} - {}", section.hint(&self.kind));
+            }
+            table.add_row(row![Fgbl -> format!("{}:", section.name), size]);
+        }
+        table.printstd();
+    }
+
+    /// Prints the interface as the "Interface" section of `kwctl inspect`
+    pub(crate) fn print(&self) {
+        let mut table = Table::new();
+        table.set_format(FormatBuilder::new().padding(0, 1).build());
+        table.add_row(row![Fmbl -> "Interface"]);
+        table.add_row(row![Fgbl -> "type:", self.kind]);
+        table.add_row(row![Fgbl -> "host functions:", list_or_none(&self.imports)]);
+        table.add_row(row![Fgbl -> "exports:", list_or_none(&self.exports)]);
+        let capabilities: Vec<String> = self
+            .capabilities
+            .iter()
+            .map(|capability| capability.label().to_string())
+            .collect();
+        table.add_row(row![Fgbl -> "host capabilities:", list_or_none(&capabilities)]);
+        table.printstd();
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join("\n")
+    }
+}
+
+/// waPC policies built with TinyGo or Rust can import WASI functions too,
+/// hence waPC is looked for first
+fn interface_kind(imports: &[String], exports: &[String]) -> InterfaceKind {
+    if exports.iter().any(|export| export.starts_with("opa_")) {
+        InterfaceKind::Opa
+    } else if imports.iter().any(|import| import.starts_with("wapc::"))
+        || exports.iter().any(|export| export == "__guest_call")
+    {
+        InterfaceKind::Wapc
+    } else if imports.iter().any(|import| import.starts_with("wasi"))
+        || exports.iter().any(|export| export == "_start")
+    {
+        InterfaceKind::Wasi
+    } else {
+        InterfaceKind::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn module(imports: &[(&str, &str)], exports: &[&str]) -> Vec<u8> {
+        let mut module = walrus::Module::default();
+        let ty = module.types.add(&[], &[]);
+        for (namespace, name) in imports {
+            module.add_import_func(namespace, name, ty);
+        }
+        for name in exports {
+            let function = walrus::FunctionBuilder::new(&mut module.types, &[], &[])
+                .finish(vec![], &mut module.funcs);
+            module.exports.add(name, function);
+        }
+        module.emit_wasm()
+    }
+
+    #[rstest]
+    #[case::wapc(
+        &[("wapc", "__host_call"), ("wapc", "__guest_request")],
+        &["__guest_call", "wapc_init"],
+        InterfaceKind::Wapc
+    )]
+    #[case::wapc_importing_wasi(
+        &[("wasi_snapshot_preview1", "fd_write"), ("wapc", "__host_call")],
+        &["__guest_call"],
+        InterfaceKind::Wapc
+    )]
+    #[case::wasi(&[("wasi_snapshot_preview1", "fd_write")], &["_start"], InterfaceKind::Wasi)]
+    #[case::opa(&[("env", "opa_abort")], &["opa_eval", "eval"], InterfaceKind::Opa)]
+    #[case::unknown(&[], &["run"], InterfaceKind::Unknown)]
+    fn interface_detection(
+        #[case] imports: &[(&str, &str)],
+        #[case] exports: &[&str],
+        #[case] expected: InterfaceKind,
+    ) {
+        let interface = WasmInterface::parse(&module(imports, exports)).unwrap();
+        assert_eq!(interface.kind, expected);
+        assert_eq!(
+            interface.imports,
+            imports
+                .iter()
+                .map(|(namespace, name)| format!("{namespace}::{name}"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(interface.exports, exports);
+    }
+
+    #[test]
+    fn section_sizes() {
+        let mut module = walrus::Module::default();
+        let function = walrus::FunctionBuilder::new(&mut module.types, &[], &[])
+            .finish(vec![], &mut module.funcs);
+        module.exports.add("_start", function);
+        module.customs.add(walrus::RawCustomSection {
+            name: "assets".to_string(),
+            data: vec![0; 2_000_000],
+        });
+        let interface = WasmInterface::parse(&module.emit_wasm()).unwrap();
+
+        let assets = &interface.sections[0];
+        assert_eq!(assets.name, "custom: assets");
+        assert!(assets.size >= 2_000_000);
+        assert!(assets.is_large(interface.size));
+        assert!(interface
+            .sections
+            .iter()
+            .any(|section| section.name == "code" && !section.is_large(interface.size)));
+    }
+
+    #[rstest]
+    #[case::debug_info(
+        "custom: .debug_info",
+        InterfaceKind::Wapc,
+        "unusually large, debug information not needed at runtime"
+    )]
+    #[case::rego_bundle(
+        "data",
+        InterfaceKind::Opa,
+        "unusually large, holds the data of the Rego bundle"
+    )]
+    #[case::data(
+        "data",
+        InterfaceKind::Wasi,
+        "unusually large, holds the static data of the policy"
+    )]
+    #[case::other("custom: assets", InterfaceKind::Wapc, "unusually large")]
+    fn section_hints(#[case] name: &str, #[case] kind: InterfaceKind, #[case] expected: &str) {
+        let section = Section {
+            name: name.to_string(),
+            size: 2_000_000,
+        };
+        assert_eq!(section.hint(&kind), expected);
+    }
+
+    #[rstest]
+    #[case::large(2_000_000, 4_000_000, true)]
+    #[case::small_share(2_000_000, 10_000_000, false)]
+    #[case::small_module(500_000, 600_000, false)]
+    fn large_sections(#[case] size: u64, #[case] module_size: u64, #[case] expected: bool) {
+        let section = Section {
+            name: "data".to_string(),
+            size,
+        };
+        assert_eq!(section.is_large(module_size), expected);
+    }
+
+    #[test]
+    fn invalid_module() {
+        assert!(WasmInterface::parse(b"not a wasm module").is_err());
+    }
+}
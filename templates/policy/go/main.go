@@ -0,0 +1,12 @@
+package main
+
+import (
+	wapc "github.com/wapc/wapc-guest-tinygo"
+)
+
+func main() {
+	wapc.RegisterFunctions(wapc.Functions{
+		"validate":          validate,
+		"validate_settings": validateSettings,
+	})
+}
@@ -0,0 +1,39 @@
+package main
+
+import (
+	"encoding/json"
+	"fmt"
+
+	kubewarden "github.com/kubewarden/policy-sdk-go"
+	kubewarden_protocol "github.com/kubewarden/policy-sdk-go/protocol"
+)
+
+type Settings struct {
+	DeniedNames []string `json:"denied_names"`
+}
+
+func (s *Settings) Valid() error {
+	for _, name := range s.DeniedNames {
+		if name == "" {
+			return fmt.Errorf("denied_names cannot contain empty names")
+		}
+	}
+	return nil
+}
+
+func NewSettingsFromValidationReq(validationReq *kubewarden_protocol.ValidationRequest) (Settings, error) {
+	settings := Settings{}
+	err := json.Unmarshal(validationReq.Settings, &settings)
+	return settings, err
+}
+
+func validateSettings(payload []byte) ([]byte, error) {
+	settings := Settings{}
+	if err := json.Unmarshal(payload, &settings); err != nil {
+		return kubewarden.RejectSettings(kubewarden.Message(fmt.Sprintf("cannot unmarshal settings: %v", err)))
+	}
+	if err := settings.Valid(); err != nil {
+		return kubewarden.RejectSettings(kubewarden.Message(err.Error()))
+	}
+	return kubewarden.AcceptSettings()
+}
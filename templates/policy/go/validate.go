@@ -0,0 +1,36 @@
+package main
+
+import (
+	"encoding/json"
+	"fmt"
+	"slices"
+
+	corev1 "github.com/kubewarden/k8s-objects/api/core/v1"
+	kubewarden "github.com/kubewarden/policy-sdk-go"
+	kubewarden_protocol "github.com/kubewarden/policy-sdk-go/protocol"
+)
+
+func validate(payload []byte) ([]byte, error) {
+	validationRequest := kubewarden_protocol.ValidationRequest{}
+	if err := json.Unmarshal(payload, &validationRequest); err != nil {
+		return kubewarden.RejectRequest(kubewarden.Message(err.Error()), kubewarden.Code(400))
+	}
+
+	settings, err := NewSettingsFromValidationReq(&validationRequest)
+	if err != nil {
+		return kubewarden.RejectRequest(kubewarden.Message(err.Error()), kubewarden.Code(400))
+	}
+
+	pod := &corev1.Pod{}
+	if err := json.Unmarshal(validationRequest.Request.Object, pod); err != nil {
+		// not a pod, nothing to validate
+		return kubewarden.AcceptRequest()
+	}
+	if pod.Metadata != nil && slices.Contains(settings.DeniedNames, pod.Metadata.Name) {
+		return kubewarden.RejectRequest(
+			kubewarden.Message(fmt.Sprintf("pod name %s is not accepted", pod.Metadata.Name)),
+			kubewarden.NoCode)
+	}
+
+	return kubewarden.AcceptRequest()
+}
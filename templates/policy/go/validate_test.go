@@ -0,0 +1,49 @@
+package main
+
+import (
+	"encoding/json"
+	"os"
+	"testing"
+
+	kubewarden_protocol "github.com/kubewarden/policy-sdk-go/protocol"
+)
+
+func evaluate(t *testing.T, deniedNames []string) kubewarden_protocol.ValidationResponse {
+	request, err := os.ReadFile("test_data/pod_creation.json")
+	if err != nil {
+		t.Fatal(err)
+	}
+	settings, err := json.Marshal(Settings{DeniedNames: deniedNames})
+	if err != nil {
+		t.Fatal(err)
+	}
+	payload, err := json.Marshal(map[string]json.RawMessage{
+		"request":  request,
+		"settings": settings,
+	})
+	if err != nil {
+		t.Fatal(err)
+	}
+
+	responsePayload, err := validate(payload)
+	if err != nil {
+		t.Fatal(err)
+	}
+	var response kubewarden_protocol.ValidationResponse
+	if err := json.Unmarshal(responsePayload, &response); err != nil {
+		t.Fatal(err)
+	}
+	return response
+}
+
+func TestAcceptPodWithValidName(t *testing.T) {
+	if !evaluate(t, []string{"bad-name"}).Accepted {
+		t.Error("the pod should be accepted")
+	}
+}
+
+func TestRejectPodWithDeniedName(t *testing.T) {
+	if evaluate(t, []string{"nginx"}).Accepted {
+		t.Error("the pod should be rejected")
+	}
+}
@@ -0,0 +1,30 @@
+package policy
+
+import rego.v1
+
+# the settings of the policy are given as data
+denied_names := object.get(data, "denied_names", [])
+
+deny contains msg if {
+	input.request.kind.kind == "Pod"
+	name := input.request.object.metadata.name
+	name in denied_names
+	msg := sprintf("pod name %v is not accepted", [name])
+}
+
+response := {
+	"uid": input.request.uid,
+	"allowed": false,
+	"status": {"message": concat(", ", deny)},
+} if {
+	count(deny) > 0
+} else := {
+	"uid": input.request.uid,
+	"allowed": true,
+}
+
+main := {
+	"apiVersion": "admission.k8s.io/v1",
+	"kind": "AdmissionReview",
+	"response": response,
+}
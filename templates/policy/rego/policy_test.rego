@@ -0,0 +1,19 @@
+package policy_test
+
+import rego.v1
+
+import data.policy
+
+pod_request := {"request": {
+	"uid": "1299d386-525b-4032-98ae-1949f69f9cfc",
+	"kind": {"group": "", "version": "v1", "kind": "Pod"},
+	"object": {"metadata": {"name": "nginx"}},
+}}
+
+test_accept_pod_with_valid_name if {
+	policy.main.response.allowed with input as pod_request with data.denied_names as ["bad-name"]
+}
+
+test_reject_pod_with_denied_name if {
+	not policy.main.response.allowed with input as pod_request with data.denied_names as ["nginx"]
+}
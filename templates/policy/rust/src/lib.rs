@@ -0,0 +1,68 @@
+use guest::prelude::*;
+use kubewarden_policy_sdk::wapc_guest as guest;
+
+use k8s_openapi::api::core::v1 as apicore;
+
+extern crate kubewarden_policy_sdk as kubewarden;
+use kubewarden::{protocol_version_guest, request::ValidationRequest, validate_settings};
+
+mod settings;
+use settings::Settings;
+
+#[no_mangle]
+pub extern "C" fn wapc_init() {
+    register_function("validate", validate);
+    register_function("validate_settings", validate_settings::<Settings>);
+    register_function("protocol_version", protocol_version_guest);
+}
+
+fn validate(payload: &[u8]) -> CallResult {
+    let validation_request: ValidationRequest<Settings> = ValidationRequest::new(payload)?;
+
+    let pod = match serde_json::from_value::<apicore::Pod>(validation_request.request.object) {
+        Ok(pod) => pod,
+        // not a pod, nothing to validate
+        Err(_) => return kubewarden::accept_request(),
+    };
+    let name = pod.metadata.name.unwrap_or_default();
+    if validation_request.settings.denied_names.contains(&name) {
+        return kubewarden::reject_request(
+            Some(format!("pod name {name} is not accepted")),
+            None,
+            None,
+            None,
+        );
+    }
+
+    kubewarden::accept_request()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kubewarden::response::ValidationResponse;
+    use std::collections::HashSet;
+
+    fn evaluate(denied_names: &[&str]) -> ValidationResponse {
+        let request = serde_json::json!({
+            "request": serde_json::from_str::<serde_json::Value>(
+                include_str!("../test_data/pod_creation.json")
+            ).unwrap(),
+            "settings": Settings {
+                denied_names: denied_names.iter().map(|name| name.to_string()).collect::<HashSet<_>>(),
+            },
+        });
+        let response = validate(&serde_json::to_vec(&request).unwrap()).unwrap();
+        serde_json::from_slice(&response).unwrap()
+    }
+
+    #[test]
+    fn accept_pod_with_valid_name() {
+        assert!(evaluate(&["bad-name"]).accepted);
+    }
+
+    #[test]
+    fn reject_pod_with_denied_name() {
+        assert!(!evaluate(&["nginx"]).accepted);
+    }
+}
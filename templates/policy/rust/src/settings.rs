@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub denied_names: HashSet<String>,
+}
+
+impl kubewarden::settings::Validatable for Settings {
+    fn validate(&self) -> Result<(), String> {
+        if self.denied_names.iter().any(|name| name.is_empty()) {
+            return Err("denied_names cannot contain empty names".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kubewarden::settings::Validatable;
+
+    #[test]
+    fn validate_settings() {
+        let settings = Settings {
+            denied_names: HashSet::from(["nginx".to_string()]),
+        };
+        assert!(settings.validate().is_ok());
+
+        let settings = Settings {
+            denied_names: HashSet::from([String::new()]),
+        };
+        assert!(settings.validate().is_err());
+    }
+}
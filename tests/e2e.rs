@@ -102,6 +102,311 @@ fn test_policies() {
         .stdout(contains("v0.1.13"));
 }
 
+#[test]
+fn test_policies_verify_remote() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("--verify-remote");
+
+    cmd.assert().success();
+    cmd.assert()
+        .stdout(contains("Remote"))
+        .stdout(contains("up to date"))
+        .stdout(contains("stale").not());
+}
+
+#[test]
+fn test_policies_check_updates() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(
+        tempdir.path(),
+        &["registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9"],
+    );
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("--check-updates");
+
+    cmd.assert()
+        .success()
+        .stdout(contains("Update"))
+        .stdout(contains("available"))
+        .stderr(contains("1 policies have newer releases available"));
+}
+
+#[test]
+fn test_update() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(
+        tempdir.path(),
+        &["registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9"],
+    );
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("update").arg("--dry-run").arg("pod-privileged");
+    cmd.assert().success().stdout(contains(
+        "would update to registry://ghcr.io/kubewarden/tests/pod-privileged:",
+    ));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("update")
+        .arg("pod-privileged")
+        .arg("--constraint")
+        .arg("~0.1");
+    cmd.assert()
+        .success()
+        .stdout(contains("up to date").or(contains("updated to")));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("update").arg("pod-privileged");
+    cmd.assert().success().stdout(contains("updated to"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert()
+        .success()
+        .stdout(contains("pod-privileged:v0.1.9").not());
+}
+
+#[test]
+fn test_pull_version_constraint() {
+    let tempdir = tempdir().unwrap();
+    let lockfile = tempdir.path().join("kwctl.lock");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:~0.1.9");
+    cmd.assert()
+        .success()
+        .stderr(contains("version constraint resolved"));
+
+    let entries: std::collections::BTreeMap<String, String> =
+        serde_yaml::from_str(&std::fs::read_to_string(&lockfile).unwrap()).unwrap();
+    let resolved = &entries["registry://ghcr.io/kubewarden/tests/pod-privileged:~0.1.9"];
+    assert!(resolved.starts_with("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1."));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert().success().stdout(contains(resolved.as_str()));
+}
+
+#[test]
+fn test_policies_json_output() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies")
+        .arg("--output")
+        .arg("json")
+        .arg("--verify-remote");
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let policies: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let policies = policies.as_array().unwrap();
+    assert_eq!(policies.len(), POLICIES.len());
+    for policy in policies {
+        assert_eq!(policy["digest"].as_str().unwrap().len(), 64);
+        assert!(policy["size"].as_u64().unwrap() > 0);
+        assert!(policy["pulled"].is_string());
+        assert!(policy["last_used"].is_null());
+        assert_eq!(policy["remote"], "up to date");
+        assert!(policy["signatures"].is_string());
+    }
+}
+
+#[test]
+fn test_store_gc() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store").arg("gc").arg("--unused").arg("--dry-run");
+    cmd.assert().success().stdout(contains(format!(
+        "{} policies would be removed",
+        POLICIES.len()
+    )));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store").arg("gc").arg("--unused");
+    cmd.assert()
+        .success()
+        .stdout(contains(format!("{} policies removed", POLICIES.len())));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("-o").arg("json");
+    cmd.assert().success().stdout("[]\n");
+}
+
+#[test]
+fn test_store_verify() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), &POLICIES[..1]);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store").arg("verify");
+    cmd.assert().success().stdout(contains("intact"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("-o").arg("json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let policies: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    let local_path = policies[0]["local_path"].as_str().unwrap();
+    let mut module = std::fs::read(local_path).unwrap();
+    module.extend_from_slice(b"tampered");
+    std::fs::write(local_path, module).unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store").arg("verify");
+    cmd.assert()
+        .failure()
+        .stdout(contains("corrupted"))
+        .stderr(contains("1 out of 1 policies are corrupted"));
+}
+
+#[test]
+fn test_store_profiles() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg(POLICIES[0])
+        .arg("--store-profile")
+        .arg("dev-scratch");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").env("KWCTL_STORE", "dev-scratch");
+    cmd.assert().success().stdout(contains(POLICIES[0]));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies")
+        .arg("--store-profile")
+        .arg("prod-verified");
+    cmd.assert().success().stdout(contains(POLICIES[0]).not());
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert().success().stdout(contains(POLICIES[0]).not());
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies")
+        .arg("--store-profile")
+        .arg("invalid name");
+    cmd.assert()
+        .failure()
+        .stderr(contains("invalid store name"));
+}
+
+#[test]
+fn test_store_export_oci_layout() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+    let layout = tempdir.path().join("layout");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store")
+        .arg("export")
+        .arg("--oci-layout")
+        .arg(&layout);
+    cmd.assert()
+        .success()
+        .stdout(contains(format!("{} policies exported", POLICIES.len())));
+
+    assert!(layout.join("oci-layout").exists());
+    let index: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(layout.join("index.json")).unwrap()).unwrap();
+    let references: HashSet<&str> = index["manifests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|manifest| {
+            manifest["annotations"]["org.opencontainers.image.ref.name"]
+                .as_str()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(
+        references,
+        POLICIES
+            .iter()
+            .map(|policy| policy.trim_start_matches("registry://"))
+            .collect()
+    );
+}
+
+#[test]
+fn test_load_oci_layout() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+    let layout = tempdir.path().join("layout");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("store")
+        .arg("export")
+        .arg("--oci-layout")
+        .arg(&layout);
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("load")
+        .arg("--oci-layout")
+        .arg(&layout)
+        .arg("--store-profile")
+        .arg("imported");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("--store-profile").arg("imported");
+    let mut assert = cmd.assert().success();
+    for policy in POLICIES {
+        assert = assert.stdout(contains(*policy));
+    }
+}
+
+#[test]
+fn test_read_only_store() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), &POLICIES[..1]);
+
+    // the policy is already inside of the store, it's not pulled again
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull").arg(POLICIES[0]).arg("--store-read-only");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull").arg(POLICIES[1]).arg("--store-read-only");
+    cmd.assert()
+        .failure()
+        .stderr(contains("missing from the read-only store"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg(POLICIES[1])
+        .arg("--store-read-only")
+        .arg("--store-overlay")
+        .arg(tempdir.path().join("overlay"));
+    cmd.assert().success();
+    assert!(tempdir.path().join("overlay").exists());
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("rm").arg(POLICIES[0]).arg("--store-read-only");
+    cmd.assert().failure().stderr(contains("read-only"));
+}
+
+#[test]
+fn test_policies_empty_json_output() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("-o").arg("json");
+
+    cmd.assert().success().stdout("[]\n");
+}
+
 #[rstest]
 #[case::https(
     "https://github.com/kubewarden/pod-privileged-policy/releases/download/v0.2.5/policy.wasm"
@@ -291,6 +596,51 @@ fn test_run_group_policy() {
         .stdout(contains(format!("\"allowed\":{}", true)));
 }
 
+#[rstest]
+#[case::valid("pod_privileged() && true", true)]
+#[case::unknown_member("pod_privileged() && signed()", false)]
+fn test_validate_group_policy(#[case] expression: &str, #[case] valid: bool) {
+    let tempdir = tempdir().expect("cannot create tempdir");
+
+    let crd = admission_policy_group::AdmissionPolicyGroup {
+        metadata: k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta {
+            name: Some("group-policy".to_string()),
+            ..Default::default()
+        },
+        spec: Some(admission_policy_group::AdmissionPolicyGroupSpec {
+            expression: expression.to_string(),
+            message: "you shall not pass!".to_string(),
+            policies: HashMap::from([(
+                "pod_privileged".to_string(),
+                admission_policy_group::PolicyGroupMember {
+                    module: "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let yaml_file = write_tmp_yaml_file(
+        serde_yaml::to_string(&crd)
+            .expect("cannot serialize CRD")
+            .as_bytes(),
+    );
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("validate").arg(yaml_file.path());
+
+    if valid {
+        cmd.assert()
+            .success()
+            .stdout(contains("Policy Group group-policy: valid"));
+    } else {
+        cmd.assert()
+            .failure()
+            .stderr(contains("references unknown policies: signed"));
+    }
+}
+
 #[rstest]
 #[case::allowed(
     "registry://ghcr.io/kubewarden/tests/context-aware-policy-demo:v0.1.0",
@@ -534,6 +884,63 @@ fn test_run_raw_non_annotated() {
     cmd.assert().stdout(contains("\"patchType\":\"JSONPatch\""));
 }
 
+#[rstest]
+#[case::no_verification(
+    None,
+    "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+    "--trusted-only requires the policies to be verified"
+)]
+#[case::local_file(
+    Some("sigstore/cosign1.pub"),
+    "file:///tmp/policy.wasm",
+    "only policies distributed via registries can be run with --trusted-only"
+)]
+fn test_run_trusted_only(#[case] key: Option<&str>, #[case] uri: &str, #[case] error: &str) {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("run")
+        .arg("--trusted-only")
+        .arg("--request-path")
+        .arg(test_data("unprivileged-pod.json"));
+    if let Some(key) = key {
+        cmd.arg("-k").arg(test_data(key));
+    }
+    cmd.arg(uri);
+
+    cmd.assert().failure().stderr(contains(error));
+}
+
+#[rstest]
+#[case::no_verification(
+    None,
+    "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+    "--trusted-only requires the policies to be verified"
+)]
+#[case::local_file(
+    Some("sigstore/cosign1.pub"),
+    "file:///tmp/policy.wasm",
+    "only policies distributed via registries can be run with --trusted-only"
+)]
+fn test_serve_trusted_only(#[case] key: Option<&str>, #[case] uri: &str, #[case] error: &str) {
+    let tempdir = tempdir().unwrap();
+    let policies = tempdir.path().join("policies.yml");
+    std::fs::write(&policies, format!("privileged-pods:\n  module: {uri}\n")).unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("serve")
+        .arg("--trusted-only")
+        .arg("--port")
+        .arg("0")
+        .arg("--policies")
+        .arg(&policies);
+    if let Some(key) = key {
+        cmd.arg("-k").arg(test_data(key));
+    }
+
+    cmd.assert().failure().stderr(contains(error));
+}
+
 #[rstest]
 fn test_bench() {
     let tempdir = tempdir().unwrap();
@@ -557,6 +964,35 @@ fn test_bench() {
         .stdout(contains("validate").and(contains("warming up")));
 }
 
+#[test]
+fn test_bench_metrics() {
+    let tempdir = tempdir().unwrap();
+    let metrics_path = tempdir.path().join("bench.prom");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("bench")
+        .arg("--warm-up-time")
+        .arg("1")
+        .arg("--measurement-time")
+        .arg("1")
+        .arg("--num-samples")
+        .arg("2")
+        .arg("--metrics-path")
+        .arg(&metrics_path)
+        .arg("--request-path")
+        .arg(test_data("unprivileged-pod.json"))
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+
+    cmd.assert().success();
+
+    let metrics = std::fs::read_to_string(metrics_path).unwrap();
+    assert!(metrics.contains("kwctl_bench_policies_total{result=\"passed\"} 1\n"));
+    assert!(metrics.contains(
+        "kwctl_bench_operation_mean_duration_seconds{operation=\"validate\",policy=\"Policy "
+    ));
+    assert!(metrics.ends_with("# EOF\n"));
+}
+
 #[rstest]
 #[case(
     "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
@@ -576,16 +1012,49 @@ fn test_digest(#[case] uri: &str, #[case] expected_sha: &str) {
     cmd.assert().stdout(contains(expected_sha));
 }
 
+#[test]
+fn test_rm_dry_run_and_interactive() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("rm").arg("--dry-run").arg("--all");
+    cmd.assert()
+        .success()
+        .stdout(contains(format!("Would remove {}", POLICIES[0])))
+        .stdout(contains("2 policies would be removed"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("rm")
+        .arg("--interactive")
+        .arg("--all")
+        .write_stdin("y\nn\n");
+    cmd.assert()
+        .success()
+        .stderr(contains("? [y/N]"))
+        .stdout(contains("1 policies removed"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert()
+        .success()
+        .stdout(contains(POLICIES[0]).not())
+        .stdout(contains(POLICIES[1]));
+}
+
 #[rstest]
 #[case(
-    "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+    &["registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5"],
     true,
-    is_empty()
+    contains("1 policies removed")
 )]
-#[case::sha_prefix("0169", true, is_empty())]
-#[case::non_existing("non-existing", false, contains("Cannot find policy"))]
+#[case::sha_prefix(&["0169"], true, contains("1 policies removed"))]
+#[case::many(&["0169", "828617"], true, contains("2 policies removed"))]
+#[case::glob(&["registry://ghcr.io/kubewarden/tests/*:v0.*"], true, contains("2 policies removed"))]
+#[case::all(&["--all"], true, contains("2 policies removed"))]
+#[case::non_existing(&["non-existing"], false, contains("Cannot find policy"))]
 fn test_rm(
-    #[case] policy_ref: &str,
+    #[case] policy_refs: &[&str],
     #[case] success: bool,
     #[case] predicate: impl predicates::str::PredicateStrExt,
 ) {
@@ -593,7 +1062,7 @@ fn test_rm(
     pull_policies(tempdir.path(), POLICIES);
 
     let mut cmd = setup_command(tempdir.path());
-    cmd.arg("rm").arg(policy_ref);
+    cmd.arg("rm").args(policy_refs);
 
     if success {
         cmd.assert().success();
@@ -672,7 +1141,7 @@ fn test_push() {
         .arg("sources.yml")
         .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
         .arg(&target_image);
-    cmd.assert().success();
+    let output = cmd.assert().success().get_output().stdout.clone();
 
     let wasm_annotations = get_wasm_annotations(
         tempdir.path(),
@@ -694,6 +1163,21 @@ fn test_push() {
     )
     .expect("cannot get OCI manifest annotations");
 
+    // the policy is signed only when a signing flag is given
+    let output = String::from_utf8(output).unwrap();
+    let (_, digest) = output
+        .trim()
+        .rsplit_once("@sha256:")
+        .expect("cannot find the digest of the pushed policy");
+    assert!(get_manifest_annotations(
+        &format!(
+            "registry://localhost:{}/my-pod-privileged-policy:sha256-{}.sig",
+            port, digest
+        ),
+        &sources,
+    )
+    .is_err());
+
     for (wasm_key, wasm_value) in &wasm_annotations {
         if wasm_value.lines().count() > 1 {
             continue;
@@ -704,12 +1188,38 @@ fn test_push() {
             .unwrap_or_else(|| panic!("missing annotation {}", wasm_key));
         assert_eq!(wasm_value, manifest_value,);
     }
+    assert!(manifest_annotations.contains_key("io.kubewarden.policy.metadata"));
 
-    let mut cmd = setup_command(tempdir.path());
-    cmd.arg("pull")
+    // the metadata is read from the OCI manifest, the policy is not pulled
+    let scaffold_dir = tempdir().unwrap();
+    std::fs::copy(
+        tempdir.path().join("sources.yml"),
+        scaffold_dir.path().join("sources.yml"),
+    )
+    .unwrap();
+    let mut cmd = setup_command(scaffold_dir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
         .arg("--sources-path")
         .arg("sources.yml")
-        .arg(format!(
+        .arg("-t")
+        .arg("ClusterAdmissionPolicy")
+        .arg(&target_image);
+    cmd.assert()
+        .success()
+        .stdout(contains(target_image.as_str()))
+        .stdout(contains("ClusterAdmissionPolicy"));
+    let mut cmd = setup_command(scaffold_dir.path());
+    cmd.arg("policies");
+    cmd.assert()
+        .success()
+        .stdout(contains("my-pod-privileged-policy").not());
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg("--sources-path")
+        .arg("sources.yml")
+        .arg(format!(
             "registry://localhost:{}/my-pod-privileged-policy:v0.1.10",
             port
         ));
@@ -750,6 +1260,215 @@ fn test_scaffold_manifest(#[case] pull_policies_before: bool) {
     cmd.assert().stdout(contains("ClusterAdmissionPolicy"));
 }
 
+#[rstest]
+#[case::granted_by_default(&[], true)]
+#[case::opt_out(&["--no-context-aware"], false)]
+fn test_scaffold_manifest_context_aware_resources(#[case] flags: &[&str], #[case] granted: bool) {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("ClusterAdmissionPolicy")
+        .args(flags)
+        .arg("registry://ghcr.io/kubewarden/tests/context-aware-policy-demo:v0.1.0");
+
+    cmd.assert().success();
+    if granted {
+        cmd.assert().stdout(contains("contextAwareResources"));
+    } else {
+        cmd.assert()
+            .stdout(contains("contextAwareResources").not())
+            .stderr(contains("will not work"));
+    }
+}
+
+#[test]
+fn test_scaffold_verification_config_from_flags() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("verification-config")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign1.pub"))
+        .arg("--cert-oidc-issuer")
+        .arg("https://token.actions.githubusercontent.com")
+        .arg("--cert-identity-regexp")
+        .arg("https://github\\.com/my-org/.*")
+        .arg("-a")
+        .arg("env=prod");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let config = String::from_utf8(output).unwrap();
+    assert!(config.contains("kind: pubKey"), "{config}");
+    assert!(config.contains("BEGIN PUBLIC KEY"), "{config}");
+    assert!(config.contains("regexp:"), "{config}");
+    assert!(config.contains("env: prod"), "{config}");
+
+    // the generated config is accepted by the commands verifying policies
+    let config_path = tempdir.path().join("verification-config.yml");
+    std::fs::write(&config_path, config).unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("verify")
+        .arg("--verification-config-path")
+        .arg(&config_path)
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+    cmd.assert()
+        .failure()
+        .stderr(contains("cannot parse").not());
+}
+
+#[test]
+fn test_scaffold_manifest_from_file() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("--from-file")
+        .arg(test_data("manifest-requirements.yml"));
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("kind: ClusterAdmissionPolicy").count(), 2);
+    assert!(output.contains("name: no-foo-labels"));
+    assert!(output.contains("denied_labels"));
+    assert!(output.contains("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5"));
+}
+
+#[test]
+fn test_scaffold_manifest_output_formats() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    for policy in POLICIES {
+        let mut cmd = setup_command(tempdir.path());
+        cmd.arg("scaffold")
+            .arg("manifest")
+            .arg("-t")
+            .arg("ClusterAdmissionPolicy")
+            .arg("--output-format")
+            .arg("kustomize")
+            .arg("--output-dir")
+            .arg("policies")
+            .arg(policy);
+        cmd.assert().success();
+    }
+    let kustomization =
+        std::fs::read_to_string(tempdir.path().join("policies/kustomization.yaml")).unwrap();
+    assert!(kustomization.contains("kind: Kustomization"));
+    assert_eq!(kustomization.matches(".yaml").count(), 2, "{kustomization}");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("ClusterAdmissionPolicy")
+        .arg("--title")
+        .arg("pod-privileged")
+        .arg("--output-format")
+        .arg("helm")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+    cmd.assert()
+        .success()
+        .stdout(contains("recommendedPolicies:"))
+        .stdout(contains("podPrivilegedPolicy:"))
+        .stdout(contains(
+            "module: ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+        ));
+}
+
+#[test]
+fn test_scaffold_manifest_deployment_options() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("ClusterAdmissionPolicy")
+        .arg("--policy-server")
+        .arg("reserved")
+        .arg("--mode")
+        .arg("monitor")
+        .arg("--failure-policy")
+        .arg("Ignore")
+        .arg("--background-audit")
+        .arg("false")
+        .arg("--namespace-selector")
+        .arg("environment in (production, staging)")
+        .arg("--object-selector")
+        .arg("app=nginx")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+
+    cmd.assert()
+        .success()
+        .stdout(contains("policyServer: reserved"))
+        .stdout(contains("mode: monitor"))
+        .stdout(contains("failurePolicy: Ignore"))
+        .stdout(contains("backgroundAudit: false"))
+        .stdout(contains("operator: In"))
+        .stdout(contains("app: nginx"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("AdmissionPolicy")
+        .arg("--namespace-selector")
+        .arg("environment=production")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+    cmd.assert().failure().stderr(contains("namespaced"));
+}
+
+#[test]
+fn test_scaffold_policy_group_manifest() {
+    let tempdir = tempdir().unwrap();
+    pull_policies(tempdir.path(), POLICIES);
+    std::fs::write(
+        tempdir.path().join("labels.yml"),
+        "denied_labels: [foo, bar]\n",
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("ClusterAdmissionPolicyGroup")
+        .arg("--title")
+        .arg("my-group")
+        .arg("--expression")
+        .arg("pod_privileged() && labels()")
+        .arg("--settings-path")
+        .arg("labels=labels.yml")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("labels=registry://ghcr.io/kubewarden/tests/safe-labels:v0.1.13");
+
+    cmd.assert().success();
+    cmd.assert()
+        .stdout(contains("kind: ClusterAdmissionPolicyGroup"))
+        .stdout(contains("pod_privileged:"))
+        .stdout(contains("labels:"))
+        .stdout(contains("denied_labels"))
+        .stdout(contains("expression: pod_privileged() && labels()"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("manifest")
+        .arg("-t")
+        .arg("AdmissionPolicyGroup")
+        .arg("--expression")
+        .arg("missing()")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+    cmd.assert()
+        .failure()
+        .stderr(contains("the expression calls missing()"));
+}
+
 #[rstest]
 #[case::latest_cel_policy(
     Some("vap/vap-with-variables.yml"),
@@ -824,6 +1543,28 @@ fn test_scaffold_from_vap(
     cmd.assert().stderr(stderr_predicate);
 }
 
+#[test]
+fn test_scaffold_from_vap_with_params() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("vap")
+        .arg("--policy")
+        .arg(test_data("vap/vap-with-params.yml"))
+        .arg("--binding")
+        .arg(test_data("vap/vap-binding-with-params.yml"));
+    cmd.assert().failure();
+    cmd.assert().stderr(contains("--params"));
+
+    cmd.arg("--params").arg(test_data("vap/vap-params.yml"));
+    cmd.assert().success();
+    cmd.assert().stdout(contains("maxReplicas"));
+    cmd.assert().stdout(contains("mode: monitor"));
+    cmd.assert()
+        .stdout(contains("app.kubernetes.io/managed-by: helm"));
+}
+
 #[rstest]
 #[case::correct("rego-annotate/metadata-correct.yml", true, is_empty())]
 #[case::wrong(
@@ -855,6 +1596,185 @@ fn test_annotate_rego(
     }
 }
 
+#[test]
+fn test_annotate_validate_only() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("--validate-only")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"));
+
+    cmd.assert()
+        .success()
+        .stdout(contains("the metadata is valid"));
+    assert!(!tempdir.path().join("annotated-policy.wasm").exists());
+}
+
+#[test]
+fn test_annotate_in_place_and_strip() {
+    let tempdir = tempdir().unwrap();
+    let policy = tempdir.path().join("policy.wasm");
+    std::fs::copy(
+        test_data("rego-annotate/no-default-namespace-rego.wasm"),
+        &policy,
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("--in-place")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg("policy.wasm");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg("policy.wasm");
+    cmd.assert()
+        .success()
+        .stdout(contains("disallow-service-loadbalancer"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("--strip")
+        .arg("--in-place")
+        .arg("policy.wasm");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg("policy.wasm");
+    cmd.assert()
+        .failure()
+        .stderr(contains("No Kubewarden metadata found"));
+}
+
+#[rstest]
+#[case::valid("rego-annotate/settings-schema.json", true, "allowedPorts")]
+#[case::invalid(
+    "rego-annotate/settings-schema-invalid.json",
+    false,
+    "properties.allowedPorts.type"
+)]
+fn test_annotate_settings_schema(
+    #[case] schema_path: &str,
+    #[case] success: bool,
+    #[case] expected: &str,
+) {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg("--settings-schema")
+        .arg(test_data(schema_path))
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"))
+        .arg("-o")
+        .arg("annotated-policy.wasm");
+
+    if !success {
+        cmd.assert().failure().stderr(contains(expected));
+        return;
+    }
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect")
+        .arg("--settings-schema-only")
+        .arg("annotated-policy.wasm");
+    cmd.assert().success().stdout(contains(expected));
+}
+
+#[test]
+fn test_annotate_from_flags() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("--rule")
+        .arg("v1/services:CREATE,UPDATE")
+        .arg("--set")
+        .arg("executionMode=gatekeeper")
+        .arg("--set")
+        .arg("annotations.io.kubewarden.policy.title=disallow-service-loadbalancer")
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"))
+        .arg("-o")
+        .arg("annotated-policy.wasm");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg("annotated-policy.wasm");
+    cmd.assert()
+        .success()
+        .stdout(contains("disallow-service-loadbalancer"))
+        .stdout(contains("services"));
+}
+
+#[test]
+fn test_annotate_set_overrides_metadata_file() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("--validate-only")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg("--set")
+        .arg("rules[0].operations[1]=PATCH")
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"));
+
+    cmd.assert()
+        .failure()
+        .stderr(contains("rules[0].operations[1]"));
+}
+
+#[rstest]
+#[case::correct("rego-annotate/metadata-correct.yml", true, "0 errors")]
+#[case::settings_schema(
+    "rego-annotate/metadata-settings-schema.yml",
+    false,
+    "missing required annotation `io.kubewarden.policy.description`"
+)]
+fn test_lint(#[case] path: &str, #[case] success: bool, #[case] expected: &str) {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("lint").arg(test_data(path));
+
+    if success {
+        cmd.assert().success().stdout(contains(expected));
+    } else {
+        cmd.assert()
+            .failure()
+            .stdout(contains(expected))
+            .stderr(contains("is not valid"));
+    }
+}
+
+#[test]
+fn test_annotate_optimize_without_wasm_opt() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"))
+        .arg("-o")
+        .arg("annotated-policy.wasm")
+        .arg("--optimize-level")
+        .arg("s")
+        .env("KWCTL_WASM_OPT", tempdir.path().join("wasm-opt"));
+
+    cmd.assert()
+        .failure()
+        .stderr(contains("cannot find wasm-opt"));
+    assert!(!tempdir.path().join("annotated-policy.wasm").exists());
+}
+
 #[rstest]
 #[case::show_signatures(true)]
 #[case::hide_signatures(false)]
@@ -882,6 +1802,598 @@ fn test_inspect_policy_yml_output(#[case] show_signatures: bool) {
     assert_eq!(show_signatures, report.contains_key("signatures"))
 }
 
+#[test]
+fn test_policy_test_suite() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test")
+        .arg(test_data("pod-privileged-test-suite.yml"));
+    cmd.assert()
+        .success()
+        .stdout(contains("ok      privileged pods are rejected"))
+        .stdout(contains("3 passed, 0 failed"));
+
+    let suite = tempdir.path().join("failing-suite.yml");
+    std::fs::write(
+        &suite,
+        format!(
+            r#"
+policy: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+tests:
+- name: privileged pods are accepted
+  request: {}
+  expect:
+    allowed: true
+"#,
+            test_data("privileged-pod.json")
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test").arg(&suite);
+    cmd.assert()
+        .failure()
+        .stdout(contains("FAILED  privileged pods are accepted"))
+        .stdout(contains(
+            "expected the request to be accepted, it was rejected",
+        ))
+        .stdout(contains("0 passed, 1 failed"))
+        .stderr(contains("1 out of 1 test cases failed"));
+}
+
+#[test]
+fn test_policy_test_suite_filter_and_jobs() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test")
+        .arg("--jobs")
+        .arg("2")
+        .arg("--filter")
+        .arg("^privileged")
+        .arg(test_data("pod-privileged-test-suite.yml"));
+    cmd.assert()
+        .success()
+        .stdout(contains("ok      privileged pods are rejected"))
+        .stdout(contains("unprivileged pods are accepted").not())
+        .stdout(contains("1 passed, 0 failed, 2 filtered out"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test")
+        .arg("--filter")
+        .arg("no such case")
+        .arg(test_data("pod-privileged-test-suite.yml"));
+    cmd.assert()
+        .failure()
+        .stderr(contains("no test case matches the filter"));
+}
+
+#[test]
+fn test_fuzz_policy() {
+    let tempdir = tempdir().unwrap();
+    let output_dir = tempdir.path().join("findings");
+
+    // the policy may misbehave on some of the requests, the run is checked
+    // to be reproducible
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("fuzz")
+        .arg("--policy")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("--seed-request")
+        .arg(test_data("privileged-pod.json"))
+        .arg("--iterations")
+        .arg("20")
+        .arg("--seed")
+        .arg("1")
+        .arg("--output-dir")
+        .arg(&output_dir);
+    let output = cmd.output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("20 requests evaluated with seed 1"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("fuzz")
+        .arg("--policy")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("--seed-request")
+        .arg(test_data("privileged-pod.json"))
+        .arg("--iterations")
+        .arg("20")
+        .arg("--seed")
+        .arg("1");
+    cmd.assert().stdout(stdout);
+
+    let seed_request = tempdir.path().join("request.json");
+    std::fs::write(&seed_request, r#"{"uid": "1", "operation": "DELETE"}"#).unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("fuzz")
+        .arg("--policy")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("--seed-request")
+        .arg(&seed_request);
+    cmd.assert()
+        .failure()
+        .stderr(contains("the seed request has no object to mutate"));
+}
+
+#[test]
+fn test_fuzz_policy_settings() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-correct.yml"))
+        .arg("--settings-schema")
+        .arg(test_data("rego-annotate/settings-schema.json"))
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"))
+        .arg("-o")
+        .arg("annotated-policy.wasm");
+    cmd.assert().success();
+
+    // the policy ignores its settings, the ones breaking the schema are
+    // accepted too
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("fuzz")
+        .arg("--fuzz-settings")
+        .arg("--policy")
+        .arg("annotated-policy.wasm")
+        .arg("--seed-request")
+        .arg(test_data("privileged-pod.json"))
+        .arg("--iterations")
+        .arg("20")
+        .arg("--output-dir")
+        .arg("findings");
+    cmd.assert()
+        .failure()
+        .stdout(contains("[invalid-settings-accepted]"));
+    assert!(tempdir.path().join("findings").read_dir().unwrap().count() > 0);
+
+    // policies without a settings schema cannot be fuzzed
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("fuzz")
+        .arg("--fuzz-settings")
+        .arg("--policy")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("--seed-request")
+        .arg(test_data("privileged-pod.json"));
+    cmd.assert()
+        .failure()
+        .stderr(contains("the policy has no settings JSON Schema"));
+}
+
+#[test]
+fn test_audit_manifests() {
+    let tempdir = tempdir().unwrap();
+    let policies = tempdir.path().join("policies.yaml");
+    std::fs::write(
+        &policies,
+        r#"apiVersion: policies.kubewarden.io/v1
+kind: ClusterAdmissionPolicy
+metadata:
+  name: no-privileged-pods
+spec:
+  module: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+  rules:
+    - apiGroups: [""]
+      apiVersions: ["v1"]
+      resources: ["pods"]
+      operations: ["CREATE", "UPDATE"]
+  mutating: false
+"#,
+    )
+    .unwrap();
+    let manifests = tempdir.path().join("manifests");
+    std::fs::create_dir_all(manifests.join("web")).unwrap();
+    std::fs::write(
+        manifests.join("web/pod.yaml"),
+        r#"apiVersion: v1
+kind: Pod
+metadata:
+  name: web
+spec:
+  containers:
+    - name: nginx
+      image: nginx
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests);
+    cmd.assert()
+        .success()
+        .stdout(contains("1 resources evaluated, 0 rejected"))
+        .stdout(contains("0 out of 1 policies reject the manifests"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests)
+        .arg("--output")
+        .arg("policy-report");
+    cmd.assert()
+        .success()
+        .stdout(contains("kind: PolicyReport"))
+        .stdout(contains("name: pod-web"))
+        .stdout(contains("policy: clusterwide-no-privileged-pods"))
+        .stdout(contains("result: pass"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg("-")
+        .arg("--report-path")
+        .arg(tempdir.path().join("kwctl.sarif"))
+        .arg("--report-format")
+        .arg("sarif")
+        .write_stdin(
+            r#"---
+# Source: debug/templates/pod.yaml
+apiVersion: v1
+kind: Pod
+metadata:
+  name: debug
+  namespace: tools
+spec:
+  containers:
+    - name: shell
+      image: busybox
+      securityContext:
+        privileged: true
+"#,
+        );
+    cmd.assert()
+        .failure()
+        .stdout(contains("Pod tools/debug (debug/templates/pod.yaml)"))
+        .stderr(contains(
+            "1 violations of severity warning or higher, more than the 0 allowed",
+        ));
+    let sarif: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(tempdir.path().join("kwctl.sarif")).unwrap())
+            .unwrap();
+    assert_eq!(
+        sarif["runs"][0]["results"][0]["locations"][0]["physicalLocation"]["artifactLocation"]
+            ["uri"],
+        "debug/templates/pod.yaml"
+    );
+
+    // the rejections of the warnings are tolerated
+    std::fs::write(
+        manifests.join("web/debug.yaml"),
+        r#"apiVersion: v1
+kind: Pod
+metadata:
+  name: debug
+spec:
+  containers:
+    - name: shell
+      image: busybox
+      securityContext:
+        privileged: true
+"#,
+    )
+    .unwrap();
+    let severities = tempdir.path().join("severities.yaml");
+    std::fs::write(&severities, "no-privileged-pods: warning\n").unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests)
+        .arg("--severities")
+        .arg(&severities)
+        .arg("--fail-on")
+        .arg("error");
+    cmd.assert()
+        .success()
+        .stdout(contains("[warning]: 2 resources evaluated, 1 rejected"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests)
+        .arg("--max-violations")
+        .arg("1");
+    cmd.assert().success();
+
+    // the resources rejected with the same message are grouped
+    let privileged_pods: Vec<String> = (0..6)
+        .map(|index| {
+            format!(
+                r#"apiVersion: v1
+kind: Pod
+metadata:
+  name: debug-{index}
+spec:
+  containers:
+    - name: shell
+      image: busybox
+      securityContext:
+        privileged: true
+"#
+            )
+        })
+        .collect();
+    std::fs::write(
+        manifests.join("web/debug-pods.yaml"),
+        privileged_pods.join("---\n"),
+    )
+    .unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests)
+        .arg("--max-violations")
+        .arg("7");
+    cmd.assert()
+        .success()
+        .stdout(contains("(7 resources):"))
+        .stdout(contains("... and 2 more"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("audit")
+        .arg("--policies")
+        .arg(&policies)
+        .arg("--path")
+        .arg(&manifests)
+        .arg("--max-violations")
+        .arg("7")
+        .arg("--expand-violations");
+    cmd.assert()
+        .success()
+        .stdout(contains("(7 resources):"))
+        .stdout(contains("... and").not());
+}
+
+#[test]
+fn test_policy_test_suite_patch_snapshots() {
+    let tempdir = tempdir().unwrap();
+    let suite = tempdir.path().join("suite.yml");
+    std::fs::write(
+        &suite,
+        format!(
+            r#"
+policy: registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5
+tests:
+- name: unprivileged pods are not mutated
+  request: {}
+  expect:
+    allowed: true
+    patchSnapshot: snapshots/unprivileged-pod.json
+"#,
+            test_data("unprivileged-pod.json")
+        ),
+    )
+    .unwrap();
+    let snapshot = tempdir
+        .path()
+        .join("snapshots")
+        .join("unprivileged-pod.json");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test").arg(&suite);
+    cmd.assert()
+        .failure()
+        .stdout(contains("record it with --update-snapshots"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test").arg("--update-snapshots").arg(&suite);
+    cmd.assert().success().stdout(contains(format!(
+        "ok      unprivileged pods are not mutated (snapshot {} updated)",
+        snapshot.display()
+    )));
+    let recorded: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&snapshot).unwrap()).unwrap();
+    assert_eq!(recorded, serde_json::json!([]));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test").arg(&suite);
+    cmd.assert()
+        .success()
+        .stdout(contains("1 passed, 0 failed"));
+}
+
+#[test]
+fn test_policy_test_suite_reports() {
+    let tempdir = tempdir().unwrap();
+    let junit = tempdir.path().join("report.xml");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("test")
+        .arg("--report-path")
+        .arg(&junit)
+        .arg(test_data("pod-privileged-test-suite.yml"));
+    cmd.assert().success();
+    let report = std::fs::read_to_string(&junit).unwrap();
+    assert!(report.contains(r#"tests="3" failures="0" errors="0""#));
+    assert!(report.contains(r#"<testcase name="privileged pods are rejected""#));
+
+    let tap = tempdir.path().join("report.tap");
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("run")
+        .arg("--report-path")
+        .arg(&tap)
+        .arg("--report-format")
+        .arg("tap")
+        .arg("--request-path")
+        .arg(test_data("privileged-pod.json"))
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5");
+    cmd.assert().success();
+    let report = std::fs::read_to_string(&tap).unwrap();
+    assert!(report.starts_with("TAP version 13\n1..1\nnot ok 1 - "));
+    assert!(report.contains("severity: fail"));
+}
+
+#[test]
+fn test_diff() {
+    let tempdir = tempdir().unwrap();
+    let requests = tempdir.path().join("requests");
+    std::fs::create_dir(&requests).unwrap();
+    for request in ["privileged-pod.json", "unprivileged-pod.json"] {
+        std::fs::copy(test_data(request), requests.join(request)).unwrap();
+    }
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("diff")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5")
+        .arg("--requests-dir")
+        .arg(&requests);
+    cmd.assert()
+        .success()
+        .stdout(contains(
+            "# registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9 → registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5",
+        ))
+        .stdout(contains("## Module size"))
+        .stdout(contains("out of 2 requests evaluated differently"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert()
+        .success()
+        .stdout(contains("pod-privileged:v0.1.9"))
+        .stdout(contains("pod-privileged:v0.2.5"));
+}
+
+#[test]
+fn test_inspect_settings_schema() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("annotate")
+        .arg("-m")
+        .arg(test_data("rego-annotate/metadata-settings-schema.yml"))
+        .arg(test_data("rego-annotate/no-default-namespace-rego.wasm"))
+        .arg("-o")
+        .arg("annotated-policy.wasm");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg("annotated-policy.wasm");
+    cmd.assert()
+        .success()
+        .stdout(contains("Settings JSON Schema"))
+        .stdout(contains("allowedNamespaces"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect")
+        .arg("--settings-schema-only")
+        .arg("-o")
+        .arg("json")
+        .arg("annotated-policy.wasm");
+    cmd.assert().success();
+    let schema: serde_json::Value = serde_json::from_slice(&cmd.assert().get_output().stdout)
+        .expect("a valid json document was expected");
+    assert_eq!(schema["properties"]["allowedNamespaces"]["type"], "array");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect")
+        .arg("-o")
+        .arg("json")
+        .arg("annotated-policy.wasm");
+    cmd.assert()
+        .failure()
+        .stderr(contains("only together with --settings-schema-only"));
+}
+
+#[test]
+fn test_inspect_remote() {
+    let uri = "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5";
+
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg("--remote").arg(uri);
+    cmd.assert()
+        .success()
+        .stdout(contains("pod-privileged"))
+        .stdout(contains("Interface").not());
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies");
+    cmd.assert()
+        .success()
+        .stdout(contains("pod-privileged").not());
+}
+
+#[test]
+fn test_inspect_policy_interface() {
+    let uri = "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.2.5";
+
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull").arg(uri);
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect").arg(uri);
+    cmd.assert()
+        .success()
+        .stdout(contains("Interface"))
+        .stdout(contains("waPC"))
+        .stdout(contains("wapc::__host_call"))
+        .stdout(contains("__guest_call"))
+        .stdout(contains("Size"))
+        .stdout(contains("code:"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_plugin() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tempdir = tempdir().unwrap();
+    let plugin = tempdir.path().join("kwctl-hello");
+    std::fs::write(
+        &plugin,
+        "#!/bin/sh\necho \"hello $@ from $KWCTL_PLUGIN_API_VERSION\"\necho \"store: $KWCTL_STORE_PATH\"\nexit 3\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = std::env::join_paths(
+        std::iter::once(tempdir.path().to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.env("PATH", &path)
+        .arg("hello")
+        .arg("--name")
+        .arg("world");
+    cmd.assert()
+        .code(3)
+        .stdout(contains("hello --name world from v1"))
+        .stdout(contains(format!(
+            "store: {}",
+            tempdir.path().join(".local/share").display()
+        )));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.env("PATH", &path).arg("missing-plugin");
+    cmd.assert()
+        .failure()
+        .stderr(contains("No 'kwctl-missing-plugin' plugin found"));
+}
+
 #[test]
 fn test_artifacthub_scaffold_find_metadata_automatically() {
     let tempdir = tempdir().unwrap();
@@ -911,6 +2423,53 @@ fn test_artifacthub_scaffold_with_custom_metadata() {
     cmd.assert().success();
 }
 
+#[test]
+fn test_artifacthub_scaffold_with_version() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("artifacthub")
+        .arg("--metadata-path")
+        .arg(test_data("artifacthub/metadata.yml"))
+        .arg("--version")
+        .arg("1.2.0")
+        .arg("--output")
+        .arg("artifacthub-pkg.yml");
+    cmd.assert().success();
+
+    let package: serde_yaml::Value = serde_yaml::from_str(
+        &std::fs::read_to_string(tempdir.path().join("artifacthub-pkg.yml")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(package["version"], serde_yaml::Value::from("1.2.0"));
+}
+
+#[rstest]
+#[case::rust("rust", "Cargo.toml")]
+#[case::go("go", "go.mod")]
+#[case::rego("rego", "policy.rego")]
+fn test_scaffold_policy(#[case] language: &str, #[case] source: &str) {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("scaffold")
+        .arg("policy")
+        .arg("--language")
+        .arg(language)
+        .arg("--name")
+        .arg("my-policy");
+    cmd.assert()
+        .success()
+        .stdout(contains("scaffolded into my-policy"));
+    assert!(tempdir.path().join("my-policy").join(source).exists());
+
+    // the generated metadata passes the lint
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("lint").arg("my-policy/metadata.yml");
+    cmd.assert().success().stdout(contains("0 errors"));
+}
+
 #[test]
 fn test_artifacthub_scaffold_fail_when_metadata_not_provided_nor_found() {
     let tempdir = tempdir().unwrap();
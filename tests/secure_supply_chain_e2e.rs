@@ -230,6 +230,167 @@ fn test_verify_oci_registry(
     cmd.assert().stderr(predicate);
 }
 
+#[rstest]
+#[case::signed_with_key("sigstore/cosign1.pub", true)]
+#[case::other_key("sigstore/cosign2.pub", false)]
+fn test_verify_local_policy_detached_signature(#[case] key: &str, #[case] success: bool) {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use policy_evaluator::policy_fetcher::sigstore::crypto::{SigStoreKeyPair, SigningScheme};
+
+    let tempdir = tempdir().unwrap();
+    let policy_path = tempdir.path().join("policy.wasm");
+    let policy = b"\0asm policy distributed outside of registries";
+    fs::write(&policy_path, policy).unwrap();
+    let signer = SigStoreKeyPair::from_encrypted_pem(
+        &fs::read(test_data("sigstore/cosign1.key")).unwrap(),
+        b"kubewarden",
+    )
+    .unwrap()
+    .to_sigstore_signer(&SigningScheme::default())
+    .unwrap();
+    fs::write(
+        tempdir.path().join("policy.wasm.sig"),
+        STANDARD.encode(signer.sign(policy).unwrap()),
+    )
+    .unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("verify")
+        .arg("-k")
+        .arg(test_data(key))
+        .arg(&policy_path);
+
+    if success {
+        cmd.assert()
+            .success()
+            .stderr(contains("Policy successfully verified"));
+    } else {
+        cmd.assert()
+            .failure()
+            .stderr(contains("no signature produced with key"));
+    }
+}
+
+#[test]
+fn test_verify_store() {
+    let tempdir = tempdir().unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("verify")
+        .arg("--store")
+        .arg("-a")
+        .arg("env=prod")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign1.pub"));
+    cmd.assert()
+        .success()
+        .stdout(contains("pod-privileged:v0.1.9"))
+        .stdout(contains("verified"));
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("verify")
+        .arg("--store")
+        .arg("-a")
+        .arg("env=prod")
+        .arg("-a")
+        .arg("stable=true")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign2.pub"));
+    cmd.assert()
+        .failure()
+        .stdout(contains("failed: "))
+        .stderr(contains("1 out of 1 policies cannot be verified"));
+}
+
+#[test]
+fn test_pull_records_provenance() {
+    let tempdir = tempdir().unwrap();
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("pull")
+        .arg("-a")
+        .arg("env=prod")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign1.pub"))
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9");
+    cmd.assert().success();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("policies").arg("-o").arg("json");
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let policies: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        policies[0]["source"],
+        "registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9"
+    );
+    assert_eq!(policies[0]["verification"]["verified"], true);
+    assert_eq!(policies[0]["verification"]["config"], "command line flags");
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("inspect")
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9");
+    cmd.assert()
+        .success()
+        .stdout(contains("Provenance"))
+        .stdout(contains("verified with command line flags"));
+}
+
+#[test]
+fn test_run_trusted_only() {
+    let tempdir = tempdir().unwrap();
+
+    let mut cmd = setup_command(tempdir.path());
+    cmd.arg("run")
+        .arg("--trusted-only")
+        .arg("-a")
+        .arg("env=prod")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign1.pub"))
+        .arg("--request-path")
+        .arg(test_data("unprivileged-pod.json"))
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9");
+
+    cmd.assert().success().stdout(contains("\"allowed\":true"));
+}
+
+#[test]
+fn test_verify_json_report() {
+    let tempdir = tempdir().unwrap();
+    let mut cmd = setup_command(tempdir.path());
+
+    cmd.arg("verify")
+        .arg("--output")
+        .arg("json")
+        .arg("-a")
+        .arg("env=prod")
+        .arg("-k")
+        .arg(test_data("sigstore/cosign1.pub"))
+        .arg("-k")
+        .arg(test_data("sigstore/cosign2.pub"))
+        .arg("registry://ghcr.io/kubewarden/tests/pod-privileged:v0.1.9");
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let report: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+    assert!(report["manifest_digest"]
+        .as_str()
+        .unwrap()
+        .starts_with("sha256:"));
+    let signatures = report["signatures"].as_array().unwrap();
+    assert_eq!(signatures.len(), 2);
+    for signature in signatures {
+        assert_eq!(signature["annotations"]["env"], "prod");
+        assert_eq!(signature["matched"].as_array().unwrap().len(), 1);
+        assert!(signature["matched"][0]
+            .as_str()
+            .unwrap()
+            .starts_with("key sha256:"));
+    }
+}
+
 #[rstest]
 #[case(
     &["sigstore/cosign1.pub"],